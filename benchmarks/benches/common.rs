@@ -1,10 +1,11 @@
 #[allow(dead_code)]
 mod common {
-    use std::cmp::min;
+    use std::{cmp::min, env, path::PathBuf, sync::OnceLock};
 
-    use etemenanki::Datastore;
+    use etemenanki::{import::WhitespaceTokenizer, Datastore};
+    #[cfg(feature = "cwb")]
     use libcl_rs::Corpus;
-    use rand::{distributions::{Distribution, Uniform}, rngs::StdRng, SeedableRng};
+    use rand::{distributions::{Distribution, Uniform, WeightedIndex}, rngs::StdRng, SeedableRng};
 
     pub fn rng() -> StdRng {
         StdRng::seed_from_u64(42)
@@ -65,11 +66,71 @@ mod common {
         Datastore::open(unsafe { DATASTORE_NAME }).unwrap()
     }
 
+    #[cfg(feature = "cwb")]
     pub fn open_cwb() -> Corpus {
         // open CWB corpus
         Corpus::new("cwb/registry", "encow_cwb").expect("Could not open corpus")
     }
 
+    pub const SYNTHETIC_TOKENS: usize = 5_000_000;
+    pub const SYNTHETIC_TYPES: usize = 50_000;
+
+    /// Datastore of the benchmarks that don't compare against CWB: the one at the path in
+    /// `ZIGGURAT_BENCH_DATASTORE` if it is set, otherwise a synthetic one that is built in
+    /// the temporary directory on first use and reused by later runs.
+    pub fn open_bench_datastore() -> Datastore<'static> {
+        static PATH: OnceLock<PathBuf> = OnceLock::new();
+        let path = PATH.get_or_init(|| match env::var_os("ZIGGURAT_BENCH_DATASTORE") {
+            Some(path) => PathBuf::from(path),
+            None => build_synthetic_datastore(),
+        });
+        Datastore::open(path).unwrap()
+    }
+
+    /// Pseudo-word of the type with rank `rank`, syllables make prefixes and infixes
+    /// shared by many types like in a real lexicon
+    pub fn synthetic_word(rank: usize) -> String {
+        const SYLLABLES: [&str; 16] = ["be", "am", "ta", "ro", "li", "ne", "sa", "ku", "do", "mi", "on", "er", "ga", "the", "s", "ly"];
+        let mut word = String::new();
+        let mut rank = rank;
+        loop {
+            word.insert_str(0, SYLLABLES[rank % SYLLABLES.len()]);
+            rank /= SYLLABLES.len();
+            if rank == 0 {
+                return word;
+            }
+        }
+    }
+
+    /// Zipf distributed pseudo-words in sentences of 5 to 40 tokens, 1000 sentences per text
+    fn build_synthetic_datastore() -> PathBuf {
+        let path = env::temp_dir().join(format!("ziggurat-bench-{}-{}", SYNTHETIC_TOKENS, SYNTHETIC_TYPES));
+        if Datastore::open(&path).is_ok() {
+            return path;
+        }
+
+        let mut rng = rng();
+        let types = WeightedIndex::new((1..=SYNTHETIC_TYPES).map(|rank| 1.0 / rank as f64)).unwrap();
+        let lengths = Uniform::new(5, 40);
+        let mut texts = Vec::new();
+        let mut tokens = 0;
+        while tokens < SYNTHETIC_TOKENS {
+            let mut text = String::new();
+            for _ in 0..1000 {
+                let len = lengths.sample(&mut rng);
+                let sentence: Vec<String> = (0..len).map(|_| synthetic_word(types.sample(&mut rng))).collect();
+                text.push_str(&sentence.join(" "));
+                text.push('\n');
+                tokens += len;
+            }
+            texts.push(text);
+        }
+
+        let _ = std::fs::remove_dir_all(&path);
+        Datastore::from_text(&path, texts, &WhitespaceTokenizer).unwrap();
+        path
+    }
+
     pub static REGEX_TESTS: [&'static str; 22] = [
         r"ziggurat",
         r"be.+",
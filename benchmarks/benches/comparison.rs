@@ -3,7 +3,7 @@
 use std::{ffi::CString, time::Duration};
 
 use criterion::{black_box, criterion_group, criterion_main, Bencher, Criterion};
-use etemenanki::{components::FnvHash, variables::IndexedStringVariable};
+use etemenanki::variables::IndexedStringVariable;
 use libcl_rs::{ClRegex, PositionalAttribute};
 use regex::Regex;
 
@@ -484,8 +484,7 @@ fn z_baseline_lexicon_index_lookup(b: &mut Bencher) {
         .unwrap();
 
     b.iter(|| {
-        let hash = "ziggurat".fnv_hash();
-        let tid = words.index().get_first(hash).unwrap() as usize;
+        let tid = words.lookup_str("ziggurat").unwrap();
         black_box(tid);
     })
 }
@@ -0,0 +1,91 @@
+use std::{fs::File, path::Path, time::Duration};
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use etemenanki::components::{LexiconBuilder, LexiconOrder, PostingsEncoding};
+use etemenanki::variables::IndexedStringVariable;
+use rand::distributions::{Distribution, WeightedIndex};
+use uuid::Uuid;
+
+criterion_group!(benches, criterion_benchmark);
+criterion_main!(benches);
+
+include!("common.rs");
+use common::*;
+
+//
+// Delta vs. Elias-Fano coded postings lists
+//
+
+const TOKENS: usize = 20_000_000;
+const TYPES: usize = 100_000;
+const FREQUENT: usize = 20;
+const RARE: usize = 10_000;
+
+/// Zipf distributed type names, so that a few types make up most of the tokens
+fn setup_tokens() -> Vec<String> {
+    let rng = rng();
+    let dist = WeightedIndex::new((1..=TYPES).map(|rank| 1.0 / rank as f64)).unwrap();
+    dist.sample_iter(rng).take(TOKENS).map(|t| format!("t{}", t)).collect()
+}
+
+fn encode_variable(dir: &Path, tokens: &[String], encoding: PostingsEncoding) -> IndexedStringVariable<'static> {
+    let path = dir.join(format!("word_{:?}.zigv", encoding).to_lowercase());
+    let file = File::options()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&path)
+        .unwrap();
+
+    let mut builder = LexiconBuilder::from_strings(tokens.iter());
+    builder.set_postings_encoding(encoding);
+    let var = IndexedStringVariable::encode_lexicon_builder(file, builder, "word".to_owned(), Uuid::new_v4(), true, LexiconOrder::Frequency, "").unwrap();
+    println!("{}: {} bytes", path.file_name().unwrap().to_string_lossy(), path.metadata().unwrap().len());
+    var
+}
+
+//
+// Criterion Main
+//
+
+fn criterion_benchmark(c: &mut Criterion) {
+    let dir = tempfile::tempdir().unwrap();
+    let tokens = setup_tokens();
+
+    let variables = [
+        ("delta", encode_variable(dir.path(), &tokens, PostingsEncoding::Delta)),
+        ("elias-fano", encode_variable(dir.path(), &tokens, PostingsEncoding::EliasFano)),
+    ];
+
+    // the lexicon is frequency sorted, so the most frequent types come first
+    let frequent: Vec<usize> = (0..FREQUENT).collect();
+    let rare: Vec<usize> = setup_rand(RARE, variables[0].1.n_types() - 1000).into_iter().map(|t| t + 1000).collect();
+
+    let mut group = c.benchmark_group("postings encoding");
+    group.sample_size(20);
+    group.measurement_time(Duration::new(30, 0));
+    group.sampling_mode(criterion::SamplingMode::Flat);
+
+    for (mode, var) in variables.iter() {
+        let index = var.inverted_index();
+
+        // Decoding the postings of the most frequent types, bypassing the cache
+        group.bench_with_input(BenchmarkId::new("frequent decode", mode), &index, |b, index| {
+            b.iter(|| {
+                for t in frequent.iter() {
+                    black_box(index.decode_postings(*t));
+                }
+            })
+        });
+
+        // Decoding the postings of random rare types, bypassing the cache
+        group.bench_with_input(BenchmarkId::new("rare decode", mode), &index, |b, index| {
+            b.iter(|| {
+                for t in rare.iter() {
+                    black_box(index.decode_postings(*t));
+                }
+            })
+        });
+    }
+}
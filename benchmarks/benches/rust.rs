@@ -1,7 +1,7 @@
 use std::{hash::{DefaultHasher, Hash, Hasher}, time::Duration};
 
 use criterion::{black_box, criterion_group, criterion_main, Bencher, Criterion};
-use etemenanki::{components::FnvHash, Datastore};
+use etemenanki::components::FnvHash;
 
 criterion_group!(benches, criterion_benchmark);
 criterion_main!(benches);
@@ -14,7 +14,7 @@ use common::*;
 //
 
 fn pattern_prefix(b: &mut Bencher) {
-    let datastore = open_ziggurat();
+    let datastore = open_bench_datastore();
     let words = datastore["primary"]["word"]
         .as_indexed_string()
         .unwrap();
@@ -27,7 +27,7 @@ fn pattern_prefix(b: &mut Bencher) {
 }
 
 fn regex_prefix(b: &mut Bencher) {
-    let datastore = open_ziggurat();
+    let datastore = open_bench_datastore();
     let words = datastore["primary"]["word"]
         .as_indexed_string()
         .unwrap();
@@ -40,7 +40,7 @@ fn regex_prefix(b: &mut Bencher) {
 }
 
 fn pattern_contains(b: &mut Bencher) {
-    let datastore = open_ziggurat();
+    let datastore = open_bench_datastore();
     let words = datastore["primary"]["word"]
         .as_indexed_string()
         .unwrap();
@@ -53,7 +53,7 @@ fn pattern_contains(b: &mut Bencher) {
 }
 
 fn regex_contains(b: &mut Bencher) {
-    let datastore = open_ziggurat();
+    let datastore = open_bench_datastore();
     let words = datastore["primary"]["word"]
         .as_indexed_string()
         .unwrap();
@@ -66,7 +66,7 @@ fn regex_contains(b: &mut Bencher) {
 }
 
 fn lexhash_fnv(b: &mut Bencher) {
-    let datastore = open_ziggurat();
+    let datastore = open_bench_datastore();
     let words = datastore["primary"]["word"]
         .as_indexed_string()
         .unwrap();
@@ -79,7 +79,7 @@ fn lexhash_fnv(b: &mut Bencher) {
 }
 
 fn lexhash_rust(b: &mut Bencher) {
-    let datastore = open_ziggurat();
+    let datastore = open_bench_datastore();
     let words = datastore["primary"]["word"]
         .as_indexed_string()
         .unwrap();
@@ -0,0 +1,123 @@
+use std::{fs::File, path::Path, time::Duration};
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use etemenanki::layers::SegmentationLayer;
+use rand::distributions::{Distribution, Uniform};
+use uuid::Uuid;
+
+criterion_group!(benches, criterion_benchmark);
+criterion_main!(benches);
+
+include!("common.rs");
+use common::*;
+
+//
+// Compressed vs. Uncompressed SegmentationLayer, with and without StartBitmap
+//
+
+const SEGMENTS: usize = 200_000;
+const RANDOM: usize = 1_000_000;
+
+/// sentence-like ranges of 5 to 40 positions, with a gap after every tenth range
+fn setup_ranges() -> Vec<(usize, usize)> {
+    let mut rng = rng();
+    let ldist = Uniform::new(5, 40);
+    let mut ranges = Vec::with_capacity(SEGMENTS);
+    let mut start = 0;
+
+    for i in 0..SEGMENTS {
+        let end = start + ldist.sample(&mut rng);
+        ranges.push((start, end));
+        start = if i % 10 == 9 { end + 3 } else { end };
+    }
+
+    ranges
+}
+
+fn encode_layer(dir: &Path, ranges: &[(usize, usize)], compressed: bool, bitmap: bool) -> SegmentationLayer<'static> {
+    let path = dir.join(format!("s_{}{}.zigl", if compressed { "comp" } else { "uncomp" }, if bitmap { "_bitmap" } else { "" }));
+    let file = File::options()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&path)
+        .unwrap();
+
+    let layer = SegmentationLayer::encode_to_file_with_bitmap(file, ranges.iter().copied(), ranges.len(), "s".to_owned(), Uuid::new_v4(), compressed, bitmap, "").unwrap();
+    println!("{}: {} bytes", path.file_name().unwrap().to_string_lossy(), path.metadata().unwrap().len());
+    layer
+}
+
+//
+// Criterion Main
+//
+
+fn criterion_benchmark(c: &mut Criterion) {
+    let dir = tempfile::tempdir().unwrap();
+    let ranges = setup_ranges();
+    let max = ranges.last().unwrap().1;
+
+    let layers = [
+        ("uncompressed", encode_layer(dir.path(), &ranges, false, false)),
+        ("compressed", encode_layer(dir.path(), &ranges, true, false)),
+        ("compressed+bitmap", encode_layer(dir.path(), &ranges, true, true)),
+    ];
+
+    let rnd_segments = setup_rand(RANDOM, ranges.len());
+    let rnd_positions = setup_rand(RANDOM, max);
+    let mut sorted_positions = rnd_positions.clone();
+    sorted_positions.sort_unstable();
+
+    let mut group = c.benchmark_group("segmentation compression");
+    group.sample_size(50);
+    group.measurement_time(Duration::new(30, 0));
+    group.sampling_mode(criterion::SamplingMode::Flat);
+
+    for (mode, layer) in layers.iter() {
+        // Sequential Segmentation Decode
+        group.bench_with_input(BenchmarkId::new("sequential decode", mode), layer, |b, s| {
+            b.iter(|| {
+                for seg in s.iter() {
+                    black_box(seg);
+                }
+            })
+        });
+
+        // Random Segmentation Decode
+        group.bench_with_input(BenchmarkId::new("random decode", mode), layer, |b, s| {
+            b.iter(|| {
+                for i in rnd_segments.iter() {
+                    black_box(s.get(*i));
+                }
+            })
+        });
+
+        // Random Segmentation Lookup
+        group.bench_with_input(BenchmarkId::new("random lookup", mode), layer, |b, s| {
+            b.iter(|| {
+                for cpos in rnd_positions.iter() {
+                    black_box(s.find_containing(*cpos));
+                }
+            })
+        });
+
+        // Sorted Segmentation Lookup, walks the range stream in a single pass
+        group.bench_with_input(BenchmarkId::new("sorted batch lookup", mode), layer, |b, s| {
+            b.iter(|| {
+                for spos in s.find_containing_iter(sorted_positions.iter().copied()) {
+                    black_box(spos);
+                }
+            })
+        });
+
+        // Range Membership
+        group.bench_with_input(BenchmarkId::new("random contains", mode), layer, |b, s| {
+            b.iter(|| {
+                for i in rnd_segments.iter() {
+                    black_box(s.contains(ranges[*i]));
+                }
+            })
+        });
+    }
+}
@@ -0,0 +1,180 @@
+use std::time::Duration;
+
+use criterion::{black_box, criterion_group, criterion_main, Bencher, Criterion};
+use etemenanki::{variables::IndexedStringVariable, Datastore};
+
+criterion_group!(benches, criterion_benchmark);
+criterion_main!(benches);
+
+include!("common.rs");
+use common::*;
+
+//
+// Self-contained Ziggurat benchmarks, run on the datastore of `open_bench_datastore`
+// so that no CWB corpus is needed
+//
+
+const ACCESSES: usize = 10_000;
+
+fn words<'a, 'map>(datastore: &'a Datastore<'map>) -> &'a IndexedStringVariable<'map> {
+    datastore["primary"]["word"]
+        .as_indexed_string()
+        .unwrap()
+}
+
+//
+// Cached const-generic vs. uncached Vector access
+//
+
+fn cached_rand(b: &mut Bencher, datastore: &Datastore) {
+    let ids = words(datastore).id_stream();
+    let positions = setup_rand(ACCESSES, ids.len());
+
+    b.iter(|| {
+        for &p in &positions {
+            black_box(ids.get_row_unchecked(p));
+        }
+    })
+}
+
+fn uncached_rand(b: &mut Bencher, datastore: &Datastore) {
+    let ids = words(datastore).id_stream().vector();
+    let positions = setup_rand(ACCESSES, ids.len());
+
+    b.iter(|| {
+        for &p in &positions {
+            black_box(ids.get_row_unchecked(p));
+        }
+    })
+}
+
+fn cached_sequential(b: &mut Bencher, datastore: &Datastore) {
+    let ids = words(datastore).id_stream();
+    let end = ids.len().min(ACCESSES * 10);
+
+    b.iter(|| {
+        for p in 0..end {
+            black_box(ids.get_row_unchecked(p));
+        }
+    })
+}
+
+fn uncached_sequential(b: &mut Bencher, datastore: &Datastore) {
+    let ids = words(datastore).id_stream().vector();
+    let end = ids.len().min(ACCESSES * 10);
+
+    b.iter(|| {
+        for p in 0..end {
+            black_box(ids.get_row_unchecked(p));
+        }
+    })
+}
+
+fn cached_iter(b: &mut Bencher, datastore: &Datastore) {
+    let ids = words(datastore).id_stream();
+    let end = ids.len().min(ACCESSES * 10);
+
+    b.iter(|| {
+        for row in ids.iter_until(end).unwrap() {
+            black_box(row);
+        }
+    })
+}
+
+fn cached_windows(b: &mut Bencher, datastore: &Datastore) {
+    let ids = words(datastore).id_stream();
+    let positions: Vec<usize> = setup_windows(ACCESSES, ids.len(), 1, 40).into_iter().flat_map(|(s, e)| s..e).collect();
+
+    b.iter(|| {
+        black_box(ids.get_rows(&positions));
+    })
+}
+
+fn uncached_windows(b: &mut Bencher, datastore: &Datastore) {
+    let ids = words(datastore).id_stream().vector();
+    let positions: Vec<usize> = setup_windows(ACCESSES, ids.len(), 1, 40).into_iter().flat_map(|(s, e)| s..e).collect();
+
+    b.iter(|| {
+        for &p in &positions {
+            black_box(ids.get_row_unchecked(p));
+        }
+    })
+}
+
+//
+// Lexicon and index lookups
+//
+
+fn lookup_hits(b: &mut Bencher, datastore: &Datastore) {
+    let words = words(datastore);
+    let strings: Vec<String> = (0..ACCESSES).map(|i| words.lexicon().get(i % words.n_types()).unwrap().to_owned()).collect();
+
+    b.iter(|| {
+        for s in &strings {
+            black_box(words.lookup_str(s));
+        }
+    })
+}
+
+fn lookup_misses(b: &mut Bencher, datastore: &Datastore) {
+    let words = words(datastore);
+    let strings: Vec<String> = (0..ACCESSES).map(|i| format!("{}-missing", synthetic_word(i))).collect();
+
+    b.iter(|| {
+        for s in &strings {
+            black_box(words.lookup_str(s));
+        }
+    })
+}
+
+fn lexicon_prefix(b: &mut Bencher, datastore: &Datastore) {
+    let words = words(datastore);
+
+    b.iter(|| {
+        for i in words.lexicon().all_starting_with("be") {
+            black_box(i);
+        }
+    })
+}
+
+fn segmentation_lookup(b: &mut Bencher, datastore: &Datastore) {
+    let sentences = datastore["s"].as_segmentation().unwrap();
+    let positions = setup_rand(ACCESSES, datastore["primary"].len());
+
+    b.iter(|| {
+        for &p in &positions {
+            black_box(sentences.find_containing(p));
+        }
+    })
+}
+
+//
+// Criterion Main
+//
+
+fn criterion_benchmark(c: &mut Criterion) {
+    let datastore = open_bench_datastore();
+
+    let mut group = c.benchmark_group("vector access");
+    group.sample_size(50);
+    group.measurement_time(Duration::new(10, 0));
+
+    group.bench_function("cached random", |b| cached_rand(b, &datastore));
+    group.bench_function("uncached random", |b| uncached_rand(b, &datastore));
+    group.bench_function("cached sequential", |b| cached_sequential(b, &datastore));
+    group.bench_function("uncached sequential", |b| uncached_sequential(b, &datastore));
+    group.bench_function("cached iterator", |b| cached_iter(b, &datastore));
+    group.bench_function("cached windows", |b| cached_windows(b, &datastore));
+    group.bench_function("uncached windows", |b| uncached_windows(b, &datastore));
+    group.finish();
+
+    let mut group = c.benchmark_group("lookups");
+    group.sample_size(50);
+    group.measurement_time(Duration::new(10, 0));
+
+    group.bench_function("lookup_str hits", |b| lookup_hits(b, &datastore));
+    group.bench_function("lookup_str misses", |b| lookup_misses(b, &datastore));
+    group.bench_function("lexicon prefix", |b| lexicon_prefix(b, &datastore));
+    group.bench_function("segmentation lookup", |b| segmentation_lookup(b, &datastore));
+    group.finish();
+}
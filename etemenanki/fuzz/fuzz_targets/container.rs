@@ -0,0 +1,22 @@
+//! Opens arbitrary bytes as a container and maps all of its components.
+//!
+//! Run with `cargo +nightly fuzz run container` from the etemenanki directory.
+//! Encoded containers, e.g. from `testdata/`, make a good seed corpus.
+
+#![no_main]
+
+use etemenanki::container::Container;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(container) = Container::from_bytes(data, "fuzz".to_owned()) {
+        let _ = container.header().try_container_type();
+        let _ = container.comment();
+        let _ = container.metadata();
+
+        let names: Vec<_> = container.component_names().collect();
+        for name in names {
+            let _ = container.get_component(name);
+        }
+    }
+});
@@ -0,0 +1,628 @@
+use std::collections::HashMap;
+use std::env;
+use std::error::Error;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use etemenanki::compare::{DatastoreDiff, LexiconDiff};
+use etemenanki::concordance::Format;
+use etemenanki::container::{self, Container};
+#[cfg(feature = "cwb")]
+use etemenanki::cwb;
+use etemenanki::frequency::{self, FrequencyList, MetadataFilter};
+use etemenanki::lock::{DatastoreLock, LockMode};
+use etemenanki::manifest::Manifest;
+use etemenanki::pagination::{Cursor, Paginator};
+use etemenanki::query::Query;
+use etemenanki::query_cache::QueryCache;
+#[cfg(feature = "server")]
+use etemenanki::server::{self, Api};
+use etemenanki::{storage, Concordance, Datastore, DatastoreError, Variable};
+#[cfg(feature = "progress")]
+use indicatif::{ProgressBar, ProgressStyle};
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+
+const USAGE: &str = "usage: ziggurat <command> [<args>]
+
+commands:
+    compare <datastore> <datastore> [<layer>/<variable> [<layer>/<variable>]]
+                                compare the layers and variables of two datastores, or one variable in detail
+    freq <datastore> <layer>/<variable>
+                                print the frequency list of a variable as TSV
+    fsck <datastore>            regenerate the manifest of a datastore, check that it can be opened,
+                                that all of its strings are valid UTF-8 and that all ranges, pointers
+                                and positions lie within their layers
+    info <datastore>            list all containers of a datastore with their comments and metadata
+    migrate <path> [<output>]   rewrite a container (or all containers in a datastore) to the current format version
+    query <datastore> [<expr>]  find the matches of a query like '[pos=\"JJ\"] \"man\"' and write them as TSV,
+                                the expression is left out when continuing from a --cursor
+    serve <datastore>           answer requests to the JSON API of the datastore over HTTP, requires the server feature
+    validate-against-cwb <datastore> <registry> <corpus>
+                                check that a datastore has the same tokens and regions as a CWB corpus,
+                                requires the cwb feature
+
+compare options:
+    --top <n>                   number of key values listed when comparing a variable, default 20
+
+freq options:
+    --where <layer>.<var>=<re>  only count positions in segments whose value matches, != negates, can be repeated
+    --by <layer>.<variable>     one frequency list per value of a segmentation variable
+    --top <n>                   only print the n most frequent values (per group)
+
+fsck options:
+    --json                      print the report of the consistency checks as JSON
+
+migrate options:
+    --jobs <n>                  number of containers migrated in parallel, requires the parallel feature
+
+query options:
+    --layer <name>              layer to query, default primary
+    --show <variable>           variable shown as context, default word
+    --context <n>               tokens of context on each side, default 5
+    --meta <layer>.<variable>   add a metadata column from a segmentation layer, can be repeated
+    --count                     count the matches by their text instead of listing them
+    --count-by <layer>.<var>    count the matches by a metadata variable instead of listing them
+    --page-size <n>             only write the first n matches and print a cursor for the following ones
+    --cursor <token>            continue with the page after a cursor printed by --page-size
+
+serve options:
+    --address <host:port>       address to listen on, default 127.0.0.1:8080
+    --max-limit <n>             largest number of matches or values a request may ask for, default 10000
+
+validate-against-cwb options:
+    --layer <name>              primary layer holding the positional attributes, default primary
+    --max-mismatches <n>        number of mismatches listed, default 100";
+
+type CmdResult = Result<(), Box<dyn Error>>;
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    let result = match args.first().map(|s| s.as_str()) {
+        Some("compare") => compare(&args[1..]),
+        Some("freq") => freq(&args[1..]),
+        Some("fsck") => fsck(&args[1..]),
+        Some("info") => info(&args[1..]),
+        Some("migrate") => migrate(&args[1..]),
+        Some("query") => query(&args[1..]),
+        Some("serve") => serve(&args[1..]),
+        Some("validate-against-cwb") => validate_against_cwb(&args[1..]),
+        _ => {
+            eprintln!("{}", USAGE);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn compare(args: &[String]) -> CmdResult {
+    let mut positional = Vec::new();
+    let mut top = 20;
+
+    let mut options = args.iter();
+    while let Some(arg) = options.next() {
+        match arg.as_str() {
+            "--top" => top = options.next().ok_or("missing value for --top")?.parse()?,
+            option if option.starts_with("--") => return Err(format!("unknown option {}", option).into()),
+            arg => positional.push(arg),
+        }
+    }
+
+    let a = Datastore::open(positional.first().ok_or("missing first datastore path")?)?;
+    let b = Datastore::open(positional.get(1).ok_or("missing second datastore path")?)?;
+
+    let (identical, what) = match positional.get(2) {
+        Some(target_a) => {
+            let target_b = positional.get(3).unwrap_or(target_a);
+            let diff = LexiconDiff::new(lookup_variable(&a, target_a)?, lookup_variable(&b, target_b)?)?;
+
+            println!("types: {} -> {} ({} shared)", diff.types_a, diff.types_b, diff.shared);
+            println!("tokens: {} -> {}", diff.tokens_a, diff.tokens_b);
+            println!("correlation: {:.4}", diff.correlation);
+            println!("value\tcount_a\tcount_b\tlog_likelihood");
+            for k in diff.keyness.iter().take(top).filter(|k| k.count_a != k.count_b) {
+                println!("{}\t{}\t{}\t{:.2}", k.value, k.count_a, k.count_b, k.log_likelihood);
+            }
+            (diff.is_identical(), "variables")
+        }
+        None => {
+            let diff = DatastoreDiff::new(&a, &b);
+            let len = |len: Option<usize>| len.map_or("-".to_owned(), |n| n.to_string());
+
+            for layer in diff.layers.iter() {
+                println!("{}: {} -> {}", layer.name, len(layer.len_a), len(layer.len_b));
+                for var in layer.variables.iter() {
+                    match (&var.lexicon, var.in_a, var.in_b) {
+                        (Some(l), _, _) => println!(
+                            "    {}: {} -> {} types, {} shared, r = {:.4}{}",
+                            var.name, l.types_a, l.types_b, l.shared, l.correlation,
+                            if l.is_identical() { "" } else { ", frequencies differ" },
+                        ),
+                        (None, true, false) => println!("    {}: only in first datastore", var.name),
+                        (None, false, true) => println!("    {}: only in second datastore", var.name),
+                        (None, _, _) => println!("    {}: not comparable", var.name),
+                    }
+                }
+            }
+            (diff.is_identical(), "datastores")
+        }
+    };
+
+    match identical {
+        true => Ok(()),
+        false => Err(format!("{} differ", what).into()),
+    }
+}
+
+/// Looks up `<layer>/<variable>`
+fn lookup_variable<'a, 'map>(datastore: &'a Datastore<'map>, target: &str) -> Result<&'a Variable<'map>, Box<dyn Error>> {
+    let (layer, variable) = target.split_once('/').ok_or("expected <layer>/<variable>")?;
+    datastore.layer_by_name(layer)
+        .ok_or_else(|| format!("no layer named {}", layer))?
+        .variable_by_name(variable)
+        .ok_or_else(|| format!("no variable named {}", variable).into())
+}
+
+fn freq(args: &[String]) -> CmdResult {
+    let path = Path::new(args.first().ok_or("missing datastore path")?);
+    let target = args.get(1).ok_or("missing <layer>/<variable>")?;
+    let (layer_name, variable) = target.split_once('/').ok_or("expected <layer>/<variable>")?;
+
+    let mut filters = Vec::new();
+    let mut by = None;
+    let mut top = usize::MAX;
+
+    let mut options = args[2..].iter();
+    while let Some(option) = options.next() {
+        let mut value = || options.next().map(String::as_str).ok_or_else(|| format!("missing value for {}", option));
+        match option.as_str() {
+            "--where" => filters.push(MetadataFilter::parse(value()?)?),
+            "--by" => by = Some(split_qualified(value()?)?),
+            "--top" => top = value()?.parse()?,
+            _ => return Err(format!("unknown option {}", option).into()),
+        }
+    }
+
+    let datastore = Datastore::open(path)?;
+    let layer = datastore.layer_by_name(layer_name).ok_or_else(|| format!("no layer named {}", layer_name))?;
+    let variable = layer.variable_by_name(variable).ok_or_else(|| format!("no variable named {}", variable))?;
+
+    // all filters have to match, None counts the whole layer
+    let mut ranges: Option<Vec<(usize, usize)>> = None;
+    for filter in filters.iter() {
+        let selected = filter.ranges(&datastore, layer)?;
+        ranges = Some(match ranges {
+            Some(ranges) => frequency::intersect_ranges(&ranges, &selected),
+            None => selected,
+        });
+    }
+    let positions = || -> Box<dyn Iterator<Item = usize>> {
+        match ranges.as_ref() {
+            Some(ranges) => Box::new(ranges.iter().flat_map(|&(start, end)| start..end)),
+            None => Box::new(0..variable.len()),
+        }
+    };
+
+    let mut out = io::BufWriter::new(io::stdout().lock());
+
+    if let Some((seg_name, group)) = by {
+        let segmentation = datastore.layer_by_name(seg_name)
+            .and_then(|l| l.as_segmentation())
+            .ok_or_else(|| format!("no segmentation layer named {}", seg_name))?;
+        if segmentation.base != layer.uuid() {
+            return Err(format!("layer {} does not annotate layer {}", seg_name, layer_name).into());
+        }
+
+        for (group, list) in FrequencyList::grouped(variable, segmentation, group, positions())? {
+            for (value, n) in list.top(top) {
+                writeln!(out, "{}\t{}\t{}", group, n, value)?;
+            }
+        }
+    } else {
+        let list = match ranges {
+            Some(_) => FrequencyList::from_positions(variable, positions())?,
+            None => FrequencyList::new(variable)?,
+        };
+        for (value, n) in list.top(top) {
+            writeln!(out, "{}\t{}", n, value)?;
+        }
+    }
+
+    out.flush()?;
+    Ok(())
+}
+
+fn fsck(args: &[String]) -> CmdResult {
+    let mut paths = Vec::new();
+    let mut json = false;
+
+    for arg in args {
+        match arg.as_str() {
+            "--json" => json = true,
+            option if option.starts_with("--") => return Err(format!("unknown option {}", option).into()),
+            path => paths.push(Path::new(path)),
+        }
+    }
+    let path = *paths.first().ok_or("missing datastore path")?;
+
+    // with --json only the report goes to stdout
+    let log = |message: String| if json { eprintln!("{}", message) } else { println!("{}", message) };
+
+    let manifest = Manifest::scan(path)?;
+    if Manifest::read(path).ok().flatten().as_ref() != Some(&manifest) {
+        let _lock = DatastoreLock::try_acquire(path, LockMode::Exclusive)?.ok_or(DatastoreError::Locked)?;
+        manifest.write(path)?;
+        log(format!("wrote manifest with {} containers", manifest.containers.len()));
+    }
+
+    let datastore = Datastore::open(path)?;
+    log(format!("opened datastore with {} layers", datastore.layer_names().len()));
+    for skipped in datastore.skipped_containers() {
+        log(format!("warning: skipped unsupported container {}", skipped));
+    }
+
+    // reads all string data, which takes a while for large corpora
+    let mut variables: Vec<(&String, &String)> = datastore.layer_names()
+        .flat_map(|l| datastore[l.as_str()].variable_names().map(move |v| (l, v)))
+        .collect();
+    variables.sort();
+    let bar = progress_bar(variables.len() as u64, false);
+    for (layer, variable) in variables {
+        bar.set_message(format!("{}.{}", layer, variable));
+        datastore[layer.as_str()][variable.as_str()].validate_utf8()
+            .map_err(|e| format!("{}.{}: {}", layer, variable, e))?;
+        bar.inc(1);
+    }
+    bar.finish_and_clear();
+    log("all strings are valid UTF-8".to_owned());
+
+    let report = datastore.check();
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        for issue in &report.issues {
+            println!("{}", issue);
+        }
+    }
+    if !report.is_ok() {
+        return Err(format!("found {} problems", report.issues.len()).into());
+    }
+    log(format!("checked {} layers and {} variables, no problems found", report.layers, report.variables));
+
+    Ok(())
+}
+
+fn info(args: &[String]) -> CmdResult {
+    let path = Path::new(args.first().ok_or("missing datastore path")?);
+
+    let mut paths = etemenanki::find_containers(path)?;
+    paths.sort();
+
+    for path in paths {
+        let name = path.file_stem().unwrap().to_string_lossy().into_owned();
+        let container = Container::from_storage(storage::open_file(&path)?, name)?;
+        let header = container.header();
+
+        let ctype = match header.try_container_type() {
+            Ok(t) => format!("{:?}", t),
+            Err(_) => format!("unknown {}", String::from_utf8_lossy(&header.type_code())),
+        };
+
+        println!(
+            "{} ({}, {} x {}) {}",
+            path.strip_prefix(&args[0]).unwrap_or(&path).display(),
+            ctype,
+            header.dim1(),
+            header.dim2(),
+            header.uuid(),
+        );
+        if let Some(comment) = container.comment().filter(|c| !c.is_empty()) {
+            println!("    comment: {}", comment);
+        }
+        for (key, value) in container.metadata() {
+            println!("    {} = {}", key, value);
+        }
+    }
+
+    Ok(())
+}
+
+fn migrate(args: &[String]) -> CmdResult {
+    let mut paths = Vec::new();
+    let mut jobs = None;
+
+    let mut options = args.iter();
+    while let Some(arg) = options.next() {
+        match arg.as_str() {
+            "--jobs" => jobs = Some(options.next().ok_or("missing value for --jobs")?.parse()?),
+            option if option.starts_with("--") => return Err(format!("unknown option {}", option).into()),
+            path => paths.push(Path::new(path)),
+        }
+    }
+    if let Some(jobs) = jobs {
+        set_jobs(jobs)?;
+    }
+
+    let input = *paths.first().ok_or("missing input path")?;
+
+    if input.is_dir() {
+        if paths.len() > 1 {
+            return Err("datastores can only be migrated in place".into());
+        }
+
+        let mut containers = etemenanki::find_containers(input)?;
+        containers.sort();
+        let sizes = containers.iter().map(|p| Ok(p.metadata()?.len())).collect::<io::Result<Vec<u64>>>()?;
+
+        let bar = progress_bar(sizes.iter().sum(), true);
+        let migrate_one = |(path, size): (&PathBuf, &u64)| -> io::Result<()> {
+            let version = container::migrate(path, path)?;
+            bar.suspend(|| println!("{}: {} -> {}", path.display(), version, container::Version::CURRENT));
+            bar.inc(*size);
+            Ok(())
+        };
+
+        #[cfg(feature = "parallel")]
+        containers.par_iter().zip(sizes.par_iter()).try_for_each(migrate_one)?;
+        #[cfg(not(feature = "parallel"))]
+        containers.iter().zip(sizes.iter()).try_for_each(migrate_one)?;
+
+        bar.finish_and_clear();
+    } else {
+        let output = paths.get(1).copied().unwrap_or(input);
+        let version = container::migrate(input, output)?;
+        println!("{}: {} -> {}", output.display(), version, container::Version::CURRENT);
+    }
+
+    Ok(())
+}
+
+/// Sets the number of threads of the parallel encoders and scans
+fn set_jobs(jobs: usize) -> CmdResult {
+    #[cfg(feature = "parallel")]
+    rayon::ThreadPoolBuilder::new().num_threads(jobs).build_global()?;
+
+    #[cfg(not(feature = "parallel"))]
+    if jobs > 1 {
+        return Err("--jobs requires the parallel feature".into());
+    }
+
+    Ok(())
+}
+
+/// Progress bar on stderr, hidden if stderr is not a terminal
+#[cfg(feature = "progress")]
+fn progress_bar(len: u64, bytes: bool) -> ProgressBar {
+    let template = match bytes {
+        true => "{wide_bar} {bytes}/{total_bytes} ({eta})",
+        false => "{wide_bar} {pos}/{len} {msg}",
+    };
+    let bar = ProgressBar::new(len);
+    bar.set_style(ProgressStyle::with_template(template).expect("valid template"));
+    bar
+}
+
+#[cfg(not(feature = "progress"))]
+fn progress_bar(_len: u64, _bytes: bool) -> ProgressBar {
+    ProgressBar
+}
+
+/// Stand-in for the indicatif progress bar without the `progress` feature
+#[cfg(not(feature = "progress"))]
+struct ProgressBar;
+
+#[cfg(not(feature = "progress"))]
+impl ProgressBar {
+    fn inc(&self, _delta: u64) {}
+    fn set_message(&self, _message: String) {}
+    fn finish_and_clear(&self) {}
+    fn suspend<F: FnOnce() -> R, R>(&self, f: F) -> R {
+        f()
+    }
+}
+
+/// Splits `layer.variable`
+fn split_qualified(name: &str) -> Result<(&str, &str), Box<dyn Error>> {
+    name.split_once('.').ok_or_else(|| format!("expected <layer>.<variable>, got {}", name).into())
+}
+
+fn query(args: &[String]) -> CmdResult {
+    let path = Path::new(args.first().ok_or("missing datastore path")?);
+    let (expr, options) = match args.get(1) {
+        Some(expr) if !expr.starts_with("--") => (Some(expr), &args[2..]),
+        _ => (None, &args[1..]),
+    };
+
+    let mut layer_name = "primary";
+    let mut show = "word";
+    let mut context = 5;
+    let mut meta = Vec::new();
+    let mut count = false;
+    let mut count_by = None;
+    let mut page_size = None;
+    let mut cursor = None;
+
+    let mut options = options.iter();
+    while let Some(option) = options.next() {
+        let mut value = || options.next().map(String::as_str).ok_or_else(|| format!("missing value for {}", option));
+        match option.as_str() {
+            "--layer" => layer_name = value()?,
+            "--show" => show = value()?,
+            "--context" => context = value()?.parse()?,
+            "--meta" => meta.push(split_qualified(value()?)?),
+            "--count" => count = true,
+            "--count-by" => count_by = Some(split_qualified(value()?)?),
+            "--page-size" => page_size = Some(value()?.parse()?),
+            "--cursor" => cursor = Some(Cursor::from_token(value()?)?),
+            _ => return Err(format!("unknown option {}", option).into()),
+        }
+    }
+    if let Some(cursor) = cursor.as_ref() {
+        layer_name = &cursor.layer;
+    }
+
+    let datastore = Datastore::open(path)?;
+    let layer = datastore.layer_by_name(layer_name).ok_or_else(|| format!("no layer named {}", layer_name))?;
+
+    let mut paginator = Paginator::new(&datastore);
+    if let Ok(cache) = QueryCache::for_datastore(&datastore) {
+        paginator = paginator.cache(cache);
+    }
+    let page = match (cursor.as_ref(), expr, page_size) {
+        (Some(cursor), _, _) => Some(paginator.next(cursor)?),
+        (None, Some(expr), Some(size)) => Some(paginator.first(expr, layer_name, size)?),
+        (None, None, _) => return Err("missing query expression".into()),
+        (None, Some(_), None) => None,
+    };
+    let matches = match page.as_ref() {
+        Some(page) => page.matches.clone(),
+        None => Query::parse(expr.unwrap())?.find_in(&datastore, layer_name)?,
+    };
+    // the cursor goes to stderr, so the TSV on stdout stays intact
+    if let Some(page) = page.as_ref() {
+        eprintln!("{} of {} matches", page.matches.len(), page.total);
+        if let Some(next) = page.next.as_ref() {
+            eprintln!("next page: --cursor {}", next.to_token());
+        }
+    }
+
+    let mut out = io::BufWriter::new(io::stdout().lock());
+
+    if let Some((seg_name, variable)) = count_by {
+        let segmentation = datastore.layer_by_name(seg_name)
+            .and_then(|l| l.as_segmentation())
+            .ok_or_else(|| format!("no segmentation layer named {}", seg_name))?;
+        let counts = segmentation.group_counts(variable, matches.iter().map(|(start, _)| *start))?;
+
+        for (value, n) in counts.groups() {
+            writeln!(out, "{}\t{}", n, value)?;
+        }
+        if counts.ungrouped() > 0 {
+            writeln!(out, "{}\t", counts.ungrouped())?;
+        }
+    } else if count {
+        let words = layer.variable_by_name(show).ok_or_else(|| format!("no variable named {}", show))?;
+        let mut counts: HashMap<String, usize> = HashMap::new();
+        for (start, end) in matches.iter() {
+            let text: Vec<_> = (*start..*end).filter_map(|i| words.get_string(i)).collect();
+            *counts.entry(text.join(" ")).or_default() += 1;
+        }
+
+        let mut counts: Vec<_> = counts.into_iter().collect();
+        counts.sort_unstable_by(|(a, n), (b, m)| m.cmp(n).then_with(|| a.cmp(b)));
+        for (text, n) in counts {
+            writeln!(out, "{}\t{}", n, text)?;
+        }
+    } else {
+        let words = layer.variable_by_name(show).ok_or_else(|| format!("no variable named {}", show))?;
+        let mut concordance = Concordance::new(words, context).format(Format::Tsv);
+
+        let mut header = vec!["start", "end"];
+        if let Some(&(seg_name, _)) = meta.first() {
+            if meta.iter().any(|(l, _)| *l != seg_name) {
+                return Err("all --meta variables must be on the same layer".into());
+            }
+            let segmentation = datastore.layer_by_name(seg_name)
+                .and_then(|l| l.as_segmentation())
+                .ok_or_else(|| format!("no segmentation layer named {}", seg_name))?;
+            let names: Vec<_> = meta.iter().map(|(_, v)| *v).collect();
+            concordance = concordance.metadata(segmentation, &names);
+            header.extend(names);
+        }
+        header.extend(["left", "match", "right"]);
+
+        writeln!(out, "{}", header.join("\t"))?;
+        concordance.write(&mut out, matches)?;
+    }
+
+    out.flush()?;
+    Ok(())
+}
+
+#[cfg(feature = "server")]
+fn serve(args: &[String]) -> CmdResult {
+    let path = Path::new(args.first().ok_or("missing datastore path")?);
+
+    let mut address = "127.0.0.1:8080";
+    let mut max_limit = server::DEFAULT_MAX_LIMIT;
+
+    let mut options = args[1..].iter();
+    while let Some(option) = options.next() {
+        let mut value = || options.next().map(String::as_str).ok_or_else(|| format!("missing value for {}", option));
+        match option.as_str() {
+            "--address" => address = value()?,
+            "--max-limit" => max_limit = value()?.parse()?,
+            _ => return Err(format!("unknown option {}", option).into()),
+        }
+    }
+
+    // writers can't change the datastore while it is served
+    let datastore = Datastore::open_read_only(path)?;
+    let mut api = Api::new(&datastore).max_limit(max_limit);
+    if let Ok(cache) = QueryCache::for_datastore(&datastore) {
+        api = api.cache(cache);
+    }
+    eprintln!("serving {} on http://{}", path.display(), address);
+    server::serve(&api, address)?;
+    Ok(())
+}
+
+#[cfg(not(feature = "server"))]
+fn serve(_args: &[String]) -> CmdResult {
+    Err("serve requires the server feature".into())
+}
+
+#[cfg(feature = "cwb")]
+fn validate_against_cwb(args: &[String]) -> CmdResult {
+    let mut positional = Vec::new();
+    let mut layer = "primary";
+    let mut max_mismatches = 100;
+
+    let mut options = args.iter();
+    while let Some(arg) = options.next() {
+        let mut value = || options.next().map(String::as_str).ok_or_else(|| format!("missing value for {}", arg));
+        match arg.as_str() {
+            "--layer" => layer = value()?,
+            "--max-mismatches" => max_mismatches = value()?.parse()?,
+            option if option.starts_with("--") => return Err(format!("unknown option {}", option).into()),
+            arg => positional.push(arg),
+        }
+    }
+
+    let [path, registry, name] = positional[..] else {
+        return Err("expected <datastore> <registry> <corpus>".into());
+    };
+    let datastore = Datastore::open(path)?;
+    let corpus = libcl_rs::Corpus::new(registry, name).ok_or_else(|| format!("could not open CWB corpus {} in {}", name, registry))?;
+
+    let report = cwb::validate(&datastore, &corpus, layer, max_mismatches)?;
+    println!("checked {}", report.checked.join(", "));
+    for missing in report.missing.iter() {
+        println!("missing: {}", missing);
+    }
+    for mismatch in report.mismatches.iter() {
+        println!("mismatch: {}", mismatch);
+    }
+    if report.total_mismatches > report.mismatches.len() {
+        println!("... {} more mismatches", report.total_mismatches - report.mismatches.len());
+    }
+
+    match report.is_valid() {
+        true => Ok(()),
+        false => Err(format!("{} mismatches, {} attributes missing", report.total_mismatches, report.missing.len()).into()),
+    }
+}
+
+#[cfg(not(feature = "cwb"))]
+fn validate_against_cwb(_args: &[String]) -> CmdResult {
+    Err("validate-against-cwb requires the cwb feature".into())
+}
@@ -0,0 +1,258 @@
+//! Checkpoints of long running datastore builds, so a crashed build can be resumed.
+//!
+//! A [`Build`] encodes the containers of a datastore one after the other and records each
+//! completed container with a hash of its content in the [`BUILD_MANIFEST`] of the
+//! datastore directory. [`Build::resume`] skips the containers recorded by an earlier run
+//! of the same build, as long as their files are unchanged, and encodes the rest.
+//!
+//! Containers usually depend on those encoded before them, e.g. a variable on the UUID of
+//! its layer, so containers must be built in the same order in every run. Once a container
+//! has to be encoded again, all containers following it are encoded again as well.
+//!
+//! ```no_run
+//! # use etemenanki::{build::Build, layers::PrimaryLayer, variables::PlainStringVariable, container::EncodeError};
+//! # fn main() -> Result<(), EncodeError> {
+//! # let words: Vec<String> = Vec::new();
+//! # let input_hash = 0;
+//! let mut build = Build::resume("dickens", input_hash)?;
+//! let primary = build.container("primary", "primary.zigl", |file| {
+//!     PrimaryLayer::encode_to_file(file, words.len(), "primary".into(), "").map(|l| l.uuid())
+//! })?;
+//! build.container("word", "word.zigv", |file| {
+//!     PlainStringVariable::encode_to_file(file, words.iter().cloned(), words.len(), "word".into(), primary, true, "").map(|v| v.uuid())
+//! })?;
+//! println!("{} containers of an earlier run reused", build.skipped());
+//! # Ok(())
+//! # }
+//! ```
+
+use std::fs::{self, File};
+use std::hash::Hasher;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::{error, fmt};
+
+use fnv::FnvHasher;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::container::EncodeError;
+use crate::lock::{DatastoreLock, LockMode, PendingFile};
+
+/// File name of the build manifest within a datastore directory
+pub const BUILD_MANIFEST: &str = "build.json";
+
+/// Containers completed by a build so far
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BuildManifest {
+    /// Hash of the input of the build, as passed to [`Build::new`]
+    pub input: String,
+    pub containers: Vec<BuildEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BuildEntry {
+    pub name: String,
+    /// Path relative to the datastore root
+    pub path: PathBuf,
+    pub uuid: Uuid,
+    /// FNV hash of the container file
+    pub hash: String,
+}
+
+impl BuildManifest {
+    /// Returns the path of the build manifest of the datastore at `datastore`.
+    pub fn path<P: AsRef<Path>>(datastore: P) -> PathBuf {
+        datastore.as_ref().join(BUILD_MANIFEST)
+    }
+
+    /// Reads the build manifest of the datastore at `datastore`, if there is one.
+    pub fn read<P: AsRef<Path>>(datastore: P) -> Result<Option<Self>, BuildError> {
+        let path = Self::path(datastore);
+        if !path.is_file() {
+            return Ok(None);
+        }
+
+        let reader = BufReader::new(File::open(path)?);
+        Ok(Some(serde_json::from_reader(reader)?))
+    }
+
+    /// Replaces the build manifest of the datastore at `datastore`.
+    pub fn write<P: AsRef<Path>>(&self, datastore: P) -> Result<(), BuildError> {
+        let pending = PendingFile::replace(Self::path(datastore))?;
+        let mut writer = BufWriter::new(pending.file()?);
+        serde_json::to_writer_pretty(&mut writer, self)?;
+        writer.write_all(b"\n")?;
+        writer.flush()?;
+        drop(writer);
+
+        pending.commit()?;
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum BuildError {
+    Io(io::Error),
+    Manifest(serde_json::Error),
+    /// The build to be resumed was started from a different input
+    InputChanged,
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{}", e),
+            Self::Manifest(e) => write!(f, "invalid build manifest: {}", e),
+            Self::InputChanged => write!(f, "the build was started from a different input"),
+        }
+    }
+}
+
+impl error::Error for BuildError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            Self::Manifest(e) => Some(e),
+            Self::InputChanged => None,
+        }
+    }
+}
+
+impl From<io::Error> for BuildError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for BuildError {
+    fn from(e: serde_json::Error) -> Self {
+        Self::Manifest(e)
+    }
+}
+
+impl From<BuildError> for EncodeError {
+    fn from(e: BuildError) -> Self {
+        match e {
+            BuildError::Io(e) => Self::Io(e),
+            e => Self::Io(io::Error::other(e)),
+        }
+    }
+}
+
+/// FNV hash of the file at `path` as hex digits
+fn hash_file(path: &Path) -> io::Result<String> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut hasher = FnvHasher::default();
+    let mut buf = [0u8; 64 * 1024];
+    loop {
+        let n = reader.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.write(&buf[..n]);
+    }
+    Ok(format!("{:016x}", hasher.finish()))
+}
+
+/// Build of a datastore which holds the exclusive [lock](crate::lock) of its directory until dropped
+#[derive(Debug)]
+pub struct Build {
+    root: PathBuf,
+    manifest: BuildManifest,
+    /// Containers of an earlier run not yet reached by this one
+    previous: Vec<BuildEntry>,
+    skipped: usize,
+    _lock: DatastoreLock,
+}
+
+impl Build {
+    /// Starts a build in the datastore directory `path`, ignoring the checkpoints of
+    /// earlier builds. `input` identifies the input of the build, e.g. a hash of the source
+    /// files, so a later [`resume`](Self::resume) can tell whether it is still the same.
+    pub fn new<P: AsRef<Path>>(path: P, input: u64) -> Result<Self, BuildError> {
+        Self::start(path.as_ref(), input, false)
+    }
+
+    /// Continues the build in the datastore directory `path`, or starts it if there are no
+    /// checkpoints. Fails with [`BuildError::InputChanged`] if the checkpoints belong to a
+    /// build of another input.
+    pub fn resume<P: AsRef<Path>>(path: P, input: u64) -> Result<Self, BuildError> {
+        Self::start(path.as_ref(), input, true)
+    }
+
+    fn start(root: &Path, input: u64, resume: bool) -> Result<Self, BuildError> {
+        fs::create_dir_all(root)?;
+        let lock = DatastoreLock::acquire(root, LockMode::Exclusive)?;
+
+        let input = format!("{:016x}", input);
+        let previous = match BuildManifest::read(root)? {
+            Some(manifest) if resume => {
+                if manifest.input != input {
+                    return Err(BuildError::InputChanged);
+                }
+                manifest.containers
+            }
+            _ => Vec::new(),
+        };
+
+        // checkpoints to resume from are only replaced as the containers are reached
+        let manifest = BuildManifest { input, containers: Vec::new() };
+        if !resume {
+            manifest.write(root)?;
+        }
+
+        Ok(Self { root: root.to_owned(), manifest, previous, skipped: 0, _lock: lock })
+    }
+
+    /// Encodes the container `name` at `path` relative to the datastore root with `encode`,
+    /// which returns the UUID of the container, unless an earlier run has completed it.
+    ///
+    /// `encode` has to commit the pending file it is passed, which encoders do when it is
+    /// their target. Returns the UUID of the new or the existing container.
+    pub fn container<F, E, P>(&mut self, name: &str, path: P, encode: F) -> Result<Uuid, E>
+    where
+        F: FnOnce(PendingFile) -> Result<Uuid, E>,
+        E: From<BuildError>,
+        P: AsRef<Path>,
+    {
+        let relative = path.as_ref();
+        let path = self.root.join(relative);
+
+        // earlier containers were skipped too, so this one was built on the same base
+        if !self.previous.is_empty() {
+            let entry = self.previous.remove(0);
+            if entry.name == name && entry.path == relative && path.is_file() && hash_file(&path).map_err(BuildError::from)? == entry.hash {
+                return self.complete(entry, true);
+            }
+            self.previous.clear();
+        }
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(BuildError::from)?;
+        }
+        let uuid = encode(PendingFile::replace(&path).map_err(BuildError::from)?)?;
+        let hash = hash_file(&path).map_err(BuildError::from)?;
+        self.complete(BuildEntry { name: name.to_owned(), path: relative.to_owned(), uuid, hash }, false)
+    }
+
+    fn complete<E: From<BuildError>>(&mut self, entry: BuildEntry, skipped: bool) -> Result<Uuid, E> {
+        let uuid = entry.uuid;
+        self.manifest.containers.push(entry);
+        self.manifest.write(&self.root)?;
+        if skipped {
+            self.skipped += 1;
+        }
+        Ok(uuid)
+    }
+
+    /// Number of containers taken from an earlier run
+    pub fn skipped(&self) -> usize {
+        self.skipped
+    }
+
+    /// Containers completed by this build
+    pub fn manifest(&self) -> &BuildManifest {
+        &self.manifest
+    }
+}
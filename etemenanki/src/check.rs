@@ -0,0 +1,221 @@
+//! Consistency checks across the containers of an opened datastore, see [`Datastore::check`].
+//!
+//! Opening a datastore only checks what is needed to connect its containers: every layer
+//! and variable has a base in the datastore and every variable is as long as its layer.
+//! The checks here also read the contents of layers and pointer variables and verify
+//! that all ranges, heads and annotated positions lie within their base layer.
+//!
+//! Problems are reported per container and kind, with the number of offending entries,
+//! the first of them and a suggestion for repairing the datastore. The report can be
+//! serialized, e.g. as JSON by `ziggurat fsck --json`.
+
+use std::collections::HashSet;
+use std::fmt;
+
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::layers::Layer;
+use crate::variables::Variable;
+use crate::Datastore;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IssueKind {
+    /// A container refers to a base that is not part of the datastore
+    UnresolvedBase,
+    /// A variable has a different length than its layer
+    LengthMismatch,
+    /// A range starts after its end
+    InvalidRange,
+    /// A range ends beyond the end of the base layer
+    RangeOutOfBounds,
+    /// Segments that overlap or are not sorted, spans not sorted by their start
+    UnsortedRanges,
+    /// A pointer head beyond the end of the layer
+    PointerOutOfBounds,
+    /// An annotated position of a sparse variable beyond the end of the layer
+    PositionOutOfBounds,
+}
+
+impl IssueKind {
+    fn description(self) -> &'static str {
+        match self {
+            Self::UnresolvedBase => "unresolved base references",
+            Self::LengthMismatch => "length differs from its layer",
+            Self::InvalidRange => "ranges starting after their end",
+            Self::RangeOutOfBounds => "ranges ending beyond the base layer",
+            Self::UnsortedRanges => "ranges out of order",
+            Self::PointerOutOfBounds => "heads beyond the end of the layer",
+            Self::PositionOutOfBounds => "annotated positions beyond the end of the layer",
+        }
+    }
+
+    fn suggestion(self) -> &'static str {
+        match self {
+            Self::UnresolvedBase => "add the missing base container or re-encode the container against a layer of this datastore",
+            Self::LengthMismatch => "re-encode the variable from the current version of its layer",
+            Self::InvalidRange | Self::RangeOutOfBounds => "re-encode the layer from its source, its base layer may have been replaced",
+            Self::UnsortedRanges => "re-encode the layer with its ranges sorted by start",
+            Self::PointerOutOfBounds | Self::PositionOutOfBounds => "re-encode the variable from the current version of its layer",
+        }
+    }
+}
+
+/// All problems of one kind in one container
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Issue {
+    pub kind: IssueKind,
+    /// Name of the layer, `<layer>.<variable>` for variables
+    pub container: String,
+    /// Number of offending entries, e.g. ranges or heads
+    pub count: usize,
+    /// Index of the first offending entry, `None` if the problem concerns the whole container
+    pub first: Option<usize>,
+    pub suggestion: &'static str,
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {} {}", self.container, self.count, self.kind.description())?;
+        if let Some(first) = self.first {
+            write!(f, ", first at {}", first)?;
+        }
+        write!(f, " ({})", self.suggestion)
+    }
+}
+
+/// Result of [`Datastore::check`]
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CheckReport {
+    /// Number of layers checked
+    pub layers: usize,
+    /// Number of variables checked
+    pub variables: usize,
+    /// Problems found, sorted by container and kind
+    pub issues: Vec<Issue>,
+}
+
+impl CheckReport {
+    /// Whether no problems were found
+    pub fn is_ok(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+    /// Counts an offending entry `first` of `container`, or the container as a whole
+    fn note(&mut self, kind: IssueKind, container: &str, first: Option<usize>) {
+        match self.issues.iter_mut().find(|i| i.kind == kind && i.container == container) {
+            Some(issue) => issue.count += 1,
+            None => self.issues.push(Issue {
+                kind,
+                container: container.to_owned(),
+                count: 1,
+                first,
+                suggestion: kind.suggestion(),
+            }),
+        }
+    }
+}
+
+pub(crate) fn check(datastore: &Datastore) -> CheckReport {
+    let mut report = CheckReport::default();
+    let mut known: HashSet<Uuid> = datastore.layer_uuids().copied().collect();
+
+    let mut names: Vec<&String> = datastore.layer_names().collect();
+    names.sort();
+
+    for name in names {
+        let layer = &datastore[name.as_str()];
+        report.layers += 1;
+
+        if let Some(base) = layer.base() {
+            match datastore.layer_by_uuid(base) {
+                Some(base) => check_ranges(&mut report, name, layer, base.len()),
+                None => report.note(IssueKind::UnresolvedBase, name, None),
+            }
+        }
+
+        let mut variables: Vec<&String> = layer.variable_names().collect();
+        variables.sort();
+        for variable in variables {
+            let var = &layer[variable.as_str()];
+            let container = format!("{}.{}", name, variable);
+            report.variables += 1;
+            let Some(uuid) = var.uuid() else {
+                // variable types that can't be read yet have no length either
+                continue;
+            };
+            known.insert(uuid);
+
+            if var.base().is_some_and(|base| base != layer.uuid()) {
+                report.note(IssueKind::UnresolvedBase, &container, None);
+            }
+            if var.len() != layer.len() {
+                report.note(IssueKind::LengthMismatch, &container, None);
+            }
+            check_positions(&mut report, &container, var, layer.len());
+        }
+    }
+
+    let mut extensions: Vec<_> = datastore.extensions.values().collect();
+    extensions.sort_by_key(|c| c.name());
+    for container in extensions {
+        let header = container.header();
+        for base in header.base1().into_iter().chain(header.base2()) {
+            if !known.contains(&base) {
+                report.note(IssueKind::UnresolvedBase, container.name(), None);
+            }
+        }
+    }
+
+    report.issues.sort_by(|a, b| (&a.container, a.kind).cmp(&(&b.container, b.kind)));
+    report
+}
+
+fn check_ranges(report: &mut CheckReport, name: &str, layer: &Layer, base_len: usize) {
+    let (ranges, disjoint) = match layer {
+        Layer::Segmentation(l) => (l.iter(), true),
+        Layer::Span(l) => (l.iter(), false),
+        Layer::Primary(_) => return,
+    };
+
+    let mut previous: Option<(usize, usize)> = None;
+    for (i, (start, end)) in ranges.enumerate() {
+        if start > end {
+            report.note(IssueKind::InvalidRange, name, Some(i));
+        }
+        if end > base_len {
+            report.note(IssueKind::RangeOutOfBounds, name, Some(i));
+        }
+        let unsorted = match previous {
+            Some((_, previous_end)) if disjoint => start < previous_end,
+            Some((previous_start, _)) => start < previous_start,
+            None => false,
+        };
+        if unsorted {
+            report.note(IssueKind::UnsortedRanges, name, Some(i));
+        }
+        previous = Some((start, end));
+    }
+}
+
+fn check_positions(report: &mut CheckReport, container: &str, var: &Variable, len: usize) {
+    match var {
+        Variable::Pointer(var) => {
+            let heads = var.get_range(0, var.len()).into_iter().flatten();
+            for (tail, head) in heads.enumerate() {
+                if head.is_some_and(|head| head >= len) {
+                    report.note(IssueKind::PointerOutOfBounds, container, Some(tail));
+                }
+            }
+        }
+        Variable::Sparse(var) => {
+            for (i, position) in var.positions().enumerate() {
+                if usize::try_from(position).map_or(true, |p| p >= len) {
+                    report.note(IssueKind::PositionOutOfBounds, container, Some(i));
+                }
+            }
+        }
+        _ => (),
+    }
+}
@@ -0,0 +1,227 @@
+//! Comparison of two datastores or two variables, e.g. to check that a re-encoded corpus
+//! matches the original or to find the words characteristic of one corpus.
+//!
+//! A [`LexiconDiff`] compares the frequency lists of two variables: vocabulary overlap,
+//! the Pearson correlation of the frequencies and a keyness report based on the
+//! log-likelihood ratio. A [`DatastoreDiff`] compares the layers and variables of two
+//! datastores by name.
+//!
+//! ```no_run
+//! # use etemenanki::{compare::LexiconDiff, Datastore};
+//! let original = Datastore::open("dickens").unwrap();
+//! let reencoded = Datastore::open("dickens-new").unwrap();
+//! let diff = LexiconDiff::new(&original["primary"]["word"], &reencoded["primary"]["word"]).unwrap();
+//! assert!(diff.is_identical());
+//! ```
+
+use std::collections::HashMap;
+
+use crate::frequency::{FrequencyError, FrequencyList};
+use crate::variables::Variable;
+use crate::Datastore;
+
+/// Frequencies of one value in both variables with its keyness
+#[derive(Debug, Clone, PartialEq)]
+pub struct Keyness {
+    pub value: String,
+    pub count_a: usize,
+    pub count_b: usize,
+    /// Log-likelihood ratio (G²), negative if the value is relatively more frequent in b
+    pub log_likelihood: f64,
+}
+
+/// Comparison of the frequency lists of two variables
+#[derive(Debug, Clone)]
+pub struct LexiconDiff {
+    pub types_a: usize,
+    pub types_b: usize,
+    pub tokens_a: usize,
+    pub tokens_b: usize,
+    /// Number of values occurring in both variables
+    pub shared: usize,
+    /// Pearson correlation of the frequencies over the values of both variables
+    pub correlation: f64,
+    /// All values, sorted by descending absolute log-likelihood
+    pub keyness: Vec<Keyness>,
+}
+
+impl LexiconDiff {
+    /// Compares the values of two variables, which may belong to different datastores.
+    ///
+    /// Values are compared by their string form, so an integer variable can be compared
+    /// with a string variable.
+    pub fn new(a: &Variable, b: &Variable) -> Result<Self, FrequencyError> {
+        let list_a = FrequencyList::new(a)?;
+        let list_b = FrequencyList::new(b)?;
+
+        let mut counts: HashMap<String, (usize, usize)> = HashMap::with_capacity(list_a.len());
+        for (value, n) in list_a.entries() {
+            counts.entry(value.to_string()).or_default().0 += n;
+        }
+        for (value, n) in list_b.entries() {
+            counts.entry(value.to_string()).or_default().1 += n;
+        }
+
+        let tokens_a = list_a.entries().iter().map(|(_, n)| n).sum();
+        let tokens_b = list_b.entries().iter().map(|(_, n)| n).sum();
+
+        let mut keyness: Vec<Keyness> = counts.into_iter()
+            .map(|(value, (count_a, count_b))| Keyness {
+                log_likelihood: log_likelihood(count_a, count_b, tokens_a, tokens_b),
+                value,
+                count_a,
+                count_b,
+            })
+            .collect();
+        keyness.sort_by(|x, y| {
+            y.log_likelihood.abs().total_cmp(&x.log_likelihood.abs())
+                .then_with(|| (y.count_a + y.count_b).cmp(&(x.count_a + x.count_b)))
+                .then_with(|| x.value.cmp(&y.value))
+        });
+
+        Ok(Self {
+            types_a: list_a.len(),
+            types_b: list_b.len(),
+            tokens_a,
+            tokens_b,
+            shared: keyness.iter().filter(|k| k.count_a > 0 && k.count_b > 0).count(),
+            correlation: correlation(keyness.iter().map(|k| (k.count_a as f64, k.count_b as f64))),
+            keyness,
+        })
+    }
+
+    /// Whether both variables have exactly the same values with the same frequencies
+    pub fn is_identical(&self) -> bool {
+        self.keyness.iter().all(|k| k.count_a == k.count_b)
+    }
+
+    /// Values only occurring in a, most frequent first
+    pub fn only_a(&self) -> impl Iterator<Item = &Keyness> {
+        let mut only: Vec<_> = self.keyness.iter().filter(|k| k.count_b == 0).collect();
+        only.sort_by(|x, y| y.count_a.cmp(&x.count_a));
+        only.into_iter()
+    }
+
+    /// Values only occurring in b, most frequent first
+    pub fn only_b(&self) -> impl Iterator<Item = &Keyness> {
+        let mut only: Vec<_> = self.keyness.iter().filter(|k| k.count_a == 0).collect();
+        only.sort_by(|x, y| y.count_b.cmp(&x.count_b));
+        only.into_iter()
+    }
+}
+
+/// Signed log-likelihood ratio of a value occurring `a` times in `total_a` and `b` times in `total_b`
+pub fn log_likelihood(a: usize, b: usize, total_a: usize, total_b: usize) -> f64 {
+    let (a, b, total_a, total_b) = (a as f64, b as f64, total_a as f64, total_b as f64);
+    if total_a == 0.0 || total_b == 0.0 {
+        return 0.0;
+    }
+
+    let expected_a = total_a * (a + b) / (total_a + total_b);
+    let expected_b = total_b * (a + b) / (total_a + total_b);
+    let term = |observed: f64, expected: f64| match observed {
+        o if o > 0.0 => o * (o / expected).ln(),
+        _ => 0.0,
+    };
+    let g2 = 2.0 * (term(a, expected_a) + term(b, expected_b));
+
+    if a / total_a < b / total_b { -g2 } else { g2 }
+}
+
+/// Pearson correlation coefficient, NaN if either side is constant
+fn correlation<I: Iterator<Item = (f64, f64)> + Clone>(pairs: I) -> f64 {
+    let n = pairs.clone().count() as f64;
+    let (sum_x, sum_y) = pairs.clone().fold((0.0, 0.0), |(sx, sy), (x, y)| (sx + x, sy + y));
+    let (mean_x, mean_y) = (sum_x / n, sum_y / n);
+
+    let (mut cov, mut var_x, mut var_y) = (0.0, 0.0, 0.0);
+    for (x, y) in pairs {
+        cov += (x - mean_x) * (y - mean_y);
+        var_x += (x - mean_x).powi(2);
+        var_y += (y - mean_y).powi(2);
+    }
+
+    cov / (var_x * var_y).sqrt()
+}
+
+/// Comparison of a variable present in at least one of two layers
+#[derive(Debug, Clone)]
+pub struct VariableDiff {
+    pub name: String,
+    pub in_a: bool,
+    pub in_b: bool,
+    /// Only for variables present in both layers with countable values
+    pub lexicon: Option<LexiconDiff>,
+}
+
+/// Comparison of a layer present in at least one of two datastores
+#[derive(Debug, Clone)]
+pub struct LayerDiff {
+    pub name: String,
+    pub len_a: Option<usize>,
+    pub len_b: Option<usize>,
+    pub variables: Vec<VariableDiff>,
+}
+
+impl LayerDiff {
+    /// Whether the layer and all of its variables are the same in both datastores
+    pub fn is_identical(&self) -> bool {
+        self.len_a.is_some() && self.len_a == self.len_b && self.variables.iter().all(|v| {
+            v.in_a && v.in_b && v.lexicon.as_ref().map_or(true, LexiconDiff::is_identical)
+        })
+    }
+}
+
+/// Comparison of the layers and variables of two datastores, matched by name
+#[derive(Debug, Clone)]
+pub struct DatastoreDiff {
+    /// Sorted by name
+    pub layers: Vec<LayerDiff>,
+}
+
+impl DatastoreDiff {
+    pub fn new(a: &Datastore, b: &Datastore) -> Self {
+        let mut names: Vec<String> = a.layer_names().chain(b.layer_names()).cloned().collect();
+        names.sort();
+        names.dedup();
+
+        let layers = names.into_iter()
+            .map(|name| {
+                let layer_a = a.layer_by_name(&name);
+                let layer_b = b.layer_by_name(&name);
+
+                let mut var_names: Vec<String> = layer_a.into_iter().flat_map(|l| l.variable_names().cloned())
+                    .chain(layer_b.into_iter().flat_map(|l| l.variable_names().cloned()))
+                    .collect();
+                var_names.sort();
+                var_names.dedup();
+
+                let variables = var_names.into_iter()
+                    .map(|var| {
+                        let var_a = layer_a.and_then(|l| l.variable_by_name(&var));
+                        let var_b = layer_b.and_then(|l| l.variable_by_name(&var));
+                        VariableDiff {
+                            name: var,
+                            in_a: var_a.is_some(),
+                            in_b: var_b.is_some(),
+                            lexicon: var_a.zip(var_b).and_then(|(va, vb)| LexiconDiff::new(va, vb).ok()),
+                        }
+                    })
+                    .collect();
+
+                LayerDiff {
+                    name,
+                    len_a: layer_a.map(|l| l.len()),
+                    len_b: layer_b.map(|l| l.len()),
+                    variables,
+                }
+            })
+            .collect();
+
+        Self { layers }
+    }
+
+    pub fn is_identical(&self) -> bool {
+        self.layers.iter().all(LayerDiff::is_identical)
+    }
+}
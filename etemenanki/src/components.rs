@@ -1,16 +1,27 @@
-pub mod index;
-pub mod inverted_index;
-pub mod set;
-pub mod string_vector;
-pub mod vector;
-
+mod bigram_index;
+mod bitmap;
+mod bloom;
+pub mod elias_fano;
+mod hash_table;
+mod index;
+mod inverted_index;
+mod set;
+mod string_vector;
+mod vector;
+mod wavelet_tree;
+
+pub use bigram_index::*;
+pub use bitmap::*;
+pub use bloom::*;
+pub use hash_table::*;
 pub use index::*;
 pub use inverted_index::*;
 pub use set::*;
 pub use string_vector::*;
 pub use vector::*;
+pub use wavelet_tree::*;
 
-use std::{error, fmt};
+use std::{error, fmt, io::{self, Seek, SeekFrom, Write}, mem, ops, slice};
 
 use enum_as_inner::EnumAsInner;
 use num_enum::{IntoPrimitive, TryFromPrimitive, TryFromPrimitiveError};
@@ -44,7 +55,7 @@ pub enum Component<'map> {
 }
 
 impl<'map> Component<'map> {
-    pub fn from_raw_parts(be: &BomEntry, start_ptr: *const u8) -> Result<Self, ComponentError> {
+    pub(crate) fn from_raw_parts(be: &BomEntry, start_ptr: *const u8) -> Result<Self, ComponentError> {
         let component_type: Type =
             (((be.ctype as u16) << 8) | be.mode as u16).try_into()?;
 
@@ -65,7 +76,7 @@ impl<'map> Component<'map> {
 
                 // check if offsets array is in bounds
                 let len = be.size as usize;
-                let len_offsets = (n + 1) * 8;
+                let len_offsets = n.saturating_add(1).saturating_mul(8);
                 if len_offsets > len {
                     Err(ComponentError::OutOfBounds("offsets in StringVector"))?
                 } else {
@@ -85,6 +96,9 @@ impl<'map> Component<'map> {
                 if d == 0 {
                     return Err(ComponentError::InvalidDimension("d must be > 0"));
                 }
+                if n.checked_mul(d).and_then(|nd| nd.checked_mul(8)).map_or(true, |l| l > be.size as usize) {
+                    return Err(ComponentError::OutOfBounds("data in Vector"));
+                }
                 let data_ptr = start_ptr as *const i64;
                 let data = unsafe { std::slice::from_raw_parts(data_ptr, n * d) };
                 Component::Vector(Vector::uncompressed_from_parts(n, d, data))
@@ -92,8 +106,8 @@ impl<'map> Component<'map> {
 
             Type::VectorComp => {
                 let n = be.param1 as usize;
-                let d = be.param2 as usize;
-                let m = ((n - 1) / 16) + 1;
+                let (d, block_size) = vector::split_param2(be.param2);
+                let m = (n.saturating_sub(1) / block_size) + 1;
 
                 if d == 0 {
                     return Err(ComponentError::InvalidDimension("d must be > 0"));
@@ -110,15 +124,15 @@ impl<'map> Component<'map> {
                         let data_ptr = start_ptr.offset(len_sync as isize);
                         let data = std::slice::from_raw_parts(data_ptr, len - len_sync);
 
-                        Component::Vector(Vector::compressed_from_parts(n, d, sync, data))
+                        Component::Vector(Vector::compressed_from_parts(n, d, sync, data).with_block_size(block_size))
                     }
                 }
             }
 
             Type::VectorDelta => {
                 let n = be.param1 as usize;
-                let d = be.param2 as usize;
-                let m = ((n - 1) / 16) + 1;
+                let (d, block_size) = vector::split_param2(be.param2);
+                let m = (n.saturating_sub(1) / block_size) + 1;
 
                 if d == 0 {
                     return Err(ComponentError::InvalidDimension("d must be > 0"));
@@ -135,7 +149,7 @@ impl<'map> Component<'map> {
                         let data_ptr = start_ptr.offset(len_sync as isize);
                         let data = std::slice::from_raw_parts(data_ptr, len - len_sync);
 
-                        Component::Vector(Vector::delta_from_parts(n, d, sync, data))
+                        Component::Vector(Vector::delta_from_parts(n, d, sync, data).with_block_size(block_size))
                     }
                 }
             }
@@ -143,7 +157,7 @@ impl<'map> Component<'map> {
             Type::Set => {
                 let n = be.param1 as usize;
                 let p = be.param2 as usize;
-                let m = ((n - 1) / 16) + 1;
+                let m = (n.saturating_sub(1) / 16) + 1;
 
                 if p == 0 {
                     return Err(ComponentError::InvalidDimension("p must be > 0"));
@@ -167,6 +181,9 @@ impl<'map> Component<'map> {
 
             Type::Index => {
                 let n = be.param1 as usize;
+                if n.checked_mul(16).map_or(true, |l| l > be.size as usize) {
+                    return Err(ComponentError::OutOfBounds("pairs in Index"));
+                }
                 let pairs_ptr = start_ptr as *const (i64, i64);
                 let pairs = unsafe { std::slice::from_raw_parts(pairs_ptr, n) };
                 Component::Index(Index::uncompressed_from_parts(n, pairs))
@@ -174,13 +191,16 @@ impl<'map> Component<'map> {
 
             Type::IndexComp => {
                 let n = be.param1 as usize;
+                let len = be.size as usize;
+                if len < 8 {
+                    return Err(ComponentError::OutOfBounds("r in IndexComp"));
+                }
                 let r = unsafe { *(start_ptr as *const i64) } as usize;
-                let mr = ((r - 1) / 16) + 1;
+                let mr = (r.saturating_sub(1) / 16) + 1;
 
                 // check if sync array is in bounds
-                let len = be.size as usize;
-                let len_sync = mr * 8 * 2;
-                if len_sync > len {
+                let len_sync = mr.saturating_mul(8 * 2);
+                if len_sync > len - 8 {
                     Err(ComponentError::OutOfBounds("sync in IndexComp"))?
                 } else {
                     unsafe {
@@ -199,7 +219,7 @@ impl<'map> Component<'map> {
 
                 // check if typeinfo array is in bounds
                 let len = be.size as usize;
-                let len_typeinfo = k * 8 * 2;
+                let len_typeinfo = k.saturating_mul(8 * 2);
                 if len_typeinfo > len {
                     Err(ComponentError::OutOfBounds("typeinfo in InvertedIndex"))?
                 } else {
@@ -208,7 +228,11 @@ impl<'map> Component<'map> {
                         let data_ptr = start_ptr.offset((len_typeinfo) as isize);
                         let data = std::slice::from_raw_parts(data_ptr, len - len_typeinfo);
 
-                        Component::InvertedIndex(InvertedIndex::from_parts(k, typeinfo, data))
+                        match PostingsEncoding::from_param(be.param2) {
+                            Some(PostingsEncoding::Delta) => Component::InvertedIndex(InvertedIndex::from_parts(k, typeinfo, data)),
+                            Some(PostingsEncoding::EliasFano) => Component::InvertedIndex(InvertedIndex::elias_fano_from_parts(k, typeinfo, data)),
+                            None => Err(ComponentError::InvalidParameter("postings encoding in InvertedIndex"))?,
+                        }
                     }
                 }
             }
@@ -222,6 +246,7 @@ pub enum ComponentError {
     NullPtr,
     OutOfBounds(&'static str),
     InvalidDimension(&'static str),
+    InvalidParameter(&'static str),
 }
 
 impl From<TryFromPrimitiveError<Type>> for ComponentError {
@@ -237,6 +262,7 @@ impl fmt::Display for ComponentError {
             Self::NullPtr => write!(f, "given pointer is a null pointer"),
             Self::OutOfBounds(s) => write!(f, "component is out of bounds: {}", s),
             Self::InvalidDimension(s) => write!(f, "component has invalid dimension: {}", s),
+            Self::InvalidParameter(s) => write!(f, "component has invalid parameter: {}", s),
         }
     }
 }
@@ -249,6 +275,93 @@ impl error::Error for ComponentError {
     }
 }
 
+/// Access statistics of a block or postings cache.
+///
+/// `hits` and `misses` count lookups in the LRU cache; every miss decodes one block
+/// (or postings list), reading `touched_bytes` of compressed data from the container.
+/// `resident_bytes` is the memory currently held by decoded cache entries.
+/// Uncompressed components are accessed directly and always report zero.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: usize,
+    pub misses: usize,
+    pub decoded_blocks: usize,
+    pub resident_bytes: usize,
+    pub touched_bytes: usize,
+}
+
+impl CacheStats {
+    /// Ratio of cache hits to all lookups, `None` if the cache was never used.
+    pub fn hit_rate(&self) -> Option<f64> {
+        match self.hits + self.misses {
+            0 => None,
+            n => Some(self.hits as f64 / n as f64),
+        }
+    }
+
+    fn record_hit(&mut self) {
+        self.hits += 1;
+    }
+
+    fn record_miss(&mut self, touched_bytes: usize) {
+        self.misses += 1;
+        self.decoded_blocks += 1;
+        self.touched_bytes += touched_bytes;
+    }
+}
+
+impl ops::Add for CacheStats {
+    type Output = Self;
+
+    fn add(mut self, rhs: Self) -> Self::Output {
+        self += rhs;
+        self
+    }
+}
+
+impl ops::AddAssign for CacheStats {
+    fn add_assign(&mut self, rhs: Self) {
+        self.hits += rhs.hits;
+        self.misses += rhs.misses;
+        self.decoded_blocks += rhs.decoded_blocks;
+        self.resident_bytes += rhs.resident_bytes;
+        self.touched_bytes += rhs.touched_bytes;
+    }
+}
+
+impl std::iter::Sum for CacheStats {
+    fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+        iter.fold(Self::default(), |acc, s| acc + s)
+    }
+}
+
+/// Writes `values` to `file` at `offset` in native byte order, i.e. with the in-memory layout
+/// the array has when the container is mapped.
+fn write_array_at<T: Copy, W: Write + Seek>(file: &mut W, offset: u64, values: &[T]) -> io::Result<()> {
+    let bytes = unsafe { slice::from_raw_parts(values.as_ptr() as *const u8, mem::size_of_val(values)) };
+    file.seek(SeekFrom::Start(offset))?;
+    file.write_all(bytes)
+}
+
+/// Returns the encoded length of a block starting at `start`, given the start of the next block.
+fn encoded_block_len(start: usize, next: Option<usize>, data_len: usize) -> usize {
+    match next {
+        Some(end) if end >= start => end - start,
+        _ => data_len.saturating_sub(start),
+    }
+}
+
+/// Returns the encoded data of the blocks in `blocks`, given their sync offsets into `data`.
+fn encoded_blocks<'map>(sync: &[i64], data: &'map [u8], blocks: ops::Range<usize>) -> &'map [u8] {
+    if blocks.is_empty() || blocks.start >= sync.len() {
+        return &data[..0];
+    }
+
+    let start = sync[blocks.start] as usize;
+    let end = sync.get(blocks.end).map(|o| *o as usize).unwrap_or(data.len());
+    &data[start..end]
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct Blob<'map> {
     data: &'map [u8],
@@ -258,6 +371,10 @@ impl<'map> Blob<'map> {
     pub fn from_parts(data: &'map [u8]) -> Self {
         Self { data }
     }
+
+    pub fn data(&self) -> &'map [u8] {
+        self.data
+    }
 }
 
 impl<'map> std::ops::Deref for Blob<'map> {
@@ -0,0 +1,148 @@
+//! Positions of pairs of adjacent types, for phrase queries.
+//!
+//! A bigram index of an indexed string variable consists of two components:
+//! "BigramKeys", an [`Index`] from the [key](BigramIndex::key) of each indexed pair of type
+//! IDs to its bigram ID, and "BigramIndex", an [`InvertedIndex`] from each bigram ID to the
+//! positions of the first type of the pair. A phrase of two types is then found with a
+//! single postings list instead of intersecting two, which pays off most for pairs of
+//! frequent types with long postings lists.
+//!
+//! Indexing every pair roughly doubles the size of the postings of a variable, so pairs
+//! can be limited to types occurring at least a minimum number of times.
+
+use std::collections::HashMap;
+use std::io::{Seek, Write};
+use std::rc::Rc;
+
+use crate::container::{BomEntry, EncodeError};
+
+use super::{CacheStats, CachedIndex, CachedInvertedIndex, Index, InvertedIndex, PostingsEncoding};
+
+#[derive(Debug)]
+pub struct BigramIndex<'map> {
+    keys: CachedIndex<'map>,
+    postings: Rc<CachedInvertedIndex<'map>>,
+    min_frequency: usize,
+}
+
+impl<'map> BigramIndex<'map> {
+    pub fn new(keys: Index<'map>, postings: InvertedIndex<'map>, min_frequency: usize) -> Self {
+        Self {
+            keys: CachedIndex::new(keys),
+            postings: Rc::new(CachedInvertedIndex::new(postings)),
+            min_frequency,
+        }
+    }
+
+    /// Key of the pair of type IDs `(first, second)` in the "BigramKeys" index
+    pub fn key(first: usize, second: usize) -> i64 {
+        ((first as i64) << 32) | second as i64
+    }
+
+    /// Frequency both types of a pair need to have for the pair to be indexed
+    pub fn min_frequency(&self) -> usize {
+        self.min_frequency
+    }
+
+    /// Whether a pair of types with the frequencies `first` and `second` is in the index
+    /// if it occurs at all, i.e. whether a missing pair does not occur
+    pub fn covers(&self, first: usize, second: usize) -> bool {
+        first >= self.min_frequency && second >= self.min_frequency
+    }
+
+    /// Number of indexed pairs
+    pub fn len(&self) -> usize {
+        self.postings.n_types()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// ID of the pair `(first, second)` in [`postings`](Self::postings), `None` if the
+    /// pair is not indexed
+    pub fn bigram_id(&self, first: usize, second: usize) -> Option<usize> {
+        self.keys.get_first(Self::key(first, second)).map(|id| id as usize)
+    }
+
+    /// Number of occurrences of the pair `(first, second)`, 0 if it is not indexed
+    pub fn frequency(&self, first: usize, second: usize) -> usize {
+        self.bigram_id(first, second)
+            .and_then(|id| self.postings.frequency(id))
+            .unwrap_or(0)
+    }
+
+    /// Postings of the pairs by their bigram IDs, each position is that of the first type
+    pub fn postings(&self) -> Rc<CachedInvertedIndex<'map>> {
+        self.postings.clone()
+    }
+
+    pub fn cache_stats(&self) -> CacheStats {
+        self.keys.cache_stats() + self.postings.cache_stats()
+    }
+}
+
+/// Collects the pairs of adjacent types of an ID stream and encodes their bigram index
+#[derive(Debug, Clone)]
+pub struct BigramBuilder {
+    ids: HashMap<i64, i64>,
+    min_frequency: usize,
+}
+
+impl BigramBuilder {
+    /// Assigns bigram IDs in order of first occurrence to the pairs of adjacent types in
+    /// `id_stream` whose types both have at least `min_frequency` of the `frequencies`.
+    pub fn new<I: Iterator<Item = i64>>(id_stream: I, frequencies: &[usize], min_frequency: usize) -> Self {
+        let mut builder = Self { ids: HashMap::new(), min_frequency };
+        let covered = |t: i64| frequencies[t as usize] >= min_frequency;
+
+        for (first, second) in Self::pairs(id_stream) {
+            if covered(first) && covered(second) {
+                let next = builder.ids.len() as i64;
+                builder.ids.entry(BigramIndex::key(first as usize, second as usize)).or_insert(next);
+            }
+        }
+
+        builder
+    }
+
+    /// Pairs of the ID at each position but the last and the next ID
+    fn pairs<I: Iterator<Item = i64>>(id_stream: I) -> impl Iterator<Item = (i64, i64)> {
+        id_stream
+            .scan(None, |previous, id| Some(previous.replace(id).map(|first| (first, id))))
+            .flatten()
+    }
+
+    /// Number of indexed pairs
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    pub fn min_frequency(&self) -> usize {
+        self.min_frequency
+    }
+
+    /// Writes the "BigramKeys" component
+    pub unsafe fn write_keys<W: Write + Seek>(&self, file: &mut W, bom_entry: &mut BomEntry, start_offset: u64) -> Result<(), EncodeError> {
+        let mut pairs: Vec<(i64, i64)> = self.ids.iter().map(|(k, v)| (*k, *v)).collect();
+        pairs.sort_unstable();
+        Index::encode_uncompressed_to_container_file(pairs.into_iter(), self.len(), file, bom_entry, start_offset)
+    }
+
+    /// Writes the "BigramIndex" component for the ID stream of `n` positions the pairs were
+    /// collected from, see [`InvertedIndex::encode_to_container_file_with_budget`]
+    pub fn write_index<F, I, W>(&self, id_stream: F, n: usize, encoding: PostingsEncoding, memory_budget: usize, file: &mut W, bom_entry: &mut BomEntry, start_offset: u64) -> Result<(), EncodeError>
+    where
+        F: Fn() -> I,
+        I: Iterator<Item = i64>,
+        W: Write + Seek,
+    {
+        let bigram_ids = || Self::pairs(id_stream())
+            .map(|(first, second)| self.ids.get(&BigramIndex::key(first as usize, second as usize)).copied().unwrap_or(-1));
+        InvertedIndex::encode_to_container_file_with_budget(self.len(), bigram_ids, n.saturating_sub(1), encoding, memory_budget, file, bom_entry, start_offset)
+    }
+}
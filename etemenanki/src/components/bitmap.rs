@@ -0,0 +1,168 @@
+use std::io::{Seek, Write};
+
+use crate::container::{BomEntry, EncodeError};
+
+use super::Vector;
+
+/// Succinct bitmap with constant time rank, stored as an uncompressed [`Vector`].
+///
+/// Each row of the vector covers 512 bits: the number of set bits before the row
+/// followed by eight 64 bit words. Rank needs a single row, select searches the
+/// rank samples and then scans one row.
+#[derive(Debug, Clone, Copy)]
+pub struct RankBitmap<'map> {
+    data: &'map [i64],
+    ones: usize,
+}
+
+impl<'map> RankBitmap<'map> {
+    pub const ROW_BITS: usize = 512;
+    pub const WIDTH: usize = 1 + Self::ROW_BITS / 64;
+
+    /// Wraps an uncompressed vector of width [`WIDTH`](Self::WIDTH)
+    pub fn from_vector(vector: Vector<'map>) -> Option<Self> {
+        match vector {
+            Vector::Uncompressed { length, width: Self::WIDTH, data } if data.len() == length * Self::WIDTH => {
+                let ones = match data.rchunks_exact(Self::WIDTH).next() {
+                    Some(row) => row[0] as usize + row[1..].iter().map(|w| w.count_ones() as usize).sum::<usize>(),
+                    None => 0,
+                };
+                Some(Self { data, ones })
+            }
+            _ => None,
+        }
+    }
+
+    /// Number of bits, a multiple of [`ROW_BITS`](Self::ROW_BITS)
+    pub fn len(&self) -> usize {
+        self.data.len() / Self::WIDTH * Self::ROW_BITS
+    }
+
+    pub fn count_ones(&self) -> usize {
+        self.ones
+    }
+
+    fn row(&self, row: usize) -> &'map [i64] {
+        &self.data[row * Self::WIDTH..(row + 1) * Self::WIDTH]
+    }
+
+    /// Whether bit `position` is set, bits beyond the end are unset
+    pub fn get(&self, position: usize) -> bool {
+        if position >= self.len() {
+            return false;
+        }
+        let word = self.row(position / Self::ROW_BITS)[1 + (position % Self::ROW_BITS) / 64];
+        (word as u64 >> (position % 64)) & 1 == 1
+    }
+
+    /// Number of set bits before `position`
+    pub fn rank(&self, position: usize) -> usize {
+        if position >= self.len() {
+            return self.ones;
+        }
+
+        let row = self.row(position / Self::ROW_BITS);
+        let offset = position % Self::ROW_BITS;
+        let full = row[1..1 + offset / 64].iter().map(|w| w.count_ones() as usize).sum::<usize>();
+        let partial = (row[1 + offset / 64] as u64 & ((1u64 << (offset % 64)) - 1)).count_ones() as usize;
+
+        row[0] as usize + full + partial
+    }
+
+    /// Position of the set bit with rank `k`, i.e. the `k`-th set bit counting from 0
+    pub fn select(&self, k: usize) -> Option<usize> {
+        if k >= self.ones {
+            return None;
+        }
+
+        // last row with fewer than k + 1 set bits before it
+        let (mut lo, mut hi) = (0, self.data.len() / Self::WIDTH);
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            if self.row(mid)[0] as usize <= k {
+                lo = mid + 1;
+            } else {
+                hi = mid;
+            }
+        }
+        let row_index = lo - 1;
+        let row = self.row(row_index);
+
+        let mut remaining = k - row[0] as usize;
+        for (i, &word) in row[1..].iter().enumerate() {
+            let mut word = word as u64;
+            let ones = word.count_ones() as usize;
+            if remaining < ones {
+                for _ in 0..remaining {
+                    word &= word - 1;
+                }
+                return Some(row_index * Self::ROW_BITS + i * 64 + word.trailing_zeros() as usize);
+            }
+            remaining -= ones;
+        }
+
+        unreachable!("rank samples are inconsistent with the bitmap")
+    }
+
+    /// Position of the unset bit with rank `k` among the unset bits, counting from 0.
+    /// The padding after the last set bit counts as unset.
+    pub fn select_zero(&self, k: usize) -> Option<usize> {
+        if k >= self.len() - self.ones {
+            return None;
+        }
+
+        let zeros_before = |row: usize| row * Self::ROW_BITS - self.row(row)[0] as usize;
+        let (mut lo, mut hi) = (0, self.data.len() / Self::WIDTH);
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            if zeros_before(mid) <= k {
+                lo = mid + 1;
+            } else {
+                hi = mid;
+            }
+        }
+        let row_index = lo - 1;
+        let row = self.row(row_index);
+
+        let mut remaining = k - zeros_before(row_index);
+        for (i, &word) in row[1..].iter().enumerate() {
+            let mut word = !(word as u64);
+            let zeros = word.count_ones() as usize;
+            if remaining < zeros {
+                for _ in 0..remaining {
+                    word &= word - 1;
+                }
+                return Some(row_index * Self::ROW_BITS + i * 64 + word.trailing_zeros() as usize);
+            }
+            remaining -= zeros;
+        }
+
+        unreachable!("rank samples are inconsistent with the bitmap")
+    }
+
+    /// Builds the rows of a bitmap with the given strictly ascending positions set
+    pub fn build_rows<I>(positions: I) -> Vec<i64> where I: IntoIterator<Item = usize> {
+        let mut rows: Vec<i64> = Vec::new();
+        let mut ones = 0;
+
+        for position in positions {
+            let row = position / Self::ROW_BITS;
+            while rows.len() <= row * Self::WIDTH {
+                rows.push(ones as i64);
+                rows.extend([0; Self::WIDTH - 1]);
+            }
+            let word = &mut rows[row * Self::WIDTH + 1 + (position % Self::ROW_BITS) / 64];
+            *word |= (1u64 << (position % 64)) as i64;
+            ones += 1;
+        }
+
+        rows
+    }
+
+    /// Encodes a bitmap with the given strictly ascending positions set
+    pub unsafe fn encode_to_container_file<I, W: Write + Seek>(positions: I, file: &mut W, bom_entry: &mut BomEntry, start_offset: u64) -> Result<(), EncodeError> where I: IntoIterator<Item = usize> {
+        let rows = Self::build_rows(positions);
+        let n = rows.len() / Self::WIDTH;
+        Vector::encode_uncompressed_to_container_file(rows.into_iter(), n, Self::WIDTH, file, bom_entry, start_offset)
+    }
+}
@@ -0,0 +1,85 @@
+use std::io::{Seek, Write};
+
+use crate::container::{BomEntry, EncodeError};
+
+use super::Vector;
+
+/// Bloom filter over the keys of an [`Index`](super::Index), stored as an uncompressed
+/// [`Vector`] of width 1.
+///
+/// The first row holds the number of hash functions, the remaining rows are the bits.
+/// A key is absent if any of its bits is unset, so most lookups of absent keys are
+/// answered without searching the index. With [`BITS_PER_KEY`](Self::BITS_PER_KEY) bits
+/// per distinct key about 1% of absent keys pass the filter.
+#[derive(Debug, Clone, Copy)]
+pub struct BloomFilter<'map> {
+    hashes: u32,
+    words: &'map [i64],
+}
+
+impl<'map> BloomFilter<'map> {
+    pub const BITS_PER_KEY: usize = 10;
+    /// Number of hash functions minimizing false positives at [`BITS_PER_KEY`](Self::BITS_PER_KEY)
+    pub const HASHES: u32 = 7;
+
+    /// Wraps an uncompressed vector of width 1, `None` if it is not a bloom filter
+    pub fn from_vector(vector: Vector<'map>) -> Option<Self> {
+        match vector {
+            Vector::Uncompressed { length, width: 1, data } if length >= 2 && data.len() == length => {
+                let hashes = u32::try_from(data[0]).ok().filter(|h| *h > 0)?;
+                Some(Self { hashes, words: &data[1..] })
+            }
+            _ => None,
+        }
+    }
+
+    /// Number of bits
+    pub fn len(&self) -> usize {
+        self.words.len() * 64
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.words.is_empty()
+    }
+
+    /// Bits of `key` among `bits`, by double hashing two mixes of the key
+    fn bits(key: i64, hashes: u32, bits: usize) -> impl Iterator<Item = usize> {
+        let h1 = mix(key as u64);
+        let h2 = mix(h1) | 1;
+        (0..hashes as u64).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % bits as u64) as usize)
+    }
+
+    /// Whether `key` may be in the index, `false` only if it is certainly not
+    pub fn may_contain(&self, key: i64) -> bool {
+        Self::bits(key, self.hashes, self.len())
+            .all(|bit| (self.words[bit / 64] as u64 >> (bit % 64)) & 1 == 1)
+    }
+
+    /// Builds the rows of a filter over `keys`, of which `n` are distinct
+    pub fn build_rows<I>(keys: I, n: usize) -> Vec<i64> where I: IntoIterator<Item = i64> {
+        let words = (n * Self::BITS_PER_KEY).div_ceil(64).max(1);
+        let mut rows = vec![0; words + 1];
+        rows[0] = Self::HASHES as i64;
+
+        for key in keys {
+            for bit in Self::bits(key, Self::HASHES, words * 64) {
+                rows[1 + bit / 64] |= 1 << (bit % 64);
+            }
+        }
+        rows
+    }
+
+    /// Encodes a filter over `keys`, of which `n` are distinct
+    pub unsafe fn encode_to_container_file<I, W: Write + Seek>(keys: I, n: usize, file: &mut W, bom_entry: &mut BomEntry, start_offset: u64) -> Result<(), EncodeError> where I: IntoIterator<Item = i64> {
+        let rows = Self::build_rows(keys, n);
+        let len = rows.len();
+        Vector::encode_uncompressed_to_container_file(rows.into_iter(), len, 1, file, bom_entry, start_offset)
+    }
+}
+
+/// Finalizer of SplitMix64, spreads keys that differ in few bits over all bits
+fn mix(x: u64) -> u64 {
+    let x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    let x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^ (x >> 31)
+}
@@ -0,0 +1,303 @@
+//! Elias-Fano coding of postings lists.
+//!
+//! A list of `n` ascending positions below `u` splits every position into `l = log2(u / n)`
+//! low bits, stored verbatim, and the remaining high bits, stored in unary as the gaps
+//! between set bits of a bit array of `n + (u >> l)` bits. This takes at most
+//! `2 + log2(u / n)` bits per position independent of the distribution of the gaps, and a
+//! position is decoded with a few shifts instead of a chain of varint reads.
+//!
+//! Each list is laid out as
+//!
+//! | bytes             | content                                                   |
+//! |-------------------|-----------------------------------------------------------|
+//! | 1                 | `l`                                                       |
+//! | 8 × (n - 1) / 128 | skip pointers, bit offsets in `high` of every 128th value |
+//! | ⌈n × l / 8⌉       | `low`, the low bits of each position                      |
+//! | rest              | `high`, the unary coded high bits                         |
+//!
+//! with all bits and integers in little endian order. The skip pointers allow jumping to
+//! any index and seeking to a position without decoding the list up to that point.
+//! An empty list takes no bytes at all.
+
+/// Number of values between two skip pointers
+pub const SKIP_QUANTUM: usize = 128;
+
+/// Number of low bits for `n` positions of which the last is `last`
+pub(crate) fn low_bits(n: usize, last: usize) -> u8 {
+    match (last + 1) / n.max(1) {
+        0 => 0,
+        ratio => ratio.ilog2() as u8,
+    }
+}
+
+/// Encoded size in bytes of `n` positions of which the last is `last`
+pub(crate) fn encoded_len(n: usize, last: usize) -> usize {
+    if n == 0 {
+        return 0;
+    }
+    let l = low_bits(n, last) as usize;
+    let (skips, low) = layout(n, l);
+    1 + skips + low + ((last >> l) + n).div_ceil(8)
+}
+
+/// Byte lengths of the skip pointers and of the low bits of a non-empty list
+fn layout(n: usize, l: usize) -> (usize, usize) {
+    (8 * ((n - 1) / SKIP_QUANTUM), (n * l).div_ceil(8))
+}
+
+/// Writes the `k`th of `n` positions into the zeroed list `buffer`.
+///
+/// Positions may be written in any order, but each must be written once.
+pub(crate) fn put(buffer: &mut [u8], n: usize, l: u8, k: usize, position: usize) {
+    let l = l as usize;
+    let (skips, low) = layout(n, l);
+    buffer[0] = l as u8;
+
+    let mut value = position & ((1 << l) - 1);
+    let mut offset = k * l;
+    let low_bytes = &mut buffer[1 + skips..1 + skips + low];
+    while value != 0 {
+        low_bytes[offset / 8] |= (value << (offset % 8)) as u8;
+        let written = 8 - offset % 8;
+        value >>= written;
+        offset += written;
+    }
+
+    let bit = (position >> l) + k;
+    buffer[1 + skips + low + bit / 8] |= 1 << (bit % 8);
+
+    if k > 0 && k.is_multiple_of(SKIP_QUANTUM) {
+        let s = 1 + 8 * (k / SKIP_QUANTUM - 1);
+        buffer[s..s + 8].copy_from_slice(&(bit as u64).to_le_bytes());
+    }
+}
+
+/// Encodes a whole list of ascending positions
+pub fn encode(positions: &[usize]) -> Vec<u8> {
+    let n = positions.len();
+    let Some(&last) = positions.last() else {
+        return Vec::new();
+    };
+    let l = low_bits(n, last);
+
+    let mut buffer = vec![0; encoded_len(n, last)];
+    for (k, &position) in positions.iter().enumerate() {
+        put(&mut buffer, n, l, k, position);
+    }
+    buffer
+}
+
+/// An Elias-Fano coded list of positions with random access
+#[derive(Debug, Clone, Copy)]
+pub struct EliasFano<'map> {
+    len: usize,
+    l: usize,
+    skips: &'map [u8],
+    low: &'map [u8],
+    high: &'map [u8],
+}
+
+impl<'map> EliasFano<'map> {
+    /// Reads a list of `len` positions from `data`, which must not extend beyond the list
+    pub fn new(len: usize, data: &'map [u8]) -> Self {
+        if len == 0 {
+            return Self { len, l: 0, skips: &[], low: &[], high: &[] };
+        }
+
+        let l = data[0] as usize;
+        let (skips, low) = layout(len, l);
+        let (skips, rest) = data[1..].split_at(skips);
+        let (low, high) = rest.split_at(low);
+
+        Self { len, l, skips, low, high }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the position at `index`
+    pub fn get(&self, index: usize) -> Option<usize> {
+        if index >= self.len {
+            return None;
+        }
+        let (skip, start) = self.skip_to(index);
+        let bit = select(self.high, start, index - skip)?;
+        Some(self.value(index, bit))
+    }
+
+    /// Iterator over all positions
+    pub fn iter(&self) -> EliasFanoIter<'map> {
+        self.iter_from(0)
+    }
+
+    /// Iterator over the positions from `index` on
+    pub fn iter_from(&self, index: usize) -> EliasFanoIter<'map> {
+        if index >= self.len {
+            return EliasFanoIter { list: *self, index: self.len, byte: 0, word: 0 };
+        }
+        let (skip, start) = self.skip_to(index);
+        let bit = select(self.high, start, index - skip).unwrap_or(0);
+        let byte = bit / 8;
+        EliasFanoIter { list: *self, index, byte, word: word_at(self.high, byte) & (u64::MAX << (bit % 8)) }
+    }
+
+    /// Index and value of the first position that is at least `target`.
+    ///
+    /// The skip pointers are searched first, so at most [`SKIP_QUANTUM`] positions are
+    /// decoded sequentially.
+    pub fn next_geq(&self, target: usize) -> Option<(usize, usize)> {
+        // last skip pointer whose position is below target
+        let (mut lo, mut hi) = (0, self.skips.len() / 8);
+        while lo < hi {
+            let mid = (lo + hi).div_ceil(2);
+            let index = mid * SKIP_QUANTUM;
+            let bit = self.skip(mid);
+            if self.value(index, bit) < target {
+                lo = mid;
+            } else {
+                hi = mid - 1;
+            }
+        }
+
+        let start = lo * SKIP_QUANTUM;
+        self.iter_from(start)
+            .enumerate()
+            .find(|(_, position)| *position >= target)
+            .map(|(i, position)| (start + i, position))
+    }
+
+    /// Decodes all positions
+    pub fn decode(&self) -> Vec<usize> {
+        let mut positions = Vec::with_capacity(self.len);
+        if self.l > 56 {
+            positions.extend(self.iter());
+            return positions;
+        }
+
+        // walks the set bits of `high` word by word, a single unaligned read covers any low bits
+        let mask = if self.l == 0 { 0 } else { u64::MAX >> (64 - self.l) };
+        let mut low_offset = 0;
+        for (i, chunk) in self.high.chunks(8).enumerate() {
+            let mut word = word_at(chunk, 0);
+            while word != 0 && positions.len() < self.len {
+                let high = i * 64 + word.trailing_zeros() as usize - positions.len();
+                let low = (word_at(self.low, low_offset / 8) >> (low_offset % 8)) & mask;
+                positions.push((high << self.l) | low as usize);
+                low_offset += self.l;
+                word &= word - 1;
+            }
+        }
+        positions
+    }
+
+    /// Bit offset in `high` of the value at index `j * SKIP_QUANTUM`
+    fn skip(&self, j: usize) -> usize {
+        if j == 0 {
+            return 0;
+        }
+        let s = 8 * (j - 1);
+        u64::from_le_bytes(self.skips[s..s + 8].try_into().unwrap()) as usize
+    }
+
+    /// Index of the closest skip pointer at or before `index` and the bit offset to search from
+    fn skip_to(&self, index: usize) -> (usize, usize) {
+        let j = index / SKIP_QUANTUM;
+        (j * SKIP_QUANTUM, self.skip(j))
+    }
+
+    fn value(&self, index: usize, bit: usize) -> usize {
+        ((bit - index) << self.l) | read_bits(self.low, index * self.l, self.l)
+    }
+}
+
+/// Iterator returned by [`EliasFano::iter`]
+#[derive(Debug, Clone)]
+pub struct EliasFanoIter<'map> {
+    list: EliasFano<'map>,
+    index: usize,
+    /// Unread set bits of the 64 bits of `high` starting at `byte`
+    byte: usize,
+    word: u64,
+}
+
+impl<'map> Iterator for EliasFanoIter<'map> {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.index >= self.list.len {
+            return None;
+        }
+        while self.word == 0 {
+            self.byte += 8;
+            if self.byte >= self.list.high.len() {
+                return None;
+            }
+            self.word = word_at(self.list.high, self.byte);
+        }
+
+        let bit = self.byte * 8 + self.word.trailing_zeros() as usize;
+        self.word &= self.word - 1;
+        let value = self.list.value(self.index, bit);
+        self.index += 1;
+        Some(value)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.list.len - self.index.min(self.list.len);
+        (remaining, Some(remaining))
+    }
+}
+
+impl<'map> ExactSizeIterator for EliasFanoIter<'map> {}
+
+/// Up to 8 bytes starting at `byte` as a little endian word, zero padded at the end
+#[inline]
+fn word_at(data: &[u8], byte: usize) -> u64 {
+    if let Some(bytes) = data.get(byte..byte + 8) {
+        return u64::from_le_bytes(bytes.try_into().unwrap());
+    }
+    let mut bytes = [0u8; 8];
+    let available = data.len().saturating_sub(byte).min(8);
+    bytes[..available].copy_from_slice(&data[byte..byte + available]);
+    u64::from_le_bytes(bytes)
+}
+
+#[inline]
+fn read_bits(data: &[u8], offset: usize, width: usize) -> usize {
+    if width == 0 {
+        return 0;
+    }
+    let shift = offset % 8;
+    let mut value = word_at(data, offset / 8) >> shift;
+    if width + shift > 64 {
+        value |= word_at(data, offset / 8 + 8) << (64 - shift);
+    }
+    (value & (u64::MAX >> (64 - width))) as usize
+}
+
+/// Offset of the `k`th set bit at or after bit `from`
+fn select(data: &[u8], from: usize, mut k: usize) -> Option<usize> {
+    let mut byte = from / 8;
+    let mut word = word_at(data, byte) & (u64::MAX << (from % 8));
+
+    loop {
+        let ones = word.count_ones() as usize;
+        if k < ones {
+            for _ in 0..k {
+                word &= word - 1;
+            }
+            return Some(byte * 8 + word.trailing_zeros() as usize);
+        }
+        k -= ones;
+        byte += 8;
+        if byte >= data.len() {
+            return None;
+        }
+        word = word_at(data, byte);
+    }
+}
@@ -0,0 +1,84 @@
+use std::io::{Seek, Write};
+
+use crate::container::{BomEntry, EncodeError};
+
+use super::Vector;
+
+/// Open addressing hash table from the FNV hashes of strings to their lexicon IDs, stored
+/// as an uncompressed [`Vector`] of width 2 with a `(hash, id)` row per slot.
+///
+/// The number of slots is a power of two and empty slots have the ID -1. A lookup starts at
+/// the slot given by the low bits of the hash and probes the following slots until it
+/// reaches an empty one, which at the load factor of at most
+/// [`MAX_LOAD`](Self::MAX_LOAD) touches one or two pages of the memory map. Unlike the
+/// sorted [`Index`](super::Index) nothing has to be searched or cached, which keeps
+/// lookups in lexicons with hundreds of millions of types cheap.
+#[derive(Debug, Clone, Copy)]
+pub struct HashTable<'map> {
+    slots: &'map [i64],
+    mask: usize,
+}
+
+impl<'map> HashTable<'map> {
+    /// Highest ratio of keys to slots
+    pub const MAX_LOAD: f64 = 0.75;
+
+    /// Wraps an uncompressed vector of width 2, `None` if it is not a hash table
+    pub fn from_vector(vector: Vector<'map>) -> Option<Self> {
+        match vector {
+            Vector::Uncompressed { length, width: 2, data } if length.is_power_of_two() && data.len() == 2 * length => {
+                Some(Self { slots: data, mask: length - 1 })
+            }
+            _ => None,
+        }
+    }
+
+    /// Number of slots
+    pub fn capacity(&self) -> usize {
+        self.mask + 1
+    }
+
+    /// Number of slots for `n` keys
+    pub fn capacity_for(n: usize) -> usize {
+        ((n as f64 / Self::MAX_LOAD) as usize + 1).next_power_of_two()
+    }
+
+    /// IDs stored with `hash`, several if different strings have the same hash
+    pub fn get_all(&self, hash: i64) -> impl Iterator<Item = usize> + 'map {
+        let slots = self.slots;
+        let mask = self.mask;
+        (0..=mask)
+            .map(move |i| (hash as usize).wrapping_add(i) & mask)
+            .map(move |slot| (slots[2 * slot], slots[2 * slot + 1]))
+            .take_while(|(_, id)| *id >= 0)
+            .filter(move |(h, _)| *h == hash)
+            .map(|(_, id)| id as usize)
+    }
+
+    /// Builds the rows of a table over the `(hash, id)` pairs, of which there are `n`
+    pub fn build_rows<I>(pairs: I, n: usize) -> Vec<i64> where I: IntoIterator<Item = (i64, i64)> {
+        let capacity = Self::capacity_for(n);
+        let mask = capacity - 1;
+        let mut rows = vec![0; 2 * capacity];
+        for slot in rows.chunks_exact_mut(2) {
+            slot[1] = -1;
+        }
+
+        for (hash, id) in pairs {
+            let mut slot = hash as usize & mask;
+            while rows[2 * slot + 1] >= 0 {
+                slot = (slot + 1) & mask;
+            }
+            rows[2 * slot] = hash;
+            rows[2 * slot + 1] = id;
+        }
+        rows
+    }
+
+    /// Encodes a table over the `(hash, id)` pairs, of which there are `n`
+    pub unsafe fn encode_to_container_file<I, W: Write + Seek>(pairs: I, n: usize, file: &mut W, bom_entry: &mut BomEntry, start_offset: u64) -> Result<(), EncodeError> where I: IntoIterator<Item = (i64, i64)> {
+        let rows = Self::build_rows(pairs, n);
+        let len = rows.len() / 2;
+        Vector::encode_uncompressed_to_container_file(rows.into_iter(), len, 2, file, bom_entry, start_offset)
+    }
+}
@@ -1,12 +1,13 @@
 use core::hash::Hasher;
-use std::{cell::RefCell, cmp::min, fs::File, io::{self, BufWriter, Seek, SeekFrom, Write}, mem, num::NonZeroUsize, rc::Rc, slice};
+use std::{cell::RefCell, cmp::{min, Reverse}, collections::BinaryHeap, fs::File, io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write}, mem, num::NonZeroUsize, rc::Rc, vec};
 
 use fnv::FnvHasher;
 use lru::LruCache;
-use memmap2::MmapOptions;
 use ziggurat_varint::EncodeVarint;
 
-use crate::container::BomEntry;
+use crate::container::{BomEntry, EncodeError};
+
+use super::{encoded_block_len, write_array_at, BloomFilter, CacheStats};
 
 pub trait FnvHash {
     fn fnv_hash(&self) -> i64;
@@ -74,7 +75,7 @@ impl<'map> Index<'map> {
     }
 
     #[inline]
-    pub fn get_all(&self, key: i64) -> IndexIterator {
+    pub fn get_all(&self, key: i64) -> IndexIterator<'map> {
         IndexIterator::new(*self, key)
     }
 
@@ -95,10 +96,13 @@ impl<'map> Index<'map> {
         }
     }
 
+    /// Returns the position of the first pair with `key`.
     fn position(pairs: &[(i64, i64)], key: i64) -> Option<usize> {
-        match pairs.binary_search_by_key(&key, |(k, _)| *k) {
-            Ok(i) => Some(i),
-            Err(_) => None,
+        // binary_search() may hit any of several equal keys, so look for the first one
+        let i = pairs.partition_point(|(k, _)| *k < key);
+        match pairs.get(i) {
+            Some((k, _)) if *k == key => Some(i),
+            _ => None,
         }
     }
 
@@ -106,28 +110,24 @@ impl<'map> Index<'map> {
         Self::Uncompressed { length: n, pairs }
     }
 
-    pub unsafe fn encode_compressed_to_container_file<I>(values: I, n: usize, file: &mut File, bom_entry: &mut BomEntry, start_offset: u64) where I: Iterator<Item=(i64, i64)> {
+    pub unsafe fn encode_compressed_to_container_file<I, W: Write + Seek>(values: I, n: usize, file: &mut W, bom_entry: &mut BomEntry, start_offset: u64) -> Result<(), EncodeError> where I: Iterator<Item=(i64, i64)> {
         const INTSIZE: usize =  mem::size_of::<i64>();
         let m = (n-1) / 16 + 1; // worst case number of blocks = no overflow items
-        let headlen = INTSIZE + (m * 2 * INTSIZE);
-
-        // map constant header
-        file.set_len(start_offset + headlen as u64).unwrap();
-        let mut mmap = unsafe { MmapOptions::new().offset(start_offset).len(headlen).map_mut(&*file).unwrap()};
-        let r = unsafe { (mmap.as_mut_ptr() as *mut i64).as_mut().unwrap() };
-        let sync = unsafe { slice::from_raw_parts_mut(mmap.as_mut_ptr().offset(INTSIZE as isize) as *mut (i64, usize), m) };
-        // since the size of sync is not known in advance it's preempively mapped as int[m][2].
+
+        // constant header, written at the end
+        let mut r = 0i64;
+        let mut sync = vec![(0i64, 0usize); m];
+        // since the size of sync is not known in advance it's preempively allocated as int[m][2].
         // Its final size will be int[mr][2]. mr can only be calculated after the number of regular items in all blocks (r) is known,
         // i.e. after encoding all blocks. If mr < m there would be empty space between sync and data in the final file, thus we encode
         // to a separate file first and copy data to the container at the end.
 
-        let tmpfile = tempfile::tempfile().unwrap();
+        let tmpfile = tempfile::tempfile()?;
         let mut writer = BufWriter::new(tmpfile);
 
         let mut values = values.take(n);
 
         let mut buffer = [0u8; 9*17]; // byte buffer for encoded data
-        *r = 0; // zero total number of regular items
         let mut total_overflow = 0; // total number of overflow items in blocks
         let mut keys = Vec::with_capacity(16); // keys of the current block
         let mut positions = Vec::with_capacity(100); // values of the current block
@@ -144,11 +144,12 @@ impl<'map> Index<'map> {
                         // 
                         keys.push(key);
                         positions.push(position);
-                        *r += 1; // count regular items
+                        r += 1; // count regular items
                     }
 
                     None => {
-                        // generate padding
+                        // generate padding. Readers only look at the first r keys overall,
+                        // so the padding key doesn't hide a real key -1
                         keys.push(-1);
                         positions.push(-1);
                     }
@@ -168,11 +169,11 @@ impl<'map> Index<'map> {
                     // encode block and continue with next
                     let mut blen = overflow.encode_varint_into(&mut buffer);
                     blen += ziggurat_varint::encode_delta_block_into(&keys, &mut buffer[blen..]);
-                    writer.write_all(&buffer[..blen]).unwrap();
+                    writer.write_all(&buffer[..blen])?;
 
                     let encoded_positions = ziggurat_varint::encode_delta_block(&positions);
                     blen += encoded_positions.len();
-                    writer.write_all(&encoded_positions).unwrap();
+                    writer.write_all(&encoded_positions)?;
 
                     sync[bi] = (keys[0], boffset);
                     bi += 1;
@@ -184,7 +185,7 @@ impl<'map> Index<'map> {
                     // add consumed iter value to next block
                     keys.push(key);
                     positions.push(position);
-                    *r += 1;
+                    r += 1;
 
                     continue 'outer;
                 }
@@ -195,53 +196,64 @@ impl<'map> Index<'map> {
             total_overflow += overflow as usize;
             let mut blen = overflow.encode_varint_into(&mut buffer);
             blen += ziggurat_varint::encode_delta_block_into(&keys, &mut buffer[blen..]);
-            writer.write_all(&buffer[..blen]).unwrap();
+            writer.write_all(&buffer[..blen])?;
 
             let encoded_positions = ziggurat_varint::encode_delta_block(&positions);
             blen += encoded_positions.len();
-            writer.write_all(&encoded_positions).unwrap();
+            writer.write_all(&encoded_positions)?;
 
             sync[bi] = (keys[0], boffset);
             boffset += blen;
 
             break;
         }
-        writer.flush().unwrap();
-        let mut tmpfile = writer.into_inner().unwrap();
+        writer.flush()?;
+        let mut tmpfile = writer.into_inner().map_err(|e| e.into_error())?;
 
-        assert!(n == *r as usize + total_overflow, "encoded different number of values than specified");
+        if r as usize + total_overflow != n {
+            return Err(EncodeError::LengthMismatch { expected: n, found: r as usize + total_overflow });
+        }
 
         // copy encoded data from tmp file into container
-        let mr = (*r as usize - 1) / 16 + 1; // actual number of blocks
+        let mr = (r as usize - 1) / 16 + 1; // actual number of blocks
         let headlen = INTSIZE + (mr * 2 * INTSIZE); // actual header size
 
-        file.seek(SeekFrom::Start(start_offset + headlen as u64)).unwrap();
-        tmpfile.seek(SeekFrom::Start(0)).unwrap();
-        io::copy(&mut tmpfile, file).unwrap();
-        file.flush().unwrap();
+        write_array_at(file, start_offset, &[r])?;
+        write_array_at(file, start_offset + INTSIZE as u64, &sync[..mr])?;
+
+        file.seek(SeekFrom::Start(start_offset + headlen as u64))?;
+        tmpfile.seek(SeekFrom::Start(0))?;
+        io::copy(&mut tmpfile, file)?;
+        file.flush()?;
 
         bom_entry.size = (headlen + boffset) as i64;
         bom_entry.param1 = n as i64;
         bom_entry.param2 = 0;
+
+        Ok(())
     }
 
-    pub unsafe fn encode_uncompressed_to_container_file<I>(values: I, n: usize, file: &mut File, bom_entry: &mut BomEntry, start_offset: u64) where I: Iterator<Item=(i64, i64)> {
-        file.seek(SeekFrom::Start(start_offset)).unwrap();
+    pub unsafe fn encode_uncompressed_to_container_file<I, W: Write + Seek>(values: I, n: usize, file: &mut W, bom_entry: &mut BomEntry, start_offset: u64) -> Result<(), EncodeError> where I: Iterator<Item=(i64, i64)> {
+        file.seek(SeekFrom::Start(start_offset))?;
 
         // write data
         let mut written = 0;
         let mut writer = BufWriter::new(file);
         for (k, v) in values.take(n) {
-            writer.write_all(&k.to_le_bytes()).unwrap();
-            writer.write_all(&v.to_le_bytes()).unwrap();
+            writer.write_all(&k.to_le_bytes())?;
+            writer.write_all(&v.to_le_bytes())?;
             written += 1;
         }
-        writer.flush().unwrap();
-        assert!(written == n, "could not write all values");
+        writer.flush()?;
+        if written != n {
+            return Err(EncodeError::LengthMismatch { expected: n, found: written });
+        }
 
         bom_entry.size = (written * mem::size_of::<i64>() * 2) as i64;
         bom_entry.param1 = n as i64;
         bom_entry.param2 = 0;
+
+        Ok(())
     }
 }
 
@@ -279,12 +291,13 @@ impl<'map> IndexIterator<'map> {
                 let (o, readlen) = ziggurat_varint::decode(&data[offset..]);
                 offset += readlen;
 
-                // read keys vector
+                // read keys vector, the last block is padded after its klen regular keys
                 let klen = min(r - (bi * 16), 16); // number of keys can be <16
                 let (keys, readlen) = ziggurat_varint::decode_delta_array::<16>(&data[offset..]);
                 offset += readlen;
+                let keys = &keys[..klen];
 
-                let p = keys[..klen].partition_point(|&x| x < key);
+                let p = keys.partition_point(|&x| x < key);
                 if p == klen {
                     // key not in block
                     Self::None
@@ -292,9 +305,9 @@ impl<'map> IndexIterator<'map> {
                     // key potentially in block at i
 
                     // determine number of elements with key in block
-                    let mut len = keys.iter().filter(|&x| *x == key).count();
-                    // add overflow items if key is the last in block
-                    if keys[keys.len() - 1] == key {
+                    let mut len = keys[p..].iter().take_while(|&x| *x == key).count();
+                    // add overflow items if key is the last in a full block
+                    if klen == 16 && keys[15] == key {
                         len += o as usize;
                     }
 
@@ -461,6 +474,7 @@ pub struct IndexBlockCache<'map> {
     sync: &'map [(i64, usize)],
     data: &'map [u8],
     cache: LruCache<usize, Rc<IndexBlock>>,
+    stats: CacheStats,
 }
 
 impl<'map> IndexBlockCache<'map> {
@@ -469,7 +483,8 @@ impl<'map> IndexBlockCache<'map> {
             r,
             sync,
             data,
-            cache: LruCache::new(NonZeroUsize::new(500).unwrap())
+            cache: LruCache::new(NonZeroUsize::new(500).unwrap()),
+            stats: CacheStats::default(),
         }
     }
 
@@ -481,10 +496,17 @@ impl<'map> IndexBlockCache<'map> {
     /// If the is not yet in the cache it will be decoded.
     pub fn get_block(&mut self, block_index: usize) -> Option<Rc<IndexBlock>> {
         if block_index < self.sync.len() {
-            if !self.cache.contains(&block_index) {
+            if self.cache.contains(&block_index) {
+                self.stats.record_hit();
+            } else {
                 let offset = self.sync[block_index].1 as usize;
                 let br = min(self.r - (block_index * 16), 16);
                 let block = Rc::new(IndexBlock::decode(&self.data[offset..], br));
+                let next = self.sync.get(block_index + 1).map(|(_, o)| *o);
+                let bytes = encoded_block_len(offset, next, self.data.len());
+                self.stats.record_miss(bytes);
+                #[cfg(feature = "tracing")]
+                tracing::trace!(block = block_index, bytes, "decoded index block");
                 self.cache.put(block_index, block);
             }
     
@@ -495,21 +517,36 @@ impl<'map> IndexBlockCache<'map> {
             None
         }
     }
+
+    pub fn stats(&self) -> CacheStats {
+        let resident_bytes = self.cache
+            .iter()
+            .map(|(_, block)| mem::size_of::<IndexBlock>() + block.positions.capacity() * mem::size_of::<i64>())
+            .sum();
+
+        CacheStats {
+            resident_bytes,
+            ..self.stats
+        }
+    }
 }
 
 /// Alternative type for `Index` implementing efficient cached access.
 /// Compressed index blocks are stored in an LRU cache and only decoded
-/// as needed.
+/// as needed. Lookups of keys rejected by the optional [`BloomFilter`]
+/// return nothing without searching the index.
 #[derive(Debug, Clone)]
 pub enum CachedIndex<'map> {
     Uncompressed {
         length: usize,
         pairs: &'map [(i64, i64)],
+        bloom: Option<BloomFilter<'map>>,
     },
 
     Compressed {
         length: usize,
         cache: Rc<RefCell<IndexBlockCache<'map>>>,
+        bloom: Option<BloomFilter<'map>>,
     },
 }
 
@@ -517,20 +554,39 @@ impl<'map> CachedIndex<'map> {
 
     pub fn new(index: Index<'map>) -> Self {
         match index {
-            Index::Uncompressed { length, pairs } => Self::Uncompressed { length, pairs },
+            Index::Uncompressed { length, pairs } => Self::Uncompressed { length, pairs, bloom: None },
             Index::Compressed { length, r, sync, data } => {
                 Self::Compressed {
                     length,
-                    cache: Rc::new(RefCell::new(IndexBlockCache::new(r, sync, data)))
+                    cache: Rc::new(RefCell::new(IndexBlockCache::new(r, sync, data))),
+                    bloom: None,
                 }
             }
         }
     }
 
+    /// Consults `bloom`, which must have been built from the keys of this index, before
+    /// every lookup of a single key
+    pub fn with_bloom(mut self, bloom: BloomFilter<'map>) -> Self {
+        match &mut self {
+            CachedIndex::Uncompressed { bloom: b, .. } |
+            CachedIndex::Compressed { bloom: b, .. } => *b = Some(bloom),
+        }
+        self
+    }
+
+    pub fn bloom(&self) -> Option<&BloomFilter<'map>> {
+        match self {
+            CachedIndex::Uncompressed { bloom, .. } |
+            CachedIndex::Compressed { bloom, .. } => bloom.as_ref(),
+        }
+    }
+
     pub fn contains_key(&self, key: i64) -> bool {
         self.get_first(key).is_some()
     }
 
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "trace", skip(self)))]
     pub fn get_all(&self, key: i64) -> CachedValueIterator<'map> {
         CachedValueIterator::new(self, key)
     }
@@ -539,6 +595,30 @@ impl<'map> CachedIndex<'map> {
         self.get_all(key).next()
     }
 
+    /// Returns the (key, value) pair with the greatest key not greater than `key`.
+    /// For duplicate keys any of the matching pairs may be returned.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "trace", skip(self)))]
+    pub fn get_floor(&self, key: i64) -> Option<(i64, i64)> {
+        match self {
+            CachedIndex::Uncompressed { pairs, .. } => {
+                let position = pairs.partition_point(|(k, _)| *k <= key);
+                position.checked_sub(1).map(|p| pairs[p])
+            }
+
+            CachedIndex::Compressed { cache, .. } => {
+                let mut cache = cache.borrow_mut();
+
+                // the sync block holds the greatest first key not greater than `key`,
+                // so the floor is either in this block or does not exist at all
+                let block_index = cache.sync_block_position(key);
+                let block = cache.get_block(block_index)?;
+
+                let position = block.keys().partition_point(|&k| k <= key);
+                position.checked_sub(1).and_then(|p| block.get_pair(p))
+            }
+        }
+    }
+
     pub fn len(&self) -> usize {
         match self {
             CachedIndex::Uncompressed { length, .. } |
@@ -546,6 +626,60 @@ impl<'map> CachedIndex<'map> {
         }
     }
 
+    /// Returns the values of all pairs with a key in `min..=max`, ordered by key
+    pub fn values_in_range(&self, min: i64, max: i64) -> Vec<i64> {
+        match self {
+            CachedIndex::Uncompressed { pairs, .. } => {
+                let start = pairs.partition_point(|(k, _)| *k < min);
+                let end = pairs.partition_point(|(k, _)| *k <= max);
+                pairs[start..end.max(start)].iter().map(|(_, v)| *v).collect()
+            }
+
+            CachedIndex::Compressed { cache, .. } => {
+                let mut cache = cache.borrow_mut();
+                let mut values = Vec::new();
+
+                // pairs with key `min` may start in the block before the first one starting at `min`
+                let first = cache.sync.partition_point(|(k, _)| *k < min).saturating_sub(1);
+                let end = cache.sync.partition_point(|(k, _)| *k <= max);
+                for block_index in first..end {
+                    let Some(block) = cache.get_block(block_index) else { break };
+                    values.extend((0..block.len())
+                        .filter_map(|i| block.get_pair(i))
+                        .filter(|(k, _)| (min..=max).contains(k))
+                        .map(|(_, v)| v));
+                }
+                values
+            }
+        }
+    }
+
+    /// Estimated number of pairs with a key in `min..=max` without decoding any block,
+    /// exact for uncompressed indices and counted in whole blocks otherwise
+    pub fn estimate_range(&self, min: i64, max: i64) -> usize {
+        match self {
+            CachedIndex::Uncompressed { pairs, .. } => {
+                let start = pairs.partition_point(|(k, _)| *k < min);
+                pairs.partition_point(|(k, _)| *k <= max).saturating_sub(start)
+            }
+
+            CachedIndex::Compressed { length, cache, .. } => {
+                let cache = cache.borrow();
+                let blocks = cache.sync.len().max(1);
+                let first = cache.sync.partition_point(|(k, _)| *k < min).saturating_sub(1);
+                let end = cache.sync.partition_point(|(k, _)| *k <= max);
+                (end.saturating_sub(first) * length).div_ceil(blocks).min(*length)
+            }
+        }
+    }
+
+    /// Returns the statistics of the block cache shared by all clones of this index.
+    pub fn cache_stats(&self) -> CacheStats {
+        match self {
+            CachedIndex::Uncompressed { .. } => CacheStats::default(),
+            CachedIndex::Compressed { cache, .. } => cache.borrow().stats(),
+        }
+    }
 }
 
 /// Iterator that yields all positions for a key from a given CachedIndex
@@ -567,8 +701,12 @@ pub enum CachedValueIterator<'map> {
 
 impl<'map> CachedValueIterator<'map> {
     fn new(cidx: &CachedIndex<'map>, key: i64) -> Self {
+        if cidx.bloom().is_some_and(|bloom| !bloom.may_contain(key)) {
+            return Self::None;
+        }
+
         match cidx {
-            CachedIndex::Uncompressed { length: _, pairs } => {
+            CachedIndex::Uncompressed { pairs, .. } => {
                 if let Some(position) = Index::position(pairs, key) {
                     CachedValueIterator::Uncompressed { 
                         pairs,
@@ -580,7 +718,7 @@ impl<'map> CachedValueIterator<'map> {
                 }
             },
 
-            CachedIndex::Compressed { length: _, cache } => {
+            CachedIndex::Compressed { cache, .. } => {
                 let mut cache = cache.borrow_mut();
 
                 let block_index = cache.sync_block_position(key);
@@ -632,3 +770,93 @@ impl<'map> Iterator for CachedValueIterator<'map> {
         }
     }
 }
+
+/// Sorts (key, value) pairs for encoding an Index without holding all of them in memory.
+///
+/// The input is cut into runs of `run_len` pairs, each run is sorted and spilled to a
+/// temporary file, and the runs are lazily merged. Pairs are ordered by key and then by
+/// value, so positions of equal keys keep their order. The last run stays in memory.
+pub fn external_sort<I>(pairs: I, run_len: usize) -> io::Result<MergedRuns> where I: Iterator<Item=(i64, i64)> {
+    assert!(run_len > 0, "run length must not be 0");
+
+    let mut pairs = pairs.peekable();
+    let mut runs = Vec::new();
+
+    loop {
+        let mut run: Vec<(i64, i64)> = pairs.by_ref().take(run_len).collect();
+        run.sort_unstable();
+
+        if pairs.peek().is_none() {
+            runs.push(SortedRun::Memory(run.into_iter()));
+            break;
+        }
+
+        let mut writer = BufWriter::new(tempfile::tempfile()?);
+        for (k, v) in run {
+            writer.write_all(&k.to_ne_bytes())?;
+            writer.write_all(&v.to_ne_bytes())?;
+        }
+        let mut file = writer.into_inner().map_err(|e| e.into_error())?;
+        file.seek(SeekFrom::Start(0))?;
+        runs.push(SortedRun::File(BufReader::new(file)));
+    }
+
+    let mut heap = BinaryHeap::with_capacity(runs.len());
+    for (i, run) in runs.iter_mut().enumerate() {
+        if let Some(pair) = run.next_pair()? {
+            heap.push(Reverse((pair, i)));
+        }
+    }
+
+    Ok(MergedRuns { runs, heap })
+}
+
+enum SortedRun {
+    Memory(vec::IntoIter<(i64, i64)>),
+    File(BufReader<File>),
+}
+
+impl SortedRun {
+    fn next_pair(&mut self) -> io::Result<Option<(i64, i64)>> {
+        match self {
+            Self::Memory(pairs) => Ok(pairs.next()),
+            Self::File(reader) => {
+                let mut buf = [0u8; 16];
+                match reader.read_exact(&mut buf) {
+                    Ok(()) => {
+                        let (k, v) = buf.split_at(8);
+                        Ok(Some((
+                            i64::from_ne_bytes(k.try_into().unwrap()),
+                            i64::from_ne_bytes(v.try_into().unwrap()),
+                        )))
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(None),
+                    Err(e) => Err(e),
+                }
+            }
+        }
+    }
+}
+
+/// Iterator over the sorted pairs produced by [`external_sort`]
+pub struct MergedRuns {
+    runs: Vec<SortedRun>,
+    heap: BinaryHeap<Reverse<((i64, i64), usize)>>,
+}
+
+impl Iterator for MergedRuns {
+    type Item = (i64, i64);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let Reverse((pair, i)) = self.heap.pop()?;
+
+        let next = self.runs[i]
+            .next_pair()
+            .expect("reading back a sorted run failed");
+        if let Some(next) = next {
+            self.heap.push(Reverse((next, i)));
+        }
+
+        Some(pair)
+    }
+}
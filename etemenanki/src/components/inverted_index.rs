@@ -1,13 +1,46 @@
-use std::{cell::RefCell, fs::File, io::{BufWriter, Seek, Write}, mem, num::NonZeroUsize, rc::Rc};
+use std::{cell::{Cell, RefCell}, cmp::Reverse, collections::BinaryHeap, io::{BufWriter, Seek, Write}, mem, num::NonZeroUsize, rc::Rc};
 
 use lru::LruCache;
 use ziggurat_varint::EncodeVarint;
 
-use crate::container::BomEntry;
+use crate::container::{BomEntry, EncodeError};
+
+use super::elias_fano::{self, EliasFano, EliasFanoIter};
+use super::{encoded_block_len, CacheStats};
+
+/// Encoding of the postings lists of an inverted index, stored in `param2` of its BOM entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PostingsEncoding {
+    /// Varint coded gaps between positions, the most compact encoding for rare types
+    #[default]
+    Delta,
+    /// [Elias-Fano coding](elias_fano) with skip pointers. Frequent types decode much
+    /// faster and take at most two bits per position more than their low bits, rare types
+    /// pay a few bytes of overhead per list.
+    EliasFano,
+}
+
+impl PostingsEncoding {
+    pub fn from_param(param: i64) -> Option<Self> {
+        match param {
+            0 => Some(Self::Delta),
+            1 => Some(Self::EliasFano),
+            _ => None,
+        }
+    }
+
+    pub fn param(self) -> i64 {
+        match self {
+            Self::Delta => 0,
+            Self::EliasFano => 1,
+        }
+    }
+}
 
 #[derive(Debug, Clone, Copy)]
 pub struct InvertedIndex<'map> {
     types: usize,
+    encoding: PostingsEncoding,
     typeinfo: &'map [(i64, i64)],
     data: &'map [u8],
 }
@@ -16,11 +49,23 @@ impl<'map> InvertedIndex<'map> {
     pub fn from_parts(k: usize, typeinfo: &'map [(i64, i64)], data: &'map [u8]) -> Self {
         Self {
             types: k,
+            encoding: PostingsEncoding::Delta,
             typeinfo,
             data,
         }
     }
 
+    pub fn elias_fano_from_parts(k: usize, typeinfo: &'map [(i64, i64)], data: &'map [u8]) -> Self {
+        Self {
+            encoding: PostingsEncoding::EliasFano,
+            ..Self::from_parts(k, typeinfo, data)
+        }
+    }
+
+    pub fn encoding(&self) -> PostingsEncoding {
+        self.encoding
+    }
+
     /// Returns the frequency of type `i`
     pub fn frequency(&self, i: usize) -> usize {
         self.typeinfo[i].0 as usize
@@ -37,24 +82,47 @@ impl<'map> InvertedIndex<'map> {
         self.typeinfo[i].1 as usize
     }
 
-    /// Returns an iterator over the postings for type `i`
-    pub fn postings(&self, i: usize) -> PostingsIterator {
-        let slice = if i < self.n_types() - 1 {
+    fn slice(&self, i: usize) -> &'map [u8] {
+        if i < self.n_types() - 1 {
             &self.data[self.offset(i)..self.offset(i + 1)]
         } else {
             &self.data[self.offset(i)..]
-        };
+        }
+    }
 
-        PostingsIterator {
-            data: slice,
-            len: self.frequency(i),
-            i: 0,
-            offset: 0,
-            value: 0,
+    /// Returns an iterator over the postings for type `i`
+    pub fn postings(&self, i: usize) -> PostingsIterator<'map> {
+        let slice = self.slice(i);
+
+        match self.encoding {
+            PostingsEncoding::Delta => PostingsIterator(PostingsCursor::Delta {
+                data: slice,
+                len: self.frequency(i),
+                i: 0,
+                offset: 0,
+                value: 0,
+            }),
+            PostingsEncoding::EliasFano => PostingsIterator(PostingsCursor::EliasFano(EliasFano::new(self.frequency(i), slice).iter())),
         }
     }
 
-    pub fn encode_to_container_file<I>(n_types: usize, id_stream: I, n: usize, file: &mut File, bom_entry: &mut BomEntry, start_offset: u64) where I: Iterator<Item=i64> {
+    /// Decodes the complete postings list of type `i`
+    pub fn decode(&self, i: usize) -> Postings {
+        match self.encoding {
+            PostingsEncoding::Delta => Postings::new(self.frequency(i), &self.data[self.offset(i)..]),
+            PostingsEncoding::EliasFano => Postings::elias_fano(self.frequency(i), self.slice(i)),
+        }
+    }
+
+    /// Random access to the postings of type `i` if they are Elias-Fano coded
+    pub fn elias_fano(&self, i: usize) -> Option<EliasFano<'map>> {
+        match self.encoding {
+            PostingsEncoding::EliasFano => Some(EliasFano::new(self.frequency(i), self.slice(i))),
+            PostingsEncoding::Delta => None,
+        }
+    }
+
+    pub fn encode_to_container_file<I, W: Write + Seek>(n_types: usize, id_stream: I, n: usize, file: &mut W, bom_entry: &mut BomEntry, start_offset: u64) -> Result<(), EncodeError> where I: Iterator<Item=i64> {
         // (frequency, last position, encoded postings) for each type
         let mut postings = vec![(0i64, 0i64, Vec::new()); n_types];
 
@@ -77,9 +145,11 @@ impl<'map> InvertedIndex<'map> {
             i += 1;
         }
 
-        assert!(i as usize == n, "encoded fewer values than n");
+        if i as usize != n {
+            return Err(EncodeError::LengthMismatch { expected: n, found: i as usize });
+        }
 
-        file.seek(std::io::SeekFrom::Start(start_offset)).unwrap();
+        file.seek(std::io::SeekFrom::Start(start_offset))?;
         let mut writer = BufWriter::new(file);
         
         // write sync
@@ -87,49 +157,264 @@ impl<'map> InvertedIndex<'map> {
         let mut datalen = 0i64;
         for pi in 0..postings.len() {
             let (freq, _, encoded) = &mut postings[pi];
-            writer.write_all(&freq.to_le_bytes()).unwrap();
-            writer.write_all(&datalen.to_le_bytes()).unwrap();
+            writer.write_all(&freq.to_le_bytes())?;
+            writer.write_all(&datalen.to_le_bytes())?;
             datalen += encoded.len() as i64;
             typeinfolen += mem::size_of::<i64>() as i64 * 2;
         }
 
         // write data
         for (_, _, encoded) in postings {
-            writer.write_all(&encoded).unwrap();
+            writer.write_all(&encoded)?;
         }
-        writer.flush().unwrap();
+        writer.flush()?;
 
         bom_entry.size = typeinfolen + datalen;
         bom_entry.param1 = n_types as i64;
         bom_entry.param2 = 0;
+
+        Ok(())
+    }
+
+    /// Postings held in memory at once by the encoder of [`LexiconBuilder`](super::LexiconBuilder)
+    /// unless configured otherwise
+    pub const DEFAULT_MEMORY_BUDGET: usize = 1 << 30;
+
+    /// Bytes per type kept by [`encode_to_container_file_with_budget`](Self::encode_to_container_file_with_budget)
+    /// for all passes: frequency, last position and offset of the postings
+    pub const TYPE_BYTES: usize = 3 * mem::size_of::<i64>();
+
+    /// Bytes per type kept by [`encode_to_container_file_with_budget`](Self::encode_to_container_file_with_budget)
+    /// for the types of the current fill pass: bytes written, previous position and low bits
+    pub const RANGE_TYPE_BYTES: usize = 2 * mem::size_of::<i64>() + 1;
+
+    /// Encodes the same component as [`encode_to_container_file`](Self::encode_to_container_file),
+    /// or its Elias-Fano coded variant, with at most about `memory_budget` bytes in memory.
+    ///
+    /// The first pass over the ID stream counts the frequency and encoded size of the
+    /// postings of each type, which fixes the layout of the component. The types are then
+    /// split into consecutive ranges whose postings fit into the budget and every range is
+    /// filled by another pass over the stream and written in place. A single type with more
+    /// postings than the budget still gets a range of its own.
+    ///
+    /// The budget covers the postings buffer and the arrays kept per type, [`TYPE_BYTES`](Self::TYPE_BYTES)
+    /// for all types and [`RANGE_TYPE_BYTES`](Self::RANGE_TYPE_BYTES) for those of the current
+    /// range. It does not cover whatever `id_stream` iterates over, which has to stay
+    /// available for all passes, e.g. the ID stream component written before the index.
+    ///
+    /// Positions with a negative ID are left out of all postings lists, e.g. the positions
+    /// of unindexed pairs in a [`BigramIndex`](super::BigramIndex).
+    pub fn encode_to_container_file_with_budget<F, I, W>(n_types: usize, id_stream: F, n: usize, encoding: PostingsEncoding, memory_budget: usize, file: &mut W, bom_entry: &mut BomEntry, start_offset: u64) -> Result<(), EncodeError>
+    where
+        F: Fn() -> I,
+        I: Iterator<Item = i64>,
+        W: Write + Seek,
+    {
+        let mut buffer = [0u8; 9];
+
+        // count pass: frequency, last position and varint size of each type
+        let mut freqs = vec![0i64; n_types];
+        let mut last = vec![0i64; n_types];
+        // turned into the offsets of the postings in place, with the total size at the end
+        let mut sizes = vec![0usize; n_types + 1];
+        let mut i = 0i64;
+        for id in id_stream().take(n) {
+            if id >= 0 {
+                let t = id as usize;
+                let delta = if freqs[t] == 0 { i } else { i - last[t] };
+                sizes[t] += delta.encode_varint_into(&mut buffer);
+                last[t] = i;
+                freqs[t] += 1;
+            }
+            i += 1;
+        }
+
+        if i as usize != n {
+            return Err(EncodeError::LengthMismatch { expected: n, found: i as usize });
+        }
+
+        if encoding == PostingsEncoding::EliasFano {
+            for t in 0..n_types {
+                sizes[t] = elias_fano::encoded_len(freqs[t] as usize, last[t] as usize);
+            }
+        }
+
+        let mut offsets = sizes;
+        let mut offset = 0;
+        for entry in offsets.iter_mut() {
+            offset += mem::replace(entry, offset);
+        }
+
+        file.seek(std::io::SeekFrom::Start(start_offset))?;
+        let mut writer = BufWriter::new(&mut *file);
+        for t in 0..n_types {
+            writer.write_all(&freqs[t].to_le_bytes())?;
+            writer.write_all(&(offsets[t] as i64).to_le_bytes())?;
+        }
+        writer.flush()?;
+        drop(writer);
+
+        let data_start = start_offset + (mem::size_of::<i64>() * 2 * n_types) as u64;
+
+        // fill passes over consecutive ranges of types, with what is left of the budget
+        let range_budget = memory_budget.saturating_sub(n_types * Self::TYPE_BYTES);
+        let range_bytes = |first: usize, end: usize| offsets[end] - offsets[first] + (end - first) * Self::RANGE_TYPE_BYTES;
+        let mut first = 0;
+        while first < n_types {
+            let mut end = first + 1;
+            while end < n_types && range_bytes(first, end + 1) <= range_budget {
+                end += 1;
+            }
+
+            let base = offsets[first];
+            let mut data = vec![0u8; offsets[end] - base];
+            // bytes (delta) or positions (Elias-Fano) written and previous position of each type in the range
+            let mut written = vec![0usize; end - first];
+            let mut previous = vec![0i64; end - first];
+            let low_bits: Vec<u8> = (first..end)
+                .map(|t| elias_fano::low_bits(freqs[t] as usize, last[t] as usize))
+                .collect();
+
+            for (i, id) in id_stream().take(n).enumerate() {
+                let t = id as usize;
+                if id < 0 || t < first || t >= end {
+                    continue;
+                }
+                let k = t - first;
+                let list = &mut data[offsets[t] - base..offsets[t + 1] - base];
+
+                match encoding {
+                    PostingsEncoding::Delta => {
+                        let delta = i as i64 - previous[k];
+                        let len = delta.encode_varint_into(&mut buffer);
+                        list[written[k]..written[k] + len].copy_from_slice(&buffer[..len]);
+                        written[k] += len;
+                    }
+                    PostingsEncoding::EliasFano => {
+                        elias_fano::put(list, freqs[t] as usize, low_bits[k], written[k], i);
+                        written[k] += 1;
+                    }
+                }
+                previous[k] = i as i64;
+            }
+
+            file.seek(std::io::SeekFrom::Start(data_start + base as u64))?;
+            file.write_all(&data)?;
+            first = end;
+        }
+        file.flush()?;
+
+        bom_entry.size = (mem::size_of::<i64>() * 2 * n_types + offsets[n_types]) as i64;
+        bom_entry.param1 = n_types as i64;
+        bom_entry.param2 = encoding.param();
+
+        Ok(())
     }
 }
 
-pub struct PostingsIterator<'map> {
-    data: &'map [u8],
-    len: usize,
-    i: usize,
-    offset: usize,
-    value: usize,
+pub struct PostingsIterator<'map>(PostingsCursor<'map>);
+
+enum PostingsCursor<'map> {
+    Delta {
+        data: &'map [u8],
+        len: usize,
+        i: usize,
+        offset: usize,
+        value: usize,
+    },
+    EliasFano(EliasFanoIter<'map>),
+    /// A list taken from the postings cache
+    Cached(CachedPostingsIterator),
 }
 
 impl<'map> Iterator for PostingsIterator<'map> {
     type Item = usize;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.i < self.len {
-            let (value, readlen) = ziggurat_varint::decode(&self.data[self.offset..]);
-            self.i += 1;
-            self.offset += readlen;
-            self.value += value as usize;
-            Some(self.value)
-        } else {
-            None
+        match &mut self.0 {
+            PostingsCursor::Delta { data, len, i, offset, value } => {
+                if *i < *len {
+                    let (delta, readlen) = ziggurat_varint::decode(&data[*offset..]);
+                    *i += 1;
+                    *offset += readlen;
+                    *value += delta as usize;
+                    Some(*value)
+                } else {
+                    None
+                }
+            }
+            PostingsCursor::EliasFano(iter) => iter.next(),
+            PostingsCursor::Cached(iter) => iter.next(),
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match &self.0 {
+            PostingsCursor::Delta { len, i, .. } => (len - i, Some(len - i)),
+            PostingsCursor::EliasFano(iter) => iter.size_hint(),
+            PostingsCursor::Cached(iter) => (iter.end - iter.position, Some(iter.end - iter.position)),
         }
     }
 }
 
 
+/// K-way merge of ascending position iterators into one ascending iterator.
+///
+/// Only the current head of every input is kept in a heap, so the merged stream is
+/// produced without collecting and sorting all positions. With `dedup`, positions present
+/// in several inputs are yielded once.
+#[derive(Debug, Clone)]
+pub struct MergedPostings<I> {
+    cursors: Vec<I>,
+    /// (position, cursor) of the next position of every unfinished cursor
+    heads: BinaryHeap<Reverse<(usize, usize)>>,
+    dedup: bool,
+    last: Option<usize>,
+}
+
+impl<I: Iterator<Item = usize>> MergedPostings<I> {
+    pub fn new<C: IntoIterator<Item = I>>(cursors: C, dedup: bool) -> Self {
+        let mut cursors: Vec<I> = cursors.into_iter().collect();
+        let heads = cursors.iter_mut()
+            .enumerate()
+            .filter_map(|(c, cursor)| cursor.next().map(|position| Reverse((position, c))))
+            .collect();
+
+        Self { cursors, heads, dedup, last: None }
+    }
+}
+
+impl<I: Iterator<Item = usize>> Iterator for MergedPostings<I> {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let Reverse((position, c)) = self.heads.pop()?;
+            if let Some(next) = self.cursors[c].next() {
+                self.heads.push(Reverse((next, c)));
+            }
+
+            if self.dedup && self.last == Some(position) {
+                continue;
+            }
+            self.last = Some(position);
+            return Some(position);
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let (lower, upper) = self.cursors.iter()
+            .map(|c| c.size_hint())
+            .fold((0, Some(0)), |(lo, hi), (l, h)| (lo + l, hi.zip(h).map(|(a, b)| a + b)));
+        let pending = self.heads.len();
+
+        match self.dedup {
+            true => (pending.min(1), upper.map(|u| u + pending)),
+            false => (lower + pending, upper.map(|u| u + pending)),
+        }
+    }
+}
+
 /// A decoded in-memory postings list
 #[derive(Debug)]
 pub struct Postings {
@@ -149,6 +434,14 @@ impl Postings {
         }
     }
 
+    /// Will decode a complete Elias-Fano coded postings list, `data` must end with the list
+    pub fn elias_fano(length: usize, data: &[u8]) -> Self {
+        Self {
+            length,
+            decoded: EliasFano::new(length, data).decode(),
+        }
+    }
+
     /// Returns an individual position from the postings list
     pub fn get(&self, index: usize) -> Option<usize> {
         self.decoded.get(index).copied()
@@ -167,23 +460,30 @@ impl Postings {
 
 #[derive(Debug, Clone)]
 pub struct CachedInvertedIndex<'map> {
+    encoding: PostingsEncoding,
     typeinfo: &'map [(i64, i64)],
     data: &'map [u8],
     cache: Rc<RefCell<LruCache<usize, Rc<Postings>>>>,
+    stats: Rc<Cell<CacheStats>>,
 }
 
 impl<'map> CachedInvertedIndex<'map> {
     pub fn new(invidx: InvertedIndex<'map>) -> Self {
-        let InvertedIndex {types: _, typeinfo, data} = invidx;
+        let InvertedIndex {types: _, encoding, typeinfo, data} = invidx;
 
         Self {
+            encoding,
             typeinfo,
             data,
             cache: Rc::new(RefCell::new(LruCache::new(NonZeroUsize::new(500).unwrap()))),
+            stats: Rc::new(Cell::new(CacheStats::default())),
         }
     }
 
-    /// Returns the frequency of a type
+    /// Returns the frequency of a type.
+    ///
+    /// Only its entry of the type information table is read, neither the postings list
+    /// nor the cache is touched.
     pub fn frequency(&self, type_id: usize) -> Option<usize> {
         self.typeinfo
             .get(type_id)
@@ -193,25 +493,33 @@ impl<'map> CachedInvertedIndex<'map> {
     /// Returns the postings list of a type
     pub fn get_postings(&self, type_id: usize) -> Option<Rc<Postings>> {
         let mut cache = self.cache.borrow_mut();
+        let mut stats = self.stats.get();
 
-        if !cache.contains(&type_id) {
-            let postings = Rc::new(self.decode_postings(type_id)?);
-            cache.put(type_id, postings.clone());
-            return Some(postings);
-        }
+        let postings = match cache.get(&type_id) {
+            Some(postings) => {
+                stats.record_hit();
+                postings.clone()
+            }
+            None => {
+                let postings = Rc::new(self.decode_postings(type_id)?);
+                let offset = self.typeinfo[type_id].1 as usize;
+                let next = self.typeinfo.get(type_id + 1).map(|(_, o)| *o as usize);
+                let bytes = encoded_block_len(offset, next, self.data.len());
+                stats.record_miss(bytes);
+                #[cfg(feature = "tracing")]
+                tracing::trace!(type_id, frequency = postings.len(), bytes, "decoded postings");
+                cache.put(type_id, postings.clone());
+                postings
+            }
+        };
 
-        None
+        self.stats.set(stats);
+        Some(postings)
     }
 
     /// Decodes the postings list for a type
     pub fn decode_postings(&self, type_id: usize) -> Option<Postings> {
-        if type_id < self.typeinfo.len() {
-            let (freq, offset) = self.typeinfo[type_id];
-            let postings = Postings::new(freq as usize, &self.data[offset as usize..]);
-            return Some(postings);
-        }
-
-        None
+        (type_id < self.typeinfo.len()).then(|| self.uncached().decode(type_id))
     }
 
     /// Returs the combined postings lists of multiple types
@@ -240,6 +548,39 @@ impl<'map> CachedInvertedIndex<'map> {
             positions
         }
 
+    /// Lazily merges the postings lists of multiple types into one ascending stream of
+    /// positions, see [`MergedPostings`].
+    ///
+    /// Lists already in the cache are read from there, all others are decoded while
+    /// iterating without adding them to the cache. Unknown types are skipped.
+    pub fn merged_postings(&self, type_ids: &[usize], dedup: bool) -> MergedPostings<PostingsIterator<'map>> {
+        let index = self.uncached();
+        let cursors: Vec<_> = type_ids.iter()
+            .filter(|t| **t < self.typeinfo.len())
+            .map(|&t| match self.is_cached(t) {
+                true => PostingsIterator(PostingsCursor::Cached(self.positions(t).expect("cached type"))),
+                false => index.postings(t),
+            })
+            .collect();
+
+        MergedPostings::new(cursors, dedup)
+    }
+
+    /// Iterator over the positions of a type in chunks of `chunk_len` positions, each with
+    /// the index of its first position in the postings list.
+    ///
+    /// A list already in the cache is read from there, otherwise it is decoded chunk by
+    /// chunk without adding it to the cache, so scanning the postings of frequent types
+    /// doesn't evict the lists used for queries.
+    pub fn postings_chunks(&self, type_id: usize, chunk_len: usize) -> Option<PostingsChunks<'map>> {
+        self.frequency(type_id)?;
+        let postings = match self.is_cached(type_id) {
+            true => PostingsIterator(PostingsCursor::Cached(self.positions(type_id)?)),
+            false => self.uncached().postings(type_id),
+        };
+        Some(PostingsChunks { postings, chunk_len: chunk_len.max(1), index: 0 })
+    }
+
     /// Iterator over the positions of a type
     pub fn positions(&self, type_id: usize) -> Option<CachedPostingsIterator> {
         self.frequency(type_id)
@@ -251,6 +592,7 @@ impl<'map> CachedInvertedIndex<'map> {
             .and_then(| max | self.positions_range(type_id, start, max))
     }
 
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "trace", skip(self)))]
     pub fn positions_range(&self, type_id: usize, start: usize, end: usize) -> Option<CachedPostingsIterator> {
         self.frequency(type_id)
             .filter(| freq | end <= *freq)
@@ -262,9 +604,113 @@ impl<'map> CachedInvertedIndex<'map> {
         self.positions_range(type_id, 0, end)
     }
 
+    /// Iterator over the positions of a type within the corpus positions `start..end`,
+    /// e.g. for a query restricted to a single document.
+    ///
+    /// Unlike [`positions_range`](Self::positions_range), which selects by index into the
+    /// postings list, this selects by position. Elias-Fano coded lists that are not cached
+    /// yet are entered through their skip pointers and only the positions inside the range
+    /// are decoded, without caching the list. Delta coded lists have no sync points, they
+    /// are decoded into the cache once and binary searched.
+    pub fn positions_within(&self, type_id: usize, start: usize, end: usize) -> Option<PostingsWithin<'map>> {
+        self.frequency(type_id)?;
+
+        if self.encoding == PostingsEncoding::EliasFano && !self.is_cached(type_id) {
+            let list = self.uncached().elias_fano(type_id)?;
+            let iter = match list.next_geq(start) {
+                Some((index, _)) => list.iter_from(index),
+                None => list.iter_from(list.len()),
+            };
+            return Some(PostingsWithin(WithinCursor::Uncached { iter, end }));
+        }
+
+        let postings = self.get_postings(type_id)?;
+        let first = postings.get_all().partition_point(|p| *p < start);
+        let last = first + postings.get_all()[first..].partition_point(|p| *p < end);
+        Some(PostingsWithin(WithinCursor::Cached(CachedPostingsIterator::new(postings, type_id, first, last))))
+    }
+
+    /// Returns the `index`th position of a type.
+    ///
+    /// Elias-Fano coded lists that are not cached are accessed through their skip
+    /// pointers, anything else is served from the cache.
+    pub fn position(&self, type_id: usize, index: usize) -> Option<usize> {
+        if self.encoding == PostingsEncoding::EliasFano && !self.is_cached(type_id) {
+            return self.uncached().elias_fano(type_id)?.get(index);
+        }
+        self.get_postings(type_id)?.get(index)
+    }
+
+    /// Decodes the postings lists of the given types on a background thread while `f` runs
+    /// on the calling thread, e.g. to prepare the next query while rendering the previous
+    /// results.
+    ///
+    /// The cache can't be shared with another thread, so the decoded lists are only added
+    /// to it once `f` has returned. Lists that are already cached are not decoded again.
+    pub fn prefetch_while<R, F: FnOnce() -> R>(&self, type_ids: &[usize], f: F) -> R {
+        let index = self.uncached();
+        let missing: Vec<usize> = type_ids.iter()
+            .copied()
+            .filter(|&t| t < self.typeinfo.len() && !self.is_cached(t))
+            .collect();
+
+        let (result, decoded) = std::thread::scope(|scope| {
+            let worker = scope.spawn(|| {
+                missing.iter().map(|&t| (t, index.decode(t))).collect::<Vec<_>>()
+            });
+            let result = f();
+            (result, worker.join().unwrap_or_else(|e| std::panic::resume_unwind(e)))
+        });
+
+        let mut cache = self.cache.borrow_mut();
+        let mut stats = self.stats.get();
+        for (t, postings) in decoded {
+            stats.record_miss(index.slice(t).len());
+            cache.put(t, Rc::new(postings));
+        }
+        self.stats.set(stats);
+
+        result
+    }
+
+    fn is_cached(&self, type_id: usize) -> bool {
+        self.cache.borrow().contains(&type_id)
+    }
+
+    /// The underlying index, for reading postings without the cache
+    fn uncached(&self) -> InvertedIndex<'map> {
+        InvertedIndex {
+            types: self.typeinfo.len(),
+            encoding: self.encoding,
+            typeinfo: self.typeinfo,
+            data: self.data,
+        }
+    }
+
     pub fn n_types(&self) -> usize {
         self.typeinfo.len()
     }
+
+    /// Decodes the postings lists of the given types into the cache ahead of access.
+    pub fn prefetch(&self, type_ids: &[usize]) {
+        for t in type_ids {
+            self.get_postings(*t);
+        }
+    }
+
+    /// Returns the statistics of the postings cache shared by all clones of this index.
+    pub fn cache_stats(&self) -> CacheStats {
+        let resident_bytes = self.cache
+            .borrow()
+            .iter()
+            .map(|(_, postings)| mem::size_of::<Postings>() + postings.decoded.capacity() * mem::size_of::<usize>())
+            .sum();
+
+        CacheStats {
+            resident_bytes,
+            ..self.stats.get()
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -285,9 +731,59 @@ impl Iterator for CachedPostingsIterator {
     type Item = usize;
 
     fn next(&mut self) -> Option<Self::Item> {
+        if self.position >= self.end {
+            return None;
+        }
         let value = self.postings
             .get(self.position);
         self.position += 1;
         value
     }
 }
+
+/// Iterator returned by [`CachedInvertedIndex::postings_chunks`]
+pub struct PostingsChunks<'map> {
+    postings: PostingsIterator<'map>,
+    chunk_len: usize,
+    index: usize,
+}
+
+impl<'map> Iterator for PostingsChunks<'map> {
+    type Item = (usize, Vec<usize>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let chunk: Vec<usize> = self.postings.by_ref().take(self.chunk_len).collect();
+        if chunk.is_empty() {
+            return None;
+        }
+        let index = self.index;
+        self.index += chunk.len();
+        Some((index, chunk))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let (_, remaining) = self.postings.size_hint();
+        (0, remaining.map(|n| n.div_ceil(self.chunk_len)))
+    }
+}
+
+/// Iterator returned by [`CachedInvertedIndex::positions_within`]
+#[derive(Debug)]
+pub struct PostingsWithin<'map>(WithinCursor<'map>);
+
+#[derive(Debug)]
+enum WithinCursor<'map> {
+    Cached(CachedPostingsIterator),
+    Uncached { iter: EliasFanoIter<'map>, end: usize },
+}
+
+impl<'map> Iterator for PostingsWithin<'map> {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match &mut self.0 {
+            WithinCursor::Cached(iter) => iter.next(),
+            WithinCursor::Uncached { iter, end } => iter.next().filter(|p| p < end),
+        }
+    }
+}
@@ -1,9 +1,9 @@
 use core::panic;
-use std::{collections::HashMap, fs::File, io::{Seek, SeekFrom, Write}, mem, slice};
+use std::{cmp::Reverse, io::{Seek, SeekFrom, Write}, mem, slice};
 
-use crate::{components::FnvHash, container::BomEntry};
+use crate::{components::FnvHash, container::{BomEntry, EncodeError}};
 
-use super::{Index, StringVector};
+use super::{string_vector::TypeIndex, Index, StringVector};
 
 #[derive(Debug, Clone, Copy)]
 pub struct Set<'map> {
@@ -57,6 +57,24 @@ impl<'map> Set<'map> {
         self.length
     }
 
+    /// Returns the encoded blocks containing the sets `start..end`.
+    pub fn raw_range(&self, start: usize, end: usize) -> &'map [u8] {
+        let end = usize::min(end, self.len());
+        if start >= end {
+            return &[];
+        }
+
+        // sync offsets are relative to the start of the component, which includes the sync array
+        let sync_len = (self.sync.len() * 8) as i64;
+        let block_start = (self.sync[start / 16] - sync_len) as usize;
+        let block_end = self.sync
+            .get((end - 1) / 16 + 1)
+            .map(|o| (o - sync_len) as usize)
+            .unwrap_or(self.data.len());
+
+        &self.data[block_start..block_end]
+    }
+
     pub fn width(&self) -> usize {
         self.width
     }
@@ -65,7 +83,7 @@ impl<'map> Set<'map> {
 
 pub struct SetBuilder {
     types: Vec<(String, usize)>,
-    type_idx: HashMap<i64, usize>,
+    type_idx: TypeIndex,
     set_stream_data: Vec<u8>,
     set_stream_sync: Vec<i64>,
     length: usize,
@@ -75,7 +93,7 @@ impl SetBuilder {
     pub fn new() -> Self {
         Self {
             types: Vec::new(),
-            type_idx: HashMap::new(),
+            type_idx: TypeIndex::default(),
             set_stream_data: Vec::new(),
             set_stream_sync: Vec::new(),
             length: 0,
@@ -117,21 +135,19 @@ impl SetBuilder {
     fn get_id_or_add(&mut self, token: &str) -> i64 {
         let hash = token.fnv_hash();
 
-        match self.type_idx.entry(hash) {
-            std::collections::hash_map::Entry::Occupied(entry) => {
-                let id = *entry.get();
-
+        match self.type_idx.get(hash, token, &self.types) {
+            Some(id) => {
                 // increase count
                 self.types[id].1 += 1;
 
                 id as i64
             }
 
-            std::collections::hash_map::Entry::Vacant(entry) => {
+            None => {
                 let id = self.types.len();
 
                 // insert element
-                entry.insert(id);
+                self.type_idx.insert(hash, id);
                 self.types.push((token.into(), 1));
 
                 id as i64
@@ -156,19 +172,10 @@ impl SetBuilder {
             set_stream.push(temp);
         }
 
-        // sort lexicon
-        self.types.sort_unstable_by_key(|(_, count)| *count);
-        self.types.reverse();
-
-        // lookup table
-        // from old id to new id
-        let mut lut = vec![0; self.types.len()];
-        for ni in 0..self.types.len() {
-            let hash = &self.types[ni].0.fnv_hash();
-            let oi = self.type_idx[hash];
-            lut[oi] = ni;
-            self.type_idx.insert(*hash, ni);
-        }
+        // sort lexicon by descending frequency, lookup table from old id to new id
+        let mut ids: Vec<usize> = (0..self.types.len()).collect();
+        ids.sort_unstable_by_key(|&id| Reverse(self.types[id].1));
+        let lut = self.type_idx.reorder(&mut self.types, &ids);
 
         // transform set_stream from old to new ids
         for set in set_stream.iter_mut() {
@@ -256,34 +263,34 @@ impl SetBuilder {
         Set { length: self.length, width: 1, sync: &self.set_stream_sync, data: &self.set_stream_data }
     }
 
-    pub unsafe fn write_lexicon(&self, file: &mut File, bom_entry: &mut BomEntry, start_offset: u64) {
+    pub unsafe fn write_lexicon<W: Write + Seek>(&self, file: &mut W, bom_entry: &mut BomEntry, start_offset: u64) -> Result<(), EncodeError> {
         let strings = self.types.iter().map(|(s, _)| s);
         StringVector::encode_to_container_file(strings, self.types(), file, bom_entry, start_offset)
     }
 
-    pub unsafe fn write_index(&self, file: &mut File, bom_entry: &mut BomEntry, start_offset: u64) {
-        let mut pairs: Vec<_> = self.type_idx.iter().map(|(k, v)| (*k, *v as i64)).collect();
-        pairs.sort_unstable_by_key(|(k, _)| *k);
-        
-        Index::encode_uncompressed_to_container_file(pairs.iter().copied(), self.types(), file, bom_entry, start_offset);
+    pub unsafe fn write_index<W: Write + Seek>(&self, file: &mut W, bom_entry: &mut BomEntry, start_offset: u64) -> Result<(), EncodeError> {
+        let pairs = self.type_idx.pairs();
+        Index::encode_uncompressed_to_container_file(pairs.iter().copied(), self.types(), file, bom_entry, start_offset)
     }
 
-    pub unsafe fn write_set_stream(&self, file: &mut File, bom_entry: &mut BomEntry, start_offset: u64) {
-        file.seek(SeekFrom::Start(start_offset)).unwrap();
+    pub unsafe fn write_set_stream<W: Write + Seek>(&self, file: &mut W, bom_entry: &mut BomEntry, start_offset: u64) -> Result<(), EncodeError> {
+        file.seek(SeekFrom::Start(start_offset))?;
 
         let m = (self.length-1) / 16 + 1;
         assert!(self.set_stream_sync.len() == m+1, "somehow encoded too many blocks?");
         let sync = slice::from_raw_parts(self.set_stream_sync.as_ptr() as *const u8, mem::size_of::<i64>() * m);
-        file.write_all(sync).unwrap();
+        file.write_all(sync)?;
         bom_entry.size = sync.len() as i64;
 
-        file.write_all(&self.set_stream_data).unwrap();
+        file.write_all(&self.set_stream_data)?;
         bom_entry.size += self.set_stream_data.len() as i64;
 
-        file.flush().unwrap();
+        file.flush()?;
 
         bom_entry.param1 = self.tokens() as i64;
         bom_entry.param2 = 1;
+
+        Ok(())
     }
 
     // pub fn write_inverted_index(&self, file: &mut File, bom_entry: &mut BomEntry, start_offset: u64) {
@@ -1,13 +1,149 @@
 use std::{
-    collections::HashMap, fs::File, io::{BufWriter, Seek, SeekFrom, Write}, mem, ops, slice, str::pattern::{Pattern, ReverseSearcher}
+    borrow::Cow, cmp::{Ordering, Reverse}, collections::{hash_map::Entry, HashMap}, error, fmt, io::{BufWriter, Seek, SeekFrom, Write}, mem, ops, slice, str::{self, pattern::{Pattern, ReverseSearcher}}
 };
 
-use memmap2::MmapOptions;
-use regex::Regex;
+use regex::{Regex, RegexBuilder};
 
-use crate::container::BomEntry;
+use crate::container::{BomEntry, EncodeError};
 
-use super::{CachedVector, FnvHash, Index, InvertedIndex, Vector};
+use super::{write_array_at, BigramBuilder, BloomFilter, CachedVector, FnvHash, HashTable, Index, InvertedIndex, PostingsEncoding, Vector, WaveletTree, DEFAULT_BLOCK_SIZE};
+
+/// A string of a string component that is not valid UTF-8, or whose offsets are out of bounds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvalidUtf8 {
+    /// Index of the string
+    pub index: usize,
+    /// Number of valid bytes at the start of the string
+    pub valid_up_to: usize,
+}
+
+impl fmt::Display for InvalidUtf8 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "string {} is not valid UTF-8 after {} bytes", self.index, self.valid_up_to)
+    }
+}
+
+impl error::Error for InvalidUtf8 {}
+
+/// Checks the NUL terminated strings in `data` delimited by `offsets`, i.e. n+1 offsets for n strings.
+pub(crate) fn validate_utf8<I>(data: &[u8], offsets: I) -> Result<(), InvalidUtf8>
+where
+    I: IntoIterator<Item = usize>,
+{
+    let mut offsets = offsets.into_iter();
+    let Some(mut start) = offsets.next() else {
+        return Ok(());
+    };
+
+    for (index, end) in offsets.enumerate() {
+        let bytes = end.checked_sub(1)
+            .and_then(|end| data.get(start..end))
+            .ok_or(InvalidUtf8 { index, valid_up_to: 0 })?;
+        str::from_utf8(bytes)
+            .map_err(|e| InvalidUtf8 { index, valid_up_to: e.valid_up_to() })?;
+        start = end;
+    }
+
+    Ok(())
+}
+
+/// Folds `string` for matching regardless of case and diacritics.
+///
+/// The string is decomposed to NFKD, combining marks with a non-zero canonical combining
+/// class are removed and the rest is lowercased, e.g. "Ångström" folds to "angstrom".
+#[cfg(feature = "normalization")]
+pub fn fold(string: &str) -> String {
+    let ccc = icu_normalizer::properties::CanonicalCombiningClassMap::new();
+    icu_normalizer::DecomposingNormalizerBorrowed::new_nfkd()
+        .normalize_iter(string.chars())
+        .filter(|&c| ccc.get_u8(c) == 0)
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// Removes the diacritics of `string` but keeps its case, e.g. "Ångström" becomes "Angstrom".
+///
+/// Unlike [`fold`] the string is decomposed canonically, so ligatures and the like are kept,
+/// and composed to NFC again afterwards.
+#[cfg(feature = "normalization")]
+pub fn strip_diacritics(string: &str) -> String {
+    let ccc = icu_normalizer::properties::CanonicalCombiningClassMap::new();
+    let stripped: String = icu_normalizer::DecomposingNormalizerBorrowed::new_nfd()
+        .normalize_iter(string.chars())
+        .filter(|&c| ccc.get_u8(c) == 0)
+        .collect();
+    icu_normalizer::ComposingNormalizerBorrowed::new_nfc().normalize(&stripped).into_owned()
+}
+
+/// How [`StringVector::regex_matches`] applies a pattern to the strings.
+///
+/// The default matches whole strings case sensitively, like the regexes of CQP.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RegexFlags {
+    anchored: bool,
+    case_insensitive: bool,
+    #[cfg(feature = "normalization")]
+    folded: bool,
+}
+
+impl Default for RegexFlags {
+    fn default() -> Self {
+        Self {
+            anchored: true,
+            case_insensitive: false,
+            #[cfg(feature = "normalization")]
+            folded: false,
+        }
+    }
+}
+
+impl RegexFlags {
+    /// Whether the pattern has to match a whole string rather than any substring of it.
+    ///
+    /// Anchoring wraps the pattern in `\A(?:...)\z`, so alternations apply to the whole
+    /// string and anchors in the pattern itself keep their meaning.
+    pub fn anchored(mut self, anchored: bool) -> Self {
+        self.anchored = anchored;
+        self
+    }
+
+    /// Whether letters match regardless of their case, by Unicode simple case folding
+    pub fn case_insensitive(mut self, case_insensitive: bool) -> Self {
+        self.case_insensitive = case_insensitive;
+        self
+    }
+
+    /// Whether the pattern is matched against the [folded](fold) form of each string.
+    ///
+    /// Only the strings are folded, literals in the pattern have to be written in folded
+    /// form, i.e. lowercase and without diacritics.
+    #[cfg(feature = "normalization")]
+    pub fn folded(mut self, folded: bool) -> Self {
+        self.folded = folded;
+        self
+    }
+
+    /// Compiles `pattern` with these flags
+    pub fn build(&self, pattern: &str) -> Result<Regex, regex::Error> {
+        let pattern = if self.anchored {
+            Cow::Owned(format!("\\A(?:{})\\z", pattern))
+        } else {
+            Cow::Borrowed(pattern)
+        };
+        RegexBuilder::new(&pattern)
+            .case_insensitive(self.case_insensitive)
+            .build()
+    }
+
+    /// Whether `regex`, compiled by [`build`](Self::build), matches `string`
+    pub fn is_match(&self, regex: &Regex, string: &str) -> bool {
+        #[cfg(feature = "normalization")]
+        if self.folded {
+            return regex.is_match(&fold(string));
+        }
+        regex.is_match(string)
+    }
+}
 
 #[derive(Debug, Clone, Copy)]
 pub struct StringVector<'map> {
@@ -17,33 +153,30 @@ pub struct StringVector<'map> {
 }
 
 impl<'map> StringVector<'map> {
-    pub fn all_matching_regex(&self, regex: &str) -> Option<MatchIterator<'map, impl Iterator<Item = usize> + '_>> {
-        Regex::new(regex).ok()
-            .map(|regex| {
-                let iter = self.iter().enumerate()
-                    .filter(move |(_, s)| regex.is_match(s))
-                    .map(|(i, _)| i);
-
-                MatchIterator {
-                    strvec: *self,
-                    inner: iter,
-                }
-            })
-    }
+    /// Lazily yields the indices of the strings matching `pattern` with `flags`.
+    ///
+    /// Unlike [`all_matching_regex`](Self::all_matching_regex) the pattern is anchored
+    /// unless [`RegexFlags::anchored`] says otherwise, and an invalid pattern is reported.
+    pub fn regex_matches(&self, pattern: &str, flags: RegexFlags) -> Result<MatchIterator<'map, impl Iterator<Item = usize> + 'map>, regex::Error> {
+        let regex = flags.build(pattern)?;
+        let iter = self.iter().enumerate()
+            .filter(move |(_, s)| flags.is_match(&regex, s))
+            .map(|(i, _)| i);
 
-    pub fn get_all_matching_regex(&self, regex: &str) -> Vec<usize> {
-        let mut output = Vec::new();
+        Ok(MatchIterator {
+            strvec: *self,
+            inner: iter,
+        })
+    }
 
-        if let Ok(regex) = Regex::new(regex) {
-            for i in 0..self.length {
-                let s = self.get_unchecked(i);
-                if regex.is_match(s) {
-                    output.push(i);
-                }
-            }
-        }
+    /// Indices of the strings containing a match of `regex`, `None` if it is invalid
+    pub fn all_matching_regex(&self, regex: &str) -> Option<MatchIterator<'map, impl Iterator<Item = usize> + 'map>> {
+        self.regex_matches(regex, RegexFlags::default().anchored(false)).ok()
+    }
 
-        output
+    pub fn get_all_matching_regex(&self, regex: &str) -> Vec<usize> {
+        self.all_matching_regex(regex)
+            .map_or_else(Vec::new, Iterator::collect)
     }
 
     pub fn all_matching<'a>(&'a self, string: &'a str) -> MatchIterator<'map, impl Iterator<Item = usize> + 'a>
@@ -135,20 +268,41 @@ impl<'map> StringVector<'map> {
         }
     }
 
+    /// Returns the string at `index` without checking its bounds or encoding.
+    ///
+    /// Strings are assumed to be valid UTF-8, use [`validate_utf8`](Self::validate_utf8) once
+    /// for data of unknown origin or [`get_lossy`](Self::get_lossy) to inspect corrupt data.
     pub fn get_unchecked(&self, index: usize) -> &'map str {
         let start = self.offsets[index] as usize;
         let end = self.offsets[index + 1] as usize;
         unsafe { std::str::from_utf8_unchecked(&self.data[start..end - 1]) }
     }
 
-    pub fn get_all<'a: 'map, I>(&'a self, indices: I) -> impl Iterator<Item = &'map str>
+    /// Returns the string at `index` with invalid UTF-8 sequences replaced by U+FFFD.
+    pub fn get_lossy(&self, index: usize) -> Option<Cow<'map, str>> {
+        let start = *self.offsets.get(index)? as usize;
+        let end = *self.offsets.get(index + 1)? as usize;
+        let bytes = self.data.get(start..end.checked_sub(1)?)?;
+        Some(String::from_utf8_lossy(bytes))
+    }
+
+    /// Checks that all strings are valid UTF-8, which all other accessors assume.
+    pub fn validate_utf8(&self) -> Result<(), InvalidUtf8> {
+        validate_utf8(self.data, self.offsets.iter().map(|&o| o as usize))
+    }
+
+    /// Returns the strings at `indices`. The iterator only borrows the indices, not this vector.
+    pub fn get_all<'a, I>(&self, indices: I) -> impl Iterator<Item = &'map str> + 'a
     where
         I: IntoIterator<Item = &'a usize>,
+        I::IntoIter: 'a,
+        'map: 'a,
     {
-        indices.into_iter().map(|x| &self[*x])
+        let strings = *self;
+        indices.into_iter().map(move |x| strings.get_unchecked(*x))
     }
 
-    pub fn iter(&self) -> StringVectorIterator {
+    pub fn iter(&self) -> StringVectorIterator<'map> {
         self.into_iter()
     }
 
@@ -156,19 +310,56 @@ impl<'map> StringVector<'map> {
         self.length
     }
 
-    pub unsafe fn encode_to_container_file<S, I>(strings: I, n: usize, file: &mut File, bom_entry: &mut BomEntry, start_offset: u64)
+    /// Index of the first string from `start` on for which `pred` is false, with `pred`
+    /// true for a prefix of these strings
+    fn partition_point<P: FnMut(&str) -> bool>(&self, start: usize, mut pred: P) -> usize {
+        let (mut lo, mut hi) = (start, self.length);
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            if pred(self.get_unchecked(mid)) {
+                lo = mid + 1;
+            } else {
+                hi = mid;
+            }
+        }
+        lo
+    }
+
+    /// Binary searches `string` in a vector sorted by bytes, see [`slice::binary_search`].
+    ///
+    /// The result is meaningless if the vector is not sorted, e.g. for lexicons in
+    /// frequency order.
+    pub fn binary_search(&self, string: &str) -> Result<usize, usize> {
+        let i = self.partition_point(0, |s| s < string);
+        if i < self.length && self.get_unchecked(i) == string {
+            Ok(i)
+        } else {
+            Err(i)
+        }
+    }
+
+    /// Range of the strings starting with `prefix` in a vector sorted by bytes.
+    ///
+    /// Strings with a common prefix are adjacent in byte order, so this takes two binary
+    /// searches. Like [`binary_search`](Self::binary_search) it requires a sorted vector.
+    pub fn prefix_range(&self, prefix: &str) -> ops::Range<usize> {
+        let start = self.partition_point(0, |s| s < prefix);
+        let end = self.partition_point(start, |s| s.starts_with(prefix));
+        start..end
+    }
+
+    pub unsafe fn encode_to_container_file<S, I, W: Write + Seek>(strings: I, n: usize, file: &mut W, bom_entry: &mut BomEntry, start_offset: u64) -> Result<(), EncodeError>
     where
         S: AsRef<str>,
         I: Iterator<Item=S>
     {
-        file.seek(SeekFrom::Start(start_offset)).unwrap();
+        file.seek(SeekFrom::Start(start_offset))?;
         let len_offsets = (n + 1) * mem::size_of::<i64>();
 
-        file.set_len(start_offset + len_offsets as u64).unwrap();
-        let mut mmap = unsafe { MmapOptions::new().offset(start_offset).len(len_offsets).map_mut(&*file).unwrap()};
-        let offsets = unsafe { slice::from_raw_parts_mut(mmap.as_mut_ptr() as *mut usize, n + 1) };
+        // the offsets array is written after all strings
+        let mut offsets = vec![0usize; n + 1];
 
-        file.seek(SeekFrom::Start(start_offset + len_offsets as u64)).unwrap();
+        file.seek(SeekFrom::Start(start_offset + len_offsets as u64))?;
         let mut writer = BufWriter::new(file);
 
         let mut count = 0;
@@ -178,20 +369,27 @@ impl<'map> StringVector<'map> {
             offsets[count] = soffset;
 
             let bytes = s.as_ref().as_bytes();
-            writer.write_all(bytes).unwrap();
-            writer.write_all(&0u8.to_le_bytes()).unwrap(); // null terminator
+            writer.write_all(bytes)?;
+            writer.write_all(&0u8.to_le_bytes())?; // null terminator
 
             soffset += bytes.len()+1;
             count += 1;
         }
         offsets[count] = soffset;
-        writer.flush().unwrap();
+        writer.flush()?;
+
+        if count != n {
+            return Err(EncodeError::LengthMismatch { expected: n, found: count });
+        }
 
-        assert!(n == count, "Number of written strings differs from n");
+        let file = writer.into_inner().map_err(|e| e.into_error())?;
+        write_array_at(file, start_offset, &offsets)?;
 
         bom_entry.size = (len_offsets + soffset) as i64;
         bom_entry.param1 = count as i64;
         bom_entry.param2 = 0;
+
+        Ok(())
     }
 }
 
@@ -220,8 +418,15 @@ impl<'map> Iterator for StringVectorIterator<'map> {
             None => None,
         }
     }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.vec.len().saturating_sub(self.index);
+        (len, Some(len))
+    }
 }
 
+impl<'map> ExactSizeIterator for StringVectorIterator<'map> {}
+
 impl<'a, 'map> IntoIterator for &'a StringVector<'map> {
     type Item = &'map str;
     type IntoIter = StringVectorIterator<'map>;
@@ -263,22 +468,200 @@ where
     }
 }
 
+/// Order of the types in a lexicon, and thereby of their IDs
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LexiconOrder {
+    /// Most frequent types first, which keeps the IDs in the ID stream small
+    #[default]
+    Frequency,
+    /// Types sorted by their UTF-8 bytes
+    Bytes,
+    /// Types sorted by the Unicode collation algorithm with the CLDR root collation
+    #[cfg(feature = "collation")]
+    Collation,
+}
+
+impl LexiconOrder {
+    /// Name of the order in the "lexicon_order" metadata of a container
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Frequency => "frequency",
+            Self::Bytes => "bytes",
+            #[cfg(feature = "collation")]
+            Self::Collation => "collation",
+        }
+    }
+
+    /// Parses a name returned by [`name`](Self::name), `None` for unknown orders
+    /// and orders that are not available in this build
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "frequency" => Some(Self::Frequency),
+            "bytes" => Some(Self::Bytes),
+            #[cfg(feature = "collation")]
+            "collation" => Some(Self::Collation),
+            _ => None,
+        }
+    }
+
+    /// Comparison function of a sorted order, `None` for frequency order
+    pub fn comparator(&self) -> Option<Box<dyn FnMut(&str, &str) -> Ordering>> {
+        match self {
+            Self::Frequency => None,
+            Self::Bytes => Some(Box::new(|a: &str, b: &str| a.cmp(b))),
+            #[cfg(feature = "collation")]
+            Self::Collation => {
+                let mut collator = feruca::Collator::default();
+                Some(Box::new(move |a: &str, b: &str| collator.collate(a, b)))
+            }
+        }
+    }
+}
+
+/// Unicode normalization form applied to strings when they are encoded.
+///
+/// Text mixing composed and decomposed characters, e.g. from different sources, would
+/// otherwise get a separate lexicon entry for each form of the same word.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Normalization {
+    /// Canonical composition, which only unifies different encodings of the same characters
+    Nfc,
+    /// Compatibility composition, which also replaces ligatures, full width forms,
+    /// superscripts etc. by their plain equivalents
+    Nfkc,
+}
+
+impl Normalization {
+    /// Name of the form in the "normalization" metadata of a container
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Nfc => "NFC",
+            Self::Nfkc => "NFKC",
+        }
+    }
+
+    /// Parses a name returned by [`name`](Self::name), `None` for unknown forms
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "NFC" => Some(Self::Nfc),
+            "NFKC" => Some(Self::Nfkc),
+            _ => None,
+        }
+    }
+
+    /// Returns `string` in this form, borrowed if it already is
+    #[cfg(feature = "normalization")]
+    pub fn normalize<'a>(&self, string: &'a str) -> Cow<'a, str> {
+        match self {
+            Self::Nfc => icu_normalizer::ComposingNormalizerBorrowed::new_nfc().normalize(string),
+            Self::Nfkc => icu_normalizer::ComposingNormalizerBorrowed::new_nfkc().normalize(string),
+        }
+    }
+}
+
+/// IDs of the types of a lexicon under construction, by the FNV hash of their strings.
+///
+/// Different strings with the same hash get different IDs: the first of them is found
+/// directly and the others in a list of collisions, which is almost always empty. The
+/// index written from it has a pair for every type, so readers find all of them among
+/// the values of the hash.
+#[derive(Debug, Default)]
+pub(crate) struct TypeIndex {
+    first: HashMap<i64, usize>,
+    collisions: HashMap<i64, Vec<usize>>,
+}
+
+impl TypeIndex {
+    /// ID of `string` with `hash` among `types`, `None` if it has none yet
+    pub(crate) fn get(&self, hash: i64, string: &str, types: &[(String, usize)]) -> Option<usize> {
+        let first = *self.first.get(&hash)?;
+        if types[first].0 == string {
+            return Some(first);
+        }
+        self.collisions.get(&hash)?.iter().copied().find(|&id| types[id].0 == string)
+    }
+
+    /// Records the ID of a new string with `hash`
+    pub(crate) fn insert(&mut self, hash: i64, id: usize) {
+        match self.first.entry(hash) {
+            Entry::Vacant(entry) => {
+                entry.insert(id);
+            }
+            Entry::Occupied(_) => self.collisions.entry(hash).or_default().push(id),
+        }
+    }
+
+    /// Moves the type with ID `ids[i]` to ID `i` and returns the new ID of every old ID
+    pub(crate) fn reorder(&mut self, types: &mut Vec<(String, usize)>, ids: &[usize]) -> Vec<usize> {
+        let mut lut = vec![0; ids.len()];
+        for (ni, &oi) in ids.iter().enumerate() {
+            lut[oi] = ni;
+        }
+
+        let mut old: Vec<_> = mem::take(types).into_iter().map(Some).collect();
+        *types = ids.iter().map(|&oi| old[oi].take().expect("ids are a permutation")).collect();
+        for id in self.first.values_mut().chain(self.collisions.values_mut().flatten()) {
+            *id = lut[*id];
+        }
+        lut
+    }
+
+    /// Distinct hashes
+    pub(crate) fn hashes(&self) -> impl Iterator<Item = i64> + '_ {
+        self.first.keys().copied()
+    }
+
+    /// Number of distinct hashes
+    pub(crate) fn n_hashes(&self) -> usize {
+        self.first.len()
+    }
+
+    /// (hash, ID) pairs of all types, ascending by hash
+    pub(crate) fn pairs(&self) -> Vec<(i64, i64)> {
+        let colliding = self.collisions.iter().flat_map(|(hash, ids)| ids.iter().map(move |id| (*hash, *id)));
+        let mut pairs: Vec<_> = self.first.iter()
+            .map(|(hash, id)| (*hash, *id))
+            .chain(colliding)
+            .map(|(hash, id)| (hash, id as i64))
+            .collect();
+        pairs.sort_unstable();
+        pairs
+    }
+}
+
 pub struct LexiconBuilder {
     types: Vec<(String, usize)>,
-    type_idx: HashMap<i64, usize>,
+    type_idx: TypeIndex,
     id_stream_data: Vec<u8>,
     id_stream_sync: Vec<i64>,
     length: usize,
+    order: LexiconOrder,
+    index_budget: usize,
+    postings: PostingsEncoding,
+    normalization: Option<Normalization>,
+    bigram_min_frequency: Option<usize>,
+    wavelet_tree: bool,
+    hash_table_threshold: Option<usize>,
 }
 
 impl LexiconBuilder {
+    /// Number of types above which a [`HashTable`] is written by default
+    pub const DEFAULT_HASH_TABLE_THRESHOLD: usize = 1 << 24;
+
     pub fn new() -> Self {
         Self {
             types: Vec::new(),
-            type_idx: HashMap::new(),
+            type_idx: TypeIndex::default(),
             id_stream_data: Vec::new(),
             id_stream_sync: Vec::new(),
             length: 0,
+            order: LexiconOrder::Frequency,
+            index_budget: InvertedIndex::DEFAULT_MEMORY_BUDGET,
+            postings: PostingsEncoding::Delta,
+            normalization: None,
+            bigram_min_frequency: None,
+            wavelet_tree: false,
+            hash_table_threshold: Some(Self::DEFAULT_HASH_TABLE_THRESHOLD),
         }
     }
 
@@ -297,30 +680,44 @@ impl LexiconBuilder {
     }
 
     fn get_id_or_add(&mut self, token: &str) -> usize {
-        let hash = token.fnv_hash();
-
-        match self.type_idx.entry(hash) {
-            std::collections::hash_map::Entry::Occupied(entry) => {
-                let id = *entry.get();
+        let id = self.get_id_or_insert(token);
+        self.types[id].1 += 1;
+        id
+    }
 
-                // increase count
-                self.types[id].1 += 1;
+    /// ID of `token`, which is added with a count of 0 if it is new
+    fn get_id_or_insert(&mut self, token: &str) -> usize {
+        let token = self.normalize(token);
+        let hash = token.as_bytes().fnv_hash();
 
-                id
-            }
+        match self.type_idx.get(hash, &token, &self.types) {
+            Some(id) => id,
 
-            std::collections::hash_map::Entry::Vacant(entry) => {
+            None => {
                 let id = self.types.len();
 
                 // insert element
-                entry.insert(id);
-                self.types.push((token.into(), 1));
+                self.type_idx.insert(hash, id);
+                self.types.push((token.into_owned(), 0));
 
                 id
             }
         }
     }
 
+    #[cfg(feature = "normalization")]
+    fn normalize<'a>(&self, token: &'a str) -> Cow<'a, str> {
+        match self.normalization {
+            Some(normalization) => normalization.normalize(token),
+            None => Cow::Borrowed(token),
+        }
+    }
+
+    #[cfg(not(feature = "normalization"))]
+    fn normalize<'a>(&self, token: &'a str) -> Cow<'a, str> {
+        Cow::Borrowed(token)
+    }
+
     pub fn add_strings<S, I>(&mut self, mut strings: I)
     where
         S: Into<String> + AsRef<str>,
@@ -333,19 +730,10 @@ impl LexiconBuilder {
             id_stream.push(self.get_id_or_add(s.as_ref()));
         }
 
-        // sort lexicon
-        self.types.sort_unstable_by_key(|(_, count)| *count);
-        self.types.reverse();
-
-        // lookup table
-        // from old id to new id
-        let mut lut = vec![0; self.types.len()];
-        for ni in 0..self.types.len() {
-            let hash = &self.types[ni].0.fnv_hash();
-            let oi = self.type_idx[hash];
-            lut[oi] = ni;
-            self.type_idx.insert(*hash, ni);
-        }
+        // sort lexicon by descending frequency, lookup table from old id to new id
+        let mut ids: Vec<usize> = (0..self.types.len()).collect();
+        ids.sort_unstable_by_key(|&id| Reverse(self.types[id].1));
+        let lut = self.type_idx.reorder(&mut self.types, &ids);
 
         // transform id_stream from old to new ids
         for i in 0..id_stream.len() {
@@ -356,11 +744,11 @@ impl LexiconBuilder {
         let mut idbuf = [0i64; 16];
 
         // compress id_stream
-        for (i, id) in id_stream.iter().enumerate() {
+        for id in id_stream.iter() {
             self.length += 1;
-            bufi = i % 16;
             idbuf[bufi] = *id as i64;
-            if bufi == 15 {
+            bufi += 1;
+            if bufi == idbuf.len() {
                 self.encode_block(&idbuf);
                 bufi = 0;
             }
@@ -385,11 +773,13 @@ impl LexiconBuilder {
             self.length += 1;
         }
 
-        // finish last id_stream block
-        for i in bufi+1..idbuf.len() {
-            idbuf[i] = -1;
+        // finish last id_stream block, bufi is the number of ids in the buffer
+        if bufi > 0 {
+            for i in bufi..idbuf.len() {
+                idbuf[i] = -1;
+            }
+            self.encode_block(&idbuf);
         }
-        self.encode_block(&idbuf);
     }
 
     pub fn from_strings<S, I>(strings: I) -> Self 
@@ -402,6 +792,166 @@ impl LexiconBuilder {
         lex
     }
 
+    /// Builds the union of several lexicons and the concatenation of their ID streams,
+    /// with every ID recoded from its own lexicon into the union, e.g. to append corpora.
+    ///
+    /// Each part is a lexicon with an ID stream over it. The union is in frequency order,
+    /// frequencies are counted from the ID streams.
+    pub fn from_id_streams<'a, L, I, P>(parts: P) -> Self
+    where
+        L: IntoIterator<Item = &'a str>,
+        I: IntoIterator<Item = usize>,
+        P: IntoIterator<Item = (L, I)>,
+    {
+        let mut lex = Self::new();
+        let mut bufi = 0;
+        let mut idbuf = [0i64; 16];
+
+        for (lexicon, ids) in parts {
+            // from id in the part to id in the union
+            let lut: Vec<usize> = lexicon.into_iter().map(|s| lex.get_id_or_insert(s)).collect();
+
+            for id in ids {
+                let id = lut[id];
+                lex.types[id].1 += 1;
+                idbuf[bufi] = id as i64;
+                bufi += 1;
+                if bufi == idbuf.len() {
+                    lex.encode_block(&idbuf);
+                    bufi = 0;
+                }
+                lex.length += 1;
+            }
+        }
+
+        if bufi > 0 {
+            idbuf[bufi..].fill(-1);
+            lex.encode_block(&idbuf);
+        }
+
+        let mut ids: Vec<usize> = (0..lex.types.len()).collect();
+        ids.sort_by(|&a, &b| lex.types[b].1.cmp(&lex.types[a].1));
+        lex.reorder(&ids);
+
+        lex
+    }
+
+    /// Reorders the types added so far, and the IDs in the ID stream with them.
+    ///
+    /// Sorting is done once after all strings are added, the compressed ID stream is
+    /// decoded and encoded again block by block. Frequency order is kept as is.
+    pub fn sort(&mut self, order: LexiconOrder) {
+        let Some(mut cmp) = order.comparator() else {
+            return;
+        };
+
+        let mut ids: Vec<usize> = (0..self.types.len()).collect();
+        ids.sort_by(|&a, &b| cmp(&self.types[a].0, &self.types[b].0));
+        self.reorder(&ids);
+
+        self.order = order;
+    }
+
+    /// Moves the type with ID `ids[i]` to ID `i` and recodes the ID stream
+    fn reorder(&mut self, ids: &[usize]) {
+        // from old id to new id
+        let lut = self.type_idx.reorder(&mut self.types, ids);
+
+        let data = mem::take(&mut self.id_stream_data);
+        let sync = mem::take(&mut self.id_stream_sync);
+        for bounds in sync.windows(2) {
+            let mut block = Vector::decode_compressed_block(1, &data[bounds[0] as usize..bounds[1] as usize]);
+            // padding at the end of the last block stays -1
+            for id in block.iter_mut().filter(|id| **id >= 0) {
+                *id = lut[*id as usize] as i64;
+            }
+            self.encode_block(&block);
+        }
+    }
+
+    /// Limits the postings held in memory while writing the inverted index, in bytes.
+    ///
+    /// Each time the budget is exceeded costs another pass over the ID stream, see
+    /// [`InvertedIndex::encode_to_container_file_with_budget`]. The passes read the ID stream
+    /// back from the written container, so the budget covers all of the index encoder. It does
+    /// not cover the lexicon with its type index, which stays in memory until the variable is
+    /// written.
+    pub fn set_index_memory_budget(&mut self, bytes: usize) {
+        self.index_budget = bytes;
+    }
+
+    /// Selects the encoding of the postings lists in the inverted index
+    pub fn set_postings_encoding(&mut self, encoding: PostingsEncoding) {
+        self.postings = encoding;
+    }
+
+    /// Adds a [`BigramIndex`](super::BigramIndex) of the pairs of adjacent types that both occur at least
+    /// `min_frequency` times, or no bigram index for `None`, which is the default.
+    ///
+    /// A threshold of 1 indexes all pairs, which answers any phrase of two types from a
+    /// single postings list but roughly doubles the size of the inverted index. Higher
+    /// thresholds only index the pairs of frequent types, whose postings are the slowest
+    /// to intersect, and leave the others to the inverted index.
+    pub fn set_bigram_index(&mut self, min_frequency: Option<usize>) {
+        self.bigram_min_frequency = min_frequency;
+    }
+
+    /// Adds a [`WaveletTree`] over the ID stream, which reads IDs at random positions and
+    /// counts a type in any range of positions with a few ranks per bit of the type IDs.
+    ///
+    /// The tree takes about as much space as the uncompressed ID stream, so it suits
+    /// variables with few types that are both accessed at random and counted in ranges,
+    /// e.g. part-of-speech tags.
+    pub fn set_wavelet_tree(&mut self, wavelet_tree: bool) {
+        self.wavelet_tree = wavelet_tree;
+    }
+
+    pub fn wavelet_tree(&self) -> bool {
+        self.wavelet_tree
+    }
+
+    /// Adds a [`HashTable`] for string lookups if the lexicon has more than `threshold`
+    /// types, never for `None`.
+    ///
+    /// Looking up a string in the sorted hash index takes a binary search, whose steps
+    /// touch pages all over a large index. The table answers most lookups from one slot
+    /// at the cost of 21 to 43 bytes per type.
+    pub fn set_hash_table_threshold(&mut self, threshold: Option<usize>) {
+        self.hash_table_threshold = threshold;
+    }
+
+    /// Whether a hash table is written for the types added so far
+    pub fn hash_table(&self) -> bool {
+        self.hash_table_threshold.is_some_and(|threshold| self.types() > threshold)
+    }
+
+    /// Collects the pairs of the bigram index, if one is configured. Types must be in their
+    /// final order, i.e. after [`sort`](Self::sort).
+    pub fn bigram_builder(&self) -> Option<BigramBuilder> {
+        let min_frequency = self.bigram_min_frequency?;
+        let frequencies: Vec<usize> = self.types.iter().map(|(_, count)| *count).collect();
+        let cvec = CachedVector::<1>::new(self.get_id_stream()).unwrap();
+        Some(BigramBuilder::new(cvec.column_iter(0), &frequencies, min_frequency))
+    }
+
+    /// Normalizes all strings added from now on to `normalization`, so that strings which
+    /// only differ in their form become the same type.
+    ///
+    /// Strings added before are kept as they are, so this is best called right after
+    /// [`new`](Self::new).
+    #[cfg(feature = "normalization")]
+    pub fn set_normalization(&mut self, normalization: Normalization) {
+        self.normalization = Some(normalization);
+    }
+
+    pub fn normalization(&self) -> Option<Normalization> {
+        self.normalization
+    }
+
+    pub fn order(&self) -> LexiconOrder {
+        self.order
+    }
+
     pub fn stats(&self) {
         println!("total ids: {}", self.length);
         println!("types: {:?}", self.types);
@@ -421,48 +971,84 @@ impl LexiconBuilder {
     }
 
     pub fn get_id_stream(&self) -> Vector<'_> {
-        Vector::Compressed { length: self.length, width: 1, sync: &self.id_stream_sync, data: &self.id_stream_data }
+        Vector::Compressed { length: self.length, width: 1, block_size: DEFAULT_BLOCK_SIZE, sync: &self.id_stream_sync, data: &self.id_stream_data }
     }
 
-    pub unsafe fn write_lexicon(&self, file: &mut File, bom_entry: &mut BomEntry, start_offset: u64) {
+    pub unsafe fn write_lexicon<W: Write + Seek>(&self, file: &mut W, bom_entry: &mut BomEntry, start_offset: u64) -> Result<(), EncodeError> {
         let strings = self.types.iter().map(|(s, _)| s);
         StringVector::encode_to_container_file(strings, self.types(), file, bom_entry, start_offset)
     }
 
-    pub unsafe fn write_index(&self, file: &mut File, bom_entry: &mut BomEntry, start_offset: u64) {
-        let mut pairs: Vec<_> = self.type_idx.iter().map(|(k, v)| (*k, *v as i64)).collect();
-        pairs.sort_unstable_by_key(|(k, _)| *k);
-        
-        Index::encode_uncompressed_to_container_file(pairs.iter().copied(), self.types(), file, bom_entry, start_offset);
+    pub unsafe fn write_index<W: Write + Seek>(&self, file: &mut W, bom_entry: &mut BomEntry, start_offset: u64) -> Result<(), EncodeError> {
+        let pairs = self.type_idx.pairs();
+        Index::encode_uncompressed_to_container_file(pairs.iter().copied(), self.types(), file, bom_entry, start_offset)
+    }
+
+    /// Writes a [`BloomFilter`] over the hashes of the index written by [`write_index`](Self::write_index)
+    pub unsafe fn write_index_bloom<W: Write + Seek>(&self, file: &mut W, bom_entry: &mut BomEntry, start_offset: u64) -> Result<(), EncodeError> {
+        BloomFilter::encode_to_container_file(self.type_idx.hashes(), self.type_idx.n_hashes(), file, bom_entry, start_offset)
     }
 
-    pub unsafe fn write_id_stream(&self, file: &mut File, bom_entry: &mut BomEntry, start_offset: u64, compressed: bool) {
+    /// Writes a [`HashTable`] over the same pairs as the index written by [`write_index`](Self::write_index)
+    pub unsafe fn write_hash_table<W: Write + Seek>(&self, file: &mut W, bom_entry: &mut BomEntry, start_offset: u64) -> Result<(), EncodeError> {
+        HashTable::encode_to_container_file(self.type_idx.pairs(), self.types(), file, bom_entry, start_offset)
+    }
+
+    pub unsafe fn write_id_stream<W: Write + Seek>(&self, file: &mut W, bom_entry: &mut BomEntry, start_offset: u64, compressed: bool) -> Result<(), EncodeError> {
         if compressed {
-            file.seek(SeekFrom::Start(start_offset)).unwrap();
+            file.seek(SeekFrom::Start(start_offset))?;
 
             let m = (self.length-1) / 16 + 1;
             assert!(self.id_stream_sync.len() == m+1, "somehow encoded too many blocks?");
             let sync = slice::from_raw_parts(self.id_stream_sync.as_ptr() as *const u8, mem::size_of::<i64>() * m);
-            file.write_all(sync).unwrap();
+            file.write_all(sync)?;
             bom_entry.size = sync.len() as i64;
 
-            file.write_all(&self.id_stream_data).unwrap();
+            file.write_all(&self.id_stream_data)?;
             bom_entry.size += self.id_stream_data.len() as i64;
 
-            file.flush().unwrap();
+            file.flush()?;
 
             bom_entry.param1 = self.tokens() as i64;
             bom_entry.param2 = 1;
+
+            Ok(())
         } else {
             // this is fucking silly
             let cvec = CachedVector::<1>::new(self.get_id_stream()).unwrap();
-            Vector::encode_uncompressed_to_container_file(cvec.column_iter(0), cvec.len(), cvec.width(), file, bom_entry, start_offset);
+            Vector::encode_uncompressed_to_container_file(cvec.column_iter(0), cvec.len(), cvec.width(), file, bom_entry, start_offset)
         }
     }
 
-    pub fn write_inverted_index(&self, file: &mut File, bom_entry: &mut BomEntry, start_offset: u64) {
-        let cvec = CachedVector::<1>::new(self.get_id_stream()).unwrap();
-        InvertedIndex::encode_to_container_file(self.types(), cvec.column_iter(0), self.tokens(), file, bom_entry, start_offset);
+    /// Writes the frequency of each type as an uncompressed Vector
+    pub unsafe fn write_frequencies<W: Write + Seek>(&self, file: &mut W, bom_entry: &mut BomEntry, start_offset: u64) -> Result<(), EncodeError> {
+        let freqs = self.types.iter().map(|(_, count)| *count as i64);
+        Vector::encode_uncompressed_to_container_file(freqs, self.types(), 1, file, bom_entry, start_offset)
+    }
+
+    /// Frees the ID stream once it has been written with [`write_id_stream`](Self::write_id_stream).
+    /// The components built from it are then written from the written copy, which is passed
+    /// as `id_stream` to [`write_inverted_index`](Self::write_inverted_index) and the like.
+    pub fn release_id_stream(&mut self) {
+        self.id_stream_data = Vec::new();
+        self.id_stream_sync = Vec::new();
+    }
+
+    /// Writes the inverted index of `id_stream`, the ID stream written by
+    /// [`write_id_stream`](Self::write_id_stream), within the configured memory budget
+    pub fn write_inverted_index<W: Write + Seek>(&self, id_stream: &CachedVector<1>, file: &mut W, bom_entry: &mut BomEntry, start_offset: u64) -> Result<(), EncodeError> {
+        InvertedIndex::encode_to_container_file_with_budget(self.types(), || id_stream.column_iter(0), id_stream.len(), self.postings, self.index_budget, file, bom_entry, start_offset)
+    }
+
+    /// Writes the "BigramIndex" component of the pairs collected by `bigrams`, see
+    /// [`write_inverted_index`](Self::write_inverted_index)
+    pub fn write_bigram_index<W: Write + Seek>(&self, bigrams: &BigramBuilder, id_stream: &CachedVector<1>, file: &mut W, bom_entry: &mut BomEntry, start_offset: u64) -> Result<(), EncodeError> {
+        bigrams.write_index(|| id_stream.column_iter(0), id_stream.len(), self.postings, self.index_budget, file, bom_entry, start_offset)
+    }
+
+    /// Writes a wavelet tree over `id_stream`, see [`set_wavelet_tree`](Self::set_wavelet_tree)
+    pub unsafe fn write_wavelet_tree<W: Write + Seek>(&self, id_stream: &CachedVector<1>, file: &mut W, bom_entry: &mut BomEntry, start_offset: u64) -> Result<(), EncodeError> {
+        WaveletTree::encode_to_container_file(id_stream.column_iter(0).map(|id| id as usize), self.types(), file, bom_entry, start_offset)
     }
 }
 
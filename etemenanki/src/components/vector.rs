@@ -1,10 +1,36 @@
 use core::slice;
-use std::{cell::RefCell, cmp::min, fs::File, io::{BufWriter, Seek, SeekFrom, Write}, mem, num::NonZeroUsize, ops, rc::Rc};
+use std::{borrow::Cow, cell::RefCell, cmp::min, io::{BufWriter, Seek, SeekFrom, Write}, mem, num::NonZeroUsize, ops, rc::Rc, thread};
 
 use lru::LruCache;
-use memmap2::MmapOptions;
 
-use crate::container::BomEntry;
+use crate::container::{BomEntry, EncodeError};
+
+use super::{encoded_block_len, encoded_blocks, write_array_at, CacheStats};
+
+/// Number of rows per block of compressed vectors unless set otherwise at encode time
+pub const DEFAULT_BLOCK_SIZE: usize = 16;
+
+/// Splits `param2` of a compressed vector into its width (low 32 bits) and block size (high 32 bits).
+///
+/// A block size of 0 stands for [`DEFAULT_BLOCK_SIZE`], so containers written before the
+/// block size was configurable are read unchanged.
+pub(crate) fn split_param2(param2: i64) -> (usize, usize) {
+    let param2 = param2 as u64;
+    let width = (param2 & 0xffff_ffff) as usize;
+    let block_size = match (param2 >> 32) as usize {
+        0 => DEFAULT_BLOCK_SIZE,
+        b => b,
+    };
+    (width, block_size)
+}
+
+fn join_param2(width: usize, block_size: usize) -> i64 {
+    if block_size == DEFAULT_BLOCK_SIZE {
+        width as i64
+    } else {
+        (((block_size as u64) << 32) | width as u64) as i64
+    }
+}
 
 #[derive(Debug, Clone, Copy)]
 pub enum CompressionType {
@@ -23,6 +49,7 @@ pub enum Vector<'map> {
     Compressed {
         length: usize,
         width: usize,
+        block_size: usize,
         sync: &'map [i64],
         data: &'map [u8],
     },
@@ -30,100 +57,32 @@ pub enum Vector<'map> {
     Delta {
         length: usize,
         width: usize,
+        block_size: usize,
         sync: &'map [i64],
         data: &'map [u8],
     },
 }
 
 impl<'map> Vector<'map> {
-    /// Decodes a compressed block and returns it as a contiguous Vec of dimension n*d in row major order.
+    /// Decodes a compressed block of the default block size and returns it as a contiguous Vec of dimension n*d in row major order.
     pub fn decode_compressed_block(d: usize, raw_data: &[u8]) -> Vec<i64> {
-        let mut block = vec![0i64; d * 16];
-        let mut offset = 0;
-
-        for i in 0..d {
-            for j in 0..16 {
-                let (int, len) = ziggurat_varint::decode(&raw_data[offset..]);
-                block[(j * d) + i] = int; // wonky because conversion from col-major to row-major
-                offset += len;
-            }
-        }
-
+        let mut block = vec![0i64; d * DEFAULT_BLOCK_SIZE];
+        decode_block_into(raw_data, d, DEFAULT_BLOCK_SIZE, false, &mut block);
         block
     }
 
-    /// Decodes a delta compressed block and returns it as a contiguous Vec of dimension n*d in row-major order.
+    /// Decodes a delta compressed block of the default block size and returns it as a contiguous Vec of dimension n*d in row-major order.
     pub fn decode_delta_block(d: usize, raw_data: &[u8]) -> Vec<i64> {
-        let mut delta_block = vec![0i64; d * 16];
-        let mut offset = 0;
-
-        for i in 0..d {
-            for j in 0..16 {
-                let (int, len) = ziggurat_varint::decode(&raw_data[offset..]);
-                let current = (j * d) + i;
-                if j == 0 {
-                    delta_block[current] = int; // initial seed values
-                } else {
-                    let last = ((j - 1) * d) + i;
-                    delta_block[current] = delta_block[last] + int;
-                }
-                offset += len;
-            }
-        }
-
-        delta_block
-    }
-
-    /// Returns a tuple (block_index, row_start, row_end) for a given row index.
-    fn row_index_to_block_offsets(width: usize, index: usize) -> (usize, usize, usize) {
-        let bi = index / 16;
-        let start = (index % 16) * width;
-        let end = start + width;
-        (bi, start, end)
-    }
-
-    /// Gets the value with `index` < `self.len()`*`self.width()`.
-    /// Use get_row instead. 
-    ///
-    /// This always triggers a full block decode on compressed Vectors,
-    /// for efficient block cached access use `CachedVector`.
-    #[deprecated]
-    #[allow(deprecated)]
-    pub fn get(&self, index: usize) -> Option<i64> {
-        if index < self.len() * self.width() {
-            Some(self.get_unchecked(index))
-        } else {
-            None
-        }
-    }
-
-    /// Gets the value with `index` < `self.len()`*`self.width()`.
-    /// Panics if index is out of bounds.
-    /// Use get_row_unchecked instead.
-    ///
-    /// This always triggers a full block decode on compressed Vectors,
-    /// for efficient block cached access use `CachedVector`.
-    #[deprecated]
-    pub fn get_unchecked(&self, index: usize) -> i64 {
-        match *self {
-            Self::Uncompressed { length: _, width: _, data } => {
-                data[index]
-            }
-
-            Self::Compressed { length: _, width, sync: _, data: _ } |
-            Self::Delta { length: _, width, sync: _, data: _ } => {
-                let ri = index / width;
-                let ci = index % width;
-                self.get_row_unchecked(ri)[ci]  
-            }
-        }
+        let mut block = vec![0i64; d * DEFAULT_BLOCK_SIZE];
+        decode_block_into(raw_data, d, DEFAULT_BLOCK_SIZE, true, &mut block);
+        block
     }
 
     /// Gets the column with `index` < `self.len()`.
     ///
     /// This always triggers a full block decode on compressed Vectors,
     /// for efficient block cached access use `CachedVector`.
-    pub fn get_row(&self, index: usize) -> Option<VecSlice> {
+    pub fn get_row(&self, index: usize) -> Option<VecSlice<'map>> {
         if index < self.len() {
             Some(self.get_row_unchecked(index))
         } else {
@@ -136,7 +95,7 @@ impl<'map> Vector<'map> {
     ///
     /// This always triggers a full block decode on compressed Vectors,
     /// for efficient block cached access use `CachedVector`.
-    pub fn get_row_unchecked(&self, index: usize) -> VecSlice {
+    pub fn get_row_unchecked(&self, index: usize) -> VecSlice<'map> {
         match *self {
                 Self::Uncompressed { length: _, width, data } => {
                     let start = index * width;
@@ -144,18 +103,33 @@ impl<'map> Vector<'map> {
                     VecSlice::Borrowed(&data[start..end])
                 }
 
-                Self::Compressed { length: _, width, sync, data } |
-                Self::Delta { length: _, width, sync, data } => {
-                    let (bi, start, end) = Vector::row_index_to_block_offsets(width, index);
+                Self::Compressed { length: _, width, block_size, sync, data } |
+                Self::Delta { length: _, width, block_size, sync, data } => {
+                    let offset = sync[index / block_size] as usize;
+                    let delta = matches!(self, Self::Delta { .. });
 
-                    let offset = sync[bi] as usize;
-                    let block = match self {
-                        Vector::Uncompressed { .. } => unreachable!("unreachable because of previous match block"),
-                        Vector::Compressed { .. } => Self::decode_compressed_block(width, &data[offset..]),
-                        Vector::Delta { .. } => Self::decode_delta_block(width, &data[offset..]),
-                    };
+                    let mut block = vec![0i64; width * block_size];
+                    decode_block_into(&data[offset..], width, block_size, delta, &mut block);
 
-                    VecSlice::Owned(block[start..end].to_owned())
+                    let start = (index % block_size) * width;
+                    VecSlice::Owned(block[start..start + width].to_owned())
+            }
+        }
+    }
+
+    /// Rows of the block `block` of [`block_size`](Self::block_size) rows in row-major order,
+    /// for reading rows of a width only known at runtime one block at a time.
+    ///
+    /// The last block of a compressed vector is padded up to the full block size.
+    pub fn block_rows(&self, block: usize) -> VecSlice<'map> {
+        match *self {
+            Self::Uncompressed { .. } => self.get_row_unchecked(block),
+            Self::Compressed { width, block_size, sync, data, .. } |
+            Self::Delta { width, block_size, sync, data, .. } => {
+                let delta = matches!(self, Self::Delta { .. });
+                let mut rows = vec![0i64; width * block_size];
+                decode_block_into(&data[sync[block] as usize..], width, block_size, delta, &mut rows);
+                VecSlice::Owned(rows)
             }
         }
     }
@@ -176,10 +150,28 @@ impl<'map> Vector<'map> {
         }
     }
 
+    /// Number of rows per block, 1 for uncompressed vectors
+    pub fn block_size(&self) -> usize {
+        match self {
+            Self::Uncompressed { .. } => 1,
+            Self::Compressed { block_size, .. } |
+            Self::Delta { block_size, .. } => *block_size,
+        }
+    }
+
+    /// Sets the number of rows per block of a compressed vector, it has no effect on uncompressed vectors.
+    pub fn with_block_size(mut self, size: usize) -> Self {
+        if let Self::Compressed { block_size, .. } | Self::Delta { block_size, .. } = &mut self {
+            *block_size = size;
+        }
+        self
+    }
+
     pub fn delta_from_parts(n: usize, d: usize, sync: &'map [i64], data: &'map [u8]) -> Self {
         Self::Delta {
             length: n,
             width: d,
+            block_size: DEFAULT_BLOCK_SIZE,
             sync,
             data,
         }
@@ -189,6 +181,7 @@ impl<'map> Vector<'map> {
         Self::Compressed {
             length: n,
             width: d,
+            block_size: DEFAULT_BLOCK_SIZE,
             sync,
             data,
         }
@@ -202,23 +195,27 @@ impl<'map> Vector<'map> {
         }
     }
 
-    unsafe fn _generic_encode_compressed_to_container_file<I, const D: usize>(values: I, n: usize, file: &mut File, bom_entry: &mut BomEntry, start_offset: u64, encode_varint: fn(&[i64], &mut[u8]) -> usize)
+    unsafe fn _generic_encode_compressed_to_container_file<I, W: Write + Seek, const D: usize>(values: I, n: usize, block_size: usize, file: &mut W, bom_entry: &mut BomEntry, start_offset: u64, encode_varint: fn(&[i64], &mut[u8]) -> usize) -> Result<(), EncodeError>
     where
         I: Iterator<Item=[i64; D]>,
     {
-        let m = (n-1) / 16 + 1;
+        if block_size == 0 || block_size > u32::MAX as usize {
+            return Err(EncodeError::InvalidBlockSize(block_size));
+        }
+
+        let m = (n-1) / block_size + 1;
         let synclen = m * mem::size_of::<i64>();
 
-        file.set_len(start_offset + synclen as u64).unwrap();
-        let mut mmap = unsafe { MmapOptions::new().offset(start_offset).len(synclen).map_mut(&*file).unwrap()};
-        let sync = unsafe { slice::from_raw_parts_mut(mmap.as_mut_ptr() as *mut usize, m) };
+        // the sync array is written after all blocks are encoded
+        let mut sync = vec![0usize; m];
 
-        file.seek(SeekFrom::Start(start_offset + synclen as u64)).unwrap();
+        file.seek(SeekFrom::Start(start_offset + synclen as u64))?;
         let mut writer = BufWriter::new(file);
 
-        let mut buffer = vec![0u8; 16 * D * 9];
-        let mut columns = vec![[0i64; 16]; D];
+        let mut buffer = vec![0u8; block_size * D * 9];
+        let mut columns = vec![vec![0i64; block_size]; D];
         let mut boffset = 0;
+        let mut count = 0;
         let mut values = values.take(n);
 
         for bi in 0..m {
@@ -226,8 +223,9 @@ impl<'map> Vector<'map> {
             sync[bi] = boffset;
 
             // collect block and bring it in column-major form
-            for ri in 0..16 {
+            for ri in 0..block_size {
                 if let Some(row) = values.next() {
+                    count += 1;
                     for ci in 0..D {
                         columns[ci][ri] = row[ci];
                     }
@@ -243,46 +241,78 @@ impl<'map> Vector<'map> {
             for column in columns.iter() {
                 len += encode_varint(column, &mut buffer[len..]);
             }
-            writer.write_all(&buffer[..len]).unwrap();
+            writer.write_all(&buffer[..len])?;
             boffset += len;
         }
-        writer.flush().unwrap();
+        writer.flush()?;
+
+        if count != n {
+            return Err(EncodeError::LengthMismatch { expected: n, found: count });
+        }
+
+        let file = writer.into_inner().map_err(|e| e.into_error())?;
+        write_array_at(file, start_offset, &sync)?;
 
         bom_entry.size = (synclen + boffset) as i64;
         bom_entry.param1 = n as i64;
-        bom_entry.param2 = D as i64;
+        bom_entry.param2 = join_param2(D, block_size);
+
+        Ok(())
     }
 
-    pub unsafe fn encode_delta_to_container_file<I, const D: usize>(values: I, n: usize, file: &mut File, bom_entry: &mut BomEntry, start_offset: u64)
+    pub unsafe fn encode_delta_to_container_file<I, W: Write + Seek, const D: usize>(values: I, n: usize, file: &mut W, bom_entry: &mut BomEntry, start_offset: u64) -> Result<(), EncodeError>
     where
         I: Iterator<Item=[i64; D]>
     {
-        Self::_generic_encode_compressed_to_container_file(values, n, file, bom_entry, start_offset, ziggurat_varint::encode_delta_block_into);
+        Self::encode_delta_to_container_file_with_block_size(values, n, DEFAULT_BLOCK_SIZE, file, bom_entry, start_offset)
     }
 
-    pub unsafe fn encode_compressed_to_container_file<I, const D: usize>(values: I, n: usize, file: &mut File, bom_entry: &mut BomEntry, start_offset: u64)
+    pub unsafe fn encode_compressed_to_container_file<I, W: Write + Seek, const D: usize>(values: I, n: usize, file: &mut W, bom_entry: &mut BomEntry, start_offset: u64) -> Result<(), EncodeError>
     where
         I: Iterator<Item=[i64; D]>
     {
-        Self::_generic_encode_compressed_to_container_file(values, n, file, bom_entry, start_offset, ziggurat_varint::encode_block_into);
+        Self::encode_compressed_to_container_file_with_block_size(values, n, DEFAULT_BLOCK_SIZE, file, bom_entry, start_offset)
     }
 
-    pub unsafe fn encode_uncompressed_to_container_file<I>(values: I, n: usize, d: usize, file: &mut File, bom_entry: &mut BomEntry, start_offset: u64) where I: Iterator<Item=i64> {
-        file.seek(SeekFrom::Start(start_offset)).unwrap();
+    /// Like [`Vector::encode_delta_to_container_file`], but with `block_size` rows per block.
+    ///
+    /// Larger blocks shrink the sync array and help wide vectors compress, at the cost of
+    /// decoding more rows for every random access.
+    pub unsafe fn encode_delta_to_container_file_with_block_size<I, W: Write + Seek, const D: usize>(values: I, n: usize, block_size: usize, file: &mut W, bom_entry: &mut BomEntry, start_offset: u64) -> Result<(), EncodeError>
+    where
+        I: Iterator<Item=[i64; D]>
+    {
+        Self::_generic_encode_compressed_to_container_file(values, n, block_size, file, bom_entry, start_offset, ziggurat_varint::encode_delta_block_into)
+    }
+
+    /// Like [`Vector::encode_compressed_to_container_file`], but with `block_size` rows per block.
+    pub unsafe fn encode_compressed_to_container_file_with_block_size<I, W: Write + Seek, const D: usize>(values: I, n: usize, block_size: usize, file: &mut W, bom_entry: &mut BomEntry, start_offset: u64) -> Result<(), EncodeError>
+    where
+        I: Iterator<Item=[i64; D]>
+    {
+        Self::_generic_encode_compressed_to_container_file(values, n, block_size, file, bom_entry, start_offset, ziggurat_varint::encode_block_into)
+    }
+
+    pub unsafe fn encode_uncompressed_to_container_file<I, W: Write + Seek>(values: I, n: usize, d: usize, file: &mut W, bom_entry: &mut BomEntry, start_offset: u64) -> Result<(), EncodeError> where I: Iterator<Item=i64> {
+        file.seek(SeekFrom::Start(start_offset))?;
         
         // write data
         let mut written = 0;
         let mut writer = BufWriter::new(file);
         for bytes in values.take(n*d).map(|i| i.to_le_bytes()) {
-            writer.write_all(&bytes).unwrap();
+            writer.write_all(&bytes)?;
             written += 1;
         }
-        writer.flush().unwrap();
-        assert!(written == n*d, "could not write all values");
+        writer.flush()?;
+        if written != n * d {
+            return Err(EncodeError::LengthMismatch { expected: n, found: written / d });
+        }
 
         bom_entry.size = (written * mem::size_of::<i64>()) as i64;
         bom_entry.param1 = n as i64;
         bom_entry.param2 = d as i64;
+
+        Ok(())
     }
 }
 
@@ -314,48 +344,49 @@ impl<'map> ToOwned for VecSlice<'map> {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+/// Decodes a (delta) compressed block of `width` columns into `rows` in row-major order.
+///
+/// This is the only block decoder, both the uncached `Vector` and `VectorBlock` use it.
+/// Blocks are stored column by column, each column as `block_size` varints. In delta blocks
+/// the first value of a column is its seed and all others are deltas to the previous row.
+fn decode_block_into(data: &[u8], width: usize, block_size: usize, delta: bool, rows: &mut [i64]) {
+    let mut offset = 0;
+
+    for ci in 0..width {
+        for ri in 0..block_size {
+            let (int, len) = ziggurat_varint::decode(&data[offset..]);
+            rows[(ri * width) + ci] = if delta && ri > 0 {
+                rows[((ri - 1) * width) + ci].wrapping_add(int)
+            } else {
+                int
+            };
+            offset += len;
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct VectorBlock<const D: usize> {
-    rows: [[i64; D]; 16],
+    rows: Vec<[i64; D]>,
     length: usize,
 }
 
 impl<const D: usize> VectorBlock<D> {
-    /// Decodes a compressed block into memory and turns it into row-major canonical representation
+    /// Decodes a compressed block of the default block size into memory and turns it into row-major canonical representation
     pub fn decode_compressed(data: &[u8], length: usize) -> Self {
-        let mut rows = [[0i64; D]; 16];
-        let mut offset = 0;
-
-        for i in 0..16 {
-            for j in 0..D {
-                let (int, len) = ziggurat_varint::decode(&data[offset..]);
-                rows[i][j] = int;
-                offset += len;
-            }
-        }
-
-        Self {
-            rows,
-            length,
-        }
+        Self::decode(data, CompressionType::VarInt, DEFAULT_BLOCK_SIZE, length)
     }
 
-    /// Decodes a delta compressed block into memory and turns it into row-major canonical representation
+    /// Decodes a delta compressed block of the default block size into memory and turns it into row-major canonical representation
     pub fn decode_delta(data: &[u8], length: usize) -> Self {
-        let mut rows = [[0i64; D]; 16];
-        let mut offset = 0;
-
-        for i in 0..D {
-            for j in 0..16 {
-                let (int, len) = ziggurat_varint::decode(&data[offset..]);
-                if j == 0 {
-                    rows[j][i] = int; // initial seed values
-                } else {
-                    rows[j][i] = rows[j-1][i] + int;
-                }
-                offset += len;
-            }
-        }
+        Self::decode(data, CompressionType::Delta, DEFAULT_BLOCK_SIZE, length)
+    }
+
+    /// Decodes a block of `block_size` rows, of which the first `length` are valid.
+    pub fn decode(data: &[u8], comp_type: CompressionType, block_size: usize, length: usize) -> Self {
+        let mut rows = vec![[0i64; D]; block_size];
+        let delta = matches!(comp_type, CompressionType::Delta);
+        decode_block_into(data, D, block_size, delta, rows.as_flattened_mut());
 
         Self {
             rows,
@@ -389,13 +420,25 @@ impl<const D: usize> VectorBlock<D> {
     }
 }
 
+/// Blocks decoded on a background thread, see [`VectorBlockCache::prefetch`]
+#[derive(Debug)]
+struct PendingBlocks<const D: usize> {
+    /// range of block indices containing all decoded blocks
+    blocks: ops::Range<usize>,
+    /// (block index, block, size of the encoded block) of every decoded block
+    worker: thread::JoinHandle<Vec<(usize, VectorBlock<D>, usize)>>,
+}
+
 #[derive(Debug)]
 pub struct VectorBlockCache<'map, const D: usize> {
     comp_type: CompressionType,
     length: usize,
+    block_size: usize,
     sync: &'map [i64],
     data: &'map [u8],
     cache: LruCache<usize, VectorBlock<D>>,
+    stats: CacheStats,
+    pending: Option<PendingBlocks<D>>,
 }
 
 impl<'map, const D: usize> VectorBlockCache<'map, D> {
@@ -403,9 +446,12 @@ impl<'map, const D: usize> VectorBlockCache<'map, D> {
         Self {
             comp_type: CompressionType::VarInt,
             length,
+            block_size: DEFAULT_BLOCK_SIZE,
             sync,
             data,
             cache: LruCache::new(NonZeroUsize::new(250).unwrap()),
+            stats: CacheStats::default(),
+            pending: None,
         }
     }
 
@@ -413,35 +459,130 @@ impl<'map, const D: usize> VectorBlockCache<'map, D> {
         Self {
             comp_type: CompressionType::Delta,
             length,
+            block_size: DEFAULT_BLOCK_SIZE,
             sync,
             data,
             cache: LruCache::new(NonZeroUsize::new(250).unwrap()),
+            stats: CacheStats::default(),
+            pending: None,
         }
     }
 
+    /// Sets the number of rows per block, which must match the encoded vector.
+    pub fn with_block_size(mut self, block_size: usize) -> Self {
+        self.block_size = block_size;
+        self
+    }
+
+    pub fn block_size(&self) -> usize {
+        self.block_size
+    }
+
     pub fn get_block(&mut self, block_index: usize) -> Option<&VectorBlock<D>> {
-        let Self {comp_type, length, sync, data, cache } = self;
-        if block_index < sync.len() {
-            if !cache.contains(&block_index) {
-                let offset = sync[block_index] as usize;
-                let blen = min(*length - (block_index * 16), 16);
-                let block = match comp_type {
-                    CompressionType::VarInt => VectorBlock::decode_compressed(&data[offset..], blen),
-                    CompressionType::Delta => VectorBlock::decode_delta(&data[offset..], blen),
-                };
-
-                cache.put(block_index, block);
+        if block_index < self.sync.len() {
+            if let Some(pending) = &self.pending {
+                if pending.worker.is_finished() || pending.blocks.contains(&block_index) {
+                    self.finish_prefetch();
+                }
+            }
+
+            if self.cache.contains(&block_index) {
+                self.stats.record_hit();
+            } else {
+                let (rows, data) = self.encoded_block(block_index);
+                let block = VectorBlock::decode(data, self.comp_type, self.block_size, rows);
+                self.insert(block_index, block, data.len());
             }
     
-            cache.get(&block_index)
+            self.cache.get(&block_index)
         } else {
             None
         }
     }
 
+    /// Number of rows and encoded data of block `block_index`
+    fn encoded_block(&self, block_index: usize) -> (usize, &'map [u8]) {
+        let offset = self.sync[block_index] as usize;
+        let next = self.sync.get(block_index + 1).map(|o| *o as usize);
+        let rows = min(self.length - (block_index * self.block_size), self.block_size);
+        (rows, &self.data[offset..offset + encoded_block_len(offset, next, self.data.len())])
+    }
+
+    /// Adds a block decoded from `bytes` of encoded data to the cache
+    fn insert(&mut self, block_index: usize, block: VectorBlock<D>, bytes: usize) {
+        self.stats.record_miss(bytes);
+        #[cfg(feature = "tracing")]
+        tracing::trace!(block = block_index, bytes, "decoded vector block");
+        self.cache.put(block_index, block);
+    }
+
+    /// Blocks in `blocks` that are not cached, of the first as many as fit into the cache
+    fn missing(&self, blocks: ops::Range<usize>) -> Vec<usize> {
+        blocks.take(self.cache.cap().get())
+            .take_while(|&bi| bi < self.sync.len())
+            .filter(|bi| !self.cache.contains(bi))
+            .collect()
+    }
+
     pub fn len(&self) -> usize {
         self.length
     }
+
+    /// Decodes the blocks in `blocks` on a background thread and returns immediately.
+    ///
+    /// The decoded blocks are added to the cache on the first access after the thread has
+    /// finished, an earlier access to one of them waits for it. The encoded blocks are copied
+    /// for the thread, which pages them in on the calling thread, and a previous prefetch is
+    /// finished first. At most as many blocks as fit into the cache are decoded, starting from
+    /// the first one, so that prefetching a large range does not evict the blocks needed next.
+    pub fn prefetch(&mut self, blocks: ops::Range<usize>) {
+        self.finish_prefetch();
+
+        let missing = self.missing(blocks);
+        let (Some(&first), Some(&last)) = (missing.first(), missing.last()) else {
+            return;
+        };
+
+        let encoded: Vec<_> = missing.into_iter()
+            .map(|bi| {
+                let (rows, data) = self.encoded_block(bi);
+                (bi, rows, data.to_vec())
+            })
+            .collect();
+        let (comp_type, block_size) = (self.comp_type, self.block_size);
+
+        let worker = thread::spawn(move || {
+            encoded.into_iter()
+                .map(|(bi, rows, data)| (bi, VectorBlock::decode(&data, comp_type, block_size, rows), data.len()))
+                .collect()
+        });
+        self.pending = Some(PendingBlocks { blocks: first..last + 1, worker });
+    }
+
+    /// Waits for the blocks of a running [`prefetch`](Self::prefetch) and adds them to the cache
+    pub fn finish_prefetch(&mut self) {
+        if let Some(pending) = self.pending.take() {
+            let decoded = pending.worker.join().unwrap_or_else(|e| std::panic::resume_unwind(e));
+            for (bi, block, bytes) in decoded {
+                // blocks decoded on demand in the meantime are already cached
+                if !self.cache.contains(&bi) {
+                    self.insert(bi, block, bytes);
+                }
+            }
+        }
+    }
+
+    /// Returns the encoded data of the blocks in `blocks`.
+    pub fn encoded_blocks(&self, blocks: ops::Range<usize>) -> &'map [u8] {
+        encoded_blocks(self.sync, self.data, blocks)
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            resident_bytes: self.cache.len() * (mem::size_of::<VectorBlock<D>>() + self.block_size * mem::size_of::<[i64; D]>()),
+            ..self.stats
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -464,15 +605,15 @@ impl<'map, const D: usize> CachedVector<'map, D> {
                     Some(Self::Uncompressed { length, data })
                 }
 
-                Vector::Compressed { length, width: _, sync, data } => {
+                Vector::Compressed { length, width: _, block_size, sync, data } => {
                     Some(Self::Compressed { 
-                        blocks: Rc::new(RefCell::new(VectorBlockCache::new_compressed(length, sync, data))),
+                        blocks: Rc::new(RefCell::new(VectorBlockCache::new_compressed(length, sync, data).with_block_size(block_size))),
                     })
                 }
 
-                Vector::Delta { length, width: _, sync, data } => {
+                Vector::Delta { length, width: _, block_size, sync, data } => {
                     Some(Self::Compressed { 
-                        blocks: Rc::new(RefCell::new(VectorBlockCache::new_delta(length, sync, data))),
+                        blocks: Rc::new(RefCell::new(VectorBlockCache::new_delta(length, sync, data).with_block_size(block_size))),
                     })
                 }
             }
@@ -515,10 +656,41 @@ impl<'map, const D: usize> CachedVector<'map, D> {
             },
             CachedVector::Compressed { blocks } => {
                 let mut blocks = blocks.borrow_mut();
-                let bi = index / 16;
-                let block = blocks.get_block(bi).unwrap();
+                let block_size = blocks.block_size;
+                let block = blocks.get_block(index / block_size).unwrap();
+
+                block.get_row_unchecked(index % block_size)
+            }
+        }
+    }
+
+    /// Returns the rows at all `indices` in the given order, `None` if any is out of bounds.
+    ///
+    /// The indices are visited in ascending order internally, so every block containing
+    /// some of them is decoded or looked up in the cache once, however the indices are
+    /// ordered.
+    pub fn get_rows(&self, indices: &[usize]) -> Option<Vec<[i64; D]>> {
+        let len = self.len();
+        if indices.iter().any(|i| *i >= len) {
+            return None;
+        }
 
-                block.get_row_unchecked(index % 16)
+        match self {
+            Self::Uncompressed { .. } => Some(indices.iter().map(|i| self.get_row_unchecked(*i)).collect()),
+            Self::Compressed { blocks } => {
+                let mut blocks = blocks.borrow_mut();
+                let block_size = blocks.block_size;
+                let mut order: Vec<usize> = (0..indices.len()).collect();
+                order.sort_unstable_by_key(|o| indices[*o]);
+
+                let mut rows = vec![[0; D]; indices.len()];
+                for group in order.chunk_by(|a, b| indices[*a] / block_size == indices[*b] / block_size) {
+                    let block = blocks.get_block(indices[group[0]] / block_size)?;
+                    for &o in group {
+                        rows[o] = block.get_row_unchecked(indices[o] % block_size);
+                    }
+                }
+                Some(rows)
             }
         }
     }
@@ -539,6 +711,14 @@ impl<'map, const D: usize> CachedVector<'map, D> {
         RowIterator::new(self, 0, end)
     }
 
+    pub fn block_iter(&self) -> BlockIterator<'map, D> {
+        BlockIterator::new(self, 0, self.len()).unwrap()
+    }
+
+    pub fn block_iter_range(&self, start: usize, end: usize) -> Option<BlockIterator<'map, D>> {
+        BlockIterator::new(self, start, end)
+    }
+
     pub fn len(&self) -> usize {
         match self {
             Self::Uncompressed { length, .. } => *length,
@@ -549,8 +729,91 @@ impl<'map, const D: usize> CachedVector<'map, D> {
     pub const fn width(&self) -> usize {
         D
     }
+
+    /// Starts decoding the blocks containing rows `start..end` on a background thread, see
+    /// [`VectorBlockCache::prefetch`]. Does nothing for uncompressed vectors.
+    pub fn prefetch(&self, start: usize, end: usize) {
+        if let Self::Compressed { blocks } = self {
+            let end = min(end, self.len());
+            if start < end {
+                let mut blocks = blocks.borrow_mut();
+                let block_size = blocks.block_size;
+                blocks.prefetch(start / block_size..(end - 1) / block_size + 1);
+            }
+        }
+    }
+
+    /// Returns the raw bytes backing rows `start..end`, i.e. the encoded blocks containing
+    /// them for compressed vectors.
+    pub fn raw_range(&self, start: usize, end: usize) -> &'map [u8] {
+        let end = min(end, self.len());
+        if start >= end {
+            return &[];
+        }
+
+        match self {
+            Self::Uncompressed { data, .. } => {
+                let rows = &data[start * D..end * D];
+                unsafe { slice::from_raw_parts(rows.as_ptr() as *const u8, mem::size_of_val(rows)) }
+            }
+            Self::Compressed { blocks } => {
+                let blocks = blocks.borrow();
+                blocks.encoded_blocks(start / blocks.block_size..(end - 1) / blocks.block_size + 1)
+            }
+        }
+    }
+
+    /// Returns the underlying vector without the block cache, e.g. to build an independent
+    /// `CachedVector` for use on another thread.
+    pub fn vector(&self) -> Vector<'map> {
+        match self {
+            Self::Uncompressed { length, data } => Vector::Uncompressed { length: *length, width: D, data },
+            Self::Compressed { blocks } => {
+                let blocks = blocks.borrow();
+                let (length, block_size, sync, data) = (blocks.length, blocks.block_size, blocks.sync, blocks.data);
+                match blocks.comp_type {
+                    CompressionType::VarInt => Vector::Compressed { length, width: D, block_size, sync, data },
+                    CompressionType::Delta => Vector::Delta { length, width: D, block_size, sync, data },
+                }
+            }
+        }
+    }
+
+    /// Returns the statistics of the block cache shared by all clones of this vector.
+    pub fn cache_stats(&self) -> CacheStats {
+        match self {
+            Self::Uncompressed { .. } => CacheStats::default(),
+            Self::Compressed { blocks } => {
+                // a running prefetch counts as decoded
+                let mut blocks = blocks.borrow_mut();
+                blocks.finish_prefetch();
+                blocks.stats()
+            }
+        }
+    }
 }
 
+/// Returns row `position` of a compressed vector, decoding its block through the cache
+/// only if `slot` doesn't already hold that block.
+fn block_row<const D: usize>(blocks: &RefCell<VectorBlockCache<'_, D>>, slot: &mut Option<(usize, VectorBlock<D>)>, position: usize) -> [i64; D] {
+    let block_size = blocks.borrow().block_size;
+    let bi = position / block_size;
+    let block = match slot {
+        Some((i, block)) if *i == bi => block,
+        _ => {
+            let block = blocks.borrow_mut().get_block(bi).unwrap().clone();
+            &slot.insert((bi, block)).1
+        }
+    };
+
+    block.get_row(position % block_size).unwrap()
+}
+
+/// Iterator over the rows `start..end` of a CachedVector.
+///
+/// Compressed blocks are only decoded once a row in them is actually yielded, so
+/// `nth()` and the adapters built on it (`skip()`, `step_by()`) jump over whole blocks.
+/// Iterating from the back keeps its own current block.
 pub enum RowIterator<'map, const D: usize> {
     Uncompressed {
         data: &'map [i64],
@@ -560,7 +823,8 @@ pub enum RowIterator<'map, const D: usize> {
 
     Compressed {
         blocks: Rc<RefCell<VectorBlockCache<'map, D>>>,
-        current: VectorBlock<D>,
+        front: Option<(usize, VectorBlock<D>)>,
+        back: Option<(usize, VectorBlock<D>)>,
         position: usize,
         end: usize,
     },
@@ -568,27 +832,27 @@ pub enum RowIterator<'map, const D: usize> {
 
 impl<'map, const D: usize> RowIterator<'map, D> {
     pub fn new(cvec: &CachedVector<'map, D>, start: usize, end: usize) -> Option<Self> {
+        if end > cvec.len() {
+            return None;
+        }
+
         match cvec {
-            CachedVector::Uncompressed { length, data } => {
-                if end <= *length {
-                    Some(Self::Uncompressed { data, position: start, end })
-                } else {
-                    None
-                }
+            CachedVector::Uncompressed { data, .. } => {
+                Some(Self::Uncompressed { data, position: start, end })
             }
 
             CachedVector::Compressed { blocks } => {
-                if end <= blocks.borrow().len() {
-                    let bi = start / 16;
-                    let current = *blocks.borrow_mut().get_block(bi).unwrap();
-
-                    Some(Self::Compressed { blocks: blocks.clone(), current, position: start, end })
-                } else {
-                    None
-                }
+                Some(Self::Compressed { blocks: blocks.clone(), front: None, back: None, position: start, end })
             }
         }
     }
+
+    fn bounds(&mut self) -> (&mut usize, &mut usize) {
+        match self {
+            Self::Uncompressed { position, end, .. } |
+            Self::Compressed { position, end, .. } => (position, end),
+        }
+    }
 }
 
 impl<'map, const D: usize> Iterator for RowIterator<'map, D> {
@@ -607,29 +871,156 @@ impl<'map, const D: usize> Iterator for RowIterator<'map, D> {
                 }
             }
 
-            Self::Compressed { blocks, current, position, end } => {
+            Self::Compressed { blocks, front, position, end, .. } => {
                 if position < end {
-                    let i = *position % 16;
-
-                    // i == 0 -> we need a new block
-                    // only go through cache when the next block is needed
-                    if i == 0 {
-                        let mut blocks = blocks.borrow_mut();
-                        let bi = *position / 16;
-                        *current = *blocks.get_block(bi).unwrap();
-                    }
-
+                    let row = block_row(blocks, front, *position);
                     *position += 1;
+                    Some(row)
+                } else {
+                    None
+                }
+            }
+        }
+    }
+
+    fn nth(&mut self, n: usize) -> Option<Self::Item> {
+        let (position, end) = self.bounds();
+        *position = min(position.saturating_add(n), *end);
+        self.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = match self {
+            Self::Uncompressed { position, end, .. } |
+            Self::Compressed { position, end, .. } => end.saturating_sub(*position),
+        };
+        (len, Some(len))
+    }
+}
+
+impl<'map, const D: usize> DoubleEndedIterator for RowIterator<'map, D> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        match self {
+            Self::Uncompressed { data, position, end } => {
+                if position < end {
+                    *end -= 1;
+                    let start = *end * D;
+                    Some(data[start..start + D].try_into().unwrap())
+                } else {
+                    None
+                }
+            }
 
-                    current.get_row(i)
+            Self::Compressed { blocks, back, position, end, .. } => {
+                if position < end {
+                    *end -= 1;
+                    Some(block_row(blocks, back, *end))
                 } else {
                     None
                 }
             }
         }
     }
+
+    fn nth_back(&mut self, n: usize) -> Option<Self::Item> {
+        let (position, end) = self.bounds();
+        *end = end.saturating_sub(n).max(*position);
+        self.next_back()
+    }
 }
 
+impl<'map, const D: usize> ExactSizeIterator for RowIterator<'map, D> {}
+
+/// Iterator over the rows `start..end` of a CachedVector in chunks, each with the index
+/// of its first row, for scans that work on whole slices of rows.
+///
+/// Compressed vectors yield one chunk per encoded block, clipped to the range. The blocks
+/// are decoded without the block cache, so a long scan doesn't evict the blocks used for
+/// random access. Uncompressed vectors yield the whole range as a single borrowed chunk.
+pub enum BlockIterator<'map, const D: usize> {
+    Uncompressed {
+        rows: Option<(usize, &'map [[i64; D]])>,
+    },
+
+    Compressed {
+        comp_type: CompressionType,
+        block_size: usize,
+        length: usize,
+        sync: &'map [i64],
+        data: &'map [u8],
+        position: usize,
+        end: usize,
+    },
+}
+
+impl<'map, const D: usize> BlockIterator<'map, D> {
+    pub fn new(cvec: &CachedVector<'map, D>, start: usize, end: usize) -> Option<Self> {
+        if end > cvec.len() {
+            return None;
+        }
+
+        match cvec {
+            CachedVector::Uncompressed { data, .. } => {
+                let (rows, _) = data.as_chunks::<D>();
+                Some(Self::Uncompressed { rows: (start < end).then(|| (start, &rows[start..end])) })
+            }
+
+            CachedVector::Compressed { blocks } => {
+                let blocks = blocks.borrow();
+                Some(Self::Compressed {
+                    comp_type: blocks.comp_type,
+                    block_size: blocks.block_size,
+                    length: blocks.length,
+                    sync: blocks.sync,
+                    data: blocks.data,
+                    position: start,
+                    end,
+                })
+            }
+        }
+    }
+}
+
+impl<'map, const D: usize> Iterator for BlockIterator<'map, D> {
+    type Item = (usize, Cow<'map, [[i64; D]]>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            Self::Uncompressed { rows } => rows.take().map(|(start, rows)| (start, Cow::Borrowed(rows))),
+
+            Self::Compressed { comp_type, block_size, length, sync, data, position, end } => {
+                if position >= end {
+                    return None;
+                }
+
+                let bi = *position / *block_size;
+                let block_start = bi * *block_size;
+                let blen = min(*length - block_start, *block_size);
+                let mut rows = VectorBlock::<D>::decode(&data[sync[bi] as usize..], *comp_type, *block_size, blen).rows;
+                rows.truncate(min(*end - block_start, blen));
+                rows.drain(..*position - block_start);
+
+                let start = *position;
+                *position = block_start + blen;
+                Some((start, Cow::Owned(rows)))
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = match self {
+            Self::Uncompressed { rows } => rows.is_some() as usize,
+            Self::Compressed { block_size, position, end, .. } if position < end => (*end - 1) / *block_size - *position / *block_size + 1,
+            Self::Compressed { .. } => 0,
+        };
+        (len, Some(len))
+    }
+}
+
+impl<'map, const D: usize> ExactSizeIterator for BlockIterator<'map, D> {}
+
+/// Iterator over a single column of the rows `start..end` of a CachedVector,
+/// with the same block skipping behaviour as [`RowIterator`].
 pub enum ColumnIterator<'map, const D: usize> {
     Uncompressed {
         data: &'map [i64],
@@ -640,7 +1031,8 @@ pub enum ColumnIterator<'map, const D: usize> {
 
     Compressed {
         blocks: Rc<RefCell<VectorBlockCache<'map, D>>>,
-        current: VectorBlock<D>,
+        front: Option<(usize, VectorBlock<D>)>,
+        back: Option<(usize, VectorBlock<D>)>,
         position: usize,
         end: usize,
         column: usize,
@@ -649,31 +1041,27 @@ pub enum ColumnIterator<'map, const D: usize> {
 
 impl<'map, const D: usize> ColumnIterator<'map, D> {
     pub fn new(cvec: &CachedVector<'map, D>, start: usize, end: usize, column: usize) -> Option<Self> {
-        if column >= D{
+        if column >= D || end > cvec.len() {
             return None;
         }
-        
+
         match cvec {
-            CachedVector::Uncompressed { length, data } => {
-                if end <= *length {
-                    Some(Self::Uncompressed { data, position: start, end, column })
-                } else {
-                    None
-                }
+            CachedVector::Uncompressed { data, .. } => {
+                Some(Self::Uncompressed { data, position: start, end, column })
             }
 
             CachedVector::Compressed { blocks } => {
-                if end <= blocks.borrow().len() {
-                    let bi = start / 16;
-                    let current = *blocks.borrow_mut().get_block(bi).unwrap();
-
-                    Some(Self::Compressed { blocks: blocks.clone(), current, position: start, end, column })
-                } else {
-                    None
-                }
+                Some(Self::Compressed { blocks: blocks.clone(), front: None, back: None, position: start, end, column })
             }
         }
     }
+
+    fn bounds(&mut self) -> (&mut usize, &mut usize) {
+        match self {
+            Self::Uncompressed { position, end, .. } |
+            Self::Compressed { position, end, .. } => (position, end),
+        }
+    }
 }
 
 impl<'map, const D: usize> Iterator for ColumnIterator<'map, D> {
@@ -681,7 +1069,7 @@ impl<'map, const D: usize> Iterator for ColumnIterator<'map, D> {
 
     fn next(&mut self) -> Option<Self::Item> {
         match self {
-            Self::Uncompressed { data, position, end, column} => {
+            Self::Uncompressed { data, position, end, column } => {
                 if position < end {
                     let i = (*position * D) + *column;
                     *position += 1;
@@ -691,25 +1079,61 @@ impl<'map, const D: usize> Iterator for ColumnIterator<'map, D> {
                 }
             }
 
-            Self::Compressed { blocks, current, position, end, column } => {
+            Self::Compressed { blocks, front, position, end, column, .. } => {
                 if position < end {
-                    let i = *position % 16;
-
-                    // i == 0 -> we need a new block
-                    // only go through cache when the next block is needed
-                    if i == 0 {
-                        let mut blocks = blocks.borrow_mut();
-                        let bi = *position / 16;
-                        *current = *blocks.get_block(bi).unwrap();
-                    }
-
+                    let row = block_row(blocks, front, *position);
                     *position += 1;
+                    Some(row[*column])
+                } else {
+                    None
+                }
+            }
+        }
+    }
+
+    fn nth(&mut self, n: usize) -> Option<Self::Item> {
+        let (position, end) = self.bounds();
+        *position = min(position.saturating_add(n), *end);
+        self.next()
+    }
 
-                    current.get_row(i).map(|r| r[*column])
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = match self {
+            Self::Uncompressed { position, end, .. } |
+            Self::Compressed { position, end, .. } => end.saturating_sub(*position),
+        };
+        (len, Some(len))
+    }
+}
+
+impl<'map, const D: usize> DoubleEndedIterator for ColumnIterator<'map, D> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        match self {
+            Self::Uncompressed { data, position, end, column } => {
+                if position < end {
+                    *end -= 1;
+                    Some(data[(*end * D) + *column])
+                } else {
+                    None
+                }
+            }
+
+            Self::Compressed { blocks, back, position, end, column, .. } => {
+                if position < end {
+                    *end -= 1;
+                    Some(block_row(blocks, back, *end)[*column])
                 } else {
                     None
                 }
             }
         }
     }
+
+    fn nth_back(&mut self, n: usize) -> Option<Self::Item> {
+        let (position, end) = self.bounds();
+        *end = end.saturating_sub(n).max(*position);
+        self.next_back()
+    }
 }
+
+impl<'map, const D: usize> ExactSizeIterator for ColumnIterator<'map, D> {}
@@ -0,0 +1,182 @@
+//! Wavelet tree over an ID stream, for random access and counting the occurrences of a
+//! type in any range of positions.
+//!
+//! The tree is laid out as a wavelet matrix: level `l` holds bit `levels - 1 - l` of every
+//! ID as a [`RankBitmap`], in the order the IDs have after stably partitioning them by
+//! all higher bits, IDs with an unset bit before those with a set bit. Reading an ID or
+//! counting a type in a range takes one rank per level, i.e. `O(log σ)` for `σ` types
+//! independent of the length of the range, and finding the `k`th occurrence of a type
+//! one select per level.
+//!
+//! All levels are stored in a single uncompressed [`Vector`] of [`RankBitmap`] rows, each
+//! level taking `⌈n / 512⌉` rows for a stream of `n` IDs.
+
+use std::io::{Seek, Write};
+
+use crate::container::{BomEntry, EncodeError};
+
+use super::{RankBitmap, Vector};
+
+#[derive(Debug, Clone)]
+pub struct WaveletTree<'map> {
+    len: usize,
+    levels: Vec<RankBitmap<'map>>,
+    /// Number of unset bits of each level
+    zeros: Vec<usize>,
+}
+
+impl<'map> WaveletTree<'map> {
+    /// Wraps an uncompressed vector of [`RankBitmap`] rows holding a stream of `len` IDs
+    pub fn from_vector(vector: Vector<'map>, len: usize) -> Option<Self> {
+        let Vector::Uncompressed { length, width: RankBitmap::WIDTH, data } = vector else {
+            return None;
+        };
+        let rows = len.div_ceil(RankBitmap::ROW_BITS);
+        if rows == 0 {
+            return (length == 0).then(|| Self { len, levels: Vec::new(), zeros: Vec::new() });
+        }
+        if length % rows != 0 || data.len() != length * RankBitmap::WIDTH {
+            return None;
+        }
+
+        let levels = data.chunks_exact(rows * RankBitmap::WIDTH)
+            .map(|level| RankBitmap::from_vector(Vector::Uncompressed { length: rows, width: RankBitmap::WIDTH, data: level }))
+            .collect::<Option<Vec<_>>>()?;
+        let zeros = levels.iter()
+            .map(|level| len.checked_sub(level.count_ones()))
+            .collect::<Option<Vec<_>>>()?;
+
+        Some(Self { len, levels, zeros })
+    }
+
+    /// Number of levels needed for the IDs `0..n_types`
+    pub fn levels_for(n_types: usize) -> usize {
+        ((usize::BITS - n_types.saturating_sub(1).leading_zeros()) as usize).max(1)
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Number of levels, i.e. of bits per ID
+    pub fn levels(&self) -> usize {
+        self.levels.len()
+    }
+
+    /// Bit of `id` stored in `level`
+    fn bit(&self, id: usize, level: usize) -> bool {
+        (id >> (self.levels.len() - 1 - level)) & 1 == 1
+    }
+
+    /// Position `position` of `level` mapped to the next level, given its bit
+    fn descend(&self, level: usize, position: usize, bit: bool) -> usize {
+        let ones = self.levels[level].rank(position);
+        if bit {
+            self.zeros[level] + ones
+        } else {
+            position - ones
+        }
+    }
+
+    /// Returns the ID at `position`
+    pub fn get(&self, position: usize) -> Option<usize> {
+        if position >= self.len {
+            return None;
+        }
+
+        let mut position = position;
+        let mut id = 0;
+        for (level, bitmap) in self.levels.iter().enumerate() {
+            let bit = bitmap.get(position);
+            id = (id << 1) | bit as usize;
+            position = self.descend(level, position, bit);
+        }
+        Some(id)
+    }
+
+    /// Range of the occurrences of `id` among the positions `start..end` in the last level
+    fn bottom_range(&self, id: usize, start: usize, end: usize) -> (usize, usize) {
+        if self.levels.len() < usize::BITS as usize && id >> self.levels.len() != 0 {
+            return (0, 0);
+        }
+
+        let (mut start, mut end) = (start, end);
+        for level in 0..self.levels.len() {
+            let bit = self.bit(id, level);
+            start = self.descend(level, start, bit);
+            end = self.descend(level, end, bit);
+        }
+        (start, end)
+    }
+
+    /// Number of occurrences of `id` in the positions `start..end`
+    pub fn count_in_range(&self, id: usize, start: usize, end: usize) -> usize {
+        let end = end.min(self.len);
+        if start >= end {
+            return 0;
+        }
+        let (start, end) = self.bottom_range(id, start, end);
+        end - start
+    }
+
+    /// Number of occurrences of `id` before `position`
+    pub fn rank(&self, id: usize, position: usize) -> usize {
+        self.count_in_range(id, 0, position)
+    }
+
+    /// Position of the `k`th occurrence of `id`, counting from 0
+    pub fn select(&self, id: usize, k: usize) -> Option<usize> {
+        let (start, end) = self.bottom_range(id, 0, self.len);
+        if k >= end - start {
+            return None;
+        }
+
+        let mut position = start + k;
+        for level in (0..self.levels.len()).rev() {
+            position = if self.bit(id, level) {
+                self.levels[level].select(position - self.zeros[level])?
+            } else {
+                self.levels[level].select_zero(position)?
+            };
+        }
+        Some(position)
+    }
+
+    /// Builds the rows of a wavelet tree over `ids`, which must all be below `n_types`
+    pub fn build_rows<I>(ids: I, n_types: usize) -> Vec<i64> where I: IntoIterator<Item = usize> {
+        let mut ids: Vec<usize> = ids.into_iter().collect();
+        let levels = Self::levels_for(n_types);
+        let rows = ids.len().div_ceil(RankBitmap::ROW_BITS);
+        let mut data = Vec::with_capacity(levels * rows * RankBitmap::WIDTH);
+
+        for level in 0..levels {
+            let bit = levels - 1 - level;
+            let is_set = |id: usize| (id >> bit) & 1 == 1;
+
+            let mut bitmap = RankBitmap::build_rows(ids.iter().enumerate().filter(|(_, id)| is_set(**id)).map(|(i, _)| i));
+            let ones = ids.iter().filter(|id| is_set(**id)).count();
+            while bitmap.len() < rows * RankBitmap::WIDTH {
+                bitmap.push(ones as i64);
+                bitmap.extend([0; RankBitmap::WIDTH - 1]);
+            }
+            data.extend(bitmap);
+
+            let (mut unset, set): (Vec<usize>, Vec<usize>) = ids.iter().partition(|id| !is_set(**id));
+            unset.extend(set);
+            ids = unset;
+        }
+
+        data
+    }
+
+    /// Encodes a wavelet tree over `ids`, which must all be below `n_types`
+    pub unsafe fn encode_to_container_file<I, W: Write + Seek>(ids: I, n_types: usize, file: &mut W, bom_entry: &mut BomEntry, start_offset: u64) -> Result<(), EncodeError> where I: IntoIterator<Item = usize> {
+        let rows = Self::build_rows(ids, n_types);
+        let n = rows.len() / RankBitmap::WIDTH;
+        Vector::encode_uncompressed_to_container_file(rows.into_iter(), n, RankBitmap::WIDTH, file, bom_entry, start_offset)
+    }
+}
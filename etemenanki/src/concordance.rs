@@ -0,0 +1,209 @@
+//! Keyword-in-context (KWIC) formatting of query matches.
+//!
+//! A [`Concordance`] turns match ranges on a layer into [`KwicLine`]s with a fixed amount
+//! of context on either side, and formats them as plain text, TSV or JSON records.
+//! Optionally each line carries the values of some variables of the segment containing
+//! the match, e.g. the title and year of a text, and the context can be snapped to the
+//! boundaries of the segments containing the match, e.g. to show whole sentences.
+
+use std::io::{self, Write};
+
+use serde::Serialize;
+
+use crate::layers::{LayerData, SegmentationLayer};
+use crate::variables::{Variable, VariableValue};
+
+/// Output format of concordance lines
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Format {
+    /// `left [keyword] right`, prefixed by the match position and metadata
+    #[default]
+    Plain,
+    /// Tab separated columns: start, end, metadata..., left, keyword, right
+    Tsv,
+    /// One JSON object per line
+    Json,
+}
+
+/// A single concordance line with its context split into tokens
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct KwicLine<'map> {
+    pub start: usize,
+    pub end: usize,
+    pub left: Vec<&'map str>,
+    pub keyword: Vec<&'map str>,
+    pub right: Vec<&'map str>,
+    /// Index of the metadata segment containing the start of the match
+    pub segment: Option<usize>,
+    pub metadata: Vec<(String, Option<VariableValue<'map>>)>,
+}
+
+/// Builder for concordance lines over a string variable.
+pub struct Concordance<'a, 'map> {
+    words: &'a Variable<'map>,
+    context: usize,
+    highlight: Option<(String, String)>,
+    segmentation: Option<&'a LayerData<'map, SegmentationLayer<'map>>>,
+    metadata: Vec<String>,
+    snap: Option<&'a SegmentationLayer<'map>>,
+    format: Format,
+}
+
+impl<'a, 'map> Concordance<'a, 'map> {
+    /// Creates a concordance over the indexed or plain string variable `words` with
+    /// `context` tokens on each side of a match.
+    pub fn new(words: &'a Variable<'map>, context: usize) -> Self {
+        Self {
+            words,
+            context,
+            highlight: None,
+            segmentation: None,
+            metadata: Vec::new(),
+            snap: None,
+            format: Format::default(),
+        }
+    }
+
+    pub fn format(mut self, format: Format) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Surrounds the keyword with `open` and `close` in plain text output
+    pub fn highlight<S: Into<String>>(mut self, open: S, close: S) -> Self {
+        self.highlight = Some((open.into(), close.into()));
+        self
+    }
+
+    /// Adds the values of the variables `names` of the segment containing a match to each line
+    pub fn metadata<S: AsRef<str>>(mut self, segmentation: &'a LayerData<'map, SegmentationLayer<'map>>, names: &[S]) -> Self {
+        self.segmentation = Some(segmentation);
+        self.metadata = names.iter().map(|n| n.as_ref().to_owned()).collect();
+        self
+    }
+
+    /// Limits the context to the segments of `segmentation` containing the start and the
+    /// end of a match, e.g. the sentence of a match, while still taking at most `context`
+    /// tokens on either side. Matches outside of all segments keep the full context.
+    pub fn snap(mut self, segmentation: &'a SegmentationLayer<'map>) -> Self {
+        self.snap = Some(segmentation);
+        self
+    }
+
+    /// Start of the left and end of the right context of the match `start..end`
+    fn context_range(&self, start: usize, end: usize) -> (usize, usize) {
+        let len = self.words.len();
+        let mut from = start.saturating_sub(self.context);
+        let mut to = (end + self.context).min(len);
+
+        if let Some(segmentation) = self.snap {
+            if let Some((segment_start, _)) = segmentation.find_containing(start).and_then(|i| segmentation.get(i)) {
+                from = from.max(segment_start);
+            }
+            // an empty match snaps to the segment it starts in
+            let last = end.saturating_sub(1).max(start);
+            if let Some((_, segment_end)) = segmentation.find_containing(last).and_then(|i| segmentation.get(i)) {
+                to = to.min(segment_end.max(end));
+            }
+        }
+
+        (from, to)
+    }
+
+    /// Builds the concordance line for the match `start..end`.
+    pub fn line(&self, start: usize, end: usize) -> KwicLine<'map> {
+        let len = self.words.len();
+        let end = end.min(len);
+        let start = start.min(end);
+        let (from, to) = self.context_range(start, end);
+
+        let tokens = |from: usize, to: usize| -> Vec<&'map str> {
+            (from..to).filter_map(|i| self.words.get_string(i)).collect()
+        };
+
+        let segment = self.segmentation.and_then(|s| s.find_containing(start));
+        let metadata = match self.segmentation {
+            Some(layer) => self.metadata.iter()
+                .map(|name| {
+                    let value = segment
+                        .zip(layer.variable_by_name(name))
+                        .and_then(|(i, var)| var.get(i));
+                    (name.clone(), value)
+                })
+                .collect(),
+            None => Vec::new(),
+        };
+
+        KwicLine {
+            start,
+            end,
+            left: tokens(from, start),
+            keyword: tokens(start, end),
+            right: tokens(end, to),
+            segment,
+            metadata,
+        }
+    }
+
+    /// Formats the line for the match `start..end` in the configured format, without a trailing newline.
+    pub fn format_line(&self, start: usize, end: usize) -> String {
+        let line = self.line(start, end);
+
+        match self.format {
+            Format::Plain => {
+                let (open, close) = self.highlight.as_ref()
+                    .map(|(o, c)| (o.as_str(), c.as_str()))
+                    .unwrap_or(("", ""));
+
+                let mut out = format!("{}: ", line.start);
+                for (name, value) in &line.metadata {
+                    out.push_str(&format!("<{}={}> ", name, display_value(value)));
+                }
+
+                let keyword = format!("{}{}{}", open, line.keyword.join(" "), close);
+                let parts = [line.left.join(" "), keyword, line.right.join(" ")];
+                out.push_str(&parts.iter().filter(|p| !p.is_empty()).cloned().collect::<Vec<_>>().join(" "));
+                out
+            }
+
+            Format::Tsv => {
+                let mut columns = vec![line.start.to_string(), line.end.to_string()];
+                columns.extend(line.metadata.iter().map(|(_, v)| display_value(v)));
+                columns.push(line.left.join(" "));
+                columns.push(line.keyword.join(" "));
+                columns.push(line.right.join(" "));
+
+                columns.iter()
+                    .map(|c| c.replace(['\t', '\n', '\r'], " "))
+                    .collect::<Vec<_>>()
+                    .join("\t")
+            }
+
+            Format::Json => serde_json::to_string(&line).expect("concordance lines are always serializable"),
+        }
+    }
+
+    /// Builds the concordance lines of all `matches`
+    pub fn lines<I>(&self, matches: I) -> Vec<KwicLine<'map>>
+    where
+        I: IntoIterator<Item = (usize, usize)>,
+    {
+        matches.into_iter().map(|(start, end)| self.line(start, end)).collect()
+    }
+
+    /// Formats all `matches` and writes them to `writer`, one line each.
+    pub fn write<W, I>(&self, writer: &mut W, matches: I) -> io::Result<()>
+    where
+        W: Write,
+        I: IntoIterator<Item = (usize, usize)>,
+    {
+        for (start, end) in matches {
+            writeln!(writer, "{}", self.format_line(start, end))?;
+        }
+        Ok(())
+    }
+}
+
+fn display_value(value: &Option<VariableValue>) -> String {
+    value.as_ref().map(|v| v.to_string()).unwrap_or_default()
+}
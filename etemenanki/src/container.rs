@@ -1,29 +1,134 @@
 use std::{
-    error, fmt, fs::File, io::{Seek, SeekFrom}, mem, num::TryFromIntError, ops::Range, str::{self, Utf8Error}
+    error, fmt, fs::{self, File}, io::{self, BufWriter, Seek, SeekFrom, Write}, mem, num::TryFromIntError, ops::Range, path::Path, str::{self, Utf8Error}
 };
 
-use memmap2::{Mmap, MmapMut, MmapOptions};
+#[cfg(feature = "mmap")]
+use memmap2::{Mmap, MmapOptions};
 use num_enum::{IntoPrimitive, TryFromPrimitive, TryFromPrimitiveError};
 use uuid::Uuid;
 
 use crate::components::{self, Component, ComponentError};
+use crate::layers::RangeError;
+use crate::lock::PendingFile;
+use crate::storage::{self, Memory, Storage};
 
 #[repr(u64)]
 #[derive(Debug, Clone, Copy, IntoPrimitive, TryFromPrimitive, PartialEq)]
 pub enum Type {
     GraphLayer = 0x5a4c67,              // "ZLg"
+    SpanLayer = 0x5a4c6f,               // "ZLo", possibly overlapping spans
     PrimaryLayer = 0x5a4c70,            // "ZLp"
     SegmentationLayer = 0x5a4c73,       // "ZLs"
     TreeLayer = 0x5a4c74,               // "ZLt"
     PlainStringVariable = 0x5a5663,     // "ZVc"
+    SourceVariable = 0x5a5664,          // "ZVd", original documents of segments
+    OffsetVariable = 0x5a5666,          // "ZVf", character offsets of positions
     HashVariable = 0x5a5668,            // "ZVh"
     IntegerVariable = 0x5a5669,         // "ZVi"
+    SparseVariable = 0x5a566f,          // "ZVo", values at some positions only
     PointerVariable = 0x5a5670,         // "ZVp"
     ExternalPointerVariable = 0x5a5671, // "ZVq"
     SetVariable = 0x5a5673,             // "ZVs"
     IndexedStringVariable = 0x5a5678,   // "ZVx"
 }
 
+/// Container format version as stored in the header, e.g. "1.0".
+///
+/// All containers with the same major version share one layout and are accepted by
+/// [`is_supported`](Self::is_supported). Minor versions may add [`Features`], which are
+/// flagged in the header, so a newer minor version is read like the current one unless it
+/// requires a feature this build does not know.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Version {
+    pub major: u8,
+    pub minor: u8,
+}
+
+impl Version {
+    /// The version written by this build
+    pub const CURRENT: Version = Version { major: 1, minor: 0 };
+
+    pub fn from_bytes(bytes: &[u8; 3]) -> Result<Self, Error> {
+        match bytes {
+            [major @ b'0'..=b'9', b'.', minor @ b'0'..=b'9'] => Ok(Self {
+                major: major - b'0',
+                minor: minor - b'0',
+            }),
+            _ => Err(Error::FormatError("Invalid container version")),
+        }
+    }
+
+    pub fn to_bytes(&self) -> [u8; 3] {
+        assert!(self.major < 10 && self.minor < 10, "version not representable in header");
+        [b'0' + self.major, b'.', b'0' + self.minor]
+    }
+
+    /// Returns true if a container of this version can be read by this build.
+    pub fn is_supported(&self) -> bool {
+        self.major == Self::CURRENT.major
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
+/// Feature flags of a container, stored in the `extensions` field of its header.
+///
+/// The low 32 bits flag optional features, which readers that don't know them may ignore.
+/// The high 32 bits flag required features, a container with an unknown required feature
+/// is rejected when it is opened. No features are defined yet.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Features(u64);
+
+impl Features {
+    /// Features known to this build
+    pub const KNOWN: Features = Features(0);
+
+    const REQUIRED: u64 = 0xffff_ffff_0000_0000;
+
+    pub fn from_bits(bits: u64) -> Self {
+        Self(bits)
+    }
+
+    pub fn bits(&self) -> u64 {
+        self.0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    /// Returns the required features that are not known to this build.
+    pub fn unsupported(&self) -> Features {
+        Features(self.0 & Self::REQUIRED & !Self::KNOWN.0)
+    }
+}
+
+impl fmt::Display for Features {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#018x}", self.0)
+    }
+}
+
+/// Maximum length of the comment stored in the header in bytes
+pub const HEADER_COMMENT_LEN: usize = 72;
+
+/// Truncates `s` to at most `len` bytes without splitting a character.
+fn truncate_str(s: &str, len: usize) -> &str {
+    if s.len() <= len {
+        return s;
+    }
+
+    let mut end = len;
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
+}
+
 #[repr(C, packed)]
 #[derive(Debug, Clone, Copy)]
 pub struct Header {
@@ -40,7 +145,7 @@ pub struct Header {
     dim1: i64,
     dim2: i64,
     extensions: i64,
-    comment: [u8; 72],
+    comment: [u8; HEADER_COMMENT_LEN],
 }
 
 impl Header {
@@ -61,15 +166,33 @@ impl Header {
         self.dim2 as usize
     }
 
+    /// Returns the container type. Panics if the type is unknown, see `try_container_type`.
     pub fn container_type(&self) -> Type {
-        (((self.family as u64) << 16) | ((self.class as u64) << 8) | self.ctype as u64)
-            .try_into().unwrap()
+        self.try_container_type().unwrap()
+    }
+
+    pub fn try_container_type(&self) -> Result<Type, Error> {
+        let raw = ((self.family as u64) << 16) | ((self.class as u64) << 8) | self.ctype as u64;
+        Ok(raw.try_into()?)
+    }
+
+    /// Returns the raw family, class and type characters, e.g. `*b"ZLp"`.
+    pub fn type_code(&self) -> [u8; 3] {
+        [self.family, self.class, self.ctype]
     }
 
     pub fn uuid(&self) -> Uuid {
         Uuid::from_bytes(self.uuid)
     }
 
+    pub fn version(&self) -> Option<Version> {
+        Version::from_bytes(&self.version).ok()
+    }
+
+    pub fn features(&self) -> Features {
+        Features::from_bits(self.extensions as u64)
+    }
+
     pub fn base1(&self) -> Option<Uuid> {
         let uuid = Uuid::from_bytes(self.base1_uuid);
         (!uuid.is_nil()).then_some(uuid)
@@ -80,8 +203,10 @@ impl Header {
         (!uuid.is_nil()).then_some(uuid)
     }
 
+    /// Returns the header comment without its zero padding.
     pub fn comment(&self) -> Option<&str> {
-        std::str::from_utf8(&self.comment).ok()
+        let len = self.comment.iter().rposition(|&b| b != 0).map_or(0, |i| i + 1);
+        std::str::from_utf8(&self.comment[..len]).ok()
     }
 }
 
@@ -108,18 +233,29 @@ impl BomEntry {
 #[derive(Debug)]
 pub struct Container<'map> {
     name: String,
-    mmap: Mmap,
+    storage: Box<dyn Storage>,
     header: &'map Header,
     bom: &'map [BomEntry]
 }
 
 impl<'map> Container<'map> {
+    #[cfg(feature = "mmap")]
     pub fn from_mmap(mmap: Mmap, name: String) -> Result<Self, Error> {
-        let Range { start, end } = mmap.as_ref().as_ptr_range();
+        Self::from_storage(Box::new(mmap), name)
+    }
+
+    /// Opens a container from a copy of `bytes`, e.g. one encoded by [`encode_in_memory`].
+    pub fn from_bytes(bytes: &[u8], name: String) -> Result<Self, Error> {
+        Self::from_storage(Box::new(Memory::new(bytes)), name)
+    }
+
+    pub fn from_storage(storage: Box<dyn Storage>, name: String) -> Result<Self, Error> {
+        let bytes = storage.bytes();
+        let start = bytes.as_ptr();
 
         // map header
         let header = unsafe {
-            if start.offset(mem::size_of::<Header>().try_into()?) <= end {
+            if mem::size_of::<Header>() <= bytes.len() {
                 (start as *const Header)
                     .as_ref()
                     .ok_or(Error::Memory("null pointer"))
@@ -134,50 +270,61 @@ impl<'map> Container<'map> {
             return Err(Error::FormatError("Invalid magic string"));
         }
 
-        // check version
-        let version = str::from_utf8(&header.version)?;
-        if !(version == "1.0") {
-            return Err(Error::FormatError("Invalid container version"));
+        // check version, any minor version of a known major version is accepted
+        let version = Version::from_bytes(&header.version)?;
+        if !version.is_supported() {
+            return Err(Error::UnsupportedVersion(version));
+        }
+
+        // unknown optional features are ignored, unknown required ones change the layout
+        let unsupported = header.features().unsupported();
+        if !unsupported.is_empty() {
+            return Err(Error::UnsupportedFeatures(unsupported));
         }
 
         // map BOM and check if its in bounds
         let bom = unsafe {
-            let bom_ptr = start.offset(160);
             let n = header.allocated as usize;
 
-            if bom_ptr.offset((mem::size_of::<BomEntry>() * n).try_into()?) <= end {
-                let first_bom = bom_ptr as *const BomEntry;
+            if 160 + mem::size_of::<BomEntry>() * n <= bytes.len() {
+                let first_bom = start.offset(160) as *const BomEntry;
                 Ok(std::slice::from_raw_parts(first_bom, n))
             } else {
                 Err(Error::Memory("BOM out of bounds"))
             }
         }?;
 
-        // check if all components are in bounds
+        // check if all components are in bounds, only those can be accessed with get_component
         for be in bom {
             if be.family != 0x01 {
                 continue;
             }
 
-            unsafe {
-                if start.offset(be.offset as isize) > end
-                    && start.offset((be.offset + be.size) as isize) > end
-                {
-                    return Err(Error::Memory("component out of bounds"));
-                }
+            let in_bounds = usize::try_from(be.offset).ok()
+                .zip(usize::try_from(be.size).ok())
+                .and_then(|(offset, size)| offset.checked_add(size))
+                .is_some_and(|end| end <= bytes.len());
+
+            if !in_bounds {
+                return Err(Error::Memory("component out of bounds"));
+            }
+
+            // components are read as i64 arrays, ContainerBuilder aligns all of them
+            if be.offset % 8 != 0 {
+                return Err(Error::Memory("component not aligned"));
             }
         }
 
         Ok(Container {
             name,
-            mmap,
+            storage,
             header,
             bom,
         })
     }
 
     pub fn get_component(&self, name: &str) -> Option<Component<'map>> {
-        let Range { start, end } = self.mmap.as_ref().as_ptr_range();
+        let Range { start, end } = self.storage.bytes().as_ptr_range();
         let be = self.bom.iter()
             .find(| be | { be.name().is_some_and(|s| s == name) })?;
 
@@ -199,16 +346,59 @@ impl<'map> Container<'map> {
         }
     }
 
+    /// Returns the key-value pairs stored in the optional "Metadata" component.
+    /// Containers without metadata yield an empty Vec.
+    pub fn metadata(&self) -> Vec<(&'map str, &'map str)> {
+        let list = match self.get_component("Metadata") {
+            Some(Component::StringList(list)) => list,
+            _ => return Vec::new(),
+        };
+
+        let mut strings = list.data()
+            .split(|&b| b == 0)
+            .take(list.len())
+            .map(|s| str::from_utf8(s).ok());
+
+        let mut pairs = Vec::new();
+        while let (Some(key), Some(value)) = (strings.next(), strings.next()) {
+            if let Some(pair) = key.zip(value) {
+                pairs.push(pair);
+            }
+        }
+        pairs
+    }
+
     pub fn name(&self) -> &str {
         &self.name
     }
 
+    /// Names of all components in the BOM, in the order they are stored
+    pub fn component_names(&self) -> impl Iterator<Item = &'map str> {
+        self.bom.iter()
+            .filter(|be| be.family == 0x01)
+            .filter_map(|be| be.name())
+    }
+
     pub fn header(&self) -> &Header {
         &self.header
     }
 
-    pub fn into_raw_parts(self) -> (String, Mmap, &'map Header, &'map [BomEntry]) {
-        (self.name, self.mmap, self.header, self.bom)
+    /// Returns the full comment of the container, either from the optional
+    /// "Comment" component or from the header.
+    pub fn comment(&self) -> Option<&'map str> {
+        match self.get_component("Comment") {
+            Some(Component::Blob(blob)) => str::from_utf8(blob.data()).ok(),
+            _ => self.header.comment(),
+        }
+    }
+
+    /// Raw bytes of the whole container
+    pub fn bytes(&self) -> &[u8] {
+        self.storage.bytes()
+    }
+
+    pub(crate) fn into_raw_parts(self) -> (String, Box<dyn Storage>, &'map Header, &'map [BomEntry]) {
+        (self.name, self.storage, self.header, self.bom)
     }
 
 }
@@ -219,6 +409,8 @@ pub enum Error {
     FormatError(&'static str),
     Utf8Error(Utf8Error),
     InvalidType(u64),
+    UnsupportedVersion(Version),
+    UnsupportedFeatures(Features),
     UuidError(uuid::Error),
     ComponentError(ComponentError),
 }
@@ -230,6 +422,8 @@ impl fmt::Display for Error {
             Self::FormatError(s) => write!(f, "{}", s),
             Self::Utf8Error(e) => write!(f, "{}", e),
             Self::InvalidType(t) => write!(f, "invalid container type {}", t),
+            Self::UnsupportedVersion(v) => write!(f, "unsupported container version {}", v),
+            Self::UnsupportedFeatures(x) => write!(f, "unsupported container features {}", x),
             Self::UuidError(e) => write!(f, "{}", e),
             Self::ComponentError(e) => write!(f, "{}", e),
         }
@@ -316,39 +510,298 @@ impl error::Error for TryFromError {
     }
 }
 
-pub struct ContainerBuilder<'map> {
-    file: File,
-    mmap: MmapMut,
+/// Errors while encoding a container.
+#[derive(Debug)]
+pub enum EncodeError {
+    Io(io::Error),
+    /// The input yielded fewer items than the declared length
+    LengthMismatch { expected: usize, found: usize },
+    InvalidRange(RangeError),
+    InvalidMetadata(&'static str),
+    /// A position is not within the base layer
+    OutOfBounds { position: usize, len: usize },
+    /// Compressed blocks need at least one row and at most 2^32-1
+    InvalidBlockSize(usize),
+    /// Partition `i` does not start after the previous one within the layer, the first at 0
+    InvalidPartition(usize),
+}
+
+impl fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{}", e),
+            Self::LengthMismatch { expected, found } => {
+                write!(f, "expected {} input items, found {}", expected, found)
+            }
+            Self::InvalidRange(e) => write!(f, "{}", e),
+            Self::InvalidMetadata(s) => write!(f, "invalid metadata: {}", s),
+            Self::OutOfBounds { position, len } => write!(f, "position {} out of bounds for base layer of length {}", position, len),
+            Self::InvalidBlockSize(size) => write!(f, "invalid block size {}", size),
+            Self::InvalidPartition(i) => write!(f, "invalid start of partition {}", i),
+        }
+    }
+}
+
+impl error::Error for EncodeError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            Self::InvalidRange(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for EncodeError {
+    fn from(value: io::Error) -> Self {
+        EncodeError::Io(value)
+    }
+}
+
+impl From<RangeError> for EncodeError {
+    fn from(value: RangeError) -> Self {
+        EncodeError::InvalidRange(value)
+    }
+}
+
+/// Migrates the container at `input` to [`Version::CURRENT`] and writes it to `output`.
+/// Returns the version the container had before.
+///
+/// All 1.x versions share one layout and keep their [`Features`], so only the version
+/// in the header is rewritten. Containers of a newer version than this build are rejected
+/// rather than downgraded. If `input` and `output` are the same file, the version is
+/// patched in place, otherwise the copy is written to a [`PendingFile`] and only replaces
+/// `output` once it is complete.
+pub fn migrate<P: AsRef<Path>, Q: AsRef<Path>>(input: P, output: Q) -> io::Result<Version> {
+    let (input, output) = (input.as_ref(), output.as_ref());
+
+    let version = {
+        let container = Container::from_storage(storage::open_file(input)?, String::new())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        container.header().version().expect("version already checked by from_storage")
+    };
+
+    if version > Version::CURRENT {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            Error::UnsupportedVersion(version),
+        ));
+    }
+
+    let offset = mem::offset_of!(Header, version) as u64;
+    let in_place = output.exists() && fs::canonicalize(input)? == fs::canonicalize(output)?;
+    if in_place {
+        let mut file = File::options().write(true).open(output)?;
+        file.seek(SeekFrom::Start(offset))?;
+        file.write_all(&Version::CURRENT.to_bytes())?;
+        file.sync_data()?;
+    } else {
+        let mut pending = PendingFile::replace(output)?;
+        io::copy(&mut File::open(input)?, &mut pending)?;
+        pending.seek(SeekFrom::Start(offset))?;
+        pending.write_all(&Version::CURRENT.to_bytes())?;
+        pending.commit()?;
+    }
+
+    Ok(version)
+}
+
+/// Destination of a [`ContainerBuilder`].
+///
+/// Files are mapped once the container is complete (or read into memory without
+/// the `mmap` feature), in-memory buffers
+/// (`Cursor<Vec<u8>>` or `Cursor<&mut Vec<u8>>`) are copied into [`Memory`] storage.
+pub trait EncodeTarget: Write + Seek {
+    /// Resizes the target to `len` bytes, padding it with zeros.
+    fn set_len(&mut self, len: u64) -> io::Result<()>;
+
+    /// Returns storage with the `len` bytes written so far at `offset`,
+    /// e.g. to read back a component while building the container.
+    fn map_range(&mut self, offset: u64, len: usize) -> io::Result<Box<dyn Storage>>;
+
+    /// Finishes the target, which has exactly `len` bytes, and returns its storage.
+    fn into_storage(self, len: usize) -> io::Result<Box<dyn Storage>>;
+}
+
+impl EncodeTarget for File {
+    fn set_len(&mut self, len: u64) -> io::Result<()> {
+        File::set_len(self, len)
+    }
+
+    #[cfg(feature = "mmap")]
+    fn map_range(&mut self, offset: u64, len: usize) -> io::Result<Box<dyn Storage>> {
+        let mmap = unsafe { MmapOptions::new().offset(offset).len(len).map(&*self)? };
+        Ok(Box::new(mmap))
+    }
+
+    #[cfg(not(feature = "mmap"))]
+    fn map_range(&mut self, offset: u64, len: usize) -> io::Result<Box<dyn Storage>> {
+        let mut bytes = vec![0u8; len];
+        self.seek(SeekFrom::Start(offset))?;
+        io::Read::read_exact(self, &mut bytes)?;
+        Ok(Box::new(Memory::new(&bytes)))
+    }
+
+    fn into_storage(mut self, len: usize) -> io::Result<Box<dyn Storage>> {
+        // resizing fails on Windows while any part of the file is mapped, e.g. by a caller
+        // reading back components, so it is only done when necessary
+        if self.metadata()?.len() != len as u64 {
+            File::set_len(&self, len as u64)?;
+        }
+
+        self.map_range(0, len)
+    }
+}
+
+impl<V> EncodeTarget for io::Cursor<V>
+where
+    V: AsRef<[u8]> + AsMut<Vec<u8>>,
+    io::Cursor<V>: Write,
+{
+    fn set_len(&mut self, len: u64) -> io::Result<()> {
+        self.get_mut().as_mut().resize(len as usize, 0);
+        Ok(())
+    }
+
+    fn map_range(&mut self, offset: u64, len: usize) -> io::Result<Box<dyn Storage>> {
+        let bytes = self.get_ref().as_ref();
+        let range = offset as usize..offset as usize + len;
+        match bytes.get(range) {
+            Some(bytes) => Ok(Box::new(Memory::new(bytes))),
+            None => Err(io::Error::new(io::ErrorKind::UnexpectedEof, "range beyond the end of the buffer")),
+        }
+    }
+
+    fn into_storage(mut self, len: usize) -> io::Result<Box<dyn Storage>> {
+        self.get_mut().as_mut().truncate(len);
+        self.map_range(0, len)
+    }
+}
+
+/// Runs an encoder writing to an in-memory buffer and returns the encoded container as bytes.
+///
+/// Any value returned by the encoder is dropped. The bytes can be opened again with
+/// [`Container::from_bytes`].
+pub fn encode_in_memory<T, F>(encode: F) -> Result<Vec<u8>, EncodeError>
+where
+    F: FnOnce(io::Cursor<&mut Vec<u8>>) -> Result<T, EncodeError>,
+{
+    let mut bytes = Vec::new();
+    drop(encode(io::Cursor::new(&mut bytes))?);
+    Ok(bytes)
+}
+
+pub struct ContainerBuilder<'map, W: EncodeTarget = File> {
+    file: W,
+    // header and BOM are kept in memory and written in `build`, a mapping of the file
+    // would prevent resizing it on Windows
+    headerbom: Vec<u8>,
     name: String,
     header_builder: HeaderBuilder<'map>,
     bom_builder: BomBuilder<'map>,
+    // first error of any step, returned by `build`; later components are skipped
+    error: Option<EncodeError>,
+}
+
+impl ContainerBuilder<'_> {
+    /// Number of additional BOM entries needed to store `comment`.
+    /// Comments that don't fit into the header need a "Comment" component.
+    pub fn comment_capacity(comment: &str) -> u8 {
+        if comment.len() > HEADER_COMMENT_LEN { 1 } else { 0 }
+    }
+}
+
+impl<'map> ContainerBuilder<'map, io::Cursor<Vec<u8>>> {
+    /// Creates a builder for a container kept in memory, see [`ContainerBuilder::new_into_file`].
+    pub fn new_in_memory(name: String, capacity: u8) -> Self {
+        Self::new_into_file(name, io::Cursor::new(Vec::new()), capacity)
+    }
 }
 
-impl<'map> ContainerBuilder<'map> {
-    pub fn new_into_file(name: String, file: File, capacity: u8) -> Self {
-        // make sure the mmap contains space for the header and 255 BOM entries
+impl<'map, W: EncodeTarget> ContainerBuilder<'map, W> {
+    pub fn new_into_file(name: String, mut file: W, capacity: u8) -> Self {
+        // reserve space for the header and capacity BOM entries
         let headerbomsize = mem::size_of::<Header>() + (mem::size_of::<BomEntry>() * capacity as usize);
-        file.set_len(headerbomsize as u64).unwrap();
+        let error = file.set_len(headerbomsize as u64).err().map(EncodeError::from);
 
-        let mut mmap = unsafe { MmapOptions::new().offset(0).len(headerbomsize).map_mut(&file).unwrap() };
-        let header = unsafe { Header::from_raw_mut(mmap.as_mut_ptr()).unwrap() };
-        let bom = unsafe { mmap.as_mut_ptr().offset(mem::size_of::<Header>() as isize) as *mut BomEntry };
+        // Header and BomEntry are packed, so the buffer needs no particular alignment.
+        // Its heap allocation does not move when the builder is moved.
+        let mut headerbom = vec![0u8; headerbomsize];
+        let header = unsafe { Header::from_raw_mut(headerbom.as_mut_ptr()).unwrap() };
+        let bom = unsafe { headerbom.as_mut_ptr().offset(mem::size_of::<Header>() as isize) as *mut BomEntry };
 
         Self {
             file,
-            mmap,
+            headerbom,
             name,
             header_builder: HeaderBuilder::new(header).allocated(capacity),
             bom_builder: BomBuilder::new(bom, capacity),
+            error,
         }
     }
 
+    /// Sets the container comment. Comments longer than the header field are
+    /// truncated in the header and stored in full in a "Comment" Blob component,
+    /// which has to be accounted for in the BOM capacity (see `comment_capacity`).
+    pub fn comment(mut self, comment: &str) -> Self {
+        self.header_builder.comment(truncate_str(comment, HEADER_COMMENT_LEN));
+
+        if comment.len() > HEADER_COMMENT_LEN {
+            self = self.add_component("Comment", components::Type::Blob, | bom_entry, file | {
+                let bytes = comment.as_bytes();
+                file.write_all(bytes)?;
+                bom_entry.size = bytes.len() as i64;
+                bom_entry.param1 = bytes.len() as i64;
+                Ok(())
+            });
+        }
+
+        self
+    }
+
+    /// Adds a "Metadata" StringList component storing arbitrary key-value pairs,
+    /// e.g. the encoding tool or a hash of the source file.
+    ///
+    /// Keys and values are stored null terminated, so building fails with
+    /// [`EncodeError::InvalidMetadata`] if any of them contains a null byte.
+    pub fn metadata<K: AsRef<str>, V: AsRef<str>>(self, pairs: &[(K, V)]) -> Self {
+        self.add_component("Metadata", components::Type::StringList, | bom_entry, file | {
+            if pairs.iter().any(|(key, value)| key.as_ref().contains('\0') || value.as_ref().contains('\0')) {
+                return Err(EncodeError::InvalidMetadata("metadata must not contain null bytes"));
+            }
+
+            let mut writer = BufWriter::new(file);
+            let mut len = 0;
+
+            for (key, value) in pairs {
+                for s in [key.as_ref(), value.as_ref()] {
+                    writer.write_all(s.as_bytes())?;
+                    writer.write_all(&[0])?;
+                    len += s.len() + 1;
+                }
+            }
+            writer.flush()?;
+
+            bom_entry.size = len as i64;
+            bom_entry.param1 = (pairs.len() * 2) as i64;
+            bom_entry.param2 = 0;
+            Ok(())
+        })
+    }
+
     pub fn edit_header(mut self, f: impl FnOnce(&mut HeaderBuilder) -> ()) -> Self {
         f(&mut self.header_builder);
         self
     }
 
-    pub fn add_component(mut self, name: &str, ctype: components::Type, f: impl FnOnce(&mut BomEntry, &mut File) -> ()) -> Self {
+    /// Adds a component whose data is written by `f`, which also fills in its size and parameters.
+    ///
+    /// If `f` or any previous step failed, the component is skipped and the error is returned by `build`.
+    pub fn add_component(mut self, name: &str, ctype: components::Type, f: impl FnOnce(&mut BomEntry, &mut W) -> Result<(), EncodeError>) -> Self {
+        if self.error.is_some() {
+            return self;
+        }
+
         let bom_entry = unsafe { self.bom_builder.new_component() };
 
         let name = name.as_bytes();
@@ -362,9 +815,14 @@ impl<'map> ContainerBuilder<'map> {
         bom_entry.mode = raw as u8;
 
         let offset = bom_entry.offset;
-        self.file.seek(SeekFrom::Start(offset as u64)).unwrap();
+        let result = self.file.seek(SeekFrom::Start(offset as u64))
+            .map_err(EncodeError::from)
+            .and_then(|_| f(bom_entry, &mut self.file));
 
-        f(bom_entry, &mut self.file);
+        if let Err(e) = result {
+            self.error = Some(e);
+            return self;
+        }
 
         assert!(bom_entry.offset == offset, "component offset modified during add_component");
 
@@ -375,11 +833,32 @@ impl<'map> ContainerBuilder<'map> {
         self.bom_builder.get_bom(index)
     }
 
-    pub fn file(&mut self) -> &File {
+    pub fn file(&mut self) -> &W {
         &self.file
     }
 
-    pub fn build(self) -> Container<'map> {
+    /// Maps the component with `index`, which has already been written, e.g. to build
+    /// another component from its values. The component borrows the returned storage.
+    pub fn map_component(&mut self, index: usize) -> Result<(BomEntry, Box<dyn Storage>), EncodeError> {
+        let be = *self.bom_builder.get_bom(index);
+        let storage = self.file.map_range(be.offset as u64, be.size as usize)?;
+        Ok((be, storage))
+    }
+
+    /// Returns the first error of the components added so far, e.g. before reading back
+    /// a written component.
+    pub fn checked(mut self) -> Result<Self, EncodeError> {
+        match self.error.take() {
+            Some(e) => Err(e),
+            None => Ok(self),
+        }
+    }
+
+    pub fn build(self) -> Result<Container<'map>, EncodeError> {
+        if let Some(e) = self.error {
+            return Err(e);
+        }
+
         let header = self.header_builder.build();
         let bom = self.bom_builder.build();
 
@@ -393,17 +872,14 @@ impl<'map> ContainerBuilder<'map> {
         } else {
             mem::size_of::<Header>() + (mem::size_of::<BomEntry>() * header.allocated as usize)
         };
-        self.file.set_len(actualsize as u64).unwrap();
-
-        let mmap = unsafe {
-            MmapOptions::new()
-                .offset(0)
-                .len(actualsize)
-                .map(&self.file)
-                .unwrap()
-        };
 
-        Container::from_mmap(mmap, self.name).unwrap()
+        let mut file = self.file;
+        file.seek(SeekFrom::Start(0))?;
+        file.write_all(&self.headerbom)?;
+        file.flush()?;
+
+        let storage = file.into_storage(actualsize)?;
+        Ok(Container::from_storage(storage, self.name).expect("container written by ContainerBuilder is invalid"))
     }
 }
 
@@ -412,9 +888,9 @@ pub struct HeaderBuilder<'map> {
 }
 
 impl<'map> HeaderBuilder<'map> {
-    pub fn new(header: &'map mut Header) -> Self {
+    pub(crate) fn new(header: &'map mut Header) -> Self {
         header.magic = "Ziggurat".as_bytes().try_into().unwrap();
-        header.version = "1.0".as_bytes().try_into().unwrap();
+        header.version = Version::CURRENT.to_bytes();
         header.family = 0;
         header.class = 0;
         header.ctype = 0;
@@ -445,7 +921,8 @@ impl<'map> HeaderBuilder<'map> {
 
     pub fn comment(&mut self, text: &str) -> &mut Self {
         let bytes = text.as_bytes();
-        assert!(bytes.len() <= 72, "comment too long");
+        assert!(bytes.len() <= HEADER_COMMENT_LEN, "comment too long");
+        self.header.comment.fill(0);
         self.header.comment[..bytes.len()].copy_from_slice(bytes);
         self
     }
@@ -488,6 +965,10 @@ impl<'map> HeaderBuilder<'map> {
         self
     }
 
+    pub fn features(&mut self, features: Features) -> &mut Self {
+        self.extensions(features.bits() as i64)
+    }
+
     pub fn base1(&mut self, uuid: Option<Uuid>) -> &mut Self {
         match uuid {
             Some(uuid) => self.header.base1_uuid = uuid.as_u128().to_be_bytes(),
@@ -504,7 +985,7 @@ impl<'map> HeaderBuilder<'map> {
         self
     }
 
-    pub fn build(self) -> &'map mut Header {
+    pub(crate) fn build(self) -> &'map mut Header {
         let header = self.header;
 
         // Identification triplet needs to be upper/lower case ascii letters
@@ -522,7 +1003,7 @@ impl<'map> HeaderBuilder<'map> {
     }
 }
 
-pub struct BomBuilder<'map> {
+pub(crate) struct BomBuilder<'map> {
     bom: &'map mut [BomEntry],
     capacity: u8,
 }
@@ -573,20 +1054,22 @@ impl<'map> BomBuilder<'map> {
 
 #[cfg(test)]
 mod tests {
-    use std::{fs::File, io::Write, mem};
+    use std::{env, fs::{self, File}, io::{self, Cursor, Seek, SeekFrom, Write}, mem};
 
     use crate::components;
+    use crate::lock::PendingFile;
+    use crate::storage;
 
-    use super::ContainerBuilder;
+    use super::{Container, ContainerBuilder, Error, Features, Header, Version};
 
     #[test]
     fn instantiate_empty() {
-        let filename = "/tmp/container.zigtest";
+        let filename = env::temp_dir().join("container.zigtest");
         let file = File::options()
             .read(true)
             .write(true)
             .create(true)
-            .open(filename)
+            .open(&filename)
             .unwrap();
 
         ContainerBuilder::new_into_file("Test".to_owned(), file, 1)
@@ -596,17 +1079,18 @@ mod tests {
                     .class('X')
                     .ctype('x');
             })
-            .build();
+            .build()
+            .unwrap();
     }
 
     #[test]
     fn instantiate_blob() {
-        let filename = "/tmp/blob.zigl";
+        let filename = env::temp_dir().join("blob.zigl");
         let file = File::options()
             .read(true)
             .write(true)
             .create(true)
-            .open(filename)
+            .open(&filename)
             .unwrap();
 
         println!();
@@ -620,27 +1104,132 @@ mod tests {
             })
             .add_component("Blob1", components::Type::Blob, | bom, file | {
                 let buf = "hello, I am the first test blob. have a nice day! :D".as_bytes();
-                file.write_all(buf).unwrap();
+                file.write_all(buf)?;
                 bom.size = buf.len() as i64;
                 bom.param1 = buf.len() as i64;
                 println!("Blob1: {:?}", bom);
+                Ok(())
             })
             .add_component("Blob2", components::Type::Blob, | bom, file | {
                 let buf = "sup, I'm another test blob. I may fuck your shit up :3".as_bytes();
-                file.write_all(buf).unwrap();
+                file.write_all(buf)?;
                 bom.size = buf.len() as i64;
                 bom.param1 = buf.len() as i64;
                 println!("Blob2: {:?}", bom);
+                Ok(())
             })
             .add_component("Blob3", components::Type::Blob, | bom, file | {
                 let buf: Vec<u64> = (1..100000).collect();
                 let blen = buf.len() * mem::size_of::<u64>();
                 let bs = unsafe { std::slice::from_raw_parts(buf.as_ptr() as *const u8, blen) };
-                file.write_all(bs).unwrap();
+                file.write_all(bs)?;
                 bom.size = blen as i64;
                 bom.param1 = blen as i64;
                 println!("Blob3: {:?}", bom);
+                Ok(())
+            })
+            .build()
+            .unwrap();
+    }
+
+    #[test]
+    fn version_parsing() {
+        assert!(Version::from_bytes(b"1.0").unwrap() == Version::CURRENT);
+        assert!(Version::from_bytes(b"1.3").unwrap().is_supported());
+        assert!(!Version::from_bytes(b"2.0").unwrap().is_supported());
+        assert!(Version::from_bytes(b"1-0").is_err());
+        assert!(&Version { major: 1, minor: 2 }.to_bytes() == b"1.2");
+    }
+
+    #[test]
+    fn feature_flags() {
+        let optional = Features::from_bits(1);
+        assert!(optional.unsupported().is_empty());
+        let required = Features::from_bits(1 << 40);
+        assert!(required.unsupported() == required);
+
+        let mut bytes = Vec::new();
+        let container = ContainerBuilder::new_into_file("Test".to_owned(), Cursor::new(&mut bytes), 1)
+            .edit_header(| hb | {
+                hb.family('X')
+                    .class('X')
+                    .ctype('x')
+                    .features(optional);
+            })
+            .build()
+            .unwrap();
+        assert!(container.header().features() == optional);
+
+        let offset = mem::offset_of!(Header, extensions);
+        bytes[offset..offset + 8].copy_from_slice(&(required.bits() | optional.bits()).to_le_bytes());
+        let error = Container::from_bytes(&bytes, String::new()).unwrap_err();
+        assert!(matches!(error, Error::UnsupportedFeatures(f) if f == required));
+    }
+
+    #[test]
+    fn migrate() {
+        let dir = tempfile::tempdir().unwrap();
+        let filename = dir.path().join("migrate.zigv");
+        let copy = dir.path().join("copy.zigv");
+
+        let mut file = PendingFile::create(&filename).unwrap();
+        ContainerBuilder::new_into_file("Test".to_owned(), file.file().unwrap(), 1)
+            .edit_header(| hb | {
+                hb.family('X')
+                    .class('X')
+                    .ctype('x')
+                    .features(Features::from_bits(1));
             })
-            .build();
+            .build()
+            .unwrap();
+        file.flush().unwrap();
+        file.commit().unwrap();
+
+        let version = super::migrate(&filename, &filename).unwrap();
+        assert!(version == Version::CURRENT);
+
+        // other major versions are rejected without writing a copy
+        let offset = mem::offset_of!(Header, version) as u64;
+        let mut patch = File::options().write(true).open(&filename).unwrap();
+        patch.seek(SeekFrom::Start(offset)).unwrap();
+        patch.write_all(b"0.9").unwrap();
+        drop(patch);
+        let error = super::migrate(&filename, &copy).unwrap_err();
+        assert!(error.kind() == io::ErrorKind::InvalidData);
+        assert!(!copy.exists());
+
+        let mut bytes = fs::read(&filename).unwrap();
+        bytes[offset as usize..offset as usize + 3].copy_from_slice(b"1.0");
+        fs::write(&filename, &bytes).unwrap();
+        // a copy keeps the features
+        assert!(super::migrate(&filename, &copy).unwrap() == Version::CURRENT);
+        assert!(fs::read(&copy).unwrap() == bytes);
+        let migrated = Container::from_storage(storage::open_file(&copy).unwrap(), String::new()).unwrap();
+        assert!(migrated.header().features() == Features::from_bits(1));
+
+        // newer versions are not downgraded
+        bytes[offset as usize..offset as usize + 3].copy_from_slice(b"1.3");
+        fs::write(&filename, &bytes).unwrap();
+        let error = super::migrate(&filename, &filename).unwrap_err();
+        assert!(error.kind() == io::ErrorKind::InvalidData);
+        assert!(fs::read(&filename).unwrap() == bytes);
+    }
+
+    #[test]
+    fn metadata_roundtrip() {
+        let file = tempfile::tempfile().unwrap();
+        let pairs = [("tool", "etemenanki"), ("source", "Dickens-1.0.xml.gz"), ("empty", "")];
+
+        let container = ContainerBuilder::new_into_file("Test".to_owned(), file, 1)
+            .edit_header(| hb | {
+                hb.family('X')
+                    .class('X')
+                    .ctype('x');
+            })
+            .metadata(&pairs)
+            .build()
+            .unwrap();
+
+        assert!(container.metadata() == pairs);
     }
 }
@@ -0,0 +1,242 @@
+//! Co-occurrence counts of the types of an indexed string variable, within windows of
+//! neighbouring positions or within the segments of a segmentation layer.
+//!
+//! The pairs of a whole corpus far outnumber the distinct pairs, so they are counted in
+//! chunks: distinct pairs are accumulated in memory up to a budget, each full chunk is
+//! spilled as a sorted run with [`external_sort`] and the runs are merged into the final
+//! counts. Only the result has to fit into memory.
+//!
+//! ```no_run
+//! # use etemenanki::{cooccurrence::{Context, CooccurrenceBuilder}, Datastore};
+//! let datastore = Datastore::open("dickens").unwrap();
+//! let lemma = datastore["primary"]["lemma"].as_indexed_string().unwrap();
+//!
+//! let matrix = CooccurrenceBuilder::new(lemma, Context::Window(5)).build().unwrap();
+//! let (dog, bark) = (lemma.type_id("dog").unwrap(), lemma.type_id("bark").unwrap());
+//! println!("{}", matrix.get(dog, bark));
+//! ```
+
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::io::{self, Seek, Write};
+use std::{error, fmt, vec};
+
+use crate::components::{external_sort, BigramIndex, Index};
+use crate::container::{BomEntry, EncodeError};
+use crate::frequency::{check_base, FrequencyError};
+use crate::layers::SegmentationLayer;
+use crate::variables::IndexedStringVariable;
+
+/// Positions whose types co-occur
+#[derive(Debug, Clone, Copy)]
+pub enum Context<'a, 'map> {
+    /// Positions at most this far apart, in either direction
+    Window(usize),
+    /// Positions in the same segment, which must be based on the layer of the variable
+    Segment(&'a SegmentationLayer<'map>),
+}
+
+#[derive(Debug)]
+pub enum CooccurrenceError {
+    Io(io::Error),
+    Frequency(FrequencyError),
+}
+
+impl fmt::Display for CooccurrenceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{}", e),
+            Self::Frequency(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl error::Error for CooccurrenceError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            Self::Frequency(e) => Some(e),
+        }
+    }
+}
+
+impl From<io::Error> for CooccurrenceError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<FrequencyError> for CooccurrenceError {
+    fn from(e: FrequencyError) -> Self {
+        Self::Frequency(e)
+    }
+}
+
+/// Counts the co-occurrences of the types of a variable in a [`Context`]
+pub struct CooccurrenceBuilder<'a, 'map> {
+    variable: &'a IndexedStringVariable<'map>,
+    context: Context<'a, 'map>,
+    memory_budget: usize,
+}
+
+impl<'a, 'map> CooccurrenceBuilder<'a, 'map> {
+    pub const DEFAULT_MEMORY_BUDGET: usize = 256 << 20;
+
+    /// Approximate memory taken by one distinct pair while counting
+    const PAIR_BYTES: usize = 32;
+
+    pub fn new(variable: &'a IndexedStringVariable<'map>, context: Context<'a, 'map>) -> Self {
+        Self { variable, context, memory_budget: Self::DEFAULT_MEMORY_BUDGET }
+    }
+
+    /// Limits the memory used for accumulating pairs, in bytes. Each time the budget is
+    /// exceeded the pairs counted so far are spilled to a temporary file.
+    pub fn set_memory_budget(&mut self, bytes: usize) {
+        self.memory_budget = bytes;
+    }
+
+    /// Keys of the pairs of types at all pairs of positions in the context, in both orders
+    fn pairs(&self) -> Result<Box<dyn Iterator<Item = i64> + 'a>, CooccurrenceError> {
+        let variable = self.variable;
+        let both = |a: usize, b: usize| [BigramIndex::key(a, b), BigramIndex::key(b, a)];
+
+        Ok(match self.context {
+            Context::Window(size) => {
+                let pairs = variable.id_stream()
+                    .column_iter(0)
+                    .scan(VecDeque::with_capacity(size + 1), move |window: &mut VecDeque<usize>, id| {
+                        let id = id as usize;
+                        let pairs: Vec<i64> = window.iter().flat_map(|&previous| both(previous, id)).collect();
+                        window.push_back(id);
+                        if window.len() > size {
+                            window.pop_front();
+                        }
+                        Some(pairs)
+                    })
+                    .flatten();
+                Box::new(pairs)
+            }
+            Context::Segment(segmentation) => {
+                check_base(variable, segmentation)?;
+                let pairs = segmentation.iter()
+                    .flat_map(move |(start, end)| {
+                        let ids: Vec<usize> = variable.id_stream()
+                            .column_iter_range(start.min(variable.len()), end.min(variable.len()), 0)
+                            .into_iter()
+                            .flatten()
+                            .map(|id| id as usize)
+                            .collect();
+                        (0..ids.len())
+                            .flat_map(|i| (i + 1..ids.len()).map(move |j| (i, j)))
+                            .flat_map(|(i, j)| both(ids[i], ids[j]))
+                            .collect::<Vec<i64>>()
+                    });
+                Box::new(pairs)
+            }
+        })
+    }
+
+    /// Counts all co-occurrences.
+    ///
+    /// Every pair of positions in the context counts for both orders of their types, so
+    /// the result is symmetric.
+    pub fn build(&self) -> Result<CooccurrenceMatrix, CooccurrenceError> {
+        let chunk_len = (self.memory_budget / Self::PAIR_BYTES).max(1);
+        let chunks = Chunks { pairs: self.pairs()?, counts: HashMap::new(), chunk_len, spilled: Vec::new().into_iter() };
+
+        // partial counts of the same pair from different chunks are adjacent after sorting
+        let mut pairs: Vec<(i64, i64)> = Vec::new();
+        for (key, count) in external_sort(chunks, chunk_len)? {
+            match pairs.last_mut() {
+                Some((last, total)) if *last == key => *total += count,
+                _ => pairs.push((key, count)),
+            }
+        }
+
+        Ok(CooccurrenceMatrix { pairs })
+    }
+}
+
+/// Partial counts of the distinct pairs of each chunk of `chunk_len` distinct pairs
+struct Chunks<'a> {
+    pairs: Box<dyn Iterator<Item = i64> + 'a>,
+    counts: HashMap<i64, i64>,
+    chunk_len: usize,
+    spilled: vec::IntoIter<(i64, i64)>,
+}
+
+impl<'a> Iterator for Chunks<'a> {
+    type Item = (i64, i64);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(pair) = self.spilled.next() {
+            return Some(pair);
+        }
+
+        for key in self.pairs.by_ref() {
+            *self.counts.entry(key).or_insert(0) += 1;
+            if self.counts.len() >= self.chunk_len {
+                break;
+            }
+        }
+        self.spilled = self.counts.drain().collect::<Vec<_>>().into_iter();
+        self.spilled.next()
+    }
+}
+
+/// Sparse matrix of co-occurrence counts of pairs of type IDs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CooccurrenceMatrix {
+    /// Counts by [`BigramIndex::key`] of the pair, ascending by key
+    pairs: Vec<(i64, i64)>,
+}
+
+impl CooccurrenceMatrix {
+    /// Reads a matrix written with [`write_component`](Self::write_component), `None` for a
+    /// compressed index
+    pub fn from_index(index: Index) -> Option<Self> {
+        match index {
+            Index::Uncompressed { pairs, .. } => Some(Self { pairs: pairs.to_vec() }),
+            Index::Compressed { .. } => None,
+        }
+    }
+
+    /// Number of pairs of types that co-occur
+    pub fn len(&self) -> usize {
+        self.pairs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pairs.is_empty()
+    }
+
+    /// Number of co-occurrences of `first` with `second`
+    pub fn get(&self, first: usize, second: usize) -> usize {
+        let key = BigramIndex::key(first, second);
+        self.pairs.binary_search_by_key(&key, |(k, _)| *k)
+            .map_or(0, |i| self.pairs[i].1 as usize)
+    }
+
+    /// Types co-occurring with `first` and their counts, ascending by type ID
+    pub fn row(&self, first: usize) -> impl Iterator<Item = (usize, usize)> + '_ {
+        let start = self.pairs.partition_point(|(k, _)| *k < BigramIndex::key(first, 0));
+        let end = self.pairs.partition_point(|(k, _)| *k < BigramIndex::key(first + 1, 0));
+        self.pairs[start..end].iter().map(|(k, n)| ((k & 0xffff_ffff) as usize, *n as usize))
+    }
+
+    /// All non-zero counts as `(first, second, count)`, ascending by both types
+    pub fn iter(&self) -> impl Iterator<Item = (usize, usize, usize)> + '_ {
+        self.pairs.iter().map(|(k, n)| ((k >> 32) as usize, (k & 0xffff_ffff) as usize, *n as usize))
+    }
+
+    /// Sum of all counts
+    pub fn total(&self) -> usize {
+        self.pairs.iter().map(|(_, n)| *n as usize).sum()
+    }
+
+    /// Writes the matrix as an uncompressed [`Index`] component from the key of each pair
+    /// to its count, e.g. to store it in a container next to the variable.
+    pub unsafe fn write_component<W: Write + Seek>(&self, file: &mut W, bom_entry: &mut BomEntry, start_offset: u64) -> Result<(), EncodeError> {
+        Index::encode_uncompressed_to_container_file(self.pairs.iter().copied(), self.len(), file, bom_entry, start_offset)
+    }
+}
@@ -0,0 +1,199 @@
+//! Round-trip validation of a datastore against the CWB corpus it was converted from.
+//!
+//! The corpus is read through libcl and walked side by side with the datastore:
+//!
+//! - positional attributes are compared token by token with the variable of the same
+//!   name on the primary layer,
+//! - structural attributes without values (e.g. `chapter`) are compared range by range
+//!   with the segmentation layer of the same name,
+//! - structural attributes with values (e.g. `chapter_title`) are compared region by
+//!   region with the variable `title` of the segmentation layer `chapter`.
+//!
+//! CWB regions end at their last token, which is converted to the exclusive end
+//! used by segmentation layers before comparing. libcl addresses corpus positions with
+//! 32 bit integers, so only corpora below 2^31 tokens can be compared, while datastores
+//! store all positions as 64 bit integers.
+
+use std::{error, fmt};
+
+use libcl_rs::{Corpus, DataAccessError};
+
+use crate::layers::Layer;
+use crate::Datastore;
+
+#[derive(Debug)]
+pub enum CwbError {
+    /// The datastore has no primary layer with this name
+    UnknownLayer(String),
+    Access { attribute: String, error: DataAccessError },
+}
+
+impl fmt::Display for CwbError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownLayer(name) => write!(f, "no primary layer named {}", name),
+            Self::Access { attribute, error } => write!(f, "could not read CWB attribute {}: {}", attribute, error),
+        }
+    }
+}
+
+impl error::Error for CwbError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::Access { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+/// Difference at one token or region of an attribute
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mismatch {
+    pub attribute: String,
+    /// Corpus position for positional attributes, region number for structural attributes
+    pub position: usize,
+    /// Value in the CWB corpus
+    pub expected: String,
+    /// Value in the datastore
+    pub found: String,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at {}: expected {:?}, found {:?}", self.attribute, self.position, self.expected, self.found)
+    }
+}
+
+/// Result of [`validate`]
+#[derive(Debug, Clone, Default)]
+pub struct ValidationReport {
+    /// CWB attributes compared with the datastore
+    pub checked: Vec<String>,
+    /// CWB attributes without counterpart in the datastore
+    pub missing: Vec<String>,
+    /// The first mismatches found, see [`validate`]
+    pub mismatches: Vec<Mismatch>,
+    /// Number of all mismatches, including those not kept in `mismatches`
+    pub total_mismatches: usize,
+}
+
+impl ValidationReport {
+    /// Whether every attribute of the corpus is present in the datastore with identical content
+    pub fn is_valid(&self) -> bool {
+        self.missing.is_empty() && self.total_mismatches == 0
+    }
+
+    fn push(&mut self, max_mismatches: usize, mismatch: Mismatch) {
+        self.total_mismatches += 1;
+        if self.mismatches.len() < max_mismatches {
+            self.mismatches.push(mismatch);
+        }
+    }
+}
+
+fn access(attribute: &str) -> impl FnOnce(DataAccessError) -> CwbError + '_ {
+    move |error| CwbError::Access { attribute: attribute.to_owned(), error }
+}
+
+/// Compares all attributes of `corpus` with the primary layer `primary` of `datastore`
+/// and the segmentation layers on top of it, keeping the first `max_mismatches` mismatches.
+pub fn validate(datastore: &Datastore, corpus: &Corpus, primary: &str, max_mismatches: usize) -> Result<ValidationReport, CwbError> {
+    let layer = datastore.layer_by_name(primary)
+        .filter(|l| l.is_primary())
+        .ok_or_else(|| CwbError::UnknownLayer(primary.to_owned()))?;
+
+    let mut report = ValidationReport::default();
+
+    for name in corpus.list_p_attributes() {
+        let (Some(attribute), Some(variable)) = (corpus.get_p_attribute(name), layer.variable_by_name(name)) else {
+            report.missing.push(name.to_owned());
+            continue;
+        };
+        report.checked.push(name.to_owned());
+
+        let len = attribute.max_cpos().map_err(access(name))? as usize;
+        if len != layer.len() {
+            report.push(max_mismatches, Mismatch {
+                attribute: name.to_owned(),
+                position: len.min(layer.len()),
+                expected: format!("{} tokens", len),
+                found: format!("{} tokens", layer.len()),
+            });
+        }
+
+        for cpos in 0..len.min(layer.len()) {
+            let expected = attribute.cpos2str(cpos as i32).map_err(access(name))?.to_string_lossy();
+            let found = variable.get(cpos).map(|v| v.to_string()).unwrap_or_default();
+            if expected != found {
+                report.push(max_mismatches, Mismatch { attribute: name.to_owned(), position: cpos, expected: expected.into_owned(), found });
+            }
+        }
+    }
+
+    let s_attributes = corpus.list_s_attributes();
+    for &name in s_attributes.iter() {
+        let Some(attribute) = corpus.get_s_attribute(name) else {
+            report.missing.push(name.to_owned());
+            continue;
+        };
+        let regions = attribute.max_struc().map_err(access(name))? as usize;
+
+        // values of region attributes are stored as <attribute>_<variable>, take the longest such prefix
+        let owner = s_attributes.iter()
+            .filter(|&&other| name.len() > other.len() + 1 && name.starts_with(other) && name.as_bytes()[other.len()] == b'_')
+            .max_by_key(|other| other.len());
+
+        let segmentation = datastore.layer_by_name(owner.copied().unwrap_or(name))
+            .and_then(Layer::as_segmentation)
+            .filter(|s| s.base == layer.uuid());
+        let Some(segmentation) = segmentation else {
+            report.missing.push(name.to_owned());
+            continue;
+        };
+
+        if regions != segmentation.len() {
+            report.push(max_mismatches, Mismatch {
+                attribute: name.to_owned(),
+                position: regions.min(segmentation.len()),
+                expected: format!("{} regions", regions),
+                found: format!("{} regions", segmentation.len()),
+            });
+        }
+        let n = regions.min(segmentation.len());
+
+        match owner {
+            None => {
+                report.checked.push(name.to_owned());
+                for struc in 0..n {
+                    let (start, end) = attribute.struc2cpos(struc as i32).map_err(access(name))?;
+                    let expected = (start as usize, end as usize + 1);
+                    let found = segmentation.get_unchecked(struc);
+                    if expected != found {
+                        report.push(max_mismatches, Mismatch {
+                            attribute: name.to_owned(),
+                            position: struc,
+                            expected: format!("{}..{}", expected.0, expected.1),
+                            found: format!("{}..{}", found.0, found.1),
+                        });
+                    }
+                }
+            }
+            Some(owner) => {
+                let Some(variable) = segmentation.variable_by_name(&name[owner.len() + 1..]) else {
+                    report.missing.push(name.to_owned());
+                    continue;
+                };
+                report.checked.push(name.to_owned());
+                for struc in 0..n {
+                    let expected = attribute.struc2str(struc as i32).map_err(access(name))?.to_string_lossy();
+                    let found = variable.get(struc).map(|v| v.to_string()).unwrap_or_default();
+                    if expected != found {
+                        report.push(max_mismatches, Mismatch { attribute: name.to_owned(), position: struc, expected: expected.into_owned(), found });
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(report)
+}
@@ -0,0 +1,123 @@
+//! Sparse document-term matrices of the segments of a segmentation layer, e.g. for topic
+//! modeling.
+//!
+//! Each segment is a document, i.e. a row, and each type of an indexed string variable
+//! that occurs often enough in the whole corpus is a term, i.e. a column:
+//!
+//! ```no_run
+//! # use std::fs::File;
+//! # use etemenanki::{dtm::DocumentTermMatrix, Datastore};
+//! let datastore = Datastore::open("dickens").unwrap();
+//! let lemma = datastore["primary"]["lemma"].as_indexed_string().unwrap();
+//! let chapters = datastore["chapter"].as_segmentation().unwrap();
+//!
+//! let matrix = DocumentTermMatrix::new(lemma, chapters, 5).unwrap();
+//! matrix.write_matrix_market(File::create("chapters.mtx").unwrap()).unwrap();
+//! matrix.write_terms(lemma, File::create("chapters.terms").unwrap()).unwrap();
+//! ```
+//!
+//! With the `arrow` feature the matrix can also be exported as a record batch, see
+//! [`export::document_term_table`](crate::export::document_term_table).
+
+use std::io::{self, BufWriter, Write};
+
+use crate::frequency::{for_each_in_segments, FrequencyError};
+use crate::layers::SegmentationLayer;
+use crate::variables::IndexedStringVariable;
+
+/// Counts of the terms of each segment, without the zeros
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DocumentTermMatrix {
+    n_segments: usize,
+    type_ids: Vec<usize>,
+    /// `(segment, column, count)`, ascending by segment and then column
+    entries: Vec<(usize, usize, usize)>,
+}
+
+impl DocumentTermMatrix {
+    /// Counts the types of `variable` with a frequency of at least `min_frequency` in each
+    /// segment of `segmentation`, which must be based on the layer of `variable`.
+    ///
+    /// Columns are the remaining types in lexicon order.
+    pub fn new(variable: &IndexedStringVariable, segmentation: &SegmentationLayer, min_frequency: usize) -> Result<Self, FrequencyError> {
+        let type_ids: Vec<usize> = (0..variable.n_types())
+            .filter(|t| variable.frequency(*t).unwrap_or(0) >= min_frequency)
+            .collect();
+        let mut columns = vec![usize::MAX; variable.n_types()];
+        for (column, &type_id) in type_ids.iter().enumerate() {
+            columns[type_id] = column;
+        }
+
+        // counts of the current segment, reset through the list of touched columns
+        let mut counts = vec![0; type_ids.len()];
+        let mut touched: Vec<usize> = Vec::new();
+        let mut current = 0;
+        let mut entries = Vec::new();
+        let mut flush = |segment: usize, counts: &mut [usize], touched: &mut Vec<usize>| {
+            touched.sort_unstable();
+            for &column in touched.iter() {
+                entries.push((segment, column, std::mem::take(&mut counts[column])));
+            }
+            touched.clear();
+        };
+
+        for_each_in_segments(variable, segmentation, |segment, id| {
+            if segment != current {
+                flush(current, &mut counts, &mut touched);
+                current = segment;
+            }
+            if let Some(&column) = columns.get(id).filter(|c| **c != usize::MAX) {
+                if counts[column] == 0 {
+                    touched.push(column);
+                }
+                counts[column] += 1;
+            }
+        })?;
+        flush(current, &mut counts, &mut touched);
+
+        Ok(Self { n_segments: segmentation.len(), type_ids, entries })
+    }
+
+    /// Number of rows
+    pub fn n_segments(&self) -> usize {
+        self.n_segments
+    }
+
+    /// Number of columns
+    pub fn n_terms(&self) -> usize {
+        self.type_ids.len()
+    }
+
+    /// Type IDs of the columns
+    pub fn type_ids(&self) -> &[usize] {
+        &self.type_ids
+    }
+
+    /// Non-zero counts as `(segment, column, count)`, ascending by segment and then column
+    pub fn entries(&self) -> &[(usize, usize, usize)] {
+        &self.entries
+    }
+
+    /// Writes the matrix in the Matrix Market coordinate format, with 1-based indices
+    pub fn write_matrix_market<W: Write>(&self, writer: W) -> io::Result<()> {
+        let mut writer = BufWriter::new(writer);
+        writeln!(writer, "%%MatrixMarket matrix coordinate integer general")?;
+        writeln!(writer, "% rows are segments, columns are terms")?;
+        writeln!(writer, "{} {} {}", self.n_segments, self.n_terms(), self.entries.len())?;
+        for &(segment, column, count) in &self.entries {
+            writeln!(writer, "{} {} {}", segment + 1, column + 1, count)?;
+        }
+        writer.flush()
+    }
+
+    /// Writes the types of the columns of a matrix of `variable`, one per line. Matrix
+    /// Market files have no labels, so this is needed to interpret the columns.
+    pub fn write_terms<W: Write>(&self, variable: &IndexedStringVariable, writer: W) -> io::Result<()> {
+        let mut writer = BufWriter::new(writer);
+        for &type_id in &self.type_ids {
+            let term = variable.lexicon().get(type_id).unwrap_or_default();
+            writeln!(writer, "{}", term)?;
+        }
+        writer.flush()
+    }
+}
@@ -0,0 +1,216 @@
+//! Conversion of corpus data into Apache Arrow record batches.
+//!
+//! Frequency tables, postings lists and decoded variable ranges are built directly
+//! into Arrow arrays, so they can be handed to dataframe libraries (pandas, polars)
+//! without going through per-item Python objects. With the `parquet` feature the
+//! batches can also be written to Parquet files.
+
+use std::sync::Arc;
+use std::{error, fmt};
+
+use arrow_array::builder::{FixedSizeListBuilder, Int64Builder, ListBuilder, StringBuilder};
+use arrow_array::{ArrayRef, Int64Array, RecordBatch, StringArray, UInt64Array};
+use arrow_schema::ArrowError;
+
+use crate::dtm::DocumentTermMatrix;
+use crate::layers::Layer;
+use crate::variables::{IndexedStringVariable, Variable};
+
+#[derive(Debug)]
+pub enum ExportError {
+    Arrow(ArrowError),
+    #[cfg(feature = "parquet")]
+    Parquet(parquet::errors::ParquetError),
+    OutOfRange(usize, usize),
+    UnknownVariable(String),
+    UnsupportedVariable,
+}
+
+impl fmt::Display for ExportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Arrow(e) => write!(f, "arrow error: {}", e),
+            #[cfg(feature = "parquet")]
+            Self::Parquet(e) => write!(f, "parquet error: {}", e),
+            Self::OutOfRange(start, end) => write!(f, "range {}..{} is out of bounds", start, end),
+            Self::UnknownVariable(name) => write!(f, "no variable named {}", name),
+            Self::UnsupportedVariable => write!(f, "variable type can not be exported"),
+        }
+    }
+}
+
+impl error::Error for ExportError {}
+
+impl From<ArrowError> for ExportError {
+    fn from(value: ArrowError) -> Self {
+        Self::Arrow(value)
+    }
+}
+
+#[cfg(feature = "parquet")]
+impl From<parquet::errors::ParquetError> for ExportError {
+    fn from(value: parquet::errors::ParquetError) -> Self {
+        Self::Parquet(value)
+    }
+}
+
+/// Builds the frequency table of an indexed string variable with the
+/// columns `id`, `type` and `frequency`, in lexicon order.
+pub fn frequency_table(var: &IndexedStringVariable) -> Result<RecordBatch, ExportError> {
+    let index = var.inverted_index();
+    let n = var.n_types();
+
+    let ids = UInt64Array::from_iter_values(0..n as u64);
+    let types = StringArray::from_iter_values(var.lexicon().iter());
+    let frequencies = UInt64Array::from_iter_values(
+        (0..n).map(|tid| index.frequency(tid).unwrap_or(0) as u64)
+    );
+
+    Ok(RecordBatch::try_from_iter([
+        ("id", Arc::new(ids) as ArrayRef),
+        ("type", Arc::new(types) as ArrayRef),
+        ("frequency", Arc::new(frequencies) as ArrayRef),
+    ])?)
+}
+
+/// Returns the postings of lexicon type `type_id`, see [`Variable::postings`]
+pub fn postings(var: &Variable, type_id: usize) -> Option<UInt64Array> {
+    var.postings(type_id)
+        .map(|positions| UInt64Array::from_iter_values(positions.map(|p| p as u64)))
+}
+
+/// Builds a table with the columns `id` and `position` holding the postings of all `type_ids`.
+/// Unknown types are skipped.
+pub fn postings_table(var: &Variable, type_ids: &[usize]) -> Result<RecordBatch, ExportError> {
+    let mut ids = Vec::new();
+    let mut positions = Vec::new();
+
+    for &tid in type_ids {
+        for position in var.postings(tid).into_iter().flatten() {
+            ids.push(tid as u64);
+            positions.push(position as u64);
+        }
+    }
+
+    Ok(RecordBatch::try_from_iter([
+        ("id", Arc::new(UInt64Array::from(ids)) as ArrayRef),
+        ("position", Arc::new(UInt64Array::from(positions)) as ArrayRef),
+    ])?)
+}
+
+/// Builds a table with the columns `segment`, `id` and `count` holding the non-zero
+/// entries of a document-term matrix, with the type IDs of the columns as `id`.
+pub fn document_term_table(matrix: &DocumentTermMatrix) -> Result<RecordBatch, ExportError> {
+    let entries = matrix.entries();
+    let segments = UInt64Array::from_iter_values(entries.iter().map(|(segment, _, _)| *segment as u64));
+    let ids = UInt64Array::from_iter_values(entries.iter().map(|(_, column, _)| matrix.type_ids()[*column] as u64));
+    let counts = UInt64Array::from_iter_values(entries.iter().map(|(_, _, count)| *count as u64));
+
+    Ok(RecordBatch::try_from_iter([
+        ("segment", Arc::new(segments) as ArrayRef),
+        ("id", Arc::new(ids) as ArrayRef),
+        ("count", Arc::new(counts) as ArrayRef),
+    ])?)
+}
+
+/// Decodes the positions `start..end` of a variable into an Arrow array.
+///
+/// Strings become `Utf8` arrays, integers `Int64` arrays, multi-column integers and
+/// character offsets fixed size lists of `Int64`, pointers nullable `UInt64` arrays of head positions, sets lists
+/// of their sorted items and sparse variables nullable `Utf8` arrays.
+pub fn variable_range(var: &Variable, start: usize, end: usize) -> Result<ArrayRef, ExportError> {
+    if start > end || end > var.len() {
+        return Err(ExportError::OutOfRange(start, end));
+    }
+
+    let array: ArrayRef = match var {
+        Variable::IndexedString(v) => {
+            Arc::new(StringArray::from_iter_values(v.get_range(start, end).unwrap()))
+        }
+
+        Variable::PlainString(v) => {
+            Arc::new(StringArray::from_iter_values((start..end).map(|i| v.get_unchecked(i))))
+        }
+
+        Variable::Integer(v) => {
+            Arc::new(Int64Array::from_iter_values((start..end).map(|i| v.get_unchecked(i))))
+        }
+
+        Variable::IntegerVector(v) => {
+            let mut builder = FixedSizeListBuilder::new(Int64Builder::new(), v.width() as i32);
+            for row in v.get_range(start, end).unwrap() {
+                builder.values().append_slice(&row);
+                builder.append(true);
+            }
+            Arc::new(builder.finish())
+        }
+
+        Variable::Offset(v) => {
+            let mut builder = FixedSizeListBuilder::new(Int64Builder::new(), 2);
+            for (start, end) in v.get_range(start, end).unwrap() {
+                builder.values().append_slice(&[start as i64, end as i64]);
+                builder.append(true);
+            }
+            Arc::new(builder.finish())
+        }
+
+        Variable::Pointer(v) => {
+            Arc::new(UInt64Array::from_iter((start..end).map(|i| v.get_unchecked(i).map(|h| h as u64))))
+        }
+
+        Variable::Set(v) => {
+            let mut builder = ListBuilder::new(StringBuilder::new());
+            for i in start..end {
+                let mut items: Vec<_> = v.get_unchecked(i).into_iter().collect();
+                items.sort_unstable();
+                builder.values().extend(items.into_iter().map(Some));
+                builder.append(true);
+            }
+            Arc::new(builder.finish())
+        }
+
+        Variable::Sparse(v) => {
+            Arc::new(StringArray::from_iter(v.get_range(start, end).unwrap()))
+        }
+
+        Variable::ExternalPointer(_) | Variable::Hash(_) => return Err(ExportError::UnsupportedVariable),
+    };
+
+    Ok(array)
+}
+
+/// Builds a table of the positions `start..end` of a layer with a `position`
+/// column followed by one column for each of the variables `names`.
+pub fn layer_range<S: AsRef<str>>(layer: &Layer, names: &[S], start: usize, end: usize) -> Result<RecordBatch, ExportError> {
+    let positions = UInt64Array::from_iter_values(start as u64..end as u64);
+    let mut columns = vec![("position".to_owned(), Arc::new(positions) as ArrayRef)];
+
+    for name in names {
+        let name = name.as_ref();
+        let var = layer.variable_by_name(name)
+            .ok_or_else(|| ExportError::UnknownVariable(name.to_owned()))?;
+        columns.push((name.to_owned(), variable_range(var, start, end)?));
+    }
+
+    Ok(RecordBatch::try_from_iter(columns)?)
+}
+
+/// Writes `batches` as a single Parquet file to `writer`. All batches must share a schema.
+#[cfg(feature = "parquet")]
+pub fn write_parquet<W>(batches: &[RecordBatch], writer: W) -> Result<(), ExportError>
+where
+    W: std::io::Write + Send,
+{
+    let schema = match batches.first() {
+        Some(batch) => batch.schema(),
+        None => return Ok(()),
+    };
+
+    let mut writer = parquet::arrow::ArrowWriter::try_new(writer, schema, None)?;
+    for batch in batches {
+        writer.write(batch)?;
+    }
+    writer.close()?;
+
+    Ok(())
+}
@@ -0,0 +1,162 @@
+//! Encoding from inputs that can fail, e.g. parsers reading from a network stream.
+//!
+//! Encoders take their input as a plain iterator. [`try_encode`] adapts an iterator of
+//! `Result`s: the encoder sees the values up to the first error, which is then returned
+//! instead of the encoder's result. The output is wrapped as well and refuses to be
+//! finished after an error, so an encoder never completes a container from truncated
+//! input, and a [`PendingFile`](crate::lock::PendingFile) target is removed again.
+//!
+//! The receiver of a bounded channel is such an iterator. Encoders pull one item at a
+//! time, so a producer in another thread blocks while the channel is full instead of
+//! buffering its whole output.
+//!
+//! ```no_run
+//! # use std::io::{BufRead, BufReader};
+//! # use std::sync::mpsc;
+//! # use etemenanki::{fallible::try_encode, lock::PendingFile, variables::PlainStringVariable};
+//! # let n = 0;
+//! let (sender, receiver) = mpsc::sync_channel(1024);
+//! std::thread::spawn(move || {
+//!     let input = BufReader::new(std::fs::File::open("words.txt").unwrap());
+//!     for line in input.lines() {
+//!         if sender.send(line).is_err() {
+//!             break;
+//!         }
+//!     }
+//! });
+//!
+//! let file = PendingFile::create("word.zigv").unwrap();
+//! let result = try_encode(file, receiver, |file, words| {
+//!     PlainStringVariable::encode_to_file(file, words, n, "word".into(), uuid::Uuid::new_v4(), true, "")
+//! });
+//! ```
+
+use std::cell::RefCell;
+use std::io::{self, Seek, SeekFrom, Write};
+use std::rc::Rc;
+use std::{error, fmt};
+
+use crate::container::{EncodeError, EncodeTarget};
+use crate::storage::Storage;
+
+#[derive(Debug)]
+pub enum TryEncodeError<E> {
+    /// The input failed
+    Source(E),
+    Encode(EncodeError),
+}
+
+impl<E: fmt::Display> fmt::Display for TryEncodeError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Source(e) => write!(f, "input failed: {}", e),
+            Self::Encode(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl<E: error::Error + 'static> error::Error for TryEncodeError<E> {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::Source(e) => Some(e),
+            Self::Encode(e) => Some(e),
+        }
+    }
+}
+
+impl<E> From<EncodeError> for TryEncodeError<E> {
+    fn from(e: EncodeError) -> Self {
+        Self::Encode(e)
+    }
+}
+
+/// Runs `encode` with `target` and the values of `items`, stopping at the first error of
+/// `items`, see the [module documentation](self).
+pub fn try_encode<W, I, T, E, F, R>(target: W, items: I, encode: F) -> Result<R, TryEncodeError<E>>
+where
+    W: EncodeTarget,
+    I: IntoIterator<Item = Result<T, E>>,
+    F: FnOnce(FallibleTarget<W, E>, Fallible<I::IntoIter, E>) -> Result<R, EncodeError>,
+{
+    let error = Rc::new(RefCell::new(None));
+    let target = FallibleTarget { inner: target, error: error.clone() };
+    let items = Fallible { inner: items.into_iter(), error: error.clone() };
+
+    let result = encode(target, items);
+    // an error of the input explains any error of the encoder, e.g. a length mismatch
+    if let Some(e) = error.borrow_mut().take() {
+        return Err(TryEncodeError::Source(e));
+    }
+    Ok(result?)
+}
+
+/// Encoder input passed by [`try_encode`], ending at the first error
+#[derive(Debug)]
+pub struct Fallible<I, E> {
+    inner: I,
+    error: Rc<RefCell<Option<E>>>,
+}
+
+impl<I, T, E> Iterator for Fallible<I, E>
+where
+    I: Iterator<Item = Result<T, E>>,
+{
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        if self.error.borrow().is_some() {
+            return None;
+        }
+        match self.inner.next()? {
+            Ok(item) => Some(item),
+            Err(e) => {
+                *self.error.borrow_mut() = Some(e);
+                None
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, self.inner.size_hint().1)
+    }
+}
+
+/// Encoder output passed by [`try_encode`], which fails to finish after an error of the input
+#[derive(Debug)]
+pub struct FallibleTarget<W, E> {
+    inner: W,
+    error: Rc<RefCell<Option<E>>>,
+}
+
+impl<W: Write, E> Write for FallibleTarget<W, E> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<W: Seek, E> Seek for FallibleTarget<W, E> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+impl<W: EncodeTarget, E> EncodeTarget for FallibleTarget<W, E> {
+    fn set_len(&mut self, len: u64) -> io::Result<()> {
+        self.inner.set_len(len)
+    }
+
+    fn map_range(&mut self, offset: u64, len: usize) -> io::Result<Box<dyn Storage>> {
+        self.inner.map_range(offset, len)
+    }
+
+    fn into_storage(self, len: usize) -> io::Result<Box<dyn Storage>> {
+        if self.error.borrow().is_some() {
+            return Err(io::Error::other("the input of the encoder failed"));
+        }
+        self.inner.into_storage(len)
+    }
+}
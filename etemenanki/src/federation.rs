@@ -0,0 +1,346 @@
+//! Several datastores queried as one virtual corpus, e.g. the yearly slices of a monitor corpus.
+//!
+//! The positions of one layer, usually the primary layer, are concatenated in member order
+//! to form the global positions of the federation. Indexed string variables of the same
+//! name are combined through a merged lexicon, which is built on first use.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::{error, fmt};
+
+use crate::components::CachedVector;
+use crate::query::{Query, QueryError};
+use crate::variables::{IndexedStringVariable, VariableValue};
+use crate::{Datastore, DatastoreError};
+
+pub struct Federation<'map> {
+    members: Vec<Datastore<'map>>,
+    layer: String,
+    /// global start position of each member, followed by the total length
+    offsets: Vec<usize>,
+    lexicons: RefCell<HashMap<String, Rc<MergedLexicon>>>,
+}
+
+impl<'map> Federation<'map> {
+    /// Federates `members` over their layer named `layer`, which all of them need to have.
+    pub fn new<S: Into<String>>(members: Vec<Datastore<'map>>, layer: S) -> Result<Self, FederationError> {
+        let layer = layer.into();
+        if members.is_empty() {
+            return Err(FederationError::Empty);
+        }
+
+        let mut offsets = vec![0];
+        for (member, datastore) in members.iter().enumerate() {
+            let len = datastore.layer_by_name(&layer)
+                .ok_or_else(|| FederationError::MissingLayer { member, layer: layer.clone() })?
+                .len();
+            offsets.push(offsets[member] + len);
+        }
+
+        Ok(Self {
+            members,
+            layer,
+            offsets,
+            lexicons: RefCell::new(HashMap::new()),
+        })
+    }
+
+    pub fn members(&self) -> &[Datastore<'map>] {
+        &self.members
+    }
+
+    pub fn layer_name(&self) -> &str {
+        &self.layer
+    }
+
+    /// Total number of positions of all members
+    pub fn len(&self) -> usize {
+        self.offsets[self.members.len()]
+    }
+
+    /// Global position of the first position of `member`
+    pub fn member_offset(&self, member: usize) -> Option<usize> {
+        (member < self.members.len()).then(|| self.offsets[member])
+    }
+
+    /// Splits a global position into the index of its member and the position within that member
+    pub fn locate(&self, position: usize) -> Option<(usize, usize)> {
+        if position >= self.len() {
+            return None;
+        }
+        // the last member starting at or before `position`, empty members are skipped
+        let member = self.offsets.partition_point(|&start| start <= position) - 1;
+        Some((member, position - self.offsets[member]))
+    }
+
+    /// Value of `variable` at global `position`
+    pub fn get(&self, variable: &str, position: usize) -> Option<VariableValue<'map>> {
+        let (member, local) = self.locate(position)?;
+        self.members[member]
+            .layer_by_name(&self.layer)?
+            .variable_by_name(variable)?
+            .get(local)
+    }
+
+    fn indexed_string(&self, member: usize, variable: &str) -> Result<&IndexedStringVariable<'map>, FederationError> {
+        self.members[member]
+            .layer_by_name(&self.layer)
+            .and_then(|layer| layer.variable_by_name(variable))
+            .and_then(|var| var.as_indexed_string())
+            .ok_or_else(|| FederationError::NotIndexedString { member, variable: variable.to_owned() })
+    }
+
+    /// Merged lexicon of the indexed string variable `variable` of all members.
+    ///
+    /// Merging reads the lexicons of all members, so the result is kept for later calls.
+    pub fn lexicon(&self, variable: &str) -> Result<Rc<MergedLexicon>, FederationError> {
+        if let Some(lexicon) = self.lexicons.borrow().get(variable) {
+            return Ok(lexicon.clone());
+        }
+
+        let variables = (0..self.members.len())
+            .map(|member| self.indexed_string(member, variable))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let merged = Rc::new(MergedLexicon::from_variables(&variables));
+        self.lexicons.borrow_mut().insert(variable.to_owned(), merged.clone());
+        Ok(merged)
+    }
+
+    /// Number of occurrences of `string` in `variable` in all members
+    pub fn frequency(&self, variable: &str, string: &str) -> Result<usize, FederationError> {
+        let mut total = 0;
+        for member in 0..self.members.len() {
+            let var = self.indexed_string(member, variable)?;
+            total += var.type_id(string)
+                .and_then(|tid| var.frequency(tid))
+                .unwrap_or(0);
+        }
+        Ok(total)
+    }
+
+    /// Frequencies of all types of `variable` in all members, indexed by their ID in the merged lexicon
+    pub fn type_frequencies(&self, variable: &str) -> Result<Vec<usize>, FederationError> {
+        let lexicon = self.lexicon(variable)?;
+        let mut frequencies = vec![0; lexicon.len()];
+
+        for (member, global_ids) in lexicon.global_ids.iter().enumerate() {
+            let var = self.indexed_string(member, variable)?;
+            for (tid, &gid) in global_ids.iter().enumerate() {
+                frequencies[gid] += var.frequency(tid).unwrap_or(0);
+            }
+        }
+
+        Ok(frequencies)
+    }
+
+    /// Global positions of `string` in `variable`, ascending
+    pub fn postings(&self, variable: &str, string: &str) -> Result<Vec<usize>, FederationError> {
+        let mut positions = Vec::new();
+        for member in 0..self.members.len() {
+            let var = self.indexed_string(member, variable)?;
+            let offset = self.offsets[member];
+
+            if let Some(postings) = var.type_id(string).and_then(|tid| var.inverted_index().positions(tid)) {
+                positions.extend(postings.map(|p| p + offset));
+            }
+        }
+        Ok(positions)
+    }
+
+    /// ID stream of `variable` in `member` with all IDs recoded into the merged lexicon,
+    /// see [`lexicon`](Self::lexicon)
+    pub fn recoded_ids(&self, variable: &str, member: usize) -> Result<RecodedIds<'map>, FederationError> {
+        let lexicon = self.lexicon(variable)?;
+        let ids = self.indexed_string(member, variable)?.id_stream();
+        Ok(RecodedIds { lexicon, member, ids })
+    }
+
+    /// Finds all matches of `query` in all members, as global start and end positions in
+    /// member order, see [`Query::find_in`].
+    ///
+    /// Every member is searched on its own, so matches never span two members. A `within`
+    /// clause refers to the segmentation layer of that name in each member.
+    pub fn find(&self, query: &Query) -> Result<Vec<(usize, usize)>, FederationError> {
+        let mut matches = Vec::new();
+        for (member, datastore) in self.members.iter().enumerate() {
+            let offset = self.offsets[member];
+            let found = query.find_in(datastore, &self.layer)
+                .map_err(|error| FederationError::Query { member, error })?;
+            matches.extend(found.into_iter().map(|(start, end)| (start + offset, end + offset)));
+        }
+        Ok(matches)
+    }
+
+    /// Runs `f` on every member in turn, with the index of the member
+    pub fn map_members<T, F>(&self, f: F) -> Vec<T>
+    where
+        F: Fn(usize, &Datastore<'map>) -> T,
+    {
+        self.members.iter()
+            .enumerate()
+            .map(|(i, datastore)| f(i, datastore))
+            .collect()
+    }
+
+    /// Runs `f` on all members in parallel, e.g. for regex queries or concordances.
+    ///
+    /// The caches of a datastore are shared through `Rc` and `RefCell`, so a [`Datastore`] can't
+    /// be sent to another thread and every worker opens its member again from its path. This
+    /// only works for members opened from a directory, and `f` does not benefit from blocks
+    /// the federation has already decoded.
+    #[cfg(feature = "parallel")]
+    pub fn par_map_members<T, F>(&self, f: F) -> Result<Vec<T>, FederationError>
+    where
+        T: Send,
+        F: Fn(usize, &Datastore) -> T + Sync,
+    {
+        use rayon::prelude::*;
+
+        let paths: Vec<_> = self.members.iter().map(|m| m.path().to_owned()).collect();
+        paths.into_par_iter()
+            .enumerate()
+            .map(|(i, path)| Ok(f(i, &Datastore::open(path)?)))
+            .collect()
+    }
+}
+
+/// Union of the lexicons of a variable in all members of a [`Federation`]
+#[derive(Debug, Default)]
+pub struct MergedLexicon {
+    types: Vec<String>,
+    ids: HashMap<String, usize>,
+    /// global ID of each local type ID, per member
+    global_ids: Vec<Vec<usize>>,
+}
+
+impl MergedLexicon {
+    /// Union of the lexicons of `variables`, with types in order of their first occurrence.
+    /// The variables take the place of the members of a federation.
+    pub fn from_variables(variables: &[&IndexedStringVariable]) -> Self {
+        let mut merged = Self::default();
+        for var in variables {
+            let global_ids = var.lexicon().iter()
+                .map(|s| merged.insert(s))
+                .collect();
+            merged.global_ids.push(global_ids);
+        }
+        merged
+    }
+
+    fn insert(&mut self, string: &str) -> usize {
+        if let Some(&id) = self.ids.get(string) {
+            return id;
+        }
+
+        let id = self.types.len();
+        self.types.push(string.to_owned());
+        self.ids.insert(string.to_owned(), id);
+        id
+    }
+
+    pub fn len(&self) -> usize {
+        self.types.len()
+    }
+
+    pub fn get(&self, id: usize) -> Option<&str> {
+        self.types.get(id).map(String::as_str)
+    }
+
+    /// Global ID of `string`
+    pub fn id(&self, string: &str) -> Option<usize> {
+        self.ids.get(string).copied()
+    }
+
+    /// Global ID of the type with lexicon ID `local` in `member`
+    pub fn global_id(&self, member: usize, local: usize) -> Option<usize> {
+        self.global_ids.get(member)?.get(local).copied()
+    }
+
+    /// Global IDs of all types of `member`, indexed by their lexicon ID in the member
+    pub fn mapping(&self, member: usize) -> Option<&[usize]> {
+        self.global_ids.get(member).map(Vec::as_slice)
+    }
+}
+
+/// ID stream of an indexed string variable viewed through a [`MergedLexicon`].
+///
+/// IDs are recoded as they are decoded, the stream itself is not copied.
+#[derive(Debug, Clone)]
+pub struct RecodedIds<'map> {
+    lexicon: Rc<MergedLexicon>,
+    member: usize,
+    ids: CachedVector<'map, 1>,
+}
+
+impl<'map> RecodedIds<'map> {
+    /// View of the ID stream of `variable`, which is member `member` of `lexicon`
+    pub fn new(lexicon: Rc<MergedLexicon>, member: usize, variable: &IndexedStringVariable<'map>) -> Option<Self> {
+        (lexicon.mapping(member)?.len() == variable.n_types())
+            .then(|| Self { lexicon, member, ids: variable.id_stream() })
+    }
+
+    pub fn lexicon(&self) -> &MergedLexicon {
+        &self.lexicon
+    }
+
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    /// Global ID at `position`
+    pub fn get(&self, position: usize) -> Option<usize> {
+        if position < self.ids.len() {
+            let local = self.ids.get_row_unchecked(position)[0] as usize;
+            self.lexicon.global_id(self.member, local)
+        } else {
+            None
+        }
+    }
+
+    /// Global IDs at positions `start..end`, `None` if the range is out of bounds
+    pub fn iter_range(&self, start: usize, end: usize) -> Option<Box<dyn Iterator<Item = usize> + '_>> {
+        let mapping = self.lexicon.mapping(self.member)?;
+        let ids = self.ids.column_iter_range(start, end, 0)?;
+        Some(Box::new(ids.map(move |id| mapping[id as usize])))
+    }
+}
+
+#[derive(Debug)]
+pub enum FederationError {
+    Empty,
+    MissingLayer { member: usize, layer: String },
+    NotIndexedString { member: usize, variable: String },
+    Datastore(DatastoreError),
+    /// The query failed on member `member`, e.g. because it lacks a queried variable
+    Query { member: usize, error: QueryError },
+}
+
+impl fmt::Display for FederationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Empty => write!(f, "federation without members"),
+            Self::MissingLayer { member, layer } => write!(f, "member {} has no layer {}", member, layer),
+            Self::NotIndexedString { member, variable } => write!(f, "member {} has no indexed string variable {}", member, variable),
+            Self::Datastore(e) => write!(f, "could not open member: {}", e),
+            Self::Query { member, error } => write!(f, "query failed on member {}: {}", member, error),
+        }
+    }
+}
+
+impl error::Error for FederationError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::Datastore(e) => Some(e),
+            Self::Query { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<DatastoreError> for FederationError {
+    fn from(e: DatastoreError) -> Self {
+        Self::Datastore(e)
+    }
+}
@@ -0,0 +1,203 @@
+//! Conjunctions of constraints on several variables at a single position, e.g. a word
+//! regex, a set of part-of-speech tags and a range of an integer variable.
+//!
+//! Unlike [`Query`](crate::query::Query), conditions are not restricted to regexes. The
+//! positions are found by estimating the number of positions each condition matches from
+//! the lexicon frequencies and the integer indices, seeding candidates from the most
+//! selective condition that has an index and checking the other conditions at each
+//! candidate, most selective first, so that most candidates are rejected by a single
+//! lookup.
+//!
+//! ```no_run
+//! # use etemenanki::{filter::{Condition, TokenFilter}, Datastore};
+//! let datastore = Datastore::open("dickens").unwrap();
+//! let filter = TokenFilter::new()
+//!     .with("word", Condition::Regex("[a-z]+ly".to_owned()))
+//!     .with("pos", Condition::OneOf(vec!["RB".to_owned(), "JJ".to_owned()]))
+//!     .without("lemma", Condition::Regex("only".to_owned()));
+//! let positions = filter.find(&datastore["primary"]).unwrap();
+//! ```
+
+use std::collections::HashSet;
+
+use regex::Regex;
+
+use crate::components::RegexFlags;
+use crate::layers::Layer;
+use crate::query::QueryError;
+use crate::variables::{IndexedStringVariable, IntegerVariable, Variable};
+
+/// Condition on the value of a variable at one position
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Condition {
+    /// String matching the regex as a whole
+    Regex(String),
+    /// String equal to one of these
+    OneOf(Vec<String>),
+    /// Integer in `min..=max`
+    Range(i64, i64),
+}
+
+/// Conjunction of conditions on the variables of a layer
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct TokenFilter {
+    /// Variable name, condition and whether it is negated
+    conditions: Vec<(String, Condition, bool)>,
+}
+
+/// How [`TokenFilter::find`] evaluates a filter on a layer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FilterPlan {
+    /// Estimated number of matching positions of each condition, in the order they were added
+    pub estimates: Vec<usize>,
+    /// Condition whose positions are the candidates, `None` to check every position
+    pub seed: Option<usize>,
+    /// Conditions checked at each candidate, in this order
+    pub checks: Vec<usize>,
+}
+
+impl TokenFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a condition that must hold
+    pub fn with(mut self, variable: &str, condition: Condition) -> Self {
+        self.conditions.push((variable.to_owned(), condition, false));
+        self
+    }
+
+    /// Adds a condition that must not hold
+    pub fn without(mut self, variable: &str, condition: Condition) -> Self {
+        self.conditions.push((variable.to_owned(), condition, true));
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.conditions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.conditions.is_empty()
+    }
+
+    fn compile<'a, 'map>(&self, layer: &'a Layer<'map>) -> Result<Vec<Check<'a, 'map>>, QueryError> {
+        self.conditions.iter()
+            .map(|(variable, condition, negated)| Check::new(layer, variable, condition, *negated))
+            .collect()
+    }
+
+    fn plan_checks(checks: &[Check], len: usize) -> FilterPlan {
+        let estimates: Vec<usize> = checks.iter().map(|c| c.estimate(len)).collect();
+        let seed = (0..checks.len())
+            .filter(|i| checks[*i].seedable())
+            .min_by_key(|i| estimates[*i])
+            .filter(|i| estimates[*i] < len);
+
+        let mut order: Vec<usize> = (0..checks.len()).filter(|i| Some(*i) != seed).collect();
+        order.sort_by_key(|i| estimates[*i]);
+
+        FilterPlan { estimates, seed, checks: order }
+    }
+
+    /// Estimates the selectivity of each condition on `layer` and picks the evaluation order
+    pub fn plan(&self, layer: &Layer) -> Result<FilterPlan, QueryError> {
+        Ok(Self::plan_checks(&self.compile(layer)?, layer.len()))
+    }
+
+    /// Positions of `layer` at which all conditions hold, ascending
+    pub fn find(&self, layer: &Layer) -> Result<Vec<usize>, QueryError> {
+        let checks = self.compile(layer)?;
+        let plan = Self::plan_checks(&checks, layer.len());
+        let is_match = |position: &usize| plan.checks.iter().all(|i| checks[*i].is_match(*position));
+
+        Ok(match plan.seed {
+            Some(seed) => checks[seed].positions().into_iter().filter(is_match).collect(),
+            None => (0..layer.len()).filter(is_match).collect(),
+        })
+    }
+}
+
+/// Compiled condition, string conditions on indexed variables are matched on lexicon IDs
+enum Check<'a, 'map> {
+    Types { var: &'a IndexedStringVariable<'map>, types: Vec<bool>, negated: bool },
+    Regex { var: &'a Variable<'map>, regex: Regex, negated: bool },
+    Strings { var: &'a Variable<'map>, strings: HashSet<String>, negated: bool },
+    Range { var: &'a IntegerVariable<'map>, min: i64, max: i64, negated: bool },
+}
+
+impl<'a, 'map> Check<'a, 'map> {
+    fn new(layer: &'a Layer<'map>, name: &str, condition: &Condition, negated: bool) -> Result<Self, QueryError> {
+        let var = layer.variable_by_name(name)
+            .ok_or_else(|| QueryError::UnknownVariable(name.to_owned()))?;
+
+        Ok(match (var, condition) {
+            (Variable::IndexedString(var), Condition::Regex(regex)) => {
+                let regex = RegexFlags::default().build(regex)?;
+                let types = var.lexicon().iter().map(|s| regex.is_match(s)).collect();
+                Self::Types { var, types, negated }
+            }
+            (Variable::IndexedString(var), Condition::OneOf(strings)) => {
+                let mut types = vec![false; var.n_types()];
+                for tid in strings.iter().filter_map(|s| var.type_id(s)) {
+                    types[tid] = true;
+                }
+                Self::Types { var, types, negated }
+            }
+            (Variable::PlainString(_) | Variable::Sparse(_), Condition::Regex(regex)) => {
+                Self::Regex { var, regex: RegexFlags::default().build(regex)?, negated }
+            }
+            (Variable::PlainString(_) | Variable::Sparse(_), Condition::OneOf(strings)) => {
+                Self::Strings { var, strings: strings.iter().cloned().collect(), negated }
+            }
+            (Variable::Integer(var), &Condition::Range(min, max)) => Self::Range { var, min, max, negated },
+            _ => return Err(QueryError::UnsupportedVariable(name.to_owned())),
+        })
+    }
+
+    fn is_match(&self, position: usize) -> bool {
+        match self {
+            Self::Types { var, types, negated } => var.get_id(position).map_or(false, |t| types[t]) != *negated,
+            Self::Regex { var, regex, negated } => var.get_string(position).map_or(false, |s| regex.is_match(s)) != *negated,
+            Self::Strings { var, strings, negated } => var.get_string(position).map_or(false, |s| strings.contains(s)) != *negated,
+            Self::Range { var, min, max, negated } => var.get(position).map_or(false, |v| (*min..=*max).contains(&v)) != *negated,
+        }
+    }
+
+    /// Estimated number of matching positions among `len`, `len` if unknown
+    fn estimate(&self, len: usize) -> usize {
+        let (matching, negated) = match self {
+            Self::Types { var, types, negated } => {
+                let count = (0..types.len())
+                    .filter(|t| types[*t])
+                    .map(|t| var.frequency(t).unwrap_or(0))
+                    .sum();
+                (count, *negated)
+            }
+            Self::Range { var, min, max, negated } => (var.estimate_range(*min, *max), *negated),
+            Self::Regex { .. } | Self::Strings { .. } => return len,
+        };
+        if negated {
+            len.saturating_sub(matching)
+        } else {
+            matching.min(len)
+        }
+    }
+
+    /// Whether the matching positions can be read from an index
+    fn seedable(&self) -> bool {
+        matches!(self, Self::Types { negated: false, .. } | Self::Range { negated: false, .. })
+    }
+
+    /// Matching positions of a seedable check, ascending
+    fn positions(&self) -> Vec<usize> {
+        match self {
+            Self::Types { var, types, .. } => {
+                let type_ids: Vec<usize> = (0..types.len()).filter(|t| types[*t]).collect();
+                var.inverted_index().merged_postings(&type_ids, false).collect()
+            }
+            Self::Range { var, min, max, .. } => var.positions_in_range(*min, *max),
+            _ => unreachable!("only seedable checks have positions"),
+        }
+    }
+}
@@ -0,0 +1,466 @@
+//! Frequency lists of the values of a variable, over a whole layer or over the parts of it
+//! selected by metadata filters like `text.genre=fiction`.
+//!
+//! ```no_run
+//! # use etemenanki::{frequency::{FrequencyList, MetadataFilter}, Datastore};
+//! let datastore = Datastore::open("dickens").unwrap();
+//! let primary = &datastore["primary"];
+//!
+//! let filter = MetadataFilter::parse("novel.title=Oliver Twist").unwrap();
+//! let ranges = filter.ranges(&datastore, primary).unwrap();
+//! let positions = ranges.iter().flat_map(|&(start, end)| start..end);
+//! let lemmas = FrequencyList::from_positions(&primary["lemma"], positions).unwrap();
+//! ```
+//!
+//! [`counts_per_segment`] instead counts a fixed set of types in every segment of a
+//! segmentation layer at once, e.g. as the features of each text of a corpus.
+
+use std::collections::HashMap;
+use std::{error, fmt};
+
+use regex::Regex;
+
+use crate::group::Key;
+use crate::layers::{Layer, LayerData, SegmentationLayer};
+use crate::variables::{IndexedStringVariable, Variable, VariableValue};
+use crate::Datastore;
+
+#[derive(Debug)]
+pub enum FrequencyError {
+    /// Filters are written as `<layer>.<variable>=<regex>` or with `!=`
+    InvalidFilter(String),
+    UnknownLayer(String),
+    UnknownVariable(String),
+    /// External pointer and hash variables
+    UnsupportedType(&'static str),
+    NotSegmentation(String),
+    /// The filter layer neither is nor annotates the counted layer
+    UnrelatedLayers { filter: String, layer: String },
+    Regex(regex::Error),
+}
+
+impl fmt::Display for FrequencyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidFilter(s) => write!(f, "invalid filter {}, expected <layer>.<variable>=<regex>", s),
+            Self::UnknownLayer(name) => write!(f, "no layer named {}", name),
+            Self::UnknownVariable(name) => write!(f, "no variable named {}", name),
+            Self::UnsupportedType(t) => write!(f, "{} variables can not be counted", t),
+            Self::NotSegmentation(name) => write!(f, "layer {} is not a segmentation layer", name),
+            Self::UnrelatedLayers { filter, layer } => write!(f, "filter layer {} does not annotate layer {}", filter, layer),
+            Self::Regex(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl error::Error for FrequencyError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::Regex(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<regex::Error> for FrequencyError {
+    fn from(e: regex::Error) -> Self {
+        Self::Regex(e)
+    }
+}
+
+/// Values of a variable with their number of occurrences, most frequent first
+#[derive(Debug, Clone)]
+pub struct FrequencyList<'map> {
+    entries: Vec<(VariableValue<'map>, usize)>,
+    total: usize,
+}
+
+/// Counts keys in order of their first occurrence
+#[derive(Default)]
+struct Counter<'map> {
+    index: HashMap<Key<'map>, usize>,
+    counts: Vec<(Key<'map>, usize)>,
+    total: usize,
+}
+
+impl<'map> Counter<'map> {
+    fn add(&mut self, value: Option<VariableValue<'map>>) {
+        let Self { index, counts, total } = self;
+        *total += 1;
+        if let Some(value) = value {
+            Key::each(value, |key| {
+                let i = *index.entry(key).or_insert_with(|| {
+                    counts.push((key, 0));
+                    counts.len() - 1
+                });
+                counts[i].1 += 1;
+            });
+        }
+    }
+
+    fn into_list(self) -> FrequencyList<'map> {
+        let entries = self.counts.into_iter().map(|(key, n)| (key.into(), n)).collect();
+        FrequencyList::from_entries(entries, self.total)
+    }
+}
+
+fn check_variable<'a, 'map>(variable: &'a Variable<'map>) -> Result<&'a Variable<'map>, FrequencyError> {
+    match variable {
+        Variable::ExternalPointer(_) => Err(FrequencyError::UnsupportedType("external pointer")),
+        Variable::Hash(_) => Err(FrequencyError::UnsupportedType("hash")),
+        var => Ok(var),
+    }
+}
+
+impl<'map> FrequencyList<'map> {
+    fn from_entries(mut entries: Vec<(VariableValue<'map>, usize)>, total: usize) -> Self {
+        // stable, so equally frequent values stay in lexicon or corpus order
+        entries.sort_by(|a, b| b.1.cmp(&a.1));
+        Self { entries, total }
+    }
+
+    /// Counts all values of `variable`.
+    ///
+    /// Indexed string variables are counted from their type frequencies without decoding
+    /// the token stream.
+    pub fn new(variable: &Variable<'map>) -> Result<Self, FrequencyError> {
+        match check_variable(variable)? {
+            Variable::IndexedString(var) => {
+                let entries = (0..var.n_types())
+                    .map(|t| (VariableValue::String(var.lexicon().get_unchecked(t)), var.frequency(t).unwrap_or(0)))
+                    .filter(|(_, n)| *n > 0)
+                    .collect();
+                Ok(Self::from_entries(entries, var.len()))
+            }
+            var => Self::from_positions(var, 0..var.len()),
+        }
+    }
+
+    /// Counts the values of `variable` at `positions`. Every item of a set is counted.
+    pub fn from_positions<I>(variable: &Variable<'map>, positions: I) -> Result<Self, FrequencyError>
+    where
+        I: IntoIterator<Item = usize>,
+    {
+        match check_variable(variable)? {
+            Variable::IndexedString(var) => {
+                let mut counts = vec![0; var.n_types()];
+                let mut total = 0;
+                for id in positions.into_iter().filter_map(|p| var.get_id(p)) {
+                    counts[id] += 1;
+                    total += 1;
+                }
+                let entries = counts.into_iter()
+                    .enumerate()
+                    .filter(|(_, n)| *n > 0)
+                    .map(|(t, n)| (VariableValue::String(var.lexicon().get_unchecked(t)), n))
+                    .collect();
+                Ok(Self::from_entries(entries, total))
+            }
+            var => {
+                let mut counter = Counter::default();
+                for position in positions.into_iter().filter(|p| *p < var.len()) {
+                    counter.add(var.get(position));
+                }
+                Ok(counter.into_list())
+            }
+        }
+    }
+
+    /// One frequency list of `variable` per value of `group` of the segment containing the
+    /// positions, largest groups first. Positions outside of all segments are not counted.
+    ///
+    /// Positions should be sorted, see [`GroupCounts::new`](crate::group::GroupCounts::new).
+    pub fn grouped<I>(variable: &Variable<'map>, segmentation: &LayerData<'map, SegmentationLayer<'map>>, group: &str, positions: I) -> Result<Vec<(VariableValue<'map>, Self)>, FrequencyError>
+    where
+        I: IntoIterator<Item = usize>,
+    {
+        let variable = check_variable(variable)?;
+        let group_var = match segmentation.variable_by_name(group) {
+            Some(var) => check_variable(var)?,
+            None => return Err(FrequencyError::UnknownVariable(group.to_owned())),
+        };
+
+        let positions: Vec<usize> = positions.into_iter().collect();
+        let mut index: HashMap<Key<'map>, usize> = HashMap::new();
+        let mut groups: Vec<(Key<'map>, Counter<'map>)> = Vec::new();
+        let mut current: Option<(usize, Vec<usize>)> = None;
+
+        for (&position, segment) in positions.iter().zip(segmentation.find_containing_iter(positions.iter().copied())) {
+            let Some(segment) = segment else { continue };
+
+            // group indices of the segment, only looked up when the segment changes
+            if current.as_ref().map(|(s, _)| *s) != Some(segment) {
+                let mut members = Vec::new();
+                if let Some(value) = group_var.get(segment) {
+                    Key::each(value, |key| {
+                        members.push(*index.entry(key).or_insert_with(|| {
+                            groups.push((key, Counter::default()));
+                            groups.len() - 1
+                        }));
+                    });
+                }
+                current = Some((segment, members));
+            }
+
+            let (_, members) = current.as_ref().expect("set above");
+            for &g in members {
+                groups[g].1.add(variable.get(position));
+            }
+        }
+
+        let mut lists: Vec<_> = groups.into_iter()
+            .map(|(key, counter)| (key.into(), counter.into_list()))
+            .collect();
+        lists.sort_by(|a, b| b.1.total.cmp(&a.1.total));
+        Ok(lists)
+    }
+
+    /// All values, most frequent first
+    pub fn entries(&self) -> &[(VariableValue<'map>, usize)] {
+        &self.entries
+    }
+
+    /// The `n` most frequent values
+    pub fn top(&self, n: usize) -> &[(VariableValue<'map>, usize)] {
+        &self.entries[..n.min(self.entries.len())]
+    }
+
+    /// Number of occurrences of `value`
+    pub fn get(&self, value: &VariableValue) -> usize {
+        self.entries.iter()
+            .find(|(v, _)| v == value)
+            .map_or(0, |(_, n)| *n)
+    }
+
+    /// Number of distinct values
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Number of positions counted
+    pub fn total(&self) -> usize {
+        self.total
+    }
+}
+
+/// Matrix of the number of occurrences of some types in each segment of a segmentation
+/// layer, with one row per segment and one column per type
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SegmentCounts {
+    n_segments: usize,
+    type_ids: Vec<usize>,
+    /// Row-major
+    counts: Vec<usize>,
+}
+
+impl SegmentCounts {
+    pub fn n_segments(&self) -> usize {
+        self.n_segments
+    }
+
+    /// Type IDs of the columns
+    pub fn type_ids(&self) -> &[usize] {
+        &self.type_ids
+    }
+
+    /// Number of occurrences of the type of `column` in `segment`
+    pub fn get(&self, segment: usize, column: usize) -> Option<usize> {
+        if segment < self.n_segments && column < self.type_ids.len() {
+            Some(self.counts[segment * self.type_ids.len() + column])
+        } else {
+            None
+        }
+    }
+
+    /// Counts of all columns in `segment`
+    pub fn row(&self, segment: usize) -> Option<&[usize]> {
+        let width = self.type_ids.len();
+        (segment < self.n_segments).then(|| &self.counts[segment * width..(segment + 1) * width])
+    }
+
+    pub fn rows(&self) -> impl Iterator<Item = &[usize]> + '_ {
+        (0..self.n_segments).map(|segment| self.row(segment).expect("segment in bounds"))
+    }
+
+    /// Counts of each column over all segments
+    pub fn column_totals(&self) -> Vec<usize> {
+        let mut totals = vec![0; self.type_ids.len()];
+        for row in self.rows() {
+            totals.iter_mut().zip(row).for_each(|(total, n)| *total += n);
+        }
+        totals
+    }
+
+    /// All counts in row-major order
+    pub fn as_slice(&self) -> &[usize] {
+        &self.counts
+    }
+}
+
+/// Counts the occurrences of each of `type_ids` in each segment of `segmentation`, which
+/// must be based on the layer of `variable`.
+///
+/// Type IDs should be distinct, a repeated type is only counted in its last column, and
+/// IDs beyond the lexicon are never counted.
+pub fn counts_per_segment(variable: &IndexedStringVariable, segmentation: &SegmentationLayer, type_ids: &[usize]) -> Result<SegmentCounts, FrequencyError> {
+    let width = type_ids.len();
+    let n_segments = segmentation.len();
+    let mut counts = vec![0; n_segments * width];
+
+    // column of each type ID, usize::MAX for types that are not counted
+    let mut columns = vec![usize::MAX; variable.n_types()];
+    for (column, &type_id) in type_ids.iter().enumerate() {
+        if let Some(c) = columns.get_mut(type_id) {
+            *c = column;
+        }
+    }
+
+    if width > 0 {
+        for_each_in_segments(variable, segmentation, |segment, id| {
+            if let Some(&column) = columns.get(id).filter(|c| **c != usize::MAX) {
+                counts[segment * width + column] += 1;
+            }
+        })?;
+    } else {
+        check_base(variable, segmentation)?;
+    }
+
+    Ok(SegmentCounts { n_segments, type_ids: type_ids.to_vec(), counts })
+}
+
+pub(crate) fn check_base(variable: &IndexedStringVariable, segmentation: &SegmentationLayer) -> Result<(), FrequencyError> {
+    if segmentation.base == variable.base() {
+        Ok(())
+    } else {
+        Err(FrequencyError::UnrelatedLayers {
+            filter: segmentation.name.clone(),
+            layer: variable.base().to_string(),
+        })
+    }
+}
+
+/// Calls `f` with the segment and type ID of each position of `variable` inside a segment
+/// of `segmentation`, in ascending order.
+///
+/// The ID stream is decoded in a single pass from the start of the first to the end of the
+/// last segment.
+pub(crate) fn for_each_in_segments<F>(variable: &IndexedStringVariable, segmentation: &SegmentationLayer, mut f: F) -> Result<(), FrequencyError>
+where
+    F: FnMut(usize, usize),
+{
+    check_base(variable, segmentation)?;
+
+    let n_segments = segmentation.len();
+    let first = segmentation.get(0).map_or(0, |(start, _)| start).min(variable.len());
+    let last = n_segments.checked_sub(1)
+        .and_then(|i| segmentation.get(i))
+        .map_or(0, |(_, end)| end)
+        .min(variable.len());
+    if first >= last {
+        return Ok(());
+    }
+
+    let ids = variable.id_stream();
+    let mut segments = segmentation.iter().enumerate().peekable();
+    for (position, id) in (first..last).zip(ids.column_iter_range(first, last, 0).expect("range within the ID stream")) {
+        while segments.next_if(|(_, (_, end))| *end <= position).is_some() {}
+        let Some(&(segment, (start, _))) = segments.peek() else { break };
+        if start <= position {
+            f(segment, id as usize);
+        }
+    }
+
+    Ok(())
+}
+
+/// Selects the segments of a segmentation layer by the value of one of its variables
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MetadataFilter {
+    pub layer: String,
+    pub variable: String,
+    pub regex: String,
+    pub negated: bool,
+}
+
+impl MetadataFilter {
+    /// Parses `<layer>.<variable>=<regex>` or `<layer>.<variable>!=<regex>`
+    pub fn parse(expr: &str) -> Result<Self, FrequencyError> {
+        let invalid = || FrequencyError::InvalidFilter(expr.to_owned());
+
+        let (name, regex) = expr.split_once('=').ok_or_else(invalid)?;
+        let (name, negated) = match name.strip_suffix('!') {
+            Some(name) => (name, true),
+            None => (name, false),
+        };
+        let (layer, variable) = name.split_once('.').ok_or_else(invalid)?;
+        if layer.is_empty() || variable.is_empty() {
+            return Err(invalid());
+        }
+
+        Ok(Self {
+            layer: layer.to_owned(),
+            variable: variable.to_owned(),
+            regex: regex.to_owned(),
+            negated,
+        })
+    }
+
+    /// Whether the value matches, sets match if any of their items does
+    fn is_match(&self, regex: &Regex, value: Option<VariableValue>) -> bool {
+        let matched = match value {
+            Some(VariableValue::Set(items)) => items.iter().any(|item| regex.is_match(item)),
+            Some(VariableValue::Missing) | None => false,
+            Some(value) => regex.is_match(&value.to_string()),
+        };
+        matched != self.negated
+    }
+
+    /// Ranges of `layer` selected by the filter, in ascending order.
+    ///
+    /// If the filter layer is `layer` itself these are single segments, otherwise the filter
+    /// layer must have `layer` as its base and the ranges are the matching segments.
+    pub fn ranges<'map>(&self, datastore: &Datastore<'map>, layer: &Layer<'map>) -> Result<Vec<(usize, usize)>, FrequencyError> {
+        let filter_layer = datastore.layer_by_name(&self.layer)
+            .ok_or_else(|| FrequencyError::UnknownLayer(self.layer.clone()))?;
+        let segmentation = filter_layer.as_segmentation()
+            .ok_or_else(|| FrequencyError::NotSegmentation(self.layer.clone()))?;
+        let variable = segmentation.variable_by_name(&self.variable)
+            .ok_or_else(|| FrequencyError::UnknownVariable(self.variable.clone()))?;
+        let regex = Regex::new(&format!("^(?:{})$", self.regex))?;
+
+        let selected = (0..segmentation.len()).filter(|i| self.is_match(&regex, variable.get(*i)));
+
+        if filter_layer.uuid() == layer.uuid() {
+            Ok(selected.map(|i| (i, i + 1)).collect())
+        } else if filter_layer.base() == Some(layer.uuid()) {
+            Ok(selected.map(|i| segmentation.get_unchecked(i)).collect())
+        } else {
+            let name = datastore.layer_names().find(|n| datastore[n.as_str()].uuid() == layer.uuid());
+            Err(FrequencyError::UnrelatedLayers {
+                filter: self.layer.clone(),
+                layer: name.cloned().unwrap_or_else(|| layer.uuid().to_string()),
+            })
+        }
+    }
+}
+
+/// Intersection of two ascending lists of non-overlapping ranges
+pub fn intersect_ranges(a: &[(usize, usize)], b: &[(usize, usize)]) -> Vec<(usize, usize)> {
+    let mut result = Vec::new();
+    let (mut i, mut j) = (0, 0);
+
+    while i < a.len() && j < b.len() {
+        let start = a[i].0.max(b[j].0);
+        let end = a[i].1.min(b[j].1);
+        if start < end {
+            result.push((start, end));
+        }
+        if a[i].1 < b[j].1 {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+
+    result
+}
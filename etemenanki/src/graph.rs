@@ -0,0 +1,171 @@
+//! Dependency graph of the containers in a datastore directory, rendered as DOT or JSON.
+//!
+//! The graph is read from the container headers alone, without the consistency checks of
+//! [`Datastore::open`](crate::Datastore::open). Datastores that fail to open can still be
+//! inspected, bases that are not part of the datastore show up as missing nodes.
+//!
+//! ```no_run
+//! # use etemenanki::graph::DependencyGraph;
+//! let graph = DependencyGraph::open("dickens").unwrap();
+//! std::fs::write("dickens.dot", graph.to_dot()).unwrap();
+//! ```
+
+use std::collections::HashSet;
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::container::Container;
+use crate::manifest::Manifest;
+use crate::storage;
+use crate::DatastoreError;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DependencyGraph {
+    pub nodes: Vec<Node>,
+    pub edges: Vec<Edge>,
+}
+
+/// A container of the datastore
+#[derive(Debug, Clone, Serialize)]
+pub struct Node {
+    pub uuid: Uuid,
+    pub name: String,
+    /// Path relative to the datastore root
+    pub path: PathBuf,
+    /// Raw type code from the header, e.g. `ZLs`
+    pub type_code: String,
+    /// Name of the container type, `None` for types unknown to this library
+    pub container_type: Option<String>,
+    pub dim1: usize,
+    pub dim2: usize,
+    /// File size in bytes
+    pub size: u64,
+}
+
+/// Which base UUID of the header an edge comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BaseSlot {
+    Base1,
+    Base2,
+}
+
+/// Dependency of a container on its base
+#[derive(Debug, Clone, Serialize)]
+pub struct Edge {
+    pub from: Uuid,
+    pub to: Uuid,
+    pub slot: BaseSlot,
+    /// The base is not one of the nodes
+    pub missing: bool,
+}
+
+impl DependencyGraph {
+    /// Reads the headers of all containers of the datastore at `path`.
+    ///
+    /// Like `Datastore::open` this uses the manifest if there is one and scans the
+    /// directory otherwise.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, DatastoreError> {
+        let path = path.as_ref();
+        let manifest = match Manifest::read(path)? {
+            Some(manifest) => manifest,
+            None => Manifest::scan(path)?,
+        };
+
+        let mut nodes = Vec::with_capacity(manifest.containers.len());
+        let mut bases = Vec::new();
+
+        for entry in manifest.containers {
+            let file = path.join(&entry.path);
+            let size = fs::metadata(&file)?.len();
+            let container = Container::from_storage(storage::open_file(&file)?, entry.name.clone())?;
+            let header = container.header();
+
+            bases.extend(header.base1().map(|b| (header.uuid(), b, BaseSlot::Base1)));
+            bases.extend(header.base2().map(|b| (header.uuid(), b, BaseSlot::Base2)));
+
+            nodes.push(Node {
+                uuid: header.uuid(),
+                name: entry.name,
+                path: entry.path,
+                type_code: String::from_utf8_lossy(&header.type_code()).into_owned(),
+                container_type: header.try_container_type().ok().map(|t| format!("{:?}", t)),
+                dim1: header.dim1(),
+                dim2: header.dim2(),
+                size,
+            });
+        }
+
+        let known: HashSet<Uuid> = nodes.iter().map(|n| n.uuid).collect();
+        let edges = bases.into_iter()
+            .map(|(from, to, slot)| Edge { from, to, slot, missing: !known.contains(&to) })
+            .collect();
+
+        Ok(Self { nodes, edges })
+    }
+
+    pub fn node(&self, uuid: Uuid) -> Option<&Node> {
+        self.nodes.iter().find(|n| n.uuid == uuid)
+    }
+
+    /// Edges whose base is not part of the datastore
+    pub fn missing(&self) -> impl Iterator<Item = &Edge> {
+        self.edges.iter().filter(|e| e.missing)
+    }
+
+    /// Renders the graph in the DOT language of Graphviz.
+    ///
+    /// Layers are drawn as boxes and variables as ellipses, edges point from a container
+    /// to its base. Missing bases are drawn dashed and red.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph datastore {\n    rankdir=BT;\n");
+
+        for node in self.nodes.iter() {
+            let shape = if node.type_code.as_bytes().get(1) == Some(&b'L') { "box" } else { "ellipse" };
+            let label = format!(
+                "{}\n{} {}\n{} x {}, {} bytes",
+                node.name,
+                node.type_code,
+                node.container_type.as_deref().unwrap_or("unknown"),
+                node.dim1,
+                node.dim2,
+                node.size,
+            );
+            writeln!(dot, "    \"{}\" [label=\"{}\", shape={}];", node.uuid, escape(&label), shape).unwrap();
+        }
+
+        let mut missing = HashSet::new();
+        for edge in self.missing() {
+            if missing.insert(edge.to) {
+                writeln!(dot, "    \"{}\" [label=\"missing\\n{}\", style=dashed, color=red];", edge.to, edge.to).unwrap();
+            }
+        }
+
+        for edge in self.edges.iter() {
+            let style = if edge.missing { ", style=dashed, color=red" } else { "" };
+            let label = match edge.slot {
+                BaseSlot::Base1 => "base1",
+                BaseSlot::Base2 => "base2",
+            };
+            writeln!(dot, "    \"{}\" -> \"{}\" [label=\"{}\"{}];", edge.from, edge.to, label, style).unwrap();
+        }
+
+        dot.push_str("}\n");
+        dot
+    }
+
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(self)
+    }
+}
+
+/// Escapes a string for a quoted DOT id, keeping newlines as line breaks
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
@@ -0,0 +1,128 @@
+//! Frequency breakdowns of query results by a variable of a segmentation layer, e.g. year or genre.
+//!
+//! The positions are joined with the ranges of the segmentation in a single pass, see
+//! [`SegmentationLayer::find_containing_iter`], so only the values of segments which
+//! contain at least one position are decoded.
+
+use std::collections::HashMap;
+
+use crate::layers::{LayerData, SegmentationLayer};
+use crate::selection::SelectionError;
+use crate::variables::{Variable, VariableValue};
+
+/// Hashable form of the single valued [`VariableValue`]s a group can have
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum Key<'map> {
+    String(&'map str),
+    Integer(i64),
+    Pointer(Option<usize>),
+}
+
+impl<'map> Key<'map> {
+    /// Calls `f` with the keys of `value`, once for every item of a set and not at all
+    /// for missing values and empty sets. Returns whether there was a key.
+    pub(crate) fn each(value: VariableValue<'map>, mut f: impl FnMut(Key<'map>)) -> bool {
+        match value {
+            VariableValue::String(s) => f(Key::String(s)),
+            VariableValue::Integer(i) => f(Key::Integer(i)),
+            VariableValue::Pointer(p) => f(Key::Pointer(p)),
+            VariableValue::Set(items) if !items.is_empty() => items.into_iter().for_each(|item| f(Key::String(item))),
+            _ => return false,
+        }
+        true
+    }
+}
+
+impl<'map> From<Key<'map>> for VariableValue<'map> {
+    fn from(key: Key<'map>) -> Self {
+        match key {
+            Key::String(s) => VariableValue::String(s),
+            Key::Integer(i) => VariableValue::Integer(i),
+            Key::Pointer(p) => VariableValue::Pointer(p),
+        }
+    }
+}
+
+/// Number of positions per value of a segmentation variable
+#[derive(Debug, Clone)]
+pub struct GroupCounts<'map> {
+    groups: Vec<(VariableValue<'map>, usize)>,
+    ungrouped: usize,
+    total: usize,
+}
+
+impl<'map> GroupCounts<'map> {
+    /// Counts `positions` by the value of `variable` of the segment containing them.
+    ///
+    /// Positions should be sorted, e.g. the postings of a type, otherwise each out of order
+    /// position costs an index lookup. For set variables a position is counted once for every
+    /// item of its segment.
+    pub fn new<I>(segmentation: &LayerData<'map, SegmentationLayer<'map>>, variable: &str, positions: I) -> Result<Self, SelectionError>
+    where
+        I: IntoIterator<Item = usize>,
+    {
+        let var = match segmentation.variable_by_name(variable) {
+            Some(Variable::ExternalPointer(_) | Variable::Hash(_)) => return Err(SelectionError::UnsupportedVariable(variable.to_owned())),
+            Some(var) => var,
+            None => return Err(SelectionError::UnknownVariable(variable.to_owned())),
+        };
+
+        // consecutive positions in the same segment are merged into one entry
+        let mut segments: Vec<(usize, usize)> = Vec::new();
+        let mut ungrouped = 0;
+        let mut total = 0;
+
+        for segment in segmentation.find_containing_iter(positions) {
+            total += 1;
+            match (segment, segments.last_mut()) {
+                (Some(i), Some((last, n))) if *last == i => *n += 1,
+                (Some(i), _) => segments.push((i, 1)),
+                (None, _) => ungrouped += 1,
+            }
+        }
+
+        let mut keys: HashMap<Key<'map>, usize> = HashMap::new();
+        let mut groups: Vec<(VariableValue<'map>, usize)> = Vec::new();
+        let mut add = |key: Key<'map>, n: usize| {
+            let i = *keys.entry(key).or_insert_with(|| {
+                groups.push((key.into(), 0));
+                groups.len() - 1
+            });
+            groups[i].1 += n;
+        };
+
+        for (segment, n) in segments {
+            let grouped = var.get(segment).map_or(false, |value| Key::each(value, |key| add(key, n)));
+            if !grouped {
+                ungrouped += n;
+            }
+        }
+
+        // stable, so groups of equal size stay in order of their first position
+        groups.sort_by(|a, b| b.1.cmp(&a.1));
+
+        Ok(Self { groups, ungrouped, total })
+    }
+
+    /// All groups with at least one position, most frequent first
+    pub fn groups(&self) -> &[(VariableValue<'map>, usize)] {
+        &self.groups
+    }
+
+    /// Number of positions in the group `value`
+    pub fn get(&self, value: &VariableValue) -> usize {
+        self.groups.iter()
+            .find(|(v, _)| v == value)
+            .map_or(0, |(_, n)| *n)
+    }
+
+    /// Number of positions outside of all segments or in segments with an empty set
+    pub fn ungrouped(&self) -> usize {
+        self.ungrouped
+    }
+
+    /// Number of positions counted, including ungrouped ones
+    pub fn total(&self) -> usize {
+        self.total
+    }
+}
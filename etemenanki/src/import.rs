@@ -0,0 +1,310 @@
+//! Import of raw text into a new datastore, for small corpora without an external
+//! tokenization pipeline.
+//!
+//! A [`Tokenizer`] splits each text into sentences of tokens. The tokens become the `word`
+//! variable of a primary layer and the sentences a segmentation layer `s` on top of it:
+//!
+//! ```no_run
+//! # use etemenanki::{import::UnicodeTokenizer, Datastore};
+//! let text = std::fs::read_to_string("novel.txt").unwrap();
+//! let datastore = Datastore::from_text("novel", [text], &UnicodeTokenizer).unwrap();
+//! ```
+//!
+//! Besides the tokenizers of this module, any function from a text to its sentences can
+//! be used, e.g. to call a language specific tokenizer.
+
+use std::hash::Hasher;
+use std::io;
+use std::path::Path;
+use std::{error, fmt};
+
+use fnv::FnvHasher;
+
+use crate::build::{Build, BuildError};
+use crate::container::EncodeError;
+use crate::layers::{PrimaryLayer, SegmentationLayer};
+use crate::variables::IndexedStringVariable;
+use crate::{Datastore, DatastoreError};
+
+/// Name of the primary layer created by [`import_text`]
+pub const PRIMARY_LAYER: &str = "primary";
+
+/// Name of the variable with the tokens
+pub const WORD_VARIABLE: &str = "word";
+
+/// Name of the segmentation layer with the sentences
+pub const SENTENCE_LAYER: &str = "s";
+
+/// Splits raw text into sentences of tokens
+pub trait Tokenizer {
+    /// Sentences of `text` in order, each a list of tokens. Empty sentences are skipped.
+    fn sentences(&self, text: &str) -> Vec<Vec<String>>;
+}
+
+impl<F> Tokenizer for F
+where
+    F: Fn(&str) -> Vec<Vec<String>>,
+{
+    fn sentences(&self, text: &str) -> Vec<Vec<String>> {
+        self(text)
+    }
+}
+
+/// Tokenizer for pre-tokenized text with one sentence per line and whitespace between tokens
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WhitespaceTokenizer;
+
+impl Tokenizer for WhitespaceTokenizer {
+    fn sentences(&self, text: &str) -> Vec<Vec<String>> {
+        text.lines()
+            .map(|line| line.split_whitespace().map(str::to_owned).collect())
+            .collect()
+    }
+}
+
+/// Tokenizer for running text based on Unicode character classes.
+///
+/// Words are runs of letters, digits and combining diacritics, which may contain `.`, `'`
+/// or `’` between two of them and `,` or `;` between two digits, as in "don't" or "3,500".
+/// Ideographs and Hiragana, which are written without spaces, are tokens of their own,
+/// just like every other character except whitespace.
+///
+/// A sentence ends after `.`, `!`, `?`, `…` or their ideographic forms and any further
+/// terminators, closing quotes and brackets following them. This approximates the default
+/// word and sentence boundaries of Unicode Standard Annex #29 without its tables, and like
+/// it abbreviations such as "Mr." end a sentence.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UnicodeTokenizer;
+
+impl UnicodeTokenizer {
+    fn is_word_char(c: char) -> bool {
+        (c.is_alphanumeric() || is_combining_diacritic(c)) && !is_ideographic(c)
+    }
+
+    /// Whether `c` joins the word characters `before` and `after` into a single word
+    fn joins(c: char, before: char, after: char) -> bool {
+        match c {
+            '.' | '\'' | '’' => true,
+            ',' | ';' => before.is_numeric() && after.is_numeric(),
+            _ => false,
+        }
+    }
+
+    /// Tokens of `text` without sentence boundaries
+    pub fn words<'t>(&self, text: &'t str) -> Vec<&'t str> {
+        let mut tokens = Vec::new();
+        let mut chars = text.char_indices().peekable();
+
+        while let Some((start, c)) = chars.next() {
+            if c.is_whitespace() {
+                continue;
+            }
+
+            let mut end = start + c.len_utf8();
+            if Self::is_word_char(c) {
+                let mut last = c;
+                while let Some(&(i, next)) = chars.peek() {
+                    if Self::is_word_char(next) {
+                        chars.next();
+                        end = i + next.len_utf8();
+                        last = next;
+                        continue;
+                    }
+
+                    // a joining character is only part of the word if another word character follows
+                    match text[i + next.len_utf8()..].chars().next() {
+                        Some(after) if Self::is_word_char(after) && Self::joins(next, last, after) => {
+                            chars.next();
+                            chars.next();
+                            end = i + next.len_utf8() + after.len_utf8();
+                            last = after;
+                        }
+                        _ => break,
+                    }
+                }
+            }
+
+            tokens.push(&text[start..end]);
+        }
+
+        tokens
+    }
+}
+
+impl Tokenizer for UnicodeTokenizer {
+    fn sentences(&self, text: &str) -> Vec<Vec<String>> {
+        let mut sentences = Vec::new();
+        let mut sentence = Vec::new();
+        let mut ended = false;
+
+        for token in self.words(text) {
+            let terminator = token.chars().all(|c| matches!(c, '.' | '!' | '?' | '…' | '。' | '！' | '？'));
+            let closing = token.chars().all(|c| matches!(c, '"' | '\'' | ')' | ']' | '}' | '»' | '”' | '’' | '」' | '』'));
+
+            if ended && !terminator && !closing {
+                sentences.push(std::mem::take(&mut sentence));
+                ended = false;
+            }
+            sentence.push(token.to_owned());
+            ended |= terminator;
+        }
+
+        if !sentence.is_empty() {
+            sentences.push(sentence);
+        }
+        sentences
+    }
+}
+
+/// Combining diacritical marks, which `char::is_alphanumeric` does not include
+fn is_combining_diacritic(c: char) -> bool {
+    matches!(c, '\u{300}'..='\u{36f}' | '\u{1ab0}'..='\u{1aff}' | '\u{1dc0}'..='\u{1dff}' | '\u{20d0}'..='\u{20ff}' | '\u{fe20}'..='\u{fe2f}')
+}
+
+/// CJK ideographs and Hiragana
+fn is_ideographic(c: char) -> bool {
+    matches!(c, '\u{3040}'..='\u{309f}' | '\u{3400}'..='\u{4dbf}' | '\u{4e00}'..='\u{9fff}' | '\u{f900}'..='\u{faff}' | '\u{20000}'..='\u{3ffff}')
+}
+
+#[derive(Debug)]
+pub enum ImportError {
+    Io(io::Error),
+    Encode(EncodeError),
+    Datastore(DatastoreError),
+    Build(BuildError),
+    /// The tokenizer found no tokens in any text
+    Empty,
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{}", e),
+            Self::Encode(e) => write!(f, "{}", e),
+            Self::Datastore(e) => write!(f, "{}", e),
+            Self::Build(e) => write!(f, "{}", e),
+            Self::Empty => write!(f, "no tokens to import"),
+        }
+    }
+}
+
+impl error::Error for ImportError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            Self::Encode(e) => Some(e),
+            Self::Datastore(e) => Some(e),
+            Self::Build(e) => Some(e),
+            Self::Empty => None,
+        }
+    }
+}
+
+impl From<io::Error> for ImportError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<EncodeError> for ImportError {
+    fn from(e: EncodeError) -> Self {
+        Self::Encode(e)
+    }
+}
+
+impl From<DatastoreError> for ImportError {
+    fn from(e: DatastoreError) -> Self {
+        Self::Datastore(e)
+    }
+}
+
+impl From<BuildError> for ImportError {
+    fn from(e: BuildError) -> Self {
+        Self::Build(e)
+    }
+}
+
+
+/// Tokenizes `texts` with `tokenizer` and encodes them as a new datastore at `path`, see
+/// [`Datastore::from_text`].
+pub fn import_text<'map, P, I, S, T>(path: P, texts: I, tokenizer: &T) -> Result<Datastore<'map>, ImportError>
+where
+    P: AsRef<Path>,
+    I: IntoIterator<Item = S>,
+    S: AsRef<str>,
+    T: Tokenizer + ?Sized,
+{
+    let path = path.as_ref();
+    if path.exists() && path.read_dir()?.next().is_some() {
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists, "datastore directory is not empty").into());
+    }
+    import(path, texts, tokenizer, false)
+}
+
+/// Continues an [`import_text`] into `path` which was interrupted, e.g. by a crash.
+///
+/// Containers the interrupted import has completed are kept, see [`build`](crate::build).
+/// `texts` and `tokenizer` have to yield the same tokens as before, otherwise the import
+/// fails with [`BuildError::InputChanged`].
+pub fn resume_import_text<'map, P, I, S, T>(path: P, texts: I, tokenizer: &T) -> Result<Datastore<'map>, ImportError>
+where
+    P: AsRef<Path>,
+    I: IntoIterator<Item = S>,
+    S: AsRef<str>,
+    T: Tokenizer + ?Sized,
+{
+    import(path.as_ref(), texts, tokenizer, true)
+}
+
+fn import<'map, I, S, T>(path: &Path, texts: I, tokenizer: &T, resume: bool) -> Result<Datastore<'map>, ImportError>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<str>,
+    T: Tokenizer + ?Sized,
+{
+    let mut tokens = Vec::new();
+    let mut sentences = Vec::new();
+    for text in texts {
+        for sentence in tokenizer.sentences(text.as_ref()) {
+            if sentence.is_empty() {
+                continue;
+            }
+            let start = tokens.len();
+            tokens.extend(sentence);
+            sentences.push((start, tokens.len()));
+        }
+    }
+    if tokens.is_empty() {
+        return Err(ImportError::Empty);
+    }
+
+    let mut hasher = FnvHasher::default();
+    for token in tokens.iter() {
+        hasher.write(token.as_bytes());
+        hasher.write_u8(0);
+    }
+    for (start, end) in sentences.iter() {
+        hasher.write_usize(*start);
+        hasher.write_usize(*end);
+    }
+
+    let mut build = if resume {
+        Build::resume(path, hasher.finish())?
+    } else {
+        Build::new(path, hasher.finish())?
+    };
+    let n = tokens.len();
+
+    let primary = build.container(PRIMARY_LAYER, "primary.zigl", |file| {
+        PrimaryLayer::encode_to_file(file, n, PRIMARY_LAYER.to_owned(), "").map(|l| l.uuid())
+    })?;
+    build.container(WORD_VARIABLE, "word.zigv", |file| {
+        IndexedStringVariable::encode_to_file(file, tokens.into_iter(), n, WORD_VARIABLE.to_owned(), primary, true, "").map(|v| v.uuid())
+    })?;
+    build.container(SENTENCE_LAYER, Path::new(SENTENCE_LAYER).join("s.zigl"), |file| {
+        SegmentationLayer::encode_to_file(file, sentences.iter().copied(), sentences.len(), SENTENCE_LAYER.to_owned(), primary, true, "").map(|l| l.uuid())
+    })?;
+    drop(build);
+
+    Ok(Datastore::open(path)?)
+}
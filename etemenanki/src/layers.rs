@@ -1,15 +1,19 @@
 use enum_as_inner::EnumAsInner;
-use memmap2::{Mmap, MmapOptions};
 use uuid::Uuid;
 
-use std::collections::{hash_map, HashMap};
-use std::fs::File;
-use std::ops;
+use std::cell::Cell;
+use std::collections::{hash_map, BTreeMap, HashMap};
+use std::{error, fmt, iter, ops};
 
-use crate::components::{CachedIndex, CachedVector, Component, Index, Vector};
-use crate::container::{self, Container, ContainerBuilder};
+use crate::components::{CacheStats, CachedIndex, CachedVector, Component, Index, RankBitmap, Vector};
+use crate::container::{self, Container, ContainerBuilder, EncodeError, EncodeTarget};
+use crate::group::GroupCounts;
 use crate::macros::{check_and_return_component, get_container_base};
-use crate::variables::Variable;
+use crate::sample;
+use crate::selection::{Selection, SelectionError};
+use crate::sources::SourceVariable;
+use crate::storage::{self, Storage};
+use crate::variables::{Variable, VariableType, VariableValue, VirtualVariable};
 use crate::{components, variables};
 
 #[derive(Debug)]
@@ -21,6 +25,66 @@ impl<'map, T> LayerData<'map, T> {
     }
 }
 
+impl<'map> LayerData<'map, SegmentationLayer<'map>> {
+    /// Counts `positions` by the value of `variable` of their containing segment, see [`GroupCounts`].
+    pub fn group_counts<I>(&self, variable: &str, positions: I) -> Result<GroupCounts<'map>, SelectionError>
+    where
+        I: IntoIterator<Item = usize>,
+    {
+        GroupCounts::new(self, variable, positions)
+    }
+
+    /// View of the range `index` and the values of all its variables, `None` if out of bounds
+    pub fn segment(&self, index: usize) -> Option<Segment<'_, 'map>> {
+        (index < self.len()).then_some(Segment { layer: self, index })
+    }
+
+    /// View of the range containing base layer position `position`
+    pub fn segment_containing(&self, position: usize) -> Option<Segment<'_, 'map>> {
+        self.find_containing(position).map(|index| Segment { layer: self, index })
+    }
+}
+
+/// One range of a segmentation layer with its variables, like the attributes of an XML tag
+#[derive(Clone, Copy)]
+pub struct Segment<'a, 'map> {
+    layer: &'a LayerData<'map, SegmentationLayer<'map>>,
+    index: usize,
+}
+
+impl<'a, 'map> Segment<'a, 'map> {
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Start and end position of the range in the base layer
+    pub fn range(&self) -> (usize, usize) {
+        self.layer.get_unchecked(self.index)
+    }
+
+    /// Value of `variable`, `None` if the layer has no such variable or it can't be read
+    pub fn get(&self, variable: &str) -> Option<VariableValue<'map>> {
+        self.layer.variable_by_name(variable)?.get(self.index)
+    }
+
+    /// Values of all readable variables by name
+    pub fn values(&self) -> BTreeMap<&'a str, VariableValue<'map>> {
+        self.layer.1.variables.iter()
+            .filter_map(|(name, var)| Some((name.as_str(), var.get(self.index)?)))
+            .collect()
+    }
+}
+
+impl<'a, 'map> fmt::Debug for Segment<'a, 'map> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Segment")
+            .field("index", &self.index)
+            .field("range", &self.range())
+            .field("values", &self.values())
+            .finish()
+    }
+}
+
 impl<'map, T> ops::Deref for LayerData<'map, T> {
     type Target = T;
 
@@ -41,19 +105,12 @@ impl<'map, T, S: AsRef<str>> ops::Index<S> for LayerData<'map, T> {
 pub enum Layer<'map> {
     Primary(LayerData<'map, PrimaryLayer<'map>>),
     Segmentation(LayerData<'map, SegmentationLayer<'map>>),
+    Span(LayerData<'map, SpanLayer<'map>>),
 }
 
 impl<'map> Layer<'map> {
     pub fn add_variable(&mut self, name: String, var: Variable<'map>) -> Result<(), Variable<'map>> {
-        let varlen = match &var {
-            Variable::IndexedString(v) => v.len(),
-            Variable::PlainString(v) => v.len(),
-            Variable::Integer(v) => v.len(),
-            Variable::Pointer(v) => v.len(),
-            Variable::ExternalPointer => todo!(),
-            Variable::Set(v) => v.len(),
-            Variable::Hash => todo!(),
-        };
+        let varlen = var.len();
 
         if varlen != self.len() {
             Err(var)
@@ -61,6 +118,7 @@ impl<'map> Layer<'map> {
             match self {
                 Self::Primary(LayerData(_, vars)) => vars.add_variable(name, var),
                 Self::Segmentation(LayerData(_, vars)) => vars.add_variable(name, var),
+                Self::Span(LayerData(_, vars)) => vars.add_variable(name, var),
             }
         }
     }
@@ -69,6 +127,7 @@ impl<'map> Layer<'map> {
         match self {
             Layer::Primary(LayerData(_, vars)) => vars.variables.get(name.as_ref()),
             Layer::Segmentation(LayerData(_, vars)) => vars.variables.get(name.as_ref()),
+            Layer::Span(LayerData(_, vars)) => vars.variables.get(name.as_ref()),
         }
     }
 
@@ -80,10 +139,60 @@ impl<'map> Layer<'map> {
         Self::Segmentation(LayerData(layer, LayerVariables::default()))
     }
 
+    pub fn new_span(layer: SpanLayer<'map>) -> Self {
+        Self::Span(LayerData(layer, LayerVariables::default()))
+    }
+
+    pub fn comment(&self) -> Option<&'map str> {
+        match &self {
+            Self::Primary(LayerData(l, _)) => l.comment(),
+            Self::Segmentation(LayerData(l, _)) => l.comment(),
+            Self::Span(LayerData(l, _)) => l.comment(),
+        }
+    }
+
+    pub fn uuid(&self) -> Uuid {
+        match &self {
+            Self::Primary(LayerData(l, _)) => l.uuid(),
+            Self::Segmentation(LayerData(l, _)) => l.uuid(),
+            Self::Span(LayerData(l, _)) => l.uuid(),
+        }
+    }
+
+    /// UUID of the layer this layer is based on, `None` for primary layers
+    pub fn base(&self) -> Option<Uuid> {
+        match &self {
+            Self::Primary(_) => None,
+            Self::Segmentation(LayerData(l, _)) => Some(l.base),
+            Self::Span(LayerData(l, _)) => Some(l.base),
+        }
+    }
+
+    /// Returns the cache statistics of the layer itself, excluding its variables.
+    pub fn cache_stats(&self) -> CacheStats {
+        match &self {
+            Self::Primary(_) => CacheStats::default(),
+            Self::Segmentation(LayerData(l, _)) => l.cache_stats(),
+            Self::Span(LayerData(l, _)) => l.cache_stats(),
+        }
+    }
+
     pub fn len(&self) -> usize {
         match &self {
             Self::Primary(LayerData(l, _)) => l.len(),
             Self::Segmentation(LayerData(l, _)) => l.len(),
+            Self::Span(LayerData(l, _)) => l.len(),
+        }
+    }
+
+    /// Partition boundaries of primary and segmentation layers, see
+    /// [`PrimaryLayer::partition_boundaries`]. Span layers are never partitioned, their
+    /// `dim2` is the length of the longest span.
+    pub fn partition_boundaries(&self) -> Option<&[usize]> {
+        match &self {
+            Self::Primary(LayerData(l, _)) => Some(l.partition_boundaries()),
+            Self::Segmentation(LayerData(l, _)) => Some(l.partition_boundaries()),
+            Self::Span(_) => None,
         }
     }
 
@@ -91,15 +200,99 @@ impl<'map> Layer<'map> {
         match &self {
             Self::Primary(LayerData(_, var)) => var.len(),
             Self::Segmentation(LayerData(_, var)) => var.len(),
+            Self::Span(LayerData(_, var)) => var.len(),
         }
     }
 
+    /// Selects the variables `names` of this layer for joint decoding
+    pub fn select<S: AsRef<str>>(&self, names: &[S]) -> Result<Selection<'_, 'map>, SelectionError> {
+        Selection::new(self, names)
+    }
+
     pub fn variable_names(&self) -> hash_map::Keys<String, variables::Variable<'map>> {
         match self {
             Layer::Primary(LayerData(_, vars)) => vars.variables.keys(),
             Layer::Segmentation(LayerData(_, vars)) => vars.variables.keys(),
+            Layer::Span(LayerData(_, vars)) => vars.variables.keys(),
         }
     }
+
+    /// All variables of this layer with their names, in no particular order
+    pub fn variables(&self) -> impl Iterator<Item = (&str, &variables::Variable<'map>)> {
+        let vars = match self {
+            Layer::Primary(LayerData(_, vars)) => vars,
+            Layer::Segmentation(LayerData(_, vars)) => vars,
+            Layer::Span(LayerData(_, vars)) => vars,
+        };
+        vars.variables.iter().map(|(name, var)| (name.as_str(), var))
+    }
+
+    /// Adds a variable computed from other variables of this layer, see [`VirtualVariable`].
+    ///
+    /// Fails if a variable of this name exists or a source is not a variable of this layer.
+    pub fn add_virtual_variable(&mut self, name: String, var: VirtualVariable) -> Result<(), VirtualVariable> {
+        if var.sources().any(|source| self.variable_by_name(source).is_none()) {
+            return Err(var);
+        }
+        match self {
+            Self::Primary(LayerData(_, vars)) => vars.add_virtual_variable(name, var),
+            Self::Segmentation(LayerData(_, vars)) => vars.add_virtual_variable(name, var),
+            Self::Span(LayerData(_, vars)) => vars.add_virtual_variable(name, var),
+        }
+    }
+
+    pub fn virtual_variable_by_name<S: AsRef<str>>(&self, name: S) -> Option<&VirtualVariable> {
+        match self {
+            Layer::Primary(LayerData(_, vars)) => vars.virtual_variables.get(name.as_ref()),
+            Layer::Segmentation(LayerData(_, vars)) => vars.virtual_variables.get(name.as_ref()),
+            Layer::Span(LayerData(_, vars)) => vars.virtual_variables.get(name.as_ref()),
+        }
+    }
+
+    /// Adds the original documents of the segments of this layer, see [`SourceVariable`].
+    ///
+    /// Fails if there isn't one document per segment or a variable of this name exists.
+    pub fn add_source(&mut self, name: String, source: SourceVariable<'map>) -> Result<(), SourceVariable<'map>> {
+        if source.len() != self.len() {
+            return Err(source);
+        }
+        match self {
+            Self::Primary(LayerData(_, vars)) => vars.add_source(name, source),
+            Self::Segmentation(LayerData(_, vars)) => vars.add_source(name, source),
+            Self::Span(LayerData(_, vars)) => vars.add_source(name, source),
+        }
+    }
+
+    pub fn source_by_name<S: AsRef<str>>(&self, name: S) -> Option<&SourceVariable<'map>> {
+        match self {
+            Layer::Primary(LayerData(_, vars)) => vars.sources.get(name.as_ref()),
+            Layer::Segmentation(LayerData(_, vars)) => vars.sources.get(name.as_ref()),
+            Layer::Span(LayerData(_, vars)) => vars.sources.get(name.as_ref()),
+        }
+    }
+
+    /// Type of the variable `name`, `None` if the layer has no such variable
+    pub fn variable_type<S: AsRef<str>>(&self, name: S) -> Option<VariableType> {
+        self.variable_by_name(name).map(Variable::variable_type)
+    }
+}
+
+/// Objects that refer to a base layer, i.e. layers and variables, see [`Datastore::base_of`]
+pub trait HasBase {
+    /// UUID of the base layer, `None` if there is none
+    fn base_uuid(&self) -> Option<Uuid>;
+}
+
+impl<'map> HasBase for Layer<'map> {
+    fn base_uuid(&self) -> Option<Uuid> {
+        self.base()
+    }
+}
+
+impl<'map> HasBase for Variable<'map> {
+    fn base_uuid(&self) -> Option<Uuid> {
+        self.base()
+    }
 }
 
 impl<'map, S: AsRef<str>> ops::Index<S> for Layer<'map> {
@@ -109,6 +302,7 @@ impl<'map, S: AsRef<str>> ops::Index<S> for Layer<'map> {
         match self {
             Layer::Primary(LayerData(_, vars)) => &vars.variables[index.as_ref()],
             Layer::Segmentation(LayerData(_, vars)) => &vars.variables[index.as_ref()],
+            Layer::Span(LayerData(_, vars)) => &vars.variables[index.as_ref()],
         }
     }
 }
@@ -116,11 +310,17 @@ impl<'map, S: AsRef<str>> ops::Index<S> for Layer<'map> {
 #[derive(Debug, Default)]
 pub struct LayerVariables<'map> {
     pub variables: HashMap<String, Variable<'map>>,
+    pub virtual_variables: HashMap<String, VirtualVariable>,
+    pub sources: HashMap<String, SourceVariable<'map>>,
 }
 
 impl<'map> LayerVariables<'map> {
+    fn contains(&self, name: &str) -> bool {
+        self.variables.contains_key(name) || self.virtual_variables.contains_key(name) || self.sources.contains_key(name)
+    }
+
     pub fn add_variable(&mut self, name: String, var: Variable<'map>) -> Result<(), Variable<'map>> {
-        if self.variables.contains_key(&name) {
+        if self.contains(&name) {
             Err(var)
         } else {
             self.variables.insert(name, var);
@@ -128,6 +328,24 @@ impl<'map> LayerVariables<'map> {
         }
     }
 
+    pub fn add_virtual_variable(&mut self, name: String, var: VirtualVariable) -> Result<(), VirtualVariable> {
+        if self.contains(&name) {
+            Err(var)
+        } else {
+            self.virtual_variables.insert(name, var);
+            Ok(())
+        }
+    }
+
+    pub fn add_source(&mut self, name: String, source: SourceVariable<'map>) -> Result<(), SourceVariable<'map>> {
+        if self.contains(&name) {
+            Err(source)
+        } else {
+            self.sources.insert(name, source);
+            Ok(())
+        }
+    }
+
     pub fn len(&self) -> usize {
         self.variables.len()
     }
@@ -135,16 +353,140 @@ impl<'map> LayerVariables<'map> {
 
 #[derive(Debug)]
 pub struct PrimaryLayer<'map> {
-    mmap: Mmap,
+    storage: Box<dyn Storage>,
     pub name: String,
-    pub header: &'map container::Header,
+    pub(crate) header: &'map container::Header,
+    comment: Option<&'map str>,
+    partition: Vec<usize>,
 }
 
 impl<'map> PrimaryLayer<'map> {
+    pub fn comment(&self) -> Option<&'map str> {
+        self.comment
+    }
+
+    /// Encodes a primary layer of `n` positions. It has no components, its variables are
+    /// encoded separately with its UUID as their base.
+    ///
+    /// This is the first container of every datastore:
+    ///
+    /// ```no_run
+    /// # use etemenanki::{layers::PrimaryLayer, lock::PendingFile, variables::IndexedStringVariable, Datastore};
+    /// let words = ["It", "was", "the", "best", "of", "times"].map(String::from);
+    /// std::fs::create_dir("best").unwrap();
+    ///
+    /// let primary = PrimaryLayer::encode_to_file(PendingFile::create("best/primary.zigl").unwrap(), words.len(), "primary".to_owned(), "").unwrap();
+    /// IndexedStringVariable::encode_to_file(PendingFile::create("best/word.zigv").unwrap(), words.into_iter(), primary.len(), "word".to_owned(), primary.uuid(), true, "").unwrap();
+    ///
+    /// let datastore = Datastore::open("best").unwrap();
+    /// assert!(datastore["primary"]["word"].as_indexed_string().unwrap().get(3) == Some("best"));
+    /// ```
+    pub fn encode_to_file<W: EncodeTarget>(file: W, n: usize, name: String, comment: &str) -> Result<Self, EncodeError> {
+        Self::encode_partitioned(file, n, &[], name, comment)
+    }
+
+    /// Encodes a primary layer of `n` positions divided into partitions, e.g. one per
+    /// document of the corpus, which start at the positions `starts`.
+    ///
+    /// The partition boundaries are stored in a "Partition" component with the start of each
+    /// partition and `n` as its end, and their number in `dim2`. The first partition has to
+    /// start at 0 and every other one after the previous one. Without any starts the layer
+    /// is a single partition and has no components, like [`encode_to_file`](Self::encode_to_file).
+    pub fn encode_partitioned<W: EncodeTarget>(file: W, n: usize, starts: &[usize], name: String, comment: &str) -> Result<Self, EncodeError> {
+        check_partition(starts, n)?;
+
+        let capacity = if starts.is_empty() { 0 } else { 1 };
+        let mut builder = ContainerBuilder::new_into_file(name, file, capacity + ContainerBuilder::comment_capacity(comment))
+            .edit_header(| h | {
+                h.ziggurat_type(container::Type::PrimaryLayer)
+                    .dim1(n)
+                    .dim2(starts.len());
+            });
+
+        if !starts.is_empty() {
+            builder = builder.add_component("Partition", components::Type::Vector, | bom_entry, file | {
+                unsafe {
+                    let boundaries = starts.iter().chain([&n]).map(|&b| b as i64);
+                    Vector::encode_uncompressed_to_container_file(boundaries, starts.len() + 1, 1, file, bom_entry, bom_entry.offset as u64)
+                }
+            });
+        }
+
+        Ok(builder.comment(comment).build()?.try_into().expect("PrimaryLayer returned by its constructor is inconsistent"))
+    }
+
+    pub fn uuid(&self) -> Uuid {
+        self.header.uuid()
+    }
+
     #[inline]
     pub fn len(&self) -> usize {
         self.header.dim1()
     }
+
+    /// Start of each partition followed by the end of the last one, `[0, len]` if the layer
+    /// isn't partitioned
+    pub fn partition_boundaries(&self) -> &[usize] {
+        &self.partition
+    }
+
+    /// Positions of partition `i`
+    pub fn partition(&self, i: usize) -> Option<ops::Range<usize>> {
+        partition_range(&self.partition, i)
+    }
+
+    /// Finds the partition containing `position`
+    pub fn find_partition(&self, position: usize) -> Option<usize> {
+        partition_containing(&self.partition, position)
+    }
+}
+
+/// Range of partition `i` between `boundaries`, as returned by `partition_boundaries`
+fn partition_range(boundaries: &[usize], i: usize) -> Option<ops::Range<usize>> {
+    Some(*boundaries.get(i)?..*boundaries.get(i + 1)?)
+}
+
+/// Index of the partition between `boundaries` that contains `index`
+fn partition_containing(boundaries: &[usize], index: usize) -> Option<usize> {
+    if index >= *boundaries.last()? {
+        return None;
+    }
+    Some(boundaries.partition_point(|&b| b <= index) - 1)
+}
+
+/// Checks that the partitions `starts` of a layer of length `n` start at 0 and are sorted and non-empty
+fn check_partition(starts: &[usize], n: usize) -> Result<(), EncodeError> {
+    for (i, &start) in starts.iter().enumerate() {
+        let valid = match i {
+            0 => start == 0 && n > 0,
+            _ => start > starts[i - 1] && start < n,
+        };
+        if !valid {
+            return Err(EncodeError::InvalidPartition(i));
+        }
+    }
+    Ok(())
+}
+
+/// Reads the "Partition" component of a layer of length `n`, see [`PrimaryLayer::encode_partitioned`]
+/// and [`SegmentationLayer::encode_partitioned`]
+fn read_partition(container: &Container, n: usize) -> Result<Vec<usize>, container::TryFromError> {
+    let Some(component) = container.get_component("Partition") else {
+        return Ok(vec![0, n]);
+    };
+    let vector = component.into_vector().map_err(|_| container::TryFromError::WrongComponentType("Partition"))?;
+    let boundaries: Vec<usize> = CachedVector::<1>::new(vector)
+        .ok_or(container::TryFromError::WrongComponentDimensions("Partition"))?
+        .column_iter(0)
+        .map(|b| b as usize)
+        .collect();
+
+    let p = container.header().dim2();
+    let valid = boundaries.len() == p + 1 && check_partition(&boundaries[..p], n).is_ok() && boundaries[p] == n;
+    if !valid {
+        return Err(container::TryFromError::WrongComponentDimensions("Partition"));
+    }
+    Ok(boundaries)
 }
 
 impl<'map> TryFrom<Container<'map>> for PrimaryLayer<'map> {
@@ -155,11 +497,15 @@ impl<'map> TryFrom<Container<'map>> for PrimaryLayer<'map> {
 
         match header.container_type() {
             container::Type::PrimaryLayer => {
-                let (name, mmap, header, _) = container.into_raw_parts();
+                let partition = read_partition(&container, header.dim1())?;
+                let comment = container.comment();
+                let (name, storage, header, _) = container.into_raw_parts();
                 Ok(Self {
-                    mmap,
+                    storage,
                     name,
                     header,
+                    comment,
+                    partition,
                 })
             }
 
@@ -171,18 +517,39 @@ impl<'map> TryFrom<Container<'map>> for PrimaryLayer<'map> {
 #[derive(Debug)]
 pub struct SegmentationLayer<'map> {
     pub base: Uuid,
-    mmap: Mmap,
+    storage: Box<dyn Storage>,
     pub name: String,
-    pub header: &'map container::Header,
+    pub(crate) header: &'map container::Header,
+    comment: Option<&'map str>,
     range_stream: components::CachedVector<'map, 2>,
     start_sort: components::CachedIndex<'map>,
     end_sort: components::CachedIndex<'map>,
+    start_bitmap: Option<components::RankBitmap<'map>>,
+    partition: Vec<usize>,
 }
 
 impl<'map> SegmentationLayer<'map> {
+    pub fn comment(&self) -> Option<&'map str> {
+        self.comment
+    }
+
+    pub fn uuid(&self) -> Uuid {
+        self.header.uuid()
+    }
+
+    pub fn cache_stats(&self) -> CacheStats {
+        self.range_stream.cache_stats()
+            + self.start_sort.cache_stats()
+            + self.end_sort.cache_stats()
+    }
+
     pub fn contains(&self, range: (usize, usize)) -> bool {
         let (start, end) = range;
 
+        if let Some(bitmap) = self.start_bitmap {
+            return bitmap.get(start) && end == self.get_unchecked(bitmap.rank(start)).1;
+        }
+
         match self.start_sort.get_first(start as i64) {
             None => false,
             Some(i) => end == self.get_unchecked(i as usize).1,
@@ -194,46 +561,57 @@ impl<'map> SegmentationLayer<'map> {
     }
 
     pub fn contains_start(&self, start: usize) -> bool {
-        self.start_sort.contains_key(start as i64)
+        match self.start_bitmap {
+            Some(bitmap) => bitmap.get(start),
+            None => self.start_sort.contains_key(start as i64),
+        }
     }
 
-    /// Finds the index of the range containing baselayer position `position`
-    pub fn find_containing(&self, position: usize) -> Option<usize> {
-        let i = match &self.start_sort {
-
-            components::CachedIndex::Compressed { length: _, cache } => {
-                let mut cache = cache.borrow_mut();
-
-                let bi = cache.sync_block_position(position as i64);
-                let block = cache.get_block(bi).unwrap();
+    /// The "StartBitmap" component with a bit set at every range start, if the layer has one
+    pub fn start_bitmap(&self) -> Option<&components::RankBitmap<'map>> {
+        self.start_bitmap.as_ref()
+    }
 
-                let vi = match block.keys().binary_search(&(position as i64)) {
-                    Ok(i) => i,
-                    Err(0) => 0,
-                    Err(i) => i-1,
-                };
+    /// Draws the indices of `n` distinct random ranges in ascending order, see [`sample::random_indices`]
+    pub fn random_segments(&self, n: usize, seed: u64) -> Vec<usize> {
+        sample::random_indices(self.len(), n, seed)
+    }
 
-                (bi * 16) + vi
-            }
-            
-            components::CachedIndex::Uncompressed { length: _, pairs } => {
-                match pairs.binary_search_by_key(&(position as i64), |(s, _)| *s) {
-                    Ok(i) => i,
-                    Err(0) => 0,
-                    Err(i) => i-1,
-                }
-            }
+    /// Finds the index of the range containing baselayer position `position`
+    pub fn find_containing(&self, position: usize) -> Option<usize> {
+        // the candidate is the last range starting at or before `position`
+        let i = match self.start_bitmap {
+            Some(bitmap) => bitmap.rank(position + 1).checked_sub(1)?,
+            None => self.start_sort.get_floor(position as i64)?.1 as usize,
         };
+        let (start, end) = self.get(i)?;
 
-        if i < self.len() {
-            let (start, end) = self.get_unchecked(i as usize);
-
-            if position >= start && end > position {
-                return Some(i)
-            }
+        if position >= start && end > position {
+            Some(i)
+        } else {
+            None
         }
+    }
 
-        None
+    /// Finds the indices of the ranges containing each of the given positions.
+    ///
+    /// If `positions` is sorted the range stream is walked in a single pass,
+    /// otherwise each out of order position causes a new index lookup.
+    pub fn find_containing_sorted(&self, positions: &[usize]) -> Vec<Option<usize>> {
+        self.find_containing_iter(positions.iter().copied()).collect()
+    }
+
+    /// Streaming version of [`find_containing_sorted`](Self::find_containing_sorted)
+    pub fn find_containing_iter<I>(&self, positions: I) -> ContainingIterator<'map, I::IntoIter> where I: IntoIterator<Item = usize> {
+        ContainingIterator {
+            start_sort: self.start_sort.clone(),
+            range_stream: self.range_stream.clone(),
+            positions: positions.into_iter(),
+            ranges: None,
+            index: 0,
+            current: None,
+            last: None,
+        }
     }
 
     pub fn get(&self, index: usize) -> Option<(usize, usize)> {
@@ -257,39 +635,113 @@ impl<'map> SegmentationLayer<'map> {
         self.header.dim1()
     }
 
-    pub fn encode_to_file<I>(file: File, values: I, n: usize, name: String, base: Uuid, compressed: bool, comment: &str) -> Self where I: Iterator<Item=(usize, usize)> {
+    /// Index of the first range of each partition followed by `len`, `[0, len]` if the layer
+    /// isn't partitioned
+    pub fn partition_boundaries(&self) -> &[usize] {
+        &self.partition
+    }
+
+    /// Indices of the ranges in partition `i`
+    pub fn partition(&self, i: usize) -> Option<ops::Range<usize>> {
+        partition_range(&self.partition, i)
+    }
+
+    /// Finds the partition containing the range at `index`
+    pub fn find_partition(&self, index: usize) -> Option<usize> {
+        partition_containing(&self.partition, index)
+    }
+
+    /// Iterates over the ranges of partition `i` without touching the other partitions
+    pub fn iter_partition(&self, i: usize) -> Option<iter::Take<SegmentationLayerIterator<'map>>> {
+        let indices = self.partition(i)?;
+        let ranges = SegmentationLayerIterator { ranges: self.range_stream.iter_from(indices.start) };
+        Some(ranges.take(indices.len()))
+    }
+
+    /// Finds the index of the range containing `position` like [`find_containing`](Self::find_containing),
+    /// but only among the ranges of partition `i`
+    pub fn find_containing_in_partition(&self, position: usize, i: usize) -> Option<usize> {
+        let index = self.find_containing(position)?;
+        self.partition(i)?.contains(&index).then_some(index)
+    }
+
+    /// Prepares the segments `start..end` for access, see `IndexedStringVariable::prefetch_range`.
+    pub fn prefetch_range(&self, start: usize, end: usize) {
+        storage::will_need(&*self.storage, self.range_stream.raw_range(start, end));
+        self.range_stream.prefetch(start, end);
+    }
+
+    /// Encodes a segmentation layer from `n` sorted, non-overlapping and non-empty ranges.
+    ///
+    /// The ranges are validated while encoding, the first invalid range is reported
+    /// and the file is left incomplete.
+    pub fn encode_to_file<W, I>(file: W, values: I, n: usize, name: String, base: Uuid, compressed: bool, comment: &str) -> Result<Self, EncodeError> where W: EncodeTarget, I: Iterator<Item=(usize, usize)> {
+        Self::encode_to_file_with_bitmap(file, values, n, name, base, compressed, false, comment)
+    }
+
+    /// Encodes the layer like [`encode_to_file`](Self::encode_to_file), optionally with a
+    /// "StartBitmap" component.
+    ///
+    /// The bitmap has a bit for every base layer position up to the last range start and
+    /// answers `find_containing`, `contains` and `contains_start` in constant time instead
+    /// of searching StartSort. It takes about 1.1 bits per base layer position.
+    pub fn encode_to_file_with_bitmap<W, I>(file: W, values: I, n: usize, name: String, base: Uuid, compressed: bool, bitmap: bool, comment: &str) -> Result<Self, EncodeError> where W: EncodeTarget, I: Iterator<Item=(usize, usize)> {
+        Self::encode_partitioned(file, values, n, &[], name, base, compressed, bitmap, comment)
+    }
+
+    /// Encodes the layer like [`encode_to_file_with_bitmap`](Self::encode_to_file_with_bitmap),
+    /// divided into partitions which start at the range indices `starts`.
+    ///
+    /// The partitions are stored like those of [`PrimaryLayer::encode_partitioned`]. They
+    /// usually follow the partitions of the base layer, e.g. the sentences of each document
+    /// of a partitioned primary layer, but this is up to the caller.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "info", skip_all, fields(name = %name, n = n, partitions = starts.len(), compressed = compressed, bitmap = bitmap)))]
+    pub fn encode_partitioned<W, I>(file: W, values: I, n: usize, starts: &[usize], name: String, base: Uuid, compressed: bool, bitmap: bool, comment: &str) -> Result<Self, EncodeError> where W: EncodeTarget, I: Iterator<Item=(usize, usize)> {
+        check_partition(starts, n)?;
+
         let vectype = if compressed { components::Type::VectorDelta } else { components::Type::Vector };
         let idxtype = if compressed { components::Type::IndexComp } else { components::Type::Index };
+
+        let error = Cell::new(None);
+        let mut previous = None;
+        let values = values.enumerate().map(|(i, range)| {
+            if error.get().is_none() {
+                if let Err(e) = check_range(i, range, previous, false) {
+                    error.set(Some(e));
+                }
+            }
+            previous = Some(range);
+            range
+        });
         
-        let mut builder = ContainerBuilder::new_into_file(name, file, 3)
+        let capacity = 3 + bitmap as u8 + !starts.is_empty() as u8;
+        let mut builder = ContainerBuilder::new_into_file(name, file, capacity + ContainerBuilder::comment_capacity(comment))
             .edit_header(| h | {
-                h.comment(comment)
-                    .ziggurat_type(container::Type::SegmentationLayer)
+                h.ziggurat_type(container::Type::SegmentationLayer)
                     .dim1(n)
-                    .dim2(0)
+                    .dim2(starts.len())
                     .base1(Some(base));
             })
             .add_component("RangeStream", vectype, | bom_entry, file | {
                 unsafe {
                     if compressed {
                         let values = values.map(|(s, e)| [s as i64, e as i64]);
-                        Vector::encode_delta_to_container_file(values, n, file, bom_entry, bom_entry.offset as u64);
+                        Vector::encode_delta_to_container_file(values, n, file, bom_entry, bom_entry.offset as u64)
                     } else {
                         let values = values.map(|(s, e)| [s as i64, e as i64]).flatten();
-                        Vector::encode_uncompressed_to_container_file(values, n, 1, file, bom_entry, bom_entry.offset as u64);
+                        Vector::encode_uncompressed_to_container_file(values, n, 2, file, bom_entry, bom_entry.offset as u64)
                     }
                 }
             });
 
-        let vecbom = *builder.get_component(0);
-        let vecmmap = unsafe { MmapOptions::new()
-            .offset(vecbom.offset as u64)
-            .len(vecbom.size as usize)
-            .map(builder.file())
-            .unwrap()
-        };
+        if let Some(e) = error.get() {
+            return Err(e.into());
+        }
+
+        builder = builder.checked()?;
+        let (vecbom, vecstorage) = builder.map_component(0)?;
 
-        let range_stream = Component::from_raw_parts(&vecbom, vecmmap.as_ptr()).unwrap().into_vector().unwrap();
+        let range_stream = Component::from_raw_parts(&vecbom, vecstorage.bytes().as_ptr()).unwrap().into_vector().unwrap();
         let range_stream = CachedVector::<2>::new(range_stream).unwrap();
 
         builder = builder.add_component("StartSort", idxtype, | bom_entry, file | {
@@ -299,9 +751,9 @@ impl<'map> SegmentationLayer<'map> {
                     .map(|(i, [start, _])| (start, i as i64));
 
                 if compressed {
-                    Index::encode_compressed_to_container_file(values, n, file, bom_entry, bom_entry.offset as u64);
+                    Index::encode_compressed_to_container_file(values, n, file, bom_entry, bom_entry.offset as u64)
                 } else {
-                    Index::encode_uncompressed_to_container_file(values, n, file, bom_entry, bom_entry.offset as u64);
+                    Index::encode_uncompressed_to_container_file(values, n, file, bom_entry, bom_entry.offset as u64)
                 }
             }
         });
@@ -313,14 +765,32 @@ impl<'map> SegmentationLayer<'map> {
                     .map(|(i, [_, end])| (end, i as i64));
 
                 if compressed {
-                    Index::encode_compressed_to_container_file(values, n, file, bom_entry, bom_entry.offset as u64);
+                    Index::encode_compressed_to_container_file(values, n, file, bom_entry, bom_entry.offset as u64)
                 } else {
-                    Index::encode_uncompressed_to_container_file(values, n, file, bom_entry, bom_entry.offset as u64);
+                    Index::encode_uncompressed_to_container_file(values, n, file, bom_entry, bom_entry.offset as u64)
                 }
             }
         });
 
-        builder.build().try_into().expect("SegmentationLayer returned by its constructor is inconsistent")
+        if bitmap {
+            builder = builder.add_component("StartBitmap", components::Type::Vector, | bom_entry, file | {
+                unsafe {
+                    let starts = range_stream.column_iter(0).map(|start| start as usize);
+                    RankBitmap::encode_to_container_file(starts, file, bom_entry, bom_entry.offset as u64)
+                }
+            });
+        }
+
+        if !starts.is_empty() {
+            builder = builder.add_component("Partition", components::Type::Vector, | bom_entry, file | {
+                unsafe {
+                    let boundaries = starts.iter().chain([&n]).map(|&b| b as i64);
+                    Vector::encode_uncompressed_to_container_file(boundaries, starts.len() + 1, 1, file, bom_entry, bom_entry.offset as u64)
+                }
+            });
+        }
+
+        Ok(builder.comment(comment).build()?.try_into().expect("SegmentationLayer returned by its constructor is inconsistent"))
     }
 }
 
@@ -353,16 +823,34 @@ impl<'map> TryFrom<Container<'map>> for SegmentationLayer<'map> {
                 }
                 let end_sort = CachedIndex::new(end_sort);
 
-                let (name, mmap, header, _) = container.into_raw_parts();
+                // optional, only written on request
+                let start_bitmap = match container.get_component("StartBitmap") {
+                    Some(component) => {
+                        let vector = component.into_vector()
+                            .map_err(|_| Self::Error::WrongComponentType("StartBitmap"))?;
+                        match RankBitmap::from_vector(vector) {
+                            Some(bitmap) if bitmap.count_ones() == header.dim1() => Some(bitmap),
+                            _ => return Err(Self::Error::WrongComponentDimensions("StartBitmap")),
+                        }
+                    }
+                    None => None,
+                };
+                let partition = read_partition(&container, header.dim1())?;
+
+                let comment = container.comment();
+                let (name, storage, header, _) = container.into_raw_parts();
 
                 Ok(Self {
                     base,
-                    mmap,
+                    storage,
                     name,
                     header,
+                    comment,
                     range_stream,
                     start_sort,
                     end_sort,
+                    start_bitmap,
+                    partition,
                 })
             }
 
@@ -394,3 +882,324 @@ impl<'a, 'map> IntoIterator for &'a SegmentationLayer<'map> {
         }
     }
 }
+
+/// Iterator that yields the index of the containing range for each position
+/// of the underlying iterator, see [`SegmentationLayer::find_containing_iter`]
+pub struct ContainingIterator<'map, I> {
+    start_sort: CachedIndex<'map>,
+    range_stream: CachedVector<'map, 2>,
+    positions: I,
+    ranges: Option<components::RowIterator<'map, 2>>,
+    index: usize,
+    current: Option<(usize, usize)>,
+    last: Option<usize>,
+}
+
+impl<'map, I> ContainingIterator<'map, I> {
+    /// Restarts the walk at the last range starting at or before `position`.
+    fn seek(&mut self, position: usize) {
+        self.index = self.start_sort
+            .get_floor(position as i64)
+            .map(|(_, i)| i as usize)
+            .unwrap_or(0);
+
+        let mut ranges = self.range_stream.iter_from(self.index);
+        self.current = ranges.next().map(|[start, end]| (start as usize, end as usize));
+        self.ranges = Some(ranges);
+    }
+}
+
+impl<'map, I> Iterator for ContainingIterator<'map, I> where I: Iterator<Item = usize> {
+    type Item = Option<usize>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let position = self.positions.next()?;
+
+        if self.range_stream.len() == 0 {
+            return Some(None);
+        }
+
+        match self.last {
+            Some(last) if last <= position => (),
+            _ => self.seek(position),
+        }
+        self.last = Some(position);
+
+        // skip all ranges ending before the position, but stay on the last one
+        while let Some((_, end)) = self.current {
+            if end > position {
+                break;
+            }
+            match self.ranges.as_mut().and_then(|r| r.next()) {
+                Some([start, end]) => {
+                    self.index += 1;
+                    self.current = Some((start as usize, end as usize));
+                }
+                None => break,
+            }
+        }
+
+        match self.current {
+            Some((start, end)) if start <= position && position < end => Some(Some(self.index)),
+            _ => Some(None),
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.positions.size_hint()
+    }
+}
+
+/// Error for an invalid range passed to a layer encoder, with the index of the offending range
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RangeError {
+    Empty(usize, (usize, usize)),
+    Unsorted(usize, (usize, usize)),
+    Overlapping(usize, (usize, usize)),
+}
+
+impl fmt::Display for RangeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Empty(i, (s, e)) => write!(f, "range {} ({}, {}) is empty", i, s, e),
+            Self::Unsorted(i, (s, e)) => write!(f, "range {} ({}, {}) starts before the previous range", i, s, e),
+            Self::Overlapping(i, (s, e)) => write!(f, "range {} ({}, {}) overlaps the previous range", i, s, e),
+        }
+    }
+}
+
+impl error::Error for RangeError {}
+
+/// Checks that the range at `index` is non-empty and does not start before `previous`.
+/// Unless `overlapping` is set, it must also start at or after the end of `previous`.
+pub(crate) fn check_range(index: usize, range: (usize, usize), previous: Option<(usize, usize)>, overlapping: bool) -> Result<(), RangeError> {
+    let (start, end) = range;
+
+    if start >= end {
+        return Err(RangeError::Empty(index, range));
+    }
+
+    match previous {
+        Some((pstart, _)) if start < pstart => Err(RangeError::Unsorted(index, range)),
+        Some((_, pend)) if !overlapping && start < pend => Err(RangeError::Overlapping(index, range)),
+        _ => Ok(()),
+    }
+}
+
+/// Layer of possibly overlapping and nested spans, e.g. named entities.
+///
+/// Spans are sorted by their start position. In addition to the components of a
+/// segmentation layer, the header stores the length of the longest span in `dim2`,
+/// which bounds the search for spans containing a position.
+#[derive(Debug)]
+pub struct SpanLayer<'map> {
+    pub base: Uuid,
+    storage: Box<dyn Storage>,
+    pub name: String,
+    pub(crate) header: &'map container::Header,
+    comment: Option<&'map str>,
+    range_stream: components::CachedVector<'map, 2>,
+    start_sort: components::CachedIndex<'map>,
+    end_sort: components::CachedIndex<'map>,
+}
+
+impl<'map> SpanLayer<'map> {
+    pub fn comment(&self) -> Option<&'map str> {
+        self.comment
+    }
+
+    pub fn uuid(&self) -> Uuid {
+        self.header.uuid()
+    }
+
+    pub fn cache_stats(&self) -> CacheStats {
+        self.range_stream.cache_stats()
+            + self.start_sort.cache_stats()
+            + self.end_sort.cache_stats()
+    }
+
+    pub fn contains_end(&self, end: usize) -> bool {
+        self.end_sort.contains_key(end as i64)
+    }
+
+    pub fn contains_start(&self, start: usize) -> bool {
+        self.start_sort.contains_key(start as i64)
+    }
+
+    /// Finds the indices of all spans containing baselayer position `position`
+    pub fn find_containing(&self, position: usize) -> Vec<usize> {
+        self.find_overlapping(position, position + 1)
+    }
+
+    /// Finds the indices of all spans overlapping the baselayer range `start..end`
+    pub fn find_overlapping(&self, start: usize, end: usize) -> Vec<usize> {
+        // only spans starting in (start - max_len, end) can overlap the range
+        let first = self.partition_point(|s| s + self.max_len() <= start);
+        let last = self.partition_point(|s| s < end);
+
+        (first..last)
+            .filter(|i| self.get_unchecked(*i).1 > start)
+            .collect()
+    }
+
+    pub fn get(&self, index: usize) -> Option<(usize, usize)> {
+        if index < self.len() {
+            Some(self.get_unchecked(index))
+        } else {
+            None
+        }
+    }
+
+    pub fn get_unchecked(&self, index: usize) -> (usize, usize) {
+        let row = self.range_stream.get_row_unchecked(index);
+        (row[0] as usize, row[1] as usize)
+    }
+
+    pub fn iter(&self) -> SegmentationLayerIterator<'map> {
+        SegmentationLayerIterator {
+            ranges: self.range_stream.iter()
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.header.dim1()
+    }
+
+    /// Length of the longest span
+    pub fn max_len(&self) -> usize {
+        self.header.dim2()
+    }
+
+    pub fn prefetch_range(&self, start: usize, end: usize) {
+        storage::will_need(&*self.storage, self.range_stream.raw_range(start, end));
+        self.range_stream.prefetch(start, end);
+    }
+
+    /// Returns the index of the first span whose start does not satisfy `pred`.
+    fn partition_point(&self, pred: impl Fn(usize) -> bool) -> usize {
+        let (mut lo, mut hi) = (0, self.len());
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            if pred(self.get_unchecked(mid).0) {
+                lo = mid + 1;
+            } else {
+                hi = mid;
+            }
+        }
+        lo
+    }
+
+    /// Encodes a span layer from `n` non-empty ranges sorted by their start. Ranges may overlap.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "info", skip_all, fields(name = %name, n = n, compressed = compressed)))]
+    pub fn encode_to_file<W, I>(file: W, values: I, n: usize, name: String, base: Uuid, compressed: bool, comment: &str) -> Result<Self, EncodeError> where W: EncodeTarget, I: Iterator<Item=(usize, usize)> {
+        let vectype = if compressed { components::Type::VectorDelta } else { components::Type::Vector };
+        let idxtype = if compressed { components::Type::IndexComp } else { components::Type::Index };
+
+        // all spans are needed in memory to sort them by their end for the EndSort index
+        let spans: Vec<(usize, usize)> = values.take(n).collect();
+        if spans.len() != n {
+            return Err(EncodeError::LengthMismatch { expected: n, found: spans.len() });
+        }
+
+        let mut previous = None;
+        for (i, span) in spans.iter().enumerate() {
+            check_range(i, *span, previous, true)?;
+            previous = Some(*span);
+        }
+        let max_len = spans.iter().map(|(s, e)| e - s).max().unwrap_or(0);
+
+        let mut ends: Vec<(i64, i64)> = spans.iter().enumerate().map(|(i, (_, e))| (*e as i64, i as i64)).collect();
+        ends.sort();
+
+        let builder = ContainerBuilder::new_into_file(name, file, 3 + ContainerBuilder::comment_capacity(comment))
+            .edit_header(| h | {
+                h.ziggurat_type(container::Type::SpanLayer)
+                    .dim1(n)
+                    .dim2(max_len)
+                    .base1(Some(base));
+            })
+            .add_component("RangeStream", vectype, | bom_entry, file | {
+                unsafe {
+                    let values = spans.iter().map(|(s, e)| [*s as i64, *e as i64]);
+                    if compressed {
+                        Vector::encode_delta_to_container_file(values, n, file, bom_entry, bom_entry.offset as u64)
+                    } else {
+                        Vector::encode_uncompressed_to_container_file(values.flatten(), n, 2, file, bom_entry, bom_entry.offset as u64)
+                    }
+                }
+            })
+            .add_component("StartSort", idxtype, | bom_entry, file | {
+                unsafe {
+                    let values = spans.iter()
+                        .enumerate()
+                        .map(|(i, (start, _))| (*start as i64, i as i64));
+
+                    if compressed {
+                        Index::encode_compressed_to_container_file(values, n, file, bom_entry, bom_entry.offset as u64)
+                    } else {
+                        Index::encode_uncompressed_to_container_file(values, n, file, bom_entry, bom_entry.offset as u64)
+                    }
+                }
+            })
+            .add_component("EndSort", idxtype, | bom_entry, file | {
+                unsafe {
+                    if compressed {
+                        Index::encode_compressed_to_container_file(ends.iter().copied(), n, file, bom_entry, bom_entry.offset as u64)
+                    } else {
+                        Index::encode_uncompressed_to_container_file(ends.iter().copied(), n, file, bom_entry, bom_entry.offset as u64)
+                    }
+                }
+            });
+
+        Ok(builder.comment(comment).build()?.try_into().expect("SpanLayer returned by its constructor is inconsistent"))
+    }
+}
+
+impl<'map> TryFrom<Container<'map>> for SpanLayer<'map> {
+    type Error = container::TryFromError;
+
+    fn try_from(container: Container<'map>) -> Result<Self, Self::Error> {
+        let header = *container.header();
+        match header.container_type() {
+            container::Type::SpanLayer => {
+                let base = get_container_base!(container, SpanLayer);
+
+                let range_stream =
+                    check_and_return_component!(container, "RangeStream", Vector)?;
+                if range_stream.width() != 2 || range_stream.len() != header.dim1() {
+                    return Err(Self::Error::WrongComponentDimensions("RangeStream"));
+                }
+                let range_stream = CachedVector::<2>::new(range_stream)
+                    .expect("width already checked, should be 2");
+
+                let start_sort = check_and_return_component!(container, "StartSort", Index)?;
+                if start_sort.len() != header.dim1() {
+                    return Err(Self::Error::WrongComponentDimensions("StartSort"));
+                }
+                let start_sort = CachedIndex::new(start_sort);
+
+                let end_sort = check_and_return_component!(container, "EndSort", Index)?;
+                if end_sort.len() != header.dim1() {
+                    return Err(Self::Error::WrongComponentDimensions("EndSort"));
+                }
+                let end_sort = CachedIndex::new(end_sort);
+
+                let comment = container.comment();
+                let (name, storage, header, _) = container.into_raw_parts();
+
+                Ok(Self {
+                    base,
+                    storage,
+                    name,
+                    header,
+                    comment,
+                    range_stream,
+                    start_sort,
+                    end_sort,
+                })
+            }
+
+            _ => Err(Self::Error::WrongContainerType),
+        }
+    }
+}
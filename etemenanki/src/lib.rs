@@ -7,28 +7,75 @@ extern crate test;
 
 use std::{
     collections::{hash_map, HashMap},
-    error, fmt,
-    fs::File,
+    error, fmt, fs,
     io, ops,
     path::{Path, PathBuf},
 };
 
+use components::CacheStats;
 use container::Container;
-use memmap2::Mmap;
+use lock::{DatastoreLock, LockMode};
+use manifest::{Manifest, ManifestEntry};
+use registry::{ContainerRegistry, SkippedContainer, UnknownPolicy};
+use storage::Storage;
 use uuid::Uuid;
 
+pub mod build;
+pub mod check;
+pub mod compare;
 pub mod components;
+pub mod concordance;
 pub mod container;
+pub mod cooccurrence;
+#[cfg(feature = "cwb")]
+pub mod cwb;
+pub mod dtm;
+#[cfg(feature = "arrow")]
+pub mod export;
+pub mod fallible;
+pub mod federation;
+pub mod filter;
+pub mod frequency;
+pub mod graph;
+pub mod group;
+pub mod import;
 pub mod layers;
+pub mod lock;
+pub mod manifest;
+pub mod materialize;
+pub mod pagination;
+pub mod prelude;
+pub mod progress;
+#[cfg(test)]
+mod proptests;
+pub mod query;
+pub mod query_cache;
+pub mod registry;
+pub mod sample;
+pub mod selection;
+pub mod server;
+pub mod sources;
+pub mod storage;
+pub mod subcorpus;
 #[cfg(test)]
 mod tests;
 pub mod variables;
+pub mod vrt;
+
+pub use concordance::Concordance;
+pub use layers::{HasBase, Layer};
+pub use selection::Selection;
+pub use variables::{Variable, VariableValue};
 
 #[derive(Debug)]
 pub struct Datastore<'map> {
     path: PathBuf,
     layers_by_uuid: HashMap<Uuid, layers::Layer<'map>>,
     uuids_by_name: HashMap<String, Uuid>,
+    extensions: HashMap<String, Container<'map>>,
+    skipped: Vec<SkippedContainer>,
+    /// Shared lock of a datastore opened read-only
+    lock: Option<DatastoreLock>,
 }
 
 fn find_objects(path: &Path, valid_paths: &mut Vec<PathBuf>) -> io::Result<()> {
@@ -47,6 +94,22 @@ fn find_objects(path: &Path, valid_paths: &mut Vec<PathBuf>) -> io::Result<()> {
     Ok(())
 }
 
+fn link_or_copy(from: &Path, to: &Path) -> io::Result<()> {
+    match fs::hard_link(from, to) {
+        Ok(()) => Ok(()),
+        Err(_) => fs::copy(from, to).map(|_| ()),
+    }
+}
+
+/// Recursively collects the paths of all container files (`.zigv`, `.zigl`) below `path`.
+///
+/// Files still being written as a [`PendingFile`](lock::PendingFile) are not included.
+pub fn find_containers<P: AsRef<Path>>(path: P) -> io::Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+    find_objects(path.as_ref(), &mut paths)?;
+    Ok(paths)
+}
+
 impl<'map> Datastore<'map> {
     pub fn layer_by_name<S: AsRef<str>>(&self, name: S) -> Option<&layers::Layer<'map>> {
         match self.uuids_by_name.get(name.as_ref()) {
@@ -55,10 +118,44 @@ impl<'map> Datastore<'map> {
         }
     }
 
+    /// Mutable access to a layer, e.g. to add [virtual variables](variables::VirtualVariable) to it
+    pub fn layer_by_name_mut<S: AsRef<str>>(&mut self, name: S) -> Option<&mut layers::Layer<'map>> {
+        match self.uuids_by_name.get(name.as_ref()) {
+            Some(u) => self.layers_by_uuid.get_mut(u),
+            None => None,
+        }
+    }
+
     pub fn layer_by_uuid(&self, uuid: Uuid) -> Option<&layers::Layer<'map>> {
         self.layers_by_uuid.get(&uuid)
     }
 
+    /// Base layer of a layer or variable, `None` for primary layers.
+    pub fn base_of<T: HasBase + ?Sized>(&self, item: &T) -> Option<&layers::Layer<'map>> {
+        self.layers_by_uuid.get(&item.base_uuid()?)
+    }
+
+    /// All layers a layer or variable depends on, starting with its base layer and
+    /// ending with a primary layer.
+    ///
+    /// The chain is empty for primary layers. Base layers are checked when the datastore
+    /// is opened, so every chain ends at a primary layer of the same datastore.
+    pub fn base_chain<T: HasBase + ?Sized>(&self, item: &T) -> Vec<&layers::Layer<'map>> {
+        let mut chain: Vec<&layers::Layer<'map>> = Vec::new();
+        let mut next = self.base_of(item);
+
+        // the length limit only guards against cyclic bases in malformed datastores
+        while let Some(layer) = next {
+            if chain.len() == self.layers_by_uuid.len() {
+                break;
+            }
+            chain.push(layer);
+            next = self.base_of(layer);
+        }
+
+        chain
+    }
+
     pub fn layer_names(&self) -> hash_map::Keys<String, Uuid> {
         self.uuids_by_name.keys()
     }
@@ -68,21 +165,147 @@ impl<'map> Datastore<'map> {
     }
 
     pub fn open<P: AsRef<Path>>(path: P) -> Result<Datastore<'map>, DatastoreError> {
+        Self::open_with_registry(path, &ContainerRegistry::default())
+    }
+
+    /// Opens a datastore and holds a shared [lock](lock) on it until the datastore is dropped.
+    ///
+    /// Waits for a writer holding the exclusive lock to finish, and writers using
+    /// [`lock_for_writing`](Self::lock_for_writing) can't change the datastore while it is open.
+    pub fn open_read_only<P: AsRef<Path>>(path: P) -> Result<Datastore<'map>, DatastoreError> {
+        let lock = DatastoreLock::acquire(path.as_ref(), LockMode::Shared)?;
+        let mut datastore = Self::open(path)?;
+        datastore.lock = Some(lock);
+        Ok(datastore)
+    }
+
+    /// Whether the datastore was opened with [`open_read_only`](Self::open_read_only)
+    pub fn is_read_only(&self) -> bool {
+        self.lock.is_some()
+    }
+
+    /// Takes the exclusive [lock](lock) of the datastore for adding or replacing containers.
+    ///
+    /// Fails with [`DatastoreError::ReadOnly`] if the datastore was opened read-only and
+    /// with [`DatastoreError::Locked`] if another reader or writer holds the lock.
+    pub fn lock_for_writing(&self) -> Result<DatastoreLock, DatastoreError> {
+        if self.is_read_only() {
+            return Err(DatastoreError::ReadOnly);
+        }
+        if !self.path.is_dir() {
+            return Err(DatastoreError::ConsistencyError("only local datastores can be locked"));
+        }
+        DatastoreLock::try_acquire(&self.path, LockMode::Exclusive)?.ok_or(DatastoreError::Locked)
+    }
+
+    /// Opens a datastore and checks all strings once with [`validate_utf8`](Self::validate_utf8).
+    ///
+    /// String accessors don't check the encoding of the strings they return, so this should
+    /// be used for datastores of unknown origin.
+    pub fn open_validated<P: AsRef<Path>>(path: P) -> Result<Datastore<'map>, DatastoreError> {
+        let datastore = Self::open(path)?;
+        datastore.validate_utf8()?;
+        Ok(datastore)
+    }
+
+    /// Opens a datastore, handling containers of types not built into this library
+    /// as configured in `registry`.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "info", skip_all, fields(path = %path.as_ref().display())))]
+    pub fn open_with_registry<P: AsRef<Path>>(path: P, registry: &ContainerRegistry) -> Result<Datastore<'map>, DatastoreError> {
         let path = path.as_ref().to_owned();
+
+        // prefer the manifest, fall back to scanning the datastore directory
+        let manifest = match Manifest::read(&path)? {
+            Some(manifest) => manifest,
+            None => Manifest::scan(&path)?,
+        };
+
+        let mut containers = Vec::with_capacity(manifest.containers.len());
+        for entry in manifest.containers {
+            let storage = storage::open_file(path.join(&entry.path))?;
+            containers.push((entry, storage));
+        }
+
+        Self::from_storage(path, containers, registry)
+    }
+
+    /// Opens a read-only datastore served over HTTP.
+    ///
+    /// Since directories can not be listed remotely, the datastore must have a manifest.
+    /// Containers are fetched in blocks with ranged requests, see
+    /// [`HttpStorage`](storage::HttpStorage); if `cache_dir` is given, fetched blocks are
+    /// kept there for subsequent opens.
+    #[cfg(feature = "remote")]
+    pub fn open_url(url: &str, cache_dir: Option<PathBuf>) -> Result<Datastore<'map>, DatastoreError> {
+        let base = url.trim_end_matches('/');
+        let manifest: Manifest =
+            serde_json::from_slice(&storage::http_get(&format!("{}/{}", base, manifest::MANIFEST_FILENAME))?)?;
+
+        let mut containers = Vec::with_capacity(manifest.containers.len());
+        for entry in manifest.containers {
+            let url = format!("{}/{}", base, entry.path.to_string_lossy().replace('\\', "/"));
+            let storage = storage::HttpStorage::open_cached(&url, cache_dir.clone())?;
+            containers.push((entry, Box::new(storage) as Box<dyn Storage>));
+        }
+
+        Self::from_storage(PathBuf::from(url), containers, &ContainerRegistry::default())
+    }
+
+    /// Opens a datastore whose containers are held in memory, e.g. fetched by a browser client.
+    ///
+    /// `load` returns the bytes of each container listed in `manifest`.
+    pub fn from_bytes<F>(manifest: Manifest, mut load: F) -> Result<Datastore<'map>, DatastoreError>
+    where
+        F: FnMut(&ManifestEntry) -> io::Result<Vec<u8>>,
+    {
+        let mut containers = Vec::with_capacity(manifest.containers.len());
+        for entry in manifest.containers {
+            let bytes = load(&entry)?;
+            containers.push((entry, Box::new(storage::Memory::new(&bytes)) as Box<dyn Storage>));
+        }
+
+        Self::from_storage(PathBuf::new(), containers, &ContainerRegistry::default())
+    }
+
+    fn from_storage(
+        path: PathBuf,
+        storages: Vec<(ManifestEntry, Box<dyn Storage>)>,
+        registry: &ContainerRegistry,
+    ) -> Result<Datastore<'map>, DatastoreError> {
         let mut containers = HashMap::new();
 
-        let mut paths = Vec::new();
-        find_objects(&path, &mut paths)?;
+        for (entry, storage) in storages {
+            #[cfg(feature = "tracing")]
+            tracing::debug!(name = %entry.name, path = %entry.path.display(), bytes = storage.bytes().len(), "opening container");
 
-        for path in paths {
-            let file = File::open(&path)?;
-            let mmap = unsafe { Mmap::map(&file)? };
-            let name = path.file_stem().unwrap().to_str().unwrap().to_owned();
-            let container = Container::from_mmap(mmap, name)?;
+            let container = Container::from_storage(storage, entry.name)?;
+
+            if container.header().uuid() != entry.uuid {
+                return Err(DatastoreError::ConsistencyError(
+                    "container UUID differs from manifest",
+                ));
+            }
 
             containers.insert(container.header().uuid(), container);
         }
 
+        // set aside all containers not instantiated below
+        let mut extensions = HashMap::new();
+        let mut skipped = Vec::new();
+        for (_, container) in containers.extract_if(|_, c| !ContainerRegistry::is_builtin(c.header())) {
+            let type_code = container.header().type_code();
+
+            if let Some(handler) = registry.handler(type_code) {
+                handler(&container)?;
+                extensions.insert(container.name().to_owned(), container);
+            } else if registry.unknown_policy() == UnknownPolicy::Skip {
+                skipped.push(SkippedContainer { name: container.name().to_owned(), type_code });
+            } else {
+                return Err(DatastoreError::UnknownContainerType(container.name().to_owned(), type_code));
+            }
+        }
+        skipped.sort_by(|a, b| a.name.cmp(&b.name));
+
         let mut layers_by_uuid = HashMap::new();
         let mut uuids_by_name = HashMap::new();
 
@@ -98,27 +321,36 @@ impl<'map> Datastore<'map> {
             uuids_by_name.insert(name, uuid);
         }
 
-        // next instantiate all segmentation layers (that are on top of the primary layers)
-        while containers
-            .values()
-            .any(|c| c.header().container_type() == container::Type::SegmentationLayer)
-        {
-            let seglayers = containers
-                .extract_if(|_, c| c.header().container_type() == container::Type::SegmentationLayer);
+        // next instantiate all segmentation and span layers (that are on top of the primary layers)
+        let is_secondary = |c: &Container| matches!(
+            c.header().container_type(),
+            container::Type::SegmentationLayer | container::Type::SpanLayer
+        );
+
+        while containers.values().any(is_secondary) {
+            let seglayers = containers.extract_if(|_, c| is_secondary(c));
 
             let mut temp_by_uuid = Vec::new();
             for (uuid, container) in seglayers {
                 let name = container.name().to_owned();
 
-                let seglayer: layers::SegmentationLayer = container.try_into()?;
-                if !layers_by_uuid.contains_key(&seglayer.base) {
+                let (base, layer) = match container.header().container_type() {
+                    container::Type::SpanLayer => {
+                        let spanlayer: layers::SpanLayer = container.try_into()?;
+                        (spanlayer.base, layers::Layer::new_span(spanlayer))
+                    }
+                    _ => {
+                        let seglayer: layers::SegmentationLayer = container.try_into()?;
+                        (seglayer.base, layers::Layer::new_segmentation(seglayer))
+                    }
+                };
+
+                if !layers_by_uuid.contains_key(&base) {
                     return Err(DatastoreError::ConsistencyError(
                         "secondary layer with base layer not in datastore",
                     ));
                 }
 
-                let layer = layers::Layer::new_segmentation(seglayer);
-
                 temp_by_uuid.push((uuid, layer));
                 uuids_by_name.insert(name, uuid);
             }
@@ -126,6 +358,21 @@ impl<'map> Datastore<'map> {
             layers_by_uuid.extend(temp_by_uuid);
         }
 
+        // source documents are kept next to the variables of their layer
+        let sources = containers.extract_if(|_, c| c.header().container_type() == container::Type::SourceVariable);
+
+        for (_, container) in sources {
+            let source: sources::SourceVariable = container.try_into()?;
+            let base = layers_by_uuid.get_mut(&source.base()).ok_or(DatastoreError::ConsistencyError(
+                "source documents with base layer not in datastore",
+            ))?;
+            if base.add_source(source.name.clone(), source).is_err() {
+                return Err(DatastoreError::ConsistencyError(
+                    "source documents inconsistent with base layer",
+                ));
+            }
+        }
+
         let vars = containers.extract_if(|_, c| c.header().class() == 'V');
 
         for (_, container) in vars {
@@ -150,16 +397,166 @@ impl<'map> Datastore<'map> {
             }
         }
 
+        #[cfg(feature = "tracing")]
+        tracing::info!(layers = layers_by_uuid.len(), extensions = extensions.len(), skipped = skipped.len(), "opened datastore");
+
         Ok(Datastore {
             path,
             layers_by_uuid,
             uuids_by_name,
+            extensions,
+            skipped,
+            lock: None,
         })
     }
 
+    /// Returns a container accepted by a handler of the `ContainerRegistry` the datastore was opened with.
+    pub fn extension_by_name<S: AsRef<str>>(&self, name: S) -> Option<&Container<'map>> {
+        self.extensions.get(name.as_ref())
+    }
+
+    /// Returns the containers left out because their type is not supported.
+    pub fn skipped_containers(&self) -> &[SkippedContainer] {
+        &self.skipped
+    }
+
+    /// Returns cache statistics for every layer and variable in the datastore.
+    ///
+    /// Each entry holds the layer name, the variable name (`None` for the caches of the
+    /// layer itself) and the statistics, sorted by layer and variable name.
+    pub fn cache_stats(&self) -> Vec<(&str, Option<&str>, CacheStats)> {
+        let mut stats = Vec::new();
+
+        for (name, uuid) in self.uuids_by_name.iter() {
+            let layer = &self.layers_by_uuid[uuid];
+            stats.push((name.as_str(), None, layer.cache_stats()));
+
+            for varname in layer.variable_names() {
+                let var = layer.variable_by_name(varname).unwrap();
+                stats.push((name.as_str(), Some(varname.as_str()), var.cache_stats()));
+            }
+        }
+
+        stats.sort_by(|a, b| (a.0, a.1).cmp(&(b.0, b.1)));
+        stats
+    }
+
     pub fn path(&self) -> &Path {
         self.path.as_path()
     }
+
+    /// Clones the datastore into the new or empty directory `path` without copying container data.
+    ///
+    /// Container files are hard-linked into `path`, falling back to a copy (which is a reflink
+    /// on filesystems that support it) if linking fails, e.g. across devices. The snapshot gets
+    /// a manifest of its own, which is also returned.
+    ///
+    /// Since linked files are shared, variables must be replaced in the snapshot by writing a
+    /// new file and renaming it over the old one, never by modifying a container in place.
+    pub fn snapshot_to<P: AsRef<Path>>(&self, path: P) -> Result<Manifest, DatastoreError> {
+        let target = path.as_ref();
+
+        if !self.path.is_dir() {
+            return Err(DatastoreError::ConsistencyError("only local datastores can be snapshotted"));
+        }
+        if target.exists() && target.read_dir()?.next().is_some() {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, "snapshot directory is not empty").into());
+        }
+
+        let manifest = match Manifest::read(&self.path)? {
+            Some(manifest) => manifest,
+            None => Manifest::scan(&self.path)?,
+        };
+
+        for entry in manifest.containers.iter() {
+            let to = target.join(&entry.path);
+            if let Some(parent) = to.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            link_or_copy(&self.path.join(&entry.path), &to)?;
+        }
+
+        fs::create_dir_all(target)?;
+        manifest.write(target)?;
+
+        Ok(manifest)
+    }
+
+    /// Checks that all strings of all variables are valid UTF-8 and reports the first invalid one.
+    ///
+    /// This reads all string data of the datastore, corrupt strings can still be inspected
+    /// with the `get_lossy` accessors.
+    pub fn validate_utf8(&self) -> Result<(), DatastoreError> {
+        for layer in self.layers_by_uuid.values() {
+            for name in layer.variable_names() {
+                layer[name].validate_utf8()
+                    .map_err(|e| DatastoreError::InvalidUtf8(name.clone(), e))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks the invariants between containers that opening the datastore does not read
+    /// the data for, e.g. that all segments lie within their base layer and all pointer
+    /// heads within their layer, see [`check`].
+    pub fn check(&self) -> check::CheckReport {
+        check::check(self)
+    }
+
+    /// Builds a datastore at `path` from raw `texts` split into sentences and tokens by
+    /// `tokenizer`, see [`import`].
+    ///
+    /// The datastore gets a primary layer "primary" with the tokens in the variable "word"
+    /// and a segmentation layer "s" of the sentences, which never span two texts. `path`
+    /// must not exist or be empty. All tokens are kept in memory until they are encoded.
+    /// An interrupted import is continued by [`import::resume_import_text`].
+    pub fn from_text<P, I, S, T>(path: P, texts: I, tokenizer: &T) -> Result<Datastore<'map>, import::ImportError>
+    where
+        P: AsRef<Path>,
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+        T: import::Tokenizer + ?Sized,
+    {
+        import::import_text(path, texts, tokenizer)
+    }
+
+    /// Writes the primary layer and the segmentation layers on top of it as VRT, see
+    /// [`vrt::VrtOptions`] for the selection of columns and tags.
+    ///
+    /// The writer is written to in many small pieces and should be buffered.
+    pub fn export_vrt<W: io::Write>(&self, writer: W, options: &vrt::VrtOptions) -> Result<(), vrt::VrtError> {
+        vrt::export(self, writer, options)
+    }
+
+    /// Returns the named subcorpora saved in the datastore, see [`subcorpus::Subcorpus`].
+    pub fn subcorpora(&self) -> Result<subcorpus::Subcorpora<'_, 'map>, subcorpus::SubcorpusError> {
+        if !self.path.is_dir() {
+            return Err(subcorpus::SubcorpusError::NotLocal);
+        }
+        Ok(subcorpus::Subcorpora::new(self))
+    }
+
+    /// Selects the variables `names` for joint decoding, see [`selection::Selection`].
+    ///
+    /// The variables are looked up on the one layer that has all of them.
+    pub fn select<S: AsRef<str>>(&self, names: &[S]) -> Result<selection::Selection<'_, 'map>, selection::SelectionError> {
+        let mut candidates = self.layers_by_uuid.values()
+            .filter(|layer| names.iter().all(|n| layer.variable_by_name(n).is_some()));
+
+        match (candidates.next(), candidates.next()) {
+            (Some(layer), None) => selection::Selection::new(layer, names),
+            (Some(_), Some(_)) => Err(selection::SelectionError::AmbiguousLayer),
+            (None, _) => {
+                let unknown = names.iter()
+                    .find(|n| self.layers_by_uuid.values().all(|layer| layer.variable_by_name(n).is_none()));
+
+                match unknown {
+                    Some(name) => Err(selection::SelectionError::UnknownVariable(name.as_ref().to_owned())),
+                    None => Err(selection::SelectionError::DifferentLayers),
+                }
+            }
+        }
+    }
 }
 
 impl<'map> ops::Index<Uuid> for Datastore<'map> {
@@ -192,6 +589,14 @@ pub enum DatastoreError {
     RawContainerError(container::Error),
     ContainerInstantiationError(container::TryFromError),
     ConsistencyError(&'static str),
+    ManifestError(serde_json::Error),
+    UnknownContainerType(String, [u8; 3]),
+    /// A string of the named variable is not valid UTF-8
+    InvalidUtf8(String, components::InvalidUtf8),
+    /// The datastore was opened read-only
+    ReadOnly,
+    /// Another process holds a conflicting lock on the datastore
+    Locked,
 }
 
 impl fmt::Display for DatastoreError {
@@ -201,6 +606,13 @@ impl fmt::Display for DatastoreError {
             DatastoreError::RawContainerError(e) => write!(f, "{}", e),
             DatastoreError::ContainerInstantiationError(e) => write!(f, "{}", e),
             DatastoreError::ConsistencyError(e) => write!(f, "consistency error: {}", e),
+            DatastoreError::ManifestError(e) => write!(f, "invalid manifest: {}", e),
+            DatastoreError::UnknownContainerType(name, code) => {
+                write!(f, "container {} has unknown type {}", name, String::from_utf8_lossy(code))
+            }
+            DatastoreError::InvalidUtf8(name, e) => write!(f, "variable {}: {}", name, e),
+            DatastoreError::ReadOnly => write!(f, "datastore is opened read-only"),
+            DatastoreError::Locked => write!(f, "datastore is locked by another reader or writer"),
         }
     }
 }
@@ -211,6 +623,8 @@ impl error::Error for DatastoreError {
             DatastoreError::IoError(e) => Some(e),
             DatastoreError::RawContainerError(e) => Some(e),
             DatastoreError::ContainerInstantiationError(e) => Some(e),
+            DatastoreError::ManifestError(e) => Some(e),
+            DatastoreError::InvalidUtf8(_, e) => Some(e),
             _ => None,
         }
     }
@@ -228,6 +642,12 @@ impl From<container::Error> for DatastoreError {
     }
 }
 
+impl From<serde_json::Error> for DatastoreError {
+    fn from(value: serde_json::Error) -> Self {
+        DatastoreError::ManifestError(value)
+    }
+}
+
 impl From<container::TryFromError> for DatastoreError {
     fn from(value: container::TryFromError) -> Self {
         DatastoreError::ContainerInstantiationError(value)
@@ -0,0 +1,256 @@
+//! Coordination of processes reading and writing the same datastore directory.
+//!
+//! Locks are advisory: a [`DatastoreLock`] is an OS file lock on the [`LOCK_FILE`] of a
+//! datastore and only excludes other processes that take the lock as well. Readers which
+//! must not see the datastore change, e.g. a server, open it with
+//! [`Datastore::open_read_only`](crate::Datastore::open_read_only) and hold a shared lock
+//! until it is dropped. Writers take the exclusive lock with
+//! [`Datastore::lock_for_writing`](crate::Datastore::lock_for_writing), which fails instead
+//! of waiting while the datastore is read.
+//!
+//! New containers are encoded into a [`PendingFile`], which can be passed to any
+//! `encode_to_file` as it is, and renamed to their final name once the encoder has finished.
+//! No reader ever opens a half-written container: pending files don't have a container
+//! extension and are skipped when a datastore directory is scanned. A pending file is locked
+//! by its writer, so [`remove_stale`] can tell those left behind by a crashed writer from
+//! those still being encoded. Nothing is removed implicitly, e.g. when a datastore is opened.
+//!
+//! ```no_run
+//! # use std::fs::File;
+//! # use etemenanki::{lock::PendingFile, variables::IndexedStringVariable, Datastore};
+//! let datastore = Datastore::open("dickens").unwrap();
+//! let lock = datastore.lock_for_writing().unwrap();
+//!
+//! let pending = PendingFile::create("dickens/lower.zigv").unwrap();
+//! let words = datastore["primary"]["word"].as_indexed_string().unwrap();
+//! let lower = words.iter().map(|w| w.to_lowercase());
+//! // the file is renamed to dickens/lower.zigv once the container is complete
+//! IndexedStringVariable::encode_to_file(pending, lower, words.len(), "lower".to_owned(), datastore["primary"].uuid(), true, "").unwrap();
+//! drop(lock);
+//! ```
+
+use std::fs::{self, File, TryLockError};
+use std::io::{self, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use crate::container::EncodeTarget;
+use crate::storage::Storage;
+
+/// Name of the file locked in a datastore directory, it is created on first use
+pub const LOCK_FILE: &str = ".lock";
+
+/// Extension appended to the name of a [`PendingFile`] until it is committed
+pub const PENDING_EXTENSION: &str = "tmp";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockMode {
+    /// Held by any number of readers at once
+    Shared,
+    /// Held by a single writer and no readers
+    Exclusive,
+}
+
+/// Advisory lock on a datastore directory, released when dropped
+#[derive(Debug)]
+pub struct DatastoreLock {
+    file: File,
+    mode: LockMode,
+}
+
+impl DatastoreLock {
+    fn open(datastore: &Path) -> io::Result<File> {
+        File::options().read(true).write(true).create(true).truncate(false).open(datastore.join(LOCK_FILE))
+    }
+
+    /// Locks the datastore in `datastore`, waiting until conflicting locks are released
+    pub fn acquire<P: AsRef<Path>>(datastore: P, mode: LockMode) -> io::Result<Self> {
+        let file = Self::open(datastore.as_ref())?;
+        match mode {
+            LockMode::Shared => file.lock_shared()?,
+            LockMode::Exclusive => file.lock()?,
+        }
+        Ok(Self { file, mode })
+    }
+
+    /// Locks the datastore in `datastore`, `None` if a conflicting lock is held
+    pub fn try_acquire<P: AsRef<Path>>(datastore: P, mode: LockMode) -> io::Result<Option<Self>> {
+        let file = Self::open(datastore.as_ref())?;
+        let result = match mode {
+            LockMode::Shared => file.try_lock_shared(),
+            LockMode::Exclusive => file.try_lock(),
+        };
+        match result {
+            Ok(()) => Ok(Some(Self { file, mode })),
+            Err(TryLockError::WouldBlock) => Ok(None),
+            Err(TryLockError::Error(e)) => Err(e),
+        }
+    }
+
+    pub fn mode(&self) -> LockMode {
+        self.mode
+    }
+}
+
+/// File written under a temporary name and renamed to its final name by
+/// [`commit`](Self::commit). It is deleted if it is dropped before.
+///
+/// The file is exclusively locked until it is committed or dropped, and a second pending
+/// file for the same path can't be created meanwhile.
+///
+/// As an [`EncodeTarget`] it is committed by the encoder when the container is complete.
+#[derive(Debug)]
+pub struct PendingFile {
+    path: PathBuf,
+    pending: PathBuf,
+    file: File,
+    committed: bool,
+}
+
+impl PendingFile {
+    /// Creates the pending file for `path`, failing if a file of that name or another
+    /// pending file for it exists
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        if path.exists() {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} exists", path.display())));
+        }
+        Self::open(path, File::options().read(true).write(true).create_new(true))
+    }
+
+    /// Creates the pending file for `path`, which replaces the file at `path` when committed.
+    /// A pending file left behind for it by a crashed writer is truncated, one that is still
+    /// being written fails with [`io::ErrorKind::WouldBlock`].
+    pub fn replace<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        // truncated only once it is locked, it may belong to a running writer
+        let pending = Self::open(path.as_ref(), File::options().read(true).write(true).create(true).truncate(false))?;
+        pending.file.set_len(0)?;
+        Ok(pending)
+    }
+
+    fn open(path: &Path, options: &fs::OpenOptions) -> io::Result<Self> {
+        let mut name = path.file_name().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "path has no file name"))?.to_owned();
+        name.push(".");
+        name.push(PENDING_EXTENSION);
+        let pending = path.with_file_name(name);
+
+        let file = options.open(&pending)?;
+        match file.try_lock() {
+            Ok(()) => Ok(Self { path: path.to_owned(), pending, file, committed: false }),
+            Err(TryLockError::WouldBlock) => Err(io::Error::new(io::ErrorKind::WouldBlock, format!("{} is being written by another writer", pending.display()))),
+            Err(TryLockError::Error(e)) => Err(e),
+        }
+    }
+
+    /// Handle to the pending file, e.g. to pass to an encoder
+    pub fn file(&self) -> io::Result<File> {
+        self.file.try_clone()
+    }
+
+    /// Final path of the file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Flushes the file to disk and renames it to its final name
+    pub fn commit(mut self) -> io::Result<PathBuf> {
+        self.file.sync_all()?;
+        fs::rename(&self.pending, &self.path)?;
+        self.committed = true;
+        Ok(self.path.clone())
+    }
+}
+
+impl Write for PendingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        (&self.file).write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        (&self.file).flush()
+    }
+}
+
+impl Seek for PendingFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        (&self.file).seek(pos)
+    }
+}
+
+impl EncodeTarget for PendingFile {
+    fn set_len(&mut self, len: u64) -> io::Result<()> {
+        self.file.set_len(len)
+    }
+
+    fn map_range(&mut self, offset: u64, len: usize) -> io::Result<Box<dyn Storage>> {
+        self.file.map_range(offset, len)
+    }
+
+    fn into_storage(self, len: usize) -> io::Result<Box<dyn Storage>> {
+        // a mapping stays valid when its file is renamed
+        let storage = self.file()?.into_storage(len)?;
+        self.commit()?;
+        Ok(storage)
+    }
+}
+
+impl Drop for PendingFile {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = fs::remove_file(&self.pending);
+        }
+    }
+}
+
+/// Whether `path` is named like the [`PendingFile`] of a container, `<name>.zigv.tmp` or
+/// `<name>.zigl.tmp`
+fn is_pending_container(path: &Path) -> bool {
+    let (Some(stem), Some(extension)) = (path.file_stem(), path.extension()) else {
+        return false;
+    };
+    let container = Path::new(stem).extension().and_then(|e| e.to_str());
+    extension == PENDING_EXTENSION && container.is_some_and(|e| e.len() == 4 && e.starts_with("zig"))
+}
+
+/// Removes the pending containers below the datastore directory `datastore` whose writer
+/// has died and returns their number.
+///
+/// Pending files which are locked by their writer are still being encoded and are kept, as
+/// are all files not named like pending containers. This is never done implicitly, call it
+/// e.g. after a crash or before a snapshot.
+pub fn remove_stale<P: AsRef<Path>>(datastore: P) -> io::Result<usize> {
+    fn find(dir: &Path, pending: &mut Vec<PathBuf>) -> io::Result<()> {
+        for entry in dir.read_dir()? {
+            let path = entry?.path();
+            if path.is_dir() {
+                find(&path, pending)?;
+            } else if is_pending_container(&path) {
+                pending.push(path);
+            }
+        }
+        Ok(())
+    }
+
+    let mut pending = Vec::new();
+    find(datastore.as_ref(), &mut pending)?;
+
+    let mut removed = 0;
+    for path in &pending {
+        // committed by a writer which finished after the directory was read
+        let file = match File::options().write(true).open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+        match file.try_lock() {
+            Ok(()) => (),
+            Err(TryLockError::WouldBlock) => continue,
+            Err(TryLockError::Error(e)) => return Err(e),
+        }
+        match fs::remove_file(path) {
+            Ok(()) => removed += 1,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => (),
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(removed)
+}
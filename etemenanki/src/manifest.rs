@@ -0,0 +1,115 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::container::Container;
+use crate::storage;
+use crate::DatastoreError;
+
+/// File name of the manifest within a datastore directory
+pub const MANIFEST_FILENAME: &str = "manifest.json";
+
+/// Index of all containers in a datastore.
+///
+/// The manifest records the canonical name, the path relative to the datastore
+/// root and the UUID of every container. If present it is used by `Datastore::open`
+/// instead of scanning the datastore directory.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub containers: Vec<ManifestEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    pub name: String,
+    pub path: PathBuf,
+    pub uuid: Uuid,
+}
+
+/// Joins the components of a relative path with `/`, so manifests are portable between platforms.
+fn portable_path(path: &Path) -> String {
+    path.components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+impl Manifest {
+    /// Returns the path of the manifest file of the datastore at `datastore`.
+    pub fn path<P: AsRef<Path>>(datastore: P) -> PathBuf {
+        datastore.as_ref().join(MANIFEST_FILENAME)
+    }
+
+    /// Reads the manifest of the datastore at `datastore`, if there is one.
+    pub fn read<P: AsRef<Path>>(datastore: P) -> Result<Option<Self>, DatastoreError> {
+        let path = Self::path(datastore);
+        if !path.is_file() {
+            return Ok(None);
+        }
+
+        let reader = BufReader::new(File::open(path)?);
+        Ok(Some(serde_json::from_reader(reader)?))
+    }
+
+    /// Builds a manifest by scanning the datastore directory for container files.
+    ///
+    /// Names are derived from the file stems. Layers sharing a name, or variables with the
+    /// same name on the same base layer, are instead named after their relative path.
+    pub fn scan<P: AsRef<Path>>(datastore: P) -> Result<Self, DatastoreError> {
+        let root = datastore.as_ref();
+        let mut paths = crate::find_containers(root)?;
+        paths.sort();
+
+        // (entry, namespace) where the namespace is None for layers and the base UUID for variables
+        let mut scanned = Vec::with_capacity(paths.len());
+        for path in paths {
+            let storage = storage::open_file(&path)?;
+            let relative = PathBuf::from(portable_path(path.strip_prefix(root).unwrap_or(&path)));
+            let name = relative.file_stem().unwrap().to_string_lossy().into_owned();
+            let container = Container::from_storage(storage, name.clone())?;
+            let header = container.header();
+
+            let namespace = match header.class() {
+                'V' => header.base1(),
+                _ => None,
+            };
+
+            scanned.push((ManifestEntry { name, path: relative, uuid: header.uuid() }, namespace));
+        }
+
+        let mut counts = HashMap::new();
+        for (entry, namespace) in scanned.iter() {
+            *counts.entry((entry.name.clone(), *namespace)).or_insert(0) += 1;
+        }
+
+        let containers = scanned
+            .into_iter()
+            .map(|(mut entry, namespace)| {
+                if counts[&(entry.name.clone(), namespace)] > 1 {
+                    entry.name = portable_path(&entry.path.with_extension(""));
+                }
+                entry
+            })
+            .collect();
+
+        Ok(Self { containers })
+    }
+
+    /// Writes the manifest into the datastore at `datastore`, replacing any existing manifest.
+    pub fn write<P: AsRef<Path>>(&self, datastore: P) -> io::Result<()> {
+        let path = Self::path(datastore);
+        let tmp = path.with_extension("json.tmp");
+
+        let mut writer = BufWriter::new(File::create(&tmp)?);
+        serde_json::to_writer_pretty(&mut writer, self)?;
+        writer.write_all(b"\n")?;
+        writer.flush()?;
+        drop(writer);
+
+        fs::rename(tmp, path)
+    }
+}
@@ -0,0 +1,165 @@
+//! Encoding of [virtual variables](VirtualVariable) as containers of the datastore, so that
+//! derived annotations which are expensive to compute are only computed once.
+//!
+//! A virtual variable with a value at every position becomes an indexed string variable,
+//! one with missing values a sparse variable. The container is written next to the
+//! container of its layer and added to the manifest, if the datastore has one. It is
+//! available as a regular variable after the datastore has been opened again.
+//!
+//! ```no_run
+//! # use std::collections::HashMap;
+//! # use etemenanki::{materialize::materialize, variables::VirtualVariable, Datastore};
+//! let mut datastore = Datastore::open("dickens").unwrap();
+//! let table = HashMap::from([("NN".to_owned(), "N".to_owned()), ("NNS".to_owned(), "N".to_owned())]);
+//! let primary = datastore.layer_by_name_mut("primary").unwrap();
+//! primary.add_virtual_variable("cpos".to_owned(), VirtualVariable::from_table("pos", table)).unwrap();
+//!
+//! materialize(&datastore, "primary", "cpos").unwrap();
+//! ```
+
+use std::collections::HashMap;
+use std::io;
+use std::path::PathBuf;
+use std::{error, fmt};
+
+use crate::components::{LexiconBuilder, LexiconOrder};
+use crate::container::EncodeError;
+use crate::layers::Layer;
+use crate::lock::PendingFile;
+use crate::manifest::{Manifest, ManifestEntry};
+use crate::variables::{IndexedStringVariable, SparseVariable, VirtualVariable};
+use crate::{Datastore, DatastoreError};
+
+#[derive(Debug)]
+pub enum MaterializeError {
+    UnknownLayer(String),
+    /// The layer has no virtual variable with this name
+    UnknownVariable(String),
+    Datastore(DatastoreError),
+    Encode(EncodeError),
+}
+
+impl fmt::Display for MaterializeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownLayer(name) => write!(f, "no layer named {}", name),
+            Self::UnknownVariable(name) => write!(f, "no virtual variable named {}", name),
+            Self::Datastore(e) => write!(f, "{}", e),
+            Self::Encode(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl error::Error for MaterializeError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::Datastore(e) => Some(e),
+            Self::Encode(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<DatastoreError> for MaterializeError {
+    fn from(e: DatastoreError) -> Self {
+        Self::Datastore(e)
+    }
+}
+
+impl From<io::Error> for MaterializeError {
+    fn from(e: io::Error) -> Self {
+        Self::Datastore(e.into())
+    }
+}
+
+impl From<EncodeError> for MaterializeError {
+    fn from(e: EncodeError) -> Self {
+        Self::Encode(e)
+    }
+}
+
+/// Distinct values of a virtual variable and the index of the value at each position,
+/// `None` where there is no value
+struct Values {
+    lexicon: Vec<String>,
+    ids: Vec<Option<u32>>,
+}
+
+impl Values {
+    fn compute(var: &VirtualVariable, layer: &Layer) -> Self {
+        let mut values = Self { lexicon: Vec::new(), ids: Vec::with_capacity(layer.len()) };
+        let mut ids: HashMap<String, u32> = HashMap::new();
+        let mut id = |value: String| *ids.entry(value).or_insert_with_key(|value| {
+            values.lexicon.push(value.clone());
+            (values.lexicon.len() - 1) as u32
+        });
+
+        match var.type_values(layer) {
+            // the mapping is applied once per type of the source
+            Some((source, types)) => {
+                let lut: Vec<Option<u32>> = types.into_iter().map(|v| v.map(&mut id)).collect();
+                values.ids.extend(source.id_stream().column_iter(0).map(|t| lut[t as usize]));
+            }
+            None => {
+                let ids: Vec<Option<u32>> = (0..layer.len()).map(|p| var.get(layer, p).map(&mut id)).collect();
+                values.ids = ids;
+            }
+        }
+        values
+    }
+}
+
+/// Encodes the virtual variable `name` of `layer` as a container of `datastore` and returns
+/// its path.
+///
+/// The container is written to a [`PendingFile`] first and renamed into place, an existing
+/// container of the same name is never overwritten. The datastore is locked for writing
+/// meanwhile, see [`Datastore::lock_for_writing`].
+pub fn materialize(datastore: &Datastore, layer: &str, name: &str) -> Result<PathBuf, MaterializeError> {
+    let root = datastore.path();
+    if !root.is_dir() {
+        return Err(DatastoreError::ConsistencyError("only local datastores can be extended").into());
+    }
+    let _lock = datastore.lock_for_writing()?;
+
+    let layer_name = layer;
+    let layer = datastore.layer_by_name(layer_name)
+        .ok_or_else(|| MaterializeError::UnknownLayer(layer_name.to_owned()))?;
+    let var = layer.virtual_variable_by_name(name)
+        .ok_or_else(|| MaterializeError::UnknownVariable(name.to_owned()))?;
+
+    let mut manifest = Manifest::read(root)?;
+    let scanned = match manifest.as_ref() {
+        Some(manifest) => manifest.clone(),
+        None => Manifest::scan(root)?,
+    };
+    let layer_path = scanned.containers.iter()
+        .find(|entry| entry.uuid == layer.uuid())
+        .map(|entry| entry.path.clone())
+        .ok_or(DatastoreError::ConsistencyError("layer container not found in datastore directory"))?;
+    let relative = layer_path.with_file_name(format!("{}.zigv", name));
+
+    // the encoder renames the pending file once the container is complete
+    let file = PendingFile::create(root.join(&relative))?;
+    let path = file.path().to_owned();
+
+    let values = Values::compute(var, layer);
+    let comment = format!("materialized from {}", var.sources().collect::<Vec<_>>().join(", "));
+    let uuid = if values.ids.iter().all(Option::is_some) {
+        let ids = values.ids.iter().map(|id| id.unwrap() as usize);
+        let lexbuilder = LexiconBuilder::from_id_streams([(values.lexicon.iter().map(String::as_str), ids)]);
+        IndexedStringVariable::encode_lexicon_builder(file, lexbuilder, name.to_owned(), layer.uuid(), true, LexiconOrder::Frequency, &comment)?.uuid()
+    } else {
+        let present = values.ids.iter()
+            .enumerate()
+            .filter_map(|(p, id)| id.map(|id| (p, values.lexicon[id as usize].clone())));
+        SparseVariable::encode_to_file(file, present, layer.len(), name.to_owned(), layer.uuid(), true, &comment)?.uuid()
+    };
+
+    if let Some(manifest) = manifest.as_mut() {
+        manifest.containers.push(ManifestEntry { name: name.to_owned(), path: relative, uuid });
+        manifest.write(root)?;
+    }
+
+    Ok(path)
+}
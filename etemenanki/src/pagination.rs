@@ -0,0 +1,215 @@
+//! Pages of query matches with cursors that stay valid between requests.
+//!
+//! A [`Cursor`] records the query, the layer and the start of the last match of a page. The
+//! next page starts after that position, so pages don't shift or repeat matches like
+//! offsets into a changing result would, and a cursor can be handed to a client as an
+//! opaque token. With a [`QueryCache`] the matches of a query are computed once and read
+//! from the cache for every following page.
+//!
+//! ```no_run
+//! # use etemenanki::{pagination::Paginator, query_cache::QueryCache, Datastore};
+//! let datastore = Datastore::open("dickens").unwrap();
+//! let paginator = Paginator::new(&datastore).cache(QueryCache::for_datastore(&datastore).unwrap());
+//!
+//! let page = paginator.first(r#""the" [pos="JJ"]"#, "primary", 50).unwrap();
+//! let token = page.next.unwrap().to_token();
+//! // ... later, e.g. in another request
+//! let cursor = etemenanki::pagination::Cursor::from_token(&token).unwrap();
+//! let page = paginator.next(&cursor).unwrap();
+//! ```
+
+use std::{error, fmt};
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::query::{Query, QueryError};
+use crate::query_cache::QueryCache;
+use crate::Datastore;
+
+#[derive(Debug)]
+pub enum PaginationError {
+    Query(QueryError),
+    /// The token is not a cursor
+    InvalidCursor,
+    /// The layer of the cursor has been re-encoded or replaced since it was created
+    StaleCursor,
+    /// Pages have at least one match
+    InvalidPageSize,
+}
+
+impl fmt::Display for PaginationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Query(e) => write!(f, "{}", e),
+            Self::InvalidCursor => write!(f, "invalid cursor"),
+            Self::StaleCursor => write!(f, "the cursor refers to a layer that has changed"),
+            Self::InvalidPageSize => write!(f, "page size must be at least 1"),
+        }
+    }
+}
+
+impl error::Error for PaginationError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::Query(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<QueryError> for PaginationError {
+    fn from(e: QueryError) -> Self {
+        Self::Query(e)
+    }
+}
+
+/// Position after the last page returned for a query, see the [module documentation](self)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Cursor {
+    /// Canonical form of the query
+    pub query: String,
+    pub layer: String,
+    /// UUID of the layer when the cursor was created
+    pub uuid: Uuid,
+    /// Start of the last match returned, the next page starts after it
+    pub after: usize,
+    /// Number of matches per page
+    pub size: usize,
+}
+
+impl Cursor {
+    /// Encodes the cursor as a string of hex digits, which can be passed in URLs as is
+    pub fn to_token(&self) -> String {
+        let json = serde_json::to_vec(self).expect("cursors serialize to JSON");
+        json.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    /// Decodes a cursor encoded by [`to_token`](Self::to_token)
+    pub fn from_token(token: &str) -> Result<Self, PaginationError> {
+        let json = token.as_bytes()
+            .chunks(2)
+            .map(|hex| std::str::from_utf8(hex).ok().filter(|h| h.len() == 2).and_then(|h| u8::from_str_radix(h, 16).ok()))
+            .collect::<Option<Vec<u8>>>()
+            .ok_or(PaginationError::InvalidCursor)?;
+        serde_json::from_slice(&json).map_err(|_| PaginationError::InvalidCursor)
+    }
+}
+
+/// Consecutive matches of a query
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Page {
+    pub matches: Vec<(usize, usize)>,
+    /// Number of matches before the page
+    pub offset: usize,
+    /// Number of matches of the query
+    pub total: usize,
+    /// Cursor of the following page, `None` on the last page
+    pub next: Option<Cursor>,
+}
+
+/// Splits the matches of queries on a datastore into pages
+pub struct Paginator<'a, 'map> {
+    datastore: &'a Datastore<'map>,
+    cache: Option<QueryCache>,
+}
+
+impl<'a, 'map> Paginator<'a, 'map> {
+    /// Creates a paginator which runs every query again for each page
+    pub fn new(datastore: &'a Datastore<'map>) -> Self {
+        Self { datastore, cache: None }
+    }
+
+    /// Keeps the matches of each query in `cache`
+    pub fn cache(mut self, cache: QueryCache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// All matches of `query` on the layer `layer`, read from the cache if possible.
+    ///
+    /// Queries on virtual variables are never cached, since the cache can't tell whether a
+    /// virtual variable of the same name computes the same values.
+    pub fn matches(&self, query: &Query, layer: &str) -> Result<Vec<(usize, usize)>, QueryError> {
+        let key = query.to_string();
+        let uuids = self.cache.as_ref().and_then(|_| self.dependencies(query, layer));
+
+        if let (Some(cache), Some(uuids)) = (self.cache.as_ref(), uuids.as_ref()) {
+            if let Some(matches) = cache.get_ranges(&key, uuids) {
+                return Ok(matches);
+            }
+        }
+
+        let matches = query.find_in(self.datastore, layer)?;
+        if let (Some(cache), Some(uuids)) = (self.cache.as_ref(), uuids.as_ref()) {
+            // a cache that can't be written only costs running the query again
+            let _ = cache.put_ranges(&key, uuids, &matches);
+        }
+        Ok(matches)
+    }
+
+    /// UUIDs of the containers the matches of `query` depend on, `None` if it uses virtual
+    /// variables or unknown names
+    fn dependencies(&self, query: &Query, layer: &str) -> Option<Vec<Uuid>> {
+        let layer = self.datastore.layer_by_name(layer)?;
+        let mut uuids = vec![layer.uuid()];
+        if let Some(within) = &query.within {
+            uuids.push(self.datastore.layer_by_name(within)?.uuid());
+        }
+        for constraint in query.tokens.iter().flat_map(|t| t.constraints.iter()) {
+            uuids.push(layer.variable_by_name(&constraint.variable)?.uuid()?);
+        }
+        Some(uuids)
+    }
+
+    /// First page of at most `size` matches of the query `expr` on the layer `layer`
+    pub fn first(&self, expr: &str, layer: &str, size: usize) -> Result<Page, PaginationError> {
+        self.at(expr, layer, 0, size)
+    }
+
+    /// Page of at most `size` matches of the query `expr` on the layer `layer`, after the
+    /// first `offset` of them
+    pub fn at(&self, expr: &str, layer: &str, offset: usize, size: usize) -> Result<Page, PaginationError> {
+        let query = Query::parse(expr)?;
+        self.page(&query, layer, size, |matches| usize::min(offset, matches.len()))
+    }
+
+    /// Page following the one `cursor` was returned with.
+    ///
+    /// Fails with [`PaginationError::StaleCursor`] if the layer has changed, since its
+    /// positions may not refer to the same tokens anymore.
+    pub fn next(&self, cursor: &Cursor) -> Result<Page, PaginationError> {
+        let layer = self.datastore.layer_by_name(&cursor.layer)
+            .ok_or_else(|| QueryError::UnknownLayer(cursor.layer.clone()))?;
+        if layer.uuid() != cursor.uuid {
+            return Err(PaginationError::StaleCursor);
+        }
+
+        let query = Query::parse(&cursor.query)?;
+        self.page(&query, &cursor.layer, cursor.size, |matches| matches.partition_point(|(start, _)| *start <= cursor.after))
+    }
+
+    /// Page starting at the index of the matches returned by `offset`
+    fn page<F>(&self, query: &Query, layer: &str, size: usize, offset: F) -> Result<Page, PaginationError>
+    where
+        F: FnOnce(&[(usize, usize)]) -> usize,
+    {
+        if size == 0 {
+            return Err(PaginationError::InvalidPageSize);
+        }
+
+        let matches = self.matches(query, layer)?;
+        let offset = offset(&matches);
+        let end = usize::min(offset.saturating_add(size), matches.len());
+
+        let next = (end < matches.len()).then(|| Cursor {
+            query: query.to_string(),
+            layer: layer.to_owned(),
+            uuid: self.datastore[layer].uuid(),
+            after: matches[end - 1].0,
+            size,
+        });
+
+        Ok(Page { matches: matches[offset..end].to_vec(), offset, total: matches.len(), next })
+    }
+}
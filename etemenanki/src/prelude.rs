@@ -0,0 +1,17 @@
+//! The types needed to open a datastore and read from it, for `use etemenanki::prelude::*`.
+//!
+//! Encoders and the component level API stay in their modules.
+
+pub use crate::concordance::{Concordance, Format, KwicLine};
+pub use crate::federation::{Federation, FederationError};
+pub use crate::frequency::{FrequencyError, FrequencyList, MetadataFilter};
+pub use crate::group::GroupCounts;
+pub use crate::layers::{HasBase, Layer, PrimaryLayer, RangeError, Segment, SegmentationLayer, SpanLayer};
+pub use crate::query::{Query, QueryError};
+pub use crate::selection::{Selection, SelectionError};
+pub use crate::subcorpus::{Subcorpus, SubcorpusError};
+pub use crate::variables::{
+    IndexedStringVariable, IntegerVariable, IntegerVectorVariable, OffsetVariable,
+    PlainStringVariable, PointerVariable, SetVariable, SparseVariable, Variable, VariableValue,
+};
+pub use crate::{Datastore, DatastoreError};
@@ -0,0 +1,132 @@
+//! Counters for reporting the progress of long running encodes.
+//!
+//! Encoders consume their input as an iterator and write to an [`EncodeTarget`], so
+//! progress is observed by wrapping both: [`Progress::counted`] counts the items taken
+//! from the input and [`Progress::target`] the bytes written. The counters are shared by
+//! all clones of a [`Progress`] and can be polled from another thread, e.g. to drive a
+//! progress bar.
+//!
+//! ```no_run
+//! # use std::fs::File;
+//! # use etemenanki::{progress::Progress, variables::PlainStringVariable};
+//! # let words: Vec<String> = Vec::new();
+//! let progress = Progress::new();
+//! let file = progress.target(File::create("word.zigv").unwrap());
+//! let n = words.len();
+//! let _ = PlainStringVariable::encode_to_file(file, progress.counted(words), n, "word".into(), uuid::Uuid::new_v4(), true, "");
+//! println!("{} tokens, {} bytes", progress.items(), progress.bytes());
+//! ```
+
+use std::io::{self, Seek, SeekFrom, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use crate::container::EncodeTarget;
+use crate::storage::Storage;
+
+/// Shared item and byte counters
+#[derive(Debug, Clone, Default)]
+pub struct Progress {
+    items: Arc<AtomicU64>,
+    bytes: Arc<AtomicU64>,
+}
+
+impl Progress {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of items (usually tokens or segments) consumed so far
+    pub fn items(&self) -> u64 {
+        self.items.load(Ordering::Relaxed)
+    }
+
+    /// Number of bytes written so far
+    pub fn bytes(&self) -> u64 {
+        self.bytes.load(Ordering::Relaxed)
+    }
+
+    pub fn add_items(&self, n: u64) {
+        self.items.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn add_bytes(&self, n: u64) {
+        self.bytes.fetch_add(n, Ordering::Relaxed);
+    }
+
+    /// Wraps an encoder input, counting every item taken from it
+    pub fn counted<I: IntoIterator>(&self, iter: I) -> Counted<I::IntoIter> {
+        Counted { inner: iter.into_iter(), progress: self.clone() }
+    }
+
+    /// Wraps an encoder output, counting every byte written to it
+    pub fn target<W: EncodeTarget>(&self, target: W) -> CountingTarget<W> {
+        CountingTarget { inner: target, progress: self.clone() }
+    }
+}
+
+/// Iterator adapter returned by [`Progress::counted`]
+#[derive(Debug)]
+pub struct Counted<I> {
+    inner: I,
+    progress: Progress,
+}
+
+impl<I: Iterator> Iterator for Counted<I> {
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let item = self.inner.next();
+        if item.is_some() {
+            self.progress.add_items(1);
+        }
+        item
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl<I: ExactSizeIterator> ExactSizeIterator for Counted<I> {}
+
+/// Encode target returned by [`Progress::target`].
+///
+/// Only bytes passed to `write` are counted, not the zero padding added by `set_len`.
+#[derive(Debug)]
+pub struct CountingTarget<W> {
+    inner: W,
+    progress: Progress,
+}
+
+impl<W: Write> Write for CountingTarget<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.progress.add_bytes(n as u64);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<W: Seek> Seek for CountingTarget<W> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+impl<W: EncodeTarget> EncodeTarget for CountingTarget<W> {
+    fn set_len(&mut self, len: u64) -> io::Result<()> {
+        self.inner.set_len(len)
+    }
+
+    fn map_range(&mut self, offset: u64, len: usize) -> io::Result<Box<dyn Storage>> {
+        self.inner.map_range(offset, len)
+    }
+
+    fn into_storage(self, len: usize) -> io::Result<Box<dyn Storage>> {
+        self.inner.into_storage(len)
+    }
+}
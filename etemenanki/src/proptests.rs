@@ -0,0 +1,202 @@
+//! Property-based round-trip tests of the container encoders.
+//!
+//! Random value streams are encoded in memory, reopened from the encoded bytes and
+//! compared value by value with the input. Set variables have no encoder yet and are
+//! not covered. All streams are non-empty, compressed encoders can't write empty
+//! vectors.
+
+use proptest::collection::{btree_set, vec};
+use proptest::prelude::*;
+use uuid::Uuid;
+
+use crate::components::elias_fano::{self, EliasFano};
+use crate::components::{Vector, WaveletTree};
+use crate::container::{encode_in_memory, Container};
+use crate::layers::{SegmentationLayer, SpanLayer};
+use crate::variables::{IndexedStringVariable, IntegerVariable, PlainStringVariable, PointerVariable};
+
+fn reopen<'map, T>(bytes: &[u8]) -> T
+where
+    T: TryFrom<Container<'map>>,
+    T::Error: std::fmt::Debug,
+{
+    let container = Container::from_bytes(bytes, "test".to_owned()).unwrap();
+    T::try_from(container).unwrap()
+}
+
+fn strings() -> impl Strategy<Value = Vec<String>> {
+    // few distinct types, so that the lexicon and inverted index see repeated values
+    vec(prop_oneof!["\\PC{0,12}", "[a-e]{1,2}"], 1..300)
+}
+
+/// Non-overlapping ranges sorted by their start, as built from gaps and lengths
+fn segments() -> impl Strategy<Value = Vec<(usize, usize)>> {
+    vec((0..5usize, 1..10usize), 1..300).prop_map(|parts| {
+        let mut end = 0;
+        parts.into_iter()
+            .map(|(gap, len)| {
+                let start = end + gap;
+                end = start + len;
+                (start, end)
+            })
+            .collect()
+    })
+}
+
+/// Possibly overlapping ranges sorted by their start
+fn spans() -> impl Strategy<Value = Vec<(usize, usize)>> {
+    vec((0..3usize, 1..20usize), 1..300).prop_map(|parts| {
+        let mut start = 0;
+        parts.into_iter()
+            .map(|(step, len)| {
+                start += step;
+                (start, start + len)
+            })
+            .collect()
+    })
+}
+
+/// Heads of a pointer variable, -1 marks positions without a head
+fn heads() -> impl Strategy<Value = Vec<i64>> {
+    (1..300usize).prop_flat_map(|n| vec(prop_oneof![Just(-1i64), 0..n as i64], n))
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(32))]
+
+    #[test]
+    fn indexed_string_roundtrip(values in strings(), compressed: bool) {
+        let bytes = encode_in_memory(|file| {
+            IndexedStringVariable::encode_to_file(file, values.iter().cloned(), values.len(), "word".to_owned(), Uuid::new_v4(), compressed, "")
+        }).unwrap();
+        let var: IndexedStringVariable = reopen(&bytes);
+
+        prop_assert_eq!(var.len(), values.len());
+        prop_assert!(var.iter().eq(values.iter().map(String::as_str)));
+        for (i, value) in values.iter().enumerate() {
+            prop_assert_eq!(var.get(i), Some(value.as_str()));
+        }
+    }
+
+    #[test]
+    fn elias_fano_roundtrip(positions in btree_set(0..1_000_000usize, 1..700), target in 0..1_000_100usize) {
+        let positions: Vec<usize> = positions.into_iter().collect();
+        let bytes = elias_fano::encode(&positions);
+        let list = EliasFano::new(positions.len(), &bytes);
+
+        prop_assert_eq!(list.decode(), positions.clone());
+        for (i, &position) in positions.iter().enumerate().step_by(37) {
+            prop_assert_eq!(list.get(i), Some(position));
+            prop_assert!(list.iter_from(i).eq(positions[i..].iter().copied()));
+        }
+        let expected = positions.iter().position(|&p| p >= target).map(|i| (i, positions[i]));
+        prop_assert_eq!(list.next_geq(target), expected);
+    }
+
+    #[test]
+    fn wavelet_tree_roundtrip(ids in vec(0..13usize, 1..1500), start in 0..1600usize, len in 0..1600usize) {
+        let rows = WaveletTree::build_rows(ids.iter().copied(), 13);
+        let vector = Vector::Uncompressed { length: rows.len() / 9, width: 9, data: &rows };
+        let tree = WaveletTree::from_vector(vector, ids.len()).unwrap();
+
+        prop_assert_eq!(tree.levels(), 4);
+        for (i, &id) in ids.iter().enumerate() {
+            prop_assert_eq!(tree.get(i), Some(id));
+        }
+        prop_assert_eq!(tree.get(ids.len()), None);
+
+        let end = start + len;
+        for id in 0..16 {
+            let expected = ids.iter().take(end).skip(start).filter(|t| **t == id).count();
+            prop_assert_eq!(tree.count_in_range(id, start, end), expected);
+
+            let positions: Vec<usize> = (0..ids.len()).filter(|i| ids[*i] == id).collect();
+            for (k, &position) in positions.iter().enumerate() {
+                prop_assert_eq!(tree.select(id, k), Some(position));
+                prop_assert_eq!(tree.rank(id, position), k);
+            }
+            prop_assert_eq!(tree.select(id, positions.len()), None);
+        }
+    }
+
+    #[test]
+    fn plain_string_roundtrip(values in strings(), compressed: bool) {
+        let bytes = encode_in_memory(|file| {
+            PlainStringVariable::encode_to_file(file, values.iter().cloned(), values.len(), "word".to_owned(), Uuid::new_v4(), compressed, "")
+        }).unwrap();
+        let var: PlainStringVariable = reopen(&bytes);
+
+        prop_assert_eq!(var.len(), values.len());
+        prop_assert!(var.iter().eq(values.iter().map(String::as_str)));
+    }
+
+    #[test]
+    fn integer_roundtrip(values in vec(any::<i64>(), 1..300), compressed: bool, delta: bool) {
+        let bytes = encode_in_memory(|file| {
+            IntegerVariable::encode_to_file(file, values.iter().copied(), values.len(), "num".to_owned(), Uuid::new_v4(), compressed, delta, "")
+        }).unwrap();
+        let var: IntegerVariable = reopen(&bytes);
+
+        prop_assert_eq!(var.len(), values.len());
+        prop_assert!(var.iter().eq(values.iter().copied()));
+    }
+
+    #[test]
+    fn pointer_roundtrip(values in heads(), compressed: bool) {
+        let bytes = encode_in_memory(|file| {
+            PointerVariable::encode_to_file(file, values.iter().copied(), values.len(), "head".to_owned(), Uuid::new_v4(), compressed, "")
+        }).unwrap();
+        let var: PointerVariable = reopen(&bytes);
+
+        prop_assert_eq!(var.len(), values.len());
+        for (tail, &head) in values.iter().enumerate() {
+            prop_assert_eq!(var.get(tail), usize::try_from(head).ok());
+        }
+    }
+
+    #[test]
+    fn segmentation_roundtrip(ranges in segments(), compressed: bool) {
+        let bytes = encode_in_memory(|file| {
+            SegmentationLayer::encode_to_file(file, ranges.iter().copied(), ranges.len(), "s".to_owned(), Uuid::new_v4(), compressed, "")
+        }).unwrap();
+        let layer: SegmentationLayer = reopen(&bytes);
+
+        prop_assert_eq!(layer.len(), ranges.len());
+        prop_assert!(layer.iter().eq(ranges.iter().copied()));
+        for (i, &(start, end)) in ranges.iter().enumerate() {
+            prop_assert_eq!(layer.find_containing(start), Some(i));
+            prop_assert_eq!(layer.find_containing(end - 1), Some(i));
+        }
+    }
+
+    #[test]
+    fn span_roundtrip(ranges in spans(), compressed: bool) {
+        let bytes = encode_in_memory(|file| {
+            SpanLayer::encode_to_file(file, ranges.iter().copied(), ranges.len(), "ne".to_owned(), Uuid::new_v4(), compressed, "")
+        }).unwrap();
+        let layer: SpanLayer = reopen(&bytes);
+
+        prop_assert_eq!(layer.len(), ranges.len());
+        for (i, &range) in ranges.iter().enumerate() {
+            prop_assert_eq!(layer.get(i), Some(range));
+        }
+    }
+
+    #[test]
+    fn corrupted_container_does_not_panic(edits in vec((any::<usize>(), any::<u8>()), 1..16)) {
+        let mut bytes = encode_in_memory(|file| {
+            SegmentationLayer::encode_to_file(file, [(0, 3), (5, 7)].into_iter(), 2, "s".to_owned(), Uuid::new_v4(), true, "")
+        }).unwrap();
+        for (offset, byte) in edits {
+            let len = bytes.len();
+            bytes[offset % len] = byte;
+        }
+
+        if let Ok(container) = Container::from_bytes(&bytes, "fuzz".to_owned()) {
+            let names: Vec<_> = container.component_names().map(str::to_owned).collect();
+            for name in names {
+                let _ = container.get_component(&name);
+            }
+        }
+    }
+}
@@ -0,0 +1,702 @@
+//! Token sequence queries over the string variables of a layer.
+//!
+//! A [`Query`] is a sequence of token patterns, each a conjunction of regex constraints,
+//! written in a subset of the CQP syntax:
+//!
+//! ```text
+//! [lemma="go"] []{0,2} [pos="N.*" & word!="[A-Z].*"]+ within s
+//! ```
+//!
+//! Regexes always match the whole value. A bare string like `"the"` is a constraint on the
+//! `word` variable and `[]` matches any token. The flags `%c` and `%d` after a regex ignore
+//! case and diacritics, the latter only with the `normalization` feature.
+//!
+//! A token pattern may be followed by a quantifier, `?`, `*`, `+`, `{n}`, `{n,}`, `{,m}`
+//! or `{n,m}`. Of the matches starting at a position only the longest is reported, and
+//! unbounded quantifiers match at most [`MAX_REPETITION`] tokens unless the query ends
+//! with a `within` clause. That clause names a segmentation layer of the queried layer,
+//! e.g. sentences, and restricts matches to a single segment.
+//!
+//! Matches are found by seeding candidates from the postings of the most selective indexed
+//! string constraint among the leading patterns without quantifier and checking all other
+//! constraints at each candidate. Two adjacent constraints on a variable with a
+//! [bigram index](crate::components::BigramIndex) can seed candidates together, which
+//! avoids the long postings lists of phrases of frequent words like `"of" "the"`.
+//!
+//! Constraints may also refer to [virtual variables](crate::variables::VirtualVariable) of
+//! the layer. Those mapping the strings of an indexed string variable are matched on the
+//! lexicon of their source like the source itself.
+//!
+//! ```no_run
+//! # use etemenanki::{query::Query, Datastore};
+//! let datastore = Datastore::open("dickens").unwrap();
+//! let query = Query::parse(r#"[pos="JJ"] "man""#).unwrap();
+//! let matches = query.find(&datastore["primary"]).unwrap();
+//!
+//! let query = Query::parse(r#""the" [pos="JJ"]* "man"%c within s"#).unwrap();
+//! let matches = query.find_in(&datastore, "primary").unwrap();
+//! ```
+
+use std::ops::Range;
+use std::rc::Rc;
+use std::{error, fmt};
+
+use regex::Regex;
+
+use crate::components::{CachedInvertedIndex, MergedPostings, RegexFlags};
+use crate::layers::{Layer, SegmentationLayer};
+use crate::variables::{IndexedStringVariable, Variable, VirtualVariable};
+use crate::Datastore;
+
+/// Variable of bare string patterns like `"the"`
+pub const DEFAULT_VARIABLE: &str = "word";
+
+/// Largest number of pairs of matching types two adjacent constraints may have to seed
+/// candidates from a bigram index
+pub const MAX_BIGRAM_PAIRS: usize = 256;
+
+/// Most tokens matched by an unbounded quantifier like `[]*` outside of a `within` clause
+pub const MAX_REPETITION: usize = 100;
+
+/// Regex constraint on the value of a string variable at one token
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Constraint {
+    pub variable: String,
+    pub regex: String,
+    /// `!=` instead of `=`
+    pub negated: bool,
+    /// Case and diacritic folding given by `%c` and `%d`
+    pub flags: RegexFlags,
+}
+
+/// Number of consecutive tokens a [`TokenPattern`] matches, `max` is `None` if unbounded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Repetition {
+    pub min: usize,
+    pub max: Option<usize>,
+}
+
+impl Repetition {
+    /// A single token, the repetition of patterns without quantifier
+    pub const ONCE: Repetition = Repetition { min: 1, max: Some(1) };
+}
+
+impl Default for Repetition {
+    fn default() -> Self {
+        Self::ONCE
+    }
+}
+
+/// Conjunction of constraints on a single token, empty for `[]`, repeated according to
+/// its quantifier
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct TokenPattern {
+    pub constraints: Vec<Constraint>,
+    pub repetition: Repetition,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Query {
+    pub tokens: Vec<TokenPattern>,
+    /// Segmentation layer whose segments matches may not cross
+    pub within: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum QueryError {
+    Syntax { position: usize, message: &'static str },
+    /// The layer has no variable with this name
+    UnknownVariable(String),
+    /// Constraints are only supported on string variables
+    UnsupportedVariable(String),
+    /// The datastore has no layer with this name, or the query was not run on a datastore
+    UnknownLayer(String),
+    /// The layer of a `within` clause is not a segmentation of the queried layer
+    UnsupportedLayer(String),
+    /// The queried layer has no partition with this index
+    UnknownPartition(String, usize),
+    Regex(regex::Error),
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Syntax { position, message } => write!(f, "syntax error at {}: {}", position, message),
+            Self::UnknownVariable(name) => write!(f, "no variable named {}", name),
+            Self::UnsupportedVariable(name) => write!(f, "variable {} is not a string variable", name),
+            Self::UnknownLayer(name) => write!(f, "no layer named {}", name),
+            Self::UnsupportedLayer(name) => write!(f, "layer {} is not a segmentation of the queried layer", name),
+            Self::UnknownPartition(name, i) => write!(f, "layer {} has no partition {}", name, i),
+            Self::Regex(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl error::Error for QueryError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::Regex(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<regex::Error> for QueryError {
+    fn from(e: regex::Error) -> Self {
+        Self::Regex(e)
+    }
+}
+
+impl fmt::Display for Constraint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let op = if self.negated { "!=" } else { "=" };
+        write!(f, "{}{}\"{}\"", self.variable, op, self.regex.replace('"', "\\\""))?;
+
+        let case = RegexFlags::default().case_insensitive(true);
+        if self.flags == case {
+            write!(f, "%c")?;
+        }
+        #[cfg(feature = "normalization")]
+        if self.flags == RegexFlags::default().folded(true) {
+            write!(f, "%d")?;
+        } else if self.flags == case.folded(true) {
+            write!(f, "%cd")?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Repetition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.min, self.max) {
+            (1, Some(1)) => Ok(()),
+            (0, Some(1)) => write!(f, "?"),
+            (0, None) => write!(f, "*"),
+            (1, None) => write!(f, "+"),
+            (min, None) => write!(f, "{{{},}}", min),
+            (min, Some(max)) if min == max => write!(f, "{{{}}}", min),
+            (min, Some(max)) => write!(f, "{{{},{}}}", min, max),
+        }
+    }
+}
+
+impl fmt::Display for TokenPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let constraints: Vec<_> = self.constraints.iter().map(Constraint::to_string).collect();
+        write!(f, "[{}]{}", constraints.join(" & "), self.repetition)
+    }
+}
+
+/// Canonical form of the query, which parses to an equal query
+impl fmt::Display for Query {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let tokens: Vec<_> = self.tokens.iter().map(TokenPattern::to_string).collect();
+        write!(f, "{}", tokens.join(" "))?;
+        if let Some(within) = &self.within {
+            write!(f, " within {}", within)?;
+        }
+        Ok(())
+    }
+}
+
+impl Query {
+    pub fn parse(expr: &str) -> Result<Self, QueryError> {
+        Parser { input: expr, position: 0 }.query()
+    }
+
+    /// Finds all matches of the query on `layer` as ranges of positions, in ascending order.
+    ///
+    /// Matches may overlap, e.g. `[] []` matches at every position but the last. Queries
+    /// with a `within` clause fail with [`QueryError::UnknownLayer`], their segmentation
+    /// layer is looked up by [`find_in`](Self::find_in).
+    pub fn find<'map>(&self, layer: &Layer<'map>) -> Result<Vec<(usize, usize)>, QueryError> {
+        match &self.within {
+            Some(name) => Err(QueryError::UnknownLayer(name.clone())),
+            None => self.find_within(layer, None, 0..layer.len()),
+        }
+    }
+
+    /// Finds all matches of the query on the layer `layer` of `datastore`, see [`find`](Self::find)
+    pub fn find_in<'map>(&self, datastore: &Datastore<'map>, layer: &str) -> Result<Vec<(usize, usize)>, QueryError> {
+        let (layer, segments) = self.resolve(datastore, layer)?;
+        self.find_within(layer, segments, 0..layer.len())
+    }
+
+    /// Finds the matches of the query on the layer `layer` of `datastore` like
+    /// [`find_in`](Self::find_in), but only those inside its partition `partition`, see
+    /// [`Layer::partition_boundaries`].
+    ///
+    /// Candidates are taken from the postings inside the partition only, see
+    /// [`CachedInvertedIndex::positions_within`]: Elias-Fano coded lists are entered at the
+    /// start of the partition through their skip pointers, delta coded lists are decoded
+    /// into the postings cache in full and binary searched.
+    pub fn find_in_partition<'map>(&self, datastore: &Datastore<'map>, layer: &str, partition: usize) -> Result<Vec<(usize, usize)>, QueryError> {
+        let (queried, segments) = self.resolve(datastore, layer)?;
+        let bounds = queried.partition_boundaries()
+            .and_then(|b| b.get(partition..=partition + 1))
+            .ok_or_else(|| QueryError::UnknownPartition(layer.to_owned(), partition))?;
+        self.find_within(queried, segments, bounds[0]..bounds[1])
+    }
+
+    /// Looks up the queried layer and the segmentation layer of the `within` clause
+    fn resolve<'a, 'map>(&self, datastore: &'a Datastore<'map>, layer: &str) -> Result<(&'a Layer<'map>, Option<&'a SegmentationLayer<'map>>), QueryError> {
+        let layer = datastore.layer_by_name(layer)
+            .ok_or_else(|| QueryError::UnknownLayer(layer.to_owned()))?;
+
+        let segments = match &self.within {
+            Some(name) => {
+                let segments = datastore.layer_by_name(name)
+                    .ok_or_else(|| QueryError::UnknownLayer(name.clone()))?;
+                match segments.as_segmentation() {
+                    Some(segments) if segments.base == layer.uuid() => Some(&**segments),
+                    _ => return Err(QueryError::UnsupportedLayer(name.clone())),
+                }
+            }
+            None => None,
+        };
+
+        Ok((layer, segments))
+    }
+
+    /// Finds the matches inside the positions `bounds` of `layer`
+    fn find_within<'map>(&self, layer: &Layer<'map>, segments: Option<&SegmentationLayer<'map>>, bounds: Range<usize>) -> Result<Vec<(usize, usize)>, QueryError> {
+        let tokens = self.compile(layer)?;
+
+        let Range { start: first, end: len } = bounds;
+        let min_len: usize = self.tokens.iter().map(|t| t.repetition.min).sum();
+        if first + min_len > len {
+            return Ok(Vec::new());
+        }
+
+        let longest = |start: usize| {
+            let limit = match segments {
+                Some(segments) => usize::min(segments.get(segments.find_containing(start)?)?.1, len),
+                None => len,
+            };
+            let unbounded = if segments.is_some() { limit } else { usize::min(limit, start + MAX_REPETITION) };
+            longest_match(&self.tokens, &tokens, start, limit, unbounded).map(|end| (start, end))
+        };
+
+        let matches = match seed(&tokens[..self.fixed_prefix()]) {
+            Some((offset, postings, ids)) if first == 0 && len == layer.len() => {
+                postings
+                    .merged_postings(&ids, false)
+                    .filter_map(|p| p.checked_sub(offset))
+                    .filter(|start| start + min_len <= len)
+                    .filter_map(longest)
+                    .collect()
+            }
+            Some((offset, postings, ids)) => {
+                // only the candidates inside the bounds are read from the postings
+                let cursors = ids.iter()
+                    .filter_map(|&t| postings.positions_within(t, first + offset, len - min_len + offset + 1));
+                MergedPostings::new(cursors, false)
+                    .map(|p| p - offset)
+                    .filter_map(longest)
+                    .collect()
+            }
+            None => (first..=len - min_len)
+                .filter_map(longest)
+                .collect(),
+        };
+
+        Ok(matches)
+    }
+
+    /// Number of leading patterns without quantifier, whose offsets in every match are known
+    fn fixed_prefix(&self) -> usize {
+        self.tokens.iter().take_while(|t| t.repetition == Repetition::ONCE).count()
+    }
+
+    /// Decodes the postings lists [`find`](Self::find) seeds its candidates from into the
+    /// postings cache, so that a following `find` on the same layer only checks candidates.
+    pub fn warm(&self, layer: &Layer) -> Result<(), QueryError> {
+        self.warm_while(layer, || ())
+    }
+
+    /// Like [`warm`](Self::warm), but the postings are decoded on a background thread while
+    /// `f` runs, e.g. while an interactive application renders the previous result page.
+    pub fn warm_while<R, F: FnOnce() -> R>(&self, layer: &Layer, f: F) -> Result<R, QueryError> {
+        let tokens = self.compile(layer)?;
+        Ok(match seed(&tokens[..self.fixed_prefix()]) {
+            Some((_, postings, ids)) => postings.prefetch_while(&ids, f),
+            None => f(),
+        })
+    }
+
+    fn compile<'a, 'map>(&self, layer: &'a Layer<'map>) -> Result<Vec<Vec<Matcher<'a, 'map>>>, QueryError> {
+        self.tokens.iter()
+            .map(|t| t.constraints.iter().map(|c| Matcher::new(layer, c)).collect::<Result<Vec<_>, _>>())
+            .collect()
+    }
+}
+
+/// End of the longest match of `patterns` starting at `start` and ending at or before
+/// `limit`, where unbounded repetitions end at or before `unbounded`
+fn longest_match(patterns: &[TokenPattern], tokens: &[Vec<Matcher>], start: usize, limit: usize, unbounded: usize) -> Option<usize> {
+    // ascending ends of the partial matches of the patterns so far
+    let mut ends = vec![start];
+
+    for (pattern, matchers) in patterns.iter().zip(tokens) {
+        let Repetition { min, max } = pattern.repetition;
+        let mut next = Vec::new();
+
+        for &end in &ends {
+            let last = match max {
+                Some(max) => usize::min(end + max, limit),
+                None => usize::max(end, unbounded),
+            };
+            if min == 0 {
+                next.push(end);
+            }
+            let mut position = end;
+            while position < last && matchers.iter().all(|m| m.is_match(position)) {
+                position += 1;
+                if position - end >= min {
+                    next.push(position);
+                }
+            }
+        }
+
+        next.sort_unstable();
+        next.dedup();
+        if next.is_empty() {
+            return None;
+        }
+        ends = next;
+    }
+
+    ends.last().copied()
+}
+
+/// Token offset, postings and IDs in them of the positive indexed constraint or pair of
+/// adjacent constraints with the fewest postings
+fn seed<'map>(tokens: &[Vec<Matcher<'_, 'map>>]) -> Option<(usize, Rc<CachedInvertedIndex<'map>>, Vec<usize>)> {
+    let single = tokens.iter()
+        .enumerate()
+        .flat_map(|(offset, matchers)| matchers.iter().map(move |m| (offset, m)))
+        .filter_map(|(offset, m)| m.postings_count().map(|count| (count, offset, m)))
+        .min_by_key(|(count, _, _)| *count);
+
+    let pair = tokens.windows(2)
+        .enumerate()
+        .flat_map(|(offset, window)| window[0].iter()
+            .flat_map(move |first| window[1].iter().map(move |second| (offset, first, second))))
+        .filter_map(|(offset, first, second)| bigram_count(first, second).map(|count| (count, offset, first, second)))
+        .min_by_key(|(count, _, _, _)| *count);
+
+    match (single, pair) {
+        (single, Some((count, offset, Matcher::Types { var, types: first, .. }, Matcher::Types { types: second, .. })))
+            if single.map_or(true, |(single_count, _, _)| count < single_count) =>
+        {
+            let bigrams = var.bigram_index()?;
+            let ids = matching_types(first).into_iter()
+                .flat_map(|a| matching_types(second).into_iter().map(move |b| (a, b)))
+                .filter_map(|(a, b)| bigrams.bigram_id(a, b))
+                .collect();
+            Some((offset, bigrams.postings(), ids))
+        }
+        (Some((_, offset, Matcher::Types { var, types, .. })), _) => {
+            Some((offset, var.inverted_index(), matching_types(types)))
+        }
+        _ => None,
+    }
+}
+
+fn matching_types(types: &[bool]) -> Vec<usize> {
+    (0..types.len()).filter(|t| types[*t]).collect()
+}
+
+/// Number of positions matched by two adjacent positive constraints on the same variable,
+/// if all pairs of their matching types are covered by the bigram index of the variable
+fn bigram_count<'map>(first: &Matcher<'_, 'map>, second: &Matcher<'_, 'map>) -> Option<usize> {
+    let (Matcher::Types { var, types: first, negated: false }, Matcher::Types { var: other, types: second, negated: false }) = (first, second) else {
+        return None;
+    };
+    if !std::ptr::eq(*var, *other) {
+        return None;
+    }
+    var.bigram_index()?;
+
+    let (first, second) = (matching_types(first), matching_types(second));
+    if first.len() * second.len() > MAX_BIGRAM_PAIRS {
+        return None;
+    }
+    first.iter()
+        .flat_map(|a| second.iter().map(move |b| (*a, *b)))
+        .map(|(a, b)| var.bigram_frequency(a, b))
+        .sum()
+}
+
+/// Compiled constraint, indexed string variables and virtual variables mapping their
+/// strings are matched on their lexicon IDs
+enum Matcher<'a, 'map> {
+    Types { var: &'a IndexedStringVariable<'map>, types: Vec<bool>, negated: bool },
+    Strings { var: &'a Variable<'map>, regex: Regex, flags: RegexFlags, negated: bool },
+    Virtual { layer: &'a Layer<'map>, var: &'a VirtualVariable, regex: Regex, flags: RegexFlags, negated: bool },
+}
+
+impl<'a, 'map> Matcher<'a, 'map> {
+    fn new(layer: &'a Layer<'map>, constraint: &Constraint) -> Result<Self, QueryError> {
+        let flags = constraint.flags;
+        let regex = flags.build(&constraint.regex)?;
+        let negated = constraint.negated;
+
+        match layer.variable_by_name(&constraint.variable) {
+            Some(Variable::IndexedString(var)) => {
+                let types = var.lexicon().iter().map(|s| flags.is_match(&regex, s)).collect();
+                Ok(Self::Types { var, types, negated })
+            }
+            Some(var @ (Variable::PlainString(_) | Variable::Sparse(_))) => Ok(Self::Strings { var, regex, flags, negated }),
+            Some(_) => Err(QueryError::UnsupportedVariable(constraint.variable.clone())),
+            None => {
+                let var = layer.virtual_variable_by_name(&constraint.variable)
+                    .ok_or_else(|| QueryError::UnknownVariable(constraint.variable.clone()))?;
+                Ok(match var.type_values(layer) {
+                    Some((var, values)) => {
+                        let types = values.iter().map(|v| v.as_deref().is_some_and(|s| flags.is_match(&regex, s))).collect();
+                        Self::Types { var, types, negated }
+                    }
+                    None => Self::Virtual { layer, var, regex, flags, negated },
+                })
+            }
+        }
+    }
+
+    fn is_match(&self, position: usize) -> bool {
+        match self {
+            Self::Types { var, types, negated } => var.get_id(position).map_or(false, |t| types[t]) != *negated,
+            Self::Strings { var, regex, flags, negated } => var.get_string(position).map_or(false, |s| flags.is_match(regex, s)) != *negated,
+            Self::Virtual { layer, var, regex, flags, negated } => var.get(layer, position).is_some_and(|s| flags.is_match(regex, &s)) != *negated,
+        }
+    }
+
+    /// Number of positions matched by a positive constraint on an indexed variable
+    fn postings_count(&self) -> Option<usize> {
+        match self {
+            Self::Types { var, types, negated: false } => Some(
+                (0..types.len())
+                    .filter(|t| types[*t])
+                    .map(|t| var.frequency(t).unwrap_or(0))
+                    .sum()
+            ),
+            _ => None,
+        }
+    }
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn error(&self, message: &'static str) -> QueryError {
+        QueryError::Syntax { position: self.position, message }
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = &self.input[self.position..];
+        self.position += rest.len() - rest.trim_start().len();
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.skip_whitespace();
+        self.input[self.position..].chars().next()
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_whitespace();
+        if self.input[self.position..].starts_with(token) {
+            self.position += token.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn query(mut self) -> Result<Query, QueryError> {
+        let mut tokens = Vec::new();
+        let mut within = None;
+
+        while let Some(c) = self.peek() {
+            let constraints = match c {
+                '[' => self.token_pattern()?,
+                '"' => {
+                    let regex = self.string()?;
+                    vec![Constraint { variable: DEFAULT_VARIABLE.to_owned(), regex, negated: false, flags: self.flags()? }]
+                }
+                _ if self.eat_keyword("within") => {
+                    within = Some(self.name("expected a layer name")?);
+                    if self.peek().is_some() {
+                        return Err(self.error("expected the end of the query after the within clause"));
+                    }
+                    break;
+                }
+                _ => return Err(self.error("expected '[', '\"' or 'within'")),
+            };
+            tokens.push(TokenPattern { constraints, repetition: self.quantifier()? });
+        }
+
+        if tokens.is_empty() {
+            return Err(self.error("empty query"));
+        }
+        if tokens.iter().all(|t| t.repetition.min == 0) {
+            return Err(self.error("query matches the empty sequence"));
+        }
+        Ok(Query { tokens, within })
+    }
+
+    /// Consumes `keyword` if it is followed by whitespace or the end of the input
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        let rest = &self.input[self.position..];
+        let followed = rest.strip_prefix(keyword).is_some_and(|r| r.chars().next().is_none_or(char::is_whitespace));
+        if followed {
+            self.position += keyword.len();
+        }
+        followed
+    }
+
+    fn token_pattern(&mut self) -> Result<Vec<Constraint>, QueryError> {
+        self.eat("[");
+        let mut constraints = Vec::new();
+
+        if self.eat("]") {
+            return Ok(constraints);
+        }
+
+        loop {
+            constraints.push(self.constraint()?);
+            if self.eat("]") {
+                return Ok(constraints);
+            } else if !self.eat("&") {
+                return Err(self.error("expected '&' or ']'"));
+            }
+        }
+    }
+
+    /// Name of a variable or layer
+    fn name(&mut self, message: &'static str) -> Result<String, QueryError> {
+        self.skip_whitespace();
+        let rest = &self.input[self.position..];
+        let len = rest.find(|c: char| !(c.is_alphanumeric() || c == '_' || c == '-')).unwrap_or(rest.len());
+        if len == 0 {
+            return Err(self.error(message));
+        }
+        self.position += len;
+        Ok(rest[..len].to_owned())
+    }
+
+    fn constraint(&mut self) -> Result<Constraint, QueryError> {
+        let variable = self.name("expected a variable name")?;
+
+        let negated = if self.eat("!=") {
+            true
+        } else if self.eat("=") {
+            false
+        } else {
+            return Err(self.error("expected '=' or '!='"));
+        };
+
+        let regex = self.string()?;
+        Ok(Constraint { variable, regex, negated, flags: self.flags()? })
+    }
+
+    /// Flags directly after a string, `%c` for case and `%d` for diacritic folding
+    fn flags(&mut self) -> Result<RegexFlags, QueryError> {
+        let mut flags = RegexFlags::default();
+        let Some(rest) = self.input[self.position..].strip_prefix('%') else {
+            return Ok(flags);
+        };
+
+        let len = rest.find(|c: char| !c.is_ascii_alphabetic()).unwrap_or(rest.len());
+        if len == 0 {
+            return Err(self.error("expected flags after '%'"));
+        }
+        self.position += 1;
+        for c in rest[..len].chars() {
+            flags = match c {
+                'c' => flags.case_insensitive(true),
+                #[cfg(feature = "normalization")]
+                'd' => flags.folded(true),
+                #[cfg(not(feature = "normalization"))]
+                'd' => return Err(self.error("the flag 'd' needs the normalization feature")),
+                _ => return Err(self.error("unknown flag, expected 'c' or 'd'")),
+            };
+            self.position += 1;
+        }
+        Ok(flags)
+    }
+
+    /// Optional quantifier after a token pattern
+    fn quantifier(&mut self) -> Result<Repetition, QueryError> {
+        let (min, max) = match self.input[self.position..].chars().next() {
+            Some('?') => (0, Some(1)),
+            Some('*') => (0, None),
+            Some('+') => (1, None),
+            Some('{') => {
+                self.position += 1;
+                let min = self.number()?;
+                let max = if self.eat(",") {
+                    self.number()?
+                } else {
+                    Some(min.ok_or_else(|| self.error("expected a number"))?)
+                };
+                if !self.eat("}") {
+                    return Err(self.error("expected '}'"));
+                }
+                let min = min.unwrap_or(0);
+                if max.is_some_and(|max| max < min || max == 0) {
+                    return Err(self.error("invalid repetition range"));
+                }
+                return Ok(Repetition { min, max });
+            }
+            _ => return Ok(Repetition::ONCE),
+        };
+        self.position += 1;
+        Ok(Repetition { min, max })
+    }
+
+    /// Decimal number, `None` if there is none
+    fn number(&mut self) -> Result<Option<usize>, QueryError> {
+        self.skip_whitespace();
+        let rest = &self.input[self.position..];
+        let len = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+        if len == 0 {
+            return Ok(None);
+        }
+        let number = rest[..len].parse().map_err(|_| self.error("number too large"))?;
+        self.position += len;
+        Ok(Some(number))
+    }
+
+    /// Double quoted regex, `\"` is an escaped quote and all other escapes are kept for the regex
+    fn string(&mut self) -> Result<String, QueryError> {
+        if !self.eat("\"") {
+            return Err(self.error("expected '\"'"));
+        }
+
+        let mut regex = String::new();
+        let mut chars = self.input[self.position..].char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => {
+                    self.position += i + 1;
+                    return Ok(regex);
+                }
+                '\\' => match chars.next() {
+                    Some((_, '"')) => regex.push('"'),
+                    Some((_, c)) => {
+                        regex.push('\\');
+                        regex.push(c);
+                    }
+                    None => break,
+                },
+                c => regex.push(c),
+            }
+        }
+
+        self.position = self.input.len();
+        Err(self.error("unterminated string"))
+    }
+}
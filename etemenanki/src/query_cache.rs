@@ -0,0 +1,216 @@
+//! Persistent cache of query results, e.g. the positions matched by a regex.
+//!
+//! Results are stored as small containers in a directory, by default `.query-cache` inside
+//! the datastore, either as a list of positions or as a list of ranges, e.g. the matches of
+//! a [`Query`](crate::query::Query). Entries are keyed by the query string and the UUIDs of
+//! the containers the query was run on. Re-encoding a variable gives it a new UUID, so stale entries are never
+//! returned, and [`QueryCache::prune`] deletes them from disk.
+//!
+//! ```no_run
+//! # use etemenanki::{components::RegexFlags, query_cache::QueryCache, Datastore};
+//! let datastore = Datastore::open("dickens").unwrap();
+//! let cache = QueryCache::for_datastore(&datastore).unwrap();
+//! let words = datastore["primary"]["word"].as_indexed_string().unwrap();
+//!
+//! let matches = cache.get_or_insert_with("word=/the.*/", &[words.uuid()], || {
+//!     let mut positions: Vec<usize> = words.lexicon().regex_matches("the.*", RegexFlags::default()).unwrap()
+//!         .flat_map(|tid| words.inverted_index().positions(tid).into_iter().flatten())
+//!         .collect();
+//!     positions.sort_unstable();
+//!     positions
+//! }).unwrap();
+//! ```
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use uuid::Uuid;
+
+use crate::components::{self, CachedVector, Component, FnvHash, Vector};
+use crate::container::{encode_in_memory, Container, ContainerBuilder, EncodeError};
+use crate::Datastore;
+
+/// Directory of the cache inside a datastore, it is not scanned for containers
+pub const CACHE_DIR: &str = ".query-cache";
+
+/// Extension of cache entries, which keeps them out of datastore scans
+const EXTENSION: &str = "zigc";
+
+#[derive(Debug, Clone)]
+pub struct QueryCache {
+    dir: PathBuf,
+}
+
+impl QueryCache {
+    /// Opens the cache in `dir`, creating the directory if needed.
+    pub fn new<P: Into<PathBuf>>(dir: P) -> io::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        Ok(Self { dir })
+    }
+
+    /// Opens the cache in the [`CACHE_DIR`] of a local datastore.
+    pub fn for_datastore(datastore: &Datastore) -> io::Result<Self> {
+        if !datastore.path().is_dir() {
+            return Err(io::Error::new(io::ErrorKind::Unsupported, "only local datastores have a query cache"));
+        }
+        Self::new(datastore.path().join(CACHE_DIR))
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// File name and UUID list of an entry, entries of positions and ranges are stored apart
+    fn key(query: &str, uuids: &[Uuid], component: &str) -> (String, String) {
+        let mut uuids = uuids.to_vec();
+        uuids.sort_unstable();
+        uuids.dedup();
+
+        let uuids = uuids.iter().map(Uuid::to_string).collect::<Vec<_>>().join(",");
+        let hash = format!("{}\0{}\0{}", component, query, uuids).fnv_hash() as u64;
+        (format!("{:016x}.{}", hash, EXTENSION), uuids)
+    }
+
+    /// Returns the cached result of `query` on the containers `uuids`, in any order.
+    ///
+    /// Missing, unreadable and colliding entries are all treated as a cache miss.
+    pub fn get(&self, query: &str, uuids: &[Uuid]) -> Option<Vec<usize>> {
+        let rows = self.get_rows::<1>(query, uuids, "Positions")?;
+        Some(rows.into_iter().map(|[p]| p as usize).collect())
+    }
+
+    /// Returns the cached ranges of `query` on the containers `uuids`, see [`get`](Self::get).
+    ///
+    /// Entries of positions and of ranges are distinct, the ranges stored by
+    /// [`put_ranges`](Self::put_ranges) are never returned as positions and vice versa.
+    pub fn get_ranges(&self, query: &str, uuids: &[Uuid]) -> Option<Vec<(usize, usize)>> {
+        let rows = self.get_rows::<2>(query, uuids, "Ranges")?;
+        Some(rows.into_iter().map(|[start, end]| (start as usize, end as usize)).collect())
+    }
+
+    fn get_rows<const D: usize>(&self, query: &str, uuids: &[Uuid], component: &str) -> Option<Vec<[i64; D]>> {
+        let (file, uuids) = Self::key(query, uuids, component);
+        let bytes = fs::read(self.dir.join(&file)).ok()?;
+        let container = Container::from_bytes(&bytes, file).ok()?;
+
+        let metadata = container.metadata();
+        if !metadata.contains(&("query", query)) || !metadata.contains(&("uuids", uuids.as_str())) {
+            return None;
+        }
+
+        let rows = match container.get_component(component)? {
+            Component::Vector(v) => CachedVector::<D>::new(v)?,
+            _ => return None,
+        };
+        Some(rows.iter().collect())
+    }
+
+    /// Stores the result of `query` on the containers `uuids`, replacing an older entry.
+    ///
+    /// The entry is written to a temporary file first, so concurrent readers never see
+    /// a partial container.
+    pub fn put(&self, query: &str, uuids: &[Uuid], positions: &[usize]) -> Result<(), EncodeError> {
+        let rows = positions.iter().map(|&p| [p as i64]);
+        self.put_rows(query, uuids, "Positions", 'p', rows, positions.len())
+    }
+
+    /// Stores the ranges matched by `query` on the containers `uuids`, see [`put`](Self::put)
+    pub fn put_ranges(&self, query: &str, uuids: &[Uuid], ranges: &[(usize, usize)]) -> Result<(), EncodeError> {
+        let rows = ranges.iter().map(|&(start, end)| [start as i64, end as i64]);
+        self.put_rows(query, uuids, "Ranges", 'r', rows, ranges.len())
+    }
+
+    fn put_rows<I, const D: usize>(&self, query: &str, uuids: &[Uuid], component: &str, ctype: char, rows: I, n: usize) -> Result<(), EncodeError>
+    where
+        I: Iterator<Item = [i64; D]>,
+    {
+        let (file, uuid_list) = Self::key(query, uuids, component);
+        let metadata = [("query", query), ("uuids", uuid_list.as_str())];
+
+        let bytes = encode_in_memory(|cursor| {
+            ContainerBuilder::new_into_file(file.clone(), cursor, 2)
+                .edit_header(|h| {
+                    h.family('X').class('Q').ctype(ctype).dim1(n).dim2(D);
+                })
+                .add_component(component, if n == 0 { components::Type::Vector } else { components::Type::VectorDelta }, |bom_entry, file| {
+                    // compressed vectors can't be empty
+                    unsafe {
+                        if n == 0 {
+                            Vector::encode_uncompressed_to_container_file(std::iter::empty(), 0, D, file, bom_entry, bom_entry.offset as u64)
+                        } else {
+                            Vector::encode_delta_to_container_file(rows, n, file, bom_entry, bom_entry.offset as u64)
+                        }
+                    }
+                })
+                .metadata(&metadata)
+                .build()
+        })?;
+
+        let mut tmp = tempfile::NamedTempFile::new_in(&self.dir)?;
+        tmp.write_all(&bytes)?;
+        tmp.persist(self.dir.join(file)).map_err(|e| e.error)?;
+        Ok(())
+    }
+
+    /// Returns the cached result of `query` or computes it with `compute` and stores it.
+    pub fn get_or_insert_with<F>(&self, query: &str, uuids: &[Uuid], compute: F) -> Result<Vec<usize>, EncodeError>
+    where
+        F: FnOnce() -> Vec<usize>,
+    {
+        if let Some(positions) = self.get(query, uuids) {
+            return Ok(positions);
+        }
+
+        let positions = compute();
+        self.put(query, uuids, &positions)?;
+        Ok(positions)
+    }
+
+    /// Deletes all entries depending on a container that is not part of `datastore` anymore
+    /// and returns the number of deleted entries.
+    pub fn prune(&self, datastore: &Datastore) -> io::Result<usize> {
+        let mut current = Vec::new();
+        for uuid in datastore.layer_uuids() {
+            let layer = &datastore[*uuid];
+            current.push(uuid.to_string());
+            for name in layer.variable_names() {
+                current.extend(layer[name].uuid().map(|u| u.to_string()));
+            }
+        }
+
+        self.remove_where(|uuids| !uuids.iter().all(|u| current.contains(u)))
+    }
+
+    /// Deletes all entries
+    pub fn clear(&self) -> io::Result<usize> {
+        self.remove_where(|_| true)
+    }
+
+    fn remove_where<F: Fn(&[String]) -> bool>(&self, remove: F) -> io::Result<usize> {
+        let mut removed = 0;
+
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some(EXTENSION) {
+                continue;
+            }
+
+            let uuids = fs::read(&path).ok()
+                .and_then(|bytes| {
+                    let container = Container::from_bytes(&bytes, String::new()).ok()?;
+                    let uuids = container.metadata().into_iter().find(|(k, _)| *k == "uuids")?.1;
+                    Some(uuids.split(',').map(str::to_owned).collect::<Vec<_>>())
+                });
+
+            // unreadable entries are removed as well
+            if uuids.map_or(true, |uuids| remove(&uuids)) {
+                fs::remove_file(&path)?;
+                removed += 1;
+            }
+        }
+
+        Ok(removed)
+    }
+}
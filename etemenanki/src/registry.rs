@@ -0,0 +1,89 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::container::{self, Container, Header};
+
+/// Validates a container of a type `Datastore::open` does not instantiate itself.
+///
+/// Containers accepted by their handler are kept as-is and can be retrieved with
+/// `Datastore::extension_by_name`.
+pub type ContainerHandler = fn(&Container) -> Result<(), container::TryFromError>;
+
+/// What `Datastore::open` does with containers no handler is registered for.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum UnknownPolicy {
+    /// Leave the container out and record it in `Datastore::skipped_containers`
+    #[default]
+    Skip,
+    /// Fail with `DatastoreError::UnknownContainerType`
+    Error,
+}
+
+/// Registry of handlers for container types beyond the built-in layers and variables.
+///
+/// This keeps datastores written by newer versions (e.g. containing a tree layer)
+/// readable: their containers can be handled by an extension, skipped, or rejected.
+#[derive(Debug, Clone, Default)]
+pub struct ContainerRegistry {
+    handlers: HashMap<[u8; 3], ContainerHandler>,
+    policy: UnknownPolicy,
+}
+
+impl ContainerRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a handler for the container type with the given type code, e.g. `*b"ZLt"`.
+    /// Built-in types can not be overridden.
+    pub fn register(mut self, type_code: [u8; 3], handler: ContainerHandler) -> Self {
+        self.handlers.insert(type_code, handler);
+        self
+    }
+
+    pub fn policy(mut self, policy: UnknownPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    pub fn unknown_policy(&self) -> UnknownPolicy {
+        self.policy
+    }
+
+    pub fn handler(&self, type_code: [u8; 3]) -> Option<ContainerHandler> {
+        self.handlers.get(&type_code).copied()
+    }
+
+    /// Checks if containers with this header are instantiated by `Datastore::open` itself.
+    pub fn is_builtin(header: &Header) -> bool {
+        use container::Type::*;
+
+        matches!(
+            header.try_container_type(),
+            Ok(PrimaryLayer
+                | SegmentationLayer
+                | SpanLayer
+                | PlainStringVariable
+                | IntegerVariable
+                | OffsetVariable
+                | PointerVariable
+                | SetVariable
+                | SparseVariable
+                | SourceVariable
+                | IndexedStringVariable)
+        )
+    }
+}
+
+/// A container left out of a datastore because its type is not supported.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SkippedContainer {
+    pub name: String,
+    pub type_code: [u8; 3],
+}
+
+impl fmt::Display for SkippedContainer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (type {})", self.name, String::from_utf8_lossy(&self.type_code))
+    }
+}
@@ -0,0 +1,41 @@
+//! Seedable random samples of positions, ranges and matches, e.g. for evaluation sets.
+//!
+//! Samples are drawn without replacement and returned in corpus order. The same seed
+//! always yields the same sample for the same datastore.
+
+use rand::rngs::StdRng;
+use rand::seq::index;
+use rand::{Rng, SeedableRng};
+
+/// Draws `n` distinct values from `0..len` in ascending order, all of them if `n >= len`.
+///
+/// Only the drawn values are materialized, not the whole range.
+pub fn random_indices(len: usize, n: usize, seed: u64) -> Vec<usize> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut sample = index::sample(&mut rng, len, n.min(len)).into_vec();
+    sample.sort_unstable();
+    sample
+}
+
+/// Reservoir sample of `n` items of an iterator of unknown length, in iteration order.
+///
+/// Consumes the whole iterator, but keeps at most `n` items in memory. Use this for
+/// query results that are only available as a stream, e.g. concordance lines.
+pub fn reservoir<I: IntoIterator>(items: I, n: usize, seed: u64) -> Vec<I::Item> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut sample: Vec<(usize, I::Item)> = Vec::with_capacity(n);
+
+    for (i, item) in items.into_iter().enumerate() {
+        if sample.len() < n {
+            sample.push((i, item));
+        } else {
+            let j = rng.gen_range(0..=i);
+            if j < n {
+                sample[j] = (i, item);
+            }
+        }
+    }
+
+    sample.sort_unstable_by_key(|(i, _)| *i);
+    sample.into_iter().map(|(_, item)| item).collect()
+}
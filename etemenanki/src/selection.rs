@@ -0,0 +1,139 @@
+//! Joint decoding of several variables of the same layer.
+//!
+//! A [`Selection`] iterates a fixed list of variables position by position and yields one
+//! row of values per position. All columns are advanced in lockstep, so each of their
+//! streams decodes every block exactly once, which makes this the fast path for exporting
+//! whole token streams (e.g. to VRT) compared to calling [`Variable::get`] per value.
+
+use std::{error, fmt};
+
+use crate::layers::Layer;
+use crate::variables::{Variable, VariableIterator, VariableValue};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SelectionError {
+    /// No layer has a variable with this name
+    UnknownVariable(String),
+    /// The variables exist, but not all on a single layer
+    DifferentLayers,
+    /// More than one layer has all of the selected variables
+    AmbiguousLayer,
+    /// The variable type can not be decoded by a selection
+    UnsupportedVariable(String),
+}
+
+impl fmt::Display for SelectionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownVariable(name) => write!(f, "no variable named {}", name),
+            Self::DifferentLayers => write!(f, "selected variables are not on the same layer"),
+            Self::AmbiguousLayer => write!(f, "selected variables exist on more than one layer"),
+            Self::UnsupportedVariable(name) => write!(f, "variable {} can not be selected", name),
+        }
+    }
+}
+
+impl error::Error for SelectionError {}
+
+/// A list of variables of one layer that are decoded together.
+#[derive(Debug)]
+pub struct Selection<'a, 'map> {
+    names: Vec<String>,
+    variables: Vec<&'a Variable<'map>>,
+    len: usize,
+}
+
+impl<'a, 'map> Selection<'a, 'map> {
+    /// Selects the variables `names` of `layer`, in the given order.
+    pub fn new<S: AsRef<str>>(layer: &'a Layer<'map>, names: &[S]) -> Result<Self, SelectionError> {
+        let mut variables = Vec::with_capacity(names.len());
+
+        for name in names {
+            let name = name.as_ref();
+            match layer.variable_by_name(name) {
+                Some(Variable::ExternalPointer(_) | Variable::Hash(_)) => {
+                    return Err(SelectionError::UnsupportedVariable(name.to_owned()))
+                }
+                Some(var) => variables.push(var),
+                None => return Err(SelectionError::UnknownVariable(name.to_owned())),
+            }
+        }
+
+        Ok(Self {
+            names: names.iter().map(|n| n.as_ref().to_owned()).collect(),
+            variables,
+            len: layer.len(),
+        })
+    }
+
+    /// Iterator over the rows at positions `start..end`, `None` if the range is out of bounds
+    pub fn iter_range(&self, start: usize, end: usize) -> Option<SelectionIterator<'map>> {
+        if start > end || end > self.len {
+            return None;
+        }
+
+        let columns = self.variables.iter()
+            .map(|var| var.get_range(start, end))
+            .collect::<Option<Vec<_>>>()?;
+
+        Some(SelectionIterator { columns, position: start, end })
+    }
+
+    pub fn iter(&self) -> SelectionIterator<'map> {
+        self.iter_range(0, self.len).expect("full range of a selection is always valid")
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    pub fn prefetch_range(&self, start: usize, end: usize) {
+        for var in &self.variables {
+            var.prefetch_range(start, end);
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.variables.len()
+    }
+}
+
+/// Iterator over the rows of a [`Selection`], one value per selected variable
+pub struct SelectionIterator<'map> {
+    columns: Vec<VariableIterator<'map>>,
+    position: usize,
+    end: usize,
+}
+
+impl<'map> SelectionIterator<'map> {
+    /// Position of the next row
+    pub fn position(&self) -> usize {
+        self.position
+    }
+}
+
+impl<'map> Iterator for SelectionIterator<'map> {
+    type Item = Vec<VariableValue<'map>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.position < self.end {
+            self.position += 1;
+            self.columns.iter_mut()
+                .map(|column| column.next())
+                .collect()
+        } else {
+            None
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.end - self.position;
+        (len, Some(len))
+    }
+}
+
+impl<'map> ExactSizeIterator for SelectionIterator<'map> {}
@@ -0,0 +1,424 @@
+//! JSON API over a datastore, the backend of `ziggurat serve` for web concordancers.
+//!
+//! [`Api::handle`] answers a `GET` request for one of these paths:
+//!
+//! | path | parameters | response |
+//! |------|------------|----------|
+//! | `/layers` | | name, type, length, base layer and variables of each layer |
+//! | `/query` | `q`, `layer`, `offset`, `limit` or `cursor` | total number of matches and a page of match ranges |
+//! | `/concordance` | `q`, `layer`, `offset`, `limit` or `cursor`, `show`, `context`, `meta` | total number of matches and a page of [`KwicLine`]s |
+//! | `/frequency` | `layer`, `variable`, `where`, `top` | total count and the most frequent values |
+//!
+//! `layer` defaults to `primary`, `show` to `word` and `context` to 5. `meta` and `where`
+//! may be repeated, they take `<layer>.<variable>` and the filters of
+//! [`MetadataFilter::parse`]. Errors are returned as `{"error": "..."}` with a 4xx status.
+//!
+//! Pages of matches come with the token of a [`Cursor`] in `next`, which is passed as
+//! `cursor` instead of the query and its layer to fetch the following page. With a
+//! [`QueryCache`] set by [`Api::cache`] the matches are not computed again for each page.
+//!
+//! With the `server` feature [`serve`] answers requests over HTTP. The caches of a
+//! datastore are not shared between threads, so requests are answered one at a time.
+//!
+//! ```no_run
+//! # use etemenanki::{server::Api, Datastore};
+//! let datastore = Datastore::open("dickens").unwrap();
+//! let response = Api::new(&datastore).handle("/query?q=%22the%22+%22man%22&limit=10");
+//! assert!(response.status == 200);
+//! ```
+
+use std::{error, fmt};
+
+use serde::Serialize;
+use serde_json::json;
+
+use crate::concordance::{Concordance, KwicLine};
+use crate::frequency::{self, FrequencyError, FrequencyList, MetadataFilter};
+use crate::layers::Layer;
+use crate::pagination::{self, Cursor, PaginationError, Paginator};
+use crate::query::QueryError;
+use crate::query_cache::QueryCache;
+use crate::variables::VariableValue;
+use crate::Datastore;
+
+/// Number of matches or values returned if a request has no `limit` or `top`
+pub const DEFAULT_LIMIT: usize = 100;
+
+/// Largest `limit` or `top` a request may ask for unless set by [`Api::max_limit`]
+pub const DEFAULT_MAX_LIMIT: usize = 10_000;
+
+#[derive(Debug)]
+pub enum ApiError {
+    /// Unknown path, or unknown layer or variable in the parameters
+    NotFound(String),
+    /// Missing or malformed parameter
+    BadRequest(String),
+    Query(QueryError),
+    Pagination(PaginationError),
+    Frequency(FrequencyError),
+}
+
+impl ApiError {
+    /// HTTP status code of the error response
+    pub fn status(&self) -> u16 {
+        match self {
+            Self::NotFound(_)
+            | Self::Query(QueryError::UnknownLayer(_) | QueryError::UnknownVariable(_))
+            | Self::Frequency(FrequencyError::UnknownLayer(_) | FrequencyError::UnknownVariable(_)) => 404,
+            Self::Pagination(PaginationError::StaleCursor) => 410,
+            _ => 400,
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotFound(what) => write!(f, "{} not found", what),
+            Self::BadRequest(message) => write!(f, "{}", message),
+            Self::Query(e) => write!(f, "{}", e),
+            Self::Pagination(e) => write!(f, "{}", e),
+            Self::Frequency(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl error::Error for ApiError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::Query(e) => Some(e),
+            Self::Pagination(e) => Some(e),
+            Self::Frequency(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<QueryError> for ApiError {
+    fn from(e: QueryError) -> Self {
+        Self::Query(e)
+    }
+}
+
+impl From<PaginationError> for ApiError {
+    fn from(e: PaginationError) -> Self {
+        match e {
+            PaginationError::Query(e) => Self::Query(e),
+            e => Self::Pagination(e),
+        }
+    }
+}
+
+impl From<FrequencyError> for ApiError {
+    fn from(e: FrequencyError) -> Self {
+        Self::Frequency(e)
+    }
+}
+
+/// Status code and JSON body of an answer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    pub status: u16,
+    pub body: String,
+}
+
+impl Response {
+    fn json<T: Serialize>(value: &T) -> Self {
+        Self { status: 200, body: serde_json::to_string(value).expect("API responses are valid JSON") }
+    }
+
+    fn error(status: u16, message: &str) -> Self {
+        Self { status, body: json!({ "error": message }).to_string() }
+    }
+}
+
+impl From<ApiError> for Response {
+    fn from(e: ApiError) -> Self {
+        Self::error(e.status(), &e.to_string())
+    }
+}
+
+#[derive(Serialize)]
+struct LayerInfo<'a> {
+    name: &'a str,
+    #[serde(rename = "type")]
+    layer_type: &'static str,
+    len: usize,
+    base: Option<&'a str>,
+    variables: Vec<VariableInfo<'a>>,
+}
+
+#[derive(Serialize)]
+struct VariableInfo<'a> {
+    name: &'a str,
+    #[serde(rename = "type")]
+    variable_type: &'static str,
+}
+
+#[derive(Serialize)]
+struct Page<T> {
+    total: usize,
+    offset: usize,
+    results: Vec<T>,
+    /// Cursor token of the following page
+    next: Option<String>,
+}
+
+impl<T> Page<T> {
+    fn new(page: pagination::Page, results: Vec<T>) -> Self {
+        Self { total: page.total, offset: page.offset, results, next: page.next.as_ref().map(Cursor::to_token) }
+    }
+}
+
+#[derive(Serialize)]
+struct FrequencyEntry<'a, 'map> {
+    value: &'a VariableValue<'map>,
+    count: usize,
+}
+
+/// Decoded parameters of the query string of a request
+struct Params(Vec<(String, String)>);
+
+impl Params {
+    fn parse(query: &str) -> Result<Self, ApiError> {
+        let pairs = query.split('&')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| {
+                let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+                Ok((percent_decode(key)?, percent_decode(value)?))
+            })
+            .collect::<Result<_, ApiError>>()?;
+        Ok(Self(pairs))
+    }
+
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str())
+    }
+
+    fn get_all<'a>(&'a self, key: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.0.iter().filter(move |(k, _)| k == key).map(|(_, v)| v.as_str())
+    }
+
+    fn required(&self, key: &str) -> Result<&str, ApiError> {
+        self.get(key).ok_or_else(|| ApiError::BadRequest(format!("missing parameter {}", key)))
+    }
+
+    fn number(&self, key: &str, default: usize) -> Result<usize, ApiError> {
+        match self.get(key) {
+            Some(value) => value.parse().map_err(|_| ApiError::BadRequest(format!("parameter {} is not a number", key))),
+            None => Ok(default),
+        }
+    }
+}
+
+/// Decodes `%XX` escapes and `+` for spaces
+fn percent_decode(s: &str) -> Result<String, ApiError> {
+    let invalid = || ApiError::BadRequest(format!("invalid escape in {}", s));
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut rest = s.as_bytes();
+
+    while let Some((&b, tail)) = rest.split_first() {
+        rest = tail;
+        match b {
+            b'+' => bytes.push(b' '),
+            b'%' => {
+                let hex = rest.get(..2).and_then(|h| std::str::from_utf8(h).ok()).ok_or_else(invalid)?;
+                bytes.push(u8::from_str_radix(hex, 16).map_err(|_| invalid())?);
+                rest = &rest[2..];
+            }
+            b => bytes.push(b),
+        }
+    }
+    String::from_utf8(bytes).map_err(|_| invalid())
+}
+
+/// Request handler over a datastore, see the [module documentation](self)
+pub struct Api<'a, 'map> {
+    datastore: &'a Datastore<'map>,
+    paginator: Paginator<'a, 'map>,
+    max_limit: usize,
+}
+
+impl<'a, 'map> Api<'a, 'map> {
+    pub fn new(datastore: &'a Datastore<'map>) -> Self {
+        Self { datastore, paginator: Paginator::new(datastore), max_limit: DEFAULT_MAX_LIMIT }
+    }
+
+    /// Keeps the matches of queries in `cache` between requests for their pages
+    pub fn cache(mut self, cache: QueryCache) -> Self {
+        self.paginator = self.paginator.cache(cache);
+        self
+    }
+
+    /// Largest `limit` or `top` a request may ask for
+    pub fn max_limit(mut self, max_limit: usize) -> Self {
+        self.max_limit = max_limit;
+        self
+    }
+
+    /// Answers a `GET` request for `url`, the path and query string of the request
+    pub fn handle(&self, url: &str) -> Response {
+        let (path, query) = url.split_once('?').unwrap_or((url, ""));
+        let result = Params::parse(query).and_then(|params| match path.trim_end_matches('/') {
+            "/layers" => self.layers(),
+            "/query" => self.query(&params),
+            "/concordance" => self.concordance(&params),
+            "/frequency" => self.frequency(&params),
+            _ => Err(ApiError::NotFound(format!("path {}", path))),
+        });
+
+        result.unwrap_or_else(Response::from)
+    }
+
+    fn layer(&self, params: &Params) -> Result<(&'a str, &'a Layer<'map>), ApiError> {
+        let name = params.get("layer").unwrap_or("primary");
+        self.datastore.layer_names()
+            .find(|n| *n == name)
+            .map(|n| (n.as_str(), &self.datastore[n.as_str()]))
+            .ok_or_else(|| ApiError::NotFound(format!("layer {}", name)))
+    }
+
+    fn limit(&self, params: &Params, key: &str) -> Result<usize, ApiError> {
+        let limit = params.number(key, DEFAULT_LIMIT)?;
+        if limit > self.max_limit {
+            return Err(ApiError::BadRequest(format!("{} is larger than {}", key, self.max_limit)));
+        }
+        Ok(limit)
+    }
+
+    fn layers(&self) -> Result<Response, ApiError> {
+        let name_of = |uuid| self.datastore.layer_names().find(|n| self.datastore[n.as_str()].uuid() == uuid);
+
+        let mut layers: Vec<LayerInfo> = self.datastore.layer_names()
+            .map(|name| {
+                let layer = &self.datastore[name.as_str()];
+                let mut variables: Vec<VariableInfo> = layer.variable_names()
+                    .map(|v| VariableInfo { name: v, variable_type: layer.variable_type(v).unwrap().name() })
+                    .collect();
+                variables.sort_unstable_by_key(|v| v.name);
+
+                LayerInfo {
+                    name,
+                    layer_type: match layer {
+                        Layer::Primary(_) => "primary",
+                        Layer::Segmentation(_) => "segmentation",
+                        Layer::Span(_) => "span",
+                    },
+                    len: layer.len(),
+                    base: layer.base().and_then(name_of).map(String::as_str),
+                    variables,
+                }
+            })
+            .collect();
+        layers.sort_unstable_by_key(|l| l.name);
+
+        Ok(Response::json(&layers))
+    }
+
+    /// Name of the queried layer and the requested page of the matches of the query in `q`,
+    /// or the page after `cursor`
+    fn matches(&self, params: &Params) -> Result<(String, pagination::Page), ApiError> {
+        if let Some(token) = params.get("cursor") {
+            let cursor = Cursor::from_token(token)?;
+            if cursor.size > self.max_limit {
+                return Err(ApiError::BadRequest(format!("limit is larger than {}", self.max_limit)));
+            }
+            return Ok((cursor.layer.clone(), self.paginator.next(&cursor)?));
+        }
+
+        let (name, _) = self.layer(params)?;
+        let limit = self.limit(params, "limit")?;
+        let page = self.paginator.at(params.required("q")?, name, params.number("offset", 0)?, limit)?;
+        Ok((name.to_owned(), page))
+    }
+
+    fn query(&self, params: &Params) -> Result<Response, ApiError> {
+        let (_, page) = self.matches(params)?;
+        let results = page.matches.clone();
+        Ok(Response::json(&Page::new(page, results)))
+    }
+
+    fn concordance(&self, params: &Params) -> Result<Response, ApiError> {
+        let (layer, page) = self.matches(params)?;
+        let layer = &self.datastore[layer.as_str()];
+        let show = params.get("show").unwrap_or("word");
+        let words = layer.variable_by_name(show)
+            .ok_or_else(|| ApiError::NotFound(format!("variable {}", show)))?;
+        let context = params.number("context", 5)?;
+        let mut concordance = Concordance::new(words, context);
+
+        let meta: Vec<(&str, &str)> = params.get_all("meta")
+            .map(|m| m.split_once('.').ok_or_else(|| ApiError::BadRequest(format!("expected <layer>.<variable>, got {}", m))))
+            .collect::<Result<_, _>>()?;
+        if let Some(&(seg_name, _)) = meta.first() {
+            if meta.iter().any(|(l, _)| *l != seg_name) {
+                return Err(ApiError::BadRequest("all meta variables must be on the same layer".to_owned()));
+            }
+            let segmentation = self.datastore.layer_by_name(seg_name)
+                .and_then(|l| l.as_segmentation())
+                .ok_or_else(|| ApiError::NotFound(format!("segmentation layer {}", seg_name)))?;
+            let names: Vec<_> = meta.iter().map(|(_, v)| *v).collect();
+            concordance = concordance.metadata(segmentation, &names);
+        }
+
+        let lines: Vec<KwicLine> = concordance.lines(page.matches.iter().copied());
+        Ok(Response::json(&Page::new(page, lines)))
+    }
+
+    fn frequency(&self, params: &Params) -> Result<Response, ApiError> {
+        let (_, layer) = self.layer(params)?;
+        let name = params.required("variable")?;
+        let variable = layer.variable_by_name(name)
+            .ok_or_else(|| ApiError::NotFound(format!("variable {}", name)))?;
+        let top = self.limit(params, "top")?;
+
+        // all filters have to match
+        let mut ranges: Option<Vec<(usize, usize)>> = None;
+        for filter in params.get_all("where") {
+            let selected = MetadataFilter::parse(filter)?.ranges(self.datastore, layer)?;
+            ranges = Some(match ranges {
+                Some(ranges) => frequency::intersect_ranges(&ranges, &selected),
+                None => selected,
+            });
+        }
+        let list = match ranges {
+            Some(ranges) => FrequencyList::from_positions(variable, ranges.into_iter().flat_map(|(start, end)| start..end))?,
+            None => FrequencyList::new(variable)?,
+        };
+
+        let entries: Vec<_> = list.top(top).iter().map(|(value, count)| FrequencyEntry { value, count: *count }).collect();
+        Ok(Response::json(&json!({ "total": list.total(), "types": list.len(), "entries": entries })))
+    }
+}
+
+/// Answers requests to `datastore` on `address`, e.g. `127.0.0.1:8080`, until the process
+/// is stopped.
+///
+/// Responses allow cross-origin requests, so the API can be used by web concordancers
+/// served from elsewhere.
+#[cfg(feature = "server")]
+pub fn serve(api: &Api, address: &str) -> std::io::Result<()> {
+    use std::io;
+    use tiny_http::{Header, Method, Server};
+
+    let server = Server::http(address).map_err(io::Error::other)?;
+    let headers = [
+        Header::from_bytes("Content-Type", "application/json").unwrap(),
+        Header::from_bytes("Access-Control-Allow-Origin", "*").unwrap(),
+    ];
+
+    for request in server.incoming_requests() {
+        let response = match request.method() {
+            Method::Get => api.handle(request.url()),
+            _ => Response::error(405, "only GET requests are supported"),
+        };
+
+        let mut http_response = tiny_http::Response::from_string(response.body).with_status_code(response.status);
+        for header in headers.iter() {
+            http_response.add_header(header.clone());
+        }
+        // a client that went away doesn't stop the server
+        let _ = request.respond(http_response);
+    }
+    Ok(())
+}
@@ -0,0 +1,186 @@
+//! Original documents stored alongside the tokenized corpus.
+//!
+//! A [`SourceVariable`] keeps the raw bytes of one document per segment of a segmentation
+//! layer, e.g. the HTML or XML a text was extracted from, so that a concordance can show a
+//! hit with its original formatting. Documents are deflate compressed unless they are
+//! stored uncompressed for direct access to the memory map.
+//!
+//! ```no_run
+//! # use etemenanki::Datastore;
+//! let datastore = Datastore::open("dickens").unwrap();
+//! let sources = datastore["text"].source_by_name("html").unwrap();
+//! let html = sources.get_source(0).unwrap();
+//! ```
+
+use std::borrow::Cow;
+use std::io::{Read, Write};
+
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use uuid::Uuid;
+
+use crate::components::{self, Blob, Vector};
+use crate::container::{self, Container, ContainerBuilder, EncodeError, EncodeTarget};
+use crate::macros::{check_and_return_component, get_container_base};
+use crate::storage::Storage;
+
+/// Raw documents of the segments of a layer, see the [module documentation](self).
+///
+/// The documents are concatenated in the "Blobs" component, "BlobOffsets" holds the
+/// offset of each of them and the end of the last one. `dim2` of the header is 1 if the
+/// documents are deflate compressed.
+#[derive(Debug)]
+pub struct SourceVariable<'map> {
+    base: Uuid,
+    storage: Box<dyn Storage>,
+    pub name: String,
+    pub(crate) header: &'map container::Header,
+    comment: Option<&'map str>,
+    blobs: Blob<'map>,
+    offsets: Vector<'map>,
+}
+
+impl<'map> SourceVariable<'map> {
+    pub fn comment(&self) -> Option<&'map str> {
+        self.comment
+    }
+
+    pub fn uuid(&self) -> Uuid {
+        self.header.uuid()
+    }
+
+    /// UUID of the layer whose segments the documents belong to
+    pub fn base(&self) -> Uuid {
+        self.base
+    }
+
+    /// Encodes the documents of `n` segments, one for each segment in order.
+    ///
+    /// Each document is compressed on its own, so a single one is decompressed on access.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "info", skip_all, fields(name = %name, n = n, compressed = compressed)))]
+    pub fn encode_to_file<W, I, B>(file: W, documents: I, n: usize, name: String, base: Uuid, compressed: bool, comment: &str) -> Result<Self, EncodeError> where W: EncodeTarget, I: Iterator<Item=B>, B: AsRef<[u8]> {
+        let mut offsets = Vec::with_capacity(n + 1);
+        offsets.push(0);
+
+        let builder = ContainerBuilder::new_into_file(name, file, 2 + ContainerBuilder::comment_capacity(comment))
+            .edit_header(| h | {
+                h.ziggurat_type(container::Type::SourceVariable)
+                    .dim1(n)
+                    .dim2(compressed as usize)
+                    .base1(Some(base));
+            })
+            .add_component("Blobs", components::Type::Blob, | bom_entry, file | {
+                let mut size = 0;
+                for document in documents.take(n) {
+                    let document = document.as_ref();
+                    if compressed {
+                        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+                        encoder.write_all(document)?;
+                        let bytes = encoder.finish()?;
+                        file.write_all(&bytes)?;
+                        size += bytes.len();
+                    } else {
+                        file.write_all(document)?;
+                        size += document.len();
+                    }
+                    offsets.push(size as i64);
+                }
+
+                if offsets.len() != n + 1 {
+                    return Err(EncodeError::LengthMismatch { expected: n, found: offsets.len() - 1 });
+                }
+                bom_entry.size = size as i64;
+                bom_entry.param1 = size as i64;
+                Ok(())
+            })
+            .add_component("BlobOffsets", components::Type::Vector, | bom_entry, file | {
+                unsafe {
+                    Vector::encode_uncompressed_to_container_file(offsets.iter().copied(), n + 1, 1, file, bom_entry, bom_entry.offset as u64)
+                }
+            });
+
+        Ok(builder.comment(comment).build()?.try_into().expect("SourceVariable returned by its constructor is inconsistent"))
+    }
+
+    /// Number of segments
+    pub fn len(&self) -> usize {
+        self.header.dim1()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn is_compressed(&self) -> bool {
+        self.header.dim2() == 1
+    }
+
+    /// Stored bytes of the document of `segment`, compressed if the variable is
+    pub fn get_raw(&self, segment: usize) -> Option<&'map [u8]> {
+        if segment >= self.len() {
+            return None;
+        }
+        let start = self.offsets.get_row_unchecked(segment)[0] as usize;
+        let end = self.offsets.get_row_unchecked(segment + 1)[0] as usize;
+        self.blobs.data().get(start..end)
+    }
+
+    /// Original document of `segment`, borrowed from the container if it is stored
+    /// uncompressed. `None` if the segment is out of bounds or its document is corrupt.
+    pub fn get_source(&self, segment: usize) -> Option<Cow<'map, [u8]>> {
+        let raw = self.get_raw(segment)?;
+        if !self.is_compressed() {
+            return Some(Cow::Borrowed(raw));
+        }
+
+        let mut document = Vec::new();
+        DeflateDecoder::new(raw).read_to_end(&mut document).ok()?;
+        Some(Cow::Owned(document))
+    }
+
+    /// Original document of `segment` as a string, with invalid UTF-8 sequences replaced
+    pub fn get_source_lossy(&self, segment: usize) -> Option<String> {
+        self.get_source(segment).map(|bytes| String::from_utf8_lossy(&bytes).into_owned())
+    }
+}
+
+impl<'map> TryFrom<Container<'map>> for SourceVariable<'map> {
+    type Error = container::TryFromError;
+
+    fn try_from(container: Container<'map>) -> Result<Self, Self::Error> {
+        let header = *container.header();
+
+        match header.container_type() {
+            container::Type::SourceVariable => {
+                let base = get_container_base!(container, SourceVariable);
+                let n = header.dim1();
+
+                let blobs = check_and_return_component!(container, "Blobs", Blob)?;
+
+                let offsets = check_and_return_component!(container, "BlobOffsets", Vector)?;
+                if offsets.len() != n + 1 || offsets.width() != 1 {
+                    return Err(Self::Error::WrongComponentDimensions("BlobOffsets"));
+                }
+                if offsets.get_row_unchecked(n)[0] as usize != blobs.len() {
+                    return Err(Self::Error::ConsistencyError("source offsets don't end at the end of the blobs"));
+                }
+
+                let comment = container.comment();
+                let (name, storage, header, _) = container.into_raw_parts();
+
+                Ok(Self {
+                    base,
+                    storage,
+                    name,
+                    header,
+                    comment,
+                    blobs,
+                    offsets,
+                })
+            }
+
+            _ => Err(Self::Error::WrongContainerType),
+        }
+    }
+}
@@ -0,0 +1,222 @@
+use std::fmt;
+use std::io;
+use std::ops::Range;
+use std::path::Path;
+
+#[cfg(all(feature = "mmap", unix))]
+use memmap2::Advice;
+#[cfg(feature = "mmap")]
+use memmap2::Mmap;
+
+/// Read-only byte storage backing a container.
+///
+/// Components are views into the bytes of their container, so implementations
+/// must keep the returned slice at a fixed address for their whole lifetime.
+pub trait Storage: fmt::Debug {
+    fn bytes(&self) -> &[u8];
+
+    /// Hints that the given byte range will be accessed soon. The default does nothing.
+    fn will_need(&self, _offset: usize, _len: usize) {}
+}
+
+#[cfg(feature = "mmap")]
+impl Storage for Mmap {
+    fn bytes(&self) -> &[u8] {
+        self
+    }
+
+    #[cfg(unix)]
+    fn will_need(&self, offset: usize, len: usize) {
+        // only a hint, failing to apply it is not an error
+        let _ = self.advise_range(Advice::WillNeed, offset, len);
+    }
+}
+
+/// Hints that `data`, which must be a view into `storage`, will be accessed soon.
+///
+/// Slices outside of the storage are ignored.
+pub fn will_need(storage: &dyn Storage, data: &[u8]) {
+    let bytes = storage.bytes().as_ptr_range();
+    let Range { start, end } = data.as_ptr_range();
+
+    if !data.is_empty() && start >= bytes.start && end <= bytes.end {
+        storage.will_need(start as usize - bytes.start as usize, data.len());
+    }
+}
+
+/// Opens a local file as memory mapped storage.
+#[cfg(feature = "mmap")]
+pub fn open_file<P: AsRef<Path>>(path: P) -> io::Result<Box<dyn Storage>> {
+    let file = std::fs::File::open(path)?;
+    let mmap = unsafe { Mmap::map(&file)? };
+    Ok(Box::new(mmap))
+}
+
+/// Opens a local file by reading it into memory, used on targets without memory maps.
+#[cfg(not(feature = "mmap"))]
+pub fn open_file<P: AsRef<Path>>(path: P) -> io::Result<Box<dyn Storage>> {
+    let bytes = std::fs::read(path)?;
+    Ok(Box::new(Memory::new(&bytes)))
+}
+
+/// Container storage held in memory, e.g. a container encoded by [`crate::container::encode_in_memory`].
+///
+/// The bytes are kept in 8 byte words, so integer components are as aligned as in a memory map.
+pub struct Memory {
+    words: Vec<u64>,
+    len: usize,
+}
+
+impl Memory {
+    pub fn new(bytes: &[u8]) -> Self {
+        let mut memory = Self::zeroed(bytes.len());
+        memory.bytes_mut().copy_from_slice(bytes);
+        memory
+    }
+
+    /// Storage of `len` zero bytes, to be filled in place
+    pub(crate) fn zeroed(len: usize) -> Self {
+        Self { words: vec![0u64; len.div_ceil(8)], len }
+    }
+
+    pub(crate) fn bytes_mut(&mut self) -> &mut [u8] {
+        unsafe { std::slice::from_raw_parts_mut(self.words.as_mut_ptr() as *mut u8, self.len) }
+    }
+}
+
+impl fmt::Debug for Memory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Memory")
+            .field("len", &self.len)
+            .finish()
+    }
+}
+
+impl Storage for Memory {
+    fn bytes(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.words.as_ptr() as *const u8, self.len) }
+    }
+}
+
+#[cfg(feature = "remote")]
+pub use remote::{get as http_get, HttpStorage, BLOCK_SIZE};
+
+#[cfg(feature = "remote")]
+mod remote {
+    use std::fmt;
+    use std::fs;
+    use std::io::{self, Read};
+    use std::path::{Path, PathBuf};
+
+    use crate::components::FnvHash;
+
+    use super::{Memory, Storage};
+
+    /// Size of the blocks fetched with a single ranged request, unless another one is given
+    pub const BLOCK_SIZE: usize = 4 << 20;
+
+    fn http_error(e: ureq::Error) -> io::Error {
+        io::Error::new(io::ErrorKind::Other, e)
+    }
+
+    /// Fetches the complete resource at `url`.
+    pub fn get(url: &str) -> io::Result<Vec<u8>> {
+        let mut data = Vec::new();
+        ureq::get(url)
+            .call()
+            .map_err(http_error)?
+            .into_reader()
+            .read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    /// Container storage fetched from an HTTP server via ranged requests.
+    ///
+    /// The container is fetched in blocks of [`BLOCK_SIZE`] bytes into word aligned memory
+    /// when it is opened, so errors are returned by [`open`](Self::open) instead of surfacing
+    /// on a later read. If a cache directory is given, blocks are kept there and are not
+    /// fetched again when the container is opened another time.
+    pub struct HttpStorage {
+        url: String,
+        data: Memory,
+    }
+
+    impl HttpStorage {
+        pub fn open(url: &str) -> io::Result<Self> {
+            Self::open_cached(url, None)
+        }
+
+        pub fn open_cached(url: &str, cache_dir: Option<PathBuf>) -> io::Result<Self> {
+            Self::open_with_block_size(url, cache_dir, BLOCK_SIZE)
+        }
+
+        /// Opens the container at `url` like [`open_cached`](Self::open_cached), fetching
+        /// blocks of `block_size` bytes.
+        pub fn open_with_block_size(url: &str, cache_dir: Option<PathBuf>, block_size: usize) -> io::Result<Self> {
+            if block_size == 0 {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "block size is 0"));
+            }
+            let len: usize = ureq::head(url)
+                .call()
+                .map_err(http_error)?
+                .header("Content-Length")
+                .and_then(|l| l.parse().ok())
+                .ok_or(io::Error::new(io::ErrorKind::InvalidData, "missing content length"))?;
+
+            let mut data = Memory::zeroed(len);
+            for (bi, block) in data.bytes_mut().chunks_mut(block_size).enumerate() {
+                let cached = cache_dir.as_ref()
+                    .map(|dir| dir.join(format!("{:016x}-{:x}-{}", url.fnv_hash() as u64, block_size, bi)));
+                fetch_block(url, bi * block_size, block, cached.as_deref())?;
+            }
+
+            Ok(Self { url: url.to_owned(), data })
+        }
+
+        pub fn url(&self) -> &str {
+            &self.url
+        }
+    }
+
+    /// Fills `buf` with the bytes of the resource at `url` from `start` on, from the file
+    /// `cached` if it has them and with a ranged request otherwise, which is then stored there
+    fn fetch_block(url: &str, start: usize, buf: &mut [u8], cached: Option<&Path>) -> io::Result<()> {
+        if let Some(block) = cached.and_then(|path| fs::read(path).ok()) {
+            if block.len() == buf.len() {
+                buf.copy_from_slice(&block);
+                return Ok(());
+            }
+        }
+
+        ureq::get(url)
+            .set("Range", &format!("bytes={}-{}", start, start + buf.len() - 1))
+            .call()
+            .map_err(http_error)?
+            .into_reader()
+            .read_exact(buf)
+            .map_err(|e| match e.kind() {
+                io::ErrorKind::UnexpectedEof => io::Error::new(io::ErrorKind::UnexpectedEof, "short ranged read"),
+                _ => e,
+            })?;
+
+        if let Some(path) = cached {
+            fs::write(path, buf)?;
+        }
+        Ok(())
+    }
+
+    impl fmt::Debug for HttpStorage {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.debug_struct("HttpStorage")
+                .field("url", &self.url)
+                .field("len", &self.data.bytes().len())
+                .finish()
+        }
+    }
+
+    impl Storage for HttpStorage {
+        fn bytes(&self) -> &[u8] {
+            self.data.bytes()
+        }
+    }
+}
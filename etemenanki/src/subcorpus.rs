@@ -0,0 +1,308 @@
+//! Named match lists saved in a datastore, like the named query results of CQP.
+//!
+//! A subcorpus is a list of ranges on one layer, e.g. the matches of a query or a
+//! selection of documents. It is defined once, saved under its name and loaded again
+//! by later queries through [`Datastore::subcorpora`]. Subcorpora are small containers
+//! in the `subcorpora` directory of the datastore. They use their own file extension,
+//! so they are not mistaken for layers or variables when the datastore is opened.
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::{error, fmt};
+
+use uuid::Uuid;
+
+use crate::components::{self, CachedVector, Component, Vector};
+use crate::container::{self, encode_in_memory, Container, ContainerBuilder, EncodeError};
+use crate::layers::RangeError;
+use crate::Datastore;
+
+/// Directory of the subcorpora inside a datastore
+pub const SUBCORPUS_DIR: &str = "subcorpora";
+
+const EXTENSION: &str = "zigs";
+
+/// Type code of subcorpus containers
+const TYPE_CODE: [u8; 3] = *b"XSr";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Subcorpus {
+    name: String,
+    base: Uuid,
+    ranges: Vec<(usize, usize)>,
+}
+
+fn check_name(name: &str) -> Result<(), SubcorpusError> {
+    let valid = !name.is_empty()
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+
+    if valid {
+        Ok(())
+    } else {
+        Err(SubcorpusError::InvalidName(name.to_owned()))
+    }
+}
+
+impl Subcorpus {
+    /// Creates a subcorpus of the half-open `ranges` on the layer `base`.
+    ///
+    /// Ranges may overlap, they are sorted by start and end. Names may only contain
+    /// ASCII letters, digits, `_` and `-`.
+    pub fn new<S: Into<String>>(name: S, base: Uuid, mut ranges: Vec<(usize, usize)>) -> Result<Self, SubcorpusError> {
+        let name = name.into();
+        check_name(&name)?;
+
+        if let Some((i, range)) = ranges.iter().enumerate().find(|(_, (s, e))| s >= e) {
+            return Err(RangeError::Empty(i, *range).into());
+        }
+        ranges.sort_unstable();
+
+        Ok(Self { name, base, ranges })
+    }
+
+    /// Creates a subcorpus of single positions, e.g. the postings of a type.
+    pub fn from_positions<S, I>(name: S, base: Uuid, positions: I) -> Result<Self, SubcorpusError>
+    where
+        S: Into<String>,
+        I: IntoIterator<Item = usize>,
+    {
+        Self::new(name, base, positions.into_iter().map(|p| (p, p + 1)).collect())
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// UUID of the layer the ranges refer to
+    pub fn base(&self) -> Uuid {
+        self.base
+    }
+
+    /// Number of ranges
+    pub fn len(&self) -> usize {
+        self.ranges.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    pub fn ranges(&self) -> &[(usize, usize)] {
+        &self.ranges
+    }
+
+    /// All positions covered by at least one range, ascending and without duplicates
+    pub fn positions(&self) -> Vec<usize> {
+        let mut positions = Vec::new();
+        let mut next = 0;
+
+        for &(start, end) in self.ranges.iter() {
+            positions.extend(start.max(next)..end);
+            next = next.max(end);
+        }
+
+        positions
+    }
+}
+
+/// The subcorpora saved in a local datastore, see [`Datastore::subcorpora`]
+pub struct Subcorpora<'a, 'map> {
+    datastore: &'a Datastore<'map>,
+    dir: PathBuf,
+}
+
+impl<'a, 'map> Subcorpora<'a, 'map> {
+    pub(crate) fn new(datastore: &'a Datastore<'map>) -> Self {
+        Self {
+            datastore,
+            dir: datastore.path().join(SUBCORPUS_DIR),
+        }
+    }
+
+    fn file(&self, name: &str) -> PathBuf {
+        self.dir.join(format!("{}.{}", name, EXTENSION))
+    }
+
+    /// Names of all saved subcorpora, sorted
+    pub fn names(&self) -> io::Result<Vec<String>> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+
+        let mut names = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) == Some(EXTENSION) {
+                names.extend(path.file_stem().and_then(|s| s.to_str()).map(str::to_owned));
+            }
+        }
+
+        names.sort_unstable();
+        Ok(names)
+    }
+
+    /// Checks that the base layer of `subcorpus` is part of the datastore and contains all ranges.
+    fn check(&self, subcorpus: &Subcorpus) -> Result<(), SubcorpusError> {
+        let layer = self.datastore.layer_by_uuid(subcorpus.base)
+            .ok_or(SubcorpusError::UnknownLayer(subcorpus.base))?;
+
+        match subcorpus.ranges.last() {
+            Some(&(_, end)) if end > layer.len() => Err(SubcorpusError::OutOfBounds { end, len: layer.len() }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Saves `subcorpus`, replacing a saved subcorpus of the same name.
+    pub fn save(&self, subcorpus: &Subcorpus) -> Result<(), SubcorpusError> {
+        self.check(subcorpus)?;
+        fs::create_dir_all(&self.dir)?;
+
+        let n = subcorpus.len();
+        let layer_len = self.datastore[subcorpus.base].len();
+
+        let bytes = encode_in_memory(|cursor| {
+            let values = subcorpus.ranges.iter().map(|&(s, e)| [s as i64, e as i64]);
+            let vectype = if n == 0 { components::Type::Vector } else { components::Type::VectorDelta };
+
+            ContainerBuilder::new_into_file(subcorpus.name.clone(), cursor, 1)
+                .edit_header(|h| {
+                    h.family(TYPE_CODE[0] as char)
+                        .class(TYPE_CODE[1] as char)
+                        .ctype(TYPE_CODE[2] as char)
+                        .dim1(n)
+                        .dim2(layer_len)
+                        .base1(Some(subcorpus.base));
+                })
+                .add_component("RangeStream", vectype, |bom_entry, file| {
+                    // compressed vectors can't be empty
+                    unsafe {
+                        if n == 0 {
+                            Vector::encode_uncompressed_to_container_file(std::iter::empty(), 0, 2, file, bom_entry, bom_entry.offset as u64)
+                        } else {
+                            Vector::encode_delta_to_container_file(values, n, file, bom_entry, bom_entry.offset as u64)
+                        }
+                    }
+                })
+                .build()
+        })?;
+
+        let mut tmp = tempfile::NamedTempFile::new_in(&self.dir)?;
+        tmp.write_all(&bytes)?;
+        tmp.persist(self.file(&subcorpus.name)).map_err(|e| e.error)?;
+        Ok(())
+    }
+
+    /// Loads the subcorpus `name`.
+    ///
+    /// Fails if its base layer is not part of the datastore anymore or is shorter than
+    /// the subcorpus requires.
+    pub fn load(&self, name: &str) -> Result<Subcorpus, SubcorpusError> {
+        check_name(name)?;
+
+        let bytes = match fs::read(self.file(name)) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Err(SubcorpusError::NotFound(name.to_owned())),
+            Err(e) => return Err(e.into()),
+        };
+        let container = Container::from_bytes(&bytes, name.to_owned())?;
+
+        let header = container.header();
+        if header.type_code() != TYPE_CODE {
+            return Err(SubcorpusError::FormatError("not a subcorpus container"));
+        }
+        let base = header.base1().ok_or(SubcorpusError::FormatError("subcorpus without base layer"))?;
+
+        let ranges = match container.get_component("RangeStream") {
+            Some(Component::Vector(v)) => CachedVector::<2>::new(v)
+                .ok_or(SubcorpusError::FormatError("RangeStream has wrong dimensions"))?,
+            _ => return Err(SubcorpusError::FormatError("missing RangeStream")),
+        };
+        let ranges = ranges.iter().map(|[s, e]| (s as usize, e as usize)).collect();
+
+        let subcorpus = Subcorpus::new(name, base, ranges)?;
+        self.check(&subcorpus)?;
+        Ok(subcorpus)
+    }
+
+    /// Deletes the subcorpus `name`, returns false if there is no such subcorpus.
+    pub fn remove(&self, name: &str) -> Result<bool, SubcorpusError> {
+        check_name(name)?;
+
+        match fs::remove_file(self.file(name)) {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum SubcorpusError {
+    InvalidName(String),
+    InvalidRange(RangeError),
+    NotFound(String),
+    UnknownLayer(Uuid),
+    OutOfBounds { end: usize, len: usize },
+    /// Subcorpora can only be saved in datastores opened from a directory
+    NotLocal,
+    FormatError(&'static str),
+    IoError(io::Error),
+    ContainerError(container::Error),
+    EncodeError(EncodeError),
+}
+
+impl fmt::Display for SubcorpusError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidName(name) => write!(f, "invalid subcorpus name {:?}", name),
+            Self::InvalidRange(e) => write!(f, "{}", e),
+            Self::NotFound(name) => write!(f, "no subcorpus named {}", name),
+            Self::UnknownLayer(uuid) => write!(f, "base layer {} not in datastore", uuid),
+            Self::OutOfBounds { end, len } => write!(f, "range ending at {} out of bounds for layer of length {}", end, len),
+            Self::NotLocal => write!(f, "only local datastores have subcorpora"),
+            Self::FormatError(s) => write!(f, "invalid subcorpus: {}", s),
+            Self::IoError(e) => write!(f, "{}", e),
+            Self::ContainerError(e) => write!(f, "{}", e),
+            Self::EncodeError(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl error::Error for SubcorpusError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::InvalidRange(e) => Some(e),
+            Self::IoError(e) => Some(e),
+            Self::ContainerError(e) => Some(e),
+            Self::EncodeError(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<RangeError> for SubcorpusError {
+    fn from(e: RangeError) -> Self {
+        Self::InvalidRange(e)
+    }
+}
+
+impl From<io::Error> for SubcorpusError {
+    fn from(e: io::Error) -> Self {
+        Self::IoError(e)
+    }
+}
+
+impl From<container::Error> for SubcorpusError {
+    fn from(e: container::Error) -> Self {
+        Self::ContainerError(e)
+    }
+}
+
+impl From<EncodeError> for SubcorpusError {
+    fn from(e: EncodeError) -> Self {
+        Self::EncodeError(e)
+    }
+}
@@ -1,18 +1,16 @@
 use std::{fs::File, num::NonZeroUsize};
 
 use lru::LruCache;
-use memmap2::Mmap;
 use test::{Bencher, black_box};
 use rand::{distributions::{Distribution, Uniform}, rngs::StdRng, SeedableRng};
 
-use crate::{components::{CachedIndex, CachedInvertedIndex, CachedVector, Index, IndexBlock, InvertedIndex, Vector, VectorBlock}, container::Container, layers::SegmentationLayer, Datastore};
+use crate::{components::{CachedIndex, CachedInvertedIndex, CachedVector, Index, IndexBlock, InvertedIndex, Vector, VectorBlock}, container::{Container, ContainerBuilder, EncodeError}, layers::{RangeError, SegmentationLayer, SpanLayer}, manifest::Manifest, registry::{ContainerRegistry, UnknownPolicy}, storage, Datastore, DatastoreError};
 
 const DATASTORE_PATH: &'static str = "testdata/simpledickens/";
 
 fn vec_setup(filename: &'static str, component_name: &'static str) -> (Vector<'static>, Container<'static>) {
-    let file = File::open(DATASTORE_PATH.to_owned() + filename).unwrap();
-    let mmap = unsafe { Mmap::map(&file) }.unwrap();
-    let container = Container::from_mmap(mmap, "word".to_owned()).unwrap();
+    let storage = storage::open_file(DATASTORE_PATH.to_owned() + filename).unwrap();
+    let container = Container::from_storage(storage, "word".to_owned()).unwrap();
 
     let vec = *container
         .get_component(component_name)
@@ -93,10 +91,19 @@ fn vec_rand_cached(b: &mut Bencher) {
     })
 }
 
+#[bench]
+fn vec_rand_get_rows(b: &mut Bencher) {
+    let (vec, _c) = vec_setup("word.zigv", "LexIDStream");
+    let ids = setup_rand(NACCESS, vec.len());
+    b.iter(|| {
+        let cached = CachedVector::<1>::new(vec).unwrap();
+        black_box(cached.get_rows(&ids));
+    })
+}
+
 fn idxcmp_setup(filename: &'static str, component_name: &'static str) -> (Index<'static>, Container<'static>) {
-    let file = File::open(DATASTORE_PATH.to_owned() + filename).unwrap();
-    let mmap = unsafe { Mmap::map(&file) }.unwrap();
-    let container = Container::from_mmap(mmap, "test".to_owned()).unwrap();
+    let storage = storage::open_file(DATASTORE_PATH.to_owned() + filename).unwrap();
+    let container = Container::from_storage(storage, "test".to_owned()).unwrap();
 
     let index = *container
         .get_component(component_name)
@@ -163,10 +170,92 @@ fn idx_iter_comp() {
     assert!(idx.get_all(9001).eq(cidx.get_all(9001)));
 }
 
+#[test]
+fn index_negative_keys() {
+    use crate::components::FnvHash;
+    use crate::container::encode_in_memory;
+    use crate::variables::{IntegerVariable, PlainStringVariable};
+
+    // the last block of a compressed index is padded with key -1
+    for values in [vec![-3, 4, 5, 9], vec![-3, -1, -1, 4], (-20..13).collect::<Vec<i64>>()] {
+        for compressed in [false, true] {
+            let bytes = encode_in_memory(|file| {
+                IntegerVariable::encode_to_file(file, values.iter().copied(), values.len(), "num".to_owned(), uuid::Uuid::new_v4(), compressed, false, "")
+            }).unwrap();
+            let container = Container::from_bytes(&bytes, "test".to_owned()).unwrap();
+            let index = *container.get_component("IntSort").unwrap().as_index().unwrap();
+            let cidx = CachedIndex::new(index);
+
+            for key in [-20, -3, -1, 0, 4, 12, 13] {
+                let expected: Vec<i64> = (0..values.len() as i64).filter(|p| values[*p as usize] == key).collect();
+                assert!(index.get_all(key).collect::<Vec<_>>() == expected);
+                assert!(cidx.get_all(key).collect::<Vec<_>>() == expected);
+                assert!(index.contains_key(key) == !expected.is_empty());
+            }
+        }
+    }
+
+    // about half of all FNV hashes are negative
+    let datastore = Datastore::open(DATASTORE_PATH).unwrap();
+    let words = datastore["primary"]["word"].as_indexed_string().unwrap();
+    let negative: Vec<&str> = words.lexicon().iter().filter(|w| w.fnv_hash() < 0).take(200).collect();
+    assert!(negative.len() == 200);
+    assert!(negative.iter().all(|w| words.type_id(w).map(|t| words.lexicon().get_unchecked(t)) == Some(*w)));
+
+    let strings: Vec<String> = negative.iter().chain(&negative[..10]).map(|s| s.to_string()).collect();
+    for compressed in [false, true] {
+        let plain = PlainStringVariable::encode_to_file(std::io::Cursor::new(Vec::new()), strings.iter().cloned(), strings.len(), "word".to_owned(), uuid::Uuid::new_v4(), compressed, "").unwrap();
+        assert!(plain.positions(negative[3]) == vec![3, 203]);
+        assert!(plain.positions(negative[150]) == vec![150]);
+        assert!(plain.positions("not a word in the list").is_empty());
+    }
+}
+
+#[test]
+fn index_bloom_filter() {
+    use crate::components::{BloomFilter, FnvHash};
+    use crate::container::encode_in_memory;
+    use crate::variables::{IndexedStringVariable, PlainStringVariable};
+
+    let keys: Vec<i64> = (0..5000).map(|i| format!("k{}", i).fnv_hash()).collect();
+    let rows = BloomFilter::build_rows(keys.iter().copied(), keys.len());
+    let bloom = BloomFilter::from_vector(Vector::Uncompressed { length: rows.len(), width: 1, data: &rows }).unwrap();
+    assert!(keys.iter().all(|k| bloom.may_contain(*k)));
+    let false_positives = (0..10000).filter(|i| bloom.may_contain(format!("m{}", i).fnv_hash())).count();
+    assert!(false_positives < 300);
+    assert!(BloomFilter::from_vector(Vector::Uncompressed { length: 1, width: 1, data: &rows[..1] }).is_none());
+
+    // misses rejected by the filter don't decode any block of a compressed index
+    let strings: Vec<String> = (0..2000).map(|i| format!("w{}", i % 700)).collect();
+    let bytes = encode_in_memory(|file| {
+        PlainStringVariable::encode_to_file(file, strings.iter().cloned(), strings.len(), "word".to_owned(), uuid::Uuid::new_v4(), true, "")
+    }).unwrap();
+    let container = Container::from_bytes(&bytes, "word".to_owned()).unwrap();
+    let index = *container.get_component("StringHash").unwrap().as_index().unwrap();
+    let bloom = BloomFilter::from_vector(*container.get_component("StringBloom").unwrap().as_vector().unwrap()).unwrap();
+    let cidx = CachedIndex::new(index).with_bloom(bloom);
+
+    let rejected = (0..1000).filter(|i| cidx.get_first(format!("x{}", i).fnv_hash()).is_none()).count();
+    assert!(rejected == 1000);
+    assert!(cidx.cache_stats().decoded_blocks < 20);
+    assert!(strings.iter().all(|s| cidx.contains_key(s.fnv_hash())));
+
+    let plain = PlainStringVariable::try_from(container).unwrap();
+    assert!(plain.positions("w3") == vec![3, 703, 1403]);
+    assert!(plain.positions("x3").is_empty());
+
+    let indexed = IndexedStringVariable::encode_to_file(std::io::Cursor::new(Vec::new()), strings.iter().cloned(), strings.len(), "word".to_owned(), uuid::Uuid::new_v4(), true, "").unwrap();
+    assert!(indexed.type_id("w699").is_some());
+    assert!(indexed.type_id("w700").is_none());
+
+    // containers without a filter, like the test datastore, are looked up as before
+    let words = Container::from_storage(storage::open_file(DATASTORE_PATH.to_owned() + "word.zigv").unwrap(), "word".to_owned()).unwrap();
+    assert!(words.get_component("LexHashBloom").is_none());
+}
+
 fn seg_setup(filename: &'static str) -> SegmentationLayer<'static> {
-    let file = File::open(DATASTORE_PATH.to_owned() + filename).unwrap();
-    let mmap = unsafe { Mmap::map(&file) }.unwrap();
-    let container = Container::from_mmap(mmap, "word".to_owned()).unwrap();
+    let storage = storage::open_file(DATASTORE_PATH.to_owned() + filename).unwrap();
+    let container = Container::from_storage(storage, "word".to_owned()).unwrap();
 
     let seg = SegmentationLayer::try_from(container).unwrap();
 
@@ -187,8 +276,8 @@ fn vec_block_decode() {
     let (vec, _c) = vec_setup("word.zigv", "LexIDStream");
     let bdata = match vec {
         Vector::Uncompressed { .. } => panic!(),
-        Vector::Compressed { length: _, width: _, sync, data } |
-        Vector::Delta { length: _, width: _, sync, data } => &data[sync[10] as usize..],
+        Vector::Compressed { sync, data, .. } |
+        Vector::Delta { sync, data, .. } => &data[sync[10] as usize..],
     };
 
     let b1 = Vector::decode_compressed_block(1, bdata);
@@ -202,8 +291,8 @@ fn vec_deltablock_decode() {
     let (vec, _c) = vec_setup("s/s.zigl", "RangeStream");
     let bdata = match vec {
         Vector::Uncompressed { .. } => panic!(),
-        Vector::Compressed { length: _, width: _, sync, data } |
-        Vector::Delta { length: _, width: _, sync, data } => &data[sync[10] as usize..],
+        Vector::Compressed { sync, data, .. } |
+        Vector::Delta { sync, data, .. } => &data[sync[10] as usize..],
     };
 
     let b1 = Vector::decode_delta_block(2, bdata);
@@ -217,8 +306,8 @@ fn vec_deltablock_decode_len() {
     let (vec, _c) = vec_setup("s/s.zigl", "RangeStream");
     let bdata = match vec {
         Vector::Uncompressed { .. } => panic!(),
-        Vector::Compressed { length: _, width: _, sync, data } |
-        Vector::Delta { length: _, width: _, sync, data } => &data[*sync.last().unwrap() as usize..],
+        Vector::Compressed { sync, data, .. } |
+        Vector::Delta { sync, data, .. } => &data[*sync.last().unwrap() as usize..],
     };
     let lastlen = vec.len() % 16;
 
@@ -230,6 +319,86 @@ fn vec_deltablock_decode_len() {
     assert!(&b1[..2] == b2.rows()[0]);
 }
 
+#[test]
+fn vec_compressed_multi_column() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = File::options()
+        .read(true)
+        .write(true)
+        .create(true)
+        .open(dir.path().join("vec.zigv"))
+        .unwrap();
+
+    let rows: Vec<[i64; 2]> = (0..100).map(|i| [i * 3, 1000 - i * i]).collect();
+    let container = ContainerBuilder::new_into_file("vec".to_owned(), file, 2)
+        .edit_header(|h| {
+            h.ziggurat_type(crate::container::Type::IntegerVariable)
+                .dim1(rows.len())
+                .base1(Some(uuid::Uuid::new_v4()));
+        })
+        .add_component("Comp", crate::components::Type::VectorComp, |bom_entry, file| unsafe {
+            Vector::encode_compressed_to_container_file(rows.iter().copied(), rows.len(), file, bom_entry, bom_entry.offset as u64)
+        })
+        .add_component("Delta", crate::components::Type::VectorDelta, |bom_entry, file| unsafe {
+            Vector::encode_delta_to_container_file(rows.iter().copied(), rows.len(), file, bom_entry, bom_entry.offset as u64)
+        })
+        .build()
+        .unwrap();
+
+    for name in ["Comp", "Delta"] {
+        let vec = container.get_component(name).unwrap().into_vector().unwrap();
+        let cvec = CachedVector::<2>::new(vec).unwrap();
+        assert!(cvec.iter().eq(rows.iter().copied()));
+        assert!(vec.get_row(42).unwrap()[..] == rows[42]);
+    }
+}
+
+#[test]
+fn vec_block_size() {
+    let rows: Vec<[i64; 2]> = (0..1000).map(|i| [i * 7, 5000 - i]).collect();
+    let bytes = crate::container::encode_in_memory(|cursor| {
+        ContainerBuilder::new_into_file("vec".to_owned(), cursor, 3)
+            .edit_header(|h| {
+                h.ziggurat_type(crate::container::Type::IntegerVariable)
+                    .dim1(rows.len())
+                    .base1(Some(uuid::Uuid::new_v4()));
+            })
+            .add_component("Default", crate::components::Type::VectorDelta, |bom_entry, file| unsafe {
+                Vector::encode_delta_to_container_file(rows.iter().copied(), rows.len(), file, bom_entry, bom_entry.offset as u64)
+            })
+            .add_component("Delta64", crate::components::Type::VectorDelta, |bom_entry, file| unsafe {
+                Vector::encode_delta_to_container_file_with_block_size(rows.iter().copied(), rows.len(), 64, file, bom_entry, bom_entry.offset as u64)
+            })
+            .add_component("Comp128", crate::components::Type::VectorComp, |bom_entry, file| unsafe {
+                Vector::encode_compressed_to_container_file_with_block_size(rows.iter().copied(), rows.len(), 128, file, bom_entry, bom_entry.offset as u64)
+            })
+            .build()
+    }).unwrap();
+    let container = Container::from_bytes(&bytes, "vec".to_owned()).unwrap();
+
+    for (name, block_size) in [("Default", 16), ("Delta64", 64), ("Comp128", 128)] {
+        let vec = container.get_component(name).unwrap().into_vector().unwrap();
+        assert!(vec.width() == 2);
+        assert!(vec.block_size() == block_size);
+        assert!(vec.get_row(999).unwrap()[..] == rows[999]);
+
+        let cvec = CachedVector::<2>::new(vec).unwrap();
+        assert!(cvec.iter().eq(rows.iter().copied()));
+        assert!(cvec.iter().rev().step_by(37).eq(rows.iter().rev().step_by(37).copied()));
+        assert!(cvec.get_row(block_size) == Some(rows[block_size]));
+        assert!(cvec.raw_range(0, block_size).len() < cvec.raw_range(0, block_size + 1).len());
+    }
+
+    let result = crate::container::encode_in_memory(|cursor| {
+        ContainerBuilder::new_into_file("vec".to_owned(), cursor, 1)
+            .add_component("Zero", crate::components::Type::VectorDelta, |bom_entry, file| unsafe {
+                Vector::encode_delta_to_container_file_with_block_size(rows.iter().copied(), rows.len(), 0, file, bom_entry, bom_entry.offset as u64)
+            })
+            .build()
+    });
+    assert!(matches!(result, Err(EncodeError::InvalidBlockSize(0))));
+}
+
 #[test]
 fn vec_cached2_access() {
     let (vec, _c) = vec_setup("word.zigv", "LexIDStream");
@@ -260,6 +429,41 @@ fn vec_cached2_iter() {
     assert!(middle.len() == 10);
 }
 
+#[test]
+fn vec_cached_iter_double_ended() {
+    let (vec, _c) = vec_setup("s/s.zigl", "RangeStream");
+    let cvec2 = CachedVector::<2>::new(vec).unwrap();
+    let all: Vec<_> = cvec2.iter().collect();
+
+    let iter = cvec2.iter_range(5, 105).unwrap();
+    assert!(iter.len() == 100);
+    let rev: Vec<_> = iter.rev().collect();
+    assert!(rev.iter().rev().eq(all[5..105].iter()));
+
+    let stepped: Vec<_> = cvec2.iter().step_by(100).collect();
+    assert!(stepped.iter().eq(all.iter().step_by(100)));
+    assert!(cvec2.iter().skip(all.len() - 3).len() == 3);
+
+    // both ends meet in the middle without yielding a row twice
+    let mut iter = cvec2.iter_range(10, 50).unwrap();
+    assert!(iter.next_back() == Some(all[49]));
+    assert!(iter.nth(20) == Some(all[30]));
+    assert!(iter.nth_back(5) == Some(all[43]));
+    assert!(iter.len() == 12);
+    assert!(iter.count() == 12);
+
+    let column: Vec<_> = cvec2.column_iter(1).rev().take(3).collect();
+    assert!(column == [all[all.len() - 1][1], all[all.len() - 2][1], all[all.len() - 3][1]]);
+    assert!(cvec2.iter_from(cvec2.len()).next().is_none());
+
+    // skipping over whole blocks must not decode them
+    let (vec, _c) = vec_setup("word.zigv", "LexIDStream");
+    let cvec = CachedVector::<1>::new(vec).unwrap();
+    let stepped = cvec.column_iter(0).step_by(64).count();
+    assert!(stepped == (cvec.len() - 1) / 64 + 1);
+    assert!(cvec.cache_stats().misses == stepped);
+}
+
 #[test]
 fn vec_cached_column_iter() {
     let (vec, _c) = vec_setup("word.zigv", "LexIDStream");
@@ -302,9 +506,8 @@ fn invidx_cache_eval() {
 }
 
 fn invidx_setup(filename: &'static str, vec_name: &'static str, invidx_name: &'static str) -> (Vector<'static>, InvertedIndex<'static>, Container<'static>) {
-    let file = File::open(DATASTORE_PATH.to_owned() + filename).unwrap();
-    let mmap = unsafe { Mmap::map(&file) }.unwrap();
-    let container = Container::from_mmap(mmap, "test".to_owned()).unwrap();
+    let storage = storage::open_file(DATASTORE_PATH.to_owned() + filename).unwrap();
+    let container = Container::from_storage(storage, "test".to_owned()).unwrap();
 
     let vec = *container
         .get_component(vec_name)
@@ -480,6 +683,136 @@ fn string_vec_regex() {
     }
 }
 
+#[test]
+fn string_vec_regex_matches() {
+    use std::io::Cursor;
+    use crate::components::{LexiconOrder, RegexFlags};
+    use crate::variables::IndexedStringVariable;
+
+    let base = uuid::Uuid::new_v4();
+    let words = ["cafe", "Café", "decaf", "CAFE", "caffeine"].map(String::from);
+    let var = IndexedStringVariable::encode_to_file_ordered(Cursor::new(Vec::new()), words.iter().cloned(), words.len(), "word".to_owned(), base, false, LexiconOrder::Bytes, "").unwrap();
+    let lexicon = var.lexicon();
+    let matches = |pattern, flags| lexicon.regex_matches(pattern, flags).unwrap().as_strs().collect::<Vec<_>>();
+
+    // CAFE, Café, cafe, caffeine, decaf
+    assert!(matches("caf.*", RegexFlags::default()) == vec!["cafe", "caffeine"]);
+    assert!(matches("caf", RegexFlags::default()).is_empty());
+    assert!(matches("caf", RegexFlags::default().anchored(false)) == vec!["cafe", "caffeine", "decaf"]);
+    assert!(matches("caf|decaf", RegexFlags::default()) == vec!["decaf"]);
+    assert!(matches("caf.", RegexFlags::default().case_insensitive(true)) == vec!["CAFE", "Café", "cafe"]);
+    assert!(lexicon.regex_matches("caf(", RegexFlags::default()).is_err());
+
+    // the old API stays unanchored
+    assert!(lexicon.get_all_matching_regex("caf") == vec![2, 3, 4]);
+
+    #[cfg(feature = "normalization")]
+    {
+        use crate::components::fold;
+
+        assert!(fold("Ångström") == "angstrom");
+        assert!(matches("cafe", RegexFlags::default().folded(true)) == vec!["CAFE", "Café", "cafe"]);
+    }
+}
+
+#[test]
+fn normalized_encoding() {
+    use std::io::Cursor;
+    use crate::variables::IndexedStringVariable;
+
+    let base = uuid::Uuid::new_v4();
+    // decomposed and composed "café", "fine" with and without a ligature
+    let words = ["cafe\u{301}", "caf\u{e9}", "\u{fb01}ne", "fine"].map(String::from);
+
+    let var = IndexedStringVariable::encode_to_file(Cursor::new(Vec::new()), words.iter().cloned(), words.len(), "word".to_owned(), base, false, "").unwrap();
+    assert!(var.normalization().is_none());
+    assert!(var.n_types() == 4);
+
+    #[cfg(feature = "normalization")]
+    {
+        use crate::components::{LexiconBuilder, LexiconOrder, Normalization};
+        use crate::variables::PlainStringVariable;
+
+        let mut lexbuilder = LexiconBuilder::new();
+        lexbuilder.set_normalization(Normalization::Nfc);
+        lexbuilder.add_strings(words.iter().cloned());
+        let var = IndexedStringVariable::encode_lexicon_builder(Cursor::new(Vec::new()), lexbuilder, "word".to_owned(), base, false, LexiconOrder::Bytes, "").unwrap();
+        assert!(var.normalization() == Some(Normalization::Nfc));
+        assert!(var.lexicon_order() == Some(LexiconOrder::Bytes));
+        assert!(var.n_types() == 3);
+        assert!(var.iter().eq(["caf\u{e9}", "caf\u{e9}", "\u{fb01}ne", "fine"]));
+        assert!(var.frequency(var.type_id("caf\u{e9}").unwrap()) == Some(2));
+
+        let plain = PlainStringVariable::encode_to_file_normalized(Cursor::new(Vec::new()), words.iter().cloned(), words.len(), "word".to_owned(), base, true, Normalization::Nfkc, "").unwrap();
+        assert!(plain.normalization() == Some(Normalization::Nfkc));
+        assert!(plain.iter().eq(["caf\u{e9}", "caf\u{e9}", "fine", "fine"]));
+    }
+}
+
+#[test]
+fn bigram_index() {
+    use std::io::Cursor;
+    use crate::components::{LexiconBuilder, LexiconOrder};
+    use crate::layers::{Layer, PrimaryLayer};
+    use crate::query::Query;
+    use crate::variables::{IndexedStringVariable, Variable};
+
+    let datastore = Datastore::open(DATASTORE_PATH).unwrap();
+    let words: Vec<String> = datastore["primary"]["word"].as_indexed_string().unwrap()
+        .iter()
+        .take(100_000)
+        .map(str::to_owned)
+        .collect();
+
+    let layer = |min_frequency| {
+        let primary = PrimaryLayer::encode_to_file(Cursor::new(Vec::new()), words.len(), "primary".to_owned(), "").unwrap();
+        let mut lexbuilder = LexiconBuilder::new();
+        lexbuilder.set_bigram_index(min_frequency);
+        lexbuilder.add_strings(words.iter().cloned());
+        let var = IndexedStringVariable::encode_lexicon_builder(Cursor::new(Vec::new()), lexbuilder, "word".to_owned(), primary.uuid(), true, LexiconOrder::Frequency, "").unwrap();
+        let mut layer = Layer::new_primary(primary);
+        layer.add_variable("word".to_owned(), Variable::IndexedString(var)).unwrap();
+        layer
+    };
+    let plain = layer(None);
+    let full = layer(Some(1));
+    let partial = layer(Some(50));
+
+    let var = plain["word"].as_indexed_string().unwrap();
+    assert!(var.bigram_index().is_none());
+    let bigrams = full["word"].as_indexed_string().unwrap().bigram_index().unwrap();
+    assert!(bigrams.min_frequency() == 1);
+    assert!(partial["word"].as_indexed_string().unwrap().bigram_index().unwrap().len() < bigrams.len());
+
+    let pairs = [("of", "the"), ("Mr", "."), ("the", "the"), ("said", "Mr"), ("Pickwick", "said"), ("of", "nonexistent")];
+    for (first, second) in pairs {
+        let expected: Vec<usize> = (0..words.len() - 1)
+            .filter(|&p| words[p] == first && words[p + 1] == second)
+            .collect();
+        for layer in [&plain, &full, &partial] {
+            let var = layer["word"].as_indexed_string().unwrap();
+            let positions = match (var.type_id(first), var.type_id(second)) {
+                (Some(a), Some(b)) => var.bigram_positions(a, b),
+                _ => Vec::new(),
+            };
+            assert!(positions == expected);
+        }
+        let var = full["word"].as_indexed_string().unwrap();
+        if let (Some(a), Some(b)) = (var.type_id(first), var.type_id(second)) {
+            assert!(var.bigram_frequency(a, b) == Some(expected.len()));
+        }
+    }
+
+    for query in [r#""of" "the""#, r#""the|a" "[A-Z].*" "said""#, r#""the" "[a-z]+" "of""#, r#"[] "of" [word!="the"]"#] {
+        let query = Query::parse(query).unwrap();
+        let expected = query.find(&plain).unwrap();
+        assert!(!expected.is_empty());
+        assert!(query.find(&full).unwrap() == expected);
+        assert!(query.find(&partial).unwrap() == expected);
+        query.warm(&full).unwrap();
+    }
+}
+
 
 #[bench]
 fn string_vec_startswith_raw(b: &mut Bencher) {
@@ -508,3 +841,2953 @@ fn string_vec_startswith_str(b: &mut Bencher) {
         }
     })
 }
+
+#[test]
+fn manifest_scan() {
+    let manifest = Manifest::scan(DATASTORE_PATH).unwrap();
+    assert!(manifest.containers.len() == 14);
+
+    let titles: Vec<_> = manifest.containers.iter()
+        .filter(|e| e.name == "title")
+        .map(|e| e.path.to_str().unwrap())
+        .collect();
+    assert!(titles == ["chapter/title.zigv", "novel/title.zigv"]);
+
+    let json = serde_json::to_string(&manifest).unwrap();
+    assert!(serde_json::from_str::<Manifest>(&json).unwrap() == manifest);
+}
+
+#[test]
+fn datastore_snapshot() {
+    let source = Datastore::open(DATASTORE_PATH).unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let target = dir.path().join("snapshot");
+
+    let manifest = source.snapshot_to(&target).unwrap();
+    assert!(manifest == Manifest::scan(DATASTORE_PATH).unwrap());
+    assert!(Manifest::read(&target).unwrap() == Some(manifest));
+
+    let snapshot = Datastore::open(&target).unwrap();
+    let mut names: Vec<_> = snapshot.layer_names().collect();
+    names.sort();
+    let mut expected: Vec<_> = source.layer_names().collect();
+    expected.sort();
+    assert!(names == expected);
+
+    let words = snapshot["primary"]["word"].as_indexed_string().unwrap();
+    assert!(words.iter().take(100).eq(source["primary"]["word"].as_indexed_string().unwrap().iter().take(100)));
+    assert!(snapshot["chapter"]["title"].get(0) == source["chapter"]["title"].get(0));
+
+    assert!(source.snapshot_to(&target).is_err());
+}
+
+#[test]
+fn datastore_from_bytes() {
+    let manifest = Manifest::scan(DATASTORE_PATH).unwrap();
+    let datastore = Datastore::from_bytes(manifest, |entry| {
+        std::fs::read(std::path::Path::new(DATASTORE_PATH).join(&entry.path))
+    }).unwrap();
+    let source = Datastore::open(DATASTORE_PATH).unwrap();
+
+    assert!(datastore.layer_names().count() == source.layer_names().count());
+    let words = datastore["primary"]["word"].as_indexed_string().unwrap();
+    assert!(words.iter().take(100).eq(source["primary"]["word"].as_indexed_string().unwrap().iter().take(100)));
+    assert!(datastore["chapter"]["title"].get(3) == source["chapter"]["title"].get(3));
+
+    let missing = Datastore::from_bytes(Manifest::scan(DATASTORE_PATH).unwrap(), |_| {
+        Err(std::io::Error::new(std::io::ErrorKind::NotFound, "not fetched"))
+    });
+    assert!(matches!(missing, Err(DatastoreError::IoError(_))));
+}
+
+#[test]
+fn datastore_check() {
+    use std::io::Cursor;
+    use crate::check::IssueKind;
+    use crate::layers::PrimaryLayer;
+    use crate::manifest::ManifestEntry;
+    use crate::variables::PointerVariable;
+
+    let report = Datastore::open(DATASTORE_PATH).unwrap().check();
+    assert!(report.is_ok());
+    assert!(report.layers == 7);
+
+    // segments past the end of the primary layer and a head past its end, which the
+    // encoders can't detect without the base layer
+    let mut containers: Vec<(&str, Vec<u8>)> = Vec::new();
+    let mut bytes = Vec::new();
+    let primary = PrimaryLayer::encode_to_file(Cursor::new(&mut bytes), 10, "primary".to_owned(), "").unwrap().uuid();
+    containers.push(("primary", bytes));
+    let mut bytes = Vec::new();
+    let ranges = [(0, 4), (4, 8), (8, 12), (12, 14)];
+    SegmentationLayer::encode_to_file(Cursor::new(&mut bytes), ranges.into_iter(), 4, "s".to_owned(), primary, false, "").unwrap();
+    containers.push(("s", bytes));
+    let mut bytes = Vec::new();
+    let heads = [-1, 0, 1, 2, 30, 4, 5, 6, 7, 8];
+    PointerVariable::encode_to_file(Cursor::new(&mut bytes), heads.into_iter(), 10, "head".to_owned(), primary, false, "").unwrap();
+    containers.push(("head", bytes));
+
+    let manifest = Manifest {
+        containers: containers.iter()
+            .map(|(name, bytes)| ManifestEntry {
+                name: name.to_string(),
+                path: format!("{}.zigl", name).into(),
+                uuid: Container::from_bytes(bytes, name.to_string()).unwrap().header().uuid(),
+            })
+            .collect(),
+    };
+    let datastore = Datastore::from_bytes(manifest, |entry| {
+        Ok(containers.iter().find(|(name, _)| *name == entry.name).unwrap().1.clone())
+    }).unwrap();
+
+    let report = datastore.check();
+    assert!(!report.is_ok());
+    let issues: Vec<_> = report.issues.iter().map(|i| (i.kind, i.container.as_str(), i.count, i.first)).collect();
+    assert!(issues == vec![
+        (IssueKind::PointerOutOfBounds, "primary.head", 1, Some(4)),
+        (IssueKind::RangeOutOfBounds, "s", 2, Some(2)),
+    ]);
+    assert!(report.issues[1].to_string().starts_with("s: 2 ranges ending beyond the base layer, first at 2"));
+}
+
+#[test]
+fn cache_stats() {
+    let (_, invidx, _c) = invidx_setup("word.zigv", "LexIDStream", "LexIDIndex");
+    let cinvidx = CachedInvertedIndex::new(invidx);
+    assert!(cinvidx.cache_stats().hit_rate().is_none());
+
+    let freq = cinvidx.positions(0).unwrap().count();
+    assert!(cinvidx.positions(0).unwrap().count() == freq);
+
+    let stats = cinvidx.cache_stats();
+    assert!(stats.misses == 1 && stats.hits == 1 && stats.decoded_blocks == 1);
+    assert!(stats.touched_bytes > 0);
+    assert!(stats.resident_bytes >= freq * std::mem::size_of::<usize>());
+
+    let datastore = Datastore::open("testdata/simpledickens").unwrap();
+    let words = datastore["primary"]["word"]
+        .as_indexed_string()
+        .unwrap();
+    for i in 0..100 {
+        black_box(words.get_id(i));
+    }
+
+    let stats = datastore.cache_stats();
+    let (_, _, word_stats) = stats.iter()
+        .find(|(layer, var, _)| *layer == "primary" && *var == Some("word"))
+        .unwrap();
+    assert!(*word_stats == words.cache_stats());
+    assert!(stats.iter().any(|(_, var, _)| var.is_none()));
+}
+
+#[test]
+fn prefetch_range() {
+    let datastore = Datastore::open("testdata/simpledickens").unwrap();
+    let words = datastore["primary"]["word"]
+        .as_indexed_string()
+        .unwrap();
+
+    words.prefetch_range(1000, 1100);
+    let prefetched = words.cache_stats();
+
+    for i in 1000..1100 {
+        black_box(words.get_id(i));
+    }
+
+    let stats = words.cache_stats();
+    assert!(stats.misses == prefetched.misses);
+    assert!(stats.hits == prefetched.hits + 100);
+
+    let sentences = datastore["s"].as_segmentation().unwrap();
+    sentences.prefetch_range(0, sentences.len() + 10);
+}
+
+#[test]
+fn prefetch_range_background() {
+    use crate::selection::Selection;
+
+    let expected_ids = {
+        let datastore = Datastore::open("testdata/simpledickens").unwrap();
+        let words = datastore["primary"]["word"].as_indexed_string().unwrap();
+        (5000..5100).map(|i| words.get_id(i)).collect::<Vec<_>>()
+    };
+
+    let datastore = Datastore::open("testdata/simpledickens").unwrap();
+    let words = datastore["primary"]["word"]
+        .as_indexed_string()
+        .unwrap();
+
+    let before = words.cache_stats();
+    words.prefetch_range(5000, 5100);
+
+    // the first access waits for the background decoder and merges its blocks
+    let ids = (5000..5100).map(|i| words.get_id(i)).collect::<Vec<_>>();
+    assert!(ids == expected_ids);
+
+    let stats = words.cache_stats();
+    assert!(stats.misses > before.misses);
+    assert!(stats.hits == before.hits + 100);
+
+    // already cached blocks are not decoded again
+    words.prefetch_range(5000, 5100);
+    assert!(words.cache_stats().misses == stats.misses);
+
+    let selection = Selection::new(&datastore["primary"], &["word", "lemma", "pos"]).unwrap();
+    let expected = selection.iter_range(7000, 7050).unwrap().collect::<Vec<_>>();
+    selection.prefetch_range(7000, 7050);
+    assert!(selection.iter_range(7000, 7050).unwrap().collect::<Vec<_>>() == expected);
+}
+
+#[test]
+fn header_only_variables() {
+    use std::io::Cursor;
+    use crate::container;
+    use crate::layers::{Layer, PrimaryLayer};
+    use crate::variables::{Variable, VariableType};
+
+    let primary = PrimaryLayer::encode_to_file(Cursor::new(Vec::new()), 10, "primary".to_owned(), "").unwrap();
+    let mut layer = Layer::new_primary(primary);
+
+    for (ty, variable_type) in [(container::Type::HashVariable, VariableType::Hash), (container::Type::ExternalPointerVariable, VariableType::ExternalPointer)] {
+        let container = ContainerBuilder::new_into_file("opaque".to_owned(), Cursor::new(Vec::new()), 0)
+            .edit_header(|h| {
+                h.ziggurat_type(ty).dim1(10).base1(Some(layer.uuid()));
+            })
+            .build()
+            .unwrap();
+        let uuid = container.header().uuid();
+        let variable = Variable::try_from(container).unwrap();
+
+        // the values can't be decoded, but everything known from the header is there
+        assert!(variable.variable_type() == variable_type);
+        assert!(variable.len() == 10 && variable.uuid() == Some(uuid) && variable.base() == Some(layer.uuid()));
+        assert!(variable.iter().count() == 0 && variable.get(3).is_none());
+        assert!(variable.get_range(2, 10).is_some() && variable.get_range(2, 11).is_none());
+        assert!(variable.random_positions(3, 7).len() == 3);
+        assert!(layer.add_variable(variable_type.name().to_owned(), variable).is_ok());
+    }
+    assert!(layer.variable_len() == 2);
+}
+
+#[test]
+fn unknown_container_types() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = File::options()
+        .read(true)
+        .write(true)
+        .create(true)
+        .open(dir.path().join("tree.zigl"))
+        .unwrap();
+    ContainerBuilder::new_into_file("tree".to_owned(), file, 0)
+        .edit_header(|h| {
+            h.family('Z').class('L').ctype('t');
+        })
+        .build()
+        .unwrap();
+
+    let datastore = Datastore::open(dir.path()).unwrap();
+    assert!(datastore.layer_names().len() == 0);
+    assert!(datastore.skipped_containers()[0].type_code == *b"ZLt");
+
+    let strict = ContainerRegistry::new().policy(UnknownPolicy::Error);
+    let result = Datastore::open_with_registry(dir.path(), &strict);
+    assert!(matches!(result, Err(DatastoreError::UnknownContainerType(name, _)) if name == "tree"));
+
+    let extended = strict.register(*b"ZLt", |_| Ok(()));
+    let datastore = Datastore::open_with_registry(dir.path(), &extended).unwrap();
+    assert!(datastore.extension_by_name("tree").is_some());
+    assert!(datastore.skipped_containers().is_empty());
+}
+
+#[test]
+fn encode_errors() {
+    use crate::variables::{IndexedStringVariable, IntegerVariable, PlainStringVariable};
+
+    let base = uuid::Uuid::new_v4();
+    let strings = || ["a", "b", "a"].into_iter().map(String::from);
+    let short = |e| matches!(e, Err(EncodeError::LengthMismatch { expected: 5, found: 3 }));
+
+    for compressed in [false, true] {
+        let file = tempfile::tempfile().unwrap();
+        assert!(short(IndexedStringVariable::encode_to_file(file, strings(), 5, "word".to_owned(), base, compressed, "").map(|_| ())));
+
+        let file = tempfile::tempfile().unwrap();
+        assert!(short(PlainStringVariable::encode_to_file(file, strings(), 5, "word".to_owned(), base, compressed, "").map(|_| ())));
+
+        let file = tempfile::tempfile().unwrap();
+        assert!(short(IntegerVariable::encode_to_file(file, 0..3, 5, "num".to_owned(), base, compressed, false, "").map(|_| ())));
+    }
+
+    let result = ContainerBuilder::new_into_file("meta".to_owned(), tempfile::tempfile().unwrap(), 1)
+        .edit_header(|h| {
+            h.family('X').class('X').ctype('x');
+        })
+        .metadata(&[("key", "null\0byte")])
+        .build();
+    assert!(matches!(result, Err(EncodeError::InvalidMetadata(_))));
+}
+
+#[test]
+fn encoder_metadata() {
+    use crate::components::{LexiconBuilder, LexiconOrder};
+    use crate::lock::PendingFile;
+    use crate::variables::IndexedStringVariable;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("word.zigv");
+    let words = ["the", "cat", "and", "the", "dog", "and", "the", "cat"];
+    let mut builder = LexiconBuilder::from_strings(words.into_iter());
+    builder.set_bigram_index(Some(2));
+    let var = IndexedStringVariable::encode_lexicon_builder(PendingFile::create(&path).unwrap(), builder, "word".to_owned(), uuid::Uuid::new_v4(), true, LexiconOrder::Bytes, "").unwrap();
+    assert!(var.lexicon_order() == Some(LexiconOrder::Bytes));
+
+    let container = Container::from_storage(storage::open_file(&path).unwrap(), "word".to_owned()).unwrap();
+    assert!(container.metadata() == [("lexicon_order", "bytes"), ("bigram_min_frequency", "2")]);
+    let reopened = IndexedStringVariable::try_from(container).unwrap();
+    assert!(reopened.lexicon_order() == Some(LexiconOrder::Bytes));
+    assert!(reopened.bigram_index().is_some());
+}
+
+#[test]
+fn sorted_lexicon() {
+    use std::io::Cursor;
+    use crate::components::LexiconOrder;
+    use crate::variables::IndexedStringVariable;
+
+    let base = uuid::Uuid::new_v4();
+    let vocab = ["pear", "Apple", "banana", "apple", "cherry", "Banana", "date", "fig"];
+    let words: Vec<String> = (0..1000).map(|i| vocab[(i * 7 + i / 3) % vocab.len()].to_owned()).collect();
+    let encode = |order| IndexedStringVariable::encode_to_file_ordered(Cursor::new(Vec::new()), words.iter().cloned(), words.len(), "word".to_owned(), base, true, order, "").unwrap();
+
+    let var = encode(LexiconOrder::Bytes);
+    assert!(var.iter().eq(words.iter().map(String::as_str)));
+    assert!(var.lexicon_order() == Some(LexiconOrder::Bytes));
+    let lexicon: Vec<_> = var.lexicon().iter().collect();
+    assert!(lexicon.windows(2).all(|w| w[0] < w[1]));
+
+    let range: Vec<_> = var.types_in_range("b", "d").unwrap().into_iter().map(|t| var.lexicon().get(t).unwrap()).collect();
+    assert!(range == vec!["banana", "cherry"]);
+    assert!(var.types_in_range("x", "a").unwrap().is_empty());
+    assert!(var.inverted_index().positions(var.lexicon().find_match("fig").unwrap()).unwrap().eq(words.iter().enumerate().filter(|(_, w)| *w == "fig").map(|(i, _)| i)));
+
+    let var = encode(LexiconOrder::Frequency);
+    assert!(var.iter().eq(words.iter().map(String::as_str)));
+    assert!(var.types_in_range("b", "d").is_none());
+
+    #[cfg(feature = "collation")]
+    {
+        let var = encode(LexiconOrder::Collation);
+        let range: Vec<_> = var.types_in_range("a", "c").unwrap().into_iter().map(|t| var.lexicon().get(t).unwrap()).collect();
+        assert!(range == vec!["apple", "Apple", "banana", "Banana"]);
+    }
+}
+
+#[test]
+fn sorted_lexicon_search() {
+    use std::io::Cursor;
+    use crate::components::LexiconOrder;
+    use crate::variables::IndexedStringVariable;
+
+    let base = uuid::Uuid::new_v4();
+    let words = ["car", "cat", "a", "cart", "dog", "ca", "b", "cat"].map(String::from);
+    let var = IndexedStringVariable::encode_to_file_ordered(Cursor::new(Vec::new()), words.iter().cloned(), words.len(), "word".to_owned(), base, false, LexiconOrder::Bytes, "").unwrap();
+    let lexicon = var.lexicon();
+
+    // a, b, ca, car, cart, cat, dog
+    assert!(lexicon.binary_search("car") == Ok(3));
+    assert!(lexicon.binary_search("cas") == Err(5));
+    assert!(lexicon.binary_search("") == Err(0));
+    assert!(lexicon.binary_search("zebra") == Err(7));
+    assert!(lexicon.prefix_range("ca") == (2..6));
+    assert!(lexicon.prefix_range("car") == (3..5));
+    assert!(lexicon.prefix_range("x").is_empty());
+    assert!(lexicon.prefix_range("") == (0..7));
+
+    assert!(var.type_id("cart") == Some(4));
+    assert!(var.type_id("cow").is_none());
+    assert!(var.types_with_prefix("ca") == Some(2..6));
+
+    let datastore = Datastore::open(DATASTORE_PATH).unwrap();
+    let dickens = datastore["primary"]["word"].as_indexed_string().unwrap();
+    assert!(dickens.type_id("the") == dickens.lexicon().find_match("the"));
+    assert!(dickens.type_id("no such word").is_none());
+    assert!(dickens.types_with_prefix("th").is_none());
+}
+
+#[test]
+fn hash_collisions() {
+    use crate::components::TypeIndex;
+
+    // real FNV collisions are rare, so the types are filed under made up hashes
+    let mut types: Vec<(String, usize)> = ["a", "b", "c", "d"].iter().map(|s| (s.to_string(), 0)).collect();
+    let mut index = TypeIndex::default();
+    for (id, hash) in [7, 7, 3, 7].into_iter().enumerate() {
+        assert!(index.get(hash, &types[id].0, &types).is_none());
+        index.insert(hash, id);
+    }
+    assert!(index.get(7, "a", &types) == Some(0));
+    assert!(index.get(7, "b", &types) == Some(1));
+    assert!(index.get(7, "d", &types) == Some(3));
+    assert!(index.get(7, "c", &types).is_none());
+    assert!(index.get(3, "c", &types) == Some(2));
+    assert!(index.n_hashes() == 2);
+
+    let lut = index.reorder(&mut types, &[3, 2, 1, 0]);
+    assert!(lut == vec![3, 2, 1, 0]);
+    assert!(types[0].0 == "d");
+    assert!(index.get(7, "d", &types) == Some(0));
+    assert!(index.get(7, "a", &types) == Some(3));
+    assert!(index.pairs() == vec![(3, 1), (7, 0), (7, 2), (7, 3)]);
+
+    let datastore = Datastore::open(DATASTORE_PATH).unwrap();
+    let words = datastore["primary"]["word"].as_indexed_string().unwrap();
+    assert!(words.lexicon().iter().take(500).enumerate().all(|(id, w)| words.lookup_str(w) == Some(id)));
+    assert!(words.lookup_str("no such word").is_none());
+}
+
+#[test]
+fn type_frequencies() {
+    use std::io::Cursor;
+    use crate::variables::IndexedStringVariable;
+
+    let base = uuid::Uuid::new_v4();
+    let words: Vec<String> = (0..500).map(|i| format!("w{}", i % 7 + i % 3)).collect();
+    let var = IndexedStringVariable::encode_to_file(Cursor::new(Vec::new()), words.iter().cloned(), words.len(), "word".to_owned(), base, true, "").unwrap();
+
+    for tid in 0..var.n_types() {
+        let expected = words.iter().filter(|w| Some(w.as_str()) == var.lexicon().get(tid)).count();
+        assert!(var.frequency(tid) == Some(expected));
+        assert!(var.inverted_index().frequency(tid) == Some(expected));
+    }
+    assert!(var.frequency(var.n_types()).is_none());
+
+    // written without a Freqs component
+    let datastore = Datastore::open(DATASTORE_PATH).unwrap();
+    let dickens = datastore["primary"]["word"].as_indexed_string().unwrap();
+    let the = dickens.type_id("the").unwrap();
+    assert!(dickens.frequency(the) == Some(dickens.inverted_index().positions(the).unwrap().count()));
+}
+
+#[test]
+fn encode_into_memory() {
+    use std::io::Cursor;
+    use crate::variables::{IntegerVariable, PointerVariable};
+
+    let base = uuid::Uuid::new_v4();
+    let values: Vec<i64> = (0..1000).map(|i| (i * 7919) % 1000 - 500).collect();
+
+    // the reverse index is built from the IntStream read back from the buffer
+    let intvar = IntegerVariable::encode_to_file(Cursor::new(Vec::new()), values.iter().copied(), values.len(), "num".to_owned(), base, true, true, "").unwrap();
+    assert!(intvar.iter().eq(values.iter().copied()));
+    assert!(intvar.get_all(-500).collect::<Vec<_>>() == vec![0]);
+
+    let mut bytes = Vec::new();
+    let heads = (0..100).map(|i| if i % 3 == 0 { -1 } else { i - 1 });
+    PointerVariable::encode_to_file(Cursor::new(&mut bytes), heads, 100, "head".to_owned(), base, false, "").unwrap();
+
+    let container = Container::from_bytes(&bytes, "head".to_owned()).unwrap();
+    assert!(container.bytes() == &bytes[..]);
+    let pointers = PointerVariable::try_from(container).unwrap();
+    assert!(pointers.get(0) == None);
+    assert!(pointers.get(5) == Some(4));
+
+    let container = ContainerBuilder::new_in_memory("empty".to_owned(), 1)
+        .edit_header(|h| {
+            h.family('X').class('X').ctype('x');
+        })
+        .comment("built in memory")
+        .build()
+        .unwrap();
+    assert!(container.comment() == Some("built in memory"));
+}
+
+#[test]
+fn pointer_joins() {
+    use std::io::Cursor;
+    use crate::variables::PointerVariable;
+
+    // every position points to the start of its group of 5, group starts have no head
+    let heads: Vec<i64> = (0..1000).map(|i| if i % 5 == 0 { -1 } else { i - i % 5 }).collect();
+
+    for compressed in [false, true] {
+        let pointers = PointerVariable::encode_to_file(Cursor::new(Vec::new()), heads.iter().copied(), heads.len(), "head".to_owned(), uuid::Uuid::new_v4(), compressed, "").unwrap();
+
+        let positions = [1, 2, 2, 5, 17, 400, 401, 999, 1000];
+        let joined = pointers.join_heads(&positions);
+        assert!(joined == positions.iter().map(|p| pointers.get(*p)).collect::<Vec<_>>());
+        assert!(joined == vec![Some(0), Some(0), Some(0), None, Some(15), None, Some(400), Some(995), None]);
+
+        // out of order positions restart the walk
+        let unsorted = [999, 3, 998, 3];
+        assert!(pointers.join_heads_iter(unsorted).eq(unsorted.iter().map(|p| pointers.get(*p))));
+
+        let pairs = pointers.join_tails(&[0, 0, 3, 995]);
+        assert!(pairs == vec![(0, 1), (0, 2), (0, 3), (0, 4), (995, 996), (995, 997), (995, 998), (995, 999)]);
+        assert!(pairs.iter().all(|(head, tail)| pointers.join_heads(&[*tail]) == vec![Some(*head)]));
+    }
+}
+
+#[test]
+fn batch_gets() {
+    use std::io::Cursor;
+    use crate::variables::{IntegerVariable, PlainStringVariable, PointerVariable};
+
+    let datastore = Datastore::open(DATASTORE_PATH).unwrap();
+    let words = datastore["primary"]["word"].as_indexed_string().unwrap();
+
+    // unsorted, clustered and repeated positions
+    let mut positions = setup_rand(2000, words.len());
+    positions.extend([5, 4, 3, 5, words.len() - 1, 0]);
+    let many = words.get_many(&positions).unwrap();
+    assert!(many.len() == positions.len());
+    assert!(many.iter().zip(&positions).all(|(w, p)| *w == words.get_unchecked(*p)));
+    assert!(words.get_many_ids(&positions).unwrap().iter().zip(&positions).all(|(t, p)| *t == words.get_id_unchecked(*p)));
+    assert!(words.get_many(&[0, words.len()]).is_none());
+    assert!(words.get_many(&[]) == Some(Vec::new()));
+
+    let base = uuid::Uuid::new_v4();
+    let values: Vec<i64> = (0..3000).map(|i| (i * 7919) % 1000 - 500).collect();
+    let strings: Vec<String> = values.iter().map(|v| v.to_string()).collect();
+    let heads: Vec<i64> = (0..3000).map(|i| if i % 5 == 0 { -1 } else { i - i % 5 }).collect();
+    let positions = [2999, 17, 18, 0, 2048, 17, 1024];
+
+    for compressed in [false, true] {
+        let ints = IntegerVariable::encode_to_file(Cursor::new(Vec::new()), values.iter().copied(), values.len(), "num".to_owned(), base, compressed, false, "").unwrap();
+        assert!(ints.get_many(&positions) == Some(positions.iter().map(|p| values[*p]).collect()));
+        assert!(ints.get_many(&[3000]).is_none());
+
+        let plain = PlainStringVariable::encode_to_file(Cursor::new(Vec::new()), strings.iter().cloned(), strings.len(), "num".to_owned(), base, compressed, "").unwrap();
+        assert!(plain.get_many(&positions) == Some(positions.iter().map(|p| strings[*p].as_str()).collect()));
+        assert!(plain.get_many(&[3000]).is_none());
+
+        let pointers = PointerVariable::encode_to_file(Cursor::new(Vec::new()), heads.iter().copied(), heads.len(), "head".to_owned(), base, compressed, "").unwrap();
+        assert!(pointers.get_many(&positions) == Some(positions.iter().map(|p| pointers.get(*p)).collect()));
+        assert!(pointers.get_many(&[3000]).is_none());
+    }
+}
+
+#[test]
+fn block_iterators() {
+    use crate::components::Vector;
+
+    let datastore = Datastore::open(DATASTORE_PATH).unwrap();
+    let words = datastore["primary"]["word"].as_indexed_string().unwrap();
+    let ids = words.id_stream();
+    let data: Vec<i64> = ids.column_iter(0).collect();
+    let uncompressed = CachedVector::<1>::new(Vector::uncompressed_from_parts(data.len(), 1, &data)).unwrap();
+
+    for vector in [&ids, &uncompressed] {
+        for (start, end) in [(0, vector.len()), (5, 40), (16, 32), (7, 7), (vector.len() - 3, vector.len())] {
+            let blocks: Vec<_> = vector.block_iter_range(start, end).unwrap().collect();
+            assert!(blocks.iter().flat_map(|(_, rows)| rows.iter().copied()).eq(vector.iter_range(start, end).unwrap()));
+            assert!(blocks.iter().all(|(first, rows)| vector.get_row(*first) == rows.first().copied()));
+            assert!(blocks.len() == vector.block_iter_range(start, end).unwrap().len());
+        }
+        assert!(vector.block_iter_range(0, vector.len() + 1).is_none());
+    }
+
+    // chunks of cached and uncached postings lists
+    let index = words.inverted_index();
+    let the = words.type_id("the").unwrap();
+    for _ in 0..2 {
+        let chunks: Vec<_> = index.postings_chunks(the, 100).unwrap().collect();
+        assert!(chunks.iter().all(|(first, chunk)| chunk.len() <= 100 && index.position(the, *first) == chunk.first().copied()));
+        assert!(chunks.into_iter().flat_map(|(_, chunk)| chunk).eq(index.positions(the).unwrap()));
+    }
+    assert!(index.postings_chunks(words.n_types(), 100).is_none());
+}
+
+/// Positions beyond `i32::MAX` and `u32::MAX` in every structure that stores positions
+/// and can be built without materializing a corpus of that size
+#[test]
+fn big_corpus_positions() {
+    use std::io::Cursor;
+    use crate::components::elias_fano::{self, EliasFano};
+    use crate::variables::SparseVariable;
+
+    const BIG: usize = 5_000_000_000;
+    let starts = [0, i32::MAX as usize - 1, u32::MAX as usize, u32::MAX as usize + 10, BIG - 3];
+    let ranges: Vec<(usize, usize)> = starts.iter().map(|&s| (s, s + 3)).collect();
+
+    for compressed in [false, true] {
+        let layer = SegmentationLayer::encode_to_file(Cursor::new(Vec::new()), ranges.iter().copied(), ranges.len(), "s".to_owned(), uuid::Uuid::new_v4(), compressed, "").unwrap();
+        assert!(layer.iter().eq(ranges.iter().copied()));
+        for (i, &(start, end)) in ranges.iter().enumerate() {
+            assert!(layer.find_containing(start) == Some(i) && layer.find_containing(end - 1) == Some(i));
+        }
+        assert!(layer.find_containing(u32::MAX as usize + 5).is_none());
+
+        let annotations: Vec<(usize, String)> = starts[1..].iter().map(|&p| (p, p.to_string())).collect();
+        let sparse = SparseVariable::encode_to_file(Cursor::new(Vec::new()), annotations.iter().cloned(), BIG, "big".to_owned(), uuid::Uuid::new_v4(), compressed, "").unwrap();
+        assert!(sparse.len() == BIG);
+        assert!(sparse.annotations().map(|(p, s)| (p, s.to_owned())).eq(annotations.iter().cloned()));
+        for (position, value) in &annotations {
+            assert!(sparse.get(*position) == Some(value.as_str()));
+            assert!(sparse.postings(sparse.type_id(value).unwrap()).unwrap().eq([*position]));
+        }
+        assert!(sparse.get(u32::MAX as usize + 1).is_none());
+    }
+
+    let positions: Vec<usize> = starts.iter().chain(&[1 << 40]).copied().collect();
+    let bytes = elias_fano::encode(&positions);
+    let list = EliasFano::new(positions.len(), &bytes);
+    assert!(list.decode() == positions);
+    assert!(list.next_geq(u32::MAX as usize + 1) == Some((3, u32::MAX as usize + 10)));
+}
+
+#[test]
+fn utf8_validation() {
+    use std::io::Cursor;
+    use crate::variables::{IndexedStringVariable, PlainStringVariable};
+
+    let datastore = Datastore::open_validated(DATASTORE_PATH).unwrap();
+    assert!(datastore["primary"]["word"].validate_utf8().is_ok());
+
+    let strings = ["alpha", "beta", "gamma", "beta"];
+    let base = uuid::Uuid::new_v4();
+
+    let mut plain = Vec::new();
+    PlainStringVariable::encode_to_file(Cursor::new(&mut plain), strings.iter().map(|s| s.to_string()), 4, "plain".to_owned(), base, false, "").unwrap();
+    let mut indexed = Vec::new();
+    IndexedStringVariable::encode_to_file(Cursor::new(&mut indexed), strings.iter().map(|s| s.to_string()), 4, "indexed".to_owned(), base, false, "").unwrap();
+
+    // replace the "mm" of gamma with an invalid sequence
+    for bytes in [&mut plain, &mut indexed] {
+        let i = bytes.windows(5).position(|w| w == b"gamma").unwrap();
+        bytes[i + 2] = 0xff;
+        bytes[i + 3] = 0xfe;
+    }
+
+    let plain = PlainStringVariable::try_from(Container::from_bytes(&plain, "plain".to_owned()).unwrap()).unwrap();
+    assert!(plain.validate_utf8() == Err(crate::components::InvalidUtf8 { index: 2, valid_up_to: 2 }));
+    assert!(plain.get_lossy(2).unwrap() == "ga\u{FFFD}\u{FFFD}a");
+    assert!(plain.get_lossy(1).unwrap() == "beta");
+    assert!(plain.get_lossy(4).is_none());
+
+    let indexed = IndexedStringVariable::try_from(Container::from_bytes(&indexed, "indexed".to_owned()).unwrap()).unwrap();
+    let error = indexed.validate_utf8().unwrap_err();
+    assert!(indexed.lexicon().get_lossy(error.index).unwrap() == "ga\u{FFFD}\u{FFFD}a");
+    assert!(indexed.get_lossy(2).unwrap() == "ga\u{FFFD}\u{FFFD}a");
+    assert!(indexed.get_lossy(3).unwrap() == "beta");
+}
+
+#[test]
+fn layer_range_validation() {
+    let dir = tempfile::tempdir().unwrap();
+    let open = |name: &str| File::options()
+        .read(true)
+        .write(true)
+        .create(true)
+        .open(dir.path().join(name))
+        .unwrap();
+    let base = uuid::Uuid::new_v4();
+
+    let overlapping = vec![(0, 3), (3, 5), (4, 8)];
+    let result = SegmentationLayer::encode_to_file(open("seg1.zigl"), overlapping.iter().copied(), 3, "s".to_owned(), base, false, "");
+    assert!(matches!(result, Err(EncodeError::InvalidRange(RangeError::Overlapping(2, (4, 8))))));
+
+    let unsorted = vec![(4, 6), (0, 3)];
+    let result = SegmentationLayer::encode_to_file(open("seg2.zigl"), unsorted.iter().copied(), 2, "s".to_owned(), base, true, "");
+    assert!(matches!(result, Err(EncodeError::InvalidRange(RangeError::Unsorted(1, (0, 3))))));
+
+    let layer = SegmentationLayer::encode_to_file(open("seg3.zigl"), [(0, 3), (5, 8)].into_iter(), 2, "s".to_owned(), base, false, "").unwrap();
+    assert!(layer.get(1) == Some((5, 8)));
+
+    let spans = vec![(0, 10), (2, 4), (3, 5), (8, 9), (12, 13)];
+    let result = SpanLayer::encode_to_file(open("span1.zigl"), [(0, 2), (1, 1)].into_iter(), 2, "ne".to_owned(), base, false, "");
+    assert!(matches!(result, Err(EncodeError::InvalidRange(RangeError::Empty(1, _)))));
+
+    for compressed in [false, true] {
+        let layer = SpanLayer::encode_to_file(open("span2.zigl"), spans.iter().copied(), spans.len(), "ne".to_owned(), base, compressed, "").unwrap();
+        assert!(layer.len() == 5);
+        assert!(layer.max_len() == 10);
+        assert!(layer.iter().collect::<Vec<_>>() == spans);
+        assert!(layer.find_containing(3) == vec![0, 1, 2]);
+        assert!(layer.find_containing(10).is_empty());
+        assert!(layer.find_overlapping(4, 13) == vec![0, 2, 3, 4]);
+        assert!(layer.contains_end(9) && !layer.contains_end(8));
+    }
+}
+
+#[test]
+fn find_containing_compressed() {
+    let dir = tempfile::tempdir().unwrap();
+    let base = uuid::Uuid::new_v4();
+
+    // gaps between the ranges and enough of them to span several index blocks
+    let ranges: Vec<(usize, usize)> = (0..100).map(|i| (i * 5 + 1, i * 5 + 1 + (i % 4) + 1)).collect();
+
+    let layers: Vec<_> = [false, true].into_iter().map(|compressed| {
+        let file = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .open(dir.path().join(format!("s{}.zigl", compressed)))
+            .unwrap();
+        SegmentationLayer::encode_to_file(file, ranges.iter().copied(), ranges.len(), "s".to_owned(), base, compressed, "").unwrap()
+    }).collect();
+
+    for position in 0..510 {
+        let expected = ranges.iter().position(|(s, e)| *s <= position && position < *e);
+        for layer in &layers {
+            assert!(layer.find_containing(position) == expected);
+        }
+    }
+    assert!(layers[1].contains(ranges[42]));
+    assert!(!layers[1].contains((ranges[42].0, ranges[42].1 + 1)));
+}
+
+#[test]
+fn base_navigation() {
+    let datastore = Datastore::open(DATASTORE_PATH).unwrap();
+    let primary = &datastore["primary"];
+    let s = &datastore["s"];
+
+    assert!(primary.base() == None);
+    assert!(datastore.base_of(primary).is_none());
+    assert!(datastore.base_chain(primary).is_empty());
+
+    assert!(s.base() == Some(primary.uuid()));
+    assert!(datastore.base_of(s).map(|l| l.uuid()) == Some(primary.uuid()));
+
+    let word = &primary["word"];
+    assert!(word.base() == Some(primary.uuid()));
+    let chain: Vec<_> = datastore.base_chain(word).iter().map(|l| l.uuid()).collect();
+    assert!(chain == vec![primary.uuid()]);
+
+    let title = &datastore["chapter"]["title"];
+    let chain: Vec<_> = datastore.base_chain(title).iter().map(|l| l.uuid()).collect();
+    assert!(chain == vec![datastore["chapter"].uuid(), primary.uuid()]);
+}
+
+#[test]
+fn dependency_graph() {
+    use crate::graph::{BaseSlot, DependencyGraph};
+
+    let datastore = Datastore::open(DATASTORE_PATH).unwrap();
+    let graph = DependencyGraph::open(DATASTORE_PATH).unwrap();
+
+    let primary = graph.node(datastore["primary"].uuid()).unwrap();
+    assert!(primary.type_code == "ZLp");
+    assert!(primary.container_type.as_deref() == Some("PrimaryLayer"));
+    assert!(primary.dim1 == datastore["primary"].len());
+    assert!(graph.missing().count() == 0);
+
+    let word = datastore["primary"]["word"].uuid().unwrap();
+    assert!(graph.edges.iter().any(|e| e.from == word && e.to == primary.uuid && e.slot == BaseSlot::Base1));
+    assert!(graph.edges.len() == graph.nodes.len() - 1);
+
+    let dot = graph.to_dot();
+    assert!(dot.starts_with("digraph datastore {"));
+    assert!(dot.contains(&format!("\"{}\" -> \"{}\" [label=\"base1\"];", word, primary.uuid)));
+
+    let json: serde_json::Value = serde_json::from_str(&graph.to_json().unwrap()).unwrap();
+    assert!(json["nodes"].as_array().unwrap().len() == graph.nodes.len());
+    assert!(json["edges"][0]["slot"] == "base1");
+
+    // a variable without its layer can't be opened as a datastore, but still be inspected
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::copy(format!("{}/chapter/title.zigv", DATASTORE_PATH), dir.path().join("title.zigv")).unwrap();
+    assert!(Datastore::open(dir.path()).is_err());
+
+    let graph = DependencyGraph::open(dir.path()).unwrap();
+    let missing: Vec<_> = graph.missing().map(|e| e.to).collect();
+    assert!(missing == vec![datastore["chapter"].uuid()]);
+    assert!(graph.to_dot().contains("style=dashed, color=red"));
+}
+
+#[test]
+fn find_containing_batch() {
+    let datastore = Datastore::open(DATASTORE_PATH).unwrap();
+    let layer = datastore["s"].as_segmentation().unwrap();
+
+    let mut positions: Vec<usize> = (0..200_000).step_by(7).collect();
+    positions.extend([2_000_000, 2_000_001, 3_407_084, 3_407_085]);
+    let expected: Vec<_> = positions.iter().map(|p| layer.find_containing(*p)).collect();
+    assert!(layer.find_containing_sorted(&positions) == expected);
+
+    // out of order positions are still answered correctly
+    let unsorted = [3_000_000, 5, 2_000_000, 5];
+    let results: Vec<_> = layer.find_containing_iter(unsorted).collect();
+    assert!(results == unsorted.iter().map(|p| layer.find_containing(*p)).collect::<Vec<_>>());
+}
+
+/// Iterators only borrow the memory map, so they can outlive the variables they came from
+struct SentenceStarts<'map> {
+    words: crate::variables::IndexedStringIterator<'map>,
+    sentences: crate::layers::ContainingIterator<'map, std::ops::Range<usize>>,
+}
+
+fn sentence_starts<'map>(datastore: &Datastore<'map>, end: usize) -> SentenceStarts<'map> {
+    let words = datastore["primary"]["word"].as_indexed_string().unwrap();
+    let sentences = datastore["s"].as_segmentation().unwrap();
+
+    SentenceStarts {
+        words: words.get_range(0, end).unwrap(),
+        sentences: sentences.find_containing_iter(0..end),
+    }
+}
+
+#[test]
+fn iterators_outlive_variables() {
+    let datastore = Datastore::open(DATASTORE_PATH).unwrap();
+    let plan = sentence_starts(&datastore, 1000);
+    assert!(plan.words.len() == 1000);
+
+    let words = datastore["primary"]["word"].as_indexed_string().unwrap();
+    let first = words.get_unchecked(0);
+    let tagged: Vec<_> = plan.words.zip(plan.sentences).collect();
+    assert!(tagged[0].0 == first);
+    assert!(tagged[999].1 == datastore["s"].as_segmentation().unwrap().find_containing(999));
+    assert!(tagged.iter().all(|(w, _)| !w.is_empty()));
+}
+
+#[test]
+fn variable_passthrough() {
+    use crate::variables::VariableValue;
+
+    let datastore = Datastore::open(DATASTORE_PATH).unwrap();
+    let primary = &datastore["primary"];
+
+    for name in primary.variable_names() {
+        let var = &primary[name];
+        assert!(var.len() == primary.len());
+        assert!(var.iter().len() == var.len());
+        assert!(var.get(var.len()).is_none());
+    }
+
+    let word = &primary["word"];
+    let words = word.as_indexed_string().unwrap();
+    let values: Vec<_> = word.iter().take(10).collect();
+    assert!(values.iter().zip(words.iter()).all(|(v, w)| *v == VariableValue::String(w)));
+    assert!(word.get_string(3) == words.get(3));
+    assert!(word.get_int(3).is_none());
+
+    let tid = word.id_stream().unwrap().get_row(3).unwrap()[0] as usize;
+    assert!(word.postings(tid).unwrap().any(|p| p == 3));
+}
+
+#[test]
+fn layer_variable_types() {
+    use crate::variables::VariableType;
+
+    let datastore = Datastore::open(DATASTORE_PATH).unwrap();
+    let primary = &datastore["primary"];
+
+    let mut variables: Vec<(&str, VariableType)> = primary.variables()
+        .map(|(name, var)| (name, var.variable_type()))
+        .collect();
+    variables.sort_by_key(|(name, _)| *name);
+    let mut names: Vec<&str> = primary.variable_names().map(|n| n.as_str()).collect();
+    names.sort();
+    assert!(variables.iter().map(|(name, _)| *name).eq(names));
+
+    for (name, var_type) in variables {
+        assert!(primary.variable_type(name) == Some(var_type));
+    }
+    assert!(primary.variable_type("word") == Some(VariableType::IndexedString));
+    assert!(primary.variable_type("missing").is_none());
+    assert!(VariableType::Integer.to_string() == "IntegerVariable");
+}
+
+#[test]
+fn document_frequencies() {
+    let datastore = Datastore::open(DATASTORE_PATH).unwrap();
+    let words = datastore["primary"]["word"].as_indexed_string().unwrap();
+    let chapters = datastore["chapter"].as_segmentation().unwrap();
+
+    let df = words.document_frequencies(chapters);
+    assert!(df.len() == words.n_types());
+
+    let the = words.lexicon().find_match("the").unwrap();
+    let (start, end) = chapters.get(0).unwrap();
+    let naive = chapters.iter()
+        .filter(|&(start, end)| words.get_range(start, end).unwrap().any(|w| w == "the"))
+        .count();
+    assert!(words.document_frequency(the, chapters) == Some(naive));
+    assert!(df[the] == naive && naive <= chapters.len());
+
+    let weights = words.tf_idf(chapters, 0, &df).unwrap();
+    let tf = words.get_range(start, end).unwrap().filter(|&w| w == "the").count();
+    let (_, w) = weights.iter().find(|(id, _)| *id == the).unwrap();
+    assert!((w - tf as f64 * (chapters.len() as f64 / naive as f64).ln()).abs() < 1e-9);
+    assert!(weights.windows(2).all(|w| w[0].0 < w[1].0));
+    assert!(words.tf_idf(chapters, chapters.len(), &df).is_none());
+}
+
+#[test]
+fn sampling() {
+    use crate::sample;
+
+    let datastore = Datastore::open(DATASTORE_PATH).unwrap();
+    let word = &datastore["primary"]["word"];
+    let words = word.as_indexed_string().unwrap();
+
+    let positions = word.random_positions(100, 42);
+    assert!(positions.len() == 100);
+    assert!(positions.windows(2).all(|p| p[0] < p[1]));
+    assert!(*positions.last().unwrap() < word.len());
+    assert!(positions == word.random_positions(100, 42));
+    assert!(positions != word.random_positions(100, 43));
+
+    let chapters = datastore["chapter"].as_segmentation().unwrap();
+    assert!(chapters.random_segments(chapters.len() + 5, 1) == (0..chapters.len()).collect::<Vec<_>>());
+
+    let matches = words.sample_matches("^[Tt]he$", 50, 7).unwrap();
+    assert!(matches.len() == 50);
+    assert!(matches.windows(2).all(|p| p[0] < p[1]));
+    assert!(matches.iter().all(|&p| matches!(words.get(p), Some("the" | "The"))));
+    assert!(matches == words.sample_matches("^[Tt]he$", 50, 7).unwrap());
+    assert!(words.sample_matches("^no such word$", 10, 7).unwrap().is_empty());
+    assert!(words.sample_matches("(", 10, 7).is_none());
+
+    let lines = sample::reservoir(words.iter().take(1000), 10, 3);
+    assert!(lines.len() == 10);
+    assert!(lines == sample::reservoir(words.iter().take(1000), 10, 3));
+    assert!(sample::reservoir(0..5, 10, 3) == vec![0, 1, 2, 3, 4]);
+}
+
+#[test]
+fn federation() {
+    use crate::federation::{Federation, FederationError};
+    use crate::query::Query;
+
+    let single = Datastore::open(DATASTORE_PATH).unwrap();
+    let words = single["primary"]["word"].as_indexed_string().unwrap();
+    let len = single["primary"].len();
+
+    let members = vec![Datastore::open(DATASTORE_PATH).unwrap(), Datastore::open(DATASTORE_PATH).unwrap()];
+    let federation = Federation::new(members, "primary").unwrap();
+    assert!(federation.len() == 2 * len);
+    assert!(federation.locate(5) == Some((0, 5)));
+    assert!(federation.locate(len + 5) == Some((1, 5)));
+    assert!(federation.locate(2 * len).is_none());
+    assert!(federation.get("word", len + 3) == single["primary"]["word"].get(3));
+
+    let lexicon = federation.lexicon("word").unwrap();
+    assert!(lexicon.len() == words.n_types());
+    let the = words.type_id("the").unwrap();
+    assert!(lexicon.global_id(1, the) == lexicon.id("the"));
+
+    let freq = words.frequency(the).unwrap();
+    assert!(federation.frequency("word", "the").unwrap() == 2 * freq);
+    assert!(federation.type_frequencies("word").unwrap()[lexicon.id("the").unwrap()] == 2 * freq);
+
+    let postings = federation.postings("word", "the").unwrap();
+    assert!(postings.len() == 2 * freq);
+    assert!(postings[freq] == postings[0] + len);
+    assert!(postings.windows(2).all(|p| p[0] < p[1]));
+
+    let query = Query::parse(r#""the" "man""#).unwrap();
+    let local = query.find_in(&single, "primary").unwrap();
+    let global = federation.find(&query).unwrap();
+    assert!(!local.is_empty() && global.len() == 2 * local.len());
+    assert!(global[..local.len()] == local[..]);
+    assert!(global[local.len()..].iter().zip(&local).all(|(g, l)| *g == (l.0 + len, l.1 + len)));
+    let query = Query::parse(r#"[pos_missing="x"]"#).unwrap();
+    assert!(matches!(federation.find(&query), Err(FederationError::Query { member: 0, .. })));
+
+    assert!(matches!(federation.frequency("pos_missing", "the"), Err(FederationError::NotIndexedString { member: 0, .. })));
+    assert!(matches!(Federation::new(vec![], "primary"), Err(FederationError::Empty)));
+    let members = vec![Datastore::open(DATASTORE_PATH).unwrap()];
+    assert!(matches!(Federation::new(members, "nope"), Err(FederationError::MissingLayer { member: 0, .. })));
+
+    #[cfg(feature = "parallel")]
+    {
+        let lens = federation.par_map_members(|_, ds| ds["primary"].len()).unwrap();
+        assert!(lens == vec![len, len]);
+    }
+    assert!(federation.map_members(|i, _| federation.member_offset(i).unwrap()) == vec![0, len]);
+}
+
+#[test]
+fn concordance_lines() {
+    use crate::concordance::{Concordance, Format};
+
+    let datastore = Datastore::open(DATASTORE_PATH).unwrap();
+    let word = &datastore["primary"]["word"];
+    let words = word.as_indexed_string().unwrap();
+    let novels = datastore["novel"].as_segmentation().unwrap();
+
+    let kwic = Concordance::new(word, 3)
+        .highlight("[", "]")
+        .metadata(novels, &["title", "missing"]);
+
+    let line = kwic.line(100, 102);
+    assert!(line.left == words.get_range(97, 100).unwrap().collect::<Vec<_>>());
+    assert!(line.keyword.len() == 2 && line.right.len() == 3);
+    assert!(line.segment == novels.find_containing(100));
+    assert!(line.metadata[1].1.is_none());
+
+    let title = novels["title"].get_string(line.segment.unwrap()).unwrap();
+    let plain = kwic.format_line(100, 102);
+    assert!(plain.starts_with(&format!("100: <title={}> <missing=> ", title)));
+    assert!(plain.contains(&format!("[{} {}]", words.get_unchecked(100), words.get_unchecked(101))));
+
+    // context is clipped at the corpus boundaries
+    let kwic = kwic.format(Format::Tsv);
+    assert!(kwic.line(0, 1).left.is_empty());
+    assert!(kwic.line(words.len() - 1, words.len()).right.is_empty());
+    assert!(kwic.format_line(0, 1).split('\t').count() == 7);
+
+    let kwic = kwic.format(Format::Json);
+    let mut out = Vec::new();
+    kwic.write(&mut out, [(100, 102), (200, 201)]).unwrap();
+    let records: Vec<serde_json::Value> = String::from_utf8(out).unwrap()
+        .lines()
+        .map(|l| serde_json::from_str(l).unwrap())
+        .collect();
+    assert!(records.len() == 2);
+    assert!(records[0]["start"] == 100);
+    assert!(records[0]["metadata"][0][1] == title);
+
+    // context snapped to the sentences containing the match, at most 50 tokens per side
+    let sentences = datastore["s"].as_segmentation().unwrap();
+    let kwic = Concordance::new(word, 50).snap(sentences);
+    let (s_start, s_end) = sentences.get(1000).unwrap();
+    let lines = kwic.lines([(s_start + 1, s_start + 2), (s_end - 1, s_end + 1)]);
+    assert!(lines[0].left == [words.get_unchecked(s_start)]);
+    assert!(lines[0].right.len() == (s_end - s_start - 2).min(50));
+
+    // a match across two sentences gets the right context of the second
+    let next = sentences.find_containing(s_end).unwrap();
+    let (_, next_end) = sentences.get(next).unwrap();
+    assert!(lines[1].left.len() == (s_end - 1 - s_start).min(50));
+    assert!(lines[1].right.len() == (next_end - s_end - 1).min(50));
+
+    // long sentences are capped
+    let long = (0..sentences.len()).map(|i| sentences.get(i).unwrap()).find(|(s, e)| e - s > 120).unwrap();
+    let line = kwic.line(long.0 + 60, long.0 + 61);
+    assert!(line.left.len() == 50 && line.right.len() == (long.1 - long.0 - 61).min(50));
+}
+
+#[cfg(feature = "arrow")]
+#[test]
+fn arrow_export() {
+    use arrow_array::{Array, StringArray, UInt64Array};
+
+    use crate::export;
+
+    let datastore = Datastore::open(DATASTORE_PATH).unwrap();
+    let primary = &datastore["primary"];
+    let words = primary["word"].as_indexed_string().unwrap();
+
+    let freqs = export::frequency_table(words).unwrap();
+    assert!(freqs.num_rows() == words.n_types());
+    let counts = freqs.column(2).as_any().downcast_ref::<UInt64Array>().unwrap();
+    assert!(counts.values().iter().sum::<u64>() == words.len() as u64);
+
+    let tid = words.get_id(10).unwrap();
+    let postings = export::postings(&primary["word"], tid).unwrap();
+    assert!(postings.len() == words.inverted_index().frequency(tid).unwrap());
+    assert!(export::postings_table(&primary["word"], &[tid, tid]).unwrap().num_rows() == 2 * postings.len());
+
+    let table = export::layer_range(primary, &["word", "pos"], 100, 200).unwrap();
+    assert!(table.num_rows() == 100 && table.num_columns() == 3);
+    let column = table.column(1).as_any().downcast_ref::<StringArray>().unwrap();
+    assert!(column.value(0) == words.get_unchecked(100));
+
+    assert!(matches!(export::layer_range(primary, &["nope"], 0, 1), Err(export::ExportError::UnknownVariable(_))));
+    assert!(matches!(export::variable_range(&primary["word"], 0, words.len() + 1), Err(export::ExportError::OutOfRange(..))));
+
+    #[cfg(feature = "parquet")]
+    {
+        use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+        let file = tempfile::tempfile().unwrap();
+        export::write_parquet(&[table.clone(), table], file.try_clone().unwrap()).unwrap();
+        let reader = ParquetRecordBatchReaderBuilder::try_new(file).unwrap().build().unwrap();
+        assert!(reader.map(|b| b.unwrap().num_rows()).sum::<usize>() == 200);
+    }
+}
+
+#[test]
+fn select_variables() {
+    use crate::selection::SelectionError;
+
+    let datastore = Datastore::open(DATASTORE_PATH).unwrap();
+    let primary = &datastore["primary"];
+
+    let selection = datastore.select(&["word", "pos", "lemma"]).unwrap();
+    assert!(selection.len() == primary.len() && selection.width() == 3);
+
+    // crosses several block boundaries with an unaligned start
+    let rows: Vec<_> = selection.iter_range(1005, 1070).unwrap().collect();
+    assert!(rows.len() == 65);
+    for (i, row) in rows.iter().enumerate() {
+        for (name, value) in selection.names().iter().zip(row) {
+            assert!(Some(value) == primary[name].get(1005 + i).as_ref());
+        }
+    }
+    assert!(selection.iter().len() == primary.len());
+    assert!(selection.iter_range(0, primary.len() + 1).is_none());
+
+    let chapters = datastore.select(&["num", "title"]).unwrap();
+    let row = chapters.iter().nth(2).unwrap();
+    assert!(row[0] == datastore["chapter"]["num"].get(2).unwrap());
+    assert!(row[1] == datastore["chapter"]["title"].get(2).unwrap());
+
+    assert!(datastore.select(&["title"]).unwrap_err() == SelectionError::AmbiguousLayer);
+    assert!(datastore.select(&["word", "num"]).unwrap_err() == SelectionError::DifferentLayers);
+    assert!(datastore.select(&["word", "nonexistent"]).unwrap_err() == SelectionError::UnknownVariable("nonexistent".to_owned()));
+}
+
+#[cfg(feature = "parallel")]
+#[test]
+fn parallel_scan() {
+    let datastore = Datastore::open(DATASTORE_PATH).unwrap();
+    let words = datastore["primary"]["word"].as_indexed_string().unwrap();
+
+    let count_the = |_, chunk: crate::variables::IndexedStringIterator| chunk.filter(|w| *w == "the").count();
+    let expected = words.iter().filter(|w| *w == "the").count();
+    assert!(words.par_scan(1000, count_the, |a, b| a + b) == Some(expected));
+
+    // chunks are merged in position order and start on block boundaries
+    let starts = words.par_scan(1000, |start, chunk| vec![(start, chunk.len())], |mut a, b| { a.extend(b); a }).unwrap();
+    assert!(starts.iter().all(|(start, _)| start % 16 == 0));
+    assert!(starts.windows(2).all(|w| w[0].0 + w[0].1 == w[1].0));
+    assert!(starts.iter().map(|(_, len)| len).sum::<usize>() == words.len());
+}
+
+#[test]
+fn query_cache() {
+    use crate::query_cache::QueryCache;
+    use uuid::Uuid;
+
+    let datastore = Datastore::open(DATASTORE_PATH).unwrap();
+    let word = datastore["primary"]["word"].uuid().unwrap();
+    let primary = datastore["primary"].uuid();
+
+    let dir = tempfile::tempdir().unwrap();
+    let cache = QueryCache::new(dir.path()).unwrap();
+    let positions = vec![3, 17, 18, 200, 5000];
+
+    assert!(cache.get("word=/the/", &[word]).is_none());
+    cache.put("word=/the/", &[word, primary], &positions).unwrap();
+    assert!(cache.get("word=/the/", &[primary, word]) == Some(positions.clone()));
+    assert!(cache.get("word=/the/", &[word]).is_none());
+    assert!(cache.get("word=/a/", &[word, primary]).is_none());
+
+    cache.put("word=/nothing/", &[word], &[]).unwrap();
+    assert!(cache.get("word=/nothing/", &[word]) == Some(vec![]));
+
+    // ranges are kept apart from positions of the same query
+    let ranges = vec![(3, 9), (4, 5), (17, 18), (200, 300)];
+    cache.put_ranges("word=/the/", &[word, primary], &ranges).unwrap();
+    assert!(cache.get_ranges("word=/the/", &[word, primary]) == Some(ranges.clone()));
+    assert!(cache.get("word=/the/", &[word, primary]) == Some(positions.clone()));
+    assert!(cache.get_ranges("word=/nothing/", &[word]).is_none());
+
+    let mut computed = 0;
+    for _ in 0..2 {
+        let result = cache.get_or_insert_with("word=/of/", &[word], || { computed += 1; vec![1, 2, 3] }).unwrap();
+        assert!(result == vec![1, 2, 3]);
+    }
+    assert!(computed == 1);
+
+    // entries of containers that are not in the datastore anymore are stale
+    cache.put("word=/the/", &[Uuid::new_v4()], &positions).unwrap();
+    assert!(cache.prune(&datastore).unwrap() == 1);
+    assert!(cache.get("word=/the/", &[word, primary]).is_some());
+    assert!(cache.clear().unwrap() == 4);
+    assert!(cache.get("word=/of/", &[word]).is_none());
+}
+
+#[test]
+fn subcorpora() {
+    use crate::subcorpus::{Subcorpus, SubcorpusError};
+
+    let dir = tempfile::tempdir().unwrap();
+    Datastore::open(DATASTORE_PATH).unwrap().snapshot_to(dir.path()).unwrap();
+    let datastore = Datastore::open(dir.path()).unwrap();
+    let primary = datastore["primary"].uuid();
+    let subcorpora = datastore.subcorpora().unwrap();
+    assert!(subcorpora.names().unwrap().is_empty());
+
+    let matches = Subcorpus::new("Matches", primary, vec![(10, 15), (3, 4), (12, 20)]).unwrap();
+    assert!(matches.ranges() == [(3, 4), (10, 15), (12, 20)]);
+    assert!(matches.positions() == [3, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19]);
+    subcorpora.save(&matches).unwrap();
+
+    let chapters = datastore["chapter"].uuid();
+    subcorpora.save(&Subcorpus::new("none", chapters, vec![]).unwrap()).unwrap();
+
+    // subcorpora don't affect opening the datastore
+    let datastore = Datastore::open(dir.path()).unwrap();
+    let subcorpora = datastore.subcorpora().unwrap();
+    assert!(subcorpora.names().unwrap() == ["Matches", "none"]);
+    assert!(subcorpora.load("Matches").unwrap() == matches);
+    assert!(subcorpora.load("none").unwrap().is_empty());
+
+    assert!(matches!(Subcorpus::new("a/b", primary, vec![]), Err(SubcorpusError::InvalidName(_))));
+    assert!(matches!(Subcorpus::new("empty", primary, vec![(4, 4)]), Err(SubcorpusError::InvalidRange(_))));
+    let too_long = Subcorpus::new("long", chapters, vec![(0, datastore["chapter"].len() + 1)]).unwrap();
+    assert!(matches!(subcorpora.save(&too_long), Err(SubcorpusError::OutOfBounds { .. })));
+    assert!(matches!(subcorpora.load("missing"), Err(SubcorpusError::NotFound(_))));
+
+    assert!(subcorpora.remove("none").unwrap());
+    assert!(!subcorpora.remove("none").unwrap());
+    assert!(subcorpora.names().unwrap() == ["Matches"]);
+}
+
+#[test]
+fn group_counts() {
+    use crate::selection::SelectionError;
+    use crate::variables::VariableValue;
+
+    let datastore = Datastore::open(DATASTORE_PATH).unwrap();
+    let words = datastore["primary"]["word"].as_indexed_string().unwrap();
+    let chapters = datastore["chapter"].as_segmentation().unwrap();
+    let the = words.lexicon().find_match("the").unwrap();
+
+    for variable in ["num", "title"] {
+        let counts = words.frequency_by(the, chapters, variable).unwrap();
+        assert!(counts.total() == words.frequency(the).unwrap());
+
+        let mut naive: Vec<(VariableValue, usize)> = Vec::new();
+        let mut ungrouped = 0;
+        for p in words.inverted_index().positions(the).unwrap() {
+            match chapters.find_containing(p).and_then(|i| chapters[variable].get(i)) {
+                Some(value) => match naive.iter_mut().find(|(v, _)| *v == value) {
+                    Some((_, n)) => *n += 1,
+                    None => naive.push((value, 1)),
+                },
+                None => ungrouped += 1,
+            }
+        }
+
+        assert!(counts.ungrouped() == ungrouped);
+        assert!(counts.groups().len() == naive.len());
+        for (value, n) in naive.iter() {
+            assert!(counts.get(value) == *n);
+        }
+        assert!(counts.groups().windows(2).all(|w| w[0].1 >= w[1].1));
+    }
+
+    // unsorted positions give the same counts
+    let positions: Vec<usize> = words.inverted_index().positions(the).unwrap().collect();
+    let sorted = chapters.group_counts("title", positions.iter().copied()).unwrap();
+    let reversed = chapters.group_counts("title", positions.iter().rev().copied()).unwrap();
+    assert!(sorted.groups().iter().all(|(value, n)| reversed.get(value) == *n));
+
+    assert!(words.frequency_by(words.n_types(), chapters, "title").unwrap().total() == 0);
+    assert!(chapters.group_counts("nonexistent", [0]).unwrap_err() == SelectionError::UnknownVariable("nonexistent".to_owned()));
+}
+
+#[test]
+fn sparse_variable() {
+    use crate::variables::{SparseVariable, VariableValue};
+
+    let dir = tempfile::tempdir().unwrap();
+    Datastore::open(DATASTORE_PATH).unwrap().snapshot_to(dir.path()).unwrap();
+    std::fs::remove_file(dir.path().join(crate::manifest::MANIFEST_FILENAME)).unwrap();
+
+    let datastore = Datastore::open(dir.path()).unwrap();
+    let primary = &datastore["primary"];
+    let words = primary["word"].as_indexed_string().unwrap();
+
+    // capitalized words after the first position stand in for named entities
+    let annotations: Vec<(usize, String)> = words.iter()
+        .enumerate()
+        .skip(1)
+        .filter(|(_, w)| w.starts_with(char::is_uppercase))
+        .map(|(p, w)| (p, if w.len() > 4 { "LONG" } else { "SHORT" }.to_owned()))
+        .collect();
+    let open = |name: &str| File::options()
+        .read(true)
+        .write(true)
+        .create(true)
+        .open(dir.path().join(name))
+        .unwrap();
+
+    for compressed in [false, true] {
+        let var = SparseVariable::encode_to_file(open("tmp.zigv"), annotations.iter().cloned(), primary.len(), "ne".to_owned(), primary.uuid(), compressed, "").unwrap();
+        assert!(var.len() == primary.len() && var.n_annotated() == annotations.len() && var.n_types() == 2);
+        assert!(var.annotations().map(|(p, s)| (p, s.to_owned())).eq(annotations.iter().cloned()));
+        assert!(var.positions().map(|p| p as usize).eq(annotations.iter().map(|(p, _)| *p)));
+
+        let (p, value) = &annotations[10];
+        assert!(var.get(*p) == Some(value.as_str()));
+        assert!(var.get(0).is_none() && var.get(primary.len()).is_none());
+
+        // ranges starting on and between annotations
+        for start in [0, *p, *p + 1] {
+            let expected = (start..start + 200).map(|i| annotations.iter().find(|(q, _)| *q == i).map(|(_, s)| s.as_str()));
+            assert!(var.get_range(start, start + 200).unwrap().eq(expected));
+        }
+        assert!(var.get_range(0, primary.len() + 1).is_none());
+
+        let long = var.type_id("LONG").unwrap();
+        let expected: Vec<_> = annotations.iter().filter(|(_, s)| s == "LONG").map(|(p, _)| *p).collect();
+        assert!(var.postings(long).unwrap().collect::<Vec<_>>() == expected);
+        assert!(var.frequency(long) == Some(expected.len()));
+        assert!(var.type_id("PERSON").is_none());
+    }
+
+    let var = SparseVariable::encode_to_file(open("empty.zigv"), std::iter::empty(), primary.len(), "empty".to_owned(), primary.uuid(), true, "").unwrap();
+    assert!(var.n_annotated() == 0 && var.get(5).is_none());
+    assert!(var.get_range(0, 3).unwrap().eq([None, None, None]));
+
+    let unsorted = [(5, "a".to_owned()), (3, "b".to_owned())];
+    assert!(matches!(SparseVariable::encode_to_file(open("err.zigv"), unsorted.into_iter(), 10, "e".to_owned(), primary.uuid(), false, ""), Err(EncodeError::InvalidRange(RangeError::Unsorted(1, _)))));
+    let outside = [(10, "a".to_owned())];
+    assert!(matches!(SparseVariable::encode_to_file(open("err.zigv"), outside.into_iter(), 10, "e".to_owned(), primary.uuid(), false, ""), Err(EncodeError::OutOfBounds { position: 10, len: 10 })));
+
+    // sparse variables are part of the datastore like any other variable
+    std::fs::remove_file(dir.path().join("err.zigv")).unwrap();
+    std::fs::remove_file(dir.path().join("empty.zigv")).unwrap();
+    std::fs::rename(dir.path().join("tmp.zigv"), dir.path().join("ne.zigv")).unwrap();
+    let datastore = Datastore::open(dir.path()).unwrap();
+    let ne = &datastore["primary"]["ne"];
+    let (p, value) = &annotations[0];
+    assert!(ne.get(*p) == Some(VariableValue::String(value)));
+    assert!(ne.get(0) == Some(VariableValue::Missing));
+    assert!(ne.get(datastore["primary"].len()).is_none());
+    assert!(ne.iter().filter(|v| *v != VariableValue::Missing).count() == annotations.len());
+}
+
+#[test]
+fn segment_view() {
+    let datastore = Datastore::open(DATASTORE_PATH).unwrap();
+    let chapters = datastore["chapter"].as_segmentation().unwrap();
+
+    let chapter = chapters.segment(3).unwrap();
+    assert!(chapter.range() == chapters.get(3).unwrap());
+    assert!(chapter.get("num") == chapters["num"].get(3));
+    assert!(chapter.get("nonexistent").is_none());
+
+    let values = chapter.values();
+    assert!(values.keys().copied().eq(["num", "title"]));
+    assert!(values["title"] == chapters["title"].get(3).unwrap());
+
+    let (start, end) = chapter.range();
+    assert!(chapters.segment_containing(start).unwrap().index() == 3);
+    assert!(chapters.segment_containing(end - 1).unwrap().get("title") == Some(values["title"].clone()));
+    assert!(chapters.segment(chapters.len()).is_none());
+}
+
+#[test]
+fn segmentation_start_bitmap() {
+    use std::io::Cursor;
+    use crate::components::RankBitmap;
+
+    let base = uuid::Uuid::new_v4();
+
+    // gaps, adjacent ranges and enough ranges to span several bitmap rows
+    let ranges: Vec<(usize, usize)> = (0..1000).map(|i| (i * 5 + (i % 3), i * 5 + 3 + (i % 3))).collect();
+    let max = ranges.last().unwrap().1 + 600;
+
+    let encode = |compressed, bitmap| SegmentationLayer::encode_to_file_with_bitmap(Cursor::new(Vec::new()), ranges.iter().copied(), ranges.len(), "s".to_owned(), base, compressed, bitmap, "").unwrap();
+    let plain = encode(true, false);
+    assert!(plain.start_bitmap().is_none());
+
+    for compressed in [false, true] {
+        let layer = encode(compressed, true);
+        let bitmap = layer.start_bitmap().unwrap();
+        assert!(bitmap.count_ones() == ranges.len());
+        assert!(bitmap.len() % RankBitmap::ROW_BITS == 0);
+
+        for position in 0..max {
+            assert!(layer.find_containing(position) == plain.find_containing(position));
+            assert!(layer.contains_start(position) == plain.contains_start(position));
+        }
+        for (i, &(start, end)) in ranges.iter().enumerate() {
+            assert!(bitmap.rank(start) == i);
+            assert!(bitmap.select(i) == Some(start));
+            assert!(layer.contains((start, end)));
+            assert!(!layer.contains((start, end + 1)));
+        }
+        assert!(bitmap.select(ranges.len()).is_none());
+        assert!(bitmap.rank(max) == ranges.len());
+    }
+}
+
+#[test]
+fn wavelet_tree_id_stream() {
+    use std::io::Cursor;
+    use crate::components::{LexiconBuilder, LexiconOrder, RankBitmap};
+    use crate::variables::IndexedStringVariable;
+
+    let datastore = Datastore::open(DATASTORE_PATH).unwrap();
+    let tags: Vec<&str> = datastore["primary"]["pos"].as_indexed_string().unwrap()
+        .iter()
+        .take(50_000)
+        .collect();
+
+    let encode = |wavelet_tree| {
+        let mut lexbuilder = LexiconBuilder::new();
+        lexbuilder.set_wavelet_tree(wavelet_tree);
+        lexbuilder.add_strings(tags.iter().copied());
+        IndexedStringVariable::encode_lexicon_builder(Cursor::new(Vec::new()), lexbuilder, "pos".to_owned(), uuid::Uuid::new_v4(), true, LexiconOrder::Frequency, "").unwrap()
+    };
+    let plain = encode(false);
+    let var = encode(true);
+    assert!(plain.wavelet_tree().is_none());
+
+    let tree = var.wavelet_tree().unwrap();
+    assert!(tree.len() == tags.len());
+    assert!(1 << tree.levels() >= var.n_types() && 1 << (tree.levels() - 1) < var.n_types());
+    assert!(var.iter().eq(tags.iter().copied()));
+    for position in (0..tags.len()).step_by(7) {
+        assert!(var.get_id(position) == plain.get_id(position));
+    }
+
+    for (start, end) in [(0, tags.len()), (0, 0), (123, 4567), (49_000, 60_000), (RankBitmap::ROW_BITS, 2 * RankBitmap::ROW_BITS)] {
+        for tid in 0..var.n_types() {
+            let expected = tags[start.min(tags.len())..end.min(tags.len())].iter()
+                .filter(|t| **t == var.lexicon().get(tid).unwrap())
+                .count();
+            assert!(var.count_in_range(tid, start, end) == expected);
+            assert!(plain.count_in_range(tid, start, end) == expected);
+        }
+    }
+
+    let tid = var.type_id("NN").unwrap();
+    let positions: Vec<usize> = plain.inverted_index().positions(tid).unwrap().collect();
+    for (k, &position) in positions.iter().enumerate().step_by(11) {
+        assert!(tree.select(tid, k) == Some(position));
+        assert!(tree.rank(tid, position) == k);
+    }
+    assert!(tree.select(tid, positions.len()).is_none());
+    assert!(tree.count_in_range(var.n_types() + 5, 0, tags.len()) == 0);
+}
+
+#[test]
+fn token_filter() {
+    use std::io::Cursor;
+    use crate::filter::{Condition, TokenFilter};
+    use crate::layers::{Layer, PrimaryLayer};
+    use crate::query::QueryError;
+    use crate::variables::{IndexedStringVariable, IntegerVariable, PlainStringVariable, Variable};
+
+    let datastore = Datastore::open(DATASTORE_PATH).unwrap();
+    let n = 50_000;
+    let column = |name: &str| -> Vec<String> {
+        datastore["primary"][name].as_indexed_string().unwrap().iter().take(n).map(str::to_owned).collect()
+    };
+    let (words, tags, lemmas) = (column("word"), column("pos"), column("lemma"));
+    let lengths: Vec<i64> = words.iter().map(|w| w.chars().count() as i64).collect();
+
+    let primary = PrimaryLayer::encode_to_file(Cursor::new(Vec::new()), n, "primary".to_owned(), "").unwrap();
+    let base = primary.uuid();
+    let mut layer = Layer::new_primary(primary);
+    let word = IndexedStringVariable::encode_to_file(Cursor::new(Vec::new()), words.iter().cloned(), n, "word".to_owned(), base, true, "").unwrap();
+    let pos = IndexedStringVariable::encode_to_file(Cursor::new(Vec::new()), tags.iter().cloned(), n, "pos".to_owned(), base, true, "").unwrap();
+    let lemma = PlainStringVariable::encode_to_file(Cursor::new(Vec::new()), lemmas.iter().cloned(), n, "lemma".to_owned(), base, true, "").unwrap();
+    layer.add_variable("word".to_owned(), Variable::IndexedString(word)).unwrap();
+    layer.add_variable("pos".to_owned(), Variable::IndexedString(pos)).unwrap();
+    layer.add_variable("lemma".to_owned(), Variable::PlainString(lemma)).unwrap();
+    for (name, compressed) in [("len", false), ("clen", true)] {
+        let var = IntegerVariable::encode_to_file(Cursor::new(Vec::new()), lengths.iter().copied(), n, name.to_owned(), base, compressed, false, "").unwrap();
+        layer.add_variable(name.to_owned(), Variable::Integer(var)).unwrap();
+    }
+
+    let nouns = Condition::OneOf(vec!["NN".to_owned(), "NNS".to_owned()]);
+    let filter = TokenFilter::new()
+        .with("word", Condition::Regex("[a-z]+s".to_owned()))
+        .with("pos", nouns.clone())
+        .with("len", Condition::Range(5, 7));
+    let expected: Vec<usize> = (0..n)
+        .filter(|&p| words[p].ends_with('s') && words[p].chars().all(|c| c.is_ascii_lowercase()))
+        .filter(|&p| tags[p] == "NN" || tags[p] == "NNS")
+        .filter(|&p| (5..=7).contains(&lengths[p]))
+        .collect();
+    assert!(!expected.is_empty());
+    assert!(filter.find(&layer).unwrap() == expected);
+
+    // the estimates of indexed strings and uncompressed integer indices are exact
+    let plan = filter.plan(&layer).unwrap();
+    let nouns_count = tags.iter().filter(|t| *t == "NN" || *t == "NNS").count();
+    assert!(plan.estimates[1] == nouns_count);
+    assert!(plan.estimates[2] == lengths.iter().filter(|l| (5..=7).contains(*l)).count());
+    let seed = plan.seed.unwrap();
+    assert!((0..3).all(|i| plan.estimates[seed] <= plan.estimates[i]));
+    assert!(plan.checks.len() == 2 && !plan.checks.contains(&seed));
+    assert!(plan.estimates[plan.checks[0]] <= plan.estimates[plan.checks[1]]);
+
+    // negated conditions, plain strings and a compressed integer index as the only seed
+    let filter = TokenFilter::new()
+        .with("clen", Condition::Range(12, 100))
+        .without("pos", nouns)
+        .with("lemma", Condition::Regex(".*[a-z]".to_owned()));
+    let expected: Vec<usize> = (0..n)
+        .filter(|&p| lengths[p] >= 12 && tags[p] != "NN" && tags[p] != "NNS" && lemmas[p].ends_with(|c: char| c.is_ascii_lowercase()))
+        .collect();
+    assert!(!expected.is_empty());
+    let plan = filter.plan(&layer).unwrap();
+    assert!(plan.seed == Some(0));
+    assert!(plan.estimates[0] >= lengths.iter().filter(|l| **l >= 12).count());
+    assert!(plan.estimates[1] == n - nouns_count && plan.estimates[2] == n);
+    assert!(filter.find(&layer).unwrap() == expected);
+
+    // nothing to seed from
+    let filter = TokenFilter::new().without("word", Condition::Regex("the".to_owned()));
+    assert!(filter.plan(&layer).unwrap().seed.is_none());
+    assert!(filter.find(&layer).unwrap().len() == words.iter().filter(|w| *w != "the").count());
+
+    assert!(matches!(TokenFilter::new().with("len", Condition::Regex("1".to_owned())).find(&layer), Err(QueryError::UnsupportedVariable(_))));
+    assert!(matches!(TokenFilter::new().with("nope", Condition::Range(0, 1)).find(&layer), Err(QueryError::UnknownVariable(_))));
+}
+
+#[test]
+fn query_token_sequences() {
+    use crate::query::{Query, QueryError};
+
+    let datastore = Datastore::open(DATASTORE_PATH).unwrap();
+    let primary = &datastore["primary"];
+    let word = &primary["word"];
+    let pos = &primary["pos"];
+
+    let query = Query::parse(r#" [pos="JJ" & word!="old"]  "man" "#).unwrap();
+    assert!(query.tokens.len() == 2);
+    assert!(query.tokens[0].constraints[1].negated);
+    assert!(query.tokens[1].constraints[0].variable == "word");
+
+    let matches = query.find(primary).unwrap();
+    assert!(!matches.is_empty());
+    for &(start, end) in matches.iter() {
+        assert!(end == start + 2);
+        assert!(pos.get_string(start) == Some("JJ"));
+        assert!(word.get_string(start) != Some("old"));
+        assert!(word.get_string(start + 1) == Some("man"));
+    }
+
+    // the same matches without an indexed seed, by scanning all positions
+    let scanned: Vec<_> = (0..primary.len() - 1)
+        .filter(|p| word.get_string(p + 1) == Some("man"))
+        .filter(|p| pos.get_string(*p) == Some("JJ") && word.get_string(*p) != Some("old"))
+        .map(|p| (p, p + 2))
+        .collect();
+    assert!(matches == scanned);
+
+    // regexes match whole values and [] matches any token
+    let wildcard = Query::parse(r#""m.n" [] "of""#).unwrap().find(primary).unwrap();
+    assert!(wildcard.iter().all(|(s, _)| matches!(word.get_string(*s), Some("man" | "men"))));
+    assert!(wildcard.iter().all(|(s, _)| word.get_string(s + 2) == Some("of")));
+
+    assert!(matches!(Query::parse(r#"[pos="JJ" "man""#), Err(QueryError::Syntax { position: 10, .. })));
+    assert!(matches!(Query::parse(r#""unterminated"#), Err(QueryError::Syntax { .. })));
+    assert!(matches!(Query::parse("  "), Err(QueryError::Syntax { .. })));
+    assert!(matches!(Query::parse(r#"[nope="x"]"#).unwrap().find(primary), Err(QueryError::UnknownVariable(_))));
+    assert!(matches!(Query::parse(r#""(""#).unwrap().find(primary), Err(QueryError::Regex(_))));
+}
+
+#[test]
+fn query_language() {
+    use crate::query::{Query, QueryError, Repetition, MAX_REPETITION};
+
+    let datastore = Datastore::open(DATASTORE_PATH).unwrap();
+    let primary = &datastore["primary"];
+    let sentences = datastore["s"].as_segmentation().unwrap();
+    let word = &primary["word"];
+    let pos = &primary["pos"];
+    let is_word = |p: usize, w: &str| word.get_string(p) == Some(w);
+
+    let query = Query::parse(r#""the" [pos="JJ"]* "man"%c within s"#).unwrap();
+    assert!(query.tokens[1].repetition == Repetition { min: 0, max: None });
+    assert!(query.tokens[2].repetition == Repetition::ONCE);
+    assert!(query.within.as_deref() == Some("s"));
+
+    // adjectives are never "man", so the longest match is the only one
+    let adjectives = Query::parse(r#""the" [pos="JJ"]* "man""#).unwrap().find(primary).unwrap();
+    let expected: Vec<_> = (0..primary.len())
+        .filter(|p| is_word(*p, "the"))
+        .filter_map(|p| {
+            let run = (p + 1..usize::min(p + 1 + MAX_REPETITION, primary.len()))
+                .take_while(|q| pos.get_string(*q) == Some("JJ"))
+                .count();
+            is_word(p + 1 + run, "man").then_some((p, p + 2 + run))
+        })
+        .collect();
+    assert!(!expected.is_empty() && expected.iter().any(|(s, e)| e - s > 2));
+    assert!(adjectives == expected);
+
+    let bounded = Query::parse(r#"[pos="JJ"]{2} "man""#).unwrap().find(primary).unwrap();
+    assert!(bounded.iter().map(|(s, _)| *s).eq((0..primary.len() - 2)
+        .filter(|p| pos.get_string(*p) == Some("JJ") && pos.get_string(p + 1) == Some("JJ") && is_word(p + 2, "man"))));
+    assert!(bounded.iter().all(|(s, e)| e - s == 3));
+
+    let folded = Query::parse(r#""the"%c"#).unwrap().find(primary).unwrap();
+    assert!(folded.iter().map(|(s, _)| *s).eq((0..primary.len()).filter(|p| word.get_string(*p).unwrap().to_lowercase() == "the")));
+    assert!(folded.len() > Query::parse(r#""the""#).unwrap().find(primary).unwrap().len());
+
+    // the longest match ends at the last "man" of the sentence
+    let within = Query::parse(r#""the" []* "man" within s"#).unwrap().find_in(&datastore, "primary").unwrap();
+    let expected: Vec<_> = (0..primary.len())
+        .filter(|p| is_word(*p, "the"))
+        .filter_map(|p| {
+            let (_, end) = sentences.get(sentences.find_containing(p)?)?;
+            (p + 1..end).rev().find(|q| is_word(*q, "man")).map(|q| (p, q + 1))
+        })
+        .collect();
+    assert!(!expected.is_empty());
+    assert!(within == expected);
+
+    assert!(matches!(Query::parse(r#""the" within s"#).unwrap().find(primary), Err(QueryError::UnknownLayer(_))));
+    assert!(matches!(Query::parse(r#""the" within nope"#).unwrap().find_in(&datastore, "primary"), Err(QueryError::UnknownLayer(_))));
+    assert!(matches!(Query::parse(r#""the" within primary"#).unwrap().find_in(&datastore, "primary"), Err(QueryError::UnsupportedLayer(_))));
+
+    for invalid in [r#""a"{3,2}"#, r#""a"{0}"#, r#""a"{}"#, r#""a"{2"#, r#""a"? []*"#, r#""a"%x"#, r#""a" within"#, r#""a" within s "b""#] {
+        assert!(matches!(Query::parse(invalid), Err(QueryError::Syntax { .. })));
+    }
+    assert!(Query::parse(r#""a"{,2} "b"{1,} "c"{3}"#).unwrap().tokens.iter().map(|t| t.repetition).eq([
+        Repetition { min: 0, max: Some(2) },
+        Repetition { min: 1, max: None },
+        Repetition { min: 3, max: Some(3) },
+    ]));
+}
+
+#[test]
+fn query_pagination() {
+    use crate::pagination::{Cursor, PaginationError, Paginator};
+    use crate::query::Query;
+    use crate::query_cache::QueryCache;
+
+    let datastore = Datastore::open(DATASTORE_PATH).unwrap();
+
+    // the canonical form of a query parses to the same query
+    for expr in [r#""the" [pos="JJ" & word!="o\"ld"%c]{1,3} "man"? within s"#, r#"[]+ [lemma="go"]{2,} []{0,4} "x"*"#] {
+        let query = Query::parse(expr).unwrap();
+        assert!(Query::parse(&query.to_string()).unwrap() == query);
+    }
+
+    let expr = r#""the" [pos="JJ"]+ within s"#;
+    let expected = Query::parse(expr).unwrap().find_in(&datastore, "primary").unwrap();
+
+    let dir = tempfile::tempdir().unwrap();
+    let paginator = Paginator::new(&datastore).cache(QueryCache::new(dir.path()).unwrap());
+    let mut page = paginator.first(expr, "primary", 7).unwrap();
+    let mut matches = page.matches.clone();
+    while let Some(cursor) = page.next {
+        let token = cursor.to_token();
+        assert!(token.bytes().all(|b| b.is_ascii_hexdigit()));
+        page = paginator.next(&Cursor::from_token(&token).unwrap()).unwrap();
+        assert!(page.offset == matches.len() && page.total == expected.len());
+        assert!(!page.matches.is_empty() && page.matches.len() <= 7);
+        matches.extend(page.matches.iter().copied());
+    }
+    assert!(matches == expected);
+    // all pages were read from a single cache entry
+    assert!(std::fs::read_dir(dir.path()).unwrap().count() == 1);
+
+    let page = paginator.at(expr, "primary", 10, 5).unwrap();
+    assert!(page.offset == 10 && page.matches == expected[10..15]);
+    assert!(paginator.at(expr, "primary", expected.len() + 1, 5).unwrap().matches.is_empty());
+
+    let mut cursor = paginator.first(expr, "primary", 5).unwrap().next.unwrap();
+    cursor.uuid = uuid::Uuid::new_v4();
+    assert!(matches!(paginator.next(&cursor), Err(PaginationError::StaleCursor)));
+    assert!(matches!(Cursor::from_token("7b7a"), Err(PaginationError::InvalidCursor)));
+    assert!(matches!(Cursor::from_token("xyz"), Err(PaginationError::InvalidCursor)));
+    assert!(matches!(paginator.first(expr, "primary", 0), Err(PaginationError::InvalidPageSize)));
+    assert!(matches!(paginator.first("[", "primary", 5), Err(PaginationError::Query(_))));
+}
+
+#[test]
+fn server_api() {
+    use serde_json::Value;
+    use crate::query::Query;
+    use crate::server::Api;
+
+    let datastore = Datastore::open(DATASTORE_PATH).unwrap();
+    let api = Api::new(&datastore).max_limit(1000);
+    let get = |url: &str| {
+        let response = api.handle(url);
+        (response.status, serde_json::from_str::<Value>(&response.body).unwrap())
+    };
+
+    let (status, layers) = get("/layers");
+    assert!(status == 200);
+    let layers = layers.as_array().unwrap();
+    assert!(layers.len() == datastore.layer_names().len());
+    let s = layers.iter().find(|l| l["name"] == "s").unwrap();
+    assert!(s["type"] == "segmentation" && s["base"] == "primary");
+    assert!(s["len"] == datastore["s"].len());
+
+    let expected = Query::parse(r#""the" [pos="JJ"]"#).unwrap().find(&datastore["primary"]).unwrap();
+    let (status, page) = get("/query?q=%22the%22+%5Bpos%3D%22JJ%22%5D&offset=10&limit=5");
+    assert!(status == 200);
+    assert!(page["total"] == expected.len() && page["offset"] == 10);
+    let results: Vec<(usize, usize)> = serde_json::from_value(page["results"].clone()).unwrap();
+    assert!(results == expected[10..15]);
+
+    // the cursor continues after the page
+    let (status, next) = get(&format!("/query?cursor={}", page["next"].as_str().unwrap()));
+    assert!(status == 200 && next["offset"] == 15);
+    let results: Vec<(usize, usize)> = serde_json::from_value(next["results"].clone()).unwrap();
+    assert!(results == expected[15..20]);
+    assert!(get("/query?cursor=00").0 == 400);
+
+    let (status, page) = get("/concordance?q=%22man%22&context=2&meta=s.id&limit=3");
+    assert!(status == 200);
+    let line = &page["results"][0];
+    assert!(line["keyword"][0] == "man" && line["left"].as_array().unwrap().len() == 2);
+    assert!(line["metadata"][0][0] == "id");
+
+    let (status, list) = get("/frequency?variable=pos&top=3");
+    assert!(status == 200);
+    assert!(list["total"] == datastore["primary"].len());
+    assert!(list["entries"].as_array().unwrap().len() == 3);
+    let counts: Vec<u64> = list["entries"].as_array().unwrap().iter().map(|e| e["count"].as_u64().unwrap()).collect();
+    assert!(counts.windows(2).all(|w| w[0] >= w[1]));
+
+    assert!(get("/nope").0 == 404);
+    assert!(get("/query?q=%22the%22&layer=nope").0 == 404);
+    assert!(get("/query").0 == 400);
+    assert!(get("/query?q=%5B").0 == 400);
+    assert!(get("/query?q=%22the%22&limit=1001").0 == 400);
+    assert!(get("/query?q=%2").0 == 400);
+    assert!(get("/frequency?variable=nope").0 == 404);
+    assert!(get("/frequency?variable=pos&where=nope").0 == 400);
+}
+
+#[test]
+fn virtual_variables() {
+    use std::collections::HashMap;
+    use crate::query::Query;
+    use crate::variables::VirtualVariable;
+
+    let mut datastore = Datastore::open(DATASTORE_PATH).unwrap();
+    let primary = datastore.layer_by_name_mut("primary").unwrap();
+
+    let table: HashMap<String, String> = ["NN", "NNS", "NP", "NPS"].iter()
+        .map(|t| (t.to_string(), "N".to_owned()))
+        .collect();
+    primary.add_virtual_variable("cpos".to_owned(), VirtualVariable::from_table("pos", table)).unwrap();
+    let tagged = VirtualVariable::combine(&["word", "pos"], |values| Some(format!("{}/{}", values[0], values[1])));
+    primary.add_virtual_variable("tagged".to_owned(), tagged).unwrap();
+
+    // names are shared with the stored variables and sources have to exist
+    assert!(primary.add_virtual_variable("word".to_owned(), VirtualVariable::from_fn("pos", |s| Some(s.to_owned()))).is_err());
+    assert!(primary.add_virtual_variable("x".to_owned(), VirtualVariable::from_fn("nope", |s| Some(s.to_owned()))).is_err());
+
+    let primary = &datastore["primary"];
+    let pos = &primary["pos"];
+    let cpos = primary.virtual_variable_by_name("cpos").unwrap();
+    assert!(cpos.sources().eq(["pos"]));
+    assert!((0..1000).all(|p| (cpos.get(primary, p).as_deref() == Some("N")) == pos.get_string(p).unwrap().starts_with('N')));
+    assert!(primary.virtual_variable_by_name("tagged").unwrap().get(primary, 0) == Some(format!("{}/{}", primary["word"].get_string(0).unwrap(), pos.get_string(0).unwrap())));
+    assert!(cpos.get(primary, primary.len()).is_none());
+
+    // indexed sources are matched on their lexicon, others at each position
+    let mapped = Query::parse(r#"[cpos="N"] "of""#).unwrap().find(primary).unwrap();
+    let stored = Query::parse(r#"[pos="NN|NNS|NP|NPS"] "of""#).unwrap().find(primary).unwrap();
+    assert!(!mapped.is_empty() && mapped == stored);
+    let combined = Query::parse(r#"[tagged="man/NN"]"#).unwrap().find(primary).unwrap();
+    assert!(combined == Query::parse(r#"[word="man" & pos="NN"]"#).unwrap().find(primary).unwrap());
+}
+
+#[test]
+fn materialize_virtual_variables() {
+    use std::collections::HashMap;
+    use crate::materialize::{materialize, MaterializeError};
+    use crate::variables::VirtualVariable;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("store");
+    Datastore::open(DATASTORE_PATH).unwrap().snapshot_to(&path).unwrap();
+
+    let mut datastore = Datastore::open(&path).unwrap();
+    let primary = datastore.layer_by_name_mut("primary").unwrap();
+    let cpos = VirtualVariable::from_fn("pos", |s| s.get(..1).map(str::to_owned));
+    primary.add_virtual_variable("cpos".to_owned(), cpos).unwrap();
+    let table = HashMap::from([("NN".to_owned(), "noun".to_owned()), ("NNS".to_owned(), "noun".to_owned())]);
+    primary.add_virtual_variable("noun".to_owned(), VirtualVariable::from_table("pos", table)).unwrap();
+
+    assert!(materialize(&datastore, "primary", "cpos").unwrap() == path.join("cpos.zigv"));
+    assert!(materialize(&datastore, "primary", "noun").is_ok());
+    assert!(matches!(materialize(&datastore, "primary", "cpos"), Err(MaterializeError::Datastore(_))));
+    assert!(matches!(materialize(&datastore, "primary", "word"), Err(MaterializeError::UnknownVariable(_))));
+    assert!(matches!(materialize(&datastore, "nope", "cpos"), Err(MaterializeError::UnknownLayer(_))));
+
+    // the manifest of the snapshot lists the new containers
+    let reopened = Datastore::open(&path).unwrap();
+    let primary = &datastore["primary"];
+    let stored = &reopened["primary"];
+    let cpos = primary.virtual_variable_by_name("cpos").unwrap();
+    let noun = primary.virtual_variable_by_name("noun").unwrap();
+    assert!(stored["cpos"].as_indexed_string().is_some() && stored["noun"].as_sparse().is_some());
+    assert!((0..primary.len()).step_by(13).all(|p| stored["cpos"].get_string(p).map(str::to_owned) == cpos.get(primary, p)));
+    assert!((0..primary.len()).step_by(13).all(|p| stored["noun"].get_string(p).map(str::to_owned) == noun.get(primary, p)));
+}
+
+#[test]
+fn datastore_locking() {
+    use std::io::Write;
+    use crate::lock::{DatastoreLock, LockMode, PendingFile};
+    use crate::materialize::{materialize, MaterializeError};
+    use crate::variables::VirtualVariable;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("store");
+    Datastore::open(DATASTORE_PATH).unwrap().snapshot_to(&path).unwrap();
+    let containers = crate::find_containers(&path).unwrap().len();
+
+    let mut writer = Datastore::open(&path).unwrap();
+    let cpos = VirtualVariable::from_fn("pos", |s| s.get(..1).map(str::to_owned));
+    writer.layer_by_name_mut("primary").unwrap().add_virtual_variable("cpos".to_owned(), cpos).unwrap();
+    assert!(!writer.is_read_only());
+
+    // readers hold a shared lock, so the datastore can't be written meanwhile
+    let reader = Datastore::open_read_only(&path).unwrap();
+    assert!(reader.is_read_only());
+    assert!(matches!(reader.lock_for_writing(), Err(DatastoreError::ReadOnly)));
+    assert!(matches!(writer.lock_for_writing(), Err(DatastoreError::Locked)));
+    assert!(matches!(materialize(&writer, "primary", "cpos"), Err(MaterializeError::Datastore(DatastoreError::Locked))));
+    assert!(DatastoreLock::try_acquire(&path, LockMode::Shared).unwrap().is_some());
+    drop(reader);
+
+    let lock = writer.lock_for_writing().unwrap();
+    assert!(lock.mode() == LockMode::Exclusive);
+    assert!(DatastoreLock::try_acquire(&path, LockMode::Shared).unwrap().is_none());
+    drop(lock);
+    assert!(materialize(&writer, "primary", "cpos").is_ok());
+
+    // pending files are invisible until committed and removed if they never are
+    let pending = PendingFile::create(path.join("a.zigv")).unwrap();
+    pending.file().unwrap().write_all(b"partial").unwrap();
+    assert!(crate::find_containers(&path).unwrap().len() == containers + 1);
+    drop(pending);
+    assert!(std::fs::read_dir(&path).unwrap().all(|e| !e.unwrap().file_name().to_string_lossy().starts_with("a.zigv")));
+
+    let pending = PendingFile::create(path.join("b.bin")).unwrap();
+    pending.file().unwrap().write_all(b"complete").unwrap();
+    assert!(pending.commit().unwrap() == path.join("b.bin"));
+    assert!(std::fs::read(path.join("b.bin")).unwrap() == b"complete");
+    assert!(PendingFile::create(path.join("b.bin")).is_err());
+}
+
+#[test]
+fn atomic_encoding() {
+    use std::io::Write;
+    use crate::lock::{self, PendingFile};
+    use crate::variables::IndexedStringVariable;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("store");
+    Datastore::open(DATASTORE_PATH).unwrap().snapshot_to(&path).unwrap();
+    // scan the directory on open to see new containers
+    std::fs::remove_file(path.join(crate::manifest::MANIFEST_FILENAME)).unwrap();
+    let base = Datastore::open(&path).unwrap()["primary"].uuid();
+    let entries = || std::fs::read_dir(&path).unwrap().map(|e| e.unwrap().file_name().into_string().unwrap()).collect::<Vec<_>>();
+    let before = entries();
+
+    // failed encoders leave nothing behind
+    let strings = || ["a", "b"].into_iter().map(String::from);
+    let result = IndexedStringVariable::encode_to_file(PendingFile::create(path.join("short.zigv")).unwrap(), strings(), 5, "short".to_owned(), base, true, "");
+    assert!(matches!(result, Err(EncodeError::LengthMismatch { expected: 5, found: 2 })));
+    assert!(entries() == before);
+
+    // complete containers are renamed to their final name
+    let n = Datastore::open(&path).unwrap()["primary"].len();
+    let strings = (0..n).map(|i| (i % 3).to_string());
+    let variable = IndexedStringVariable::encode_to_file(PendingFile::create(path.join("mod.zigv")).unwrap(), strings, n, "mod".to_owned(), base, true, "").unwrap();
+    assert!(!entries().iter().any(|e| e.ends_with(lock::PENDING_EXTENSION)));
+    let datastore = Datastore::open(&path).unwrap();
+    assert!(datastore["primary"]["mod"].uuid() == Some(variable.uuid()));
+    drop(datastore);
+
+    // replacing a file keeps the old one until the new one is complete
+    let pending = PendingFile::replace(path.join("mod.zigv")).unwrap();
+    assert!(Datastore::open(&path).unwrap()["primary"]["mod"].uuid() == Some(variable.uuid()));
+    drop(pending);
+
+    // files of crashed writers are skipped but kept on open
+    std::fs::write(path.join("crashed.zigv.tmp"), b"partial").unwrap();
+    std::fs::write(path.join("s").join("crashed.zigl.tmp"), b"partial").unwrap();
+    std::fs::write(path.join("s").join("notes.tmp"), b"user file").unwrap();
+    let datastore = Datastore::open(&path).unwrap();
+    assert!(datastore.layer_by_name("crashed").is_none());
+    assert!(path.join("crashed.zigv.tmp").exists());
+
+    // files of running writers are locked, so they are neither replaced nor removed
+    let pending = PendingFile::create(path.join("slow.zigv")).unwrap();
+    pending.file().unwrap().write_all(b"partial").unwrap();
+    assert!(PendingFile::replace(path.join("slow.zigv")).unwrap_err().kind() == std::io::ErrorKind::WouldBlock);
+    assert!(lock::remove_stale(&path).unwrap() == 2);
+    assert!(!path.join("crashed.zigv.tmp").exists() && !path.join("s").join("crashed.zigl.tmp").exists());
+    assert!(std::fs::read(path.join("slow.zigv.tmp")).unwrap() == b"partial");
+    assert!(path.join("s").join("notes.tmp").exists());
+    drop(pending);
+    assert!(lock::remove_stale(&path).unwrap() == 0);
+}
+
+#[test]
+fn fallible_encoding() {
+    use std::sync::mpsc;
+    use crate::fallible::{try_encode, TryEncodeError};
+    use crate::lock::PendingFile;
+    use crate::variables::IndexedStringVariable;
+
+    let dir = tempfile::tempdir().unwrap();
+    let base = uuid::Uuid::new_v4();
+    let words = |n: usize| (0..n).map(|i| format!("w{}", i % 7));
+
+    // a producer blocked on a bounded channel
+    let (sender, receiver) = mpsc::sync_channel::<Result<String, String>>(2);
+    let producer = std::thread::spawn(move || {
+        for word in words(1000) {
+            sender.send(Ok(word)).unwrap();
+        }
+    });
+    let target = PendingFile::create(dir.path().join("word.zigv")).unwrap();
+    let variable = try_encode(target, receiver, |target, words| {
+        IndexedStringVariable::encode_to_file(target, words, 1000, "word".to_owned(), base, true, "")
+    }).unwrap();
+    producer.join().unwrap();
+    assert!(variable.iter().eq(words(1000)));
+    assert!(dir.path().join("word.zigv").exists());
+
+    // the first error of the input is returned and nothing is written
+    let failing = words(10).enumerate().map(|(i, w)| if i == 3 { Err("broken pipe") } else { Ok(w) });
+    let target = PendingFile::create(dir.path().join("failed.zigv")).unwrap();
+    let result = try_encode(target, failing, |target, words| {
+        IndexedStringVariable::encode_to_file(target, words, 10, "failed".to_owned(), base, true, "")
+    });
+    assert!(matches!(result, Err(TryEncodeError::Source("broken pipe"))));
+    assert!(std::fs::read_dir(dir.path()).unwrap().count() == 1);
+
+    // errors of the encoder are passed on
+    let result = try_encode(tempfile::tempfile().unwrap(), words(3).map(Ok::<_, ()>), |target, words| {
+        IndexedStringVariable::encode_to_file(target, words, 5, "short".to_owned(), base, true, "")
+    });
+    assert!(matches!(result, Err(TryEncodeError::Encode(EncodeError::LengthMismatch { expected: 5, found: 3 }))));
+}
+
+#[test]
+fn frequency_lists() {
+    use crate::frequency::{intersect_ranges, FrequencyError, FrequencyList, MetadataFilter};
+    use crate::VariableValue;
+
+    let datastore = Datastore::open(DATASTORE_PATH).unwrap();
+    let primary = &datastore["primary"];
+    let pos = &primary["pos"];
+
+    let all = FrequencyList::new(pos).unwrap();
+    assert!(all.total() == primary.len());
+    assert!(all.entries().windows(2).all(|w| w[0].1 >= w[1].1));
+    assert!(all.entries().iter().map(|(_, n)| n).sum::<usize>() == primary.len());
+    assert!(all.top(3).len() == 3);
+
+    // counting from positions gives the same list as the stored type frequencies
+    let scanned = FrequencyList::from_positions(pos, 0..primary.len()).unwrap();
+    assert!(scanned.get(&VariableValue::String("NN")) == all.get(&VariableValue::String("NN")));
+    assert!(scanned.len() == all.len());
+
+    let filter = MetadataFilter::parse("novel.title=Oliver Twist").unwrap();
+    let ranges = filter.ranges(&datastore, primary).unwrap();
+    let novels = datastore["novel"].as_segmentation().unwrap();
+    let oliver = (0..novels.len()).find(|i| novels["title"].get_string(*i) == Some("Oliver Twist")).unwrap();
+    assert!(ranges == [novels.get(oliver).unwrap()]);
+
+    let (start, end) = ranges[0];
+    let filtered = FrequencyList::from_positions(pos, start..end).unwrap();
+    assert!(filtered.total() == end - start);
+
+    let grouped = FrequencyList::grouped(pos, novels, "title", 0..primary.len()).unwrap();
+    assert!(grouped.len() == novels.len());
+    let (_, list) = grouped.iter().find(|(g, _)| *g == VariableValue::String("Oliver Twist")).unwrap();
+    assert!(list.entries() == filtered.entries());
+
+    // filters on the counted layer select single segments
+    let negated = MetadataFilter::parse("novel.title!=Oliver Twist").unwrap();
+    let others = negated.ranges(&datastore, &datastore["novel"]).unwrap();
+    assert!(others.len() == novels.len() - 1 && !others.contains(&(oliver, oliver + 1)));
+
+    assert!(intersect_ranges(&[(0, 10), (20, 30)], &[(5, 25)]) == [(5, 10), (20, 25)]);
+    assert!(matches!(MetadataFilter::parse("title=x"), Err(FrequencyError::InvalidFilter(_))));
+    assert!(matches!(filter.ranges(&datastore, &datastore["chapter"]), Err(FrequencyError::UnrelatedLayers { .. })));
+}
+
+#[test]
+fn segment_counts() {
+    use crate::frequency::{counts_per_segment, FrequencyError, FrequencyList};
+    use crate::variables::VariableValue;
+
+    let datastore = Datastore::open(DATASTORE_PATH).unwrap();
+    let pos = datastore["primary"]["pos"].as_indexed_string().unwrap();
+    let novels = datastore["novel"].as_segmentation().unwrap();
+    let chapters = datastore["chapter"].as_segmentation().unwrap();
+
+    let tags = ["NN", "JJ", "VBD", "DT"];
+    let type_ids: Vec<usize> = tags.iter().map(|t| pos.type_id(t).unwrap()).collect();
+    let counts = counts_per_segment(pos, novels, &type_ids).unwrap();
+    assert!(counts.n_segments() == novels.len());
+    assert!(counts.type_ids() == type_ids);
+
+    for (segment, row) in counts.rows().enumerate() {
+        let (start, end) = novels.get(segment).unwrap();
+        let list = FrequencyList::from_positions(&datastore["primary"]["pos"], start..end).unwrap();
+        for (column, tag) in tags.iter().enumerate() {
+            assert!(row[column] == list.get(&VariableValue::String(tag)));
+            assert!(counts.get(segment, column) == Some(row[column]));
+        }
+    }
+    assert!(counts.get(novels.len(), 0).is_none());
+    assert!(counts.get(0, tags.len()).is_none());
+
+    // novels cover the whole corpus, so the totals are the type frequencies
+    let totals = counts.column_totals();
+    for (column, &tid) in type_ids.iter().enumerate() {
+        assert!(totals[column] == pos.frequency(tid).unwrap());
+    }
+
+    // segments with gaps between them
+    let by_chapter = counts_per_segment(pos, chapters, &type_ids[..1]).unwrap();
+    let expected: Vec<usize> = chapters.iter()
+        .map(|(start, end)| (start..end).filter(|p| pos.get_id(*p) == Some(type_ids[0])).count())
+        .collect();
+    assert!(by_chapter.as_slice() == expected);
+
+    let empty = counts_per_segment(pos, novels, &[]).unwrap();
+    assert!(empty.rows().all(|row| row.is_empty()) && empty.n_segments() == novels.len());
+
+    let unrelated = SegmentationLayer::encode_to_file(std::io::Cursor::new(Vec::new()), [(0, 10)].into_iter(), 1, "s".to_owned(), uuid::Uuid::new_v4(), false, "").unwrap();
+    assert!(matches!(counts_per_segment(pos, &unrelated, &type_ids), Err(FrequencyError::UnrelatedLayers { .. })));
+}
+
+#[test]
+fn document_term_matrix() {
+    use crate::dtm::DocumentTermMatrix;
+    use crate::frequency::counts_per_segment;
+
+    let datastore = Datastore::open(DATASTORE_PATH).unwrap();
+    let pos = datastore["primary"]["pos"].as_indexed_string().unwrap();
+    let chapters = datastore["chapter"].as_segmentation().unwrap();
+
+    let all = DocumentTermMatrix::new(pos, chapters, 0).unwrap();
+    assert!(all.n_segments() == chapters.len());
+    assert!(all.n_terms() == pos.n_types());
+
+    // the dense counts of all types, without the zeros
+    let dense = counts_per_segment(pos, chapters, all.type_ids()).unwrap();
+    let expected: Vec<(usize, usize, usize)> = dense.rows()
+        .enumerate()
+        .flat_map(|(segment, row)| row.iter().enumerate().filter(|(_, n)| **n > 0).map(move |(column, n)| (segment, column, *n)))
+        .collect();
+    assert!(all.entries() == expected);
+
+    let min_frequency = 1000;
+    let pruned = DocumentTermMatrix::new(pos, chapters, min_frequency).unwrap();
+    assert!(pruned.n_terms() < all.n_terms());
+    assert!(pruned.type_ids().iter().all(|t| pos.frequency(*t).unwrap() >= min_frequency));
+    assert!(pruned.type_ids().windows(2).all(|w| w[0] < w[1]));
+    let kept = |(segment, column, n): &(usize, usize, usize)| pruned.type_ids().binary_search(&all.type_ids()[*column])
+        .ok()
+        .map(|c| (*segment, c, *n));
+    assert!(pruned.entries() == all.entries().iter().filter_map(kept).collect::<Vec<_>>());
+
+    let mut mtx = Vec::new();
+    pruned.write_matrix_market(&mut mtx).unwrap();
+    let mtx = String::from_utf8(mtx).unwrap();
+    let mut lines = mtx.lines().filter(|l| !l.starts_with('%'));
+    assert!(mtx.starts_with("%%MatrixMarket matrix coordinate integer general\n"));
+    assert!(lines.next() == Some(format!("{} {} {}", chapters.len(), pruned.n_terms(), pruned.entries().len()).as_str()));
+    let (segment, column, n) = pruned.entries()[0];
+    assert!(lines.next() == Some(format!("{} {} {}", segment + 1, column + 1, n).as_str()));
+    assert!(lines.count() == pruned.entries().len() - 1);
+
+    let mut terms = Vec::new();
+    pruned.write_terms(pos, &mut terms).unwrap();
+    let terms = String::from_utf8(terms).unwrap();
+    assert!(terms.lines().eq(pruned.type_ids().iter().map(|t| pos.lexicon().get(*t).unwrap())));
+
+    #[cfg(feature = "arrow")]
+    {
+        use arrow_array::{Array, UInt64Array};
+
+        let table = crate::export::document_term_table(&pruned).unwrap();
+        assert!(table.num_rows() == pruned.entries().len());
+        let ids = table.column_by_name("id").unwrap().as_any().downcast_ref::<UInt64Array>().unwrap();
+        assert!(ids.value(0) as usize == pruned.type_ids()[column]);
+    }
+}
+
+#[test]
+fn cooccurrence_counts() {
+    use std::collections::HashMap;
+    use std::io::Cursor;
+    use crate::container::{encode_in_memory, Container, ContainerBuilder};
+    use crate::cooccurrence::{Context, CooccurrenceBuilder, CooccurrenceMatrix};
+    use crate::layers::PrimaryLayer;
+    use crate::variables::IndexedStringVariable;
+
+    let datastore = Datastore::open(DATASTORE_PATH).unwrap();
+    let n = 20_000;
+    let words = datastore["primary"]["word"].as_indexed_string().unwrap().iter().take(n).map(str::to_owned);
+    let sentences: Vec<(usize, usize)> = datastore["s"].as_segmentation().unwrap()
+        .iter()
+        .take_while(|(_, end)| *end <= n)
+        .collect();
+
+    let primary = PrimaryLayer::encode_to_file(Cursor::new(Vec::new()), n, "primary".to_owned(), "").unwrap();
+    let var = IndexedStringVariable::encode_to_file(Cursor::new(Vec::new()), words, n, "word".to_owned(), primary.uuid(), true, "").unwrap();
+    let s = SegmentationLayer::encode_to_file(Cursor::new(Vec::new()), sentences.iter().copied(), sentences.len(), "s".to_owned(), primary.uuid(), true, "").unwrap();
+    let ids: Vec<usize> = (0..n).map(|p| var.get_id(p).unwrap()).collect();
+
+    let brute_force = |pairs: &mut dyn Iterator<Item = (usize, usize)>| {
+        let mut counts: HashMap<(usize, usize), usize> = HashMap::new();
+        for (i, j) in pairs {
+            *counts.entry((ids[i], ids[j])).or_default() += 1;
+            *counts.entry((ids[j], ids[i])).or_default() += 1;
+        }
+        let mut counts: Vec<(usize, usize, usize)> = counts.into_iter().map(|((a, b), c)| (a, b, c)).collect();
+        counts.sort_unstable();
+        counts
+    };
+
+    let window = brute_force(&mut (0..n).flat_map(|i| (i + 1..(i + 4).min(n)).map(move |j| (i, j))));
+    let within = brute_force(&mut sentences.iter().flat_map(|&(start, end)| (start..end).flat_map(move |i| (i + 1..end).map(move |j| (i, j)))));
+
+    for (context, expected) in [(Context::Window(3), &window), (Context::Segment(&s), &within)] {
+        let mut builder = CooccurrenceBuilder::new(&var, context);
+        let matrix = builder.build().unwrap();
+        assert!(matrix.iter().eq(expected.iter().copied()));
+
+        // a budget of a few hundred distinct pairs spills many runs
+        builder.set_memory_budget(10_000);
+        assert!(builder.build().unwrap() == matrix);
+
+        let (a, b, count) = expected[expected.len() / 2];
+        assert!(matrix.get(a, b) == count && matrix.get(b, a) == count);
+        assert!(matrix.row(a).eq(expected.iter().filter(|e| e.0 == a).map(|e| (e.1, e.2))));
+        assert!(matrix.total() == expected.iter().map(|e| e.2).sum::<usize>());
+    }
+
+    let matrix = CooccurrenceBuilder::new(&var, Context::Window(3)).build().unwrap();
+    let bytes = encode_in_memory(|cursor| {
+        ContainerBuilder::new_into_file("cooc".to_owned(), cursor, 1)
+            .edit_header(|h| {
+                h.ziggurat_type(crate::container::Type::IntegerVariable)
+                    .dim1(n)
+                    .base1(Some(primary.uuid()));
+            })
+            .add_component("Cooccur", crate::components::Type::Index, |bom_entry, file| unsafe {
+                matrix.write_component(file, bom_entry, bom_entry.offset as u64)
+            })
+            .build()
+    }).unwrap();
+    let container = Container::from_bytes(&bytes, "cooc".to_owned()).unwrap();
+    let index = container.get_component("Cooccur").unwrap().into_index().unwrap();
+    assert!(CooccurrenceMatrix::from_index(index) == Some(matrix));
+
+    let unrelated = SegmentationLayer::encode_to_file(Cursor::new(Vec::new()), [(0, 10)].into_iter(), 1, "s".to_owned(), uuid::Uuid::new_v4(), false, "").unwrap();
+    assert!(CooccurrenceBuilder::new(&var, Context::Segment(&unrelated)).build().is_err());
+}
+
+#[test]
+fn encoder_progress() {
+    use std::io::Cursor;
+    use crate::progress::Progress;
+    use crate::variables::PlainStringVariable;
+
+    let strings = ["alpha", "beta", "gamma", "beta"];
+    let progress = Progress::new();
+
+    let mut bytes = Vec::new();
+    let target = progress.target(Cursor::new(&mut bytes));
+    let input = progress.counted(strings.iter().map(|s| s.to_string()));
+    let var = PlainStringVariable::encode_to_file(target, input, 4, "plain".to_owned(), uuid::Uuid::new_v4(), true, "").unwrap();
+
+    assert!(var.get(2) == Some("gamma"));
+    assert!(progress.items() == 4);
+    assert!(progress.bytes() > 0 && progress.bytes() <= bytes.len() as u64);
+
+    // clones share their counters
+    progress.clone().add_items(6);
+    assert!(progress.items() == 10);
+}
+
+#[test]
+fn datastore_comparison() {
+    use crate::compare::{log_likelihood, DatastoreDiff, LexiconDiff};
+
+    let a = Datastore::open(DATASTORE_PATH).unwrap();
+    let b = Datastore::open(DATASTORE_PATH).unwrap();
+
+    let diff = DatastoreDiff::new(&a, &b);
+    assert!(diff.is_identical());
+    assert!(diff.layers.len() == a.layer_names().len());
+
+    let words = LexiconDiff::new(&a["primary"]["word"], &b["primary"]["word"]).unwrap();
+    assert!(words.is_identical());
+    assert!(words.shared == words.types_a && words.types_a == words.types_b);
+    assert!((words.correlation - 1.0).abs() < 1e-9);
+    assert!(words.only_a().next().is_none());
+
+    let mixed = LexiconDiff::new(&a["primary"]["word"], &b["primary"]["lemma"]).unwrap();
+    assert!(!mixed.is_identical());
+    assert!(mixed.tokens_a == mixed.tokens_b);
+    assert!(mixed.keyness.windows(2).all(|w| w[0].log_likelihood.abs() >= w[1].log_likelihood.abs()));
+    assert!(mixed.only_a().all(|k| k.count_b == 0 && k.log_likelihood > 0.0));
+
+    assert!(log_likelihood(10, 10, 100, 100) == 0.0);
+    assert!(log_likelihood(1, 20, 100, 100) < 0.0);
+}
+
+#[test]
+fn text_import() {
+    use crate::import::{ImportError, Tokenizer, UnicodeTokenizer, WhitespaceTokenizer};
+
+    let tokenizer = UnicodeTokenizer;
+    assert!(tokenizer.words("Mr. Smith didn't pay 3,500 £, did he?") == ["Mr", ".", "Smith", "didn't", "pay", "3,500", "£", ",", "did", "he", "?"]);
+    assert!(tokenizer.sentences("He said: \"Go!\" Then he left. 東京へ行く。") == [
+        vec!["He", "said", ":", "\"", "Go", "!", "\""],
+        vec!["Then", "he", "left", "."],
+        vec!["東", "京", "へ", "行", "く", "。"],
+    ]);
+    assert!(WhitespaceTokenizer.sentences("a b\n\n c ") == [vec!["a", "b"], vec![], vec!["c"]]);
+
+    let dir = tempfile::tempdir().unwrap();
+    let texts = ["First text. Two sentences!", "Second text"];
+    let datastore = Datastore::from_text(dir.path().join("unicode"), texts, &tokenizer).unwrap();
+    let words = datastore["primary"]["word"].as_indexed_string().unwrap();
+    assert!(words.iter().eq(["First", "text", ".", "Two", "sentences", "!", "Second", "text"]));
+    assert!(words.frequency(words.type_id("text").unwrap()) == Some(2));
+    let sentences = datastore["s"].as_segmentation().unwrap();
+    assert!(sentences.iter().eq([(0, 3), (3, 6), (6, 8)]));
+
+    // one sentence per text, split at commas
+    let by_commas = |text: &str| vec![text.split(',').map(str::to_owned).collect()];
+    let datastore = Datastore::from_text(dir.path().join("closure"), ["a,b", "c"], &by_commas).unwrap();
+    assert!(datastore["primary"]["word"].as_indexed_string().unwrap().iter().eq(["a", "b", "c"]));
+    assert!(datastore["s"].as_segmentation().unwrap().iter().eq([(0, 2), (2, 3)]));
+
+    let datastore = Datastore::from_text(dir.path().join("whitespace"), ["a b\n\nc"], &WhitespaceTokenizer).unwrap();
+    assert!(datastore["s"].as_segmentation().unwrap().iter().eq([(0, 2), (2, 3)]));
+
+    assert!(matches!(Datastore::from_text(dir.path().join("empty"), [" \n "], &tokenizer), Err(ImportError::Empty)));
+    assert!(matches!(Datastore::from_text(dir.path().join("unicode"), texts, &tokenizer), Err(ImportError::Io(_))));
+}
+
+#[test]
+fn resumable_builds() {
+    use crate::build::{Build, BuildError, BuildManifest};
+    use crate::import::{resume_import_text, ImportError, UnicodeTokenizer};
+    use crate::layers::PrimaryLayer;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("novel");
+    let texts = ["It was the best of times. It was the worst of times."];
+    let datastore = Datastore::from_text(&path, texts, &UnicodeTokenizer).unwrap();
+    let (primary, word) = (datastore["primary"].uuid(), datastore["primary"]["word"].uuid());
+    drop(datastore);
+    let manifest = BuildManifest::read(&path).unwrap().unwrap();
+    assert!(manifest.containers.iter().map(|c| c.name.as_str()).eq(["primary", "word", "s"]));
+
+    // a crash before the last container: the others are kept
+    std::fs::remove_file(path.join("s").join("s.zigl")).unwrap();
+    let datastore = resume_import_text(&path, texts, &UnicodeTokenizer).unwrap();
+    assert!(datastore["primary"].uuid() == primary && datastore["primary"]["word"].uuid() == word);
+    assert!(datastore["s"].as_segmentation().unwrap().len() == 2);
+    drop(datastore);
+
+    // a changed container is encoded again, and so is everything after it
+    let mut bytes = std::fs::read(path.join("word.zigv")).unwrap();
+    bytes.push(0);
+    std::fs::write(path.join("word.zigv"), bytes).unwrap();
+    let datastore = resume_import_text(&path, texts, &UnicodeTokenizer).unwrap();
+    assert!(datastore["primary"].uuid() == primary && datastore["primary"]["word"].uuid() != word);
+    drop(datastore);
+
+    let other = ["It was the age of wisdom."];
+    assert!(matches!(resume_import_text(&path, other, &UnicodeTokenizer), Err(ImportError::Build(BuildError::InputChanged))));
+
+    // containers are only skipped in the order they were built
+    let path = dir.path().join("layers");
+    let encode = |build: &mut Build, names: &[&str]| {
+        for name in names {
+            build.container(name, format!("{}.zigl", name), |file| PrimaryLayer::encode_to_file(file, 3, name.to_string(), "").map(|l| l.uuid())).unwrap();
+        }
+    };
+    encode(&mut Build::new(&path, 1).unwrap(), &["a", "b", "c"]);
+    let mut build = Build::resume(&path, 1).unwrap();
+    encode(&mut build, &["a", "c", "b"]);
+    assert!(build.skipped() == 1);
+    drop(build);
+    let mut build = Build::resume(&path, 1).unwrap();
+    encode(&mut build, &["a", "c", "b"]);
+    assert!(build.skipped() == 3);
+    drop(build);
+    let mut build = Build::new(&path, 1).unwrap();
+    encode(&mut build, &["a"]);
+    assert!(build.skipped() == 0);
+}
+
+#[test]
+fn vrt_export() {
+    use crate::vrt::{VrtError, VrtOptions};
+
+    let datastore = Datastore::open(DATASTORE_PATH).unwrap();
+    let options = VrtOptions {
+        columns: Some(vec!["word".to_owned(), "pos".to_owned()]),
+        structures: Some(vec!["chapter".to_owned(), "s".to_owned()]),
+        ..Default::default()
+    };
+
+    let mut vrt = Vec::new();
+    datastore.export_vrt(&mut vrt, &options).unwrap();
+    let vrt = String::from_utf8(vrt).unwrap();
+
+    let primary = &datastore["primary"];
+    let chapters = datastore["chapter"].as_segmentation().unwrap();
+    let (start, _) = chapters.get(0).unwrap();
+
+    let lines: Vec<&str> = vrt.lines().collect();
+    assert!(lines.iter().filter(|l| !l.starts_with('<')).count() == primary.len());
+    assert!(lines.iter().filter(|l| **l == "<s>").count() == datastore["s"].len());
+    assert!(lines.iter().filter(|l| l.starts_with("<chapter ")).count() == chapters.len());
+
+    // the first chapter tag encloses its first sentence and carries escaped attributes
+    let first = lines.iter().position(|l| l.starts_with("<chapter ")).unwrap();
+    let title = chapters["title"].get_string(0).unwrap().replace('&', "&amp;").replace('"', "&quot;");
+    assert!(lines[first].contains(&format!("title=\"{}\"", title)));
+    assert!(lines[first + 1] == "<s>");
+    let token = format!("{}\t{}", primary["word"].get_string(start).unwrap(), primary["pos"].get_string(start).unwrap());
+    assert!(lines[first + 2] == token);
+
+    // tags are properly nested
+    let mut stack = Vec::new();
+    for line in lines.iter().filter(|l| l.starts_with('<')) {
+        match line.strip_prefix("</") {
+            Some(name) => assert!(stack.pop() == Some(name.trim_end_matches('>'))),
+            None => stack.push(line[1..].split(|c| c == ' ' || c == '>').next().unwrap()),
+        }
+    }
+    assert!(stack.is_empty());
+
+    let options = VrtOptions { layer: "s".to_owned(), ..Default::default() };
+    assert!(matches!(datastore.export_vrt(std::io::sink(), &options), Err(VrtError::NotPrimary(_))));
+}
+
+#[test]
+fn lexicon_recoding() {
+    use std::io::Cursor;
+    use std::rc::Rc;
+    use crate::components::LexiconOrder;
+    use crate::federation::{MergedLexicon, RecodedIds};
+    use crate::variables::IndexedStringVariable;
+
+    let first: Vec<String> = "the cat saw the dog".split(' ').map(String::from).collect();
+    let second: Vec<String> = (0..40).map(|i| ["a", "dog", "barked"][i % 3].to_owned()).collect();
+    let base = uuid::Uuid::new_v4();
+
+    let (mut buf_a, mut buf_b) = (Vec::new(), Vec::new());
+    let a = IndexedStringVariable::encode_to_file(Cursor::new(&mut buf_a), first.clone().into_iter(), first.len(), "a".to_owned(), base, true, "").unwrap();
+    let b = IndexedStringVariable::encode_to_file(Cursor::new(&mut buf_b), second.clone().into_iter(), second.len(), "b".to_owned(), base, true, "").unwrap();
+
+    // in-memory view
+    let lexicon = Rc::new(MergedLexicon::from_variables(&[&a, &b]));
+    assert!(lexicon.len() == 6);
+    let recoded = RecodedIds::new(lexicon.clone(), 1, &b).unwrap();
+    assert!(recoded.len() == second.len());
+    let ids: Vec<usize> = recoded.iter_range(0, recoded.len()).unwrap().collect();
+    assert!(ids.iter().zip(second.iter()).all(|(&id, s)| lexicon.get(id) == Some(s.as_str())));
+    assert!(recoded.get(1) == lexicon.id("dog") && recoded.get(second.len()).is_none());
+    assert!(RecodedIds::new(lexicon, 0, &b).is_none());
+
+    // offline recoding into a new variable
+    let mut merged = Vec::new();
+    let merged = IndexedStringVariable::encode_merged(Cursor::new(&mut merged), &[&a, &b], "merged".to_owned(), base, true, LexiconOrder::Frequency, "").unwrap();
+    let all: Vec<&String> = first.iter().chain(second.iter()).collect();
+    assert!(merged.len() == all.len() && merged.n_types() == 6);
+    assert!((0..all.len()).all(|i| merged.get(i) == Some(all[i].as_str())));
+    assert!(merged.frequency(merged.type_id("dog").unwrap()) == Some(14));
+    assert!(merged.lexicon().get(0) == Some("dog"));
+    assert!(merged.inverted_index().positions(merged.type_id("the").unwrap()).unwrap().collect::<Vec<_>>() == [0, 3]);
+}
+
+#[test]
+fn derived_variable() {
+    use std::io::Cursor;
+    use crate::components::LexiconOrder;
+    use crate::variables::IndexedStringVariable;
+
+    let datastore = Datastore::open(DATASTORE_PATH).unwrap();
+    let words = datastore["primary"]["word"].as_indexed_string().unwrap();
+
+    let mut buffer = Vec::new();
+    let lower = IndexedStringVariable::encode_derived(Cursor::new(&mut buffer), words, str::to_lowercase, "lower".to_owned(), true, LexiconOrder::Frequency, "").unwrap();
+    assert!(lower.len() == words.len() && lower.base() == words.base());
+    assert!(lower.n_types() < words.n_types());
+    assert!((0..words.len()).step_by(97).all(|i| lower.get(i).unwrap() == words.get(i).unwrap().to_lowercase()));
+
+    let frequency = |var: &IndexedStringVariable, s: &str| var.type_id(s).and_then(|t| var.frequency(t)).unwrap_or(0);
+    assert!(frequency(&lower, "the") == frequency(words, "the") + frequency(words, "The") + frequency(words, "THE"));
+    assert!(lower.type_id("The").is_none());
+
+    // a crude stemmer merging plurals
+    let mut buffer = Vec::new();
+    let stem = |s: &str| s.strip_suffix('s').unwrap_or(s).to_owned();
+    let stemmed = IndexedStringVariable::encode_derived(Cursor::new(&mut buffer), words, stem, "stem".to_owned(), false, LexiconOrder::Frequency, "").unwrap();
+    assert!(frequency(&stemmed, "dog") == frequency(words, "dog") + frequency(words, "dogs"));
+    assert!(stemmed.inverted_index().positions(stemmed.type_id("dog").unwrap()).unwrap().count() == frequency(&stemmed, "dog"));
+
+    #[cfg(feature = "normalization")]
+    {
+        use crate::components::strip_diacritics;
+
+        assert!(strip_diacritics("Ångström") == "Angstrom");
+        assert!(strip_diacritics("ﬁancée") == "ﬁancee");
+    }
+}
+
+#[test]
+fn inverted_index_memory_budget() {
+    use std::io::Cursor;
+    use crate::components::{InvertedIndex, LexiconBuilder, LexiconOrder};
+    use crate::lock::PendingFile;
+    use crate::variables::IndexedStringVariable;
+
+    let words = ["a", "rose", "is", "a", "rose", "is", "a", "rose", "said", "Gertrude", "Stein"];
+    let tokens: Vec<&str> = (0..2000).map(|i| words[(i * 7 + i / 13) % words.len()]).collect();
+    let base = uuid::Uuid::new_v4();
+
+    let encode = |budget: Option<usize>, buffer: &mut Vec<u8>| {
+        let mut builder = LexiconBuilder::from_strings(tokens.iter().copied());
+        if let Some(budget) = budget {
+            builder.set_index_memory_budget(budget);
+        }
+        IndexedStringVariable::encode_lexicon_builder(Cursor::new(buffer), builder, "word".to_owned(), base, true, LexiconOrder::Frequency, "").unwrap();
+    };
+
+    let (mut full, mut small) = (Vec::new(), Vec::new());
+    encode(None, &mut full);
+    // the arrays of the 6 types take most of the budget, leaving room for a few lists per pass
+    encode(Some(6 * InvertedIndex::TYPE_BYTES + 256), &mut small);
+
+    // both encodings differ only in the random container UUID
+    assert!(full.len() == small.len());
+    let differing = full.iter().zip(small.iter()).filter(|(a, b)| a != b).count();
+    assert!(differing <= 16);
+
+    // one pass per type
+    let mut builder = LexiconBuilder::from_strings(tokens.iter().copied());
+    builder.set_index_memory_budget(1);
+    let var = IndexedStringVariable::encode_lexicon_builder(Cursor::new(Vec::new()), builder, "word".to_owned(), base, true, LexiconOrder::Frequency, "").unwrap();
+    for t in 0..var.n_types() {
+        let expected: Vec<usize> = (0..tokens.len()).filter(|&i| var.get_id(i) == Some(t)).collect();
+        assert!(var.inverted_index().positions(t).unwrap().collect::<Vec<_>>() == expected);
+    }
+
+    // an uncompressed ID stream is read back from the file as well
+    let dir = tempfile::tempdir().unwrap();
+    let mut builder = LexiconBuilder::from_strings(tokens.iter().copied());
+    builder.set_index_memory_budget(1);
+    let file = PendingFile::create(dir.path().join("word.zigv")).unwrap();
+    let uncompressed = IndexedStringVariable::encode_lexicon_builder(file, builder, "word".to_owned(), base, false, LexiconOrder::Frequency, "").unwrap();
+    for t in 0..var.n_types() {
+        assert!(uncompressed.inverted_index().positions(t).unwrap().eq(var.inverted_index().positions(t).unwrap()));
+    }
+}
+
+#[test]
+fn elias_fano_postings() {
+    use std::io::Cursor;
+    use crate::components::{InvertedIndex, LexiconBuilder, LexiconOrder, PostingsEncoding};
+    use crate::variables::IndexedStringVariable;
+
+    let primary = &Datastore::open(DATASTORE_PATH).unwrap()["primary"];
+    let word = primary["word"].as_indexed_string().unwrap();
+    let n = 200_000;
+
+    let mut builder = LexiconBuilder::from_strings(word.iter().take(n));
+    builder.set_postings_encoding(PostingsEncoding::EliasFano);
+    builder.set_index_memory_budget(builder.types() * InvertedIndex::TYPE_BYTES + (1 << 14));
+    let var = IndexedStringVariable::encode_lexicon_builder(Cursor::new(Vec::new()), builder, "word".to_owned(), uuid::Uuid::new_v4(), true, LexiconOrder::Frequency, "").unwrap();
+
+    assert!(var.len() == n);
+    for t in (0..var.n_types()).step_by(7) {
+        let original = word.type_id(var.lexicon().get_unchecked(t)).unwrap();
+        let expected: Vec<usize> = word.inverted_index().positions(original).unwrap().take_while(|&p| p < n).collect();
+        assert!(var.inverted_index().positions(t).unwrap().collect::<Vec<_>>() == expected);
+    }
+}
+
+#[test]
+fn merged_postings() {
+    use crate::components::MergedPostings;
+
+    let datastore = Datastore::open(DATASTORE_PATH).unwrap();
+    let word = datastore["primary"]["word"].as_indexed_string().unwrap();
+    let index = word.inverted_index();
+
+    let type_ids: Vec<usize> = ["the", "of", "Oliver", "Twist"].iter().map(|w| word.type_id(w).unwrap()).collect();
+    let merged: Vec<usize> = index.merged_postings(&type_ids, false).collect();
+    assert!(merged == index.decode_combined_postings(&type_ids));
+
+    // repeated types only repeat their positions without dedup
+    let repeated = [type_ids[2], type_ids[2], type_ids[3]];
+    assert!(index.merged_postings(&repeated, false).count() == 2 * word.frequency(type_ids[2]).unwrap() + word.frequency(type_ids[3]).unwrap());
+    let deduped: Vec<usize> = index.merged_postings(&repeated, true).collect();
+    assert!(deduped == index.decode_combined_postings(&repeated[1..]));
+
+    let lists = MergedPostings::new([vec![1, 4, 9].into_iter(), vec![].into_iter(), vec![2, 4, 5].into_iter()], true);
+    assert!(lists.collect::<Vec<_>>() == [1, 2, 4, 5, 9]);
+}
+
+#[test]
+fn postings_within_positions() {
+    use std::io::Cursor;
+    use crate::components::{LexiconBuilder, LexiconOrder, PostingsEncoding};
+    use crate::variables::IndexedStringVariable;
+
+    let datastore = Datastore::open(DATASTORE_PATH).unwrap();
+    let primary = &datastore["primary"];
+    let word = primary["word"].as_indexed_string().unwrap();
+    let the = word.type_id("the").unwrap();
+    let all: Vec<usize> = word.inverted_index().positions(the).unwrap().collect();
+
+    let (start, end) = datastore["s"].as_segmentation().unwrap().get_unchecked(1000);
+    let expected: Vec<usize> = all.iter().copied().filter(|p| (start..end).contains(p)).collect();
+    assert!(primary["word"].postings_within(the, start, end).unwrap().collect::<Vec<_>>() == expected);
+    assert!(word.inverted_index().positions_within(the, 0, 0).unwrap().next().is_none());
+    assert!(word.inverted_index().position(the, 17) == Some(all[17]));
+
+    // Elias-Fano coded lists are read through their skip pointers without filling the cache
+    let n = 100_000;
+    let mut builder = LexiconBuilder::from_strings(word.iter().take(n));
+    builder.set_postings_encoding(PostingsEncoding::EliasFano);
+    let var = IndexedStringVariable::encode_lexicon_builder(Cursor::new(Vec::new()), builder, "word".to_owned(), uuid::Uuid::new_v4(), true, LexiconOrder::Frequency, "").unwrap();
+    let index = var.inverted_index();
+    let the = var.type_id("the").unwrap();
+    let all: Vec<usize> = all.into_iter().take_while(|&p| p < n).collect();
+
+    for (start, end) in [(0, n), (5000, 5100), (n / 2, n / 2 + 7000), (n - 10, n + 10)] {
+        let expected: Vec<usize> = all.iter().copied().filter(|p| (start..end).contains(p)).collect();
+        assert!(index.positions_within(the, start, end).unwrap().collect::<Vec<_>>() == expected);
+    }
+    assert!(index.position(the, 300) == Some(all[300]) && index.position(the, all.len()).is_none());
+    assert!(index.cache_stats().misses == 0);
+}
+
+#[test]
+fn query_warm() {
+    use crate::query::Query;
+
+    let datastore = Datastore::open(DATASTORE_PATH).unwrap();
+    let primary = &datastore["primary"];
+    let word = primary["word"].as_indexed_string().unwrap();
+    let query = Query::parse(r#"[pos="JJ"] "man""#).unwrap();
+
+    let before = word.inverted_index().cache_stats();
+    let rendered = query.warm_while(primary, || "previous page").unwrap();
+    assert!(rendered == "previous page");
+
+    let warmed = word.inverted_index().cache_stats();
+    assert!(warmed.misses == before.misses + 1);
+
+    // find reads the warmed list from the cache
+    let matches = query.find(primary).unwrap();
+    assert!(!matches.is_empty());
+    let found = word.inverted_index().cache_stats();
+    assert!(found.misses == warmed.misses && found.hits > warmed.hits);
+
+    // already cached lists are not decoded again
+    query.warm(primary).unwrap();
+    assert!(word.inverted_index().cache_stats().misses == warmed.misses);
+}
+
+#[test]
+fn integer_vector_variable() {
+    use crate::variables::{IntegerVectorVariable, VariableType, VariableValue};
+
+    let dir = tempfile::tempdir().unwrap();
+    Datastore::open(DATASTORE_PATH).unwrap().snapshot_to(dir.path()).unwrap();
+    std::fs::remove_file(dir.path().join(crate::manifest::MANIFEST_FILENAME)).unwrap();
+
+    let datastore = Datastore::open(dir.path()).unwrap();
+    let primary = &datastore["primary"];
+    let words = primary["word"].as_indexed_string().unwrap();
+
+    // byte offsets of each token in the text joined by spaces
+    let mut offset = 0;
+    let spans: Vec<[i64; 2]> = words.iter()
+        .map(|w| {
+            let span = [offset, offset + w.len() as i64];
+            offset = span[1] + 1;
+            span
+        })
+        .collect();
+    let open = |name: &str| File::options()
+        .read(true)
+        .write(true)
+        .create(true)
+        .open(dir.path().join(name))
+        .unwrap();
+
+    for (compressed, delta) in [(false, false), (true, false), (true, true)] {
+        let var = IntegerVectorVariable::encode_to_file(open("tmp.zigv"), spans.iter().copied(), primary.len(), "span".to_owned(), primary.uuid(), compressed, delta, "").unwrap();
+        assert!(var.len() == primary.len() && var.width() == 2);
+        assert!(var.get::<2>(1000) == Some(spans[1000]));
+        assert!(var.get::<3>(1000).is_none() && var.get::<2>(primary.len()).is_none());
+        assert!(var.get_vec(7) == Some(spans[7].to_vec()));
+
+        let rows = var.rows::<2>().unwrap();
+        assert!(rows.get_row(5000) == Some(spans[5000]));
+        assert!(var.rows::<1>().is_none());
+
+        assert!(var.iter().eq(spans.iter().map(|s| s.to_vec())));
+        assert!(var.get_range(300, 900).unwrap().len() == 600);
+        assert!(var.get_range(300, 900).unwrap().eq(spans[300..900].iter().map(|s| s.to_vec())));
+        assert!(var.get_range(0, primary.len() + 1).is_none());
+
+        assert!(var.get_all(spans[42][0]).collect::<Vec<_>>() == vec![42]);
+        assert!(var.get_all(-1).next().is_none());
+    }
+
+    // multi-column integer variables are opened like any other variable
+    std::fs::rename(dir.path().join("tmp.zigv"), dir.path().join("span.zigv")).unwrap();
+    let datastore = Datastore::open(dir.path()).unwrap();
+    let primary = &datastore["primary"];
+    assert!(primary.variable_type("span") == Some(VariableType::IntegerVector));
+    let span = &primary["span"];
+    assert!(span.as_integer_vector().unwrap().get::<2>(10) == Some(spans[10]));
+    assert!(span.get(10) == Some(VariableValue::Integers(spans[10].to_vec())));
+    assert!(span.get(10).unwrap().to_string() == format!("{},{}", spans[10][0], spans[10][1]));
+    assert!(span.get_range(0, 10).unwrap().eq(spans[..10].iter().map(|s| VariableValue::Integers(s.to_vec()))));
+}
+
+#[test]
+fn offset_variable() {
+    use crate::variables::{OffsetVariable, VariableType, VariableValue};
+
+    let dir = tempfile::tempdir().unwrap();
+    Datastore::open(DATASTORE_PATH).unwrap().snapshot_to(dir.path()).unwrap();
+    std::fs::remove_file(dir.path().join(crate::manifest::MANIFEST_FILENAME)).unwrap();
+
+    let datastore = Datastore::open(dir.path()).unwrap();
+    let primary = &datastore["primary"];
+    let words = primary["word"].as_indexed_string().unwrap();
+
+    // the source text is the tokens joined by single spaces
+    let text = words.iter().collect::<Vec<_>>().join(" ");
+    let mut offset = 0;
+    let offsets: Vec<(usize, usize)> = words.iter()
+        .map(|w| {
+            let range = (offset, offset + w.len());
+            offset = range.1 + 1;
+            range
+        })
+        .collect();
+    let open = |name: &str| File::options()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(dir.path().join(name))
+        .unwrap();
+
+    for compressed in [false, true] {
+        let var = OffsetVariable::encode_to_file(open("tmp.zigv"), offsets.iter().copied(), primary.len(), "offsets".to_owned(), primary.uuid(), compressed, "").unwrap();
+        assert!(var.len() == primary.len());
+        assert!(var.iter().eq(offsets.iter().copied()));
+        assert!(var.get_range(100, 200).unwrap().eq(offsets[100..200].iter().copied()));
+        assert!(var.get(primary.len()).is_none() && var.get_range(0, primary.len() + 1).is_none());
+
+        // positions to characters and back
+        let (start, end) = var.char_range(1000, 1010).unwrap();
+        assert!(text[start..end] == words.get_range(1000, 1010).unwrap().collect::<Vec<_>>().join(" "));
+        assert!(var.positions(start, end) == Some((1000, 1010)));
+        assert!(var.char_range(5, 5).is_none() && var.char_range(0, primary.len() + 1).is_none());
+
+        // partially covered tokens are included, whitespace only is not
+        assert!(var.positions(start + 1, end - 1) == Some((1000, 1010)));
+        assert!(var.positions(end, end + 1).is_none());
+        assert!(var.positions(end - 1, end + 2) == Some((1009, 1011)));
+        assert!(var.positions(offset, offset + 10).is_none());
+        assert!(var.position_at(start) == Some(1000) && var.position_at(end - 1) == Some(1009));
+        assert!(var.position_at(end).is_none());
+        assert!(var.position_at(0) == Some(0));
+    }
+
+    let overlapping = [(0, 5), (3, 8)];
+    assert!(matches!(OffsetVariable::encode_to_file(open("err.zigv"), overlapping.into_iter(), 2, "e".to_owned(), primary.uuid(), true, ""), Err(EncodeError::InvalidRange(RangeError::Overlapping(1, _)))));
+    let empty = [(0, 5), (6, 6)];
+    assert!(matches!(OffsetVariable::encode_to_file(open("err.zigv"), empty.into_iter(), 2, "e".to_owned(), primary.uuid(), false, ""), Err(EncodeError::InvalidRange(RangeError::Empty(1, _)))));
+    std::fs::remove_file(dir.path().join("err.zigv")).unwrap();
+
+    std::fs::rename(dir.path().join("tmp.zigv"), dir.path().join("offsets.zigv")).unwrap();
+    let datastore = Datastore::open(dir.path()).unwrap();
+    let primary = &datastore["primary"];
+    assert!(primary.variable_type("offsets") == Some(VariableType::Offset));
+    let (start, end) = offsets[7];
+    assert!(primary["offsets"].get(7) == Some(VariableValue::Offsets(start, end)));
+    assert!(primary["offsets"].get(7).unwrap().to_string() == format!("{}-{}", start, end));
+    assert!(primary["offsets"].as_offset().unwrap().position_at(start) == Some(7));
+}
+
+#[test]
+fn source_documents() {
+    use crate::sources::SourceVariable;
+
+    let dir = tempfile::tempdir().unwrap();
+    Datastore::open(DATASTORE_PATH).unwrap().snapshot_to(dir.path()).unwrap();
+    std::fs::remove_file(dir.path().join(crate::manifest::MANIFEST_FILENAME)).unwrap();
+
+    let datastore = Datastore::open(dir.path()).unwrap();
+    let chapters = &datastore["chapter"];
+    let words = datastore["primary"]["word"].as_indexed_string().unwrap();
+    let segmentation = chapters.as_segmentation().unwrap();
+
+    // a document of markup around the words of each chapter
+    let documents: Vec<String> = (0..chapters.len())
+        .map(|i| {
+            let (start, end) = segmentation.get(i).unwrap();
+            format!("<h1>{}</h1>\n<p>{}</p>\n", i, words.get_range(start, end).unwrap().collect::<Vec<_>>().join(" "))
+        })
+        .collect();
+    let open = |name: &str| File::options()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(dir.path().join(name))
+        .unwrap();
+
+    for compressed in [false, true] {
+        let sources = SourceVariable::encode_to_file(open("tmp.zigv"), documents.iter(), chapters.len(), "html".to_owned(), chapters.uuid(), compressed, "").unwrap();
+        assert!(sources.len() == chapters.len() && sources.is_compressed() == compressed);
+        for (i, document) in documents.iter().enumerate() {
+            assert!(sources.get_source(i).unwrap().as_ref() == document.as_bytes());
+        }
+        assert!(sources.get_source_lossy(3).unwrap() == documents[3]);
+        assert!(sources.get_source(chapters.len()).is_none());
+        assert!((sources.get_raw(0).unwrap() == documents[0].as_bytes()) != compressed);
+    }
+
+    let short = SourceVariable::encode_to_file(open("err.zigv"), documents.iter().take(2), chapters.len(), "e".to_owned(), chapters.uuid(), true, "");
+    assert!(matches!(short, Err(EncodeError::LengthMismatch { found: 2, .. })));
+    std::fs::remove_file(dir.path().join("err.zigv")).unwrap();
+
+    // source documents are attached to their layer when the datastore is opened
+    std::fs::rename(dir.path().join("tmp.zigv"), dir.path().join("html.zigv")).unwrap();
+    let datastore = Datastore::open(dir.path()).unwrap();
+    let sources = datastore["chapter"].source_by_name("html").unwrap();
+    assert!(sources.get_source(1).unwrap().as_ref() == documents[1].as_bytes());
+    assert!(datastore["chapter"].variable_by_name("html").is_none());
+    assert!(datastore["primary"].source_by_name("html").is_none());
+}
+
+#[test]
+fn lexicon_hash_table() {
+    use std::io::Cursor;
+    use crate::components::{HashTable, LexiconBuilder, LexiconOrder};
+    use crate::variables::IndexedStringVariable;
+
+    // colliding hashes are found by probing past each other
+    let pairs = [(5, 0), (13, 1), (5, 2), (6, 3)];
+    let rows = HashTable::build_rows(pairs, pairs.len());
+    let table = HashTable::from_vector(Vector::Uncompressed { length: rows.len() / 2, width: 2, data: &rows }).unwrap();
+    assert!(table.capacity() == 8);
+    assert!(table.get_all(5).collect::<Vec<_>>() == vec![0, 2]);
+    assert!(table.get_all(13).collect::<Vec<_>>() == vec![1]);
+    assert!(table.get_all(6).collect::<Vec<_>>() == vec![3]);
+    assert!(table.get_all(7).next().is_none());
+    assert!(HashTable::from_vector(Vector::Uncompressed { length: 3, width: 2, data: &rows[..6] }).is_none());
+
+    let datastore = Datastore::open(DATASTORE_PATH).unwrap();
+    let words = datastore["primary"]["word"].as_indexed_string().unwrap();
+
+    let encode = |threshold: Option<usize>| {
+        let mut lexbuilder = LexiconBuilder::new();
+        lexbuilder.set_hash_table_threshold(threshold);
+        lexbuilder.add_strings(words.iter());
+        let mut buffer = Vec::new();
+        IndexedStringVariable::encode_lexicon_builder(Cursor::new(&mut buffer), lexbuilder, "word".to_owned(), words.base(), true, LexiconOrder::Frequency, "").unwrap();
+        buffer
+    };
+
+    // the table is only written above the threshold
+    let buffer = encode(None);
+    let container = Container::from_bytes(&buffer, "word".to_owned()).unwrap();
+    assert!(IndexedStringVariable::try_from(container).unwrap().hash_table().is_none());
+    let buffer = encode(Some(words.n_types()));
+    let container = Container::from_bytes(&buffer, "word".to_owned()).unwrap();
+    assert!(IndexedStringVariable::try_from(container).unwrap().hash_table().is_none());
+
+    let buffer = encode(Some(words.n_types() - 1));
+    let container = Container::from_bytes(&buffer, "word".to_owned()).unwrap();
+    let var = IndexedStringVariable::try_from(container).unwrap();
+    let table = var.hash_table().unwrap();
+    assert!(table.capacity() == HashTable::capacity_for(words.n_types()));
+    assert!(var.lexicon().iter().enumerate().all(|(id, s)| var.lookup_str(s) == Some(id)));
+    assert!(var.lookup_str("not a word").is_none());
+}
+
+#[test]
+fn primary_layer_partitions() {
+    use std::io::Cursor;
+    use crate::layers::PrimaryLayer;
+    use crate::lock::PendingFile;
+    use crate::variables::IndexedStringVariable;
+
+    // a datastore built from scratch, with one partition per sentence
+    let dir = tempfile::tempdir().unwrap();
+    let words = ["It", "was", "the", "best", "of", "times", ".", "It", "was", "the", "worst", "."].map(String::from);
+    let primary = PrimaryLayer::encode_partitioned(PendingFile::create(dir.path().join("primary.zigl")).unwrap(), words.len(), &[0, 7], "primary".to_owned(), "").unwrap();
+    assert!(primary.partition_boundaries() == [0, 7, 12]);
+    IndexedStringVariable::encode_to_file(PendingFile::create(dir.path().join("word.zigv")).unwrap(), words.into_iter(), primary.len(), "word".to_owned(), primary.uuid(), true, "").unwrap();
+
+    let datastore = Datastore::open(dir.path()).unwrap();
+    let layer = datastore["primary"].as_primary().unwrap();
+    assert!(layer.partition_boundaries() == [0, 7, 12] && layer.header.dim2() == 2);
+    assert!(datastore["primary"]["word"].as_indexed_string().unwrap().get(10) == Some("worst"));
+
+    // unpartitioned layers are a single partition without components
+    let mut bytes = Vec::new();
+    let primary = PrimaryLayer::encode_to_file(Cursor::new(&mut bytes), 12, "primary".to_owned(), "").unwrap();
+    assert!(primary.partition_boundaries() == [0, 12] && primary.header.dim2() == 0);
+    let reference = Datastore::open(DATASTORE_PATH).unwrap();
+    let n = reference["primary"].len();
+    assert!(reference["primary"].as_primary().unwrap().partition_boundaries() == [0, n]);
+
+    let partitioned = |starts: &[usize]| PrimaryLayer::encode_partitioned(Cursor::new(Vec::new()), 12, starts, "primary".to_owned(), "").map(|l| l.partition_boundaries().to_vec());
+    assert!(partitioned(&[0]).unwrap() == [0, 12]);
+    assert!(matches!(partitioned(&[1, 7]), Err(EncodeError::InvalidPartition(0))));
+    assert!(matches!(partitioned(&[0, 7, 7]), Err(EncodeError::InvalidPartition(2))));
+    assert!(matches!(partitioned(&[0, 12]), Err(EncodeError::InvalidPartition(1))));
+}
+
+#[test]
+fn segmentation_layer_partitions() {
+    use std::io::Cursor;
+    use crate::layers::PrimaryLayer;
+    use crate::lock::PendingFile;
+    use crate::query::{Query, QueryError};
+    use crate::variables::IndexedStringVariable;
+
+    // two documents of two clauses each
+    let dir = tempfile::tempdir().unwrap();
+    let words = ["It", "was", "the", "best", "of", "times", ".", "It", "was", "the", "worst", "."].map(String::from);
+    let clauses = [(0, 3), (3, 7), (7, 10), (10, 12)];
+    let primary = PrimaryLayer::encode_partitioned(PendingFile::create(dir.path().join("primary.zigl")).unwrap(), words.len(), &[0, 7], "primary".to_owned(), "").unwrap();
+    IndexedStringVariable::encode_to_file(PendingFile::create(dir.path().join("word.zigv")).unwrap(), words.into_iter(), primary.len(), "word".to_owned(), primary.uuid(), true, "").unwrap();
+    let layer = SegmentationLayer::encode_partitioned(PendingFile::create(dir.path().join("s.zigl")).unwrap(), clauses.into_iter(), clauses.len(), &[0, 2], "s".to_owned(), primary.uuid(), true, true, "").unwrap();
+    assert!(layer.partition_boundaries() == [0, 2, 4]);
+
+    let datastore = Datastore::open(dir.path()).unwrap();
+    assert!(datastore["primary"].partition_boundaries() == Some(&[0, 7, 12][..]));
+    let s = datastore["s"].as_segmentation().unwrap();
+    assert!(s.partition_boundaries() == [0, 2, 4] && s.header.dim2() == 2);
+    assert!(s.partition(1) == Some(2..4) && s.partition(2).is_none());
+    assert!(s.find_partition(3) == Some(1) && s.find_partition(4).is_none());
+    assert!(s.iter_partition(1).unwrap().collect::<Vec<_>>() == clauses[2..]);
+    assert!(s.find_containing_in_partition(8, 1) == Some(2) && s.find_containing_in_partition(8, 0).is_none());
+    assert!(datastore["primary"].as_primary().unwrap().find_partition(7) == Some(1));
+
+    // queries restricted to a partition don't cross its boundaries
+    let query = Query::parse(r#""It" "was""#).unwrap();
+    assert!(query.find_in(&datastore, "primary").unwrap() == [(0, 2), (7, 9)]);
+    assert!(query.find_in_partition(&datastore, "primary", 1).unwrap() == [(7, 9)]);
+    let query = Query::parse(r#""." "It""#).unwrap();
+    assert!(query.find_in(&datastore, "primary").unwrap() == [(6, 8)]);
+    assert!(query.find_in_partition(&datastore, "primary", 0).unwrap().is_empty());
+    assert!(query.find_in_partition(&datastore, "primary", 1).unwrap().is_empty());
+    let query = Query::parse(r#""was" []* within s"#).unwrap();
+    assert!(query.find_in_partition(&datastore, "primary", 1).unwrap() == [(8, 10)]);
+    assert!(matches!(query.find_in_partition(&datastore, "primary", 2), Err(QueryError::UnknownPartition(_, 2))));
+
+    let partitioned = |starts: &[usize]| SegmentationLayer::encode_partitioned(Cursor::new(Vec::new()), clauses.into_iter(), clauses.len(), starts, "s".to_owned(), primary.uuid(), false, false, "").map(|l| l.partition_boundaries().to_vec());
+    assert!(partitioned(&[]).unwrap() == [0, 4]);
+    assert!(matches!(partitioned(&[0, 4]), Err(EncodeError::InvalidPartition(1))));
+}
+
+#[test]
+fn partition_query_elias_fano() {
+    use crate::components::{LexiconBuilder, LexiconOrder, PostingsEncoding};
+    use crate::layers::PrimaryLayer;
+    use crate::lock::PendingFile;
+    use crate::query::Query;
+    use crate::variables::IndexedStringVariable;
+
+    let reference = Datastore::open(DATASTORE_PATH).unwrap();
+    let words = reference["primary"]["word"].as_indexed_string().unwrap();
+
+    let n = 100_000;
+    let dir = tempfile::tempdir().unwrap();
+    let primary = PrimaryLayer::encode_partitioned(PendingFile::create(dir.path().join("primary.zigl")).unwrap(), n, &[0, 30_000, 70_000], "primary".to_owned(), "").unwrap();
+    let mut builder = LexiconBuilder::from_strings(words.iter().take(n));
+    builder.set_postings_encoding(PostingsEncoding::EliasFano);
+    IndexedStringVariable::encode_lexicon_builder(PendingFile::create(dir.path().join("word.zigv")).unwrap(), builder, "word".to_owned(), primary.uuid(), true, LexiconOrder::Frequency, "").unwrap();
+
+    let datastore = Datastore::open(dir.path()).unwrap();
+    let query = Query::parse(r#"[] "of" "the""#).unwrap();
+    let all = query.find_in(&datastore, "primary").unwrap();
+    assert!(!all.is_empty());
+
+    // candidates are read through the skip pointers of the lists, which are not cached
+    let index = datastore["primary"]["word"].as_indexed_string().unwrap().inverted_index();
+    let before = index.cache_stats();
+    for (partition, bounds) in [0, 30_000, 70_000, n].windows(2).enumerate() {
+        let expected: Vec<_> = all.iter().copied().filter(|(start, end)| *start >= bounds[0] && *end <= bounds[1]).collect();
+        assert!(query.find_in_partition(&datastore, "primary", partition).unwrap() == expected);
+    }
+    assert!(index.cache_stats().misses == before.misses);
+}
+
+#[cfg(feature = "remote")]
+#[test]
+fn remote_storage() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use crate::storage::{HttpStorage, Storage};
+
+    // serves the files below `root` and counts the ranged requests
+    fn serve(root: std::path::PathBuf, ranged: Arc<AtomicUsize>) -> String {
+        let server = tiny_http::Server::http("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", server.server_addr().to_ip().unwrap());
+        std::thread::spawn(move || {
+            for request in server.incoming_requests() {
+                let Ok(data) = std::fs::read(root.join(request.url().trim_start_matches('/'))) else {
+                    let _ = request.respond(tiny_http::Response::empty(404));
+                    continue;
+                };
+                let range = request.headers().iter()
+                    .find(|h| h.field.equiv("Range"))
+                    .and_then(|h| h.value.as_str().strip_prefix("bytes=")?.split_once('-').map(|(s, e)| (s.parse::<usize>().unwrap(), e.parse::<usize>().unwrap() + 1)));
+                let response = match range {
+                    Some((start, end)) => {
+                        ranged.fetch_add(1, Ordering::SeqCst);
+                        tiny_http::Response::from_data(data[start..end].to_vec()).with_status_code(206)
+                    }
+                    None => tiny_http::Response::from_data(data),
+                };
+                // with a Content-Length instead of chunks, also for HEAD requests
+                let _ = request.respond(response.with_chunked_threshold(usize::MAX));
+            }
+        });
+        url
+    }
+
+    let dir = tempfile::tempdir().unwrap();
+    let bytes: Vec<u8> = (0..300_000u32).map(|i| (i % 251) as u8).collect();
+    std::fs::write(dir.path().join("blob"), &bytes).unwrap();
+    let ranged = Arc::new(AtomicUsize::new(0));
+    let url = serve(dir.path().to_owned(), ranged.clone());
+    let blob = format!("{}/blob", url);
+
+    // blocks are fetched on open, into word aligned memory
+    let cache = tempfile::tempdir().unwrap();
+    let storage = HttpStorage::open_with_block_size(&blob, Some(cache.path().to_owned()), 64 << 10).unwrap();
+    assert!(ranged.load(Ordering::SeqCst) == 5);
+    let data = storage.bytes();
+    assert!(data.len() == bytes.len() && data.as_ptr() as usize % 8 == 0);
+    assert!(data == bytes);
+    drop(storage);
+
+    // failed requests are returned as errors
+    assert!(HttpStorage::open(&format!("{}/missing", url)).is_err());
+
+    // cached blocks are not fetched again
+    let storage = HttpStorage::open_with_block_size(&blob, Some(cache.path().to_owned()), 64 << 10).unwrap();
+    assert!(storage.bytes() == bytes && ranged.load(Ordering::SeqCst) == 5);
+
+    // datastores are opened by the URL of their manifest
+    let local = Datastore::open(DATASTORE_PATH).unwrap();
+    local.snapshot_to(dir.path().join("store")).unwrap();
+    let remote = Datastore::open_url(&format!("{}/store", url), None).unwrap();
+    let (local_words, remote_words) = (local["primary"]["word"].as_indexed_string().unwrap(), remote["primary"]["word"].as_indexed_string().unwrap());
+    assert!(remote_words.len() == local_words.len());
+    assert!((0..local_words.len()).step_by(97).all(|i| remote_words.get(i) == local_words.get(i)));
+}
@@ -1,27 +1,126 @@
-use std::collections::HashSet;
-use std::fs::File;
-use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::borrow::Cow;
+use std::cell::Cell;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::io::{BufWriter, SeekFrom, Write};
+use std::ops;
 use std::rc::Rc;
 
 use enum_as_inner::EnumAsInner;
-use memmap2::Mmap;
+use serde::Serialize;
 use uuid::Uuid;
 
-use crate::components::{self, CachedIndex, CachedInvertedIndex, CachedVector, ColumnIterator, FnvHash, Index, LexiconBuilder, Vector};
-use crate::container::{self, Container, ContainerBuilder};
+use crate::components::{self, BigramIndex, BloomFilter, CacheStats, HashTable, WaveletTree, CachedIndex, CachedInvertedIndex, CachedVector, ColumnIterator, Component, FnvHash, Index, InvalidUtf8, LexiconBuilder, LexiconOrder, Normalization, Vector};
+use crate::container::{self, Container, ContainerBuilder, EncodeError, EncodeTarget};
+use crate::group::GroupCounts;
+use crate::layers::{check_range, Layer, LayerData, RangeError, SegmentationLayer};
 use crate::macros::{check_and_return_component, get_container_base};
+use crate::sample;
+use crate::selection::SelectionError;
+use crate::storage::{self, Storage};
+
+/// Number of (value, position) pairs sorted in memory at once when building a reverse index
+const SORT_RUN_LEN: usize = 1 << 20;
+
+/// Splits the positions `0..len` into chunks of at least `chunk_len` rows, rounded up to whole
+/// vector blocks, runs `scan` on each chunk in parallel and combines the results with `merge`.
+///
+/// Results are merged in position order, so `merge` only needs to be associative.
+#[cfg(feature = "parallel")]
+fn par_chunks<T, S, M>(len: usize, chunk_len: usize, scan: S, merge: M) -> Option<T>
+where
+    T: Send,
+    S: Fn(usize, usize) -> T + Sync,
+    M: Fn(T, T) -> T + Sync,
+{
+    use rayon::prelude::*;
+
+    let chunk_len = chunk_len.max(1).next_multiple_of(16);
+    (0..len.div_ceil(chunk_len))
+        .into_par_iter()
+        .map(|chunk| {
+            let start = chunk * chunk_len;
+            scan(start, usize::min(start + chunk_len, len))
+        })
+        .reduce_with(|a, b| merge(a, b))
+}
+
+/// Attaches the bloom filter component `name` to `index` if the container has one, older
+/// containers and those written by other encoders may not.
+fn with_optional_bloom<'map>(container: &Container<'map>, index: CachedIndex<'map>, name: &'static str) -> Result<CachedIndex<'map>, container::TryFromError> {
+    match container.get_component(name) {
+        Some(component) => {
+            let vector = component.into_vector()
+                .map_err(|_| container::TryFromError::WrongComponentType(name))?;
+            let bloom = BloomFilter::from_vector(vector)
+                .ok_or(container::TryFromError::WrongComponentDimensions(name))?;
+            Ok(index.with_bloom(bloom))
+        }
+        None => Ok(index),
+    }
+}
 
 #[derive(Debug, EnumAsInner)]
 pub enum Variable<'map> {
     IndexedString(IndexedStringVariable<'map>),
     PlainString(PlainStringVariable<'map>),
     Integer(IntegerVariable<'map>),
+    IntegerVector(IntegerVectorVariable<'map>),
+    Offset(OffsetVariable<'map>),
     Pointer(PointerVariable<'map>),
-    ExternalPointer,
+    /// Only the header, the values can't be decoded yet
+    ExternalPointer(container::Header),
     Set(SetVariable<'map>),
+    Sparse(SparseVariable<'map>),
+    /// Only the header, the values can't be decoded yet
+    Hash(container::Header),
+}
+
+/// Type of a [`Variable`], e.g. to pick an encoding when exporting variables by type
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum VariableType {
+    IndexedString,
+    PlainString,
+    Integer,
+    IntegerVector,
+    Offset,
+    Pointer,
+    ExternalPointer,
+    Set,
+    Sparse,
     Hash,
 }
 
+impl VariableType {
+    /// Name of the type as used in the container headers, e.g. `IndexedStringVariable`
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::IndexedString => "IndexedStringVariable",
+            Self::PlainString => "PlainStringVariable",
+            Self::Integer | Self::IntegerVector => "IntegerVariable",
+            Self::Offset => "OffsetVariable",
+            Self::Pointer => "PointerVariable",
+            Self::ExternalPointer => "ExternalPointerVariable",
+            Self::Set => "SetVariable",
+            Self::Sparse => "SparseVariable",
+            Self::Hash => "HashVariable",
+        }
+    }
+}
+
+impl fmt::Display for VariableType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// Width of the IntStream of an integer variable, 1 if it has none
+fn int_stream_width(container: &Container) -> usize {
+    container.get_component("IntStream")
+        .and_then(|c| c.into_vector().ok())
+        .map_or(1, |v| v.width())
+}
+
 impl<'map> TryFrom<Container<'map>> for Variable<'map> {
     type Error = container::TryFromError;
 
@@ -35,75 +134,516 @@ impl<'map> TryFrom<Container<'map>> for Variable<'map> {
                 Ok(Self::PlainString(PlainStringVariable::try_from(container)?))
             }
 
+            container::Type::IntegerVariable if int_stream_width(&container) > 1 => {
+                Ok(Self::IntegerVector(IntegerVectorVariable::try_from(container)?))
+            }
+
             container::Type::IntegerVariable => {
                 Ok(Self::Integer(IntegerVariable::try_from(container)?))
             }
 
+            container::Type::OffsetVariable => Ok(Self::Offset(OffsetVariable::try_from(container)?)),
+
             container::Type::PointerVariable => {
                 Ok(Self::Pointer(PointerVariable::try_from(container)?))
             }
 
-            container::Type::ExternalPointerVariable => todo!(),
+            container::Type::ExternalPointerVariable => Ok(Self::ExternalPointer(*container.header())),
 
             container::Type::SetVariable => Ok(Self::Set(SetVariable::try_from(container)?)),
 
-            container::Type::HashVariable => todo!(),
+            container::Type::SparseVariable => Ok(Self::Sparse(SparseVariable::try_from(container)?)),
+
+            container::Type::HashVariable => Ok(Self::Hash(*container.header())),
 
             _ => Err(Self::Error::WrongContainerType),
         }
     }
 }
 
+impl<'map> Variable<'map> {
+    pub fn variable_type(&self) -> VariableType {
+        match self {
+            Self::IndexedString(_) => VariableType::IndexedString,
+            Self::PlainString(_) => VariableType::PlainString,
+            Self::Integer(_) => VariableType::Integer,
+            Self::IntegerVector(_) => VariableType::IntegerVector,
+            Self::Offset(_) => VariableType::Offset,
+            Self::Pointer(_) => VariableType::Pointer,
+            Self::ExternalPointer(_) => VariableType::ExternalPointer,
+            Self::Set(_) => VariableType::Set,
+            Self::Sparse(_) => VariableType::Sparse,
+            Self::Hash(_) => VariableType::Hash,
+        }
+    }
+
+    pub fn cache_stats(&self) -> CacheStats {
+        match self {
+            Self::IndexedString(v) => v.cache_stats(),
+            Self::PlainString(v) => v.cache_stats(),
+            Self::Integer(v) => v.cache_stats(),
+            Self::IntegerVector(v) => v.cache_stats(),
+            Self::Offset(v) => v.cache_stats(),
+            Self::Pointer(v) => v.cache_stats(),
+            Self::Set(v) => v.cache_stats(),
+            Self::Sparse(v) => v.cache_stats(),
+            Self::ExternalPointer(_) | Self::Hash(_) => CacheStats::default(),
+        }
+    }
+
+    /// UUID of the container
+    pub fn uuid(&self) -> Option<Uuid> {
+        match self {
+            Self::IndexedString(v) => Some(v.uuid()),
+            Self::PlainString(v) => Some(v.uuid()),
+            Self::Integer(v) => Some(v.uuid()),
+            Self::IntegerVector(v) => Some(v.uuid()),
+            Self::Offset(v) => Some(v.uuid()),
+            Self::Pointer(v) => Some(v.uuid()),
+            Self::Set(v) => Some(v.uuid()),
+            Self::Sparse(v) => Some(v.uuid()),
+            Self::ExternalPointer(h) | Self::Hash(h) => Some(h.uuid()),
+        }
+    }
+
+    /// UUID of the layer this variable annotates
+    pub fn base(&self) -> Option<Uuid> {
+        match self {
+            Self::IndexedString(v) => Some(v.base()),
+            Self::PlainString(v) => Some(v.base()),
+            Self::Integer(v) => Some(v.base()),
+            Self::IntegerVector(v) => Some(v.base()),
+            Self::Offset(v) => Some(v.base()),
+            Self::Pointer(v) => Some(v.base()),
+            Self::Set(v) => Some(v.base()),
+            Self::Sparse(v) => Some(v.base()),
+            Self::ExternalPointer(h) | Self::Hash(h) => h.base1(),
+        }
+    }
+
+    /// Checks that all strings of the variable are valid UTF-8, see [`Datastore::validate_utf8`](crate::Datastore::validate_utf8).
+    pub fn validate_utf8(&self) -> Result<(), InvalidUtf8> {
+        match self {
+            Self::IndexedString(v) => v.validate_utf8(),
+            Self::PlainString(v) => v.validate_utf8(),
+            Self::Set(v) => v.validate_utf8(),
+            Self::Sparse(v) => v.validate_utf8(),
+            Self::Integer(_) | Self::IntegerVector(_) | Self::Offset(_) | Self::Pointer(_) | Self::ExternalPointer(_) | Self::Hash(_) => Ok(()),
+        }
+    }
+
+    /// Returns the value at `index` regardless of the variable type
+    pub fn get(&self, index: usize) -> Option<VariableValue<'map>> {
+        match self {
+            Self::IndexedString(v) => v.get(index).map(VariableValue::String),
+            Self::PlainString(v) => v.get(index).map(VariableValue::String),
+            Self::Integer(v) => v.get(index).map(VariableValue::Integer),
+            Self::IntegerVector(v) => v.get_vec(index).map(VariableValue::Integers),
+            Self::Offset(v) => v.get(index).map(|(start, end)| VariableValue::Offsets(start, end)),
+            Self::Pointer(v) => (index < v.len()).then(|| VariableValue::Pointer(v.get_unchecked(index))),
+            Self::Set(v) => v.get(index).map(VariableValue::Set),
+            Self::Sparse(v) => (index < v.len()).then(|| v.get(index).map_or(VariableValue::Missing, VariableValue::String)),
+            Self::ExternalPointer(_) | Self::Hash(_) => None,
+        }
+    }
+
+    /// Returns the integer at `index`, `None` for out of bounds or non-integer variables
+    pub fn get_int(&self, index: usize) -> Option<i64> {
+        match self {
+            Self::Integer(v) => v.get(index),
+            _ => None,
+        }
+    }
+
+    /// Returns the string at `index`, `None` for out of bounds or non-string variables
+    pub fn get_string(&self, index: usize) -> Option<&'map str> {
+        match self {
+            Self::IndexedString(v) => v.get(index),
+            Self::PlainString(v) => v.get(index),
+            Self::Sparse(v) => v.get(index),
+            _ => None,
+        }
+    }
+
+    /// Returns the stream of lexicon IDs of an indexed string variable
+    pub fn id_stream(&self) -> Option<components::CachedVector<'map, 1>> {
+        match self {
+            Self::IndexedString(v) => Some(v.id_stream()),
+            _ => None,
+        }
+    }
+
+    /// Returns an iterator over the values at `start..end`, `None` if the range is out of bounds
+    pub fn get_range(&self, start: usize, end: usize) -> Option<VariableIterator<'map>> {
+        match self {
+            Self::IndexedString(v) => v.get_range(start, end).map(VariableIterator::IndexedString),
+            Self::PlainString(v) => v.get_range(start, end).map(VariableIterator::PlainString),
+            Self::Integer(v) => v.get_range(start, end).map(VariableIterator::Integer),
+            Self::IntegerVector(v) => v.get_range(start, end).map(VariableIterator::IntegerVector),
+            Self::Offset(v) => v.get_range(start, end).map(VariableIterator::Offset),
+            Self::Pointer(v) => v.get_range(start, end).map(VariableIterator::Pointer),
+            Self::Set(v) => v.get_range(start, end).map(VariableIterator::Set),
+            Self::Sparse(v) => v.get_range(start, end).map(VariableIterator::Sparse),
+            Self::ExternalPointer(h) | Self::Hash(h) => (start <= end && end <= h.dim1()).then_some(VariableIterator::Empty),
+        }
+    }
+
+    pub fn iter(&self) -> VariableIterator<'map> {
+        self.get_range(0, self.len()).expect("full range of a variable is always valid")
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Self::IndexedString(v) => v.len(),
+            Self::PlainString(v) => v.len(),
+            Self::Integer(v) => v.len(),
+            Self::IntegerVector(v) => v.len(),
+            Self::Offset(v) => v.len(),
+            Self::Pointer(v) => v.len(),
+            Self::Set(v) => v.len(),
+            Self::Sparse(v) => v.len(),
+            Self::ExternalPointer(h) | Self::Hash(h) => h.dim1(),
+        }
+    }
+
+    /// Iterator over the positions of lexicon type `type_id` for variables with an inverted index,
+    /// i.e. indexed string and set variables
+    pub fn postings(&self, type_id: usize) -> Option<components::CachedPostingsIterator> {
+        match self {
+            Self::IndexedString(v) => v.inverted_index().positions(type_id),
+            Self::Set(v) => v.id_set_index.positions(type_id),
+            _ => None,
+        }
+    }
+
+    /// Iterator over the positions of lexicon type `type_id` within `start..end`, see
+    /// [`CachedInvertedIndex::positions_within`](components::CachedInvertedIndex::positions_within)
+    pub fn postings_within(&self, type_id: usize, start: usize, end: usize) -> Option<components::PostingsWithin<'map>> {
+        match self {
+            Self::IndexedString(v) => v.lex_id_index.positions_within(type_id, start, end),
+            Self::Set(v) => v.id_set_index.positions_within(type_id, start, end),
+            _ => None,
+        }
+    }
+
+    /// Draws `n` distinct random positions in ascending order, see [`sample::random_indices`]
+    pub fn random_positions(&self, n: usize, seed: u64) -> Vec<usize> {
+        sample::random_indices(self.len(), n, seed)
+    }
+
+    /// Decodes the postings lists of the given types into the postings cache of variables
+    /// with an inverted index, does nothing for other variables
+    pub fn prefetch_postings(&self, type_ids: &[usize]) {
+        match self {
+            Self::IndexedString(v) => v.prefetch_postings(type_ids),
+            Self::Set(v) => v.id_set_index.prefetch(type_ids),
+            _ => (),
+        }
+    }
+
+    /// Prepares the positions `start..end` for access. Compressed blocks are decoded on a
+    /// background thread, see [`CachedVector::prefetch`](components::CachedVector::prefetch).
+    pub fn prefetch_range(&self, start: usize, end: usize) {
+        match self {
+            Self::IndexedString(v) => v.prefetch_range(start, end),
+            Self::PlainString(v) => v.prefetch_range(start, end),
+            Self::Integer(v) => v.prefetch_range(start, end),
+            Self::Offset(v) => v.prefetch_range(start, end),
+            Self::Pointer(v) => v.prefetch_range(start, end),
+            Self::Set(v) => v.prefetch_range(start, end),
+            Self::Sparse(v) => v.prefetch_range(start, end),
+            Self::IntegerVector(_) | Self::ExternalPointer(_) | Self::Hash(_) => (),
+        }
+    }
+
+}
+
+/// A single value of any variable type, see [`Variable::get`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(untagged)]
+pub enum VariableValue<'map> {
+    String(&'map str),
+    Integer(i64),
+    /// Row of an [`IntegerVectorVariable`]
+    Integers(Vec<i64>),
+    /// Byte offsets `(start, end)` of an [`OffsetVariable`]
+    Offsets(usize, usize),
+    Pointer(Option<usize>),
+    Set(HashSet<&'map str>),
+    /// No value at this position of a sparse variable
+    Missing,
+}
+
+/// Pointers without a head are shown as `-`, rows of integers separated by `,`, offsets as
+/// `start-end`, sets as their sorted items separated by `|` and missing values of sparse
+/// variables as `_`
+impl<'map> fmt::Display for VariableValue<'map> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::String(s) => write!(f, "{}", s),
+            Self::Integer(i) => write!(f, "{}", i),
+            Self::Integers(row) => {
+                let row: Vec<String> = row.iter().map(i64::to_string).collect();
+                write!(f, "{}", row.join(","))
+            }
+            Self::Offsets(start, end) => write!(f, "{}-{}", start, end),
+            Self::Pointer(Some(head)) => write!(f, "{}", head),
+            Self::Pointer(None) => write!(f, "-"),
+            Self::Set(items) => {
+                let mut items: Vec<_> = items.iter().copied().collect();
+                items.sort_unstable();
+                write!(f, "{}", items.join("|"))
+            }
+            Self::Missing => write!(f, "_"),
+        }
+    }
+}
+
+/// Iterator over the values of any variable, see [`Variable::get_range`]
+pub enum VariableIterator<'map> {
+    IndexedString(IndexedStringIterator<'map>),
+    PlainString(PlainStringIterator<'map>),
+    Integer(ColumnIterator<'map, 1>),
+    IntegerVector(IntegerVectorIterator<'map>),
+    Offset(OffsetIterator<'map>),
+    Pointer(PointerIterator<'map>),
+    Set(SetIterator<'map>),
+    Sparse(SparseIterator<'map>),
+    /// Values of external pointer and hash variables, which can't be decoded yet
+    Empty,
+}
+
+impl<'map> Iterator for VariableIterator<'map> {
+    type Item = VariableValue<'map>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            Self::IndexedString(it) => it.next().map(VariableValue::String),
+            Self::PlainString(it) => it.next().map(VariableValue::String),
+            Self::Integer(it) => it.next().map(VariableValue::Integer),
+            Self::IntegerVector(it) => it.next().map(VariableValue::Integers),
+            Self::Offset(it) => it.next().map(|(start, end)| VariableValue::Offsets(start, end)),
+            Self::Pointer(it) => it.next().map(VariableValue::Pointer),
+            Self::Set(it) => it.next().map(VariableValue::Set),
+            Self::Sparse(it) => it.next().map(|v| v.map_or(VariableValue::Missing, VariableValue::String)),
+            Self::Empty => None,
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match self {
+            Self::IndexedString(it) => it.size_hint(),
+            Self::PlainString(it) => it.size_hint(),
+            Self::Integer(it) => it.size_hint(),
+            Self::IntegerVector(it) => it.size_hint(),
+            Self::Offset(it) => it.size_hint(),
+            Self::Pointer(it) => it.size_hint(),
+            Self::Set(it) => it.size_hint(),
+            Self::Sparse(it) => it.size_hint(),
+            Self::Empty => (0, Some(0)),
+        }
+    }
+}
+
+impl<'map> ExactSizeIterator for VariableIterator<'map> {}
+
 #[derive(Debug)]
 pub struct IndexedStringVariable<'map> {
     base: Uuid,
-    mmap: Mmap,
+    storage: Box<dyn Storage>,
     pub name: String,
-    pub header: &'map container::Header,
+    pub(crate) header: &'map container::Header,
+    comment: Option<&'map str>,
     lexicon: components::StringVector<'map>,
     lex_hash: components::CachedIndex<'map>,
+    lex_hash_table: Option<HashTable<'map>>,
     lex_id_stream: components::CachedVector<'map, 1>,
     lex_id_index: Rc<components::CachedInvertedIndex<'map>>,
+    order: Option<LexiconOrder>,
+    normalization: Option<Normalization>,
+    freqs: Option<&'map [i64]>,
+    bigrams: Option<BigramIndex<'map>>,
+    wavelet: Option<WaveletTree<'map>>,
 }
 
 impl<'map> IndexedStringVariable<'map> {
-    pub fn encode_to_file<I>(file: File, strings: I, n: usize, name: String, base: Uuid, compressed: bool, comment: &str) -> Self where I: Iterator<Item=String> {
-        let vectype = if compressed { components::Type::VectorComp } else { components::Type::Vector };
+    pub fn comment(&self) -> Option<&'map str> {
+        self.comment
+    }
 
+    pub fn uuid(&self) -> Uuid {
+        self.header.uuid()
+    }
+
+    /// UUID of the layer this variable annotates
+    pub fn base(&self) -> Uuid {
+        self.base
+    }
+
+    pub fn cache_stats(&self) -> CacheStats {
+        let stats = self.lex_hash.cache_stats()
+            + self.lex_id_stream.cache_stats()
+            + self.lex_id_index.cache_stats();
+        match self.bigrams.as_ref() {
+            Some(bigrams) => stats + bigrams.cache_stats(),
+            None => stats,
+        }
+    }
+
+    pub fn encode_to_file<W, I>(file: W, strings: I, n: usize, name: String, base: Uuid, compressed: bool, comment: &str) -> Result<Self, EncodeError> where W: EncodeTarget, I: Iterator<Item=String> {
+        Self::encode_to_file_ordered(file, strings, n, name, base, compressed, LexiconOrder::Frequency, comment)
+    }
+
+    /// Encodes the variable with its lexicon in `order`.
+    ///
+    /// The types of sorted lexicons are numbered in sort order and the name of the order is
+    /// recorded in the container metadata, see [`types_in_range`](Self::types_in_range).
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "info", skip_all, fields(name = %name, n = n, compressed = compressed)))]
+    pub fn encode_to_file_ordered<W, I>(file: W, strings: I, n: usize, name: String, base: Uuid, compressed: bool, order: LexiconOrder, comment: &str) -> Result<Self, EncodeError> where W: EncodeTarget, I: Iterator<Item=String> {
         let lexbuilder = LexiconBuilder::from_strings(strings);
-        assert!(lexbuilder.tokens() == n, "found fewer tokens than layer size");
+        if lexbuilder.tokens() != n {
+            return Err(EncodeError::LengthMismatch { expected: n, found: lexbuilder.tokens() });
+        }
+        Self::encode_lexicon_builder(file, lexbuilder, name, base, compressed, order, comment)
+    }
+
+    /// Encodes the concatenation of `variables` over the union of their lexicons, e.g. to
+    /// append corpora.
+    ///
+    /// The ID streams are recoded into the union lexicon block by block, the tokens are never
+    /// decoded to strings. The new variable annotates the layer `base`, which has to be as long
+    /// as all `variables` together.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "info", skip_all, fields(name = %name, parts = variables.len())))]
+    pub fn encode_merged<W>(file: W, variables: &[&IndexedStringVariable], name: String, base: Uuid, compressed: bool, order: LexiconOrder, comment: &str) -> Result<Self, EncodeError> where W: EncodeTarget {
+        let parts = variables.iter()
+            .map(|var| (var.lexicon.iter(), var.lex_id_stream.column_iter(0).map(|id| id as usize)));
+        let lexbuilder = LexiconBuilder::from_id_streams(parts);
+        Self::encode_lexicon_builder(file, lexbuilder, name, base, compressed, order, comment)
+    }
+
+    /// Encodes a variable derived from `variable` by applying `transform` to each type of its
+    /// lexicon, e.g. `str::to_lowercase`, [`strip_diacritics`](components::strip_diacritics)
+    /// or a stemmer.
+    ///
+    /// Types transformed to the same string are merged. The ID stream is recoded through a
+    /// table from the old to the new type IDs, so `transform` is called once per type and not
+    /// per token. The new variable annotates the same layer as `variable`.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "info", skip_all, fields(name = %name)))]
+    pub fn encode_derived<W, F>(file: W, variable: &IndexedStringVariable, transform: F, name: String, compressed: bool, order: LexiconOrder, comment: &str) -> Result<Self, EncodeError> where W: EncodeTarget, F: FnMut(&str) -> String {
+        let lexicon: Vec<String> = variable.lexicon.iter().map(transform).collect();
+        let ids = variable.lex_id_stream.column_iter(0).map(|id| id as usize);
+        let lexbuilder = LexiconBuilder::from_id_streams([(lexicon.iter().map(String::as_str), ids)]);
+        Self::encode_lexicon_builder(file, lexbuilder, name, variable.base, compressed, order, comment)
+    }
+
+    /// Encodes the strings added to `lexbuilder`, e.g. after limiting the memory used for
+    /// building the inverted index with [`LexiconBuilder::set_index_memory_budget`].
+    ///
+    /// The normalization of the lexicon builder, if any, is recorded in the container metadata.
+    pub fn encode_lexicon_builder<W>(file: W, mut lexbuilder: LexiconBuilder, name: String, base: Uuid, compressed: bool, order: LexiconOrder, comment: &str) -> Result<Self, EncodeError> where W: EncodeTarget {
+        let vectype = if compressed { components::Type::VectorComp } else { components::Type::Vector };
+
+        lexbuilder.sort(order);
+        let sorted = lexbuilder.order() != LexiconOrder::Frequency;
+
+        let bigrams = lexbuilder.bigram_builder();
+
+        let mut metadata = Vec::new();
+        if sorted {
+            metadata.push(("lexicon_order", order.name().to_owned()));
+        }
+        if let Some(normalization) = lexbuilder.normalization() {
+            metadata.push(("normalization", normalization.name().to_owned()));
+        }
+        if let Some(bigrams) = bigrams.as_ref() {
+            metadata.push(("bigram_min_frequency", bigrams.min_frequency().to_string()));
+        }
+
+        let wavelet_tree = lexbuilder.wavelet_tree();
+        let hash_table = lexbuilder.hash_table();
 
-        let builder = ContainerBuilder::new_into_file(name, file, 4)
+        let capacity = 6 + 2 * bigrams.is_some() as u8 + wavelet_tree as u8 + hash_table as u8 + !metadata.is_empty() as u8;
+        let mut builder = ContainerBuilder::new_into_file(name, file, capacity + ContainerBuilder::comment_capacity(comment))
             .edit_header(| h | {
-                h.comment(comment)
-                    .ziggurat_type(container::Type::IndexedStringVariable)
+                h.ziggurat_type(container::Type::IndexedStringVariable)
                     .dim1(lexbuilder.tokens())
                     .dim2(lexbuilder.types())
                     .base1(Some(base));
             })
             .add_component("Lexicon", components::Type::StringVector, | bom_entry, file | {
                 unsafe {
-                    lexbuilder.write_lexicon(file, bom_entry, bom_entry.offset as u64);
+                    lexbuilder.write_lexicon(file, bom_entry, bom_entry.offset as u64)
                 }
             })
             .add_component("LexHash", components::Type::Index, | bom_entry, file | {
                 unsafe {
-                    lexbuilder.write_index(file, bom_entry, bom_entry.offset as u64);
+                    lexbuilder.write_index(file, bom_entry, bom_entry.offset as u64)
+                }
+            })
+            .add_component("LexHashBloom", components::Type::Vector, | bom_entry, file | {
+                unsafe {
+                    lexbuilder.write_index_bloom(file, bom_entry, bom_entry.offset as u64)
                 }
             })
             .add_component("LexIDStream", vectype, | bom_entry, file | {
                 unsafe {
-                    lexbuilder.write_id_stream(file, bom_entry, bom_entry.offset as u64, compressed);
+                    lexbuilder.write_id_stream(file, bom_entry, bom_entry.offset as u64, compressed)
                 }
             })
+            .checked()?;
+
+        // the indices are built from passes over the LexIDStream written above, so the
+        // builder's copy is not kept in memory alongside the postings
+        lexbuilder.release_id_stream();
+        let (streambom, streamstorage) = builder.map_component(3)?;
+        let id_stream = Component::from_raw_parts(&streambom, streamstorage.bytes().as_ptr()).unwrap().into_vector().unwrap();
+        let id_stream = CachedVector::<1>::new(id_stream).unwrap();
+
+        builder = builder
             .add_component("LexIDIndex", components::Type::InvertedIndex, | bom_entry, file | {
-                lexbuilder.write_inverted_index(file, bom_entry, bom_entry.offset as u64);
+                lexbuilder.write_inverted_index(&id_stream, file, bom_entry, bom_entry.offset as u64)
+            })
+            .add_component("Freqs", components::Type::Vector, | bom_entry, file | {
+                unsafe {
+                    lexbuilder.write_frequencies(file, bom_entry, bom_entry.offset as u64)
+                }
             });
 
-        builder.build().try_into().expect("IndexedStringVariable returned by its constructor is inconsistent")
+        if let Some(bigrams) = bigrams.as_ref() {
+            builder = builder
+                .add_component("BigramKeys", components::Type::Index, | bom_entry, file | {
+                    unsafe {
+                        bigrams.write_keys(file, bom_entry, bom_entry.offset as u64)
+                    }
+                })
+                .add_component("BigramIndex", components::Type::InvertedIndex, | bom_entry, file | {
+                    lexbuilder.write_bigram_index(bigrams, &id_stream, file, bom_entry, bom_entry.offset as u64)
+                });
+        }
+        if wavelet_tree {
+            builder = builder
+                .add_component("LexIDWavelet", components::Type::Vector, | bom_entry, file | {
+                    unsafe {
+                        lexbuilder.write_wavelet_tree(&id_stream, file, bom_entry, bom_entry.offset as u64)
+                    }
+                });
+        }
+        if hash_table {
+            builder = builder
+                .add_component("LexHashTable", components::Type::Vector, | bom_entry, file | {
+                    unsafe {
+                        lexbuilder.write_hash_table(file, bom_entry, bom_entry.offset as u64)
+                    }
+                });
+        }
+        if !metadata.is_empty() {
+            builder = builder.metadata(&metadata);
+        }
+
+        Ok(builder.comment(comment).build()?.try_into().expect("IndexedStringVariable returned by its constructor is inconsistent"))
     }
 
-    pub fn get(&self, index: usize) -> Option<&str> {
+    pub fn get(&self, index: usize) -> Option<&'map str> {
         if index < self.lex_id_stream.len() {
             let ti = self.lex_id_stream.get_row_unchecked(index)[0];
             self.lexicon.get(ti as usize)
@@ -112,9 +652,28 @@ impl<'map> IndexedStringVariable<'map> {
         }
     }
 
-    pub fn get_unchecked(&self, index: usize) -> &str {
+    pub fn get_unchecked(&self, index: usize) -> &'map str {
         let ti = self.lex_id_stream.get_row_unchecked(index)[0];
-        &self.lexicon[ti as usize]
+        self.lexicon.get_unchecked(ti as usize)
+    }
+
+    /// Returns the strings at all `indices` in the given order, `None` if any is out of
+    /// bounds. Each block of the ID stream is decoded once, see [`CachedVector::get_rows`].
+    pub fn get_many(&self, indices: &[usize]) -> Option<Vec<&'map str>> {
+        self.get_many_ids(indices)?
+            .into_iter()
+            .map(|ti| self.lexicon.get(ti))
+            .collect()
+    }
+
+    /// Returns the string at `index` with invalid UTF-8 replaced, for inspecting corrupt data.
+    pub fn get_lossy(&self, index: usize) -> Option<Cow<'map, str>> {
+        self.lexicon.get_lossy(self.get_id(index)?)
+    }
+
+    /// Checks that all types in the lexicon are valid UTF-8.
+    pub fn validate_utf8(&self) -> Result<(), InvalidUtf8> {
+        self.lexicon.validate_utf8()
     }
 
     pub fn get_id(&self, index: usize) -> Option<usize> {
@@ -125,8 +684,36 @@ impl<'map> IndexedStringVariable<'map> {
         }
     }
 
+    /// Returns the IDs at all `indices` in the given order, `None` if any is out of bounds
+    pub fn get_many_ids(&self, indices: &[usize]) -> Option<Vec<usize>> {
+        let rows = self.lex_id_stream.get_rows(indices)?;
+        Some(rows.into_iter().map(|[ti]| ti as usize).collect())
+    }
+
+    /// Returns the ID at `index`, read from the wavelet tree if the variable has one, which
+    /// avoids decoding a whole block of a compressed ID stream.
     pub fn get_id_unchecked(&self, index: usize) -> usize {
-        self.lex_id_stream.get_row_unchecked(index)[0] as usize
+        match self.wavelet.as_ref().and_then(|wavelet| wavelet.get(index)) {
+            Some(id) => id,
+            None => self.lex_id_stream.get_row_unchecked(index)[0] as usize,
+        }
+    }
+
+    /// Wavelet tree over the ID stream, if the variable was encoded with one, see
+    /// [`LexiconBuilder::set_wavelet_tree`]
+    pub fn wavelet_tree(&self) -> Option<&WaveletTree<'map>> {
+        self.wavelet.as_ref()
+    }
+
+    /// Number of occurrences of type `type_id` in the positions `start..end`.
+    ///
+    /// With a wavelet tree this takes a few ranks regardless of the range, otherwise the
+    /// postings of the type inside the range are counted.
+    pub fn count_in_range(&self, type_id: usize, start: usize, end: usize) -> usize {
+        match self.wavelet.as_ref() {
+            Some(wavelet) => wavelet.count_in_range(type_id, start, end),
+            None => self.lex_id_index.positions_within(type_id, start, end).map_or(0, Iterator::count),
+        }
     }
 
     pub fn get_range(&self, start: usize, end: usize) -> Option<IndexedStringIterator<'map>> {
@@ -137,6 +724,7 @@ impl<'map> IndexedStringVariable<'map> {
         self.lex_id_stream.clone()
     }
 
+    /// Hash index from the FNV hash of each type to its lexicon ID, see [`lookup_str`](Self::lookup_str)
     pub fn index(&self) -> components::CachedIndex<'map> {
         self.lex_hash.clone()
     }
@@ -145,6 +733,170 @@ impl<'map> IndexedStringVariable<'map> {
         self.lex_id_index.clone()
     }
 
+    /// Index of the pairs of adjacent types, if the variable was encoded with one, see
+    /// [`LexiconBuilder::set_bigram_index`]
+    pub fn bigram_index(&self) -> Option<&BigramIndex<'map>> {
+        self.bigrams.as_ref()
+    }
+
+    /// Number of positions at which type `first` is directly followed by type `second`,
+    /// `None` unless the bigram index covers the pair
+    pub fn bigram_frequency(&self, first: usize, second: usize) -> Option<usize> {
+        let bigrams = self.bigrams.as_ref()?;
+        bigrams.covers(self.frequency(first)?, self.frequency(second)?)
+            .then(|| bigrams.frequency(first, second))
+    }
+
+    /// Positions at which type `first` is directly followed by type `second`, ascending.
+    ///
+    /// Pairs covered by the bigram index are read from a single postings list, all others
+    /// by checking the type following each position of the rarer of both types.
+    pub fn bigram_positions(&self, first: usize, second: usize) -> Vec<usize> {
+        if self.bigram_frequency(first, second).is_some() {
+            let bigrams = self.bigrams.as_ref().unwrap();
+            return match bigrams.bigram_id(first, second) {
+                Some(id) => bigrams.postings().positions(id).map_or_else(Vec::new, Iterator::collect),
+                None => Vec::new(),
+            };
+        }
+
+        let index = &self.lex_id_index;
+        let (Some(f1), Some(f2)) = (index.frequency(first), index.frequency(second)) else {
+            return Vec::new();
+        };
+        if f1 <= f2 {
+            index.positions(first).into_iter().flatten()
+                .filter(|&p| p + 1 < self.len() && self.get_id(p + 1) == Some(second))
+                .collect()
+        } else {
+            index.positions(second).into_iter().flatten()
+                .filter_map(|p| p.checked_sub(1))
+                .filter(|&p| self.get_id(p) == Some(first))
+                .collect()
+        }
+    }
+
+    /// Draws `n` distinct positions of the types matching `regex`, in ascending order.
+    /// `None` if `regex` is invalid.
+    ///
+    /// The sample is drawn over the summed frequencies of the matching types, so only the
+    /// postings lists of types that are actually hit are decoded.
+    pub fn sample_matches(&self, regex: &str, n: usize, seed: u64) -> Option<Vec<usize>> {
+        let types: Vec<_> = self.lexicon.all_matching_regex(regex)?
+            .map(|tid| (tid, self.lex_id_index.frequency(tid).unwrap_or(0)))
+            .collect();
+
+        let total = types.iter().map(|(_, f)| f).sum();
+        let mut ranks = sample::random_indices(total, n, seed).into_iter().peekable();
+        let mut positions = Vec::new();
+        let mut offset = 0;
+
+        for (tid, f) in types {
+            while let Some(r) = ranks.next_if(|&r| r < offset + f) {
+                positions.extend(self.lex_id_index.position(tid, r - offset));
+            }
+            offset += f;
+        }
+
+        positions.sort_unstable();
+        Some(positions)
+    }
+
+    /// Number of ranges of `segments` containing type `type_id` at least once, `None` for unknown types.
+    ///
+    /// Looks up the containing range of each position in the postings list, positions
+    /// outside of all ranges are not counted.
+    pub fn document_frequency(&self, type_id: usize, segments: &SegmentationLayer) -> Option<usize> {
+        let positions = self.lex_id_index.positions(type_id)?;
+
+        // postings are sorted, so all positions of a range are adjacent
+        let mut last = None;
+        let mut df = 0;
+        for range in segments.find_containing_iter(positions).flatten() {
+            if last != Some(range) {
+                last = Some(range);
+                df += 1;
+            }
+        }
+
+        Some(df)
+    }
+
+    /// Occurrences of type `type_id` per value of `variable` of the segmentation layer
+    /// `segments`, e.g. per year or genre. Unknown types have no occurrences.
+    pub fn frequency_by<'s>(&self, type_id: usize, segments: &LayerData<'s, SegmentationLayer<'s>>, variable: &str) -> Result<GroupCounts<'s>, SelectionError> {
+        let positions = self.lex_id_index.positions(type_id);
+        GroupCounts::new(segments, variable, positions.into_iter().flatten())
+    }
+
+    /// Document frequencies of all types, indexed by type ID, see [`document_frequency`](Self::document_frequency).
+    ///
+    /// Walks the ID stream once instead of the postings of every type.
+    pub fn document_frequencies(&self, segments: &SegmentationLayer) -> Vec<usize> {
+        let mut df = vec![0; self.n_types()];
+        let mut last_seen = vec![usize::MAX; self.n_types()];
+
+        for (i, (start, end)) in segments.iter().enumerate() {
+            let ids = self.lex_id_stream.column_iter_range(start, end.min(self.len()), 0);
+            for id in ids.into_iter().flatten() {
+                let id = id as usize;
+                if last_seen[id] != i {
+                    last_seen[id] = i;
+                    df[id] += 1;
+                }
+            }
+        }
+
+        df
+    }
+
+    /// Frequencies of the types in range `index` of `segments` weighted by inverse document
+    /// frequency, as pairs of type ID and `tf * ln(n / df)` sorted by type ID.
+    ///
+    /// `df` are the document frequencies of all types in the same layer, as returned by
+    /// [`document_frequencies`](Self::document_frequencies), so they are only computed once
+    /// for all ranges.
+    pub fn tf_idf(&self, segments: &SegmentationLayer, index: usize, df: &[usize]) -> Option<Vec<(usize, f64)>> {
+        let (start, end) = segments.get(index)?;
+        let ids = self.lex_id_stream.column_iter_range(start, end.min(self.len()), 0)?;
+
+        let mut tf = BTreeMap::new();
+        for id in ids {
+            *tf.entry(id as usize).or_insert(0usize) += 1;
+        }
+
+        let n = segments.len() as f64;
+        Some(tf.into_iter()
+            .map(|(id, f)| {
+                let df = df.get(id).copied().unwrap_or(0).max(1);
+                (id, f as f64 * (n / df as f64).ln())
+            })
+            .collect())
+    }
+
+    /// Runs `scan` over the variable in chunks of about `chunk_len` positions on all cores
+    /// and merges the per-chunk results, `None` for an empty variable.
+    ///
+    /// `scan` receives the start position of its chunk and an iterator over its strings.
+    /// Each chunk decodes its blocks through a cache of its own.
+    #[cfg(feature = "parallel")]
+    pub fn par_scan<T, F, M>(&self, chunk_len: usize, scan: F, merge: M) -> Option<T>
+    where
+        T: Send,
+        F: Fn(usize, IndexedStringIterator<'map>) -> T + Sync,
+        M: Fn(T, T) -> T + Sync,
+    {
+        let ids = self.lex_id_stream.vector();
+        let lexicon = self.lexicon;
+
+        par_chunks(self.len(), chunk_len, |start, end| {
+            let ids = CachedVector::<1>::new(ids).unwrap()
+                .column_iter_range(start, end, 0)
+                .unwrap();
+            scan(start, IndexedStringIterator { lexicon, ids })
+        }, merge)
+    }
+
     pub fn iter(&self) -> IndexedStringIterator<'map> {
         self.get_range(0, self.len()).unwrap()
     }
@@ -160,6 +912,116 @@ impl<'map> IndexedStringVariable<'map> {
     pub fn n_types(&self) -> usize {
         self.header.dim2()
     }
+
+    /// Number of occurrences of type `type_id`, `None` for unknown types.
+    ///
+    /// Read from the "Freqs" component if present, otherwise from the inverted index.
+    pub fn frequency(&self, type_id: usize) -> Option<usize> {
+        match self.freqs {
+            Some(freqs) => freqs.get(type_id).map(|&f| f as usize),
+            None => self.lex_id_index.frequency(type_id),
+        }
+    }
+
+    /// Lexicon ID of `string`, `None` if it does not occur.
+    ///
+    /// Lexicons sorted by bytes are binary searched, others are looked up with [`lookup_str`](Self::lookup_str).
+    pub fn type_id(&self, string: &str) -> Option<usize> {
+        if self.order == Some(LexiconOrder::Bytes) {
+            return self.lexicon.binary_search(string).ok();
+        }
+
+        self.lookup_str(string)
+    }
+
+    /// Lexicon ID of `string` looked up in the hash index, `None` if it does not occur.
+    ///
+    /// Different types may have the same FNV hash, so the strings of all candidates with
+    /// the hash of `string` are compared until one is equal. Use this instead of the raw
+    /// [`index`](Self::index), whose first value for a hash may belong to another type.
+    pub fn lookup_str(&self, string: &str) -> Option<usize> {
+        let hash = string.fnv_hash();
+        match self.lex_hash_table {
+            Some(table) => table.get_all(hash).find(|&id| self.lexicon.get(id) == Some(string)),
+            None => self.lex_hash.get_all(hash)
+                .map(|id| id as usize)
+                .find(|&id| self.lexicon.get(id) == Some(string)),
+        }
+    }
+
+    /// Open addressing hash table for [`lookup_str`](Self::lookup_str), only written for
+    /// large lexicons, see [`LexiconBuilder::set_hash_table_threshold`]
+    pub fn hash_table(&self) -> Option<HashTable<'map>> {
+        self.lex_hash_table
+    }
+
+    /// Range of the lexicon IDs of all types starting with `prefix`, `None` if the lexicon
+    /// is not sorted by bytes
+    pub fn types_with_prefix(&self, prefix: &str) -> Option<ops::Range<usize>> {
+        (self.order == Some(LexiconOrder::Bytes)).then(|| self.lexicon.prefix_range(prefix))
+    }
+
+    /// Order of the lexicon as stored in the container metadata, `None` if the order is
+    /// unknown or not available in this build
+    pub fn lexicon_order(&self) -> Option<LexiconOrder> {
+        self.order
+    }
+
+    /// Unicode normalization the types were encoded in, `None` if none is recorded.
+    ///
+    /// Strings looked up in a normalized lexicon, e.g. with [`type_id`](Self::type_id), have
+    /// to be in the same form.
+    pub fn normalization(&self) -> Option<Normalization> {
+        self.normalization
+    }
+
+    /// IDs of all types `t` with `start <= t < end` in the sort order of the lexicon,
+    /// ascending in that order.
+    ///
+    /// The types of a sorted lexicon are numbered in sort order, so the lexicon is binary
+    /// searched. `None` for lexicons in frequency order or an order not available in this build.
+    pub fn types_in_range(&self, start: &str, end: &str) -> Option<ops::Range<usize>> {
+        let mut cmp = self.order?.comparator()?;
+
+        // first ID whose type is not less than `bound`
+        let mut lower_bound = |bound: &str| {
+            let (mut lo, mut hi) = (0, self.lexicon.len());
+            while lo < hi {
+                let mid = lo + (hi - lo) / 2;
+                let t = self.lexicon.get_unchecked(mid);
+                if cmp(t, bound) == std::cmp::Ordering::Less {
+                    lo = mid + 1;
+                } else {
+                    hi = mid;
+                }
+            }
+            lo
+        };
+
+        let first = lower_bound(start);
+        let last = lower_bound(end).max(first);
+        Some(first..last)
+    }
+
+    /// Prepares the positions `start..end` for access, e.g. before rendering a concordance page.
+    ///
+    /// Advises the OS to read the backing pages and decodes the compressed blocks of the
+    /// lexicon ID stream into the block cache on a background thread.
+    pub fn prefetch_range(&self, start: usize, end: usize) {
+        storage::will_need(&*self.storage, self.lex_id_stream.raw_range(start, end));
+        self.lex_id_stream.prefetch(start, end);
+    }
+
+    /// Decodes the postings lists of the given types into the postings cache ahead of access.
+    pub fn prefetch_postings(&self, type_ids: &[usize]) {
+        self.lex_id_index.prefetch(type_ids);
+    }
+
+    /// Decodes the postings lists of the given types in the background while `f` runs, see
+    /// [`CachedInvertedIndex::prefetch_while`](components::CachedInvertedIndex::prefetch_while).
+    pub fn prefetch_postings_while<R, F: FnOnce() -> R>(&self, type_ids: &[usize], f: F) -> R {
+        self.lex_id_index.prefetch_while(type_ids, f)
+    }
 }
 
 impl<'map> TryFrom<Container<'map>> for IndexedStringVariable<'map> {
@@ -183,7 +1045,20 @@ impl<'map> TryFrom<Container<'map>> for IndexedStringVariable<'map> {
                 if lex_hash.len() != v {
                     return Err(Self::Error::WrongComponentDimensions("LexHash"));
                 }
-                let lex_hash = CachedIndex::new(lex_hash);
+                let lex_hash = with_optional_bloom(&container, CachedIndex::new(lex_hash), "LexHashBloom")?;
+
+                // optional, only written for lexicons above a size threshold
+                let lex_hash_table = match container.get_component("LexHashTable") {
+                    Some(component) => {
+                        let vector = component.into_vector()
+                            .map_err(|_| Self::Error::WrongComponentType("LexHashTable"))?;
+                        match HashTable::from_vector(vector) {
+                            Some(table) if table.capacity() > v => Some(table),
+                            _ => return Err(Self::Error::WrongComponentDimensions("LexHashTable")),
+                        }
+                    }
+                    None => None,
+                };
 
                 let lex_id_stream = check_and_return_component!(container, "LexIDStream", Vector)?;
                 if lex_id_stream.len() != n || lex_id_stream.width() != 1 {
@@ -199,17 +1074,72 @@ impl<'map> TryFrom<Container<'map>> for IndexedStringVariable<'map> {
                 }
                 let lex_id_index = Rc::new(CachedInvertedIndex::new(lex_id_index));
 
-                let (name, mmap, header, _) = container.into_raw_parts();
+                // optional, containers written before it was introduced fall back to the inverted index
+                let freqs = match container.get_component("Freqs") {
+                    Some(component) => match component.into_vector() {
+                        Ok(Vector::Uncompressed { length, width: 1, data }) if length == v => Some(data),
+                        Ok(_) => return Err(Self::Error::WrongComponentDimensions("Freqs")),
+                        Err(_) => return Err(Self::Error::WrongComponentType("Freqs")),
+                    },
+                    None => None,
+                };
+                let metadata = container.metadata();
+                let order = metadata.iter()
+                    .find(|(key, _)| *key == "lexicon_order")
+                    .and_then(|(_, name)| LexiconOrder::from_name(name));
+                let normalization = metadata.iter()
+                    .find(|(key, _)| *key == "normalization")
+                    .and_then(|(_, name)| Normalization::from_name(name));
+
+                // optional, without a recorded minimum frequency no pair is known to be covered
+                let bigrams = match (container.get_component("BigramKeys"), container.get_component("BigramIndex")) {
+                    (Some(keys), Some(postings)) => {
+                        let keys = keys.into_index()
+                            .map_err(|_| Self::Error::WrongComponentType("BigramKeys"))?;
+                        let postings = postings.into_inverted_index()
+                            .map_err(|_| Self::Error::WrongComponentType("BigramIndex"))?;
+                        if keys.len() != postings.n_types() {
+                            return Err(Self::Error::WrongComponentDimensions("BigramIndex"));
+                        }
+                        let min_frequency = metadata.iter()
+                            .find(|(key, _)| *key == "bigram_min_frequency")
+                            .and_then(|(_, value)| value.parse().ok())
+                            .unwrap_or(usize::MAX);
+                        Some(BigramIndex::new(keys, postings, min_frequency))
+                    }
+                    _ => None,
+                };
+                let wavelet = match container.get_component("LexIDWavelet") {
+                    Some(component) => {
+                        let vector = component.into_vector()
+                            .map_err(|_| Self::Error::WrongComponentType("LexIDWavelet"))?;
+                        match WaveletTree::from_vector(vector, n) {
+                            Some(wavelet) if n == 0 || wavelet.levels() == WaveletTree::levels_for(v) => Some(wavelet),
+                            _ => return Err(Self::Error::WrongComponentDimensions("LexIDWavelet")),
+                        }
+                    }
+                    None => None,
+                };
+
+                let comment = container.comment();
+                let (name, storage, header, _) = container.into_raw_parts();
 
                 Ok(Self {
                     base,
-                    mmap,
+                    storage,
                     name,
                     header,
+                    comment,
                     lexicon,
                     lex_hash,
+                    lex_hash_table,
                     lex_id_stream,
                     lex_id_index,
+                    order,
+                    normalization,
+                    freqs,
+                    bigrams,
+                    wavelet,
                 })
             }
 
@@ -244,8 +1174,30 @@ impl<'map> Iterator for IndexedStringIterator<'map> {
                 self.lexicon.get(id as usize)
             })
     }
+
+    fn nth(&mut self, n: usize) -> Option<Self::Item> {
+        self.ids.nth(n)
+            .and_then(| id | {
+                self.lexicon.get(id as usize)
+            })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.ids.size_hint()
+    }
+}
+
+impl<'map> DoubleEndedIterator for IndexedStringIterator<'map> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.ids.next_back()
+            .and_then(| id | {
+                self.lexicon.get(id as usize)
+            })
+    }
 }
 
+impl<'map> ExactSizeIterator for IndexedStringIterator<'map> {}
+
 impl<'a, 'map> IntoIterator for &'a IndexedStringVariable<'map> {
     type Item = &'map str;
     type IntoIter = IndexedStringIterator<'map>;
@@ -258,35 +1210,79 @@ impl<'a, 'map> IntoIterator for &'a IndexedStringVariable<'map> {
 #[derive(Debug)]
 pub struct PlainStringVariable<'map> {
     base: Uuid,
-    mmap: Mmap,
+    storage: Box<dyn Storage>,
     pub name: String,
-    pub header: &'map container::Header,
+    pub(crate) header: &'map container::Header,
+    comment: Option<&'map str>,
     string_data: components::StringList<'map>,
     offset_stream: components::CachedVector<'map, 1>,
     string_hash: components::CachedIndex<'map>,
+    normalization: Option<Normalization>,
 }
 
 impl<'map> PlainStringVariable<'map> {
-    pub fn encode_to_file<I>(file: File, strings: I, n: usize, name: String, base: Uuid, compressed: bool, comment: &str) -> Self where I: Iterator<Item=String> {
-        let vectype = if compressed { components::Type::VectorDelta } else { components::Type::Vector };
-        let idxtype = if compressed { components::Type::IndexComp } else { components::Type::Index };
+    pub fn comment(&self) -> Option<&'map str> {
+        self.comment
+    }
 
-        let mut offsets = Vec::with_capacity(n + 1);
-        offsets.push(0);
+    pub fn uuid(&self) -> Uuid {
+        self.header.uuid()
+    }
 
-        let mut hashes = Vec::with_capacity(n);
+    /// UUID of the layer this variable annotates
+    pub fn base(&self) -> Uuid {
+        self.base
+    }
 
-        let builder = ContainerBuilder::new_into_file(name, file, 3)
-            .edit_header(| h | {
-                h.comment(comment)
-                    .ziggurat_type(container::Type::PlainStringVariable)
-                    .dim1(n)
-                    .dim2(0)
-                    .base1(Some(base));
-            })
-            .add_component("StringData", components::Type::StringList, | bom_entry, file | {
+    pub fn cache_stats(&self) -> CacheStats {
+        self.offset_stream.cache_stats()
+            + self.string_hash.cache_stats()
+    }
+
+    /// Unicode normalization the strings were encoded in, `None` if none is recorded
+    pub fn normalization(&self) -> Option<Normalization> {
+        self.normalization
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "info", skip_all, fields(name = %name, n = n, compressed = compressed)))]
+    pub fn encode_to_file<W, I>(file: W, strings: I, n: usize, name: String, base: Uuid, compressed: bool, comment: &str) -> Result<Self, EncodeError> where W: EncodeTarget, I: Iterator<Item=String> {
+        Self::encode(file, strings, n, name, base, compressed, None, comment)
+    }
+
+    /// Encodes the strings in the Unicode `normalization` form and records it in the
+    /// container metadata.
+    #[cfg(feature = "normalization")]
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "info", skip_all, fields(name = %name, n = n, compressed = compressed)))]
+    pub fn encode_to_file_normalized<W, I>(file: W, strings: I, n: usize, name: String, base: Uuid, compressed: bool, normalization: Normalization, comment: &str) -> Result<Self, EncodeError> where W: EncodeTarget, I: Iterator<Item=String> {
+        // strings already in the normalization form are passed on without a copy
+        let strings = strings.map(|s| match normalization.normalize(&s) {
+            Cow::Borrowed(_) => s,
+            Cow::Owned(normalized) => normalized,
+        });
+        Self::encode(file, strings, n, name, base, compressed, Some(normalization), comment)
+    }
+
+    /// Encodes `strings` as they are, `normalization` is only recorded in the metadata
+    fn encode<W, I>(file: W, strings: I, n: usize, name: String, base: Uuid, compressed: bool, normalization: Option<Normalization>, comment: &str) -> Result<Self, EncodeError> where W: EncodeTarget, I: Iterator<Item=String> {
+        let vectype = if compressed { components::Type::VectorDelta } else { components::Type::Vector };
+        let idxtype = if compressed { components::Type::IndexComp } else { components::Type::Index };
+
+        let mut offsets = Vec::with_capacity(n + 1);
+        offsets.push(0);
+
+        let mut hashes = Vec::with_capacity(n);
+
+        let capacity = 4 + normalization.is_some() as u8;
+        let mut builder = ContainerBuilder::new_into_file(name, file, capacity + ContainerBuilder::comment_capacity(comment))
+            .edit_header(| h | {
+                h.ziggurat_type(container::Type::PlainStringVariable)
+                    .dim1(n)
+                    .dim2(0)
+                    .base1(Some(base));
+            })
+            .add_component("StringData", components::Type::StringList, | bom_entry, file | {
                 let start_offset = bom_entry.offset as u64;
-                file.seek(SeekFrom::Start(start_offset)).unwrap();
+                file.seek(SeekFrom::Start(start_offset))?;
 
                 let mut writer = BufWriter::new(file);
 
@@ -296,8 +1292,8 @@ impl<'map> PlainStringVariable<'map> {
                 for (i, s) in strings.take(n).enumerate() {
                     let bytes = s.as_bytes();
 
-                    writer.write_all(bytes).unwrap();
-                    writer.write_all(&[0]).unwrap();
+                    writer.write_all(bytes)?;
+                    writer.write_all(&[0])?;
 
                     // offset
                     if let Some(offset) = offsets.last() {
@@ -309,18 +1305,23 @@ impl<'map> PlainStringVariable<'map> {
                     hashes.push((hash, i as i64));
                 }
 
-                assert!(offsets.len() == n + 1, "found fewer tokens than layer size");
+                writer.flush()?;
+
+                if offsets.len() != n + 1 {
+                    return Err(EncodeError::LengthMismatch { expected: n, found: offsets.len() - 1 });
+                }
 
                 bom_entry.size = *offsets.last().unwrap();
                 bom_entry.param1 = n as i64;
                 bom_entry.param2 = 0;
+                Ok(())
             })
             .add_component("OffsetStream", vectype, | bom_entry, file | {
                 unsafe {
                     if compressed {
-                        Vector::encode_delta_to_container_file(offsets.into_iter().map(|i| [i]), n + 1, file, bom_entry, bom_entry.offset as u64);
+                        Vector::encode_delta_to_container_file(offsets.into_iter().map(|i| [i]), n + 1, file, bom_entry, bom_entry.offset as u64)
                     } else {
-                        Vector::encode_uncompressed_to_container_file(offsets.into_iter(), n + 1, 1, file, bom_entry, bom_entry.offset as u64);
+                        Vector::encode_uncompressed_to_container_file(offsets.into_iter(), n + 1, 1, file, bom_entry, bom_entry.offset as u64)
                     }
                 }
             })
@@ -329,14 +1330,25 @@ impl<'map> PlainStringVariable<'map> {
 
                 unsafe {
                     if compressed {
-                        Index::encode_compressed_to_container_file(hashes.into_iter(), n, file, bom_entry, bom_entry.offset as u64);
+                        Index::encode_compressed_to_container_file(hashes.iter().copied(), n, file, bom_entry, bom_entry.offset as u64)
                     } else {
-                        Index::encode_uncompressed_to_container_file(hashes.into_iter(), n, file, bom_entry, bom_entry.offset as u64);
+                        Index::encode_uncompressed_to_container_file(hashes.iter().copied(), n, file, bom_entry, bom_entry.offset as u64)
                     }
                 }
+            })
+            .add_component("StringBloom", components::Type::Vector, | bom_entry, file | {
+                // the hashes are sorted now, so repeated strings are adjacent
+                let distinct = hashes.chunk_by(|(a, _), (b, _)| a == b).map(|chunk| chunk[0].0);
+                unsafe {
+                    BloomFilter::encode_to_container_file(distinct.clone(), distinct.count(), file, bom_entry, bom_entry.offset as u64)
+                }
             });
 
-        builder.build().try_into().expect("PlainStringVariable returned by its constructor is inconsistent")
+        if let Some(normalization) = normalization {
+            builder = builder.metadata(&[("normalization", normalization.name())]);
+        }
+
+        Ok(builder.comment(comment).build()?.try_into().expect("PlainStringVariable returned by its constructor is inconsistent"))
     }
 
     pub fn get(&self, index: usize) -> Option<&'map str> {
@@ -354,13 +1366,81 @@ impl<'map> PlainStringVariable<'map> {
         unsafe { std::str::from_utf8_unchecked(&self.string_data.data()[start..end - 1]) }
     }
 
-    pub fn iter(&'map self) -> PlainStringIterator<'map> {
+    /// Returns the strings at all `indices` in the given order, `None` if any is out of
+    /// bounds. Both offsets of each string are gathered from the offset stream in one go.
+    pub fn get_many(&self, indices: &[usize]) -> Option<Vec<&'map str>> {
+        if indices.iter().any(|i| *i >= self.len()) {
+            return None;
+        }
+        let bounds: Vec<usize> = indices.iter().flat_map(|i| [*i, i + 1]).collect();
+        let offsets = self.offset_stream.get_rows(&bounds)?;
+
+        let data = self.string_data.data();
+        Some(offsets.chunks_exact(2)
+            .map(|o| unsafe { std::str::from_utf8_unchecked(&data[o[0][0] as usize..o[1][0] as usize - 1]) })
+            .collect())
+    }
+
+    /// Positions with the value `string`, ascending, looked up in the hash index
+    pub fn positions(&self, string: &str) -> Vec<usize> {
+        self.string_hash.get_all(string.fnv_hash())
+            .map(|position| position as usize)
+            .filter(|&position| self.get(position) == Some(string))
+            .collect()
+    }
+
+    /// Returns the string at `index` with invalid UTF-8 replaced, for inspecting corrupt data.
+    pub fn get_lossy(&self, index: usize) -> Option<Cow<'map, str>> {
+        if index >= self.len() {
+            return None;
+        }
+        let start = self.offset_stream.get_row_unchecked(index)[0] as usize;
+        let end = self.offset_stream.get_row_unchecked(index + 1)[0] as usize;
+
+        let bytes = self.string_data.data().get(start..end.checked_sub(1)?)?;
+        Some(String::from_utf8_lossy(bytes))
+    }
+
+    /// Checks that all strings are valid UTF-8 in a single pass over the string data.
+    pub fn validate_utf8(&self) -> Result<(), InvalidUtf8> {
+        components::validate_utf8(self.string_data.data(), self.offset_stream.column_iter(0).map(|o| o as usize))
+    }
+
+    pub fn get_range(&self, start: usize, end: usize) -> Option<PlainStringIterator<'map>> {
+        if start <= end && end <= self.len() {
+            Some(PlainStringIterator {
+                string_data: self.string_data,
+                offset_stream: self.offset_stream.clone(),
+                len: end,
+                index: start,
+            })
+        } else {
+            None
+        }
+    }
+
+    pub fn iter(&self) -> PlainStringIterator<'map> {
         self.into_iter()
     }
 
     pub fn len(&self) -> usize {
         self.header.dim1()
     }
+
+    pub fn prefetch_range(&self, start: usize, end: usize) {
+        let end = usize::min(end, self.len());
+        if start >= end {
+            return;
+        }
+
+        // the offsets bounding the strings are decoded right away, the rest in the background
+        let data_start = self.offset_stream.get_row_unchecked(start)[0] as usize;
+        let data_end = self.offset_stream.get_row_unchecked(end)[0] as usize;
+        storage::will_need(&*self.storage, &self.string_data.data()[data_start..data_end]);
+
+        storage::will_need(&*self.storage, self.offset_stream.raw_range(start, end + 1));
+        self.offset_stream.prefetch(start, end + 1);
+    }
 }
 
 impl<'map> TryFrom<Container<'map>> for PlainStringVariable<'map> {
@@ -392,18 +1472,26 @@ impl<'map> TryFrom<Container<'map>> for PlainStringVariable<'map> {
                 if string_hash.len() != n {
                     return Err(Self::Error::WrongComponentDimensions("StringHash"));
                 }
-                let string_hash = CachedIndex::new(string_hash);
+                let string_hash = with_optional_bloom(&container, CachedIndex::new(string_hash), "StringBloom")?;
+
+                let normalization = container.metadata()
+                    .into_iter()
+                    .find(|(key, _)| *key == "normalization")
+                    .and_then(|(_, name)| Normalization::from_name(name));
 
-                let (name, mmap, header, _) = container.into_raw_parts();
+                let comment = container.comment();
+                let (name, storage, header, _) = container.into_raw_parts();
 
                 Ok(Self {
                     base,
-                    mmap,
+                    storage,
                     name,
                     header,
+                    comment,
                     string_data,
                     offset_stream,
                     string_hash,
+                    normalization,
                 })
             }
 
@@ -433,34 +1521,56 @@ impl<'map> Iterator for PlainStringIterator<'map> {
             None
         }
     }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.len - self.index;
+        (len, Some(len))
+    }
 }
 
+impl<'map> ExactSizeIterator for PlainStringIterator<'map> {}
+
 impl<'a, 'map> IntoIterator for &'a PlainStringVariable<'map> {
     type Item = &'map str;
     type IntoIter = PlainStringIterator<'map>;
 
     fn into_iter(self) -> Self::IntoIter {
-        PlainStringIterator {
-            string_data: self.string_data,
-            offset_stream: self.offset_stream.clone(),
-            len: self.len(),
-            index: 0,
-        }
+        self.get_range(0, self.len()).unwrap()
     }
 }
 
 #[derive(Debug)]
 pub struct IntegerVariable<'map> {
     base: Uuid,
-    mmap: Mmap,
+    storage: Box<dyn Storage>,
     pub name: String,
-    pub header: &'map container::Header,
+    pub(crate) header: &'map container::Header,
+    comment: Option<&'map str>,
     int_stream: components::CachedVector<'map, 1>,
     int_sort: components::CachedIndex<'map>,
 }
 
 impl<'map> IntegerVariable<'map> {
-    pub fn encode_to_file<I>(file: File, values: I, n: usize, name: String, base: Uuid, compressed: bool, delta: bool, comment: &str) -> Self where I: Iterator<Item=i64> {
+    pub fn comment(&self) -> Option<&'map str> {
+        self.comment
+    }
+
+    pub fn uuid(&self) -> Uuid {
+        self.header.uuid()
+    }
+
+    /// UUID of the layer this variable annotates
+    pub fn base(&self) -> Uuid {
+        self.base
+    }
+
+    pub fn cache_stats(&self) -> CacheStats {
+        self.int_stream.cache_stats()
+            + self.int_sort.cache_stats()
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "info", skip_all, fields(name = %name, n = n, compressed = compressed)))]
+    pub fn encode_to_file<W, I>(file: W, values: I, n: usize, name: String, base: Uuid, compressed: bool, delta: bool, comment: &str) -> Result<Self, EncodeError> where W: EncodeTarget, I: Iterator<Item=i64> {
         let vectype = if compressed { 
             if delta {
                 components::Type::VectorDelta
@@ -472,15 +1582,11 @@ impl<'map> IntegerVariable<'map> {
         };
         let idxtype = if compressed { components::Type::IndexComp } else { components::Type::Index };
 
-        // we need to load all values into memory so we can sort them later
-        // this step is very memory-intensive and could be replaced with a reverse index component later on
-        // format: [(value, index); n]
-        let mut values: Vec<(i64, i64)> = values.take(n).enumerate().map(|(i, v)| (v, i as i64)).collect();
-        
-        let mut builder = ContainerBuilder::new_into_file(name, file, 2)
+        let values = values.take(n).map(|v| [v; 1]);
+
+        let mut builder = ContainerBuilder::new_into_file(name, file, 2 + ContainerBuilder::comment_capacity(comment))
             .edit_header(| h | {
-                h.comment(comment)
-                    .ziggurat_type(container::Type::IntegerVariable)
+                h.ziggurat_type(container::Type::IntegerVariable)
                     .dim1(n)
                     .dim2(1)
                     .base1(Some(base));
@@ -488,32 +1594,41 @@ impl<'map> IntegerVariable<'map> {
             .add_component("IntStream", vectype, | bom_entry, file | {
                 unsafe {
                     if compressed {
-                        let values = values.iter().map(|(v, _)| [*v; 1]);
                         if delta {
-                            Vector::encode_delta_to_container_file(values, n, file, bom_entry, bom_entry.offset as u64);
+                            Vector::encode_delta_to_container_file(values, n, file, bom_entry, bom_entry.offset as u64)
                         } else {
-                            Vector::encode_compressed_to_container_file(values, n, file, bom_entry, bom_entry.offset as u64);
+                            Vector::encode_compressed_to_container_file(values, n, file, bom_entry, bom_entry.offset as u64)
                         }
                     } else {
-                        Vector::encode_uncompressed_to_container_file(values.iter().map(|(v, _)| *v), n, 1, file, bom_entry, bom_entry.offset as u64);
+                        Vector::encode_uncompressed_to_container_file(values.flatten(), n, 1, file, bom_entry, bom_entry.offset as u64)
                     }
                 }
             });
 
-        // sort values via their value
-        values.sort_by_key(|(v, _)| *v);
+        // the reverse index is built from a second pass over the IntStream written above,
+        // sorting it in runs of bounded size instead of keeping all values in memory
+        builder = builder.checked()?;
+        let (vecbom, vecstorage) = builder.map_component(0)?;
+
+        let int_stream = Component::from_raw_parts(&vecbom, vecstorage.bytes().as_ptr()).unwrap().into_vector().unwrap();
+        let int_stream = CachedVector::<1>::new(int_stream).unwrap();
+
+        let pairs = int_stream.column_iter(0)
+            .enumerate()
+            .map(|(i, v)| (v, i as i64));
+        let sorted = components::external_sort(pairs, SORT_RUN_LEN)?;
 
         builder = builder.add_component("IntSort", idxtype, | bom_entry, file | {
             unsafe {
                 if compressed {
-                    Index::encode_compressed_to_container_file(values.iter().copied(), n, file, bom_entry, bom_entry.offset as u64);
+                    Index::encode_compressed_to_container_file(sorted, n, file, bom_entry, bom_entry.offset as u64)
                 } else {
-                    Index::encode_uncompressed_to_container_file(values.iter().copied(), n, file, bom_entry, bom_entry.offset as u64);
+                    Index::encode_uncompressed_to_container_file(sorted, n, file, bom_entry, bom_entry.offset as u64)
                 }
             }
         });
 
-        builder.build().try_into().expect("IntegerVariable returned by its constructor is inconsistent")
+        Ok(builder.comment(comment).build()?.try_into().expect("IntegerVariable returned by its constructor is inconsistent"))
     }
 
     pub fn get(&self, index: usize) -> Option<i64> {
@@ -528,18 +1643,71 @@ impl<'map> IntegerVariable<'map> {
         self.int_sort.get_all(value)
     }
 
+    /// Positions with a value in `min..=max`, ascending
+    pub fn positions_in_range(&self, min: i64, max: i64) -> Vec<usize> {
+        let mut positions: Vec<usize> = self.int_sort.values_in_range(min, max)
+            .into_iter()
+            .map(|p| p as usize)
+            .collect();
+        positions.sort_unstable();
+        positions
+    }
+
+    /// Estimated number of positions with a value in `min..=max`, from the index only
+    pub fn estimate_range(&self, min: i64, max: i64) -> usize {
+        self.int_sort.estimate_range(min, max)
+    }
+
     pub fn get_unchecked(&self, index: usize) -> i64 {
         self.int_stream.get_row_unchecked(index)[0]
     }
 
+    /// Returns the values at all `indices` in the given order, `None` if any is out of bounds
+    pub fn get_many(&self, indices: &[usize]) -> Option<Vec<i64>> {
+        let rows = self.int_stream.get_rows(indices)?;
+        Some(rows.into_iter().map(|[value]| value).collect())
+    }
+
+    pub fn get_range(&self, start: usize, end: usize) -> Option<ColumnIterator<'map, 1>> {
+        self.int_stream.column_iter_range(start, end, 0)
+    }
+
     pub fn iter(&self) -> ColumnIterator<'map, 1> {
         self.int_stream.column_iter(0)
     }
 
+    pub fn int_stream(&self) -> components::CachedVector<'map, 1> {
+        self.int_stream.clone()
+    }
+
+    /// Runs `scan` over the variable in chunks of about `chunk_len` positions on all cores
+    /// and merges the per-chunk results, see [`IndexedStringVariable::par_scan`].
+    #[cfg(feature = "parallel")]
+    pub fn par_scan<T, F, M>(&self, chunk_len: usize, scan: F, merge: M) -> Option<T>
+    where
+        T: Send,
+        F: Fn(usize, ColumnIterator<'map, 1>) -> T + Sync,
+        M: Fn(T, T) -> T + Sync,
+    {
+        let ints = self.int_stream.vector();
+
+        par_chunks(self.len(), chunk_len, |start, end| {
+            let ints = CachedVector::<1>::new(ints).unwrap()
+                .column_iter_range(start, end, 0)
+                .unwrap();
+            scan(start, ints)
+        }, merge)
+    }
+
     pub fn len(&self) -> usize {
         self.header.dim1()
     }
 
+    pub fn prefetch_range(&self, start: usize, end: usize) {
+        storage::will_need(&*self.storage, self.int_stream.raw_range(start, end));
+        self.int_stream.prefetch(start, end);
+    }
+
     pub fn b(&self) -> usize {
         self.header.dim2()
     }
@@ -578,13 +1746,233 @@ impl<'map> TryFrom<Container<'map>> for IntegerVariable<'map> {
                 }
                 let int_sort = CachedIndex::new(int_sort);
 
-                let (name, mmap, header, _) = container.into_raw_parts();
+                let comment = container.comment();
+                let (name, storage, header, _) = container.into_raw_parts();
+
+                Ok(Self {
+                    base,
+                    storage,
+                    name,
+                    header,
+                    comment,
+                    int_stream,
+                    int_sort,
+                })
+            }
+
+            _ => Err(Self::Error::WrongContainerType),
+        }
+    }
+}
+
+/// Integer variable with several values per position, e.g. the start and end offsets of
+/// each token or a vector of morphological features.
+///
+/// It is stored like an [`IntegerVariable`] whose IntStream has more than one column and
+/// whose IntSort only indexes the first column. The width is only known at runtime, the
+/// typed accessors take it as a const parameter and fail for any other width.
+#[derive(Debug)]
+pub struct IntegerVectorVariable<'map> {
+    base: Uuid,
+    storage: Box<dyn Storage>,
+    pub name: String,
+    pub(crate) header: &'map container::Header,
+    comment: Option<&'map str>,
+    int_stream: Vector<'map>,
+    int_sort: components::CachedIndex<'map>,
+}
+
+impl<'map> IntegerVectorVariable<'map> {
+    pub fn comment(&self) -> Option<&'map str> {
+        self.comment
+    }
+
+    pub fn uuid(&self) -> Uuid {
+        self.header.uuid()
+    }
+
+    /// UUID of the layer this variable annotates
+    pub fn base(&self) -> Uuid {
+        self.base
+    }
+
+    pub fn cache_stats(&self) -> CacheStats {
+        self.int_sort.cache_stats()
+    }
+
+    /// Encodes rows of `D` values, the first column is indexed for [`get_all`](Self::get_all).
+    ///
+    /// Delta encoding suits columns that grow with the position, like offsets into a text.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "info", skip_all, fields(name = %name, n = n, width = D, compressed = compressed)))]
+    pub fn encode_to_file<W, I, const D: usize>(file: W, values: I, n: usize, name: String, base: Uuid, compressed: bool, delta: bool, comment: &str) -> Result<Self, EncodeError> where W: EncodeTarget, I: Iterator<Item=[i64; D]> {
+        let vectype = match (compressed, delta) {
+            (true, true) => components::Type::VectorDelta,
+            (true, false) => components::Type::VectorComp,
+            (false, _) => components::Type::Vector,
+        };
+        let idxtype = if compressed { components::Type::IndexComp } else { components::Type::Index };
+
+        let values = values.take(n);
+
+        let mut builder = ContainerBuilder::new_into_file(name, file, 2 + ContainerBuilder::comment_capacity(comment))
+            .edit_header(| h | {
+                h.ziggurat_type(container::Type::IntegerVariable)
+                    .dim1(n)
+                    .dim2(D)
+                    .base1(Some(base));
+            })
+            .add_component("IntStream", vectype, | bom_entry, file | {
+                unsafe {
+                    match (compressed, delta) {
+                        (true, true) => Vector::encode_delta_to_container_file(values, n, file, bom_entry, bom_entry.offset as u64),
+                        (true, false) => Vector::encode_compressed_to_container_file(values, n, file, bom_entry, bom_entry.offset as u64),
+                        (false, _) => Vector::encode_uncompressed_to_container_file(values.flatten(), n, D, file, bom_entry, bom_entry.offset as u64),
+                    }
+                }
+            });
+
+        // like for IntegerVariable the index is sorted from the IntStream written above
+        builder = builder.checked()?;
+        let (vecbom, vecstorage) = builder.map_component(0)?;
+
+        let int_stream = Component::from_raw_parts(&vecbom, vecstorage.bytes().as_ptr()).unwrap().into_vector().unwrap();
+        let int_stream = CachedVector::<D>::new(int_stream).unwrap();
+
+        let pairs = int_stream.column_iter(0)
+            .enumerate()
+            .map(|(i, v)| (v, i as i64));
+        let sorted = components::external_sort(pairs, SORT_RUN_LEN)?;
+
+        builder = builder.add_component("IntSort", idxtype, | bom_entry, file | {
+            unsafe {
+                if compressed {
+                    Index::encode_compressed_to_container_file(sorted, n, file, bom_entry, bom_entry.offset as u64)
+                } else {
+                    Index::encode_uncompressed_to_container_file(sorted, n, file, bom_entry, bom_entry.offset as u64)
+                }
+            }
+        });
+
+        Ok(builder.comment(comment).build()?.try_into().expect("IntegerVectorVariable returned by its constructor is inconsistent"))
+    }
+
+    pub fn len(&self) -> usize {
+        self.header.dim1()
+    }
+
+    /// Number of values per position
+    pub fn width(&self) -> usize {
+        self.int_stream.width()
+    }
+
+    /// Block cached access to the rows, `None` unless `D` is the width of the variable.
+    ///
+    /// Keep the returned vector for repeated access, its cache is not shared.
+    pub fn rows<const D: usize>(&self) -> Option<CachedVector<'map, D>> {
+        CachedVector::<D>::new(self.int_stream)
+    }
+
+    /// Row at `index`, `None` if it is out of bounds or `D` is not the width of the variable.
+    ///
+    /// This decodes a whole block on every call, see [`rows`](Self::rows).
+    pub fn get<const D: usize>(&self, index: usize) -> Option<[i64; D]> {
+        (D == self.width()).then_some(())?;
+        self.get_vec(index)?.try_into().ok()
+    }
+
+    /// Row at `index` of any width, `None` if it is out of bounds
+    pub fn get_vec(&self, index: usize) -> Option<Vec<i64>> {
+        (index < self.len()).then(|| self.int_stream.get_row_unchecked(index).to_vec())
+    }
+
+    pub fn get_range(&self, start: usize, end: usize) -> Option<IntegerVectorIterator<'map>> {
+        (start <= end && end <= self.len()).then_some(IntegerVectorIterator {
+            int_stream: self.int_stream,
+            block: None,
+            position: start,
+            end,
+        })
+    }
+
+    pub fn iter(&self) -> IntegerVectorIterator<'map> {
+        self.get_range(0, self.len()).unwrap()
+    }
+
+    /// Positions whose first value is `value`
+    pub fn get_all(&self, value: i64) -> components::CachedValueIterator<'map> {
+        self.int_sort.get_all(value)
+    }
+}
+
+/// Rows of an [`IntegerVectorVariable`], decoded a block at a time
+pub struct IntegerVectorIterator<'map> {
+    int_stream: Vector<'map>,
+    /// Index and rows of the current block
+    block: Option<(usize, components::VecSlice<'map>)>,
+    position: usize,
+    end: usize,
+}
+
+impl<'map> Iterator for IntegerVectorIterator<'map> {
+    type Item = Vec<i64>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.position >= self.end {
+            return None;
+        }
+
+        let width = self.int_stream.width();
+        let block_size = self.int_stream.block_size();
+        let block = self.position / block_size;
+        if self.block.as_ref().is_none_or(|(b, _)| *b != block) {
+            self.block = Some((block, self.int_stream.block_rows(block)));
+        }
+        let rows = &self.block.as_ref().unwrap().1;
+        let start = (self.position % block_size) * width;
+        self.position += 1;
+
+        Some(rows[start..start + width].to_vec())
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.end - self.position;
+        (len, Some(len))
+    }
+}
+
+impl<'map> ExactSizeIterator for IntegerVectorIterator<'map> {}
+
+impl<'map> TryFrom<Container<'map>> for IntegerVectorVariable<'map> {
+    type Error = container::TryFromError;
+
+    fn try_from(container: Container<'map>) -> Result<Self, Self::Error> {
+        let header = *container.header();
+
+        match header.container_type() {
+            container::Type::IntegerVariable => {
+                let base = get_container_base!(container, IntegerVariable);
+                let n = header.dim1();
+
+                let int_stream = check_and_return_component!(container, "IntStream", Vector)?;
+                if int_stream.len() != n || int_stream.width() == 0 {
+                    return Err(Self::Error::WrongComponentDimensions("IntStream"));
+                }
+
+                let int_sort = check_and_return_component!(container, "IntSort", Index)?;
+                if int_sort.len() != n {
+                    return Err(Self::Error::WrongComponentDimensions("IntSort"));
+                }
+                let int_sort = CachedIndex::new(int_sort);
+
+                let comment = container.comment();
+                let (name, storage, header, _) = container.into_raw_parts();
 
                 Ok(Self {
                     base,
-                    mmap,
+                    storage,
                     name,
                     header,
+                    comment,
                     int_stream,
                     int_sort,
                 })
@@ -598,9 +1986,10 @@ impl<'map> TryFrom<Container<'map>> for IntegerVariable<'map> {
 #[derive(Debug)]
 pub struct SetVariable<'map> {
     base: Uuid,
-    mmap: Mmap,
+    storage: Box<dyn Storage>,
     pub name: String,
-    pub header: &'map container::Header,
+    pub(crate) header: &'map container::Header,
+    comment: Option<&'map str>,
     lexicon: components::StringVector<'map>,
     lex_hash: components::CachedIndex<'map>,
     id_set_stream: components::Set<'map>,
@@ -608,7 +1997,25 @@ pub struct SetVariable<'map> {
 }
 
 impl<'map> SetVariable<'map> {
-    pub fn get(&self, index: usize) -> Option<HashSet<&str>> {
+    pub fn comment(&self) -> Option<&'map str> {
+        self.comment
+    }
+
+    pub fn uuid(&self) -> Uuid {
+        self.header.uuid()
+    }
+
+    /// UUID of the layer this variable annotates
+    pub fn base(&self) -> Uuid {
+        self.base
+    }
+
+    pub fn cache_stats(&self) -> CacheStats {
+        self.lex_hash.cache_stats()
+            + self.id_set_index.cache_stats()
+    }
+
+    pub fn get(&self, index: usize) -> Option<HashSet<&'map str>> {
         if index < self.len() {
             Some(self.get_unchecked(index))
         } else {
@@ -616,13 +2023,31 @@ impl<'map> SetVariable<'map> {
         }
     }
 
-    pub fn get_unchecked(&self, index: usize) -> HashSet<&str> {
+    pub fn get_unchecked(&self, index: usize) -> HashSet<&'map str> {
         let tids = self.id_set_stream.get_unchecked(index);
 
         tids.iter()
             .map(|id| *id as usize)
             .map(|id| self.lexicon.get_unchecked(id))
-            .collect::<HashSet<&str>>()
+            .collect::<HashSet<&'map str>>()
+    }
+
+    /// Checks that all items in the lexicon are valid UTF-8.
+    pub fn validate_utf8(&self) -> Result<(), InvalidUtf8> {
+        self.lexicon.validate_utf8()
+    }
+
+    pub fn get_range(&self, start: usize, end: usize) -> Option<SetIterator<'map>> {
+        if start <= end && end <= self.len() {
+            Some(SetIterator {
+                lexicon: self.lexicon,
+                sets: self.id_set_stream,
+                position: start,
+                end,
+            })
+        } else {
+            None
+        }
     }
 
     pub fn len(&self) -> usize {
@@ -632,8 +2057,42 @@ impl<'map> SetVariable<'map> {
     pub fn n_types(&self) -> usize {
         self.header.dim2()
     }
+
+    /// Only advises the OS to read the backing pages, the ID set stream has no block cache.
+    pub fn prefetch_range(&self, start: usize, end: usize) {
+        storage::will_need(&*self.storage, self.id_set_stream.raw_range(start, end));
+    }
+}
+
+pub struct SetIterator<'map> {
+    lexicon: components::StringVector<'map>,
+    sets: components::Set<'map>,
+    position: usize,
+    end: usize,
+}
+
+impl<'map> Iterator for SetIterator<'map> {
+    type Item = HashSet<&'map str>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.position < self.end {
+            let tids = self.sets.get_unchecked(self.position);
+            self.position += 1;
+
+            Some(tids.iter().map(|id| self.lexicon.get_unchecked(*id as usize)).collect())
+        } else {
+            None
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.end - self.position;
+        (len, Some(len))
+    }
 }
 
+impl<'map> ExactSizeIterator for SetIterator<'map> {}
+
 impl<'map> TryFrom<Container<'map>> for SetVariable<'map> {
     type Error = container::TryFromError;
 
@@ -670,13 +2129,15 @@ impl<'map> TryFrom<Container<'map>> for SetVariable<'map> {
                 }
                 let id_set_index = CachedInvertedIndex::new(id_set_index);
 
-                let (name, mmap, header, _) = container.into_raw_parts();
+                let comment = container.comment();
+                let (name, storage, header, _) = container.into_raw_parts();
 
                 Ok(Self {
                     base,
-                    mmap,
+                    storage,
                     name,
                     header,
+                    comment,
                     lexicon,
                     lex_hash,
                     id_set_stream,
@@ -693,14 +2154,33 @@ impl<'map> TryFrom<Container<'map>> for SetVariable<'map> {
 #[derive(Debug)]
 pub struct PointerVariable<'map> {
     base: Uuid,
-    mmap: Mmap,
+    storage: Box<dyn Storage>,
     pub name: String,
-    pub header: &'map container::Header,
+    pub(crate) header: &'map container::Header,
+    comment: Option<&'map str>,
     head_stream: components::CachedVector<'map, 1>,
     head_sort: components::CachedIndex<'map>,
 }
 
 impl<'map> PointerVariable<'map> {
+    pub fn comment(&self) -> Option<&'map str> {
+        self.comment
+    }
+
+    pub fn uuid(&self) -> Uuid {
+        self.header.uuid()
+    }
+
+    /// UUID of the layer this variable annotates
+    pub fn base(&self) -> Uuid {
+        self.base
+    }
+
+    pub fn cache_stats(&self) -> CacheStats {
+        self.head_stream.cache_stats()
+            + self.head_sort.cache_stats()
+    }
+
     pub fn get(&self, tail: usize) -> Option<usize> {
         if tail < self.len() {
             self.get_unchecked(tail)
@@ -717,6 +2197,11 @@ impl<'map> PointerVariable<'map> {
         }
     }
 
+    pub fn get_range(&self, start: usize, end: usize) -> Option<PointerIterator<'map>> {
+        self.head_stream.column_iter_range(start, end, 0)
+            .map(|heads| PointerIterator { heads })
+    }
+
     pub fn get_unchecked(&self, index: usize) -> Option<usize> {
         let head = self.head_stream.get_row_unchecked(index)[0];
         if head.is_negative() {
@@ -726,23 +2211,81 @@ impl<'map> PointerVariable<'map> {
         }
     }
 
-    pub fn len(&self) -> usize {
-        self.header.dim1()
+    /// Returns the heads at all `indices` in the given order, `None` if any is out of bounds.
+    ///
+    /// Unlike [`join_heads`](Self::join_heads) the indices need not be sorted to decode each
+    /// block once.
+    pub fn get_many(&self, indices: &[usize]) -> Option<Vec<Option<usize>>> {
+        let rows = self.head_stream.get_rows(indices)?;
+        Some(rows.into_iter().map(|[head]| (!head.is_negative()).then_some(head as usize)).collect())
     }
 
-    pub fn encode_to_file<I>(file: File, heads: I, n: usize, name: String, base: Uuid, compressed: bool, comment: &str) -> Self where I: Iterator<Item=i64> {
-        let vectype = if compressed { components::Type::VectorDelta } else { components::Type::Vector };
-        let idxtype = if compressed { components::Type::IndexComp } else { components::Type::Index };
+    /// Returns the heads of all `positions`, `None` for positions without a head or out of bounds.
+    ///
+    /// For sorted positions the HeadStream is walked in a single pass and every block is
+    /// decoded at most once, blocks without any of the positions are skipped.
+    pub fn join_heads(&self, positions: &[usize]) -> Vec<Option<usize>> {
+        self.join_heads_iter(positions.iter().copied()).collect()
+    }
 
-        // we need to load all values into memory so we can sort them later
-        // this step is very memory-intensive and could be replaced with a reverse index component later on
-        // format: [(head, cpos); n]
-        let mut values: Vec<(i64, i64)> = heads.take(n).enumerate().map(|(cpos, head)| (head, cpos as i64)).collect();
-        
-        let mut builder = ContainerBuilder::new_into_file(name, file, 2)
-            .edit_header(| h | {
-                h.comment(comment)
-                    .ziggurat_type(container::Type::PointerVariable)
+    /// Streaming version of [`join_heads`](Self::join_heads), each out of order position
+    /// restarts the walk.
+    pub fn join_heads_iter<I>(&self, positions: I) -> HeadJoinIterator<'map, I::IntoIter> where I: IntoIterator<Item = usize> {
+        HeadJoinIterator {
+            head_stream: self.head_stream.clone(),
+            positions: positions.into_iter(),
+            heads: None,
+            next: 0,
+            last: None,
+        }
+    }
+
+    /// Returns the `(head, tail)` pairs of all tails pointing to one of `heads`, the inverse of
+    /// [`join_heads`](Self::join_heads).
+    ///
+    /// Pairs are ordered like `heads` and by tail within each head. For sorted heads all
+    /// lookups go through the same few blocks of the HeadSort index in turn, duplicate heads
+    /// are only looked up once.
+    pub fn join_tails(&self, heads: &[usize]) -> Vec<(usize, usize)> {
+        let mut pairs = Vec::new();
+        let mut last = None;
+
+        for &head in heads {
+            if last == Some(head) {
+                continue;
+            }
+            last = Some(head);
+
+            if let Some(tails) = self.tail_positions(head) {
+                pairs.extend(tails.map(|tail| (head, tail as usize)));
+            }
+        }
+
+        pairs
+    }
+
+    pub fn len(&self) -> usize {
+        self.header.dim1()
+    }
+
+    pub fn prefetch_range(&self, start: usize, end: usize) {
+        storage::will_need(&*self.storage, self.head_stream.raw_range(start, end));
+        self.head_stream.prefetch(start, end);
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "info", skip_all, fields(name = %name, n = n, compressed = compressed)))]
+    pub fn encode_to_file<W, I>(file: W, heads: I, n: usize, name: String, base: Uuid, compressed: bool, comment: &str) -> Result<Self, EncodeError> where W: EncodeTarget, I: Iterator<Item=i64> {
+        let vectype = if compressed { components::Type::VectorDelta } else { components::Type::Vector };
+        let idxtype = if compressed { components::Type::IndexComp } else { components::Type::Index };
+
+        // we need to load all values into memory so we can sort them later
+        // this step is very memory-intensive and could be replaced with a reverse index component later on
+        // format: [(head, cpos); n]
+        let mut values: Vec<(i64, i64)> = heads.take(n).enumerate().map(|(cpos, head)| (head, cpos as i64)).collect();
+        
+        let mut builder = ContainerBuilder::new_into_file(name, file, 2 + ContainerBuilder::comment_capacity(comment))
+            .edit_header(| h | {
+                h.ziggurat_type(container::Type::PointerVariable)
                     .dim1(n)
                     .dim2(0)
                     .base1(Some(base));
@@ -751,9 +2294,9 @@ impl<'map> PointerVariable<'map> {
                 unsafe {
                     if compressed {
                         let values = values.iter().map(|(head, _)| [*head; 1]);
-                        Vector::encode_delta_to_container_file(values, n, file, bom_entry, bom_entry.offset as u64);
+                        Vector::encode_delta_to_container_file(values, n, file, bom_entry, bom_entry.offset as u64)
                     } else {
-                        Vector::encode_uncompressed_to_container_file(values.iter().map(|(head, _)| *head), n, 1, file, bom_entry, bom_entry.offset as u64);
+                        Vector::encode_uncompressed_to_container_file(values.iter().map(|(head, _)| *head), n, 1, file, bom_entry, bom_entry.offset as u64)
                     }
                 }
             });
@@ -764,14 +2307,80 @@ impl<'map> PointerVariable<'map> {
         builder = builder.add_component("HeadSort", idxtype, | bom_entry, file | {
             unsafe {
                 if compressed {
-                    Index::encode_compressed_to_container_file(values.iter().copied(), n, file, bom_entry, bom_entry.offset as u64);
+                    Index::encode_compressed_to_container_file(values.iter().copied(), n, file, bom_entry, bom_entry.offset as u64)
                 } else {
-                    Index::encode_uncompressed_to_container_file(values.iter().copied(), n, file, bom_entry, bom_entry.offset as u64);
+                    Index::encode_uncompressed_to_container_file(values.iter().copied(), n, file, bom_entry, bom_entry.offset as u64)
                 }
             }
         });
 
-        builder.build().try_into().expect("PointerVariable returned by its constructor is inconsistent")
+        Ok(builder.comment(comment).build()?.try_into().expect("PointerVariable returned by its constructor is inconsistent"))
+    }
+}
+
+/// Iterator over the heads of a pointer variable, `None` for positions without a head
+pub struct PointerIterator<'map> {
+    heads: ColumnIterator<'map, 1>,
+}
+
+impl<'map> Iterator for PointerIterator<'map> {
+    type Item = Option<usize>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.heads.next()
+            .map(|head| (!head.is_negative()).then_some(head as usize))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.heads.size_hint()
+    }
+}
+
+impl<'map> ExactSizeIterator for PointerIterator<'map> {}
+
+/// Iterator that yields the head of each position of the underlying iterator,
+/// see [`PointerVariable::join_heads_iter`]
+pub struct HeadJoinIterator<'map, I> {
+    head_stream: CachedVector<'map, 1>,
+    positions: I,
+    heads: Option<ColumnIterator<'map, 1>>,
+    /// position `heads` yields next
+    next: usize,
+    last: Option<(usize, Option<usize>)>,
+}
+
+impl<'map, I> Iterator for HeadJoinIterator<'map, I> where I: Iterator<Item = usize> {
+    type Item = Option<usize>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let position = self.positions.next()?;
+
+        if position >= self.head_stream.len() {
+            return Some(None);
+        }
+        if let Some((last, head)) = self.last {
+            if last == position {
+                return Some(head);
+            }
+        }
+
+        if self.heads.is_none() || position < self.next {
+            self.heads = Some(self.head_stream.column_iter_from(position, 0));
+            self.next = position;
+        }
+
+        // nth() skips the blocks between the previous and the current position
+        let head = self.heads.as_mut()
+            .and_then(|heads| heads.nth(position - self.next))
+            .and_then(|head| (!head.is_negative()).then_some(head as usize));
+        self.next = position + 1;
+        self.last = Some((position, head));
+
+        Some(head)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.positions.size_hint()
     }
 }
 
@@ -799,13 +2408,15 @@ impl<'map> TryFrom<Container<'map>> for PointerVariable<'map> {
                 }
                 let head_sort = CachedIndex::new(head_sort);
 
-                let (name, mmap, header, _) = container.into_raw_parts();
+                let comment = container.comment();
+                let (name, storage, header, _) = container.into_raw_parts();
 
                 Ok(Self {
                     base,
-                    mmap,
+                    storage,
                     name,
                     header,
+                    comment,
                     head_stream,
                     head_sort,
                 })
@@ -816,10 +2427,624 @@ impl<'map> TryFrom<Container<'map>> for PointerVariable<'map> {
     }
 }
 
+/// String annotations of only some positions of the base layer, e.g. named entity types.
+///
+/// Only annotated positions are stored, as a stream of positions with a lexicon ID for
+/// each of them, so unannotated positions take no space at all.
+#[derive(Debug)]
+pub struct SparseVariable<'map> {
+    base: Uuid,
+    storage: Box<dyn Storage>,
+    pub name: String,
+    pub(crate) header: &'map container::Header,
+    comment: Option<&'map str>,
+    /// annotated positions in ascending order, one entry per annotation
+    position_stream: components::CachedVector<'map, 1>,
+    /// annotated position to entry
+    position_sort: components::CachedIndex<'map>,
+    lexicon: components::StringVector<'map>,
+    lex_hash: components::CachedIndex<'map>,
+    /// lexicon ID of each entry
+    lex_id_stream: components::CachedVector<'map, 1>,
+    /// type to entries, not positions
+    lex_id_index: Rc<components::CachedInvertedIndex<'map>>,
+}
+
+impl<'map> SparseVariable<'map> {
+    pub fn comment(&self) -> Option<&'map str> {
+        self.comment
+    }
+
+    pub fn uuid(&self) -> Uuid {
+        self.header.uuid()
+    }
+
+    /// UUID of the layer this variable annotates
+    pub fn base(&self) -> Uuid {
+        self.base
+    }
+
+    pub fn cache_stats(&self) -> CacheStats {
+        self.position_stream.cache_stats()
+            + self.position_sort.cache_stats()
+            + self.lex_hash.cache_stats()
+            + self.lex_id_stream.cache_stats()
+            + self.lex_id_index.cache_stats()
+    }
+
+    /// Encodes the `(position, value)` pairs `values` as annotations of a base layer of length `n`.
+    ///
+    /// Positions must be strictly ascending and less than `n`.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "info", skip_all, fields(name = %name, n = n, compressed = compressed)))]
+    pub fn encode_to_file<W, I>(file: W, values: I, n: usize, name: String, base: Uuid, compressed: bool, comment: &str) -> Result<Self, EncodeError> where W: EncodeTarget, I: Iterator<Item=(usize, String)> {
+        // only the annotations are kept in memory, which are few by definition
+        let mut positions: Vec<usize> = Vec::new();
+        let mut strings = Vec::new();
+        for (i, (position, value)) in values.enumerate() {
+            if position >= n {
+                return Err(EncodeError::OutOfBounds { position, len: n });
+            }
+            match positions.last() {
+                Some(&last) if position < last => return Err(RangeError::Unsorted(i, (position, position + 1)).into()),
+                Some(&last) if position == last => return Err(RangeError::Overlapping(i, (position, position + 1)).into()),
+                _ => (),
+            }
+            positions.push(position);
+            strings.push(value);
+        }
+
+        let m = positions.len();
+        // compressed components can't be empty
+        let compressed = compressed && m > 0;
+        let vectype = if compressed { components::Type::VectorDelta } else { components::Type::Vector };
+        let idxtype = if compressed { components::Type::IndexComp } else { components::Type::Index };
+        let idvectype = if compressed { components::Type::VectorComp } else { components::Type::Vector };
+
+        let mut lexbuilder = LexiconBuilder::from_strings(strings.into_iter());
+
+        let builder = ContainerBuilder::new_into_file(name, file, 7 + ContainerBuilder::comment_capacity(comment))
+            .edit_header(| h | {
+                h.ziggurat_type(container::Type::SparseVariable)
+                    .dim1(n)
+                    .dim2(m)
+                    .base1(Some(base));
+            })
+            .add_component("PosStream", vectype, | bom_entry, file | {
+                unsafe {
+                    if compressed {
+                        let values = positions.iter().map(|&p| [p as i64]);
+                        Vector::encode_delta_to_container_file(values, m, file, bom_entry, bom_entry.offset as u64)
+                    } else {
+                        Vector::encode_uncompressed_to_container_file(positions.iter().map(|&p| p as i64), m, 1, file, bom_entry, bom_entry.offset as u64)
+                    }
+                }
+            })
+            .add_component("PosSort", idxtype, | bom_entry, file | {
+                unsafe {
+                    let pairs = positions.iter().enumerate().map(|(entry, &p)| (p as i64, entry as i64));
+                    if compressed {
+                        Index::encode_compressed_to_container_file(pairs, m, file, bom_entry, bom_entry.offset as u64)
+                    } else {
+                        Index::encode_uncompressed_to_container_file(pairs, m, file, bom_entry, bom_entry.offset as u64)
+                    }
+                }
+            })
+            .add_component("Lexicon", components::Type::StringVector, | bom_entry, file | {
+                unsafe {
+                    lexbuilder.write_lexicon(file, bom_entry, bom_entry.offset as u64)
+                }
+            })
+            .add_component("LexHash", components::Type::Index, | bom_entry, file | {
+                unsafe {
+                    lexbuilder.write_index(file, bom_entry, bom_entry.offset as u64)
+                }
+            })
+            .add_component("LexHashBloom", components::Type::Vector, | bom_entry, file | {
+                unsafe {
+                    lexbuilder.write_index_bloom(file, bom_entry, bom_entry.offset as u64)
+                }
+            })
+            .add_component("LexIDStream", idvectype, | bom_entry, file | {
+                unsafe {
+                    lexbuilder.write_id_stream(file, bom_entry, bom_entry.offset as u64, compressed)
+                }
+            });
+
+        // see IndexedStringVariable::encode_lexicon_builder
+        let mut builder = builder.checked()?;
+        lexbuilder.release_id_stream();
+        let (streambom, streamstorage) = builder.map_component(5)?;
+        let id_stream = Component::from_raw_parts(&streambom, streamstorage.bytes().as_ptr()).unwrap().into_vector().unwrap();
+        let id_stream = CachedVector::<1>::new(id_stream).unwrap();
+
+        let builder = builder
+            .add_component("LexIDIndex", components::Type::InvertedIndex, | bom_entry, file | {
+                lexbuilder.write_inverted_index(&id_stream, file, bom_entry, bom_entry.offset as u64)
+            });
+
+        Ok(builder.comment(comment).build()?.try_into().expect("SparseVariable returned by its constructor is inconsistent"))
+    }
+
+    /// Index of the first annotation at or after `position`
+    fn first_entry_from(&self, position: usize) -> usize {
+        match self.position_sort.get_floor(position as i64) {
+            Some((p, entry)) if p as usize == position => entry as usize,
+            Some((_, entry)) => entry as usize + 1,
+            None => 0,
+        }
+    }
+
+    /// Value at `position`, `None` if the position is not annotated or out of bounds
+    pub fn get(&self, position: usize) -> Option<&'map str> {
+        self.get_id(position)
+            .map(|id| self.lexicon.get_unchecked(id))
+    }
+
+    /// Lexicon ID of the value at `position`, `None` if the position is not annotated or out of bounds
+    pub fn get_id(&self, position: usize) -> Option<usize> {
+        let entry = self.position_sort.get_first(position as i64)?;
+        Some(self.lex_id_stream.get_row_unchecked(entry as usize)[0] as usize)
+    }
+
+    /// Iterator over the positions `start..end`, with `None` for positions without annotation
+    pub fn get_range(&self, start: usize, end: usize) -> Option<SparseIterator<'map>> {
+        if start > end || end > self.len() {
+            return None;
+        }
+
+        let entry = self.first_entry_from(start);
+        let m = self.n_annotated();
+        Some(SparseIterator {
+            lexicon: self.lexicon,
+            positions: self.position_stream.column_iter_range(entry, m, 0)?.peekable(),
+            ids: self.lex_id_stream.column_iter_range(entry, m, 0)?,
+            position: start,
+            end,
+        })
+    }
+
+    /// All annotations as `(position, value)` pairs in ascending order of their position
+    pub fn annotations(&self) -> impl Iterator<Item = (usize, &'map str)> + 'map {
+        let lexicon = self.lexicon;
+        self.position_stream.column_iter(0)
+            .zip(self.lex_id_stream.column_iter(0))
+            .map(move |(position, id)| (position as usize, lexicon.get_unchecked(id as usize)))
+    }
+
+    /// Annotated positions in ascending order
+    pub fn positions(&self) -> ColumnIterator<'map, 1> {
+        self.position_stream.column_iter(0)
+    }
+
+    /// Ascending positions annotated with type `type_id`, `None` for unknown types
+    pub fn postings(&self, type_id: usize) -> Option<impl Iterator<Item = usize> + 'map> {
+        let positions = self.position_stream.clone();
+        self.lex_id_index.positions(type_id)
+            .map(move |entries| entries.map(move |entry| positions.get_row_unchecked(entry)[0] as usize))
+    }
+
+    /// Lexicon ID of `string`, `None` if no position is annotated with it
+    pub fn type_id(&self, string: &str) -> Option<usize> {
+        self.lex_hash.get_all(string.fnv_hash())
+            .map(|id| id as usize)
+            .find(|&id| self.lexicon.get(id) == Some(string))
+    }
+
+    /// Number of positions annotated with type `type_id`
+    pub fn frequency(&self, type_id: usize) -> Option<usize> {
+        self.lex_id_index.frequency(type_id)
+    }
+
+    pub fn lexicon(&self) -> &components::StringVector<'map> {
+        &self.lexicon
+    }
+
+    /// Checks that all values in the lexicon are valid UTF-8.
+    pub fn validate_utf8(&self) -> Result<(), InvalidUtf8> {
+        self.lexicon.validate_utf8()
+    }
+
+    /// Length of the base layer, not the number of annotations
+    pub fn len(&self) -> usize {
+        self.header.dim1()
+    }
+
+    /// Number of annotated positions
+    pub fn n_annotated(&self) -> usize {
+        self.header.dim2()
+    }
+
+    pub fn n_types(&self) -> usize {
+        self.lexicon.len()
+    }
+
+    pub fn prefetch_range(&self, start: usize, end: usize) {
+        let first = self.first_entry_from(start);
+        let last = self.first_entry_from(end);
+        storage::will_need(&*self.storage, self.position_stream.raw_range(first, last));
+        storage::will_need(&*self.storage, self.lex_id_stream.raw_range(first, last));
+        self.position_stream.prefetch(first, last);
+        self.lex_id_stream.prefetch(first, last);
+    }
+}
+
+/// Iterator over the values of a sparse variable, `None` for positions without annotation
+pub struct SparseIterator<'map> {
+    lexicon: components::StringVector<'map>,
+    positions: std::iter::Peekable<ColumnIterator<'map, 1>>,
+    ids: ColumnIterator<'map, 1>,
+    position: usize,
+    end: usize,
+}
+
+impl<'map> Iterator for SparseIterator<'map> {
+    type Item = Option<&'map str>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.position >= self.end {
+            return None;
+        }
+
+        let position = self.position as i64;
+        self.position += 1;
+
+        if self.positions.next_if_eq(&position).is_some() {
+            Some(self.ids.next().map(|id| self.lexicon.get_unchecked(id as usize)))
+        } else {
+            Some(None)
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.end - self.position;
+        (len, Some(len))
+    }
+}
+
+impl<'map> ExactSizeIterator for SparseIterator<'map> {}
+
+impl<'map> TryFrom<Container<'map>> for SparseVariable<'map> {
+    type Error = container::TryFromError;
+
+    fn try_from(container: Container<'map>) -> Result<Self, Self::Error> {
+        let header = *container.header();
+
+        match header.container_type() {
+            container::Type::SparseVariable => {
+                let base = get_container_base!(container, SparseVariable);
+                let m = header.dim2();
+
+                let position_stream = check_and_return_component!(container, "PosStream", Vector)?;
+                if position_stream.len() != m || position_stream.width() != 1 {
+                    return Err(Self::Error::WrongComponentDimensions("PosStream"));
+                }
+                let position_stream = CachedVector::<1>::new(position_stream)
+                    .expect("width already checked, should be 1");
+
+                let position_sort = check_and_return_component!(container, "PosSort", Index)?;
+                if position_sort.len() != m {
+                    return Err(Self::Error::WrongComponentDimensions("PosSort"));
+                }
+                let position_sort = CachedIndex::new(position_sort);
+
+                let lexicon = check_and_return_component!(container, "Lexicon", StringVector)?;
+                let v = lexicon.len();
+
+                let lex_hash = check_and_return_component!(container, "LexHash", Index)?;
+                if lex_hash.len() != v {
+                    return Err(Self::Error::WrongComponentDimensions("LexHash"));
+                }
+                let lex_hash = with_optional_bloom(&container, CachedIndex::new(lex_hash), "LexHashBloom")?;
+
+                let lex_id_stream = check_and_return_component!(container, "LexIDStream", Vector)?;
+                if lex_id_stream.len() != m || lex_id_stream.width() != 1 {
+                    return Err(Self::Error::WrongComponentDimensions("LexIDStream"));
+                }
+                let lex_id_stream = CachedVector::<1>::new(lex_id_stream)
+                    .expect("width already checked, should be 1");
+
+                let lex_id_index = check_and_return_component!(container, "LexIDIndex", InvertedIndex)?;
+                if lex_id_index.n_types() != v {
+                    return Err(Self::Error::WrongComponentDimensions("LexIDIndex"));
+                }
+                let lex_id_index = Rc::new(CachedInvertedIndex::new(lex_id_index));
+
+                let comment = container.comment();
+                let (name, storage, header, _) = container.into_raw_parts();
+
+                Ok(Self {
+                    base,
+                    storage,
+                    name,
+                    header,
+                    comment,
+                    position_stream,
+                    position_sort,
+                    lexicon,
+                    lex_hash,
+                    lex_id_stream,
+                    lex_id_index,
+                })
+            }
+
+            _ => Err(Self::Error::WrongContainerType),
+        }
+    }
+}
+
+/// Character offsets of each position in the source document, for stand-off annotation.
+///
+/// Every position has a non-empty `start..end` range of byte offsets into the source. The
+/// ranges are sorted and don't overlap, so both directions are answered by binary search:
+/// positions to the characters they cover and characters to the positions covering them.
+#[derive(Debug)]
+pub struct OffsetVariable<'map> {
+    base: Uuid,
+    storage: Box<dyn Storage>,
+    pub name: String,
+    pub(crate) header: &'map container::Header,
+    comment: Option<&'map str>,
+    offset_stream: components::CachedVector<'map, 2>,
+}
+
+impl<'map> OffsetVariable<'map> {
+    pub fn comment(&self) -> Option<&'map str> {
+        self.comment
+    }
+
+    pub fn uuid(&self) -> Uuid {
+        self.header.uuid()
+    }
+
+    /// UUID of the layer this variable annotates
+    pub fn base(&self) -> Uuid {
+        self.base
+    }
+
+    pub fn cache_stats(&self) -> CacheStats {
+        self.offset_stream.cache_stats()
+    }
+
+    /// Encodes the `(start, end)` byte offsets of `n` positions.
+    ///
+    /// Offsets are validated like the ranges of a segmentation layer, they must be sorted,
+    /// non-empty and non-overlapping. The first invalid range is reported.
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "info", skip_all, fields(name = %name, n = n, compressed = compressed)))]
+    pub fn encode_to_file<W, I>(file: W, offsets: I, n: usize, name: String, base: Uuid, compressed: bool, comment: &str) -> Result<Self, EncodeError> where W: EncodeTarget, I: Iterator<Item=(usize, usize)> {
+        let vectype = if compressed { components::Type::VectorDelta } else { components::Type::Vector };
+
+        let error = Cell::new(None);
+        let mut previous = None;
+        let offsets = offsets.enumerate().map(|(i, range)| {
+            if error.get().is_none() {
+                if let Err(e) = check_range(i, range, previous, false) {
+                    error.set(Some(e));
+                }
+            }
+            previous = Some(range);
+            [range.0 as i64, range.1 as i64]
+        });
+
+        let builder = ContainerBuilder::new_into_file(name, file, 1 + ContainerBuilder::comment_capacity(comment))
+            .edit_header(| h | {
+                h.ziggurat_type(container::Type::OffsetVariable)
+                    .dim1(n)
+                    .dim2(2)
+                    .base1(Some(base));
+            })
+            .add_component("OffsetStream", vectype, | bom_entry, file | {
+                unsafe {
+                    if compressed {
+                        Vector::encode_delta_to_container_file(offsets, n, file, bom_entry, bom_entry.offset as u64)
+                    } else {
+                        Vector::encode_uncompressed_to_container_file(offsets.flatten(), n, 2, file, bom_entry, bom_entry.offset as u64)
+                    }
+                }
+            });
+
+        if let Some(e) = error.get() {
+            return Err(e.into());
+        }
+
+        Ok(builder.comment(comment).build()?.try_into().expect("OffsetVariable returned by its constructor is inconsistent"))
+    }
+
+    pub fn len(&self) -> usize {
+        self.header.dim1()
+    }
+
+    /// Byte offsets `(start, end)` of `position`
+    pub fn get(&self, position: usize) -> Option<(usize, usize)> {
+        self.offset_stream.get_row(position).map(|[start, end]| (start as usize, end as usize))
+    }
+
+    pub fn get_range(&self, start: usize, end: usize) -> Option<OffsetIterator<'map>> {
+        (start <= end).then_some(())?;
+        self.offset_stream.iter_range(start, end).map(OffsetIterator)
+    }
+
+    pub fn iter(&self) -> OffsetIterator<'map> {
+        OffsetIterator(self.offset_stream.iter())
+    }
+
+    /// First position at or after `from` whose offset in `column` is not less than `offset`
+    fn search(&self, column: usize, offset: usize, from: usize) -> usize {
+        let (mut lo, mut hi) = (from, self.len());
+        while lo < hi {
+            let mid = lo + (hi - lo) / 2;
+            if (self.offset_stream.get_row_unchecked(mid)[column] as usize) < offset {
+                lo = mid + 1;
+            } else {
+                hi = mid;
+            }
+        }
+        lo
+    }
+
+    /// Characters covered by the positions `start..end`, from the start of the first to the
+    /// end of the last position. `None` if the range is empty or out of bounds.
+    pub fn char_range(&self, start: usize, end: usize) -> Option<(usize, usize)> {
+        if start >= end || end > self.len() {
+            return None;
+        }
+        Some((self.get(start)?.0, self.get(end - 1)?.1))
+    }
+
+    /// Positions `start..end` overlapping the characters `char_start..char_end`, `None` if the
+    /// characters only cover whitespace between positions or nothing at all
+    pub fn positions(&self, char_start: usize, char_end: usize) -> Option<(usize, usize)> {
+        if char_start >= char_end {
+            return None;
+        }
+        // first position ending after char_start, then the first starting at or after char_end
+        let start = self.search(1, char_start + 1, 0);
+        let end = self.search(0, char_end, start);
+        (start < end).then_some((start, end))
+    }
+
+    /// Position covering the character at `offset`
+    pub fn position_at(&self, offset: usize) -> Option<usize> {
+        self.positions(offset, offset + 1).map(|(start, _)| start)
+    }
+
+    pub fn prefetch_range(&self, start: usize, end: usize) {
+        storage::will_need(&*self.storage, self.offset_stream.raw_range(start, end));
+        self.offset_stream.prefetch(start, end);
+    }
+}
+
+/// Byte offsets of consecutive positions of an [`OffsetVariable`]
+pub struct OffsetIterator<'map>(components::RowIterator<'map, 2>);
+
+impl<'map> Iterator for OffsetIterator<'map> {
+    type Item = (usize, usize);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(|[start, end]| (start as usize, end as usize))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+impl<'map> ExactSizeIterator for OffsetIterator<'map> {}
+
+impl<'map> TryFrom<Container<'map>> for OffsetVariable<'map> {
+    type Error = container::TryFromError;
+
+    fn try_from(container: Container<'map>) -> Result<Self, Self::Error> {
+        let header = *container.header();
+
+        match header.container_type() {
+            container::Type::OffsetVariable => {
+                let base = get_container_base!(container, OffsetVariable);
+
+                let offset_stream = check_and_return_component!(container, "OffsetStream", Vector)?;
+                if offset_stream.len() != header.dim1() || offset_stream.width() != 2 {
+                    return Err(Self::Error::WrongComponentDimensions("OffsetStream"));
+                }
+                let offset_stream = CachedVector::<2>::new(offset_stream)
+                    .expect("width already checked, should be 2");
+
+                let comment = container.comment();
+                let (name, storage, header, _) = container.into_raw_parts();
+
+                Ok(Self {
+                    base,
+                    storage,
+                    name,
+                    header,
+                    comment,
+                    offset_stream,
+                })
+            }
+
+            _ => Err(Self::Error::WrongContainerType),
+        }
+    }
+}
+
+/// How a [`VirtualVariable`] computes its value from its sources
+enum VirtualMapping {
+    /// From the string of its only source
+    String(Box<dyn Fn(&str) -> Option<String>>),
+    /// From the values of all its sources
+    Values(Box<dyn Fn(&[VariableValue]) -> Option<String>>),
+}
+
+/// String variable computed on the fly from other variables of a layer, e.g. coarse
+/// part-of-speech tags mapped from fine ones, see [`Layer::add_virtual_variable`](crate::layers::Layer::add_virtual_variable).
+///
+/// Nothing is stored on disk. Queries on a virtual variable of a single indexed string
+/// variable are evaluated on the lexicon of the source and seeded from its inverted index,
+/// all others compute the value at each candidate position.
+pub struct VirtualVariable {
+    sources: Vec<String>,
+    mapping: VirtualMapping,
+}
+
+impl VirtualVariable {
+    /// Maps the strings of the variable `source` with `f`, positions for which `f` returns
+    /// `None` have no value
+    pub fn from_fn<F>(source: &str, f: F) -> Self where F: Fn(&str) -> Option<String> + 'static {
+        Self { sources: vec![source.to_owned()], mapping: VirtualMapping::String(Box::new(f)) }
+    }
+
+    /// Maps the strings of the variable `source` through `table`, strings that are not in
+    /// the table have no value
+    pub fn from_table(source: &str, table: HashMap<String, String>) -> Self {
+        Self::from_fn(source, move |s| table.get(s).cloned())
+    }
+
+    /// Computes the value from the values of all `sources` at a position, in this order
+    pub fn combine<F>(sources: &[&str], f: F) -> Self where F: Fn(&[VariableValue]) -> Option<String> + 'static {
+        let sources = sources.iter().map(|s| (*s).to_owned()).collect();
+        Self { sources, mapping: VirtualMapping::Values(Box::new(f)) }
+    }
+
+    /// Names of the variables the value is computed from
+    pub fn sources(&self) -> impl Iterator<Item = &str> {
+        self.sources.iter().map(String::as_str)
+    }
+
+    /// Value at `position` of `layer`, `None` if it has none or the position is out of bounds
+    pub fn get(&self, layer: &Layer, position: usize) -> Option<String> {
+        match &self.mapping {
+            VirtualMapping::String(f) => f(layer.variable_by_name(&self.sources[0])?.get_string(position)?),
+            VirtualMapping::Values(f) => {
+                let values = self.sources.iter()
+                    .map(|s| layer.variable_by_name(s)?.get(position))
+                    .collect::<Option<Vec<_>>>()?;
+                f(&values)
+            }
+        }
+    }
+
+    /// Source and value of each of its types if this variable maps the strings of a single
+    /// indexed string variable of `layer`
+    pub fn type_values<'a, 'map>(&self, layer: &'a Layer<'map>) -> Option<(&'a IndexedStringVariable<'map>, Vec<Option<String>>)> {
+        let VirtualMapping::String(f) = &self.mapping else {
+            return None;
+        };
+        let source = layer.variable_by_name(&self.sources[0])?.as_indexed_string()?;
+        Some((source, source.lexicon().iter().map(|s| f(s)).collect()))
+    }
+}
+
+impl fmt::Debug for VirtualVariable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("VirtualVariable").field("sources", &self.sources).finish_non_exhaustive()
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
     use uuid::Uuid;
 
+    use crate::components::external_sort;
+
     use super::IntegerVariable;
 
     #[test]
@@ -828,7 +3053,7 @@ mod tests {
 
         let values = 1337..9_000_001;
         
-        let _ = IntegerVariable::encode_to_file(file, values, 5_000_000, "testintvar".to_owned(), Uuid::new_v4(), false, true, "IntVar encoded for testing purposes.");
+        let _ = IntegerVariable::encode_to_file(file, values, 5_000_000, "testintvar".to_owned(), Uuid::new_v4(), false, true, "IntVar encoded for testing purposes.").unwrap();
     }
 
     #[test]
@@ -837,6 +3062,55 @@ mod tests {
 
         let values = 1337..9_000_001;
         
-        let _ = IntegerVariable::encode_to_file(file, values, 5_000_001, "testintvar".to_owned(), Uuid::new_v4(), true, true, "IntVar encoded for testing purposes.");
+        let _ = IntegerVariable::encode_to_file(file, values, 5_000_001, "testintvar".to_owned(), Uuid::new_v4(), true, true, "IntVar encoded for testing purposes.").unwrap();
+    }
+
+    #[test]
+    fn encode_long_comment() {
+        let short = "IntVar encoded for testing purposes.";
+        let var = IntegerVariable::encode_to_file(tempfile::tempfile().unwrap(), 0..100, 100, "testintvar".to_owned(), Uuid::new_v4(), false, false, short).unwrap();
+        assert!(var.comment() == Some(short));
+        assert!(var.header.comment() == Some(short));
+
+        let long = "This comment is too long for the header of a container and thus has to be stored in a separate component.";
+        let var = IntegerVariable::encode_to_file(tempfile::tempfile().unwrap(), 0..100, 100, "testintvar".to_owned(), Uuid::new_v4(), false, false, long).unwrap();
+        assert!(var.comment() == Some(long));
+        assert!(long.starts_with(var.header.comment().unwrap()));
+    }
+
+    #[test]
+    fn external_sort_runs() {
+        let mut rng = StdRng::seed_from_u64(42);
+        let pairs: Vec<(i64, i64)> = (0..10_000).map(|i| (rng.gen_range(-50..50), i)).collect();
+
+        let mut expected = pairs.clone();
+        expected.sort_by_key(|(v, _)| *v);
+
+        for run_len in [1, 333, 10_000, 20_000] {
+            let sorted: Vec<_> = external_sort(pairs.iter().copied(), run_len).unwrap().collect();
+            assert!(sorted == expected);
+        }
+    }
+
+    #[test]
+    fn intvar_reverse_index() {
+        let mut rng = StdRng::seed_from_u64(1337);
+        let values: Vec<i64> = (0..50_000).map(|_| rng.gen_range(0..100)).collect();
+
+        for compressed in [false, true] {
+            let var = IntegerVariable::encode_to_file(tempfile::tempfile().unwrap(), values.iter().copied(), values.len(), "testintvar".to_owned(), Uuid::new_v4(), compressed, false, "").unwrap();
+            let positions: Vec<i64> = var.get_all(42).collect();
+            let expected: Vec<i64> = (0..values.len() as i64).filter(|i| values[*i as usize] == 42).collect();
+            assert!(positions == expected);
+        }
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn intvar_par_scan() {
+        let var = IntegerVariable::encode_to_file(tempfile::tempfile().unwrap(), 0..100_000, 100_000, "testintvar".to_owned(), Uuid::new_v4(), true, true, "").unwrap();
+
+        let sum = var.par_scan(4000, |_, chunk| chunk.sum::<i64>(), |a, b| a + b);
+        assert!(sum == Some((0..100_000).sum()));
     }
 }
@@ -0,0 +1,220 @@
+//! Export of a datastore as VRT, the inverse of the import pipeline.
+//!
+//! Every position of the primary layer becomes a line of tab separated values, the
+//! segmentation layers on top of it become XML tags around these lines with the values
+//! of their variables as attributes. Layers with fewer segments are assumed to be the
+//! outer ones, so `<text>` encloses `<p>` which encloses `<s>`.
+
+use std::io::{self, Write};
+use std::{error, fmt};
+
+use crate::layers::{LayerData, SegmentationLayer};
+use crate::selection::{Selection, SelectionError};
+use crate::variables::{Variable, VariableValue};
+use crate::Datastore;
+
+/// What [`Datastore::export_vrt`] writes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VrtOptions {
+    /// Primary layer providing the token lines
+    pub layer: String,
+    /// Variables written as columns, all variables with `word` first if `None`
+    pub columns: Option<Vec<String>>,
+    /// Segmentation layers written as tags from the outermost to the innermost, all
+    /// segmentation layers on top of `layer` if `None`
+    pub structures: Option<Vec<String>>,
+}
+
+impl Default for VrtOptions {
+    fn default() -> Self {
+        Self {
+            layer: "primary".to_owned(),
+            columns: None,
+            structures: None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum VrtError {
+    Io(io::Error),
+    UnknownLayer(String),
+    NotPrimary(String),
+    NotSegmentation(String),
+    /// The segmentation layer is not based on the exported primary layer
+    UnrelatedLayer(String),
+    Selection(SelectionError),
+}
+
+impl fmt::Display for VrtError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{}", e),
+            Self::UnknownLayer(name) => write!(f, "no layer named {}", name),
+            Self::NotPrimary(name) => write!(f, "layer {} is not a primary layer", name),
+            Self::NotSegmentation(name) => write!(f, "layer {} is not a segmentation layer", name),
+            Self::UnrelatedLayer(name) => write!(f, "layer {} is not based on the exported layer", name),
+            Self::Selection(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl error::Error for VrtError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            Self::Selection(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for VrtError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<SelectionError> for VrtError {
+    fn from(e: SelectionError) -> Self {
+        Self::Selection(e)
+    }
+}
+
+/// Segmentation layer written as tags, `next` is the first segment not opened yet
+struct Structure<'a, 'map> {
+    name: &'a str,
+    layer: &'a LayerData<'map, SegmentationLayer<'map>>,
+    attributes: Vec<(&'a str, &'a Variable<'map>)>,
+    next: usize,
+}
+
+impl<'a, 'map> Structure<'a, 'map> {
+    fn write_start<W: Write>(&self, writer: &mut W, segment: usize) -> io::Result<()> {
+        write!(writer, "<{}", self.name)?;
+        for (name, variable) in self.attributes.iter() {
+            match variable.get(segment) {
+                Some(VariableValue::Missing) | None => {}
+                Some(value) => write!(writer, " {}=\"{}\"", name, escape(&value.to_string()))?,
+            }
+        }
+        writeln!(writer, ">")
+    }
+}
+
+/// Escapes a string for use in a double quoted XML attribute
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Writes `datastore` as VRT, see [`Datastore::export_vrt`]
+pub fn export<W: Write>(datastore: &Datastore, mut writer: W, options: &VrtOptions) -> Result<(), VrtError> {
+    let layer = datastore.layer_by_name(&options.layer)
+        .ok_or_else(|| VrtError::UnknownLayer(options.layer.clone()))?;
+    if !layer.is_primary() {
+        return Err(VrtError::NotPrimary(options.layer.clone()));
+    }
+
+    let columns = match options.columns.as_ref() {
+        Some(columns) => columns.clone(),
+        None => {
+            let mut names: Vec<String> = layer.variable_names()
+                .filter(|n| !matches!(layer.variable_by_name(n), Some(Variable::ExternalPointer(_) | Variable::Hash(_))))
+                .cloned()
+                .collect();
+            names.sort_by_key(|n| (n.as_str() != "word", n.clone()));
+            names
+        }
+    };
+    let selection = Selection::new(layer, &columns)?;
+
+    let names: Vec<&String> = match options.structures.as_ref() {
+        Some(names) => names.iter().collect(),
+        None => {
+            let mut names: Vec<&String> = datastore.layer_names()
+                .filter(|n| datastore[n.as_str()].as_segmentation().is_some_and(|s| s.base == layer.uuid()))
+                .collect();
+            names.sort_by_key(|n| (datastore[n.as_str()].len(), n.as_str()));
+            names
+        }
+    };
+
+    let mut structures = Vec::with_capacity(names.len());
+    for name in names {
+        let seg_layer = datastore.layer_by_name(name)
+            .ok_or_else(|| VrtError::UnknownLayer(name.clone()))?;
+        let segmentation = seg_layer.as_segmentation()
+            .ok_or_else(|| VrtError::NotSegmentation(name.clone()))?;
+        if segmentation.base != layer.uuid() {
+            return Err(VrtError::UnrelatedLayer(name.clone()));
+        }
+
+        let mut attributes: Vec<(&str, &Variable)> = seg_layer.variables()
+            .filter(|(_, v)| !matches!(v, Variable::ExternalPointer(_) | Variable::Hash(_)))
+            .collect();
+        attributes.sort_by_key(|(n, _)| *n);
+
+        structures.push(Structure { name, layer: segmentation, attributes, next: 0 });
+    }
+
+    // open segments as (structure, end), innermost last
+    let mut open: Vec<(usize, usize)> = Vec::new();
+
+    let mut tags = |writer: &mut W, position: usize| -> io::Result<()> {
+        // close innermost first, crossing segments are closed out of order
+        let mut i = open.len();
+        while i > 0 {
+            i -= 1;
+            let (s, end) = open[i];
+            if end <= position {
+                writeln!(writer, "</{}>", structures[s].name)?;
+                open.remove(i);
+            }
+        }
+
+        for (s, structure) in structures.iter_mut().enumerate() {
+            while structure.next < structure.layer.len() {
+                let (start, end) = structure.layer.get_unchecked(structure.next);
+                if start > position {
+                    break;
+                }
+                structure.write_start(writer, structure.next)?;
+                if end <= position {
+                    writeln!(writer, "</{}>", structure.name)?;
+                } else {
+                    open.push((s, end));
+                }
+                structure.next += 1;
+            }
+        }
+
+        Ok(())
+    };
+
+    for (position, row) in selection.iter().enumerate() {
+        tags(&mut writer, position)?;
+
+        let mut values = row.iter();
+        if let Some(value) = values.next() {
+            write!(writer, "{}", value)?;
+        }
+        for value in values {
+            write!(writer, "\t{}", value)?;
+        }
+        writeln!(writer)?;
+    }
+    tags(&mut writer, selection.len())?;
+
+    writer.flush()?;
+    Ok(())
+}
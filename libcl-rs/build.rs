@@ -2,6 +2,11 @@ use std::env;
 use std::path::PathBuf;
 
 fn main() {
+    // libcl is only available on unix, the crate is empty on other targets
+    if env::var_os("CARGO_CFG_UNIX").is_none() {
+        return;
+    }
+
     // Tell cargo to look for shared libraries in the specified directory
     println!("cargo:rustc-link-search=/path/to/lib");
 
@@ -1,4 +1,6 @@
 #![feature(test)]
+// bindings to the CWB corpus library, which is only available on unix
+#![cfg(unix)]
 
 use core::fmt;
 use std::{
@@ -1,12 +1,22 @@
+#[cfg(unix)]
 use std::error::Error;
+#[cfg(unix)]
 use std::io::{BufWriter, Write};
+#[cfg(unix)]
 use std::{env, io};
 
+#[cfg(unix)]
 use libcl_rs::*;
 
+#[cfg(not(unix))]
+fn main() {
+    eprintln!("cwb-decode needs the CWB corpus library, which is only available on unix");
+}
+
 // simple example implementation of cwb-decode
 // running this program on a corpus should be eqivalent to
 // cwb-decode -Cn -r <registry> <corpus> -ALL
+#[cfg(unix)]
 fn main() -> Result<(), Box<dyn Error>> {
     let args: Vec<_> = env::args().collect();
     if args.len() != 3 {
@@ -25,6 +25,9 @@ parser.add_argument("-u", "--uncompressed", action="store_true",
                     help="Write all components uncompressed (storage mode 0x00)")
 parser.add_argument("-x", "--invalid-xml", action="store_true",
                     help="Fix invalid XML. Encloses whole corpus in an additional root element.")
+parser.add_argument("-c", "--charset", type=str, metavar="charset",
+                    help="""Character encoding of the input, e.g. latin1 or iso-8859-15. The input gets transcoded to UTF-8
+                    while encoding. Default is UTF-8. Requires ziggypy to be built with the "charset" feature.""")
 parser.add_argument("-p", action="append", metavar="p_attribute_name", default=[],
                     help="""Declares and names a p-attribute. Order of declaration must correspond to order of columns in input.
                     P-attributes are encoded as variables on the primary layer of the corpus.
@@ -102,12 +105,12 @@ print("Scanning VRT...")
 
 def open_input():
     if args.input.suffix == ".gz":
-        return gzip.open(args.input, mode = "rt")
+        return gzip.open(args.input, mode = "rt", encoding = args.charset)
     else:
-        return args.input.open()
+        return args.input.open(encoding = args.charset)
 
 # scan file
-clen, pcount, scounts = vrt_stats(realpath(args.input))
+clen, pcount, scounts = vrt_stats(realpath(args.input), charset = args.charset)
 
 print(f"Input corpus has {clen} corpus positions")
 print(f"\t found {pcount} p-attrs in input")
@@ -195,18 +198,18 @@ for i, (name, type) in enumerate(p_attrs):
 
         try:
             if type == "indexed":
-                variable = RustyIndexedStringVariable(primary_layer, f, i, clen, compressed = not args.uncompressed, comment = c)
+                variable = RustyIndexedStringVariable(primary_layer, f, i, clen, compressed = not args.uncompressed, comment = c, charset = args.charset)
             elif type == "plain":
-                variable = RustyPlainStringVariable(primary_layer, f, i, clen, compressed = not args.uncompressed, comment = c)
+                variable = RustyPlainStringVariable(primary_layer, f, i, clen, compressed = not args.uncompressed, comment = c, charset = args.charset)
             elif type == "int":
-                variable = RustyIntegerVariable(primary_layer, f, i, clen, compressed = not args.uncompressed, comment = c, default=args.int_default)
+                variable = RustyIntegerVariable(primary_layer, f, i, clen, compressed = not args.uncompressed, comment = c, default=args.int_default, charset = args.charset)
             elif type == "delta":
-                variable = RustyIntegerVariable(primary_layer, f, i, clen, compressed = not args.uncompressed, comment = c, default=args.int_default, delta=True)
+                variable = RustyIntegerVariable(primary_layer, f, i, clen, compressed = not args.uncompressed, comment = c, default=args.int_default, delta=True, charset = args.charset)
             elif type == "set":
                 variable = FileSetVariable(primary_layer, fileiter, clen, parse_set, comment = c)
             elif type == "ptr":
                 base_index = next(i for i, (n, _) in enumerate(p_attrs) if n == args.ptr_base)
-                variable = RustyPointerVariable(primary_layer, f, base_index, i, clen, compressed = not args.uncompressed, comment = c)
+                variable = RustyPointerVariable(primary_layer, f, base_index, i, clen, compressed = not args.uncompressed, comment = c, charset = args.charset)
             elif type == "skip":
                 continue
             else:
@@ -225,7 +228,7 @@ s_attr_layers = dict()
 
 for attr in s_attrs:
 
-    layer = RustySegmentationLayer(primary_layer, open_input(), attr, scounts[attr], compressed = not args.uncompressed, comment = f"s-attr {attr}")
+    layer = RustySegmentationLayer(primary_layer, open_input(), attr, scounts[attr], compressed = not args.uncompressed, comment = f"s-attr {attr}", charset = args.charset)
 
     s_attr_layers[attr] = layer
     write_datastore_object(layer, f"{attr}/{attr}")
@@ -242,13 +245,13 @@ for attr, annos in s_annos.items():
 
             try:
                 if type == "indexed":
-                    variable = RustyIndexedStringVariable(base_layer, f, (attr, anno), base_layer.n, compressed = not args.uncompressed, comment = c)
+                    variable = RustyIndexedStringVariable(base_layer, f, (attr, anno), base_layer.n, compressed = not args.uncompressed, comment = c, charset = args.charset)
                 elif type == "plain":
-                    variable = RustyPlainStringVariable(base_layer, f, (attr, anno), base_layer.n, compressed = not args.uncompressed, comment = c)
+                    variable = RustyPlainStringVariable(base_layer, f, (attr, anno), base_layer.n, compressed = not args.uncompressed, comment = c, charset = args.charset)
                 elif type == "int":
-                    variable = RustyIntegerVariable(base_layer, f, (attr, anno), base_layer.n, compressed = not args.uncompressed, comment = c, default=args.int_default)
+                    variable = RustyIntegerVariable(base_layer, f, (attr, anno), base_layer.n, compressed = not args.uncompressed, comment = c, default=args.int_default, charset = args.charset)
                 elif type == "delta":
-                    variable = RustyIntegerVariable(base_layer, f, (attr, anno), base_layer.n, compressed = not args.uncompressed, comment = c, default=args.int_default, delta=True)
+                    variable = RustyIntegerVariable(base_layer, f, (attr, anno), base_layer.n, compressed = not args.uncompressed, comment = c, default=args.int_default, delta=True, charset = args.charset)
                 elif type == "set":
                     fileiter = SFileIter(f, attr, fix=args.invalid_xml)
                     data = [a[anno] for _, a in fileiter]
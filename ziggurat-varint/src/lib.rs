@@ -1,6 +1,9 @@
+#[cfg(feature = "python")]
 use pyo3::prelude::*;
+#[cfg(feature = "python")]
 use pyo3::types::PyBytes;
 
+#[cfg(feature = "python")]
 #[pyfunction]
 fn encode_varint(py: Python, x: i64) -> PyObject {
     let mut buffer = [0u8; 9];
@@ -8,6 +11,7 @@ fn encode_varint(py: Python, x: i64) -> PyObject {
     PyBytes::new(py, &buffer[..len]).into()
 }
 
+#[cfg(feature = "python")]
 #[pyfunction]
 fn encode_varint_unsigned(py: Python, x: u64) -> PyObject {
     let mut buffer = [0u8; 9];
@@ -15,6 +19,7 @@ fn encode_varint_unsigned(py: Python, x: u64) -> PyObject {
     PyBytes::new(py, &buffer[..len]).into()
 }
 
+#[cfg(feature = "python")]
 #[pyfunction]
 fn encode_varint_block(py: Python, ints: Vec<i64>) -> PyObject {
     let mut buffer = vec![0u8; ints.len() * 9];
@@ -27,6 +32,7 @@ fn encode_varint_block(py: Python, ints: Vec<i64>) -> PyObject {
     PyBytes::new(py, &buffer[..blen]).into()
 }
 
+#[cfg(feature = "python")]
 #[pyfunction]
 fn encode_varint_block_unsigned(py: Python, ints: Vec<u64>) -> PyObject {
     let mut buffer = vec![0u8; ints.len() * 9];
@@ -40,6 +46,7 @@ fn encode_varint_block_unsigned(py: Python, ints: Vec<u64>) -> PyObject {
 }
 
 /// A Python module implemented in Rust.
+#[cfg(feature = "python")]
 #[pymodule]
 fn ziggurat_varint(_py: Python, m: &PyModule) -> PyResult<()> {
     m.add_function(wrap_pyfunction!(encode_varint, m)?)?;
@@ -111,7 +118,7 @@ pub fn decode_delta_array<const N: usize>(bytes: &[u8]) -> ([i64; N], usize) {
         // rest
         for i in 1..N {
             let (int, readlen) = decode(&bytes[offset..]);
-            output[i] = output[i-1] + int;
+            output[i] = output[i-1].wrapping_add(int);
             offset += readlen;
         }
     }
@@ -143,7 +150,7 @@ pub fn decode_fixed_delta_block(bytes: &[u8], len: usize) -> (Vec<i64>, usize) {
         // rest
         for i in 1..len {
             let (int, readlen) = decode(&bytes[offset..]);
-            output.push(output[i-1] + int);
+            output.push(output[i-1].wrapping_add(int));
             offset += readlen;
         }
     }
@@ -170,7 +177,7 @@ pub fn encode_block_into<I: EncodeVarint>(block: &[I], buffer: &mut [u8]) -> usi
     offset
 }
 
-pub fn encode_delta_block<I: EncodeVarint + Copy + std::ops::Sub<I, Output = I>>(block: &[I]) -> Vec<u8> {
+pub fn encode_delta_block(block: &[i64]) -> Vec<u8> {
     let mut output = vec![0; block.len()*9];
 
     // first value raw
@@ -178,7 +185,7 @@ pub fn encode_delta_block<I: EncodeVarint + Copy + std::ops::Sub<I, Output = I>>
 
     //following values delta
     for i in 1..block.len() {
-        let v = block[i] - block[i-1];
+        let v = block[i].wrapping_sub(block[i-1]);
         len += v.encode_varint_into(&mut output[len..]);
     }
 
@@ -186,13 +193,16 @@ pub fn encode_delta_block<I: EncodeVarint + Copy + std::ops::Sub<I, Output = I>>
     output
 }
 
-pub fn encode_delta_block_into<I: EncodeVarint + Copy + std::ops::Sub<I, Output = I>>(block: &[I], buffer: &mut [u8]) -> usize {
+/// Encodes the first value of `block` and the deltas of all following values.
+///
+/// Deltas wrap around, so any sequence of values can be encoded.
+pub fn encode_delta_block_into(block: &[i64], buffer: &mut [u8]) -> usize {
     // first value raw
     let mut offset = block[0].encode_varint_into(buffer);
 
     // following values delta
     for i in 1..block.len() {
-        let v = block[i] - block[i-1];
+        let v = block[i].wrapping_sub(block[i-1]);
         offset += v.encode_varint_into(&mut buffer[offset..]);
     }
 
@@ -0,0 +1,209 @@
+//! Transcoding of VRT input in legacy charsets to UTF-8.
+//!
+//! CWB corpora are frequently encoded in Latin-1 or another 8-bit charset. The importer
+//! works on UTF-8 only, so such input gets decoded on the fly between the (possibly
+//! gzipped) file and the VRT reader or parser. Charsets are given as WHATWG labels,
+//! e.g. `latin1`, `iso-8859-15` or `windows-1252`.
+//!
+//! Without the `charset` feature only UTF-8 input is accepted.
+
+use std::io::{Error, ErrorKind, Read, Result as IoResult};
+
+#[cfg(feature = "charset")]
+use encoding_rs::{Decoder, Encoding, UTF_8};
+
+/// Wraps `input` so that it yields UTF-8, decoding from `charset` if it is some other encoding
+pub fn decoding_reader(input: Box<dyn Read>, charset: Option<&str>) -> IoResult<Box<dyn Read>> {
+    let label = match charset {
+        Some(label) => label,
+        None => return Ok(input),
+    };
+
+    #[cfg(feature = "charset")]
+    {
+        let encoding = Encoding::for_label(label.trim().as_bytes())
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, format!("unknown charset '{}'", label)))?;
+
+        if encoding == UTF_8 {
+            Ok(input)
+        } else {
+            Ok(Box::new(TranscodingReader::new(input, encoding)))
+        }
+    }
+
+    #[cfg(not(feature = "charset"))]
+    {
+        if label.trim().eq_ignore_ascii_case("utf-8") || label.trim().eq_ignore_ascii_case("utf8") {
+            Ok(input)
+        } else {
+            Err(Error::new(ErrorKind::Unsupported, format!("charset '{}' requires the charset feature", label)))
+        }
+    }
+}
+
+/// Reader adapter that decodes its input from a legacy encoding into UTF-8.
+///
+/// Malformed input sequences are replaced with U+FFFD, a byte order mark at the start of
+/// the input takes precedence over the given encoding.
+#[cfg(feature = "charset")]
+pub struct TranscodingReader<R: Read> {
+    inner: R,
+    decoder: Decoder,
+    input: Box<[u8]>,
+    in_start: usize,
+    in_end: usize,
+    output: Vec<u8>,
+    out_pos: usize,
+    eof: bool,
+}
+
+#[cfg(feature = "charset")]
+impl<R: Read> TranscodingReader<R> {
+    const BUFFER_SIZE: usize = 64 * 1024;
+
+    pub fn new(inner: R, encoding: &'static Encoding) -> Self {
+        Self {
+            inner,
+            decoder: encoding.new_decoder(),
+            input: vec![0; Self::BUFFER_SIZE].into_boxed_slice(),
+            in_start: 0,
+            in_end: 0,
+            output: Vec::new(),
+            out_pos: 0,
+            eof: false,
+        }
+    }
+
+    /// Decodes the next chunk of input into the output buffer, returns false at the end of input
+    fn fill(&mut self) -> IoResult<bool> {
+        if self.eof {
+            return Ok(false);
+        }
+
+        if self.in_start == self.in_end {
+            self.in_start = 0;
+            self.in_end = loop {
+                match self.inner.read(&mut self.input) {
+                    Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                    r => break r?,
+                }
+            };
+            self.eof = self.in_end == 0;
+        }
+
+        let src = &self.input[self.in_start..self.in_end];
+        let max = self.decoder.max_utf8_buffer_length(src.len())
+            .ok_or_else(|| Error::new(ErrorKind::Other, "transcoding buffer overflow"))?;
+        self.output.resize(max, 0);
+
+        let (_, read, written, _) = self.decoder.decode_to_utf8(src, &mut self.output, self.eof);
+        self.in_start += read;
+        self.output.truncate(written);
+        self.out_pos = 0;
+
+        Ok(true)
+    }
+}
+
+#[cfg(feature = "charset")]
+impl<R: Read> Read for TranscodingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        while self.out_pos == self.output.len() {
+            if !self.fill()? {
+                return Ok(0);
+            }
+        }
+
+        let n = buf.len().min(self.output.len() - self.out_pos);
+        buf[..n].copy_from_slice(&self.output[self.out_pos..self.out_pos + n]);
+        self.out_pos += n;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, ErrorKind, Read};
+
+    use super::decoding_reader;
+
+    fn decode(input: Box<dyn Read>, charset: &str) -> String {
+        let mut output = String::new();
+        decoding_reader(input, Some(charset)).unwrap()
+            .read_to_string(&mut output)
+            .unwrap();
+        output
+    }
+
+    #[cfg(feature = "charset")]
+    #[test]
+    fn latin1_across_buffer_boundary() {
+        use super::TranscodingReader;
+
+        // 0xE9 as the last byte of the first buffer and the first byte of the second
+        let size = TranscodingReader::<Cursor<Vec<u8>>>::BUFFER_SIZE;
+        let mut input = vec![b'a'; size - 1];
+        input.extend_from_slice(&[0xE9, 0xE9]);
+        input.extend_from_slice(b"bc");
+
+        let output = decode(Box::new(Cursor::new(input)), "latin1");
+        assert!(output.len() == size - 1 + 2 * 2 + 2);
+        assert!(output[size - 1..] == *"éébc");
+        assert!(output[..size - 1].bytes().all(|b| b == b'a'));
+    }
+
+    #[cfg(feature = "charset")]
+    #[test]
+    fn multibyte_split_between_reads() {
+        /// Hands out its data in reads of at most `chunk` bytes
+        struct Chunked {
+            data: Vec<u8>,
+            pos: usize,
+            chunk: usize,
+        }
+
+        impl Read for Chunked {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                let n = buf.len().min(self.chunk).min(self.data.len() - self.pos);
+                buf[..n].copy_from_slice(&self.data[self.pos..self.pos + n]);
+                self.pos += n;
+                Ok(n)
+            }
+        }
+
+        // 日本語 in Shift_JIS, two bytes per character
+        let input = vec![0x93, 0xFA, 0x96, 0x7B, 0x8C, 0xEA, b'\n'];
+
+        for chunk in [1, 3, 5] {
+            let reader = Chunked { data: input.clone(), pos: 0, chunk };
+            assert!(decode(Box::new(reader), "shift_jis") == "日本語\n");
+        }
+    }
+
+    #[test]
+    fn utf8_is_passed_through() {
+        let input = "Größe".as_bytes().to_vec();
+        assert!(decode(Box::new(Cursor::new(input.clone())), "utf-8") == "Größe");
+
+        let mut output = Vec::new();
+        decoding_reader(Box::new(Cursor::new(input.clone())), None).unwrap()
+            .read_to_end(&mut output)
+            .unwrap();
+        assert!(output == input);
+    }
+
+    #[test]
+    fn unknown_charset() {
+        let error = match decoding_reader(Box::new(Cursor::new(Vec::new())), Some("klingon")) {
+            Ok(_) => panic!("unknown charset accepted"),
+            Err(e) => e,
+        };
+
+        if cfg!(feature = "charset") {
+            assert!(error.kind() == ErrorKind::InvalidInput);
+            assert!(error.to_string().contains("klingon"));
+        } else {
+            assert!(error.kind() == ErrorKind::Unsupported);
+        }
+    }
+}
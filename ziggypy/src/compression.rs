@@ -0,0 +1,67 @@
+//! Decompression of VRT input.
+//!
+//! Corpora are usually shipped compressed, classic CWB dumps as gzip and web corpora often
+//! as zstd, bzip2 or xz. The format is detected from the magic bytes at the start of the
+//! file rather than its name, so misnamed files and uncompressed files ending in `.gz` are
+//! read correctly. Concatenated streams, as written by `pigz` or `pbzip2`, are read as one.
+//!
+//! gzip is always supported, the other formats need the `zstd`, `bzip2` and `xz` features.
+
+use std::io::{BufRead, BufReader, Error, ErrorKind, Read, Result as IoResult};
+
+use flate2::bufread::MultiGzDecoder;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+    Bzip2,
+    Xz,
+}
+
+impl Compression {
+    const MAGIC: [(Self, &'static [u8]); 4] = [
+        (Self::Gzip, &[0x1f, 0x8b]),
+        (Self::Zstd, &[0x28, 0xb5, 0x2f, 0xfd]),
+        (Self::Bzip2, b"BZh"),
+        (Self::Xz, &[0xfd, b'7', b'z', b'X', b'Z', 0x00]),
+    ];
+
+    /// Detects the compression of a file from its first bytes
+    pub fn detect(header: &[u8]) -> Self {
+        Self::MAGIC.iter()
+            .find(|(_, magic)| header.starts_with(magic))
+            .map_or(Self::None, |(compression, _)| *compression)
+    }
+
+    /// Name of the format, which is also the name of the feature needed to read it
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::None => "uncompressed",
+            Self::Gzip => "gzip",
+            Self::Zstd => "zstd",
+            Self::Bzip2 => "bzip2",
+            Self::Xz => "xz",
+        }
+    }
+}
+
+/// Wraps `input` so that it yields the decompressed content of any supported format
+pub fn decompressing_reader<R: Read + 'static>(input: R) -> IoResult<Box<dyn Read>> {
+    let mut input = BufReader::new(input);
+    let compression = Compression::detect(input.fill_buf()?);
+
+    match compression {
+        Compression::None => Ok(Box::new(input)),
+        Compression::Gzip => Ok(Box::new(MultiGzDecoder::new(input))),
+        #[cfg(feature = "zstd")]
+        Compression::Zstd => Ok(Box::new(zstd::stream::read::Decoder::with_buffer(input)?)),
+        #[cfg(feature = "bzip2")]
+        Compression::Bzip2 => Ok(Box::new(bzip2::bufread::MultiBzDecoder::new(input))),
+        #[cfg(feature = "xz")]
+        Compression::Xz => Ok(Box::new(xz2::bufread::XzDecoder::new_multi_decoder(input))),
+        #[allow(unreachable_patterns)]
+        other => Err(Error::new(ErrorKind::Unsupported, format!("{} input requires the {} feature", other.name(), other.name()))),
+    }
+}
@@ -0,0 +1,273 @@
+//! Read access to datastores.
+//!
+//! Integer results are returned as `Int64Array`s, which implement the buffer protocol,
+//! so numpy can wrap them without copying. Uncompressed integer streams are not copied
+//! at all, the array is a view into the container and keeps the datastore alive.
+
+use std::os::raw::{c_char, c_int, c_void};
+use std::ptr;
+
+use etemenanki::components::Vector;
+use etemenanki::variables::{Variable, VariableIterator, VariableValue};
+use etemenanki::{Datastore, DatastoreError};
+use pyo3::exceptions::{PyBufferError, PyIOError, PyIndexError, PyKeyError, PyTypeError, PyValueError};
+use pyo3::ffi;
+use pyo3::prelude::*;
+
+/// Raises IO errors as `IOError` and invalid datastores as `ValueError`
+fn datastore_error(e: DatastoreError) -> PyErr {
+    match e {
+        DatastoreError::IoError(e) => PyIOError::new_err(e.to_string()),
+        e => PyValueError::new_err(e.to_string()),
+    }
+}
+
+fn check_range(start: usize, end: usize, len: usize) -> PyResult<()> {
+    if start <= end && end <= len {
+        Ok(())
+    } else {
+        Err(PyIndexError::new_err(format!("range {}..{} out of bounds for length {}", start, end, len)))
+    }
+}
+
+#[pyclass(unsendable)]
+pub struct DatastoreCore {
+    datastore: Datastore<'static>,
+}
+
+impl DatastoreCore {
+    fn variable(&self, layer: &str, variable: &str) -> PyResult<&Variable<'static>> {
+        self.datastore
+            .layer_by_name(layer)
+            .ok_or_else(|| PyKeyError::new_err(format!("unknown layer {}", layer)))?
+            .variable_by_name(variable)
+            .ok_or_else(|| PyKeyError::new_err(format!("unknown variable {} on layer {}", variable, layer)))
+    }
+}
+
+#[pymethods]
+impl DatastoreCore {
+    #[new]
+    fn new(path: &str) -> PyResult<Self> {
+        let datastore = Datastore::open(path).map_err(datastore_error)?;
+        Ok(Self { datastore })
+    }
+
+    fn layers(&self) -> Vec<String> {
+        let mut names: Vec<_> = self.datastore.layer_names().cloned().collect();
+        names.sort_unstable();
+        names
+    }
+
+    fn variables(&self, layer: &str) -> PyResult<Vec<String>> {
+        let layer = self.datastore
+            .layer_by_name(layer)
+            .ok_or_else(|| PyKeyError::new_err(format!("unknown layer {}", layer)))?;
+
+        let mut names: Vec<_> = layer.variable_names().cloned().collect();
+        names.sort_unstable();
+        Ok(names)
+    }
+
+    fn layer_len(&self, layer: &str) -> PyResult<usize> {
+        self.datastore
+            .layer_by_name(layer)
+            .map(|l| l.len())
+            .ok_or_else(|| PyKeyError::new_err(format!("unknown layer {}", layer)))
+    }
+
+    /// Positions of `value` in an indexed string variable, empty if the value does not occur
+    fn postings(&self, layer: &str, variable: &str, value: &str) -> PyResult<Int64Array> {
+        let var = self.variable(layer, variable)?;
+        let lexicon = var.as_indexed_string()
+            .ok_or_else(|| PyTypeError::new_err(format!("{} is not an indexed string variable", variable)))?
+            .lexicon();
+
+        let positions = lexicon.find_match(value)
+            .and_then(|tid| var.postings(tid))
+            .map(|positions| positions.map(|p| p as i64).collect())
+            .unwrap_or_default();
+
+        Ok(Int64Array::new(ArrayData::Owned(positions)))
+    }
+
+    /// Values `start..end` of an integer variable, a view into the container if it is uncompressed
+    fn int_range(slf: &PyCell<Self>, layer: &str, variable: &str, start: usize, end: usize) -> PyResult<Int64Array> {
+        let this = slf.borrow();
+        let var = this.variable(layer, variable)?
+            .as_integer()
+            .ok_or_else(|| PyTypeError::new_err(format!("{} is not an integer variable", variable)))?;
+        check_range(start, end, var.len())?;
+
+        let data = match var.int_stream().vector() {
+            Vector::Uncompressed { width: 1, data, .. } => ArrayData::View {
+                data: &data[start..end],
+                _owner: slf.into(),
+            },
+            _ => ArrayData::Owned(var.get_range(start, end).expect("range was checked").collect()),
+        };
+
+        Ok(Int64Array::new(data))
+    }
+
+    /// Iterator over the values `start..end` of any variable
+    fn values(slf: &PyCell<Self>, layer: &str, variable: &str, start: usize, end: usize) -> PyResult<ValueIterator> {
+        let this = slf.borrow();
+        let var = this.variable(layer, variable)?;
+        check_range(start, end, var.len())?;
+
+        let values = var.get_range(start, end)
+            .ok_or_else(|| PyTypeError::new_err(format!("values of {} can't be read", variable)))?;
+
+        Ok(ValueIterator {
+            values,
+            _owner: slf.into(),
+        })
+    }
+}
+
+enum ArrayData {
+    Owned(Vec<i64>),
+    /// Uncompressed data of a container, `_owner` keeps its datastore alive
+    View {
+        data: &'static [i64],
+        _owner: Py<DatastoreCore>,
+    },
+}
+
+/// Read-only array of 64 bit integers, exported via the buffer protocol
+#[pyclass]
+pub struct Int64Array {
+    data: ArrayData,
+    shape: [ffi::Py_ssize_t; 1],
+    strides: [ffi::Py_ssize_t; 1],
+}
+
+impl Int64Array {
+    fn new(data: ArrayData) -> Self {
+        let mut array = Self {
+            data,
+            shape: [0],
+            strides: [8],
+        };
+        array.shape[0] = array.as_slice().len() as ffi::Py_ssize_t;
+        array
+    }
+
+    fn as_slice(&self) -> &[i64] {
+        match &self.data {
+            ArrayData::Owned(values) => values,
+            ArrayData::View { data, .. } => data,
+        }
+    }
+}
+
+#[pymethods]
+impl Int64Array {
+    fn __len__(&self) -> usize {
+        self.as_slice().len()
+    }
+
+    fn __getitem__(&self, index: isize) -> PyResult<i64> {
+        let values = self.as_slice();
+        let i = if index < 0 { index + values.len() as isize } else { index };
+
+        usize::try_from(i).ok()
+            .and_then(|i| values.get(i))
+            .copied()
+            .ok_or_else(|| PyIndexError::new_err("index out of range"))
+    }
+
+    /// True if the array points into a memory mapped container instead of owning its data
+    #[getter]
+    fn is_view(&self) -> bool {
+        matches!(self.data, ArrayData::View { .. })
+    }
+
+    fn tolist(&self) -> Vec<i64> {
+        self.as_slice().to_vec()
+    }
+
+    /// Wraps the array in a read-only numpy array without copying
+    fn as_numpy(slf: &PyCell<Self>) -> PyResult<PyObject> {
+        let py = slf.py();
+        let array = py.import("numpy")?
+            .getattr("frombuffer")?
+            .call1((slf, "int64"))?;
+        Ok(array.into())
+    }
+
+    unsafe fn __getbuffer__(slf: &PyCell<Self>, view: *mut ffi::Py_buffer, flags: c_int) -> PyResult<()> {
+        if view.is_null() {
+            return Err(PyBufferError::new_err("view is null"));
+        }
+        if (flags & ffi::PyBUF_WRITABLE) == ffi::PyBUF_WRITABLE {
+            return Err(PyBufferError::new_err("Int64Array is read-only"));
+        }
+
+        let this = slf.borrow();
+        let values = this.as_slice();
+
+        (*view).obj = ffi::_Py_NewRef(slf.as_ptr());
+        (*view).buf = values.as_ptr() as *mut c_void;
+        (*view).len = (values.len() * 8) as ffi::Py_ssize_t;
+        (*view).readonly = 1;
+        (*view).itemsize = 8;
+
+        (*view).format = if (flags & ffi::PyBUF_FORMAT) == ffi::PyBUF_FORMAT {
+            b"q\0".as_ptr() as *mut c_char
+        } else {
+            ptr::null_mut()
+        };
+
+        (*view).ndim = 1;
+        (*view).shape = if (flags & ffi::PyBUF_ND) == ffi::PyBUF_ND {
+            this.shape.as_ptr() as *mut ffi::Py_ssize_t
+        } else {
+            ptr::null_mut()
+        };
+        (*view).strides = if (flags & ffi::PyBUF_STRIDES) == ffi::PyBUF_STRIDES {
+            this.strides.as_ptr() as *mut ffi::Py_ssize_t
+        } else {
+            ptr::null_mut()
+        };
+
+        (*view).suboffsets = ptr::null_mut();
+        (*view).internal = ptr::null_mut();
+
+        Ok(())
+    }
+}
+
+/// Python iterator over the values of a variable, strings, ints, `None` for missing
+/// pointer heads and sparse values, and sets of strings
+#[pyclass(unsendable)]
+pub struct ValueIterator {
+    // declared first, so it is dropped before the datastore it borrows from
+    values: VariableIterator<'static>,
+    _owner: Py<DatastoreCore>,
+}
+
+#[pymethods]
+impl ValueIterator {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(mut slf: PyRefMut<'_, Self>) -> Option<PyObject> {
+        let py = slf.py();
+        slf.values.next().map(|value| match value {
+            VariableValue::String(s) => s.into_py(py),
+            VariableValue::Integer(i) => i.into_py(py),
+            VariableValue::Integers(row) => row.into_py(py),
+            VariableValue::Offsets(start, end) => (start, end).into_py(py),
+            VariableValue::Pointer(head) => head.into_py(py),
+            VariableValue::Set(items) => items.into_py(py),
+            VariableValue::Missing => py.None(),
+        })
+    }
+
+    fn __length_hint__(&self) -> usize {
+        self.values.len()
+    }
+}
@@ -2,12 +2,19 @@
 
 extern crate test;
 
-use std::{collections::{HashMap, VecDeque}, fs::File, io::{BufRead, BufReader, Read, Result as IoResult}, str::FromStr};
-use etemenanki::{layers::SegmentationLayer, variables::{IndexedStringVariable, IntegerVariable, PlainStringVariable, PointerVariable}};
-use flate2::read::MultiGzDecoder;
+mod charset;
+mod compression;
+mod datastore;
+mod profile;
+mod scan;
+
+use std::{collections::{HashMap, VecDeque}, error, fmt, fs::File, io::{BufRead, BufReader, Error as IoError, ErrorKind, Read, Result as IoResult}, str::FromStr, string::FromUtf8Error};
+use etemenanki::{container::EncodeError, fallible::{try_encode, TryEncodeError}, layers::SegmentationLayer, lock::PendingFile, variables::{IndexedStringVariable, IntegerVariable, PlainStringVariable, PointerVariable}};
 use quick_xml::events::Event;
 use quick_xml::reader::Reader;
 
+use profile::{Profile, ProfileError};
+use pyo3::exceptions::{PyIOError, PyValueError};
 use pyo3::prelude::*;
 use uuid::Uuid;
 
@@ -20,13 +27,49 @@ fn module(_py: Python<'_>, m: &PyModule) -> PyResult<()> {
     m.add_function(wrap_pyfunction!(encode_plain_from_p, m)?)?;
     m.add_function(wrap_pyfunction!(encode_ptr_from_p, m)?)?;
     m.add_function(wrap_pyfunction!(encode_seg_from_s, m)?)?;
+    m.add_function(wrap_pyfunction!(encode_seg_with_attrs, m)?)?;
     m.add_function(wrap_pyfunction!(encode_int_from_a, m)?)?;
     m.add_function(wrap_pyfunction!(encode_int_from_p, m)?)?;
+    m.add_function(wrap_pyfunction!(encode_shared, m)?)?;
     m.add_function(wrap_pyfunction!(vrt_stats, m)?)?;
+    m.add_function(wrap_pyfunction!(build_from_profile, m)?)?;
     m.add_class::<IntVariableCore>()?;
+    m.add_class::<scan::Sink>()?;
+    m.add_class::<datastore::DatastoreCore>()?;
+    m.add_class::<datastore::Int64Array>()?;
+    m.add_class::<datastore::ValueIterator>()?;
     Ok(())
 }
 
+/// Raises encoder IO errors as `IOError` and invalid input as `ValueError`
+fn encode_error(e: EncodeError) -> PyErr {
+    match e {
+        EncodeError::Io(e) => PyIOError::new_err(e.to_string()),
+        e => PyValueError::new_err(e.to_string()),
+    }
+}
+
+/// Raises the error of the input if it failed, which explains any error of the encoder
+fn input_error(e: TryEncodeError<VrtError>) -> PyErr {
+    match e {
+        TryEncodeError::Source(e) => e.into(),
+        TryEncodeError::Encode(e) => encode_error(e),
+    }
+}
+
+fn profile_error(e: ProfileError) -> PyErr {
+    match e {
+        ProfileError::Io(e) => PyIOError::new_err(e.to_string()),
+        ProfileError::Encode(e) => encode_error(e),
+        ProfileError::Input(e) => e.into(),
+        e => PyValueError::new_err(e.to_string()),
+    }
+}
+
+fn parse_uuid(base: &str) -> PyResult<Uuid> {
+    Uuid::from_str(base).map_err(|e| PyValueError::new_err(format!("invalid base UUID '{}': {}", base, e)))
+}
+
 #[pyclass]
 struct IntVariableCore {
     length: usize,
@@ -46,199 +89,340 @@ impl IntVariableCore {
     }
 }
 
+
 #[pyfunction]
-fn encode_indexed_from_a(input: &str, tag: &str, attr: &str, length: usize, base: &str, compressed: bool, comment: &str, output: &str){
-    let parser = open_parser(input).unwrap();
+#[pyo3(signature = (input, tag, attr, length, base, compressed, comment, output, charset=None))]
+fn encode_indexed_from_a(input: &str, tag: &str, attr: &str, length: usize, base: &str, compressed: bool, comment: &str, output: &str, charset: Option<&str>) -> PyResult<()> {
+    let base_uuid = parse_uuid(base)?;
+    let parser = open_parser(input, charset)?;
     let strings = parser
         .a_iter(tag, attr)
-        .map(|(_, _, str)| str);
-
-
-    let base_uuid = Uuid::from_str(base).unwrap();
+        .checked()
+        .map(|r| r.map(|(_, _, str)| str));
 
-    let file = File::options()
-        .read(true)
-        .write(true)
-        .create(true)
-        .open(output)
-        .unwrap();
+    let file = PendingFile::replace(output)?;
 
-    IndexedStringVariable::encode_to_file(file, strings, length, "mar".to_owned(), base_uuid, compressed, comment);
+    try_encode(file, strings, |file, strings| {
+        IndexedStringVariable::encode_to_file(file, strings, length, "mar".to_owned(), base_uuid, compressed, comment)
+    }).map_err(input_error)?;
+    Ok(())
 }
 
 #[pyfunction]
-fn encode_indexed_from_p(input: &str, column: usize, length: usize, base: &str, compressed: bool, comment: &str, output: &str){
-    let reader = open_reader(input).unwrap();
-    let strings = reader.iter_p(column).map(|(_, s)| s);
-
-    let base_uuid = Uuid::from_str(base).unwrap();
+#[pyo3(signature = (input, column, length, base, compressed, comment, output, charset=None))]
+fn encode_indexed_from_p(input: &str, column: usize, length: usize, base: &str, compressed: bool, comment: &str, output: &str, charset: Option<&str>) -> PyResult<()> {
+    let base_uuid = parse_uuid(base)?;
+    let reader = open_reader(input, charset)?;
+    let strings = reader.iter_p(column).checked().map(|r| r.map(|(_, s)| s));
 
-    let file = File::options()
-        .read(true)
-        .write(true)
-        .create(true)
-        .open(output)
-        .unwrap();
+    let file = PendingFile::replace(output)?;
 
-    IndexedStringVariable::encode_to_file(file, strings, length, "mar".to_owned(), base_uuid, compressed, comment);
+    try_encode(file, strings, |file, strings| {
+        IndexedStringVariable::encode_to_file(file, strings, length, "mar".to_owned(), base_uuid, compressed, comment)
+    }).map_err(input_error)?;
+    Ok(())
 }
 
 #[pyfunction]
-fn encode_plain_from_a(input: &str, tag: &str, attr: &str, length: usize, base: &str, compressed: bool, comment: &str, output: &str){
-    let parser = open_parser(input).unwrap();
+#[pyo3(signature = (input, tag, attr, length, base, compressed, comment, output, charset=None))]
+fn encode_plain_from_a(input: &str, tag: &str, attr: &str, length: usize, base: &str, compressed: bool, comment: &str, output: &str, charset: Option<&str>) -> PyResult<()> {
+    let base_uuid = parse_uuid(base)?;
+    let parser = open_parser(input, charset)?;
     let strings = parser
         .a_iter(tag, attr)
-        .map(|(_, _, str)| str);
+        .checked()
+        .map(|r| r.map(|(_, _, str)| str));
 
+    let file = PendingFile::replace(output)?;
 
-    let base_uuid = Uuid::from_str(base).unwrap();
+    try_encode(file, strings, |file, strings| {
+        PlainStringVariable::encode_to_file(file, strings, length, "duk".to_owned(), base_uuid, compressed, comment)
+    }).map_err(input_error)?;
+    Ok(())
+}
+
+#[pyfunction]
+#[pyo3(signature = (input, column, length, base, compressed, comment, output, charset=None))]
+fn encode_plain_from_p(input: &str, column: usize, length: usize, base: &str, compressed: bool, comment: &str, output: &str, charset: Option<&str>) -> PyResult<()> {
+    let base_uuid = parse_uuid(base)?;
+    let reader = open_reader(input, charset)?;
+    let strings = reader.iter_p(column).checked().map(|r| r.map(|(_, s)| s));
 
-    let file = File::options()
-        .read(true)
-        .write(true)
-        .create(true)
-        .open(output)
-        .unwrap();
+    let file = PendingFile::replace(output)?;
 
-    PlainStringVariable::encode_to_file(file, strings, length, "duk".to_owned(), base_uuid, compressed, comment);
+    try_encode(file, strings, |file, strings| {
+        PlainStringVariable::encode_to_file(file, strings, length, "duk".to_owned(), base_uuid, compressed, comment)
+    }).map_err(input_error)?;
+    Ok(())
 }
 
+/// Values which are not integers are encoded as `default`.
 #[pyfunction]
-fn encode_plain_from_p(input: &str, column: usize, length: usize, base: &str, compressed: bool, comment: &str, output: &str){
-    let reader = open_reader(input).unwrap();
-    let strings = reader.iter_p(column).map(|(_, s)| s);
+#[pyo3(signature = (input, column, length, default, base, compressed, delta, comment, output, charset=None))]
+fn encode_int_from_p(input: &str, column: usize, length: usize, default: i64, base: &str, compressed: bool, delta: bool, comment: &str, output: &str, charset: Option<&str>) -> PyResult<()> {
+    let base_uuid = parse_uuid(base)?;
+    let reader = open_reader(input, charset)?;
+    let values = reader
+        .iter_p(column)
+        .checked()
+        .map(|r| r.map(|(_, str)| str.parse().unwrap_or(default)));
+
+    let file = PendingFile::replace(output)?;
+
+    try_encode(file, values, |file, values| {
+        IntegerVariable::encode_to_file(file, values, length, "bla".to_owned(), base_uuid, compressed, delta, comment)
+    }).map_err(input_error)?;
+    Ok(())
+}
 
-    let base_uuid = Uuid::from_str(base).unwrap();
+/// Values which are not integers are encoded as `default`.
+#[pyfunction]
+#[pyo3(signature = (input, tag, attr, length, default, base, compressed, delta, comment, output, charset=None))]
+fn encode_int_from_a(input: &str, tag: &str, attr: &str, length: usize, default: i64, base: &str, compressed: bool, delta: bool, comment: &str, output: &str, charset: Option<&str>) -> PyResult<()> {
+    let base_uuid = parse_uuid(base)?;
+    let parser = open_parser(input, charset)?;
+    let values = parser
+        .a_iter(tag, attr)
+        .checked()
+        .map(|r| r.map(|(_, _, str)| str.parse().unwrap_or(default)));
 
-    let file = File::options()
-        .read(true)
-        .write(true)
-        .create(true)
-        .open(output)
-        .unwrap();
+    let file = PendingFile::replace(output)?;
 
-    PlainStringVariable::encode_to_file(file, strings, length, "duk".to_owned(), base_uuid, compressed, comment);
+    try_encode(file, values, |file, values| {
+        IntegerVariable::encode_to_file(file, values, length, "bla".to_owned(), base_uuid, compressed, delta, comment)
+    }).map_err(input_error)?;
+    Ok(())
 }
 
 #[pyfunction]
-fn encode_int_from_p(input: &str, column: usize, length: usize, default: i64, base: &str, compressed: bool, delta: bool, comment: &str, output: &str) {
-    let reader = open_reader(input).unwrap();
-    let values = PIntIter {
-        reader,
-        column,
-        default,
-    };
+#[pyo3(signature = (input, s_tag, length, base, compressed, comment, output, charset=None))]
+fn encode_seg_from_s(input: &str, s_tag: &str, length: usize, base: &str, compressed: bool, comment: &str, output: &str, charset: Option<&str>) -> PyResult<(usize, String)> {
+    let base_uuid = parse_uuid(base)?;
+    let parser = open_parser(input, charset)?;
+    let values = parser
+        .s_iter(s_tag)
+        .checked();
 
-    let base_uuid = Uuid::from_str(base).unwrap();
+    let file = PendingFile::replace(output)?;
 
-    let file = File::options()
-        .read(true)
-        .write(true)
-        .create(true)
-        .open(output)
-        .unwrap();
-    IntegerVariable::encode_to_file(file, values, length, "bla".to_owned(), base_uuid, compressed, delta, comment);
+    let layer = try_encode(file, values, |file, values| {
+        SegmentationLayer::encode_to_file(file, values, length, "bla".to_owned(), base_uuid, compressed, comment)
+    }).map_err(input_error)?;
+    Ok((layer.len(), layer.uuid().to_string()))
 }
 
+/// Encodes the `s_tag` ranges and its attributes `attrs` as indexed string variables in a
+/// single pass over the input. Tags without one of the attributes get an empty string.
+///
+/// Returns the length and UUID of the layer and the UUIDs of the variables.
 #[pyfunction]
-fn encode_int_from_a(input: &str, tag: &str, attr: &str, length: usize, default: i64, base: &str, compressed: bool, delta: bool, comment: &str, output: &str) {
-    let parser = open_parser(input).unwrap();
-    let values = parser
-        .a_iter(tag, attr)
-        .map(|(_, _, str)| str.parse().unwrap_or(default));
+#[pyo3(signature = (input, s_tag, attrs, length, base, compressed, comment, output, attr_outputs, charset=None))]
+fn encode_seg_with_attrs(input: &str, s_tag: &str, attrs: Vec<String>, length: usize, base: &str, compressed: bool, comment: &str, output: &str, attr_outputs: Vec<String>, charset: Option<&str>) -> PyResult<(usize, String, Vec<String>)> {
+    if attrs.len() != attr_outputs.len() {
+        return Err(PyValueError::new_err(format!("need one output file per attribute, got {} attributes and {} files", attrs.len(), attr_outputs.len())));
+    }
+    let base_uuid = parse_uuid(base)?;
+
+    let mut ranges = Vec::new();
+    let mut values = vec![Vec::new(); attrs.len()];
+    for result in open_parser(input, charset)?.sa_iter(s_tag).checked() {
+        let (start, end, mut tag_attrs) = result?;
+        ranges.push((start, end));
+        for (attr, values) in attrs.iter().zip(values.iter_mut()) {
+            values.push(tag_attrs.remove(attr).unwrap_or_default());
+        }
+    }
 
-    let base_uuid = Uuid::from_str(base).unwrap();
+    let layer = SegmentationLayer::encode_to_file(PendingFile::replace(output)?, ranges.into_iter(), length, s_tag.to_owned(), base_uuid, compressed, comment)
+        .map_err(encode_error)?;
 
-    let file = File::options()
-        .read(true)
-        .write(true)
-        .create(true)
-        .open(output)
-        .unwrap();
+    let mut uuids = Vec::with_capacity(attrs.len());
+    for ((attr, values), path) in attrs.into_iter().zip(values).zip(attr_outputs) {
+        let variable = IndexedStringVariable::encode_to_file(PendingFile::replace(&path)?, values.into_iter(), layer.len(), attr, layer.uuid(), compressed, comment)
+            .map_err(encode_error)?;
+        uuids.push(variable.uuid().to_string());
+    }
 
-    IntegerVariable::encode_to_file(file, values, length, "bla".to_owned(), base_uuid, compressed, delta, comment);
+    Ok((layer.len(), layer.uuid().to_string(), uuids))
 }
 
 #[pyfunction]
-fn encode_seg_from_s(input: &str, s_tag: &str, length: usize, base: &str, compressed: bool, comment: &str, output: &str) -> (usize, String) {
-    let parser = open_parser(input).unwrap();
-    let values = parser
-        .s_iter(s_tag);
+#[pyo3(signature = (input, basecol, headcol, length, base, compressed, comment, output, charset=None))]
+fn encode_ptr_from_p(input: &str, basecol: usize, headcol: usize, length: usize, base: &str, compressed: bool, comment: &str, output: &str, charset: Option<&str>) -> PyResult<usize> {
+    let base_uuid = parse_uuid(base)?;
+    let tails = open_reader(input, charset)?.iter_p(basecol).checked();
+    let heads = open_reader(input, charset)?.iter_p(headcol).checked();
+
+    let values = tails.zip(heads).map(|(tail, head)| {
+        let ((cpos, base), (_, head)) = (tail?, head?);
+        Ok(pointer(cpos, &base, &head))
+    });
+
+    let file = PendingFile::replace(output)?;
+
+    let variable = try_encode(file, values, |file, values| {
+        PointerVariable::encode_to_file(file, values, length, "".to_owned(), base_uuid, compressed, comment)
+    }).map_err(input_error)?;
+    Ok(variable.len())
+}
 
-    let base_uuid = Uuid::from_str(base).unwrap();
+/// Encodes the layers and variables described by `sinks` in a single pass over the input,
+/// see the `scan` module. Returns the length and UUID of each container.
+#[pyfunction]
+#[pyo3(signature = (input, sinks, charset=None))]
+fn encode_shared(input: &str, sinks: Vec<scan::Sink>, charset: Option<&str>) -> PyResult<Vec<(usize, String)>> {
+    let parser = open_parser(input, charset)?;
+    let encoded = scan::encode_shared(parser, &sinks).map_err(input_error)?;
+    Ok(encoded.into_iter().map(|(len, uuid)| (len, uuid.to_string())).collect())
+}
 
-    let file = File::options()
-        .read(true)
-        .write(true)
-        .create(true)
-        .open(output)
-        .unwrap();
+/// Position of the head of the token at `cpos`, from the index of the token `base` and of
+/// its head `head` within the sentence. Roots (head 0) point to themselves, tokens without
+/// valid indices to -1.
+fn pointer(cpos: usize, base: &str, head: &str) -> i64 {
+    match (base.parse::<i64>(), head.parse::<i64>()) {
+        (Ok(_), Ok(0)) => cpos as i64,
+        (Ok(b), Ok(h)) => cpos as i64 + (h - b),
+        _ => -1,
+    }
+}
 
-    let layer = SegmentationLayer::encode_to_file(file, values, length, "bla".to_owned(), base_uuid, compressed, comment);
-    (layer.len(), layer.header.uuid().to_string())
+/// Encodes `input` as a datastore in `output` as described by the corpus profile at `profile`
+/// and returns the number of tokens, see the `profile` module.
+#[pyfunction]
+#[pyo3(signature = (input, profile, output, resume=false))]
+fn build_from_profile(input: &str, profile: &str, output: &str, resume: bool) -> PyResult<usize> {
+    let profile = Profile::read(profile).map_err(profile_error)?;
+    profile::build_from_profile(input, &profile, output, resume).map_err(profile_error)
 }
 
 #[pyfunction]
-fn encode_ptr_from_p(input: &str, basecol: usize, headcol: usize, length: usize, base: &str, compressed: bool, comment: &str, output: &str) -> usize {
-    let tails = open_reader(input).unwrap().iter_p(basecol);
-    let heads = open_reader(input).unwrap().iter_p(headcol);
-
-    let values = tails.zip(heads).map(|((cpos, base), (_, head))| {
-        if let Ok(h) = head.parse::<i64>() {
-            if let Ok(b) = base.parse::<i64>() {
-                if h == 0 {
-                    return cpos as i64;
-                } else {
-                    return cpos as i64 + (h - b);
-                }
+#[pyo3(signature = (input, charset=None))]
+fn vrt_stats(input: &str, charset: Option<&str>) -> PyResult<(usize, usize, HashMap<String, usize>)> {
+    let mut reader = open_reader(input, charset)?;
+    let stats = reader.stats();
+    match reader.take_error() {
+        Some(e) => Err(e.into()),
+        None => Ok(stats),
+    }
+}
+
+/// Error which ended the reading of a VRT file
+#[derive(Debug)]
+pub enum VrtError {
+    Io(IoError),
+    Xml(quick_xml::Error),
+    /// A token line has no column `column`
+    MissingColumn { position: usize, column: usize, found: usize },
+    /// A tag `tag` without the attribute `attr` starting at `position`
+    MissingAttribute { position: usize, tag: String, attr: String },
+    /// An end tag which doesn't close the last open tag
+    UnbalancedTag { position: usize, tag: String },
+}
+
+impl fmt::Display for VrtError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{}", e),
+            Self::Xml(e) => write!(f, "invalid XML: {}", e),
+            Self::MissingColumn { position, column, found } => {
+                write!(f, "token {} has {} columns, no column {}", position, found, column)
+            }
+            Self::MissingAttribute { position, tag, attr } => {
+                write!(f, "<{}> at {} has no attribute {}", tag, position, attr)
             }
+            Self::UnbalancedTag { position, tag } => write!(f, "unexpected </{}> at {}", tag, position),
         }
-        -1
-    });
+    }
+}
 
-    let base_uuid = Uuid::from_str(base).unwrap();
+impl error::Error for VrtError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            Self::Xml(e) => Some(e),
+            _ => None,
+        }
+    }
+}
 
-    let file = File::options()
-        .read(true)
-        .write(true)
-        .create(true)
-        .open(output)
-        .unwrap();
+impl From<IoError> for VrtError {
+    fn from(e: IoError) -> Self {
+        Self::Io(e)
+    }
+}
 
-    let variable = PointerVariable::encode_to_file(file, values, length, "".to_owned(), base_uuid, compressed, comment);
-    variable.len()
+impl From<quick_xml::Error> for VrtError {
+    fn from(e: quick_xml::Error) -> Self {
+        Self::Xml(e)
+    }
 }
 
-#[pyfunction]
-fn vrt_stats(input: &str) -> (usize, usize, HashMap<String, usize>) {
-    let mut reader = open_reader(input).unwrap();
-    reader.stats()
+impl From<FromUtf8Error> for VrtError {
+    fn from(e: FromUtf8Error) -> Self {
+        Self::Io(IoError::new(ErrorKind::InvalidData, e))
+    }
 }
 
-pub struct PIter<R: Read> {
-    reader: VrtReader<R>,
-    column: usize,
+impl From<VrtError> for PyErr {
+    fn from(e: VrtError) -> Self {
+        match e {
+            VrtError::Io(e) => PyIOError::new_err(e.to_string()),
+            e => PyValueError::new_err(e.to_string()),
+        }
+    }
 }
 
-impl<R: Read> Iterator for PIter<R> {
-    type Item = (usize, String);
+/// Iterator over a VRT file which ends at the first error of the input
+pub trait VrtIterator: Iterator + Sized {
+    /// Takes the error which ended the iterator, if any
+    fn take_error(&mut self) -> Option<VrtError>;
+
+    /// Yields the items as `Ok`, followed by the error if the input failed, e.g. for
+    /// [`try_encode`]
+    fn checked(self) -> Checked<Self> {
+        Checked { iter: self, done: false }
+    }
+}
+
+pub struct Checked<I> {
+    iter: I,
+    done: bool,
+}
+
+impl<I: VrtIterator> Iterator for Checked<I> {
+    type Item = Result<I::Item, VrtError>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.reader.next_p(self.column).map(|(i, s)| (i, s.to_string()))
+        if self.done {
+            return None;
+        }
+        match self.iter.next() {
+            Some(item) => Some(Ok(item)),
+            None => {
+                self.done = true;
+                self.iter.take_error().map(Err)
+            }
+        }
     }
 }
 
-struct PIntIter<R: Read> {
+pub struct PIter<R: Read> {
     reader: VrtReader<R>,
     column: usize,
-    default: i64,
 }
 
-impl<R: Read> Iterator for PIntIter<R> {
-    type Item = i64;
+impl<R: Read> Iterator for PIter<R> {
+    type Item = (usize, String);
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.reader.next_p(self.column).map(|(_, str)| str.parse().unwrap_or(self.default))
+        self.reader.next_p(self.column).map(|(i, s)| (i, s.to_string()))
+    }
+}
+
+impl<R: Read> VrtIterator for PIter<R> {
+    fn take_error(&mut self) -> Option<VrtError> {
+        self.reader.take_error()
     }
 }
 
@@ -249,10 +433,38 @@ pub enum ReaderEvent<'a> {
     TagClose(usize, &'a str),
 }
 
+/// Token line of a VRT file split into its columns
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record<'a> {
+    pub position: usize,
+    pub values: Vec<&'a str>,
+    /// Names of the columns known to the reader, empty without a declaration
+    pub columns: &'a [String],
+}
+
+impl<'a> Record<'a> {
+    /// Value of the column `name`, `None` if the column is unknown or missing in this token
+    pub fn get(&self, name: &str) -> Option<&'a str> {
+        let i = self.columns.iter().position(|c| c == name)?;
+        self.values.get(i).copied()
+    }
+
+    /// Value of the column `name` parsed as `T`
+    pub fn parse<T: FromStr>(&self, name: &str) -> Option<Result<T, T::Err>> {
+        self.get(name).map(str::parse)
+    }
+}
+
+/// Declaration of the column names in a comment of a VRT file, as emitted by e.g. the
+/// Korp pipeline: `<!-- #vrt positional-attributes: word pos lemma -->`
+pub const COLUMNS_DECLARATION: &str = "#vrt positional-attributes:";
+
 pub struct VrtReader<R: Read> {
     reader: BufReader<R>,
     cpos: usize,
     last_line: String,
+    error: Option<VrtError>,
+    columns: Option<Vec<String>>,
 }
 
 impl<R: Read> VrtReader<R> {
@@ -261,6 +473,8 @@ impl<R: Read> VrtReader<R> {
             reader: BufReader::new(readable),
             cpos: 0,
             last_line: String::new(),
+            error: None,
+            columns: None,
         }
     }
 
@@ -268,38 +482,97 @@ impl<R: Read> VrtReader<R> {
         &self.last_line
     }
 
+    /// Takes the error which ended the reader, if any
+    pub fn take_error(&mut self) -> Option<VrtError> {
+        self.error.take()
+    }
+
+    /// Names of the columns, from [`set_columns`](Self::set_columns) or the first
+    /// [declaration](COLUMNS_DECLARATION) read so far
+    pub fn columns(&self) -> Option<&[String]> {
+        self.columns.as_deref()
+    }
+
+    /// Names the columns of an input without a declaration, any declaration is ignored
+    pub fn set_columns(&mut self, columns: Vec<String>) {
+        self.columns = Some(columns);
+    }
+
+    /// Index of the column `name`
+    pub fn column(&self, name: &str) -> Option<usize> {
+        self.columns.as_ref()?.iter().position(|c| c == name)
+    }
+
+    /// Reads the next line, skipping comments after taking the column names from a declaration
     pub fn read_next(&mut self) -> Option<ReaderEvent> {
-        self.last_line.clear();
-        match self.reader.read_line(&mut self.last_line) {
-            Ok(0) => None,
-
-            Ok(_) => {
-                let mut line = self.last_line.trim();
-                if line.starts_with("</") {
-                    line = line.trim_start_matches("</");
-                    line = line.split_whitespace().next().unwrap();
-                    line = line.trim_end_matches('>');
-                    Some(ReaderEvent::TagClose(self.cpos, line))
-                } else if line.starts_with('<') {
-                    line = line.trim_start_matches('<');
-                    line = line.split_whitespace().next().unwrap();
-                    line = line.trim_end_matches('>');
-                    Some(ReaderEvent::TagOpen(self.cpos, line))
-                } else {
-                    let value = ReaderEvent::Line(self.cpos);
-                    self.cpos += 1;
-                    Some(value)
+        if self.error.is_some() {
+            return None;
+        }
+
+        // comments are skipped after taking the column names from a declaration
+        loop {
+            self.last_line.clear();
+            match self.reader.read_line(&mut self.last_line) {
+                Ok(0) => return None,
+                Ok(_) => (),
+                Err(e) => {
+                    self.error = Some(e.into());
+                    return None;
                 }
             }
 
-            Err(_) => None,
+            let Some(comment) = self.last_line.trim().strip_prefix("<!--") else {
+                break;
+            };
+            let comment = comment.trim_end_matches("-->").trim();
+            if let (None, Some(names)) = (&self.columns, comment.strip_prefix(COLUMNS_DECLARATION)) {
+                self.columns = Some(names.split_whitespace().map(str::to_owned).collect());
+            }
+        }
+
+        let mut line = self.last_line.trim();
+        if line.starts_with("</") {
+            line = line.trim_start_matches("</");
+            line = line.split_whitespace().next().unwrap_or_default();
+            line = line.trim_end_matches('>');
+            Some(ReaderEvent::TagClose(self.cpos, line))
+        } else if line.starts_with('<') {
+            line = line.trim_start_matches('<');
+            line = line.split_whitespace().next().unwrap_or_default();
+            line = line.trim_end_matches('>');
+            Some(ReaderEvent::TagOpen(self.cpos, line))
+        } else {
+            let value = ReaderEvent::Line(self.cpos);
+            self.cpos += 1;
+            Some(value)
         }
     }
 
+    /// Returns the next token with all of its columns
+    pub fn next_record(&mut self) -> Option<Record> {
+        while let Some(event) = self.read_next() {
+            if let ReaderEvent::Line(cpos) = event {
+                return Some(Record {
+                    position: cpos,
+                    values: self.last_line.trim().split('\t').collect(),
+                    columns: self.columns.as_deref().unwrap_or_default(),
+                });
+            }
+        }
+        None
+    }
+
+    /// Returns the value of `column` of the next token, ending the reader with
+    /// [`VrtError::MissingColumn`] if the token has fewer columns
     pub fn next_p(&mut self, column: usize) -> Option<(usize, &str)> {
         while let Some(event) = self.read_next() {
             match event {
                 ReaderEvent::Line(cpos) => {
+                    let found = self.last_line.trim().split('\t').count();
+                    if column >= found {
+                        self.error = Some(VrtError::MissingColumn { position: cpos, column, found });
+                        return None;
+                    }
                     return self.last_line.trim()
                         .split('\t')
                         .nth(column)
@@ -341,22 +614,19 @@ impl<R: Read> VrtReader<R> {
     }
 }
 
-pub fn open_reader(filename: &str) -> IoResult<VrtReader<Box<dyn Read>>> {
-    let file = File::open(filename)?;
-    if filename.ends_with("gz") {
-        Ok(VrtReader::new(Box::new(MultiGzDecoder::new(file))))
-    } else {
-        Ok(VrtReader::new(Box::new(file)))
-    }
+/// Opens `filename`, decompressing it if it is compressed and transcoding from `charset` to UTF-8
+fn open_input(filename: &str, charset: Option<&str>) -> IoResult<Box<dyn Read>> {
+    let file = File::open(filename).map_err(|e| IoError::new(e.kind(), format!("{}: {}", filename, e)))?;
+    let input = compression::decompressing_reader(file).map_err(|e| IoError::new(e.kind(), format!("{}: {}", filename, e)))?;
+    charset::decoding_reader(input, charset)
 }
 
-pub fn open_parser(filename: &str) -> IoResult<VrtParser<Box<dyn Read>>> {
-    let file = File::open(filename)?;
-    if filename.ends_with("gz") {
-        Ok(VrtParser::new(Box::new(MultiGzDecoder::new(file))))
-    } else {
-        Ok(VrtParser::new(Box::new(file)))
-    }
+pub fn open_reader(filename: &str, charset: Option<&str>) -> IoResult<VrtReader<Box<dyn Read>>> {
+    Ok(VrtReader::new(open_input(filename, charset)?))
+}
+
+pub fn open_parser(filename: &str, charset: Option<&str>) -> IoResult<VrtParser<Box<dyn Read>>> {
+    Ok(VrtParser::new(open_input(filename, charset)?))
 }
 
 #[derive(Debug)]
@@ -373,6 +643,7 @@ pub struct VrtParser<R: Read> {
     lpos: usize,
     ltotal: usize,
     stack: Vec<(usize, String, HashMap<String, String>)>,
+    error: Option<VrtError>,
 }
 
 impl<R: Read> VrtParser<R> {
@@ -389,17 +660,38 @@ impl<R: Read> VrtParser<R> {
             lpos: 0,
             ltotal: 0,
             stack: Vec::new(),
+            error: None,
         }
     }
 
+    /// Takes the error which ended the parser, if any
+    pub fn take_error(&mut self) -> Option<VrtError> {
+        self.error.take()
+    }
+
     fn read_next(&mut self) -> Option<ParserEvent> {
+        if self.error.is_some() {
+            return None;
+        }
+
+        match self.try_read_next() {
+            Ok(event) => event,
+            Err(e) => {
+                self.error = Some(e);
+                None
+            }
+        }
+    }
+
+    fn try_read_next(&mut self) -> Result<Option<ParserEvent>, VrtError> {
         // if there are lines in the buffer return them as individual line events
         if self.lpos < self.ltotal {
-            let line = self.lines.pop_front().unwrap();
-            let attr = ParserEvent::PLine(self.cpos, line);
-            self.cpos += 1;
-            self.lpos += 1;
-            return Some(attr);
+            if let Some(line) = self.lines.pop_front() {
+                let attr = ParserEvent::PLine(self.cpos, line);
+                self.cpos += 1;
+                self.lpos += 1;
+                return Ok(Some(attr));
+            }
         }
 
         // line buffer done
@@ -408,64 +700,74 @@ impl<R: Read> VrtParser<R> {
         self.lines.clear(); // line buffer
         self.buffer.clear(); // event buffer
 
-        while let Some(event) = self.xml.read_event_into(&mut self.buffer).ok() {
+        loop {
             // process next XML event
-            match event {
+            match self.xml.read_event_into(&mut self.buffer)? {
                 Event::Start(s) => {
                     // copy tag name and attributes and put it on the parse stack
-                    let name = String::from_utf8(s.local_name().into_inner().to_owned()).unwrap();
-                    let attrs: Result<HashMap<String, String>, _> = s.attributes().map(| res | {
-                        res.map(| attr | {
-                            let key = String::from_utf8(attr.key.local_name().into_inner().to_owned()).unwrap();
-                            let value = attr.decode_and_unescape_value(&mut self.xml).unwrap().to_string();
-                            (key, value)
-                        })
-                    })
-                    .collect();
-                    
-                    self.stack.push((self.cpos, name, attrs.unwrap()));
+                    let name = String::from_utf8(s.local_name().into_inner().to_owned())?;
+                    let mut attrs = HashMap::new();
+                    for attr in s.attributes() {
+                        let attr = attr.map_err(quick_xml::Error::from)?;
+                        let key = String::from_utf8(attr.key.local_name().into_inner().to_owned())?;
+                        let value = attr.decode_and_unescape_value(&self.xml)?.to_string();
+                        attrs.insert(key, value);
+                    }
+
+                    self.stack.push((self.cpos, name, attrs));
                     continue
                 }
 
                 Event::End(e) => {
                     // try close last tag from the stack and return event
-                    if let Some((start, name, attrs)) = self.stack.pop() {
-                        // if the last start tag returned from the stack does not match the current end tag
-                        // we have invalid xml. <a><b></a></b> cannot be possible.
-                        assert!(e.local_name().into_inner() == name.as_bytes(), "unclosed S attr");
-                        return Some(ParserEvent::SAttr(start, self.cpos, name, attrs))
-                    }
-                    panic!("encountered end tag before first start tag");
+                    // if the last start tag returned from the stack does not match the current end tag
+                    // we have invalid xml. <a><b></a></b> cannot be possible.
+                    return match self.stack.pop() {
+                        Some((start, name, attrs)) if e.local_name().into_inner() == name.as_bytes() => {
+                            Ok(Some(ParserEvent::SAttr(start, self.cpos, name, attrs)))
+                        }
+                        _ => Err(VrtError::UnbalancedTag {
+                            position: self.cpos,
+                            tag: String::from_utf8_lossy(e.local_name().into_inner()).into_owned(),
+                        }),
+                    };
                 }
 
                 Event::Text(t) => {
                     // split text into lines and push them into the line buffer
                     for l in t.lines() {
-                        self.lines.push_back(l.unwrap());
+                        self.lines.push_back(l?);
                     }
                     // this is fine because this code cannot be reached if lpos/ltotal > 0
                     self.ltotal = self.lines.len();
                     self.lpos = 1; // we issue the first line event from this code block
-                    let attr = ParserEvent::PLine(self.cpos, self.lines.pop_front().unwrap());
+                    let Some(line) = self.lines.pop_front() else {
+                        continue
+                    };
+                    let attr = ParserEvent::PLine(self.cpos, line);
                     self.cpos += 1;
 
-                    return Some(attr)
+                    return Ok(Some(attr))
                 }
 
-                Event::Eof => return None,
+                Event::Eof => return Ok(None),
 
                 _ => continue,
             };
         }
-
-        None
     }
 
+    /// Returns the value of `column` of the next token, ending the parser with
+    /// [`VrtError::MissingColumn`] if the token has fewer columns
     pub fn next_p(&mut self, column: usize) -> Option<(usize, String)> {
         while let Some(event) = self.read_next() {
             match event {
                 ParserEvent::PLine(cpos, line) => {
-                    let value = line.split('\t').nth(column)?;
+                    let Some(value) = line.split('\t').nth(column) else {
+                        let found = line.split('\t').count();
+                        self.error = Some(VrtError::MissingColumn { position: cpos, column, found });
+                        return None;
+                    };
                     return Some((cpos, value.to_owned()));
                 }
 
@@ -492,12 +794,26 @@ impl<R: Read> VrtParser<R> {
         None
     }
 
+    /// Returns the next `tag` with the value of its attribute `attr`, ending the parser with
+    /// [`VrtError::MissingAttribute`] if the tag doesn't have it
     pub fn next_a(&mut self, tag: &str, attr: &str) -> Option<(usize, usize, String)> {
+        let (start, end, mut attrs) = self.next_s_attrs(tag)?;
+        match attrs.remove(attr) {
+            Some(value) => Some((start, end, value)),
+            None => {
+                self.error = Some(VrtError::MissingAttribute { position: start, tag: tag.to_owned(), attr: attr.to_owned() });
+                None
+            }
+        }
+    }
+
+    /// Returns the next `tag` with all of its attributes
+    pub fn next_s_attrs(&mut self, tag: &str) -> Option<(usize, usize, HashMap<String, String>)> {
         while let Some(event) = self.read_next() {
             match event {
-                ParserEvent::SAttr(start, end, name, mut attrs) => {
+                ParserEvent::SAttr(start, end, name, attrs) => {
                     if name == tag {
-                        return Some((start, end, attrs.remove(attr).unwrap()))
+                        return Some((start, end, attrs))
                     }
                 }
 
@@ -522,6 +838,13 @@ impl<R: Read> VrtParser<R> {
             parser: self,
         }
     }
+
+    pub fn sa_iter(self, tag: &str) -> SAIter<R> {
+        SAIter {
+            tag: tag.to_string(),
+            parser: self,
+        }
+    }
 }
 
 pub struct SIter<R: Read> {
@@ -537,6 +860,12 @@ impl<R: Read> Iterator for SIter<R> {
     }
 }
 
+impl<R: Read> VrtIterator for SIter<R> {
+    fn take_error(&mut self) -> Option<VrtError> {
+        self.parser.take_error()
+    }
+}
+
 pub struct AIter<R: Read> {
     tag: String,
     attr: String,
@@ -551,21 +880,47 @@ impl<R: Read> Iterator for AIter<R> {
     }
 }
 
+impl<R: Read> VrtIterator for AIter<R> {
+    fn take_error(&mut self) -> Option<VrtError> {
+        self.parser.take_error()
+    }
+}
+
+pub struct SAIter<R: Read> {
+    tag: String,
+    parser: VrtParser<R>,
+}
+
+impl<R: Read> Iterator for SAIter<R> {
+    type Item = (usize, usize, HashMap<String, String>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.parser.next_s_attrs(&self.tag)
+    }
+}
+
+impl<R: Read> VrtIterator for SAIter<R> {
+    fn take_error(&mut self) -> Option<VrtError> {
+        self.parser.take_error()
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use std::collections::HashMap;
     use test::{Bencher, black_box};
     use crate::open_reader;
     use crate::open_parser;
 
     #[test]
     fn it_works() {
-        let mut file = open_reader("../etemenanki/testdata/Dickens-1.0.xml.gz").unwrap();
+        let mut file = open_reader("../etemenanki/testdata/Dickens-1.0.xml.gz", None).unwrap();
         file.read_next();
     }
 
     #[test]
     fn read_events() {
-        let mut file = open_reader("../etemenanki/testdata/Dickens-1.0.xml.gz").unwrap();
+        let mut file = open_reader("../etemenanki/testdata/Dickens-1.0.xml.gz", None).unwrap();
         while let Some(event) = file.read_next() {
             println!("{:?}", event);
         }
@@ -573,13 +928,13 @@ mod tests {
 
     #[test]
     fn it_works_parser() {
-        let mut file = open_parser("../etemenanki/testdata/Dickens-1.0.xml.gz").unwrap();
+        let mut file = open_parser("../etemenanki/testdata/Dickens-1.0.xml.gz", None).unwrap();
         file.read_next();
     }
 
     #[test]
     fn read_events_parser() {
-        let mut file = open_parser("../etemenanki/testdata/Dickens-1.0.xml.gz").unwrap();
+        let mut file = open_parser("../etemenanki/testdata/Dickens-1.0.xml.gz", None).unwrap();
         println!();
         while let Some(event) = file.read_next() {
             match event {
@@ -591,8 +946,8 @@ mod tests {
 
     #[test]
     fn read_p_comp() {
-        let mut file1 = open_reader("../etemenanki/testdata/Dickens-1.0.xml.gz").unwrap();
-        let mut file2 = open_parser("../etemenanki/testdata/Dickens-1.0.xml.gz").unwrap();
+        let mut file1 = open_reader("../etemenanki/testdata/Dickens-1.0.xml.gz", None).unwrap();
+        let mut file2 = open_parser("../etemenanki/testdata/Dickens-1.0.xml.gz", None).unwrap();
 
         while let Some(((cpos1, v1), (cpos2, v2))) = file1.next_p(0).zip(file2.next_p(0)) {
             assert!(cpos1 == cpos2, "discrepancy in cpos");
@@ -602,7 +957,7 @@ mod tests {
 
     #[test]
     fn read_s_parser() {
-        let mut file = open_parser("../etemenanki/testdata/Dickens-1.0.xml.gz").unwrap();
+        let mut file = open_parser("../etemenanki/testdata/Dickens-1.0.xml.gz", None).unwrap();
         println!();
         while let Some((start, end)) = file.next_s("s") {
             println!("text {}, {}", start, end);
@@ -611,17 +966,259 @@ mod tests {
 
     #[test]
     fn read_a_parser() {
-        let mut file = open_parser("../etemenanki/testdata/Dickens-1.0.xml.gz").unwrap();
+        let mut file = open_parser("../etemenanki/testdata/Dickens-1.0.xml.gz", None).unwrap();
         println!();
         while let Some((start, end, value)) = file.next_a("text", "id") {
             println!("text_id {}, {}: {}", start, end, value);
         }
     }
 
+    #[test]
+    fn read_s_attrs_parser() {
+        let file = open_parser("../etemenanki/testdata/Dickens-1.0.xml.gz", None).unwrap();
+        let titles = open_parser("../etemenanki/testdata/Dickens-1.0.xml.gz", None).unwrap().a_iter("novel", "title");
+
+        let mut n = 0;
+        for ((start, end, attrs), (tstart, tend, title)) in file.sa_iter("novel").zip(titles) {
+            assert!((start, end) == (tstart, tend));
+            assert!(attrs["title"] == title);
+            n += 1;
+        }
+        assert!(n > 0);
+    }
+
+    #[test]
+    fn build_dickens_profile() {
+        use etemenanki::{compare::DatastoreDiff, Datastore};
+        use crate::profile::{build_from_profile, Profile};
+
+        let profile = Profile::read("../etemenanki/testdata/simpledickens.toml").unwrap();
+        let output = std::env::temp_dir().join(format!("ziggypy-profile-{}", std::process::id()));
+        let n = build_from_profile("../etemenanki/testdata/Dickens-1.0.xml.gz", &profile, &output, false).unwrap();
+
+        let built = Datastore::open(&output).unwrap();
+        let reference = Datastore::open("../etemenanki/testdata/simpledickens").unwrap();
+        assert!(n == reference["primary"].len());
+
+        // the last string of each lexicon of simpledickens ends in a null byte added by the old encoder
+        for layer in DatastoreDiff::new(&built, &reference).layers {
+            assert!(layer.len_a.is_some() && layer.len_a == layer.len_b);
+            for variable in layer.variables {
+                let mut counts: HashMap<String, i64> = HashMap::new();
+                for k in variable.lexicon.unwrap().keyness {
+                    *counts.entry(k.value.trim_end_matches('\0').to_owned()).or_default() += k.count_a as i64 - k.count_b as i64;
+                }
+                assert!(counts.values().all(|&c| c == 0), "{} differs", variable.name);
+            }
+        }
+
+        drop(built);
+        std::fs::remove_dir_all(output).unwrap();
+    }
+
+    #[test]
+    fn shared_scan() {
+        use etemenanki::{layers::PrimaryLayer, lock::PendingFile, variables::VariableValue, Datastore};
+        use crate::scan::{encode_shared, Sink, SinkKind};
+
+        let input = "../etemenanki/testdata/Dickens-1.0.xml.gz";
+        let output = std::env::temp_dir().join(format!("ziggypy-scan-{}", std::process::id()));
+        std::fs::create_dir_all(output.join("s")).unwrap();
+        std::fs::create_dir_all(output.join("chapter")).unwrap();
+
+        let (n, _, counts) = open_reader(input, None).unwrap().stats();
+        let primary = PrimaryLayer::encode_to_file(PendingFile::create(output.join("primary.zigl")).unwrap(), n, "primary".to_owned(), "").unwrap().uuid();
+        let sink = |tag: &str, kind, base, file: &str| Sink {
+            tag: tag.to_owned(),
+            kind,
+            length: counts[tag],
+            base,
+            compressed: true,
+            comment: String::new(),
+            output: output.join(tag).join(file),
+        };
+
+        let layers = [sink("s", SinkKind::Layer, primary, "s.zigl"), sink("chapter", SinkKind::Layer, primary, "chapter.zigl")];
+        let encoded = encode_shared(open_parser(input, None).unwrap(), &layers).unwrap();
+        assert!(encoded.iter().map(|(len, _)| *len).eq([counts["s"], counts["chapter"]]));
+
+        let chapter = encoded[1].1;
+        let variables = [
+            sink("chapter", SinkKind::Int { attr: "num".to_owned(), default: 0, delta: false }, chapter, "num.zigv"),
+            sink("chapter", SinkKind::Plain("title".to_owned()), chapter, "title.zigv"),
+        ];
+        encode_shared(open_parser(input, None).unwrap(), &variables).unwrap();
+
+        let built = Datastore::open(&output).unwrap();
+        let reference = Datastore::open("../etemenanki/testdata/simpledickens").unwrap();
+        let ranges = |ds: &Datastore, tag: &str| ds[tag].as_segmentation().unwrap().iter().collect::<Vec<_>>();
+        assert!(ranges(&built, "s") == ranges(&reference, "s"));
+        assert!(ranges(&built, "chapter") == ranges(&reference, "chapter"));
+        for i in 0..counts["chapter"] {
+            assert!(built["chapter"]["num"].get(i) == reference["chapter"]["num"].get(i));
+            let (Some(VariableValue::String(a)), Some(VariableValue::String(b))) = (built["chapter"]["title"].get(i), reference["chapter"]["title"].get(i)) else {
+                panic!("chapter titles missing");
+            };
+            assert!(a == b.trim_end_matches('\0'));
+        }
+
+        // the input is checked before the encoders
+        let unknown = sink("chapter", SinkKind::Layer, primary, "unknown.zigl");
+        assert!(encode_shared(open_parser("../etemenanki/testdata/simpledickens.toml", None).unwrap(), &[unknown]).is_err());
+        assert!(!output.join("chapter/unknown.zigl").exists());
+
+        drop(built);
+        std::fs::remove_dir_all(output).unwrap();
+    }
+
+    #[test]
+    fn profile_validation() {
+        use crate::profile::{Profile, ProfileError, VariableType};
+
+        let profile = Profile::from_toml("version = 1\n[[columns]]\nname = \"word\"\n[[columns]]\nname = \"head\"\ntype = \"ptr\"\nbase = \"word\"").unwrap();
+        assert!(profile.compressed && profile.columns[0].kind == VariableType::Indexed);
+
+        let invalid = |toml: &str| Profile::from_toml(toml).unwrap_err();
+        assert!(matches!(invalid("version = 2"), ProfileError::UnsupportedVersion(2)));
+        assert!(matches!(invalid("version = 1\n[[columns]]\nname = \"word\"\ntype = \"set\""), ProfileError::Syntax(_)));
+        assert!(matches!(invalid("version = 1\ncolumns = [{ name = \"word\" }, { name = \"word\" }]"), ProfileError::Invalid(_)));
+        assert!(matches!(invalid("version = 1\ncolumns = [{ name = \"head\", type = \"ptr\" }]"), ProfileError::Invalid(_)));
+        assert!(matches!(invalid("version = 1\n[[structures]]\ntag = \"s\"\nannotations = [{ name = \"x\", type = \"skip\" }]"), ProfileError::Invalid(_)));
+    }
+
+    #[test]
+    fn malformed_vrt() {
+        use crate::{VrtError, VrtIterator};
+
+        let path = std::env::temp_dir().join(format!("ziggypy-malformed-{}.vrt", std::process::id()));
+        let filename = path.to_str().unwrap();
+
+        std::fs::write(&path, "<text id=\"a\">\n<s>\nthe\tDT\nend\n</s>\n</text>\n<text>\n</text>\n").unwrap();
+        let tags: Vec<_> = open_reader(filename, None).unwrap().iter_p(1).checked().collect();
+        assert!(tags.len() == 2 && tags[0].as_ref().unwrap() == &(0, "DT".to_owned()));
+        assert!(matches!(tags[1], Err(VrtError::MissingColumn { position: 1, column: 1, found: 1 })));
+
+        let ids: Vec<_> = open_parser(filename, None).unwrap().a_iter("text", "id").checked().collect();
+        assert!(ids.len() == 2 && ids[0].as_ref().unwrap() == &(0, 2, "a".to_owned()));
+        assert!(matches!(&ids[1], Err(VrtError::MissingAttribute { position: 2, tag, attr }) if tag == "text" && attr == "id"));
+
+        // quick-xml already rejects end tags which don't match
+        std::fs::write(&path, "<text>\n<s>\nthe\n</text>\n</s>\n").unwrap();
+        let mut sentences = open_parser(filename, None).unwrap().s_iter("s").checked();
+        assert!(matches!(sentences.next(), Some(Err(VrtError::Xml(_)))));
+        assert!(sentences.next().is_none());
+
+        std::fs::remove_file(path).unwrap();
+        let missing = open_reader("no-such-file.vrt", None).err().unwrap();
+        assert!(missing.to_string().contains("no-such-file.vrt"));
+    }
+
+    #[test]
+    fn read_records() {
+        use crate::VrtReader;
+
+        let vrt = "<!-- #vrt positional-attributes: word pos head -->\n<s>\nIt\tPRP\t2\nrains\tVBZ\t0\n.\n</s>\n";
+        let mut reader = VrtReader::new(vrt.as_bytes());
+        let record = reader.next_record().unwrap();
+        assert!(record.position == 0 && record.values == ["It", "PRP", "2"]);
+        assert!(record.get("pos") == Some("PRP") && record.get("lemma").is_none());
+        assert!(record.parse::<i64>("head") == Some(Ok(2)));
+        assert!(reader.columns().unwrap() == ["word", "pos", "head"] && reader.column("head") == Some(2));
+
+        reader.next_record();
+        let last = reader.next_record().unwrap();
+        assert!(last.position == 2 && last.get("word") == Some(".") && last.get("pos").is_none());
+        assert!(reader.next_record().is_none());
+
+        // without a declaration the columns are only known by position
+        let mut reader = VrtReader::new("<s>\nIt\tPRP\n</s>\n".as_bytes());
+        assert!(reader.next_record().unwrap().get("word").is_none());
+        let mut reader = VrtReader::new("It\tPRP\n".as_bytes());
+        reader.set_columns(vec!["word".to_owned(), "pos".to_owned()]);
+        assert!(reader.next_record().unwrap().get("pos") == Some("PRP"));
+    }
+
+    #[test]
+    fn compressed_input() {
+        use std::io::Write;
+        use crate::compression::Compression;
+
+        let vrt = "<s>\nIt\tPRP\nrains\tVBZ\n</s>\n";
+        let dir = std::env::temp_dir().join(format!("ziggypy-compressed-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let tokens = |path: &std::path::Path| -> Vec<String> {
+            open_reader(path.to_str().unwrap(), None).unwrap().iter_p(0).map(|(_, s)| s).collect()
+        };
+
+        // formats are detected by content, not by name
+        let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gzip.write_all(vrt.as_bytes()).unwrap();
+        let gzip = gzip.finish().unwrap();
+        assert!(Compression::detect(&gzip) == Compression::Gzip);
+        std::fs::write(dir.join("gzip.vrt"), &gzip).unwrap();
+        std::fs::write(dir.join("plain.vrt.gz"), vrt).unwrap();
+        assert!(tokens(&dir.join("gzip.vrt")) == ["It", "rains"]);
+        assert!(tokens(&dir.join("plain.vrt.gz")) == ["It", "rains"]);
+
+        // without the feature, only the magic bytes of zstd are known
+        #[cfg(feature = "zstd")]
+        let zstd = zstd::encode_all(vrt.as_bytes(), 0).unwrap();
+        #[cfg(not(feature = "zstd"))]
+        let zstd = vec![0x28, 0xb5, 0x2f, 0xfd];
+        assert!(Compression::detect(&zstd) == Compression::Zstd);
+        std::fs::write(dir.join("zstd.vrt"), &zstd).unwrap();
+        #[cfg(feature = "zstd")]
+        assert!(tokens(&dir.join("zstd.vrt")) == ["It", "rains"]);
+        #[cfg(not(feature = "zstd"))]
+        {
+            let e = open_reader(dir.join("zstd.vrt").to_str().unwrap(), None).err().unwrap();
+            assert!(e.kind() == std::io::ErrorKind::Unsupported && e.to_string().contains("zstd feature"));
+        }
+
+        #[cfg(feature = "bzip2")]
+        {
+            let mut bzip2 = bzip2::write::BzEncoder::new(Vec::new(), bzip2::Compression::default());
+            bzip2.write_all(vrt.as_bytes()).unwrap();
+            std::fs::write(dir.join("bzip2.vrt"), bzip2.finish().unwrap()).unwrap();
+            assert!(tokens(&dir.join("bzip2.vrt")) == ["It", "rains"]);
+        }
+
+        #[cfg(feature = "xz")]
+        {
+            let mut xz = xz2::write::XzEncoder::new(Vec::new(), 6);
+            xz.write_all(vrt.as_bytes()).unwrap();
+            std::fs::write(dir.join("xz.vrt"), xz.finish().unwrap()).unwrap();
+            assert!(tokens(&dir.join("xz.vrt")) == ["It", "rains"]);
+        }
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(feature = "charset")]
+    #[test]
+    fn read_latin1() {
+        let vrt = "<text id=\"caf\u{e9}\">\n<s>\nM\u{fc}nchen\tNE\nist\tVAFIN\ngro\u{df}\tADJD\n</s>\n</text>\n";
+        let latin1: Vec<u8> = vrt.chars().map(|c| c as u8).collect();
+        assert!(latin1.len() < vrt.len());
+
+        let path = std::env::temp_dir().join(format!("ziggypy-latin1-{}.vrt", std::process::id()));
+        std::fs::write(&path, latin1).unwrap();
+        let filename = path.to_str().unwrap();
+
+        let tokens: Vec<_> = open_reader(filename, Some("latin1")).unwrap().iter_p(0).map(|(_, s)| s).collect();
+        assert!(tokens == ["M\u{fc}nchen", "ist", "gro\u{df}"]);
+
+        let mut parser = open_parser(filename, Some("ISO-8859-1")).unwrap();
+        assert!(parser.next_a("text", "id") == Some((0, 3, "caf\u{e9}".to_owned())));
+
+        assert!(open_reader(filename, Some("no-such-charset")).is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+
     #[bench]
     fn bench_read_p(b: &mut Bencher) {
         b.iter(||{
-            let mut file = open_reader("../etemenanki/testdata/Dickens-1.0.xml.gz").unwrap();
+            let mut file = open_reader("../etemenanki/testdata/Dickens-1.0.xml.gz", None).unwrap();
             while let Some(attr) = file.next_p(0) {
                 black_box(attr);
             }
@@ -631,7 +1228,7 @@ mod tests {
     #[bench]
     fn bench_read_p_parser(b: &mut Bencher) {
         b.iter(||{
-            let mut file = open_parser("../etemenanki/testdata/Dickens-1.0.xml.gz").unwrap();
+            let mut file = open_parser("../etemenanki/testdata/Dickens-1.0.xml.gz", None).unwrap();
             while let Some(attr) = file.next_p(0) {
                 black_box(attr);
             }
@@ -641,7 +1238,7 @@ mod tests {
     #[bench]
     fn bench_read_s_parser(b: &mut Bencher) {
         b.iter(||{
-            let mut file = open_parser("../etemenanki/testdata/Dickens-1.0.xml.gz").unwrap();
+            let mut file = open_parser("../etemenanki/testdata/Dickens-1.0.xml.gz", None).unwrap();
             while let Some(attr) = file.next_s("s") {
                 black_box(attr);
             }
@@ -651,7 +1248,7 @@ mod tests {
     #[bench]
     fn bench_read_a_parser(b: &mut Bencher) {
         b.iter(||{
-            let mut file = open_parser("../etemenanki/testdata/Dickens-1.0.xml.gz").unwrap();
+            let mut file = open_parser("../etemenanki/testdata/Dickens-1.0.xml.gz", None).unwrap();
             while let Some(attr) = file.next_a("text", "id") {
                 black_box(attr);
             }
@@ -660,7 +1257,7 @@ mod tests {
 
     #[test]
     fn vrt_stats() {
-        let mut reader = open_reader("../etemenanki/testdata/Dickens-1.0.xml.gz").unwrap();
+        let mut reader = open_reader("../etemenanki/testdata/Dickens-1.0.xml.gz", None).unwrap();
         let (clen, pcount, scounts) = reader.stats();
 
         println!("\nCorpus with {} positions and {} P attrs", clen, pcount);
@@ -0,0 +1,406 @@
+//! Corpus profiles, declarative descriptions of how a VRT file is encoded as a datastore.
+//!
+//! A profile is a TOML file listing the columns of the input (p-attributes), the XML
+//! tags (s-attributes) and their annotations together with the variable types and
+//! compression settings, so every rebuild of a corpus uses the same settings:
+//!
+//! ```toml
+//! version = 1
+//! compressed = true
+//!
+//! [[columns]]
+//! name = "word"
+//!
+//! [[columns]]
+//! name = "pos"
+//! type = "indexed"
+//!
+//! [[structures]]
+//! tag = "text"
+//! annotations = [{ name = "id", type = "plain" }]
+//! ```
+//!
+//! Column types are `indexed` (the default), `plain`, `int`, `delta`, `ptr` and `skip`,
+//! annotations take the first four. Pointer columns name the column of the token indices
+//! their heads refer to in `base`, see `encode_ptr_from_p`. If the input names its columns
+//! in a [declaration](crate::COLUMNS_DECLARATION), they must match those of the profile.
+//!
+//! [`build_from_profile`] lays out the datastore like `vrt_to_zig.py`: the primary layer
+//! and its variables in the output directory, each structure and its annotations in a
+//! directory named after the tag. Containers are checkpointed, so an interrupted build
+//! can be resumed.
+
+use std::collections::HashSet;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::path::Path;
+use std::{error, fmt};
+
+use etemenanki::build::{Build, BuildError};
+use etemenanki::container::EncodeError;
+use etemenanki::fallible::{try_encode, TryEncodeError};
+use etemenanki::layers::{PrimaryLayer, SegmentationLayer};
+use etemenanki::lock::PendingFile;
+use etemenanki::variables::{IndexedStringVariable, IntegerVariable, PlainStringVariable, PointerVariable};
+use fnv::FnvHasher;
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::{open_parser, open_reader, pointer, VrtError, VrtIterator};
+
+/// Version of the profile format read by this library
+pub const PROFILE_VERSION: u32 = 1;
+
+fn default_compressed() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    /// Version of the profile format, must be [`PROFILE_VERSION`]
+    pub version: u32,
+    /// Default compression of all variables and layers
+    #[serde(default = "default_compressed")]
+    pub compressed: bool,
+    /// Charset of the input if it isn't UTF-8, see the `charset` module
+    #[serde(default)]
+    pub charset: Option<String>,
+    /// Value of invalid integers, which fail the build if `None`
+    #[serde(default)]
+    pub int_default: Option<i64>,
+    /// Columns of the input in order
+    #[serde(default)]
+    pub columns: Vec<Column>,
+    #[serde(default)]
+    pub structures: Vec<Structure>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VariableType {
+    #[default]
+    Indexed,
+    Plain,
+    Int,
+    /// Delta encoded integers
+    Delta,
+    /// Dependency heads, only for columns
+    Ptr,
+    /// Column which is not encoded
+    Skip,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Column {
+    pub name: String,
+    #[serde(default, rename = "type")]
+    pub kind: VariableType,
+    /// Column with the token indices pointers refer to
+    #[serde(default)]
+    pub base: Option<String>,
+    #[serde(default)]
+    pub compressed: Option<bool>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Structure {
+    /// XML tag of the structure, also the name of its layer
+    pub tag: String,
+    #[serde(default)]
+    pub compressed: Option<bool>,
+    #[serde(default)]
+    pub annotations: Vec<Annotation>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Annotation {
+    /// Name of the XML attribute and of the variable
+    pub name: String,
+    #[serde(default, rename = "type")]
+    pub kind: VariableType,
+    #[serde(default)]
+    pub compressed: Option<bool>,
+}
+
+#[derive(Debug)]
+pub enum ProfileError {
+    Io(io::Error),
+    Syntax(toml::de::Error),
+    UnsupportedVersion(u32),
+    /// The profile doesn't fit itself or the input
+    Invalid(String),
+    /// A value of an integer variable without `int_default` is not an integer
+    InvalidInteger { variable: String, position: usize, value: String },
+    /// The input is not a valid VRT file
+    Input(VrtError),
+    Encode(EncodeError),
+    Build(BuildError),
+}
+
+impl fmt::Display for ProfileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{}", e),
+            Self::Syntax(e) => write!(f, "invalid profile: {}", e),
+            Self::UnsupportedVersion(v) => write!(f, "unsupported profile version {}, expected {}", v, PROFILE_VERSION),
+            Self::Invalid(e) => write!(f, "invalid profile: {}", e),
+            Self::InvalidInteger { variable, position, value } => {
+                write!(f, "value '{}' of {} at {} is not an integer", value, variable, position)
+            }
+            Self::Input(e) => write!(f, "{}", e),
+            Self::Encode(e) => write!(f, "{}", e),
+            Self::Build(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl error::Error for ProfileError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            Self::Syntax(e) => Some(e),
+            Self::Input(e) => Some(e),
+            Self::Encode(e) => Some(e),
+            Self::Build(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ProfileError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<toml::de::Error> for ProfileError {
+    fn from(e: toml::de::Error) -> Self {
+        Self::Syntax(e)
+    }
+}
+
+impl From<VrtError> for ProfileError {
+    fn from(e: VrtError) -> Self {
+        Self::Input(e)
+    }
+}
+
+impl From<EncodeError> for ProfileError {
+    fn from(e: EncodeError) -> Self {
+        Self::Encode(e)
+    }
+}
+
+impl From<BuildError> for ProfileError {
+    fn from(e: BuildError) -> Self {
+        Self::Build(e)
+    }
+}
+
+impl From<TryEncodeError<ProfileError>> for ProfileError {
+    fn from(e: TryEncodeError<ProfileError>) -> Self {
+        match e {
+            TryEncodeError::Source(e) => e,
+            TryEncodeError::Encode(e) => Self::Encode(e),
+        }
+    }
+}
+
+impl Profile {
+    /// Parses and validates a profile
+    pub fn from_toml(toml: &str) -> Result<Self, ProfileError> {
+        let profile: Self = toml::from_str(toml)?;
+        profile.validate()?;
+        Ok(profile)
+    }
+
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self, ProfileError> {
+        Self::from_toml(&fs::read_to_string(path)?)
+    }
+
+    fn validate(&self) -> Result<(), ProfileError> {
+        if self.version != PROFILE_VERSION {
+            return Err(ProfileError::UnsupportedVersion(self.version));
+        }
+
+        let invalid = |e: String| Err(ProfileError::Invalid(e));
+        let mut names = HashSet::new();
+        for column in self.columns.iter().filter(|c| c.kind != VariableType::Skip) {
+            if !names.insert(column.name.as_str()) || column.name == "primary" {
+                return invalid(format!("duplicate column {}", column.name));
+            }
+        }
+
+        for column in self.columns.iter() {
+            match (column.kind, &column.base) {
+                (VariableType::Ptr, Some(base)) if self.columns.iter().any(|c| &c.name == base) => (),
+                (VariableType::Ptr, _) => return invalid(format!("pointer column {} needs the name of a column as base", column.name)),
+                (_, Some(_)) => return invalid(format!("column {} is not a pointer column but has a base", column.name)),
+                _ => (),
+            }
+        }
+
+        let mut tags = HashSet::new();
+        for structure in self.structures.iter() {
+            if !tags.insert(structure.tag.as_str()) {
+                return invalid(format!("duplicate structure {}", structure.tag));
+            }
+
+            let mut names = HashSet::new();
+            for annotation in structure.annotations.iter() {
+                if matches!(annotation.kind, VariableType::Ptr | VariableType::Skip) {
+                    return invalid(format!("annotation {} of {} can't be of type {:?}", annotation.name, structure.tag, annotation.kind));
+                }
+                if !names.insert(annotation.name.as_str()) || annotation.name == structure.tag {
+                    return invalid(format!("duplicate annotation {} of {}", annotation.name, structure.tag));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Parses the value of an integer variable at `position`
+    fn integer(&self, variable: &str, position: usize, value: &str) -> Result<i64, ProfileError> {
+        match (value.trim().parse(), self.int_default) {
+            (Ok(i), _) => Ok(i),
+            (Err(_), Some(default)) => Ok(default),
+            (Err(_), None) => Err(ProfileError::InvalidInteger {
+                variable: variable.to_owned(),
+                position,
+                value: value.to_owned(),
+            }),
+        }
+    }
+}
+
+/// Encodes the VRT file `vrt` as a datastore in `outdir` as described by `profile` and
+/// returns the number of tokens.
+///
+/// With `resume`, containers completed by an interrupted build of the same input and
+/// profile are kept, see [`etemenanki::build`].
+pub fn build_from_profile<P: AsRef<Path>>(vrt: &str, profile: &Profile, outdir: P, resume: bool) -> Result<usize, ProfileError> {
+    let charset = profile.charset.as_deref();
+    let mut reader = open_reader(vrt, charset)?;
+    let (n, columns, counts) = reader.stats();
+    if let Some(e) = reader.take_error() {
+        return Err(e.into());
+    }
+
+    if profile.columns.len() > columns {
+        return Err(ProfileError::Invalid(format!("profile has {} columns, input only {}", profile.columns.len(), columns)));
+    }
+    if let Some((column, declared)) = profile.columns.iter().zip(reader.columns().unwrap_or_default()).find(|(c, d)| &c.name != *d) {
+        return Err(ProfileError::Invalid(format!("column {} is declared as {} by the input", column.name, declared)));
+    }
+    if let Some(structure) = profile.structures.iter().find(|s| !counts.contains_key(&s.tag)) {
+        return Err(ProfileError::Invalid(format!("input has no structure {}", structure.tag)));
+    }
+
+    let mut hasher = FnvHasher::default();
+    profile.hash(&mut hasher);
+    fs::metadata(vrt)?.len().hash(&mut hasher);
+    let mut build = if resume {
+        Build::resume(outdir, hasher.finish())?
+    } else {
+        Build::new(outdir, hasher.finish())?
+    };
+
+    let source = Path::new(vrt).file_name().map(|f| f.to_string_lossy().into_owned()).unwrap_or_default();
+    let primary = build.container("primary", "primary.zigl", |file| {
+        PrimaryLayer::encode_to_file(file, n, "primary".to_owned(), &source).map(|l| l.uuid())
+    })?;
+
+    for (i, column) in profile.columns.iter().enumerate() {
+        if column.kind == VariableType::Skip {
+            continue;
+        }
+
+        let compressed = column.compressed.unwrap_or(profile.compressed);
+        let comment = format!("p-attr {}", column.name);
+        let values = open_reader(vrt, charset)?.iter_p(i).checked().map(|r| r.map_err(ProfileError::from));
+        build.container(&column.name, format!("{}.zigv", column.name), |file| -> Result<Uuid, ProfileError> {
+            match column.kind {
+                VariableType::Ptr => {
+                    let base = profile.columns.iter().position(|c| Some(&c.name) == column.base.as_ref()).expect("bases are validated");
+                    let bases = open_reader(vrt, charset)?.iter_p(base).checked();
+                    let heads = bases.zip(values).map(|(base, head)| {
+                        let ((cpos, base), (_, head)) = (base?, head?);
+                        Ok(pointer(cpos, &base, &head))
+                    });
+                    let variable = try_encode(file, heads, |file, heads| {
+                        PointerVariable::encode_to_file(file, heads, n, column.name.clone(), primary, compressed, &comment)
+                    })?;
+                    Ok(variable.uuid())
+                }
+                kind => encode_variable(file, kind, &column.name, values, n, primary, compressed, &comment, profile),
+            }
+        })?;
+    }
+
+    for structure in profile.structures.iter() {
+        let mut ranges = Vec::new();
+        let mut values = vec![Vec::new(); structure.annotations.len()];
+        for result in open_parser(vrt, charset)?.sa_iter(&structure.tag).checked() {
+            let (start, end, mut attrs) = result?;
+            ranges.push((start, end));
+            for (annotation, values) in structure.annotations.iter().zip(values.iter_mut()) {
+                values.push(attrs.remove(&annotation.name).unwrap_or_default());
+            }
+        }
+
+        let tag = structure.tag.as_str();
+        let compressed = structure.compressed.unwrap_or(profile.compressed);
+        let layer = build.container(tag, Path::new(tag).join(format!("{}.zigl", tag)), |file| -> Result<Uuid, ProfileError> {
+            Ok(SegmentationLayer::encode_to_file(file, ranges.iter().copied(), ranges.len(), tag.to_owned(), primary, compressed, &format!("s-attr {}", tag))?.uuid())
+        })?;
+
+        for (annotation, values) in structure.annotations.iter().zip(values) {
+            let compressed = annotation.compressed.unwrap_or(compressed);
+            let comment = format!("s-attr {}_{}", tag, annotation.name);
+            let path = Path::new(tag).join(format!("{}.zigv", annotation.name));
+            build.container(&annotation.name, path, |file| {
+                encode_variable(file, annotation.kind, &annotation.name, values.into_iter().enumerate().map(Ok), ranges.len(), layer, compressed, &comment, profile)
+            })?;
+        }
+    }
+
+    Ok(n)
+}
+
+/// Encodes the string and integer variable types from the values at their positions
+fn encode_variable<I>(file: PendingFile, kind: VariableType, name: &str, values: I, n: usize, base: Uuid, compressed: bool, comment: &str, profile: &Profile) -> Result<Uuid, ProfileError>
+where
+    I: Iterator<Item = Result<(usize, String), ProfileError>>,
+{
+    match kind {
+        VariableType::Indexed => {
+            let strings = values.map(|r| r.map(|(_, s)| s));
+            let variable = try_encode(file, strings, |file, strings| {
+                IndexedStringVariable::encode_to_file(file, strings, n, name.to_owned(), base, compressed, comment)
+            })?;
+            Ok(variable.uuid())
+        }
+        VariableType::Plain => {
+            let strings = values.map(|r| r.map(|(_, s)| s));
+            let variable = try_encode(file, strings, |file, strings| {
+                PlainStringVariable::encode_to_file(file, strings, n, name.to_owned(), base, compressed, comment)
+            })?;
+            Ok(variable.uuid())
+        }
+        VariableType::Int | VariableType::Delta => {
+            let delta = kind == VariableType::Delta;
+            let integers = values.map(|r| r.and_then(|(position, s)| profile.integer(name, position, &s)));
+            let variable = try_encode(file, integers, |file, integers| {
+                IntegerVariable::encode_to_file(file, integers, n, name.to_owned(), base, compressed, delta, comment)
+            })?;
+            Ok(variable.uuid())
+        }
+        VariableType::Ptr | VariableType::Skip => unreachable!("only for columns and handled there"),
+    }
+}
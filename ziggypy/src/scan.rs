@@ -0,0 +1,207 @@
+//! Encoding several structures and annotations of a VRT file in a single pass.
+//!
+//! Each `encode_*_from_a` and `encode_seg_from_s` parses the whole input for one container.
+//! [`encode_shared`] parses it once and sends the values of every [`Sink`] through a bounded
+//! channel to its own encoder thread, so the encoders run concurrently with the parser and
+//! with each other while only a few values per sink are buffered.
+//!
+//! The bases of the sinks must exist before the scan, a variable can't be based on a layer
+//! encoded by the same scan. `encode_seg_with_attrs` encodes a layer with its annotations.
+
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::PathBuf;
+use std::sync::mpsc::{sync_channel, SyncSender};
+use std::thread;
+
+use etemenanki::container::EncodeError;
+use etemenanki::fallible::{try_encode, TryEncodeError};
+use etemenanki::layers::SegmentationLayer;
+use etemenanki::lock::PendingFile;
+use etemenanki::variables::{IndexedStringVariable, IntegerVariable, PlainStringVariable};
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use uuid::Uuid;
+
+use crate::{parse_uuid, ParserEvent, VrtError, VrtParser};
+
+/// Values buffered per sink while its encoder is busy
+const CHANNEL_CAPACITY: usize = 4096;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SinkKind {
+    /// Segmentation layer of the ranges of the tag
+    Layer,
+    /// Indexed string variable of an attribute, empty for tags without it
+    Indexed(String),
+    /// Plain string variable of an attribute, empty for tags without it
+    Plain(String),
+    /// Integer variable of an attribute, `default` for tags without a valid integer
+    Int { attr: String, default: i64, delta: bool },
+}
+
+/// Container encoded from the occurrences of `tag` by [`encode_shared`]
+#[pyclass]
+#[derive(Debug, Clone)]
+pub struct Sink {
+    pub tag: String,
+    pub kind: SinkKind,
+    /// Number of occurrences of `tag`
+    pub length: usize,
+    pub base: Uuid,
+    pub compressed: bool,
+    pub comment: String,
+    pub output: PathBuf,
+}
+
+#[pymethods]
+impl Sink {
+    /// Sink of the layer of `tag` without `attr`, otherwise of a variable of type `type`, one
+    /// of `indexed`, `plain`, `int` and `delta`
+    #[new]
+    #[pyo3(signature = (tag, output, length, base, attr=None, r#type="indexed", default=0, compressed=true, comment=String::new()))]
+    fn py_new(tag: String, output: PathBuf, length: usize, base: &str, attr: Option<String>, r#type: &str, default: i64, compressed: bool, comment: String) -> PyResult<Self> {
+        let kind = match (attr, r#type) {
+            (None, _) => SinkKind::Layer,
+            (Some(attr), "indexed") => SinkKind::Indexed(attr),
+            (Some(attr), "plain") => SinkKind::Plain(attr),
+            (Some(attr), "int") => SinkKind::Int { attr, default, delta: false },
+            (Some(attr), "delta") => SinkKind::Int { attr, default, delta: true },
+            (Some(_), other) => return Err(PyValueError::new_err(format!("unknown variable type '{}'", other))),
+        };
+        Ok(Self { tag, kind, length, base: parse_uuid(base)?, compressed, comment, output })
+    }
+}
+
+/// The input of a sink ended early because the parser failed
+#[derive(Debug)]
+struct Aborted;
+
+type Sender<T> = SyncSender<Result<T, Aborted>>;
+
+/// Sending end of the channel of a sink, with the attribute it takes
+enum Feed {
+    Ranges(Sender<(usize, usize)>),
+    Strings(String, Sender<String>),
+    Ints(String, i64, Sender<i64>),
+}
+
+impl Feed {
+    fn send(&self, start: usize, end: usize, attrs: &HashMap<String, String>) {
+        // a failed encoder drops its receiver, its error is returned when it is joined
+        match self {
+            Self::Ranges(sender) => {
+                let _ = sender.send(Ok((start, end)));
+            }
+            Self::Strings(attr, sender) => {
+                let _ = sender.send(Ok(attrs.get(attr).cloned().unwrap_or_default()));
+            }
+            Self::Ints(attr, default, sender) => {
+                let _ = sender.send(Ok(attrs.get(attr).and_then(|v| v.parse().ok()).unwrap_or(*default)));
+            }
+        }
+    }
+
+    fn abort(&self) {
+        match self {
+            Self::Ranges(sender) => {
+                let _ = sender.send(Err(Aborted));
+            }
+            Self::Strings(_, sender) => {
+                let _ = sender.send(Err(Aborted));
+            }
+            Self::Ints(_, _, sender) => {
+                let _ = sender.send(Err(Aborted));
+            }
+        }
+    }
+}
+
+type Encoded = Result<(usize, Uuid), TryEncodeError<Aborted>>;
+
+/// Encodes all `sinks` in one pass of `parser` and returns the length and UUID of each
+/// container. Errors of the input take precedence over those of the encoders.
+pub fn encode_shared<R: Read>(mut parser: VrtParser<R>, sinks: &[Sink]) -> Result<Vec<(usize, Uuid)>, TryEncodeError<VrtError>> {
+    thread::scope(|scope| {
+        let mut feeds = Vec::with_capacity(sinks.len());
+        let mut encoders = Vec::with_capacity(sinks.len());
+
+        for sink in sinks {
+            let file = PendingFile::replace(&sink.output).map_err(EncodeError::Io)?;
+            let (feed, encoder) = match &sink.kind {
+                SinkKind::Layer => {
+                    let (sender, receiver) = sync_channel(CHANNEL_CAPACITY);
+                    let encoder = scope.spawn(move || {
+                        try_encode(file, receiver, |file, ranges| {
+                            SegmentationLayer::encode_to_file(file, ranges, sink.length, sink.tag.clone(), sink.base, sink.compressed, &sink.comment)
+                                .map(|layer| (layer.len(), layer.uuid()))
+                        })
+                    });
+                    (Feed::Ranges(sender), encoder)
+                }
+
+                SinkKind::Indexed(attr) | SinkKind::Plain(attr) => {
+                    let (sender, receiver) = sync_channel(CHANNEL_CAPACITY);
+                    let plain = matches!(sink.kind, SinkKind::Plain(_));
+                    let encoder = scope.spawn(move || {
+                        try_encode(file, receiver, |file, strings| {
+                            if plain {
+                                PlainStringVariable::encode_to_file(file, strings, sink.length, attr.clone(), sink.base, sink.compressed, &sink.comment)
+                                    .map(|variable| (variable.len(), variable.uuid()))
+                            } else {
+                                IndexedStringVariable::encode_to_file(file, strings, sink.length, attr.clone(), sink.base, sink.compressed, &sink.comment)
+                                    .map(|variable| (variable.len(), variable.uuid()))
+                            }
+                        })
+                    });
+                    (Feed::Strings(attr.clone(), sender), encoder)
+                }
+
+                SinkKind::Int { attr, default, delta } => {
+                    let (sender, receiver) = sync_channel(CHANNEL_CAPACITY);
+                    let delta = *delta;
+                    let encoder = scope.spawn(move || {
+                        try_encode(file, receiver, |file, values| {
+                            IntegerVariable::encode_to_file(file, values, sink.length, attr.clone(), sink.base, sink.compressed, delta, &sink.comment)
+                                .map(|variable| (variable.len(), variable.uuid()))
+                        })
+                    });
+                    (Feed::Ints(attr.clone(), *default, sender), encoder)
+                }
+            };
+            feeds.push(feed);
+            encoders.push(encoder);
+        }
+
+        while let Some(event) = parser.read_next() {
+            if let ParserEvent::SAttr(start, end, tag, attrs) = event {
+                for (sink, feed) in sinks.iter().zip(&feeds) {
+                    if sink.tag == tag {
+                        feed.send(start, end, &attrs);
+                    }
+                }
+            }
+        }
+
+        let error = parser.take_error();
+        if error.is_some() {
+            feeds.iter().for_each(Feed::abort);
+        }
+        // closing the channels ends the input of the encoders
+        drop(feeds);
+
+        let results: Vec<Encoded> = encoders.into_iter()
+            .map(|encoder| encoder.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic)))
+            .collect();
+        if let Some(e) = error {
+            return Err(TryEncodeError::Source(e));
+        }
+
+        results.into_iter()
+            .map(|result| result.map_err(|e| match e {
+                TryEncodeError::Encode(e) => TryEncodeError::Encode(e),
+                TryEncodeError::Source(Aborted) => unreachable!("sinks are only aborted after an error of the input"),
+            }))
+            .collect()
+    })
+}
@@ -0,0 +1,3 @@
+from ziggypy._rustypy import DatastoreCore as Datastore, Int64Array, ValueIterator
+
+__all__ = ["Datastore", "Int64Array", "ValueIterator"]
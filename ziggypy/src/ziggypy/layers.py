@@ -4,7 +4,7 @@ from uuid import UUID, uuid4
 from io import RawIOBase
 from os.path import realpath
 
-from ziggypy._rustypy import encode_seg_from_s
+from ziggypy._rustypy import encode_seg_from_s, encode_seg_with_attrs
 
 from .container import Container
 from .components import *
@@ -71,7 +71,7 @@ class SegmentationLayer(Layer):
         )
 
 class RustySegmentationLayer(Layer):
-    def __init__(self, base_layer: Layer, file: RawIOBase, s_tag: str, length: int, uuid: Optional[UUID] = None, compressed: bool = True, comment: str = ""):
+    def __init__(self, base_layer: Layer, file: RawIOBase, s_tag: str, length: int, uuid: Optional[UUID] = None, compressed: bool = True, comment: str = "", charset: Optional[str] = None):
         super().__init__(length, None)
 
         self.base = str(base_layer.uuid)
@@ -79,9 +79,20 @@ class RustySegmentationLayer(Layer):
         self.s_tag = s_tag
         self.compressed = compressed
         self.comment = comment
+        self.charset = charset
 
     def write(self, f: RawIOBase):
         output = realpath(f.name)
-        encodedlen, uuid = encode_seg_from_s(self.input, self.s_tag, self.n, self.base, self.compressed, self.comment, output)
+        encodedlen, uuid = encode_seg_from_s(self.input, self.s_tag, self.n, self.base, self.compressed, self.comment, output, charset=self.charset)
         assert encodedlen == self.n, "discrepancy between specified and actual encoded len"
         self.uuid = UUID(uuid)
+
+    def write_with_attributes(self, f: RawIOBase, attributes: dict[str, RawIOBase]) -> dict[str, UUID]:
+        """Writes the layer to f and each of its attributes as an indexed string variable
+        to its file, reading the input only once. Returns the UUIDs of the variables."""
+        names = list(attributes)
+        outputs = [realpath(attributes[name].name) for name in names]
+        encodedlen, uuid, uuids = encode_seg_with_attrs(self.input, self.s_tag, names, self.n, self.base, self.compressed, self.comment, realpath(f.name), outputs, charset=self.charset)
+        assert encodedlen == self.n, "discrepancy between specified and actual encoded len"
+        self.uuid = UUID(uuid)
+        return {name: UUID(u) for name, u in zip(names, uuids)}
@@ -0,0 +1,3 @@
+from ziggypy._rustypy import build_from_profile
+
+__all__ = ["build_from_profile"]
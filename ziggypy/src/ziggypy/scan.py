@@ -0,0 +1,3 @@
+from ziggypy._rustypy import Sink, encode_shared
+
+__all__ = ["Sink", "encode_shared"]
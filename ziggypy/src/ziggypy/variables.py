@@ -73,22 +73,23 @@ class PlainStringVariable(Variable):
 
 
 class RustyPlainStringVariable:
-    def __init__(self, base_layer: Layer, file: RawIOBase, src: int | tuple[str, str], length: int, uuid: Optional[UUID] = None, compressed: bool = True, comment: str = ""):
+    def __init__(self, base_layer: Layer, file: RawIOBase, src: int | tuple[str, str], length: int, uuid: Optional[UUID] = None, compressed: bool = True, comment: str = "", charset: Optional[str] = None):
         self.base = str(base_layer.uuid)
         self.input = realpath(file.name)
         self.src = src
         self.length = length
         self.compressed = compressed
         self.comment = comment
+        self.charset = charset
 
     def write(self, f: RawIOBase):
         output = realpath(f.name)
 
         if type(self.src) is int:
-            encode_plain_from_p(self.input, self.src, self.length, self.base, self.compressed, self.comment, output)
+            encode_plain_from_p(self.input, self.src, self.length, self.base, self.compressed, self.comment, output, charset=self.charset)
         elif type(self.src) is tuple and len(self.src) == 2 and type(self.src[0]) is str and type(self.src[1]) is str:
             tag, attr = self.src
-            encode_plain_from_a(self.input, tag, attr, self.length, self.base, self.compressed, self.comment, output)
+            encode_plain_from_a(self.input, tag, attr, self.length, self.base, self.compressed, self.comment, output, charset=self.charset)
         else:
             raise TypeError("wrong type for src, must be int or (str, str)")
 
@@ -138,22 +139,23 @@ class IndexedStringVariable(Variable):
         )
 
 class RustyIndexedStringVariable:
-    def __init__(self, base_layer: Layer, file: RawIOBase, src: int | tuple[str, str], length: int, uuid: Optional[UUID] = None, compressed: bool = True, comment: str = ""):
+    def __init__(self, base_layer: Layer, file: RawIOBase, src: int | tuple[str, str], length: int, uuid: Optional[UUID] = None, compressed: bool = True, comment: str = "", charset: Optional[str] = None):
         self.base = str(base_layer.uuid)
         self.input = realpath(file.name)
         self.src = src
         self.length = length
         self.compressed = compressed
         self.comment = comment
+        self.charset = charset
 
     def write(self, f: RawIOBase):
         output = realpath(f.name)
 
         if type(self.src) is int:
-            encode_indexed_from_p(self.input, self.src, self.length, self.base, self.compressed, self.comment, output)
+            encode_indexed_from_p(self.input, self.src, self.length, self.base, self.compressed, self.comment, output, charset=self.charset)
         elif type(self.src) is tuple and len(self.src) == 2 and type(self.src[0]) is str and type(self.src[1]) is str:
             tag, attr = self.src
-            encode_indexed_from_a(self.input, tag, attr, self.length, self.base, self.compressed, self.comment, output)
+            encode_indexed_from_a(self.input, tag, attr, self.length, self.base, self.compressed, self.comment, output, charset=self.charset)
         else:
             raise TypeError("wrong type for src, must be int or (str, str)")
 
@@ -293,7 +295,7 @@ class FileIntegerVariable(Variable):
 
 class RustyIntegerVariable:
 
-    def __init__(self, base_layer: Layer, file: RawIOBase, src: int | tuple[str, str], length: int, default: int = 0, uuid: Optional[UUID] = None, compressed: bool = True, delta: bool = False, comment: str = ""):
+    def __init__(self, base_layer: Layer, file: RawIOBase, src: int | tuple[str, str], length: int, default: int = 0, uuid: Optional[UUID] = None, compressed: bool = True, delta: bool = False, comment: str = "", charset: Optional[str] = None):
         self.base = str(base_layer.uuid)
         self.input = realpath(file.name)
         self.src = src
@@ -302,15 +304,16 @@ class RustyIntegerVariable:
         self.compressed = compressed
         self.delta = delta
         self.comment = comment
+        self.charset = charset
 
     def write(self, f: RawIOBase):
         output = realpath(f.name)
 
         if type(self.src) is int:
-            encode_int_from_p(self.input, self.src, self.length, self.default, self.base, self.compressed, self.delta, self.comment, output)
+            encode_int_from_p(self.input, self.src, self.length, self.default, self.base, self.compressed, self.delta, self.comment, output, charset=self.charset)
         elif type(self.src) is tuple and len(self.src) == 2 and type(self.src[0]) is str and type(self.src[1]) is str:
             tag, attr = self.src
-            encode_int_from_a(self.input, tag, attr, self.length, self.default, self.base, self.compressed, self.delta, self.comment, output)
+            encode_int_from_a(self.input, tag, attr, self.length, self.default, self.base, self.compressed, self.delta, self.comment, output, charset=self.charset)
         else:
             raise TypeError("wrong type for src, must be int or (str, str)")
 
@@ -444,7 +447,7 @@ class PointerVariable(Variable):
         )
 
 class RustyPointerVariable:
-    def __init__(self, base_layer: Layer, file: RawIOBase, basecol: int, headcol: int, length: int, uuid: Optional[UUID] = None, compressed: bool = True, comment: str = ""):
+    def __init__(self, base_layer: Layer, file: RawIOBase, basecol: int, headcol: int, length: int, uuid: Optional[UUID] = None, compressed: bool = True, comment: str = "", charset: Optional[str] = None):
         self.base = str(base_layer.uuid)
         self.input = realpath(file.name)
         self.basecol = basecol
@@ -452,8 +455,9 @@ class RustyPointerVariable:
         self.length = length
         self.compressed = compressed
         self.comment = comment
+        self.charset = charset
 
     def write(self, f: RawIOBase):
         output = realpath(f.name)
-        encodedlen = encode_ptr_from_p(self.input, self.basecol, self.headcol, self.length, self.base, self.compressed, self.comment, output)
+        encodedlen = encode_ptr_from_p(self.input, self.basecol, self.headcol, self.length, self.base, self.compressed, self.comment, output, charset=self.charset)
         assert encodedlen == self.length, "discrepancy between specified and actual encoded len"